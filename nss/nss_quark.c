@@ -0,0 +1,243 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The glibc nss module of the quark host resolver. With HostResolver in config.json, the
+// sandbox has /proc/quark_resolver which looks up the names with the dns client of qvisor in
+// one call, instead of the socket calls of the dns lookup of glibc. To use it, install
+// libnss_quark.so.2 in the libc dir of the image and put it before dns in /etc/nsswitch.conf:
+//
+//     hosts: files quark dns
+//
+// The module returns UNAVAIL when the sandbox has no /proc/quark_resolver, so the lookup goes
+// on to dns, e.g. in runc.
+
+#include <errno.h>
+#include <fcntl.h>
+#include <netdb.h>
+#include <nss.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+#include <arpa/inet.h>
+#include <sys/socket.h>
+
+#define RESOLVER_PATH "/proc/quark_resolver"
+#define RESOLVER_MAX_ADDRS 64
+
+struct quark_addr {
+    int family;
+    unsigned char addr[16];
+};
+
+static int addr_len(int family)
+{
+    return family == AF_INET ? 4 : 16;
+}
+
+// quark_lookup gets the addresses of name, it returns the count or -errno
+static int quark_lookup(const char *name, int af, struct quark_addr *addrs, int max)
+{
+    const char *family = af == AF_INET ? "inet" : af == AF_INET6 ? "inet6" : "any";
+    char req[512];
+    int n = snprintf(req, sizeof(req), "%s %s", family, name);
+    if (n < 0 || n >= (int)sizeof(req))
+        return -EINVAL;
+
+    int fd = open(RESOLVER_PATH, O_RDWR | O_CLOEXEC);
+    if (fd < 0)
+        return -ENODEV;
+
+    if (write(fd, req, n) != n) {
+        int err = errno;
+        close(fd);
+        return -err;
+    }
+
+    char buf[RESOLVER_MAX_ADDRS * 48];
+    size_t len = 0;
+    for (;;) {
+        ssize_t cnt = pread(fd, buf + len, sizeof(buf) - 1 - len, len);
+        if (cnt < 0 && errno == EINTR)
+            continue;
+        if (cnt < 0) {
+            int err = errno;
+            close(fd);
+            return -err;
+        }
+        len += cnt;
+        if (cnt == 0 || len == sizeof(buf) - 1)
+            break;
+    }
+    close(fd);
+    buf[len] = 0;
+
+    int count = 0;
+    char *save = NULL;
+    for (char *line = strtok_r(buf, "\n", &save); line && count < max; line = strtok_r(NULL, "\n", &save)) {
+        char *sp = strchr(line, ' ');
+        if (!sp)
+            continue;
+        *sp = 0;
+
+        int family = strcmp(line, "inet") == 0 ? AF_INET : strcmp(line, "inet6") == 0 ? AF_INET6 : -1;
+        if (family < 0 || inet_pton(family, sp + 1, addrs[count].addr) != 1)
+            continue;
+
+        addrs[count].family = family;
+        count++;
+    }
+
+    return count > 0 ? count : -ENOENT;
+}
+
+// lookup_status maps the error of quark_lookup to the nss status
+static enum nss_status lookup_status(int err, int *errnop, int *herrnop)
+{
+    switch (err) {
+    case ENODEV:
+        *errnop = ENOENT;
+        *herrnop = NO_RECOVERY;
+        return NSS_STATUS_UNAVAIL;
+    case ENOENT:
+        *errnop = ENOENT;
+        *herrnop = HOST_NOT_FOUND;
+        return NSS_STATUS_NOTFOUND;
+    case EAGAIN:
+        *errnop = EAGAIN;
+        *herrnop = TRY_AGAIN;
+        return NSS_STATUS_TRYAGAIN;
+    default:
+        *errnop = err;
+        *herrnop = NO_RECOVERY;
+        return NSS_STATUS_UNAVAIL;
+    }
+}
+
+// buf_alloc takes size bytes aligned for pointers from the buffer of the caller
+static void *buf_alloc(char **buffer, size_t *buflen, size_t size)
+{
+    size_t pad = -(uintptr_t)*buffer & (sizeof(void *) - 1);
+    if (pad + size > *buflen)
+        return NULL;
+
+    void *p = *buffer + pad;
+    *buffer += pad + size;
+    *buflen -= pad + size;
+    return p;
+}
+
+static enum nss_status buf_range(int *errnop, int *herrnop)
+{
+    *errnop = ERANGE;
+    *herrnop = NETDB_INTERNAL;
+    return NSS_STATUS_TRYAGAIN;
+}
+
+enum nss_status _nss_quark_gethostbyname4_r(const char *name, struct gaih_addrtuple **pat,
+                                            char *buffer, size_t buflen, int *errnop,
+                                            int *herrnop, int32_t *ttlp)
+{
+    struct quark_addr addrs[RESOLVER_MAX_ADDRS];
+    int count = quark_lookup(name, AF_UNSPEC, addrs, RESOLVER_MAX_ADDRS);
+    if (count < 0)
+        return lookup_status(-count, errnop, herrnop);
+
+    char *hname = buf_alloc(&buffer, &buflen, strlen(name) + 1);
+    if (!hname)
+        return buf_range(errnop, herrnop);
+    strcpy(hname, name);
+
+    struct gaih_addrtuple *prev = NULL;
+    for (int i = 0; i < count; i++) {
+        struct gaih_addrtuple *t = buf_alloc(&buffer, &buflen, sizeof(*t));
+        if (!t)
+            return buf_range(errnop, herrnop);
+
+        memset(t, 0, sizeof(*t));
+        t->name = i == 0 ? hname : NULL;
+        t->family = addrs[i].family;
+        memcpy(t->addr, addrs[i].addr, addr_len(addrs[i].family));
+        if (prev)
+            prev->next = t;
+        else
+            *pat = t;
+        prev = t;
+    }
+
+    // the ttl is kept by the host cache
+    if (ttlp)
+        *ttlp = 0;
+    return NSS_STATUS_SUCCESS;
+}
+
+enum nss_status _nss_quark_gethostbyname3_r(const char *name, int af, struct hostent *result,
+                                            char *buffer, size_t buflen, int *errnop,
+                                            int *herrnop, int32_t *ttlp, char **canonp)
+{
+    if (af != AF_INET && af != AF_INET6) {
+        *errnop = EAFNOSUPPORT;
+        *herrnop = NO_DATA;
+        return NSS_STATUS_UNAVAIL;
+    }
+
+    struct quark_addr addrs[RESOLVER_MAX_ADDRS];
+    int count = quark_lookup(name, af, addrs, RESOLVER_MAX_ADDRS);
+    if (count < 0)
+        return lookup_status(-count, errnop, herrnop);
+
+    int len = addr_len(af);
+    char *hname = buf_alloc(&buffer, &buflen, strlen(name) + 1);
+    char **aliases = buf_alloc(&buffer, &buflen, sizeof(char *));
+    char **list = buf_alloc(&buffer, &buflen, (count + 1) * sizeof(char *));
+    char *data = buf_alloc(&buffer, &buflen, count * len);
+    if (!hname || !aliases || !list || !data)
+        return buf_range(errnop, herrnop);
+
+    strcpy(hname, name);
+    aliases[0] = NULL;
+    for (int i = 0; i < count; i++) {
+        list[i] = data + i * len;
+        memcpy(list[i], addrs[i].addr, len);
+    }
+    list[count] = NULL;
+
+    result->h_name = hname;
+    result->h_aliases = aliases;
+    result->h_addrtype = af;
+    result->h_length = len;
+    result->h_addr_list = list;
+
+    if (ttlp)
+        *ttlp = 0;
+    if (canonp)
+        *canonp = hname;
+    return NSS_STATUS_SUCCESS;
+}
+
+enum nss_status _nss_quark_gethostbyname2_r(const char *name, int af, struct hostent *result,
+                                            char *buffer, size_t buflen, int *errnop,
+                                            int *herrnop)
+{
+    return _nss_quark_gethostbyname3_r(name, af, result, buffer, buflen, errnop, herrnop,
+                                       NULL, NULL);
+}
+
+enum nss_status _nss_quark_gethostbyname_r(const char *name, struct hostent *result,
+                                           char *buffer, size_t buflen, int *errnop,
+                                           int *herrnop)
+{
+    return _nss_quark_gethostbyname3_r(name, AF_INET, result, buffer, buflen, errnop, herrnop,
+                                       NULL, NULL);
+}
@@ -29,6 +29,8 @@ use super::qlib::perf_tunning::*;
 use super::SHARESPACE;
 use super::qlib::singleton::*;
 use super::qlib::pagetable::PageTables;
+use super::qlib::kernel::profiler::PROFILER;
+use super::qlib::kernel::kernel::deterministic::*;
 
 #[derive(Clone, Copy, Debug)]
 pub enum ExceptionStackVec {
@@ -52,6 +54,7 @@ pub enum ExceptionStackVec {
     MachineCheck,
     SIMDFloatingPointException,
     VirtualizationException,
+    ControlProtection,
     SecurityException,
     SyscallInt80,
     NrInterrupts,
@@ -82,6 +85,7 @@ extern "C" {
     pub fn machine_check_handler();
     pub fn simd_fp_handler();
     pub fn virtualization_handler();
+    pub fn control_protection_handler();
     pub fn security_handler();
 }
 
@@ -112,6 +116,7 @@ pub unsafe fn InitSingleton() {
     idt.set_handler(18, machine_check_handler).set_stack_index(0);
     idt.set_handler(19, simd_fp_handler).set_stack_index(0);
     idt.set_handler(20, virtualization_handler).set_stack_index(0);
+    idt.set_handler(21, control_protection_handler).set_stack_index(0);
 
     idt.set_handler(30, security_handler).set_stack_index(0);
 
@@ -228,6 +233,7 @@ pub fn ExceptionHandler(ev: ExceptionStackVec, sf: &mut PtRegs, errorCode: u64)
             thread.forceSignal(Signal(info.Signo), false);
             thread.SendSignal(&info).expect("DivByZeroHandler send signal fail");
         }
+        ExceptionStackVec::GeneralProtectionFault if IsDeterministic() && EmulateRdtsc(currTask, sf) => {}
         ExceptionStackVec::GeneralProtectionFault |
         ExceptionStackVec::SegmentNotPresent |
         ExceptionStackVec::BoundRangeExceeded |
@@ -268,6 +274,18 @@ pub fn ExceptionHandler(ev: ExceptionStackVec, sf: &mut PtRegs, errorCode: u64)
             thread.forceSignal(Signal(info.Signo), false);
             thread.SendSignal(&info).expect("InvalidOpcode send signal fail");
         }
+        ExceptionStackVec::ControlProtection => {
+            // the shadow stack mismatch of ret/iret or the missing endbr of the user app
+            let info = SignalInfo {
+                Signo: Signal::SIGSEGV,
+                Code: 10,  // SEGV_CPERR (control protection fault).
+                ..Default::default()
+            };
+
+            let thread = currTask.Thread();
+            thread.forceSignal(Signal(info.Signo), false);
+            thread.SendSignal(&info).expect("ControlProtection send signal fail");
+        }
         ExceptionStackVec::AlignmentCheck => {
             let info = SignalInfo {
                 Signo: Signal::SIGBUS,
@@ -360,6 +378,29 @@ pub extern fn StackSegmentHandler(sf: &mut PtRegs, errorCode: u64) {
     ExceptionHandler(ExceptionStackVec::StackSegmentFault, sf, errorCode);
 }
 
+// EmulateRdtsc emulates the user rdtsc/rdtscp which fault with CR4.TSD in the deterministic
+// mode, it returns false if the fault is not from them
+pub fn EmulateRdtsc(task: &Task, sf: &mut PtRegs) -> bool {
+    let len = match task.CopyInVec::<u8>(sf.rip, 2) {
+        Ok(code) if code[..] == [0x0f, 0x31] => 2,
+        Ok(code) if code[..] == [0x0f, 0x01] => {
+            match task.CopyInObj::<u8>(sf.rip + 2) {
+                // rdtscp, the cpu id of the single vcpu
+                Ok(0xf9) => sf.rcx = 0,
+                _ => return false,
+            }
+            3
+        }
+        _ => return false,
+    };
+
+    let tsc = DeterministicTsc();
+    sf.rax = tsc & 0xffff_ffff;
+    sf.rdx = tsc >> 32;
+    sf.rip += len;
+    return true
+}
+
 // General Protection Fault
 #[no_mangle]
 pub extern fn GPHandler(sf: &mut PtRegs, errorCode: u64) {
@@ -374,6 +415,8 @@ bitflags! {
         const USER_MODE = 1 << 2;
         const MALFORMED_TABLE = 1 << 3;
         const INSTRUCTION_FETCH = 1 << 4;
+        const PROTECTION_KEY = 1 << 5;
+        const SHADOW_STACK = 1 << 6;
     }
 }
 
@@ -519,6 +562,25 @@ pub extern fn PageFaultHandler(ptRegs: &mut PtRegs, errorCode: u64) {
             return
         }
 
+        // the shadow stack access to the shadow stack page which is readonly and clean after
+        // fork or mprotect. The other accesses to the shadow stack are handled as readonly vma.
+        if errbits & PageFaultErrorCode::SHADOW_STACK == PageFaultErrorCode::SHADOW_STACK {
+            if !vma.shadowStack {
+                signal = Signal::SIGSEGV;
+                break;
+            }
+
+            currTask.mm.ShadowStackCopyOnWriteLocked(pageAddr);
+            if fromUser {
+                currTask.AccountTaskEnter(SchedState::RunningApp);
+                if SHARESPACE.config.read().KernelPagetable {
+                    currTask.SwitchPageTable();
+                }
+            }
+
+            return
+        }
+
         if vma.private == false {
             signal = Signal::SIGSEGV;
             break;
@@ -603,6 +665,12 @@ pub extern fn AlignmentCheckHandler(sf: &mut PtRegs, errorCode: u64) {
     ExceptionHandler(ExceptionStackVec::AlignmentCheck, sf, errorCode);
 }
 
+// Control Protection Exception, #CP of the CET shadow stack
+#[no_mangle]
+pub extern fn ControlProtectionHandler(sf: &mut PtRegs, errorCode: u64) {
+    ExceptionHandler(ExceptionStackVec::ControlProtection, sf, errorCode);
+}
+
 #[no_mangle]
 pub extern fn MachineCheckHandler(sf: &mut PtRegs) {
     ExceptionHandler(ExceptionStackVec::MachineCheck, sf, 0);
@@ -618,6 +686,11 @@ pub extern fn VirtualizationHandler(ptRegs: &mut PtRegs) {
     let mask = CPULocal::Myself().ResetInterruptMask();
     let currTask = Task::Current();
 
+    // sample before the thread timeout yields to other task
+    if CPULocal::InterruptByProfileSample(mask) {
+        PROFILER.Sample(CPULocal::CpuId(), ptRegs);
+    }
+
     if CPULocal::InterruptByTlbShootdown(mask) {
         if ptRegs.ss & 0x3 != 0 {
             // from user
@@ -634,7 +707,10 @@ pub extern fn VirtualizationHandler(ptRegs: &mut PtRegs) {
     }
 
     if CPULocal::InterruptByThreadTimeout(mask) {
-        if ptRegs.ss & 0x3 != 0 { // from user
+        if ptRegs.ss & 0x3 != 0 && SHARESPACE.scheduler.GlobalReadyTaskCnt() == 0 {
+            // no other task is waiting for the vcpu, start a new time slice
+            CPULocal::Myself().SetEnterAppTimestamp(TSC.Rdtsc());
+        } else if ptRegs.ss & 0x3 != 0 { // from user
             let mut rflags = ptRegs.eflags;
             rflags &= !USER_FLAGS_CLEAR;
             rflags |= USER_FLAGS_SET;
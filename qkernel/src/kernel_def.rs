@@ -219,7 +219,18 @@ pub fn switch(from: TaskId, to: TaskId) {
     if !SHARESPACE.config.read().KernelPagetable {
         toCtx.SwitchPageTable();
     }
-    toCtx.SetFS();
+
+    // the wait task doesn't use the fs base, keep the one of the last task for the task which
+    // may be switched back soon
+    let waitTask = CPULocal::WaitTask();
+    if from.data != waitTask {
+        fromCtx.SaveFS();
+        fromCtx.SaveShadowStack();
+    }
+    if to.data != waitTask {
+        toCtx.SetFS();
+        toCtx.SetShadowStack();
+    }
 
     fromCtx.mm.VcpuLeave();
     toCtx.mm.VcpuEnter();
@@ -232,6 +243,7 @@ pub fn switch(from: TaskId, to: TaskId) {
         context_swap(fromCtx.GetContext(), toCtx.GetContext(), 1, 0);
     }
 
+    CPULocal::Myself().SetKernelEnterTimestamp(TSC.Rdtsc());
     Task::Current().PerfGofrom(PerfType::Blocked);
     Task::Current().AccountTaskLeave(SchedState::Blocked);
 }
@@ -291,6 +303,17 @@ impl HostSpace {
         return HostSpace::HCall(&mut msg, false) as i64;
     }
 
+    // ShutdownAsync shuts down the host socket without waiting for the host, it is called in
+    // the uring completion, e.g. the SHUT_WR after the write buf is drained
+    pub fn ShutdownAsync(fd: i32, how: i32) {
+        let msg = HostOutputMsg::ShutdownAsync(qcall::ShutdownAsync {
+            fd,
+            how,
+        });
+
+        super::SHARESPACE.AQCall(&msg);
+    }
+
     pub fn Call(msg: &mut Msg, _mustAsync: bool) -> u64 {
         let current = Task::Current().GetTaskId();
 
@@ -101,12 +101,16 @@ use self::interrupt::virtualization_handler;
 
 use vcpu::CPU_LOCAL;
 use self::qlib::kernel::vcpu::*;
+use self::qlib::kernel::arch::x86_64::shadow_stack::*;
+use self::qlib::kernel::kernel::deterministic::*;
 
 use alloc::string::String;
 use core::panic::PanicInfo;
 use core::sync::atomic::AtomicI32;
+use core::sync::atomic::AtomicI64;
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
 use core::{mem, ptr};
 use qlib::mutex::*;
 
@@ -128,7 +132,6 @@ use self::qlib::loader::*;
 use self::qlib::pagetable::*;
 use self::qlib::perf_tunning::*;
 use self::qlib::vcpu_mgr::*;
-use self::qlib::linux::time::*;
 use self::syscalls::syscalls::*;
 use self::task::*;
 use self::threadmgr::task_sched::*;
@@ -159,6 +162,9 @@ pub fn AllocatorPrint(_class: usize) -> String {
 
 use self::qlib::kernel::*;
 
+// the tsc when vcpu 0 starts to boot the qkernel
+pub static BOOT_TSC: AtomicI64 = AtomicI64::new(0);
+
 pub fn SingletonInit() {
     unsafe {
         KERNEL_PAGETABLE.Init(PageTables::Init(CurrentCr3()));
@@ -187,6 +193,7 @@ pub fn SingletonInit() {
         perflog::THREAD_COUNTS.Init(QMutex::new(perflog::ThreadPerfCounters::default()));
 
         fs::file::InitSingleton();
+        quring::uring_mgr::InitSingleton();
         fs::filesystems::InitSingleton();
         interrupt::InitSingleton();
         kernel::abstract_socket_namespace::InitSingleton();
@@ -209,7 +216,7 @@ extern "C" {
 
 pub fn Init() {
     self::fs::Init();
-    self::socket::Init();
+    // the socket providers are initialized by the first socket call, see socket::socket::InitProviders
 }
 
 #[no_mangle]
@@ -277,9 +284,12 @@ pub extern "C" fn syscall_handler(
 
     let enterAppTimestamp = CPULocal::Myself().ResetEnterAppTimestamp() as i64;
     let worktime = Tsc::Scale(startTime - enterAppTimestamp) * 1000; // the thread has used up time slot
-    if worktime > CLOCK_TICK {
+    let timeSlice = SHARESPACE.TimeSlice();
+    if timeSlice != 0 && worktime > timeSlice {
         taskMgr::Yield();
     }
+    CPULocal::Myself().SetKernelEnterTimestamp(TSC.Rdtsc());
+    DeterministicTick();
 
     let res;
     let args = SyscallArguments {
@@ -299,6 +309,8 @@ pub extern "C" fn syscall_handler(
     currTask.PerfGofrom(PerfType::SysCall);
 
     res = currTask.Return();
+    IOURING.ReapLagging();
+    ASYNC_PROCESS.ProcessOverdue();
     //HostInputProcess();
     //ProcessOne();
 
@@ -455,6 +467,8 @@ pub extern "C" fn rust_main(
         SingletonInit();
 
         InitTsc();
+        BOOT_TSC.store(TSC.Rdtsc(), Ordering::SeqCst);
+        InitDeterministic(&SHARESPACE.config.read());
         InitTimeKeeper(vdsoParamAddr);
 
         //Kernel::HostSpace::KernelMsg(0, 0, 1);
@@ -470,6 +484,7 @@ pub extern "C" fn rust_main(
         self::guestfdnotifier::GUEST_NOTIFIER.InitPollHostEpoll(SHARESPACE.HostHostEpollfd());
         SetVCPCount(vcpuCnt as usize);
         VDSO.Initialization(vdsoParamAddr);
+        self::syscalls::hooks::Init();
 
         // release other vcpus
         HyperCall64(qlib::HYPERCALL_RELEASE_VCPU, 0, 0, 0);
@@ -481,6 +496,8 @@ pub extern "C" fn rust_main(
     SHARESPACE.IncrVcpuSearching();
     taskMgr::AddNewCpu();
     RegisterSysCall(syscall_entry as u64);
+    InitFsGsBase();
+    InitShadowStack(SHARESPACE.config.read().ShadowStack);
 
     //interrupts::init_idt();
     interrupt::init();
@@ -575,11 +592,20 @@ fn StartRootContainer(_para: *const u8) {
         process
     };
 
+    self::syscalls::compat::LoadCompatProfiles();
+    self::socket::hostinet::nat::LoadNatRules();
+    self::socket::hostinet::policy::LoadNetPolicy();
+
     let (_tid, entry, userStackAddr, kernelStackAddr) = {
         let mut processArgs = LOADER.Lock(task).unwrap().Init(process);
         LOADER.LoadRootProcess(&mut processArgs).unwrap()
     };
 
+    let bootTime = Scale(TSC.Rdtsc() - BOOT_TSC.load(Ordering::SeqCst));
+    SHARESPACE.kernelBootTime.store(bootTime, Ordering::SeqCst);
+    info!("boot to exec: vm init {} us, kernel boot {} us",
+          SHARESPACE.vmInitTime.load(Ordering::SeqCst), bootTime);
+
     //CreateTask(StartExecProcess, ptr::null());
     let currTask = Task::Current();
     currTask.AccountTaskEnter(SchedState::RunningApp);
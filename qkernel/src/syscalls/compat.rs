@@ -0,0 +1,115 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::SysCallID;
+use super::super::qlib::kernel::compat::*;
+use super::super::fs::anon::*;
+use super::super::fs::dirent::*;
+use super::super::fs::file::*;
+use super::super::fs::flags::*;
+use super::super::fs::fsutil::file::readonly_file::*;
+use super::super::kernel::fd_table::*;
+use super::super::Kernel::HostSpace;
+use super::super::task::*;
+use super::hooks::*;
+use super::sys_file::*;
+use super::syscalls::*;
+
+// CompatHook applies the quirks of the compat profile of the calling process
+pub struct CompatHook {}
+
+impl CompatHook {
+    // OpenSysctl opens the /proc/sys file given by the sysctl quirk
+    fn OpenSysctl(task: &mut Task, id: usize, addr: u64, flags: i32) -> Result<Option<i32>> {
+        let (path, _) = copyInPath(task, addr, false)?;
+        let value = match COMPAT_DB.Sysctl(id, &path) {
+            None => return Ok(None),
+            Some(v) => v,
+        };
+
+        let inode = NewAnonInode(task);
+        let dirent = Dirent::New(&inode, &path);
+        let file = File::New(&dirent, &FileFlags { Read: true, ..Default::default() },
+                             NewSnapshotReadonlyFileOperations(value.into_bytes()));
+        let fd = task.NewFDFrom(0, &file, &FDFlags {
+            CloseOnExec: flags & Flags::O_CLOEXEC != 0,
+        })?;
+
+        return Ok(Some(fd))
+    }
+}
+
+impl SyscallHook for CompatHook {
+    fn Name(&self) -> &'static str {
+        return "compat"
+    }
+
+    fn Enter(&self, task: &mut Task, nr: u64, args: &SyscallArguments) -> Result<()> {
+        let id = match task.mm.CompatProfile() {
+            None => return Ok(()),
+            Some(id) => id,
+        };
+
+        let argv = [args.arg0, args.arg1, args.arg2, args.arg3, args.arg4, args.arg5];
+        if let Some(ret) = COMPAT_DB.SyscallQuirk(id, nr, &argv) {
+            return Err(Error::SysCallRetCtrlWithRet(TaskRunState::RunApp, ret as u64))
+        }
+
+        let fd = if nr == SysCallID::sys_open as u64 {
+            Self::OpenSysctl(task, id, args.arg0, args.arg1 as i32)?
+        } else if nr == SysCallID::sys_openat as u64 {
+            Self::OpenSysctl(task, id, args.arg1, args.arg2 as i32)?
+        } else {
+            None
+        };
+
+        match fd {
+            None => return Ok(()),
+            Some(fd) => return Err(Error::SysCallRetCtrlWithRet(TaskRunState::RunApp, fd as u64)),
+        }
+    }
+}
+
+pub static COMPAT_HOOK: CompatHook = CompatHook {};
+
+// LoadCompatProfiles loads the compat profiles from the host and registers the hook when
+// there is any profile
+pub fn LoadCompatProfiles() {
+    let mut buf: Vec<u8> = Vec::with_capacity(COMPAT_FILE_MAX);
+    buf.resize(COMPAT_FILE_MAX, 0);
+    let ret = HostSpace::LoadCompatProfiles(&mut buf[0] as * mut _ as u64, buf.len());
+    if ret <= 0 {
+        if ret < 0 {
+            error!("load compat profiles fail with errno {}", -ret);
+        }
+        return
+    }
+
+    let profiles: Vec<CompatProfile> = match serde_json::from_slice(&buf[0..ret as usize]) {
+        Err(e) => {
+            error!("invalid compat profiles {}: {:?}", COMPAT_FILE, e);
+            return
+        }
+        Ok(p) => p,
+    };
+
+    COMPAT_DB.Load(profiles);
+    if !COMPAT_DB.IsEmpty() {
+        SYSCALL_HOOKS.Register(&COMPAT_HOOK).unwrap();
+    }
+}
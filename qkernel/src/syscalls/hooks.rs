@@ -0,0 +1,141 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Syscall hooks are the extension points of the syscall dispatcher. The optional features
+// (audit, tracing, policy, ...) register a hook instead of adding checks in SysCall.
+// When no hook is registered, the cost in the syscall path is one atomic load.
+
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use super::super::qlib::common::*;
+use super::super::qlib::mutex::*;
+use super::super::qlib::kernel::syscall_hooks::*;
+use super::super::task::*;
+use super::syscalls::*;
+
+// The hook is called with the hook list locked, it must not block or switch task.
+pub trait SyscallHook: Sync {
+    fn Name(&self) -> &'static str;
+
+    // Enter is called before the syscall, an error skips the syscall and is the syscall result
+    fn Enter(&self, _task: &mut Task, _nr: u64, _args: &SyscallArguments) -> Result<()> {
+        return Ok(())
+    }
+
+    // Exit is called with the syscall result before it is returned to the app, only when Enter of
+    // the hook has returned Ok
+    fn Exit(&self, _task: &mut Task, _nr: u64, _args: &SyscallArguments, _res: &Result<i64>) {}
+}
+
+pub struct SyscallHooks {
+    pub cnt: AtomicUsize,
+    // the lock is only held when the hooks are called, not across the syscall,
+    // a hook unregistered during a syscall doesn't get the Exit call and a hook registered
+    // during a syscall doesn't get the Exit call either as its Enter didn't run
+    pub hooks: QRwLock<Vec<&'static dyn SyscallHook>>,
+}
+
+pub static SYSCALL_HOOKS: SyscallHooks = SyscallHooks::New();
+
+impl SyscallHooks {
+    pub const fn New() -> Self {
+        return Self {
+            cnt: AtomicUsize::new(0),
+            hooks: QRwLock::new(Vec::new()),
+        }
+    }
+
+    // Register adds the hook, the hooks are called in the register order for Enter and
+    // in the reverse order for Exit
+    pub fn Register(&self, hook: &'static dyn SyscallHook) -> Result<()> {
+        let mut hooks = self.hooks.write();
+        if hooks.iter().any(|h| h.Name() == hook.Name()) {
+            return Err(Error::Common(format!("syscall hook {} is registered", hook.Name())))
+        }
+
+        if hooks.len() == MAX_SYSCALL_HOOKS {
+            return Err(Error::Common(format!("syscall hook {}: too many hooks", hook.Name())))
+        }
+
+        hooks.push(hook);
+        self.cnt.store(hooks.len(), Ordering::Release);
+        return Ok(())
+    }
+
+    pub fn Unregister(&self, name: &str) -> bool {
+        let mut hooks = self.hooks.write();
+        let len = hooks.len();
+        hooks.retain(|h| h.Name() != name);
+        self.cnt.store(hooks.len(), Ordering::Release);
+        return hooks.len() != len
+    }
+
+    #[inline]
+    pub fn Active(&self) -> bool {
+        return self.cnt.load(Ordering::Relaxed) > 0
+    }
+
+    pub fn Call(&self, task: &mut Task, nr: u64, args: &SyscallArguments, func: SyscallFn) -> Result<i64> {
+        let mut res = Ok(0);
+        let mut entered = EnteredHooks::default();
+        for hook in self.hooks.read().iter() {
+            if let Err(e) = hook.Enter(task, nr, args) {
+                res = Err(e);
+                break;
+            }
+            entered.Enter(hook.Name());
+        }
+
+        if res.is_ok() {
+            res = func(task, args);
+        }
+
+        // the hook failing the Enter and the ones behind it didn't enter
+        for hook in self.hooks.read().iter().rev() {
+            if entered.Entered(hook.Name()) {
+                hook.Exit(task, nr, args, &res);
+            }
+        }
+
+        return res
+    }
+}
+
+// ErrContextHook reports the host error context of the failed syscall, see HostErr
+pub struct ErrContextHook {}
+
+impl SyscallHook for ErrContextHook {
+    fn Name(&self) -> &'static str {
+        return "errctx"
+    }
+
+    fn Exit(&self, task: &mut Task, nr: u64, _args: &SyscallArguments, res: &Result<i64>) {
+        let errno = match res {
+            Err(Error::SysError(e)) => *e,
+            _ => 0,
+        };
+        task.ReportErrContext(nr, errno);
+    }
+}
+
+pub static ERR_CONTEXT_HOOK: ErrContextHook = ErrContextHook {};
+
+// Init registers the built-in hooks
+pub fn Init() {
+    if cfg!(debug_assertions) {
+        SYSCALL_HOOKS.Register(&ERR_CONTEXT_HOOK).unwrap();
+    }
+}
@@ -13,6 +13,9 @@
 // limitations under the License.
 
 pub mod syscalls;
+pub mod hooks;
+pub mod unimplemented;
+pub mod compat;
 pub mod sys_file;
 pub mod sys_read;
 pub mod sys_write;
@@ -45,4 +48,6 @@ pub mod sys_capability;
 pub mod sys_membarrier;
 pub mod sys_splice;
 pub mod sys_timer;
-pub mod sys_mempolicy;
\ No newline at end of file
+pub mod sys_mempolicy;
+pub mod sys_mount;
+pub mod sys_inotify;
\ No newline at end of file
@@ -17,6 +17,7 @@ use super::super::task::*;
 use super::super::Kernel::HostSpace;
 use super::super::qlib::common::*;
 use super::super::qlib::linux_def::*;
+use super::super::qlib::linux::inotify::*;
 use super::super::fs::dirent::*;
 use super::super::util::cstring::*;
 use super::super::syscalls::syscalls::*;
@@ -36,7 +37,7 @@ fn Chmod(task: &Task, d: &Dirent, mode: FileMode) -> Result<()> {
     }
 
     // File attribute changed, generate notification.
-    //d.InotifyEvent(IN_ATTRIB, 0);
+    d.InotifyEvent(IN_ATTRIB, 0);
 
     return Ok(())
 }
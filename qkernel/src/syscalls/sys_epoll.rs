@@ -159,6 +159,10 @@ pub fn WaitEpoll(task: &Task, epfd: i32, max: i32, timeout: i32) -> Result<Vec<E
     ep.EventRegister(task, &general, EVENT_READ);
     defer!(ep.EventUnregister(task, &general));
 
+    // the entries ready while the task waits are batched in its epoll ring
+    let attached = ep.AttachRing(&task.epollRing);
+    defer!(if attached { ep.DetachRing() });
+
     // Try to read the events again until we succeed, timeout or get
     // interrupted.
     loop {
@@ -27,6 +27,7 @@ use super::super::qlib::common::*;
 use super::super::qlib::linux_def::*;
 use super::super::qlib::path::*;
 use super::super::qlib::linux::fcntl::*;
+use super::super::qlib::linux::inotify::*;
 use super::super::fs::dirent::*;
 use super::super::fs::file::*;
 use super::super::fs::flags::*;
@@ -39,6 +40,8 @@ use super::super::kernel::pipe::writer::*;
 use super::super::kernel::pipe::reader_writer::*;
 use super::super::syscalls::syscalls::*;
 use super::super::kernel_def::*;
+use super::super::qlib::device::*;
+use super::super::fs::dev::dev::*;
 
 fn fileOpAt(task: &Task, dirFd: i32, path: &str,
             func: &mut FnMut(&Dirent, &Dirent, &str, u32) -> Result<()>) -> Result<()> {
@@ -205,6 +208,7 @@ pub fn openAt(task: &Task, dirFd: i32, addr: u64, flags: u32) -> Result<i32> {
         })?;
 
         fd = newFd;
+        d.InotifyEvent(IN_OPEN, 0);
 
         return Ok(())
     })?;
@@ -216,11 +220,9 @@ pub fn openAt(task: &Task, dirFd: i32, addr: u64, flags: u32) -> Result<i32> {
 pub fn SysMknode(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let path = args.arg0 as u64;
     let mode = args.arg1 as u16;
+    let dev = args.arg2 as u32;
 
-    // We don't need this argument until we support creation of device nodes.
-    //let _dev = args.arg2 as u16 as u32;
-
-    mknodeAt(task, ATType::AT_FDCWD, path, FileMode(mode))?;
+    mknodeAt(task, ATType::AT_FDCWD, path, FileMode(mode), dev)?;
     return Ok(0)
 }
 
@@ -229,15 +231,13 @@ pub fn SysMknodeat(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let dirFD = args.arg0 as i32;
     let path = args.arg1 as u64;
     let mode = args.arg2 as u16;
+    let dev = args.arg3 as u32;
 
-    // We don't need this argument until we support creation of device nodes.
-    //let _dev = args.arg3 as u16 as u32;
-
-    mknodeAt(task, dirFD, path, FileMode(mode))?;
+    mknodeAt(task, dirFD, path, FileMode(mode), dev)?;
     return Ok(0)
 }
 
-pub fn mknodeAt(task: &Task, dirFd: i32, addr: u64, mode: FileMode) -> Result<()> {
+pub fn mknodeAt(task: &Task, dirFd: i32, addr: u64, mode: FileMode, dev: u32) -> Result<()> {
     let (path, dirPath) = copyInPath(task,  addr, false)?;
 
     if dirPath {
@@ -271,7 +271,23 @@ pub fn mknodeAt(task: &Task, dirFd: i32, addr: u64, mode: FileMode) -> Result<()
             ModeType::MODE_SOCKET => {
                 return Err(Error::SysError(SysErr::EOPNOTSUPP))
             }
-            ModeType::MODE_CHARACTER_DEVICE | ModeType::MODE_BLOCK_DEVICE => {
+            // the standard character devices can be created in tmpfs, e.g. the /dev of runc
+            ModeType::MODE_CHARACTER_DEVICE => {
+                if !task.Creds().HasCapability(Capability::CAP_MKNOD) {
+                    return Err(Error::SysError(SysErr::EPERM))
+                }
+
+                let (major, minor) = DecodeDeviceId(dev);
+                let msrc = inode.lock().MountSource.clone();
+                let mode = FileMode(mode.0 & !task.Umask() as u16);
+                let device = match NewDeviceNode(task, major, minor, &task.FileOwner(), &mode, &msrc) {
+                    None => return Err(Error::SysError(SysErr::EPERM)),
+                    Some(device) => device,
+                };
+
+                return d.CreateDevice(task, root, name, &device)
+            }
+            ModeType::MODE_BLOCK_DEVICE => {
                 return Err(Error::SysError(SysErr::EPERM))
             }
             _ => return Err(Error::SysError(SysErr::EINVAL))
@@ -425,7 +441,7 @@ pub fn createAt(task: &Task, dirFd: i32, addr: u64, flags: u32, mode: FileMode)
         // automatically queued when the dirent is found. The open
         // events are implemented at the syscall layer so we need to
         // manually queue one here.
-        //found.InotifyEvent(linux.IN_OPEN, 0)
+        newFile.Dirent.InotifyEvent(IN_OPEN, 0);
 
         return Ok(())
     })?;
@@ -1582,6 +1598,7 @@ fn chown(task: &Task, d: &Dirent, uid: UID, gid: GID) -> Result<i64> {
     info!("workaround enable setowner for host inode, the owner is {:?}", &owner);
     let mut inode = d.Inode();
     inode.SetOwner(task, d, &owner)?;
+    d.InotifyEvent(IN_ATTRIB, 0);
 
     // When the owner or group are changed by an unprivileged user,
     // chown(2) also clears the set-user-ID and set-group-ID bits, but
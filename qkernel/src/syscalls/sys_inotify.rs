@@ -0,0 +1,114 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::super::kernel::fd_table::*;
+use super::super::qlib::linux::inotify::*;
+use super::super::fs::inotify::*;
+use super::super::fs::flags::*;
+use super::super::fs::dirent::*;
+use super::super::fs::attr::*;
+use super::super::task::*;
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::syscalls::syscalls::*;
+use super::sys_file::*;
+
+// InotifyInit1 implements the inotify_init1() syscalls.
+pub fn SysInotifyInit1(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let flags = args.arg0 as u32;
+
+    return InotifyInit1(task, flags)
+}
+
+// InotifyInit implements the inotify_init() syscalls.
+pub fn SysInotifyInit(task: &mut Task, _args: &SyscallArguments) -> Result<i64> {
+    return InotifyInit1(task, 0)
+}
+
+pub fn InotifyInit1(task: &mut Task, flags: u32) -> Result<i64> {
+    if flags & !(IN_NONBLOCK | IN_CLOEXEC) != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let f = NewInotify(task);
+    f.SetFlags(task, SettableFileFlags {
+        NonBlocking: flags & IN_NONBLOCK != 0,
+        ..Default::default()
+    });
+    f.flags.lock().0.NonSeekable = true;
+
+    let fd = task.NewFDFrom(0, &f, &FDFlags {
+        CloseOnExec: flags & IN_CLOEXEC != 0,
+    })?;
+
+    return Ok(fd as i64)
+}
+
+fn fdToInotify(task: &Task, fd: i32) -> Result<InotifyOperations> {
+    let file = task.GetFile(fd)?;
+
+    match file.FileOp.as_any().downcast_ref::<InotifyOperations>() {
+        None => return Err(Error::SysError(SysErr::EINVAL)),
+        Some(ino) => return Ok(ino.clone()),
+    }
+}
+
+// AddWatch implements the inotify_add_watch() syscall.
+pub fn SysInotifyAddWatch(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let fd = args.arg0 as i32;
+    let addr = args.arg1 as u64;
+    let mask = args.arg2 as u32;
+
+    // "IN_DONT_FOLLOW: Don't dereference pathname if it is a symbolic link."
+    let resolve = mask & IN_DONT_FOLLOW == 0;
+
+    // "EINVAL: The given event mask contains no valid events."
+    if mask & ALL_INOTIFY_BITS & IN_ALL_EVENTS == 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let ino = fdToInotify(task, fd)?;
+    let (path, _) = copyInPath(task, addr, false)?;
+
+    let mut wd = 0;
+    fileOpOn(task, ATType::AT_FDCWD, &path, resolve, &mut |_root: &Dirent, d: &Dirent, _remainingTraversals: u32| -> Result<()> {
+        let inode = d.Inode();
+
+        // "IN_ONLYDIR: Only watch pathname if it is a directory."
+        if mask & IN_ONLYDIR != 0 && !inode.StableAttr().IsDir() {
+            return Err(Error::SysError(SysErr::ENOTDIR))
+        }
+
+        // Requires read permission on the file.
+        inode.CheckPermission(task, &PermMask {
+            read: true,
+            ..Default::default()
+        })?;
+
+        wd = ino.AddWatch(&inode, mask & ALL_INOTIFY_BITS)?;
+        Ok(())
+    })?;
+
+    return Ok(wd as i64)
+}
+
+// RmWatch implements the inotify_rm_watch() syscall.
+pub fn SysInotifyRmWatch(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let fd = args.arg0 as i32;
+    let wd = args.arg1 as i32;
+
+    let ino = fdToInotify(task, fd)?;
+    ino.RemoveWatch(wd)?;
+    return Ok(0)
+}
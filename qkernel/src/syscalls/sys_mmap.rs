@@ -56,6 +56,7 @@ pub fn SysMmap(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         Perms: AccessType(prot),
         MaxPerms: AccessType::AnyAccess(),
         GrowsDown: flags & MmapFlags::MAP_GROWSDOWN != 0,
+        ShadowStack: false,
         Precommit: flags & MmapFlags::MAP_POPULATE != 0,
         MLockMode: MLockMode::default(),
         Kernel: false,
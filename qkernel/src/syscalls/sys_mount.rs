@@ -0,0 +1,220 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+
+use super::super::task::*;
+use super::super::qlib::auth::cap_set::*;
+use super::super::util::cstring::*;
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::fs::dirent::*;
+use super::super::fs::filesystems::*;
+use super::super::syscalls::syscalls::*;
+use super::sys_file::*;
+
+// the flags which only change the propagation type of the mount
+const MS_PROPAGATION: u64 = LibcConst::MS_SHARED | LibcConst::MS_PRIVATE | LibcConst::MS_SLAVE |
+    LibcConst::MS_UNBINDABLE;
+
+fn lookup(task: &Task, path: &str, resolve: bool) -> Result<Dirent> {
+    let mut dirent = None;
+    fileOpOn(task, ATType::AT_FDCWD, path, resolve, &mut |_root: &Dirent, d: &Dirent, _remainingTraversals: u32| -> Result<()> {
+        dirent = Some(d.clone());
+        Ok(())
+    })?;
+
+    return Ok(dirent.unwrap())
+}
+
+fn lookupDir(task: &Task, path: &str) -> Result<Dirent> {
+    let d = lookup(task, path, true)?;
+    if !d.Inode().StableAttr().IsDir() {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    return Ok(d)
+}
+
+// MountFlags returns the mount source flags of the mount(2) flags
+pub fn MountFlags(flags: u64) -> MountSourceFlags {
+    return MountSourceFlags {
+        ReadOnly: flags & LibcConst::MS_RDONLY != 0,
+        NoAtime: flags & LibcConst::MS_NOATIME != 0,
+        NoExec: flags & LibcConst::MS_NOEXEC != 0,
+        ..Default::default()
+    }
+}
+
+// Mount implements Linux syscall mount(2).
+pub fn SysMount(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let sourceAddr = args.arg0 as u64;
+    let targetAddr = args.arg1 as u64;
+    let typeAddr = args.arg2 as u64;
+    let mut flags = args.arg3 as u64;
+    let dataAddr = args.arg4 as u64;
+
+    // "If the mountflags argument has the value MS_MGC_VAL in the top 16 bits,
+    // then this value is ignored" - mount(2)
+    if flags & LibcConst::MS_MGC_MSK == LibcConst::MS_MGC_VAL {
+        flags &= !LibcConst::MS_MGC_MSK;
+    }
+
+    if !task.Creds().HasCapability(Capability::CAP_SYS_ADMIN) {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    let (target, _) = copyInPath(task, targetAddr, false)?;
+
+    // There is one mount table shared by the whole sandbox, the propagation type is always private.
+    if flags & MS_PROPAGATION != 0 {
+        lookup(task, &target, true)?;
+        return Ok(0)
+    }
+
+    // The remount only changes the flags of the mount, which are not tracked per mount.
+    if flags & LibcConst::MS_REMOUNT != 0 {
+        lookup(task, &target, true)?;
+        return Ok(0)
+    }
+
+    if flags & LibcConst::MS_MOVE != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let targetDirent = lookup(task, &target, true)?;
+
+    if flags & LibcConst::MS_BIND != 0 {
+        // The submounts of the source are not carried over, MS_REC binds the top mount only.
+        let (source, _) = copyInPath(task, sourceAddr, false)?;
+        let sourceDirent = lookup(task, &source, true)?;
+        if sourceDirent.Inode().StableAttr().IsDir() != targetDirent.Inode().StableAttr().IsDir() {
+            return Err(Error::SysError(SysErr::ENOTDIR))
+        }
+
+        task.mountNS.Mount(&targetDirent, &sourceDirent.Inode())?;
+        return Ok(0)
+    }
+
+    let fsType = CString::ToString(task, typeAddr)?;
+    let source = if sourceAddr == 0 {
+        String::new()
+    } else {
+        CString::ToString(task, sourceAddr)?
+    };
+
+    let data = if dataAddr == 0 {
+        String::new()
+    } else {
+        CString::ToString(task, dataAddr)?
+    };
+
+    let fs = match FindFilesystem(&fsType) {
+        None => return Err(Error::SysError(SysErr::ENODEV)),
+        Some(fs) => fs,
+    };
+
+    if !fs.lock().AllowUserMount() {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    if !targetDirent.Inode().StableAttr().IsDir() {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    let inode = match fs.lock().Mount(task, &source, &MountFlags(flags), &data) {
+        Ok(inode) => inode,
+        Err(Error::SysError(SysErr::ENOENT)) => return Err(Error::SysError(SysErr::ENOENT)),
+        Err(_) => return Err(Error::SysError(SysErr::EINVAL)),
+    };
+
+    task.mountNS.Mount(&targetDirent, &inode)?;
+    return Ok(0)
+}
+
+// Umount2 implements Linux syscall umount2(2).
+pub fn SysUmount2(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let addr = args.arg0 as u64;
+    let flags = args.arg1 as u64;
+
+    // MNT_FORCE is only meaningful for the network filesystems and MNT_EXPIRE needs the mount
+    // to be marked by a previous call, neither applies here.
+    if flags & (LibcConst::MNT_FORCE | LibcConst::MNT_EXPIRE) != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    if flags & !(LibcConst::MNT_DETACH | LibcConst::UMOUNT_NOFOLLOW) != 0 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    if !task.Creds().HasCapability(Capability::CAP_SYS_ADMIN) {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    let (path, _) = copyInPath(task, addr, false)?;
+    let resolve = flags & LibcConst::UMOUNT_NOFOLLOW == 0;
+    let detachOnly = flags & LibcConst::MNT_DETACH != 0;
+
+    let d = lookup(task, &path, resolve)?;
+
+    // pivot_root(".", ".") stacks the old root under the new one and the old root is then
+    // detached through the new root, the old root is already out of the task view.
+    let root = task.Root();
+    if d == root && root != task.mountNS.Root() && detachOnly {
+        return Ok(0)
+    }
+
+    task.mountNS.Unmount(&d, detachOnly)?;
+    return Ok(0)
+}
+
+// PivotRoot implements Linux syscall pivot_root(2).
+// The old root is bound at put_old and the new root becomes the root and, when it was the old
+// root, the working directory of the task. The tasks sharing the fs context share the change.
+pub fn SysPivotRoot(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    let newRootAddr = args.arg0 as u64;
+    let putOldAddr = args.arg1 as u64;
+
+    if !task.Creds().HasCapability(Capability::CAP_SYS_ADMIN) {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    let (newRootPath, _) = copyInPath(task, newRootAddr, false)?;
+    let (putOldPath, _) = copyInPath(task, putOldAddr, false)?;
+
+    let newRoot = lookupDir(task, &newRootPath)?;
+    let putOld = lookupDir(task, &putOldPath)?;
+
+    let oldRoot = task.Root();
+    if newRoot == oldRoot {
+        return Err(Error::SysError(SysErr::EBUSY))
+    }
+
+    // "put_old must be at or underneath new_root" - pivot_root(2)
+    if !putOld.DescendantOf(&newRoot) {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    if putOld != newRoot {
+        task.mountNS.Mount(&putOld, &oldRoot.Inode())?;
+    }
+
+    if task.Workdir() == oldRoot {
+        task.fsContext.SetWorkDirectory(&newRoot);
+    }
+
+    task.fsContext.SetRootDirectory(&newRoot);
+    return Ok(0)
+}
+
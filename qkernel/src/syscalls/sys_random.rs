@@ -17,6 +17,7 @@ use super::super::Kernel::HostSpace;
 use super::super::qlib::linux_def::*;
 use super::super::syscalls::syscalls::*;
 use super::super::task::Task;
+use super::super::kernel::deterministic::*;
 
 pub fn SysGetRandom(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let addr = args.arg0;
@@ -32,7 +33,14 @@ pub fn SysGetRandom(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         length = core::i32::MAX as u32;
     }
 
-    let buf = DataBuff::New(length as usize);
+    // same as linux the large read is short
+    let mut buf = DataBuff::NewUserIO(length as usize)?;
+    let length = buf.Len() as u32;
+    if IsDeterministic() {
+        task.DeterministicRandom(&mut buf.buf[..]);
+        task.CopyOutSlice(&buf.buf[..], addr, length as usize)?;
+        return Ok(length as i64)
+    }
 
     let ret = HostSpace::GetRandom(buf.Ptr(), buf.Len() as u64, flags as u32);
     if ret < 0 {
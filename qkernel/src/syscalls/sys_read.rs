@@ -20,6 +20,7 @@ use super::super::task::*;
 use super::super::qlib::common::*;
 use super::super::qlib::mem::block::*;
 use super::super::qlib::linux_def::*;
+use super::super::qlib::linux::inotify::*;
 use super::super::syscalls::syscalls::*;
 use super::super::kernel_def::*;
 
@@ -56,6 +57,9 @@ pub fn Read(task: &Task, fd: i32, addr: u64, size: i64) -> Result<i64> {
     let mut iovs: [IoVec; 1] = [iov];
 
     let n = readv(task, &file, &mut iovs)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_ACCESS, 0);
+    }
     /*if fd == 0 {
         use alloc::string::ToString;
         use super::super::qlib::util::*;
@@ -106,7 +110,12 @@ pub fn Pread64(task: &Task, fd: i32, addr: u64, size: i64, offset: i64) -> Resul
 
     let iov = IoVec::NewFromAddr(addr, size as usize);
     let mut iovs: [IoVec; 1] = [iov];
-    return preadv(task, &file, &mut iovs, offset)
+    let n = preadv(task, &file, &mut iovs, offset)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_ACCESS, 0);
+    }
+
+    return Ok(n)
 }
 
 pub fn SysReadv(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
@@ -132,7 +141,12 @@ pub fn Readv(task: &Task, fd: i32, addr: u64, iovcnt: i32) -> Result<i64> {
 
     let mut dsts = task.IovsFromAddr(addr, iovcnt as usize)?;
 
-    return readv(task, &file, &mut dsts);
+    let n = readv(task, &file, &mut dsts)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_ACCESS, 0);
+    }
+
+    return Ok(n)
 }
 
 pub fn SysPreadv(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
@@ -205,7 +219,12 @@ pub fn Preadv(task: &Task, fd: i32, addr: u64, iovcnt: i32, offset: i64) -> Resu
     }
 
     let mut dsts = task.IovsFromAddr(addr, iovcnt as usize)?;
-    return preadv(task, &file, &mut dsts, offset);
+    let n = preadv(task, &file, &mut dsts, offset)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_ACCESS, 0);
+    }
+
+    return Ok(n)
 }
 
 fn RepReadv(task: &Task, f: &File, dsts: &mut [IoVec]) -> Result<i64> {
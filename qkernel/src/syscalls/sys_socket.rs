@@ -29,13 +29,12 @@ use super::super::kernel::time::*;
 use super::super::qlib::linux::time::*;
 //use super::super::qlib::linux::socket::*;
 use super::super::kernel::timer::*;
+use super::super::fs::procfs::sys::sysctl::*;
+use super::sys_write::*;
 
 // minListenBacklog is the minimum reasonable backlog for listening sockets.
 const MIN_LISTEN_BACKLOG: u32 = 8;
 
-// maxListenBacklog is the maximum allowed backlog for listening sockets.
-const MAX_LISTEN_BACKLOG: u32 = 1024;
-
 // maxAddrLen is the maximum socket address length we're willing to accept.
 const MAX_ADDR_LEN: u32 = 200;
 
@@ -47,6 +46,11 @@ const MAX_OPT_LEN: u32 = 1024;
 // buffers upto INT_MAX.
 const MAX_CONTROL_LEN: usize = 10 * 1024 * 1024;
 
+// CONTROL_BUF_MAX is the kernel buffer of the control messages, same as the optmem_max of
+// linux. The larger control of sendmsg fails with ENOBUFS, the received control messages are
+// far smaller and the larger buffer of recvmsg is only used up to it.
+const CONTROL_BUF_MAX: usize = 128 * 1024;
+
 // nameLenOffset is the offset from the start of the MessageHeader64 struct to
 // the NameLen field.
 const NAME_LEN_OFFSET: u32 = 8;
@@ -260,8 +264,10 @@ pub fn SysListen(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         backlog = MIN_LISTEN_BACKLOG as i32;
     }
 
-    if backlog >= MAX_LISTEN_BACKLOG as i32 {
-        backlog = MAX_LISTEN_BACKLOG as i32;
+    // the max backlog is net.core.somaxconn of the sandbox
+    let somaxconn = SYSCTL.Somaxconn();
+    if backlog >= somaxconn {
+        backlog = somaxconn;
     }
 
     let res = sock.Listen(task, backlog);
@@ -406,28 +412,19 @@ fn recvSingleMsg(task: &Task, sock: &Arc<FileOperations>, msgPtr: u64, flags: i3
     //let msg = task.GetTypeMut::<MsgHdr>(msgPtr)?;
 
     let mut msg : MsgHdr = task.CopyInObj(msgPtr)?;
-    if msg.iovLen > UIO_MAXIOV {
-        return Err(Error::SysError(SysErr::EMSGSIZE))
-    }
-    
-    if msg.msgControl == 0 {
-        msg.msgControlLen = 0;
-    }
-
-    if msg.msgName == 0 {
-        msg.nameLen = 0;
-    }
-
-    let mut dst = task.IovsFromAddr(msg.iov, msg.iovLen)?;
+    let mut req = recvMsgIn(task, &mut msg)?;
+    let dst = &mut req.dsts;
 
     if flags & MsgType::MSG_ERRQUEUE != 0 {
-        // Pretend we have an empty error queue.
-        return Err(Error::SysError(SysErr::EAGAIN))
+        let ret = sock.RecvErrQueue(task, dst, flags, req.senderRequested, req.controlDataLen)?;
+        let n = recvMsgOut(task, &mut msg, ret)?;
+        task.CopyOutObj(&msg, msgPtr)?;
+        return Ok(n)
     }
 
     // Fast path when no control message nor name buffers are provided.
     if msg.msgControlLen == 0 && msg.nameLen == 0 {
-        let (n, mut mflags, _ , controlMessageBuffer) = sock.RecvMsg(task, &mut dst, flags, deadline, false, 0)?;
+        let (n, mut mflags, _ , controlMessageBuffer) = sock.RecvMsg(task, dst, flags, deadline, false, 0)?;
 
         if controlMessageBuffer.len() != 0 {
             mflags |= MsgType::MSG_CTRUNC;
@@ -437,16 +434,9 @@ fn recvSingleMsg(task: &Task, sock: &Arc<FileOperations>, msgPtr: u64, flags: i3
         return Ok(n as i64)
     }
 
-    if msg.msgControlLen > MAX_CONTROL_LEN {
-        return Err(Error::SysError(SysErr::ENOBUFS))
-    }
-
-
-    let mut addressVec: Vec<u8> = vec![0; msg.nameLen as usize];
     //let mut controlVec: Vec<u8> = vec![0; msg.msgControlLen as usize];
 
-
-    let (n, mflags, sender, controlMessageBuffer) = sock.RecvMsg(task, &mut dst, flags, deadline, msg.nameLen!=0, msg.msgControlLen)?;
+    let ret = sock.RecvMsg(task, dst, flags, deadline, req.senderRequested, req.controlDataLen)?;
 
     /* 
      let controlData = &mut controlVec[..];
@@ -496,33 +486,73 @@ fn recvSingleMsg(task: &Task, sock: &Arc<FileOperations>, msgPtr: u64, flags: i3
 
     msg.msgControlLen = msg.msgControlLen - controlData.len();
     */
+    let n = recvMsgOut(task, &mut msg, ret)?;
+    task.CopyOutObj(&msg, msgPtr)?;
+    return Ok(n)
+}
+
+// recvMsgIn checks the msghdr of recvmsg and recvmmsg and returns the buffers to receive to
+fn recvMsgIn(task: &Task, msg: &mut MsgHdr) -> Result<MMsgRecv> {
+    if msg.iovLen > UIO_MAXIOV {
+        return Err(Error::SysError(SysErr::EMSGSIZE))
+    }
+
+    if msg.msgControl == 0 {
+        msg.msgControlLen = 0;
+    }
+
+    if msg.msgName == 0 {
+        msg.nameLen = 0;
+    }
+
+    // same as linux, the name buffer is used up to the max address
+    if msg.nameLen > MAX_ADDR_LEN {
+        msg.nameLen = MAX_ADDR_LEN;
+    }
+
+    if msg.msgControlLen > MAX_CONTROL_LEN {
+        return Err(Error::SysError(SysErr::ENOBUFS))
+    }
+
+    return Ok(MMsgRecv {
+        dsts: task.IovsFromAddr(msg.iov, msg.iovLen)?,
+        senderRequested: msg.nameLen != 0,
+        controlDataLen: core::cmp::min(msg.msgControlLen, CONTROL_BUF_MAX),
+    })
+}
+
+// recvMsgOut copies the sender address and the control messages of the received message to the
+// buffers of msg and updates msg
+fn recvMsgOut(task: &Task, msg: &mut MsgHdr, ret: RecvMsgResult) -> Result<i64> {
+    let (n, mut mflags, sender, controlMessageBuffer) = ret;
     msg.msgControlLen = controlMessageBuffer.len();
 
     if msg.nameLen != 0 && msg.msgName != 0 && sender.is_some() {
         let (sender, senderLen) = sender.unwrap();
-        if msg.nameLen < senderLen as u32 {
-            return Err(Error::SysError(SysErr::ERANGE));
-        }
+        let mut addressVec: Vec<u8> = vec![0; senderLen];
         sender.Marsh(&mut addressVec[..], senderLen)?;
-        task.CopyOutSlice(&addressVec[0..senderLen], msg.msgName, msg.nameLen as usize)?;
+        // same as linux, the address is truncated to the buffer and the name length is the
+        // full one
+        let len = core::cmp::min(senderLen, msg.nameLen as usize);
+        task.CopyOutSlice(&addressVec[0..len], msg.msgName, len)?;
         msg.nameLen = senderLen as u32;
     }
     if msg.msgControl!=0 && msg.msgControlLen!=0 {
         task.CopyOutSlice(&controlMessageBuffer[0..msg.msgControlLen as usize], msg.msgControl, msg.msgControlLen)?;
     } else {
+        if msg.msgControlLen != 0 {
+            mflags |= MsgType::MSG_CTRUNC;
+        }
         msg.msgControlLen = 0;
     }
 
     msg.msgFlags = mflags;
-
-    task.CopyOutObj(&msg, msgPtr)?;
     return Ok(n)
 }
 
-fn sendSingleMsg(task: &Task, sock: &Arc<FileOperations>, msgPtr: u64, flags: i32, deadline: Option<Time>) -> Result<i64> {
-    let msg = task.CopyInObj::<MsgHdr>(msgPtr)?;
-
-    if msg.msgControlLen > MAX_CONTROL_LEN as usize {
+// sendMsgIn copies in the name and the control of the msghdr of sendmsg and sendmmsg
+fn sendMsgIn(task: &Task, msg: &MsgHdr) -> Result<MMsgSend> {
+    if msg.msgControlLen > CONTROL_BUF_MAX {
         return Err(Error::SysError(SysErr::ENOBUFS))
     }
 
@@ -530,10 +560,14 @@ fn sendSingleMsg(task: &Task, sock: &Arc<FileOperations>, msgPtr: u64, flags: i3
         return Err(Error::SysError(SysErr::EMSGSIZE))
     }
 
+    if msg.nameLen > MAX_ADDR_LEN {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
     let msgVec: Vec<u8> = task.CopyInVec(msg.msgName, msg.nameLen as usize)?;
     let controlVec: Vec<u8> = task.CopyInVec(msg.msgControl, msg.msgControlLen as usize)?;
 
-    let mut pMsg = msg;
+    let mut pMsg = *msg;
     if msg.nameLen > 0 {
         pMsg.msgName = &msgVec[0] as *const _ as u64;
     }
@@ -542,9 +576,19 @@ fn sendSingleMsg(task: &Task, sock: &Arc<FileOperations>, msgPtr: u64, flags: i3
         pMsg.msgControl = &controlVec[0] as *const _ as u64;
     }
 
-    let src = task.IovsFromAddr(msg.iov, msg.iovLen)?;
+    return Ok(MMsgSend {
+        srcs: task.IovsFromAddr(msg.iov, msg.iovLen)?,
+        msgHdr: pMsg,
+        name: msgVec,
+        control: controlVec,
+    })
+}
+
+fn sendSingleMsg(task: &Task, sock: &Arc<FileOperations>, msgPtr: u64, flags: i32, deadline: Option<Time>) -> Result<i64> {
+    let msg = task.CopyInObj::<MsgHdr>(msgPtr)?;
+    let mut req = sendMsgIn(task, &msg)?;
 
-    let res = sock.SendMsg(task, &src, flags, &mut pMsg, deadline)?;
+    let res = sock.SendMsg(task, &req.srcs, flags, &mut req.msgHdr, deadline)?;
     task.CopyOutObj(&msg, msgPtr)?;
     return Ok(res);
 }
@@ -582,7 +626,7 @@ pub fn SysRecvMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 pub fn SysRecvMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let fd = args.arg0 as i32;
     let msgPtr = args.arg1 as u64;
-    let vlen = args.arg2 as u32;
+    let mut vlen = args.arg2 as u32;
     let mut flags = args.arg3 as i32;
     let timeout = args.arg4 as u64;
 
@@ -590,10 +634,18 @@ pub fn SysRecvMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 
     let sock = file.FileOp.clone();
 
-    if flags & !(MsgType::BASE_RECV_FLAGS | MsgType::MSG_CMSG_CLOEXEC | MsgType::MSG_ERRQUEUE) != 0 {
+    if flags & !(MsgType::BASE_RECV_FLAGS | MsgType::MSG_PEEK | MsgType::MSG_WAITFORONE | MsgType::MSG_CMSG_CLOEXEC | MsgType::MSG_ERRQUEUE) != 0 {
         return Err(Error::SysError(SysErr::EINVAL))
     }
 
+    if vlen > UIO_MAXIOV as u32 {
+        vlen = UIO_MAXIOV as u32;
+    }
+
+    if !file.Blocking() {
+        flags |= MsgType::MSG_DONTWAIT
+    }
+
     let mut deadline = None;
     if timeout != 0 {
         let timePtr = task.CopyInObj::<Timespec>(timeout)?;
@@ -612,39 +664,32 @@ pub fn SysRecvMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         }
     }
 
-    let mut count = 0;
-    let mut res = 0;
     //let msgs = task.GetSliceMut::<MMsgHdr>(msgPtr, vlen as usize)?;
     let mut msgs = task.CopyInVec::<MMsgHdr>(msgPtr, vlen as usize)?;
+    let mut reqs = Vec::with_capacity(msgs.len());
+    for msg in msgs.iter_mut() {
+        reqs.push(recvMsgIn(task, &mut msg.msgHdr)?);
+    }
 
-    info!("SysRecvMMsg 1 vlen is {}", vlen);
-    for i in 0..vlen as usize {
-        res = match recvSingleMsg(task, &sock, &(msgs[i].msgHdr) as *const MsgHdr as u64, flags, deadline) {
+    let rets = sock.RecvMMsg(task, &mut reqs, flags, deadline)?;
+    let mut count = 0;
+    for ret in rets {
+        match recvMsgOut(task, &mut msgs[count].msgHdr, ret) {
             Err(e) => {
                 if count > 0 {
-                    return Ok(count)
+                    break;
                 }
 
                 return Err(e)
             }
-            Ok(n) => n,
-        };
-
-        if res < 0 {
-            break;
+            Ok(n) => msgs[count].msgLen = n as u32,
         }
 
-        msgs[i].msgLen = res as u32;
         count += 1;
     }
 
-    if count == 0 {
-        return Err(Error::SysError(-res as i32))
-    }
-
-    task.CopyOutSlice(&msgs, msgPtr, vlen as usize)?;
-
-    return Ok(count)
+    task.CopyOutSlice(&msgs[..count], msgPtr, vlen as usize)?;
+    return Ok(count as i64)
 }
 
 pub const BASE_RECV_FLAGS :i32 = MsgType::MSG_OOB
@@ -689,10 +734,13 @@ pub fn SysRecvFrom(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let mut nameLen: i32 = 0;
     if nameLenPtr != 0 {
         nameLen = task.CopyInObj(nameLenPtr)?;
+        if nameLen < 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
     }
 
     //todo: handle the msg.nameLen > 1024
-    let _msgVec = if namePtr != 0 {
+    let _msgVec = if namePtr != 0 && nameLen > 0 {
         let msgVec: Vec<u8> = vec![0; nameLen as usize];
         pMsg.msgName = &msgVec[0] as *const _ as u64;
         msgVec
@@ -714,14 +762,13 @@ pub fn SysRecvFrom(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     if nameLenPtr != 0 && sender.is_some() {
         let (sender, senderLen) = sender.unwrap();
         if senderLen != 2 {
-            if nameLen < senderLen as i32 {
-                return Err(Error::SysError(SysErr::ERANGE));
-            }
             //let slices = task.GetSliceMut::<u8>(namePtr, nameLen as usize)?;
             //sender.Marsh(slices, senderLen)?;
-            let mut dataBuf = DataBuff::New(nameLen as usize);
+            let mut dataBuf = DataBuff::New(senderLen as usize);
             sender.Marsh(&mut dataBuf.buf, senderLen)?;
-            task.CopyOutSlice(&mut dataBuf.buf, namePtr, nameLen as usize)?;
+            // the data is consumed already, the address is truncated to the buffer like recvmsg
+            let len = core::cmp::min(senderLen, nameLen as usize);
+            task.CopyOutSlice(&dataBuf.buf[0..len], namePtr, len)?;
             //task.CopyOutSlice(&msgVec[0..pMsg.nameLen as usize], namePtr, nameLen as usize)?;
             task.CopyOutObj(&(senderLen as u32), nameLenPtr)?;
         } else {
@@ -742,7 +789,7 @@ pub fn SysSendMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 
     let sock = file.FileOp.clone();
 
-    if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
+    if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_FASTOPEN | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
         return Err(Error::SysError(SysErr::EINVAL))
     }
 
@@ -759,21 +806,25 @@ pub fn SysSendMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         flags |= MsgType::MSG_DONTWAIT
     }
 
-    let res = sendSingleMsg(task, &sock, msgPtr, flags, deadline)?;
-    return Ok(res)
+    let res = sendSingleMsg(task, &sock, msgPtr, flags, deadline);
+    if flags & MsgType::MSG_NOSIGNAL != 0 {
+        return res
+    }
+
+    return RaiseSigPipe(task, res)
 }
 
 pub fn SysSendMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let fd = args.arg0 as i32;
     let msgPtr = args.arg1 as u64;
-    let vlen = args.arg2 as u32;
+    let mut vlen = args.arg2 as u32;
     let mut flags = args.arg3 as i32;
 
     let file = task.GetFile(fd)?;
 
     let sock = file.FileOp.clone();
 
-    if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
+    if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_FASTOPEN | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
         return Err(Error::SysError(SysErr::EINVAL))
     }
 
@@ -790,28 +841,29 @@ pub fn SysSendMMsg(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         flags |= MsgType::MSG_DONTWAIT
     }
 
-    let mut count = 0;
-    let mut res = 0;
+    if vlen > UIO_MAXIOV as u32 {
+        vlen = UIO_MAXIOV as u32;
+    }
+
     //let msgs = task.GetSliceMut::<MMsgHdr>(msgPtr, vlen as usize)?;
     let mut msgs = task.CopyInVec::<MMsgHdr>(msgPtr, vlen as usize)?;
-    for i in 0..vlen as usize {
-        res = sendSingleMsg(task, &sock, &(msgs[i].msgHdr) as *const MsgHdr as u64, flags, deadline)?;
-
-        if res < 0 {
-            break;
-        }
-
-        msgs[i].msgLen = res as u32;
-        count += 1;
+    let mut reqs = Vec::with_capacity(msgs.len());
+    for msg in &msgs {
+        reqs.push(sendMsgIn(task, &msg.msgHdr)?);
     }
 
-    if count == 0 {
-        return Err(Error::SysError(-res as i32))
+    let mut rets = sock.SendMMsg(task, &mut reqs, flags, deadline);
+    if flags & MsgType::MSG_NOSIGNAL == 0 {
+        rets = RaiseSigPipe(task, rets);
     }
 
-    task.CopyOutSlice(&msgs, msgPtr, vlen as usize)?;
+    let rets = rets?;
+    for (i, n) in rets.iter().enumerate() {
+        msgs[i].msgLen = *n as u32;
+    }
 
-    return Ok(count)
+    task.CopyOutSlice(&msgs[..rets.len()], msgPtr, vlen as usize)?;
+    return Ok(rets.len() as i64)
 }
 
 pub fn SysSendTo(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
@@ -862,8 +914,12 @@ pub fn SysSendTo(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         flags |= MsgType::MSG_DONTWAIT;
     }
 
-    let res = sock.SendMsg(task, &iovs, flags, &mut pMsg, deadline)?;
-    return Ok(res);
+    let res = sock.SendMsg(task, &iovs, flags, &mut pMsg, deadline);
+    if flags & MsgType::MSG_NOSIGNAL != 0 {
+        return res
+    }
+
+    return RaiseSigPipe(task, res)
 }
 
 
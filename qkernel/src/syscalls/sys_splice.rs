@@ -20,6 +20,7 @@ use super::super::task::*;
 use super::super::qlib::common::*;
 use super::super::qlib::linux_def::*;
 use super::super::syscalls::syscalls::*;
+use super::sys_write::*;
 
 // Splice moves data to this file, directly from another.
 //
@@ -69,7 +70,7 @@ pub fn Splice(task: &Task, dst: &File, src: &File, opts: &mut SpliceOpts) -> Res
         opts.DstStart = *dstLock;
     } else if !srcPipe && !opts.SrcOffset {
         srcLock = src.offset.Lock(task)?;
-        opts.SrcStart = *dstLock;
+        opts.SrcStart = *srcLock;
     }
 
     // Check append-only mode and the limit.
@@ -112,7 +113,7 @@ pub fn Splice(task: &Task, dst: &File, src: &File, opts: &mut SpliceOpts) -> Res
                     // back to a slow path in this case. This copies without doing
                     // any mode changes, so should still be more efficient.
 
-                    let buf = DataBuff::New(opts.Length as usize);
+                    let buf = DataBuff::NewUserIO(opts.Length as usize)?;
                     let mut iovs = buf.Iovs();
 
                     let srcStart = if opts.SrcOffset {
@@ -309,7 +310,7 @@ pub fn SysSplice(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         return Err(Error::SysError(SysErr::EINVAL));
     }
 
-    return DoSplice(task, &dst, &src, &mut opts, nonBlocking)
+    return RaiseSigPipe(task, DoSplice(task, &dst, &src, &mut opts, nonBlocking))
 }
 
 pub fn SysSendfile(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
@@ -346,26 +347,26 @@ pub fn SysSendfile(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 
         let offset : i64 = task.CopyInObj(offsetAddr)?;
 
-        n = DoSplice(task, &outFile, &inFile, &mut SpliceOpts{
+        n = RaiseSigPipe(task, DoSplice(task, &outFile, &inFile, &mut SpliceOpts{
             Length: count,
             SrcOffset: true,
             SrcStart: offset,
             Dup: false,
             DstOffset: false,
             DstStart: 0,
-        }, outFile.Flags().NonBlocking)?;
+        }, outFile.Flags().NonBlocking))?;
 
         //*task.GetTypeMut(offsetAddr)? = offset + n;
         task.CopyOutObj(&(offset + n), offsetAddr)?;
     } else {
-        n = DoSplice(task, &outFile, &inFile, &mut SpliceOpts{
+        n = RaiseSigPipe(task, DoSplice(task, &outFile, &inFile, &mut SpliceOpts{
             Length: count,
             SrcOffset: false,
             SrcStart: 0,
             Dup: false,
             DstOffset: false,
             DstStart: 0,
-        }, outFile.Flags().NonBlocking)?;
+        }, outFile.Flags().NonBlocking))?;
     }
 
     return Ok(n)
@@ -38,6 +38,8 @@ use super::super::threadmgr::task_exit::*;
 use super::super::threadmgr::task_exec::*;
 use super::super::threadmgr::task_clone::*;
 use super::super::threadmgr::task_sched::*;
+use super::super::qlib::auth::cap_set::*;
+use super::super::qlib::limits::*;
 use super::super::memmgr::mm::*;
 use super::super::SHARESPACE;
 
@@ -286,6 +288,7 @@ pub fn SysExecve(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 
             SetFs(0);
             task.context.fs = 0;
+            task.ResetShadowStack();
 
             let newMM = MemoryManager::Init(false);
             let oldMM = task.mm.clone();
@@ -583,6 +586,15 @@ pub fn SysSetTidAddr(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
 pub fn SysUnshare(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let flags = args.arg0 as i32;
 
+    // The sandbox has one mount table and one cgroup view, so the mount and cgroup namespaces
+    // are not supported and fail the call rather than being shared silently.
+    const UNSHARE_FLAGS: i32 = CloneOp::CLONE_VM | CloneOp::CLONE_SIGHAND | CloneOp::CLONE_THREAD |
+        CloneOp::CLONE_NEWPID | CloneOp::CLONE_NEWUSER | CloneOp::CLONE_NEWNET | CloneOp::CLONE_FILES |
+        CloneOp::CLONE_FS | CloneOp::CLONE_NEWUTS | CloneOp::CLONE_NEWIPC | CloneOp::CLONE_SYSVSEM;
+    if flags & !UNSHARE_FLAGS != 0 {
+        return Err(Error::SysError(SysErr::EINVAL));
+    }
+
     let mut opts = SharingOptions {
         NewAddressSpace: flags & CloneOp::CLONE_VM == CloneOp::CLONE_VM,
         NewSignalHandlers: flags & CloneOp::CLONE_SIGHAND == CloneOp::CLONE_SIGHAND,
@@ -607,7 +619,6 @@ pub fn SysUnshare(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     if opts.NewUserNamespace {
         opts.NewThreadGroup = true;
         opts.NewFSContext = true;
-        panic!("Doesn't support create new usernamespace...");
     }
 
     task.Unshare(&opts)?;
@@ -872,9 +883,8 @@ pub fn SysGetpriority(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     }
 }
 
-// Setpriority pretends to implement the linux syscall setpriority(2).
-//
-// This is a stub; real priorities require a full scheduler.
+// Setpriority implements the linux syscall setpriority(2), the niceness picks the level of the
+// ready queue of the task.
 pub fn SysSetpriority(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let which = args.arg0 as i32;
     let who = args.arg1 as i32;
@@ -901,6 +911,31 @@ pub fn SysSetpriority(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
                 }
             };
 
+            // "The caller must have the CAP_SYS_NICE capability or be the owner of the target
+            // process" and "the RLIMIT_NICE resource limit can be used to define a limit to
+            // which an unprivileged process's nice value can be raised" - setpriority(2)
+            let creds = task.Creds();
+            let privileged = creds.HasCapability(Capability::CAP_SYS_NICE);
+            let tcreds = t.Credentials();
+            if !privileged && creds != tcreds {
+                let euid = creds.lock().EffectiveKUID;
+                let (truid, teuid) = {
+                    let c = tcreds.lock();
+                    (c.RealKUID, c.EffectiveKUID)
+                };
+
+                if truid != euid && teuid != euid {
+                    return Err(Error::SysError(SysErr::EPERM));
+                }
+            }
+
+            if niceval < t.Niceness() && !privileged {
+                let limit = t.ThreadGroup().Limits().Get(LimitType::Nice).Cur;
+                if ((20 - niceval) as u64) > limit {
+                    return Err(Error::SysError(SysErr::EACCES));
+                }
+            }
+
             t.SetNiceness(niceval);
             return Ok(0);
         }
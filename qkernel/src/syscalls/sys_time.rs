@@ -24,6 +24,7 @@ use super::super::threadmgr::task_syscall::*;
 use super::super::threadmgr::thread::*;
 use super::super::kernel::timer::timer::*;
 use super::super::kernel::timer::*;
+use super::super::kernel::deterministic::*;
 use super::sys_poll::TIMEOUT_PROCESS_TIME;
 use super::super::taskMgr::*;
 
@@ -153,7 +154,11 @@ pub fn SysClockGetTime(task: &mut Task, args: &SyscallArguments) -> Result<i64>
     //let ts : &mut Timespec = task.GetTypeMut(addr)?;
     //*ts = clock.Now().Timespec();
 
-    let ts = clock.Now().Timespec();
+    let ts = if IsDeterministic() {
+        Timespec::FromNs(DeterministicNow(clockID))
+    } else {
+        clock.Now().Timespec()
+    };
     task.CopyOutObj(&ts, addr)?;
     //info!("SysClockGetTime: output is {:?}", ts);
 
@@ -167,7 +172,12 @@ pub fn SysClockSettime(_task: &mut Task, _args: &SyscallArguments) -> Result<i64
 pub fn SysTime(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let addr = args.arg0 as u64;
 
-    let now = REALTIME_CLOCK.Now().0 / 1_000_000_000;
+    let now = if IsDeterministic() {
+        DeterministicNow(CLOCK_REALTIME) / SECOND
+    } else {
+        REALTIME_CLOCK.Now().0 / 1_000_000_000
+    };
+
     if addr == 0 {
         return Ok(now)
     }
@@ -229,8 +239,12 @@ pub fn SysClockNanosleep(task: &mut Task, args: &SyscallArguments) -> Result<i64
     let clock = GetClock(task, clockID)?;
 
     if flags & TIMER_ABSTIME != 0 {
-        let now = clock.Now();
-        dur = dur - now.0;
+        let now = if IsDeterministic() {
+            DeterministicNow(clockID)
+        } else {
+            clock.Now().0
+        };
+        dur = dur - now;
     }
 
     if dur < TIMEOUT_PROCESS_TIME {
@@ -303,6 +317,10 @@ pub fn SysGettimeofday(task: &mut Task, args: &SyscallArguments) -> Result<i64>
         return Err(Error::SysError(-ret as i32));
     }
 
+    if IsDeterministic() {
+        timeV = Timeval::FromNs(DeterministicNow(CLOCK_REALTIME));
+    }
+
     if tvAddr != 0 {
         //let tv : &mut Timeval = task.GetTypeMut(tvAddr)?;
         //*tv = timeV;
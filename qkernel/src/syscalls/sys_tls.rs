@@ -20,6 +20,7 @@ use super::super::syscalls::syscalls::*;
 use super::super::vcpu::*;
 use super::super::task::Task;
 use super::super::arch::x86_64::context::*;
+use super::super::arch::x86_64::shadow_stack::*;
 
 pub fn IsValidSegmentBase(addr: u64) -> bool {
     return addr < MAX_ADDR64
@@ -29,6 +30,10 @@ pub fn SysArchPrctl(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let code = args.arg0;
     let addr = args.arg1 as u64;
 
+    if ARCH_SHSTK_ENABLE <= code && code <= ARCH_SHSTK_STATUS {
+        return task.ShadowStackPrctl(code, addr);
+    }
+
     if code != PrCtlEnum::ARCH_SET_GS as u64 &&
         code != PrCtlEnum::ARCH_SET_FS as u64 &&
         code != PrCtlEnum::ARCH_GET_FS as u64 &&
@@ -20,20 +20,35 @@ use super::super::fs::file::*;
 use super::super::task::*;
 use super::super::qlib::common::*;
 use super::super::qlib::linux_def::*;
+use super::super::qlib::linux::inotify::*;
 use super::super::qlib::mem::block::*;
 use super::super::syscalls::syscalls::*;
 use super::super::kernel_def::*;
+use super::super::SignalDef::*;
 
 pub fn SysWrite(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     let fd = args.arg0 as i32;
     let addr = args.arg1 as u64;
     let size = args.arg2 as i64;
 
-    let n = Write(task, fd, addr, size)?;
+    let n = RaiseSigPipe(task, Write(task, fd, addr, size))?;
     task.ioUsage.AccountWriteSyscall(n);
     return Ok(n);
 }
 
+// RaiseSigPipe sends SIGPIPE to the task when the write fails with EPIPE, i.e. the pipe or stream
+// socket has no reader any more. A host socket's SIGPIPE goes to the sandbox process rather
+// than to the guest task, so the app only gets the signal from here.
+pub fn RaiseSigPipe<T>(task: &Task, res: Result<T>) -> Result<T> {
+    if let Err(Error::SysError(SysErr::EPIPE)) = res {
+        if let Err(e) = task.Thread().SendSignal(&SignalInfoPriv(Signal::SIGPIPE)) {
+            error!("RaiseSigPipe: send SIGPIPE fail with {:?}", e);
+        }
+    }
+
+    return res
+}
+
 pub fn Write(task: &Task, fd: i32, addr: u64, size: i64) -> Result<i64> {
     //task.PerfGoto(PerfType::Write);
     //defer!(task.PerfGofrom(PerfType::Write));
@@ -72,7 +87,12 @@ pub fn Write(task: &Task, fd: i32, addr: u64, size: i64) -> Result<i64> {
     let iov = IoVec::NewFromAddr(addr, size as usize);
     let iovs: [IoVec; 1] = [iov];
 
-    return writev(task, &file, &iovs);
+    let n = writev(task, &file, &iovs)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_MODIFY, 0);
+    }
+
+    return Ok(n)
 }
 
 pub fn SysPwrite64(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
@@ -115,7 +135,12 @@ pub fn Pwrite64(task: &Task, fd: i32, addr: u64, size: i64, offset: i64) -> Resu
     let iov = IoVec::NewFromAddr(addr, size as usize);
     let iovs: [IoVec; 1] = [iov];
 
-    return pwritev(task, &file, &iovs, offset);
+    let n = pwritev(task, &file, &iovs, offset)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_MODIFY, 0);
+    }
+
+    return Ok(n)
 }
 
 pub fn SysPWritev2(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
@@ -149,7 +174,7 @@ pub fn SysPWritev2(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
     }
 
     if offset == -1 {
-        let n = Writev(task, fd, addr, iovcnt)?;
+        let n = RaiseSigPipe(task, Writev(task, fd, addr, iovcnt))?;
         task.ioUsage.AccountWriteSyscall(n);
         return Ok(n);
     }
@@ -175,7 +200,7 @@ pub fn SysWritev(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
         }
     }
 
-    let n = Writev(task, fd, addr, iovcnt)?;
+    let n = RaiseSigPipe(task, Writev(task, fd, addr, iovcnt))?;
     task.ioUsage.AccountWriteSyscall(n);
     return Ok(n);
 }
@@ -197,7 +222,12 @@ pub fn Writev(task: &Task, fd: i32, addr: u64, iovcnt: i32) -> Result<i64> {
     }
 
     let srcs = task.IovsFromAddr(addr, iovcnt as usize)?;
-    return writev(task, &file, &srcs);
+    let n = writev(task, &file, &srcs)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_MODIFY, 0);
+    }
+
+    return Ok(n)
 }
 
 pub fn SysPwritev(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
@@ -235,7 +265,12 @@ pub fn Pwritev(task: &Task, fd: i32, addr: u64, iovcnt: i32, offset: i64) -> Res
     }
 
     let srcs = task.IovsFromAddr(addr, iovcnt as usize)?;
-    return pwritev(task, &file, &srcs, offset);
+    let n = pwritev(task, &file, &srcs, offset)?;
+    if n > 0 {
+        file.Dirent.InotifyEvent(IN_MODIFY, 0);
+    }
+
+    return Ok(n)
 }
 
 fn RepWritev(task: &Task, f: &File, srcs: &[IoVec]) -> Result<i64> {
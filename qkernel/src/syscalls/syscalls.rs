@@ -38,15 +38,18 @@ use super::super::syscalls::sys_prctl::*;
 use super::super::syscalls::sys_timerfd::*;
 use super::super::syscalls::sys_chmod::*;
 use super::super::syscalls::sys_rusage::*;
+use super::super::syscalls::hooks::*;
+use super::super::syscalls::unimplemented::*;
 use super::super::syscalls::sys_aio::*;
 use super::super::syscalls::sys_capability::*;
 use super::super::syscalls::sys_membarrier::*;
 use super::super::syscalls::sys_splice::*;
 use super::super::syscalls::sys_timer::*;
 use super::super::syscalls::sys_mempolicy::*;
+use super::super::syscalls::sys_mount::*;
+use super::super::syscalls::sys_inotify::*;
 
 use super::super::task::*;
-use super::super::qlib::SysCallID;
 use super::super::qlib::common::*;
 use super::super::qlib::linux_def::*;
 
@@ -65,7 +68,13 @@ pub struct SyscallArguments {
 pub fn SysCall(task: &mut Task, nr: u64, args: &SyscallArguments) -> TaskRunState {
     let idx = nr as usize;
     let func = SYS_CALL_TABLE.get(idx).unwrap();
-    match func(task, args) {
+    let res = if SYSCALL_HOOKS.Active() {
+        SYSCALL_HOOKS.Call(task, nr, args, *func)
+    } else {
+        func(task, args)
+    };
+
+    match res {
         Err(Error::SysCallRetCtrlWithRet(state, ret)) => {
             task.SetReturn(ret);
             return state;
@@ -83,8 +92,7 @@ pub fn SysCall(task: &mut Task, nr: u64, args: &SyscallArguments) -> TaskRunStat
             return TaskRunState::RunApp
         }
         Err(Error::SysCallNotImplement) => {
-            let callId: SysCallID = unsafe { core::mem::transmute(nr as u64) };
-            panic!("Sycall not implement syscall is {:?}", callId);
+            return UnimplementedSyscall(task, nr, args)
         }
         Err(e) => {
             info!("Syscall[{}]: get unexpected error {:x?}", nr, e);
@@ -251,7 +259,7 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     SysMunlockall, //sys_munlockall,
     NotImplementSyscall, //sys_vhangup,
     NotImplementSyscall, //sys_modify_ldt,
-    NotImplementSyscall, //sys_pivot_root,
+    SysPivotRoot, //sys_pivot_root,
     NotImplementSyscall, //sys__sysctl,
     SysPrctl, //sys_prctl,
     SysArchPrctl, //sys_arch_prctl,
@@ -261,8 +269,8 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     SysSync, //sys_sync,
     NotImplementSyscall, //sys_acct,
     NotImplementSyscall, //sys_settimeofday,
-    NotImplementSyscall, //sys_mount,
-    NotImplementSyscall, //sys_umount2,
+    SysMount, //sys_mount,
+    SysUmount2, //sys_umount2,
     NotImplementSyscall, //sys_swapon,
     NotImplementSyscall, //sys_swapoff,
     NotImplementSyscall, //sys_reboot,
@@ -349,9 +357,9 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     NotImplementSyscall, //sys_keyctl,    //250
     NotImplementSyscall, //sys_ioprio_set,
     NotImplementSyscall, //sys_ioprio_get,
-    NotImplementSyscall, //sys_inotify_init,
-    NotImplementSyscall, //sys_inotify_add_watch,
-    NotImplementSyscall, //sys_inotify_rm_watch,
+    SysInotifyInit, //sys_inotify_init,
+    SysInotifyAddWatch, //sys_inotify_add_watch,
+    SysInotifyRmWatch, //sys_inotify_rm_watch,
     NotImplementSyscall, //sys_migrate_pages,
     SysOpenAt, //sys_openat,
     SysMkdirat, //sys_mkdirat,
@@ -368,7 +376,7 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     SysFaccessat, //sys_faccessat,
     SysPSelect, //sys_pselect6,    //270
     SysPpoll, //sys_ppoll,
-    NotImplementSyscall, //sys_unshare,
+    SysUnshare, //sys_unshare,
     SysSetRobustList, //sys_set_robust_list,
     SysGetRobustList, //sys_get_robust_list,
     SysSplice, //sys_splice,
@@ -390,7 +398,7 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     SysEpollCreate1, //sys_epoll_create1,
     SysDup3, //sys_dup3,
     SysPipe2, //sys_pipe2,
-    NotImplementSyscall, //sys_inotify_init1,
+    SysInotifyInit1, //sys_inotify_init1,
     SysPreadv, //sys_preadv,
     SysPwritev, //sys_pwritev,
     SysRtTgsigqueueinfo, //sys_rt_tgsigqueueinfo,
@@ -404,7 +412,7 @@ pub const SYS_CALL_TABLE: &'static [SyscallFn] = &[
     NotImplementSyscall, //sys_clock_adjtime,
     SysSyncFs, //sys_syncfs,
     SysSendMMsg, //sys_sendmmsg,
-    NotImplementSyscall, //sys_setns,
+    SysNoSys, //sys_setns,
     SysGetcpu, //sys_getcpu,
     NotImplementSyscall, //sys_process_vm_readv,//310
     NotImplementSyscall, //sys_process_vm_writev,
@@ -445,6 +453,7 @@ pub fn SysObsolete(_task: &mut Task, _args: &SyscallArguments) -> Result<i64> {
     //return Err(Error::SysError(SysErr::ENOTSUP));
 }
 
-pub fn SysNoSys(_task: &mut Task, _args: &SyscallArguments) -> Result<i64> {
+pub fn SysNoSys(task: &mut Task, args: &SyscallArguments) -> Result<i64> {
+    ReportUnimplemented(task, task.GetPtRegs().orig_rax, args);
     return Err(Error::SysError(SysErr::ENOSYS));
 }
\ No newline at end of file
@@ -0,0 +1,94 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The unimplemented syscalls are reported so that the user can see why an app misbehaves
+// in Quark: the first UnimplementedSyscallLogLimit calls of each syscall are logged with the
+// calling process and the arguments, then the config decides whether the sandbox panics, the
+// syscall fails with ENOSYS or the caller also gets SIGSYS.
+
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use super::super::qlib::SysCallID;
+use super::super::qlib::config::*;
+use super::super::qlib::linux_def::*;
+use super::super::SignalDef::*;
+use super::super::task::*;
+use super::super::SHARESPACE;
+use super::syscalls::*;
+
+// AUDIT_ARCH_X86_64, the arch of the SIGSYS siginfo
+pub const AUDIT_ARCH_X86_64: u32 = 0xc000003e;
+
+const MAX_SYSCALL: usize = SysCallID::maxsupport as usize;
+const ZERO: AtomicU64 = AtomicU64::new(0);
+static UNIMPLEMENTED_CNT: [AtomicU64; MAX_SYSCALL] = [ZERO; MAX_SYSCALL];
+
+// ReportUnimplemented counts the call and logs it when it is one of the first ones
+pub fn ReportUnimplemented(task: &Task, nr: u64, args: &SyscallArguments) {
+    let idx = nr as usize;
+    if idx >= MAX_SYSCALL {
+        error!("unknown syscall {} args {:x?}", nr, args);
+        return
+    }
+
+    let cnt = UNIMPLEMENTED_CNT[idx].fetch_add(1, Ordering::Relaxed) + 1;
+    let limit = SHARESPACE.config.read().UnimplementedSyscallLogLimit;
+    if cnt > limit {
+        return
+    }
+
+    let callId: SysCallID = unsafe { core::mem::transmute(nr) };
+    let thread = task.Thread();
+    error!("unimplemented syscall {:?} ({}) #{} from pid {} tid {} ({}): args {:x?}",
+           callId, nr, cnt, thread.ThreadGroup().ID(), thread.ThreadID(), thread.Name(), args);
+    if cnt == limit {
+        error!("unimplemented syscall {:?}: the next calls are not logged", callId);
+    }
+}
+
+// UnimplementedSyscall handles the syscall which returns SysCallNotImplement
+pub fn UnimplementedSyscall(task: &mut Task, nr: u64, args: &SyscallArguments) -> TaskRunState {
+    ReportUnimplemented(task, nr, args);
+
+    let mode = SHARESPACE.config.read().UnimplementedSyscall;
+    match mode {
+        UnimplementedSyscallMode::Panic => {
+            panic!("Sycall not implement syscall is {} args {:x?}", nr, args);
+        }
+        UnimplementedSyscallMode::Enosys => (),
+        UnimplementedSyscallMode::Sigsys => {
+            let info = SignalInfo {
+                Signo: Signal::SIGSYS,
+                Code: SignalInfo::SYS_SECCOMP,
+                ..Default::default()
+            };
+
+            let sigsys = info.SigSys();
+            sigsys.callAddr = task.GetPtRegs().rip;
+            sigsys.syscall = nr as i32;
+            sigsys.arch = AUDIT_ARCH_X86_64;
+
+            let thread = task.Thread();
+            thread.forceSignal(Signal(info.Signo), false);
+            if let Err(e) = thread.SendSignal(&info) {
+                error!("unimplemented syscall {}: send SIGSYS fail with {:?}", nr, e);
+            }
+        }
+    }
+
+    task.haveSyscallReturn = true;
+    task.SetReturn(-SysErr::ENOSYS as u64);
+    return TaskRunState::RunApp
+}
@@ -229,6 +229,12 @@ impl PageOpts {
         return PageOpts(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE);
     }
 
+    // the CET shadow stack page is readonly and dirty, it is not executable
+    pub fn UserShadowStack() -> Self {
+        return PageOpts(PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::DIRTY |
+            PageTableFlags::ACCESSED | PageTableFlags::NO_EXECUTE);
+    }
+
     pub fn KernelReadOnly() -> Self {
         return PageOpts(PageTableFlags::PRESENT);
     }
@@ -44,15 +44,19 @@ impl SocketBufIovs {
 
 pub enum RingeBufAllocator {
     HeapAllocator,
-    ShareAllocator,
+    // the ring in the shared memory: the address of the head/tail and of the buffer. The memory
+    // is owned by the mapping of the shared memory, the ring doesn't free it.
+    ShareAllocator(u64, u64),
 }
 
 impl RingeBufAllocator {
     pub fn AllocHeadTail(&self) -> &'static [AtomicU32] {
         match self {
             Self::HeapAllocator => return HeapAllocator::AllocHeadTail(),
-            _ => {
-                panic!("ShareAllocator call ...");
+            Self::ShareAllocator(headtail, _) => {
+                // the head/tail is shared with the peer, it is not reset
+                let ptr = *headtail as *const AtomicU32;
+                return unsafe { slice::from_raw_parts(ptr, 2) }
             }
         }
     }
@@ -60,17 +64,16 @@ impl RingeBufAllocator {
     pub fn FreeHeadTail(&self, data: &'static [AtomicU32]) {
         match self {
             Self::HeapAllocator => return HeapAllocator::FreeHeadTail(data),
-            _ => {
-                panic!("ShareAllocator call ...");
-            }
+            Self::ShareAllocator(..) => (),
         }
     }
 
     pub fn AlllocBuf(&self, pageCount: usize) -> u64 {
         match self {
             Self::HeapAllocator => return HeapAllocator::AlllocBuf(pageCount),
-            _ => {
-                panic!("ShareAllocator call ...");
+            Self::ShareAllocator(_, buf) => {
+                assert!(IsPowerOfTwo(pageCount) && *buf % MemoryDef::PAGE_SIZE == 0);
+                return *buf
             }
         }
     }
@@ -78,9 +81,7 @@ impl RingeBufAllocator {
     pub fn FreeBuf(&self, addr: u64, size: usize) {
         match self {
             Self::HeapAllocator => return HeapAllocator::FreeBuf(addr, size),
-            _ => {
-                panic!("ShareAllocator call ...");
-            }
+            Self::ShareAllocator(..) => (),
         }
     }
 }
@@ -413,6 +414,12 @@ impl RingBuf {
         return trigger
     }
 
+    // Truncate drops the last count bytes produced, they are not consumed yet
+    pub fn Truncate(&self, count: usize) {
+        let tail = self.headtail[1].load(Ordering::Relaxed);
+        self.headtail[1].store(tail.wrapping_sub(count as u32), Ordering::Release);
+    }
+
     /// return: write user buffer to socket bytestream and determine whether to trigger async socket ops
     pub fn write(&mut self, buf: &[u8]) -> Result<(bool, usize)> {
         let head = self.headtail[0].load(Ordering::Acquire);
@@ -472,6 +479,54 @@ impl RingBuf {
     }
 }
 
+// BufAutoTune measures the data moved through a ring buf in one rtt, same as the linux
+// tcp_moderate_rcvbuf the ring holds 2 rtt of data of the connection
+#[derive(Default, Debug)]
+pub struct BufAutoTune {
+    // the measurement window: start time in ns and the bytes moved since then
+    pub start: i64,
+    pub bytes: usize,
+    // the bytes moved in one rtt, estimated at the end of the last window
+    pub bytesPerRtt: usize,
+    // the page count the ring grows to when there is no uring op on it, the memory is reserved
+    pub pendingPages: u64,
+}
+
+impl BufAutoTune {
+    pub fn Account(&mut self, count: usize) {
+        self.bytes += count;
+    }
+
+    // Sample ends the measurement window at now when the ring is full
+    pub fn Sample(&mut self, now: i64, rtt: i64) -> usize {
+        let elapsed = now - self.start;
+        if self.start != 0 && elapsed > 0 {
+            self.bytesPerRtt = (self.bytes as u128 * rtt as u128 / elapsed as u128) as usize;
+        }
+
+        self.start = now;
+        self.bytes = 0;
+        return self.bytesPerRtt
+    }
+
+    // Target returns the page count for the ring of bufSize bytes when the app moves more than
+    // half of it in one rtt, i.e. the ring rather than the app limits the throughput
+    pub fn Target(&self, bufSize: usize, maxPages: u64) -> Option<u64> {
+        let want = 2 * self.bytesPerRtt as u64;
+        if want <= bufSize as u64 {
+            return None
+        }
+
+        let pages = ((want + MemoryDef::PAGE_SIZE - 1) / MemoryDef::PAGE_SIZE).next_power_of_two();
+        let pages = core::cmp::min(pages, maxPages);
+        if pages * MemoryDef::PAGE_SIZE <= bufSize as u64 {
+            return None
+        }
+
+        return Some(pages)
+    }
+}
+
 /// ByteStream serves as a buffer for socket operations, backed by a fixed size byte slice.
 ///
 ///
@@ -481,6 +536,7 @@ pub struct ByteStream {
     pub buf: RingBuf,
     pub dataIovs: SocketBufIovs,
     pub spaceiovs: SocketBufIovs,
+    pub tune: BufAutoTune,
 }
 
 impl ByteStream {
@@ -498,9 +554,39 @@ impl ByteStream {
             buf: buf,
             dataIovs: SocketBufIovs::default(),
             spaceiovs: SocketBufIovs::default(),
+            tune: BufAutoTune::default(),
         };
     }
 
+    // Resize moves the data to a new ring of pageCount pages and frees the old one, the caller
+    // makes sure that there is no uring op on the old ring and that the data fits the new one
+    pub fn Resize(&mut self, pageCount: u64) {
+        let mut buf = RingBuf::New(pageCount as usize, RingeBufAllocator::HeapAllocator);
+        loop {
+            let (addr, len) = self.buf.GetDataBuf();
+            if len == 0 {
+                break;
+            }
+
+            buf.writeViaAddr(addr, len as u64);
+            self.buf.Consume(len);
+        }
+
+        self.buf = buf;
+    }
+
+    // GrowPending grows the empty ring to the page count pending in the autotuning. The AsyncSend
+    // of the write buf stops when it is empty and the held data keeps it non-empty.
+    pub fn GrowPending(&mut self) {
+        if self.tune.pendingPages == 0 || self.AvailableDataSize() != 0 {
+            return
+        }
+
+        let pages = self.tune.pendingPages;
+        self.tune.pendingPages = 0;
+        self.Resize(pages);
+    }
+
     //return (bufAddr, bufSize)
     pub fn GetRawBuf(&self) -> (u64, usize) {
         return self.buf.GetRawBuf();
@@ -584,6 +670,10 @@ impl ByteStream {
         return self.buf.Produce(count)
     }
 
+    pub fn Truncate(&mut self, count: usize) {
+        self.buf.Truncate(count)
+    }
+
     /// return: write user buffer to socket bytestream and determine whether to trigger async socket ops
     pub fn write(&mut self, buf: &[u8]) -> Result<(bool, usize)> {
         return self.buf.write(buf);
@@ -0,0 +1,77 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+// ClockSetSeq counts the host CLOCK_REALTIME sets. The host has set the clock seq times and
+// the kernel timer subsystem has handled the sets up to handled, the sets happened before one
+// handling are merged into it.
+#[derive(Debug, Default)]
+pub struct ClockSetSeq {
+    seq: AtomicU64,
+    handled: AtomicU64,
+}
+
+impl ClockSetSeq {
+    pub fn Set(&self) {
+        self.seq.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Take returns true for the caller which has to handle the pending sets
+    pub fn Take(&self) -> bool {
+        let seq = self.seq.load(Ordering::Acquire);
+        let handled = self.handled.load(Ordering::Acquire);
+        if seq == handled {
+            return false
+        }
+
+        return self.handled.compare_exchange(handled, seq, Ordering::AcqRel, Ordering::Relaxed).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_set_take() {
+        let c = ClockSetSeq::default();
+        assert!(!c.Take());
+
+        c.Set();
+        assert!(c.Take());
+        assert!(!c.Take());
+
+        // the sets before the handling are handled once
+        c.Set();
+        c.Set();
+        assert!(c.Take());
+        assert!(!c.Take());
+    }
+
+    #[test]
+    fn test_clock_set_one_taker() {
+        let c = std::sync::Arc::new(ClockSetSeq::default());
+        c.Set();
+
+        let threads: Vec<_> = (0..4).map(|_| {
+            let c = c.clone();
+            std::thread::spawn(move || c.Take())
+        }).collect();
+
+        let taken = threads.into_iter().map(|t| t.join().unwrap()).filter(|t| *t).count();
+        assert_eq!(taken, 1);
+    }
+}
@@ -14,7 +14,8 @@
 
 extern crate alloc;
 
-pub const RDMA_ENABLE : bool= false;
+// the RDMA data path of qvisor is built, see the rdma feature of qvisor
+pub const RDMA_ENABLE : bool= cfg!(feature = "rdma");
 
 // Segment indices and Selectors.
 pub const SEG_KCODE      : u16 = 1;
@@ -42,6 +43,7 @@ pub const CR0_NW : u64 = 1 << 29;
 pub const CR0_CD : u64 = 1 << 30;
 pub const CR0_PG : u64 = 1 << 31;
 
+pub const CR4_TSD        : u64 = 1 << 2;
 pub const CR4_PSE        : u64 = 1 << 4;
 pub const CR4_PAE        : u64 = 1 << 5;
 pub const CR4_PGE        : u64 = 1 << 7;
@@ -53,6 +55,7 @@ pub const CR4_PCIDE      : u64 = 1 << 17;
 pub const CR4_OSXSAVE    : u64 = 1 << 18;
 pub const CR4_SMEP       : u64 = 1 << 20;
 pub const CR4_SMAP       : u64 = 1 << 21;
+pub const CR4_CET        : u64 = 1 << 23;
 
 pub const RFLAGS_CF : u64 = 1 << 0;
 pub const RFLAGS_RESERVED : u64 = 1 << 1;
@@ -311,6 +314,19 @@ impl Default for Error {
     fn default() -> Self { Error::None }
 }
 
+// ErrContext records where an errno returned by the host enters the guest kernel. It is
+// kept by the task in debug build and reported when the errno reaches the syscall boundary,
+// Error itself stays a plain errno so that the error matching is not affected.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ErrContext {
+    // the host operation, e.g. "IoCtl"
+    pub op: &'static str,
+    pub fd: i32,
+    pub errno: i32,
+    pub file: &'static str,
+    pub line: u32,
+}
+
 pub fn ConvertIntr(err: Error, intr: Error) -> Error {
     if err == Error::ErrInterrupted {
         return intr
@@ -12,6 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::common::*;
+use super::MAX_VCPU_COUNT;
+use super::kernel::quring::uring_mgr::QUring;
+use super::kernel::quring::buf_pool::RECV_POOL_MAX_BUFS;
+use super::uring::sys::sys::*;
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Config {
@@ -32,12 +40,169 @@ pub struct Config {
     pub DedicateUring: usize,
     pub UringSize: usize,
     pub UringEpollCtl: bool,
+    // the RDMA devices are "RDMADevices" of the config file, a list of the device names read by
+    // qvisor only, all the devices of the host are used without it
     pub EnableRDMA: bool,
+    // the port of the RDMA devices, 0 uses all the active ports
     pub RDMAPort: u8,
+    // the default gid index of the ports, -1 uses the first RoCEv2 gid or the index 0 when the
+    // port has none. The connection uses the gid of its local address when the port has it.
+    pub RDMAGidIndex: i32,
+    // time in ms, the RDMA connection which can't finish the handshake in it falls back to the
+    // tcp path. 0 waits for the handshake without the fallback
+    pub RDMAHandshakeTimeout: u64,
+    // the host udp port on which qvisor resolves the udp addresses of the peers to their UD
+    // queue pairs, it has to be the same on all the hosts
+    pub RDMAUdpResolvePort: u16,
+    // the max inline data asked for the RDMA queue pairs, the writes not larger than it are
+    // copied to the work requests. 0 disables the inline sends
+    pub RDMAInlineThreshold: u32,
+    // time in seconds, the period of the summary of the RDMA counters in the log. 0 disables it
+    pub RDMAStatsLogSec: u64,
+    // the RDMA connections to the same peer host share one queue pair of the port, the hosts
+    // have to use the same setting
+    pub RDMAMultiplex: bool,
+    // the RDMA buffers are registered as on-demand paging memory regions when the device supports
+    // it, so they are not pinned up front and don't count to the locked memory
+    pub RDMAOdp: bool,
+    // time in seconds, the ready RDMA connection is reset when the keepalive of its tcp socket
+    // gets no answer of the peer host in it. 0 disables it
+    pub RDMAKeepaliveSec: u32,
     pub PerSandboxLog: bool,
     pub ReserveCpuCount: usize,
+    // max vcpus of the sandbox, it is capped by the host cpu count
+    pub VcpuCount: usize,
     pub EnableMemInfo: bool,
     pub ShimMode: bool,
+    pub AsyncClose: bool,
+    pub EnableRawSocket: bool,
+    // sandbox ephemeral port range for hostinet sockets, 0 means the host allocates the port
+    pub EphemeralPortStart: u16,
+    pub EphemeralPortEnd: u16,
+    // max host fds held by the async accept queue of a listening socket, 0 means the backlog
+    pub AcceptQueueHighWatermark: usize,
+    // fetch the peer address, SO_PEERCRED and TCP_INFO of the accepted connection with the
+    // accept, the follow-up getpeername and getsockopt don't need host calls
+    pub AcceptPeerInfo: bool,
+    // max MB of the socket ring bufs grown by the autotuning in the sandbox, 0 disables it
+    pub SocketBufAutoTuneMB: usize,
+    // the ring bufs of the socket without data transfer for the secs are shrunk, 0 disables it
+    pub SocketBufIdleSec: u64,
+    // the epoll readiness events arriving while a task waits in epoll_wait are batched in the
+    // epoll ring of the task, which is woken up once for them
+    pub EpollWakeupBatch: bool,
+    // max time in micro sec an app thread runs before it is preempted by the timer interrupt
+    // when there are other ready tasks, 0 disables the preemption
+    pub TimeSlice: u64,
+    // host io threads submitting the uring requests when DedicateUring is 0, each thread has
+    // its own uring and the guest shards the requests by fd
+    pub IOThreadCount: usize,
+    // the host io completion rate (per sec) above which the vcpu kicks are batched, 0 disables
+    // the wakeup moderation
+    pub WakeupModerationRate: u64,
+    // max time in micro sec a batched vcpu kick is deferred
+    pub WakeupModerationInterval: u64,
+    // idle time in sec after which the guest memory is written to disk and freed on the host,
+    // it is restored when a vcpu runs again. 0 disables the hibernation
+    pub HibernateIdleTimeout: u64,
+    // generate /etc/hostname, /etc/hosts and /etc/resolv.conf in the guest instead of the bind mounts
+    pub EtcFilesInGuest: bool,
+    // answer the NETLINK_ROUTE link/address/route dumps in the guest from the snapshot of the
+    // host interfaces taken at the sandbox start instead of the host netlink socket
+    pub NetlinkRouteInGuest: bool,
+    // what the unimplemented syscall does, the first UnimplementedSyscallLogLimit calls of
+    // each syscall are logged with the caller and the arguments
+    pub UnimplementedSyscall: UnimplementedSyscallMode,
+    pub UnimplementedSyscallLogLimit: u64,
+    // idle host connections kept per destination the guest connected to recently, the guest
+    // connect gets one of them instead of doing the handshake. 0 disables the pool
+    pub ConnPoolSize: u64,
+    // time in sec a destination and its idle connections stay in the pool after the last connect
+    pub ConnPoolIdleTimeout: u64,
+    // the TCP connections to the listeners of the other sandboxes on the host go over a shared
+    // memory ring pair instead of the host TCP stack, the host unix socket of the connection
+    // carries the setup and the doorbells
+    pub LoopbackFastPath: bool,
+    // max readahead and background uring requests in flight, the others are held until they
+    // complete so that the foreground requests are not delayed. 0 submits all of them at once
+    pub UringBackgroundInflight: u64,
+    // socketpair(AF_UNIX) creates a host socket pair instead of the guest unix sockets
+    pub HostUnixSocketPair: bool,
+    // the udp sockets receive and send the datagrams through the uring with a guest datagram
+    // buffer instead of a hostcall per datagram
+    pub UdpUringBuf: bool,
+    // size in KB of the journal file in /var/log/quark/journal where qvisor records the
+    // lifecycle events, the fatal errors and the resource snapshots. 0 disables the journal
+    pub JournalSize: u64,
+    // time in sec between 2 resource snapshots in the journal, 0 disables the snapshots
+    pub JournalSnapshotInterval: u64,
+    // time in us the idle io threads poll the urings and the host events before they block
+    pub IOSpinBudget: u64,
+    // time in us an idle vcpu looks for the ready tasks before it blocks on the host
+    pub VcpuSpinBudget: u64,
+    // the power save mode shortens both spins to PowerSaveSpinBudget us so that the idle threads
+    // block in the host kernel sooner, and the tasks waiting for the async uring ops give up the
+    // vcpu instead of spinning. Auto turns it on when the host cpufreq governor is powersave
+    pub PowerSave: PowerSaveMode,
+    pub PowerSaveSpinBudget: u64,
+    // time in us, an idle vcpu keeps polling when the next timer expires within it and fires the
+    // timer itself, so the short deadlines of the blocking calls don't wait for the host wakeup.
+    // It is off in the power save mode, 0 disables it
+    pub HighResTimerWindow: u64,
+    // time in us, a vcpu returning from a syscall reaps the uring completions itself when the
+    // host io thread hasn't checked them for that long, e.g. when it is starved by the busy vcpu
+    // threads on an oversubscribed host. 0 disables it
+    pub IOReapLag: u64,
+    // nice value of the host io threads, a negative value keeps them ahead of the vcpu threads
+    // when qvisor has CAP_SYS_NICE. 0 leaves their priority unchanged
+    pub IOThreadNice: i32,
+    // how the host serves the guest urings: the host kernel io_uring or its emulation with epoll
+    // and a thread pool for the hosts where io_uring is missing or prohibited by the seccomp/LSM
+    // policy. Auto uses io_uring when the host can set up one
+    pub IoBackend: IoBackendMode,
+    // allow the applications to enable the CET shadow stack with arch_prctl(ARCH_SHSTK_ENABLE),
+    // it is turned off when kvm doesn't expose the shadow stack of the host cpu to the guest
+    pub ShadowStack: bool,
+    // expose /proc/sandbox_identity, the sandbox identity token signed by qvisor with the Ed25519
+    // key in /etc/quark/identity.key, to prove to the external services which sandbox the workload
+    // runs in
+    pub SandboxIdentity: bool,
+    // the lifetime in seconds of the sandbox identity token
+    pub SandboxIdentityTtl: u64,
+    // deterministic execution to help reproduce the concurrency bugs of the apps: one vcpu runs
+    // the tasks in fifo order without the time slice preemption and the IO without the uring,
+    // the apps see a virtual clock which advances DeterministicTickNs per syscall and per rdtsc
+    // and getrandom returns a stream of each thread seeded with DeterministicSeed. It is not a
+    // replay, the timed waits and the host IO still follow the host timing
+    pub Deterministic: bool,
+    pub DeterministicSeed: u64,
+    pub DeterministicTickNs: u64,
+    // number of the 16KB receive bufs provided to each uring, the uring socket reads take one of
+    // them when the data arrives instead of waiting on the free space of their own read buf.
+    // 0 disables the shared receive pool
+    pub UringRecvPoolBufs: usize,
+    // the bufs of the receive pool are given back through a buffer ring registered to the host
+    // uring (linux 5.19) instead of a provide_buffers request for each buf, the pool falls back
+    // to the requests when the host can't register the ring
+    pub UringRecvPoolRing: bool,
+    // expose /proc/quark_resolver which looks up the host names with the dns client of qvisor
+    // and caches the answers with their ttl, for the nss module in nss/ to replace the dns
+    // lookup of glibc
+    pub HostResolver: bool,
+    // the kernel bufs sized by the app, e.g. the bounce buf of a read, are charged to a pool of
+    // UserBufPoolMB, 0 is a quarter of KernelMemSize, and to the thread group up to UserBufGroupMB,
+    // 0 is half of the pool. A buf is at most UserBufMaxMB, the reads and the stream writes larger
+    // than it are short and the others fail with ENOMEM. When the pool or the group is used up,
+    // the reads and writes are short and the others wait for the bufs in use to be freed.
+    pub UserBufPoolMB: u64,
+    pub UserBufMaxMB: u64,
+    pub UserBufGroupMB: u64,
+    // where the abstract unix socket names are bound. Sandbox keeps them in the guest only, Host
+    // binds the shadow host socket to the same name too, so the names taken by the host processes
+    // or the other sandboxes in the same host network namespace fail the bind with EADDRINUSE.
+    // In the Host mode the stream sockets connect to the host names which are not bound in the
+    // sandbox through the host, and the listeners accept the host connections too
+    pub UnixAbstract: UnixAbstractMode,
 }
 
 impl Config {
@@ -48,9 +213,248 @@ impl Config {
     pub fn Async(&self) -> bool {
         return self.LogType == LogType::Async;
     }
+
+    // IOSpinNs is the spin budget of the io threads in ns, Auto is resolved by the host before
+    // the config is copied to the guest
+    pub fn IOSpinNs(&self) -> i64 {
+        if self.PowerSave == PowerSaveMode::On {
+            return self.PowerSaveSpinBudget as i64 * 1000
+        }
+
+        return self.IOSpinBudget as i64 * 1000
+    }
+
+    pub fn VcpuSpinNs(&self) -> i64 {
+        if self.PowerSave == PowerSaveMode::On {
+            return core::cmp::min(self.PowerSaveSpinBudget, self.VcpuSpinBudget) as i64 * 1000
+        }
+
+        return self.VcpuSpinBudget as i64 * 1000
+    }
+
+    pub fn HighResTimerNs(&self) -> i64 {
+        if self.PowerSave == PowerSaveMode::On {
+            return 0
+        }
+
+        return self.HighResTimerWindow as i64 * 1000
+    }
+
+    pub fn IOReapLagNs(&self) -> i64 {
+        return self.IOReapLag as i64 * 1000
+    }
+
+    pub fn UserBufPoolSize(&self) -> usize {
+        let mb = if self.UserBufPoolMB == 0 {
+            self.KernelMemSize * 1024 / 4
+        } else {
+            self.UserBufPoolMB
+        };
+
+        return (mb << 20) as usize
+    }
+
+    pub fn UserBufMaxSize(&self) -> usize {
+        return (self.UserBufMaxMB << 20) as usize
+    }
+
+    pub fn UserBufGroupSize(&self) -> usize {
+        if self.UserBufGroupMB == 0 {
+            return self.UserBufPoolSize() / 2
+        }
+
+        return (self.UserBufGroupMB << 20) as usize
+    }
 }
 
-impl Config {}
+impl Config {
+    // Validate checks the option combinations before the sandbox starts. The options which
+    // can't work with the others are turned off and reported in the returned notes,
+    // the conflicts which have no safe default are returned as error.
+    pub fn Validate(&mut self) -> Result<Vec<String>> {
+        let mut errs = Vec::new();
+        let mut notes = Vec::new();
+
+        if self.KernelMemSize == 0 {
+            errs.push(String::from("KernelMemSize must be larger than 0"));
+        }
+
+        if self.UringSize == 0 || !self.UringSize.is_power_of_two() {
+            errs.push(format!("UringSize {} must be a power of 2", self.UringSize));
+        }
+
+        if self.Deterministic && self.UringIO {
+            self.UringIO = false;
+            notes.push(String::from("UringIO is disabled, the deterministic mode runs the IO in the task order"));
+        }
+
+        if self.EnableRDMA && !self.UringIO {
+            errs.push(String::from("EnableRDMA requires UringIO"));
+        }
+
+        if self.EnableRDMA && !cfg!(feature = "rdma") {
+            self.EnableRDMA = false;
+            notes.push(String::from("EnableRDMA is turned off, quark is built without the rdma feature"));
+        }
+
+        if self.SandboxIdentity && self.SandboxIdentityTtl == 0 {
+            errs.push(String::from("SandboxIdentityTtl must be larger than 0"));
+        }
+
+        if self.RDMAGidIndex < -1 || self.RDMAGidIndex > 255 {
+            errs.push(format!("RDMAGidIndex {} must be -1 or in 0..255", self.RDMAGidIndex));
+        }
+
+        if self.EphemeralPortStart != 0 || self.EphemeralPortEnd != 0 {
+            if self.EphemeralPortStart == 0 || self.EphemeralPortStart > self.EphemeralPortEnd {
+                errs.push(format!("invalid ephemeral port range {}-{}", self.EphemeralPortStart, self.EphemeralPortEnd));
+            }
+        }
+
+        if self.VcpuCount < 2 || self.VcpuCount > MAX_VCPU_COUNT {
+            errs.push(format!("VcpuCount {} must be in 2..{}", self.VcpuCount, MAX_VCPU_COUNT));
+        }
+
+        if self.TimeSlice != 0 && self.TimeSlice < 1000 {
+            errs.push(format!("TimeSlice {} us is less than 1000 us", self.TimeSlice));
+        }
+
+        if self.IOThreadCount == 0 || self.IOThreadCount > QUring::MAX_URING_COUNT {
+            errs.push(format!("IOThreadCount {} must be in 1..{}", self.IOThreadCount, QUring::MAX_URING_COUNT));
+        }
+
+        if self.DedicateUring > QUring::MAX_URING_COUNT {
+            errs.push(format!("DedicateUring {} is larger than {}", self.DedicateUring, QUring::MAX_URING_COUNT));
+        }
+
+        if self.DedicateUring > 0 && self.IOThreadCount > 1 {
+            self.IOThreadCount = 1;
+            notes.push(String::from("IOThreadCount is reset to 1, the dedicated urings are polled by the host kernel"));
+        }
+
+        if self.IOThreadNice < -20 || self.IOThreadNice > 19 {
+            errs.push(format!("IOThreadNice {} must be in -20..19", self.IOThreadNice));
+        }
+
+        if self.UserBufMaxMB == 0 || self.UserBufMaxSize() > self.UserBufPoolSize() {
+            errs.push(format!("UserBufMaxMB {} must be in 1..{}", self.UserBufMaxMB, self.UserBufPoolSize() >> 20));
+        }
+
+        if self.UserBufGroupSize() < self.UserBufMaxSize() || self.UserBufGroupSize() > self.UserBufPoolSize() {
+            errs.push(format!("UserBufGroupMB {} must be in {}..{}", self.UserBufGroupMB, self.UserBufMaxMB, self.UserBufPoolSize() >> 20));
+        }
+
+        if self.Deterministic {
+            if self.DeterministicTickNs == 0 {
+                errs.push(String::from("DeterministicTickNs must be larger than 0 in the deterministic mode"));
+            }
+
+            if self.TimeSlice != 0 {
+                self.TimeSlice = 0;
+                notes.push(String::from("TimeSlice is reset to 0, the deterministic mode has no preemption"));
+            }
+        }
+
+        if self.UringRecvPoolBufs * QUring::MAX_URING_COUNT > RECV_POOL_MAX_BUFS {
+            errs.push(format!("UringRecvPoolBufs {} must be at most {}", self.UringRecvPoolBufs,
+                              RECV_POOL_MAX_BUFS / QUring::MAX_URING_COUNT));
+        }
+
+        if self.WakeupModerationRate > 0 && self.WakeupModerationInterval == 0 {
+            errs.push(String::from("WakeupModerationInterval must be larger than 0 when WakeupModerationRate is set"));
+        }
+
+        if self.AsyncAccept && (!self.UringIO || self.DedicateUring == 0) {
+            self.AsyncAccept = false;
+            notes.push(String::from("AsyncAccept is disabled, it requires UringIO and DedicateUring > 0"));
+        }
+
+        if !self.UringIO {
+            if self.UringStatx {
+                self.UringStatx = false;
+                notes.push(String::from("UringStatx is disabled, it requires UringIO"));
+            }
+
+            if self.UringEpollCtl {
+                self.UringEpollCtl = false;
+                notes.push(String::from("UringEpollCtl is disabled, it requires UringIO"));
+            }
+
+            if self.UdpUringBuf {
+                self.UdpUringBuf = false;
+                notes.push(String::from("UdpUringBuf is disabled, it requires UringIO"));
+            }
+
+            if self.UringRecvPoolBufs > 0 {
+                self.UringRecvPoolBufs = 0;
+                notes.push(String::from("UringRecvPoolBufs is reset to 0, it requires UringIO"));
+            }
+        }
+
+        if errs.len() > 0 {
+            return Err(Error::Common(errs.join("; ")))
+        }
+
+        return Ok(notes)
+    }
+
+    // ApplyUringOps turns off the options using the uring opcodes which the host kernel doesn't
+    // support. ops is the bitmap of the supported IORING_OP_* probed from the host uring.
+    pub fn ApplyUringOps(&mut self, ops: u64) -> Result<Vec<String>> {
+        let supported = |op: u32| ops & (1 << op) != 0;
+        let mut notes = Vec::new();
+
+        if self.UringIO && !(supported(IORING_OP_READ) && supported(IORING_OP_WRITE)
+            && supported(IORING_OP_SEND) && supported(IORING_OP_RECV)) {
+            self.UringIO = false;
+            notes.push(String::from("UringIO is disabled, the host uring has no read/write/send/recv"));
+        }
+
+        // the async log is flushed by the uring write
+        if self.LogType == LogType::Async && !supported(IORING_OP_WRITE) {
+            self.LogType = LogType::Sync;
+            notes.push(String::from("LogType is set to Sync, the host uring has no write"));
+        }
+
+        if self.AsyncAccept && !supported(IORING_OP_ACCEPT) {
+            self.AsyncAccept = false;
+            notes.push(String::from("AsyncAccept is disabled, the host uring has no accept"));
+        }
+
+        if self.UringStatx && !supported(IORING_OP_STATX) {
+            self.UringStatx = false;
+            notes.push(String::from("UringStatx is disabled, the host uring has no statx"));
+        }
+
+        if self.UringEpollCtl && !supported(IORING_OP_EPOLL_CTL) {
+            self.UringEpollCtl = false;
+            notes.push(String::from("UringEpollCtl is disabled, the host uring has no epoll_ctl"));
+        }
+
+        if self.UringRecvPoolBufs > 0 && !supported(IORING_OP_PROVIDE_BUFFERS) {
+            self.UringRecvPoolBufs = 0;
+            notes.push(String::from("UringRecvPoolBufs is reset to 0, the host uring has no provide_buffers"));
+        }
+
+        // the options depending on UringIO follow it
+        notes.append(&mut self.Validate()?);
+        return Ok(notes)
+    }
+
+    // ApplyIoBackend adjusts the options to the selected io backend, the emulated urings have no
+    // kernel sq poll thread so all of them are submitted by the host io threads
+    pub fn ApplyIoBackend(&mut self, epoll: bool) -> Result<Vec<String>> {
+        let mut notes = Vec::new();
+
+        if epoll && self.DedicateUring > 0 {
+            self.DedicateUring = 0;
+            notes.push(String::from("DedicateUring is reset to 0, the epoll io backend has no sq poll thread"));
+        }
+
+        notes.append(&mut self.Validate()?);
+        return Ok(notes)
+    }
+}
 
 impl Default for Config {
     fn default() -> Self {
@@ -74,10 +478,66 @@ impl Default for Config {
             UringEpollCtl: false,
             EnableRDMA: false,
             RDMAPort: 1,
+            RDMAGidIndex: -1,
+            RDMAHandshakeTimeout: 3000,
+            RDMAUdpResolvePort: 18520,
+            RDMAInlineThreshold: 128,
+            RDMAStatsLogSec: 60,
+            RDMAMultiplex: false,
+            RDMAOdp: false,
+            RDMAKeepaliveSec: 30,
             PerSandboxLog: false,
             ReserveCpuCount: 2,
+            VcpuCount: 16,
             EnableMemInfo: true,
             ShimMode: false,
+            AsyncClose: true,
+            EnableRawSocket: true,
+            EphemeralPortStart: 0,
+            EphemeralPortEnd: 0,
+            AcceptQueueHighWatermark: 256,
+            AcceptPeerInfo: false,
+            SocketBufAutoTuneMB: 0,
+            SocketBufIdleSec: 0,
+            EpollWakeupBatch: false,
+            TimeSlice: 10_000,
+            IOThreadCount: 1,
+            WakeupModerationRate: 0,
+            WakeupModerationInterval: 50,
+            HibernateIdleTimeout: 0,
+            EtcFilesInGuest: false,
+            NetlinkRouteInGuest: true,
+            UnimplementedSyscall: UnimplementedSyscallMode::Panic,
+            UnimplementedSyscallLogLimit: 3,
+            ConnPoolSize: 0,
+            ConnPoolIdleTimeout: 10,
+            LoopbackFastPath: false,
+            UringBackgroundInflight: 16,
+            HostUnixSocketPair: false,
+            UdpUringBuf: false,
+            JournalSize: 0,
+            JournalSnapshotInterval: 60,
+            IOSpinBudget: 10_000,
+            VcpuSpinBudget: 500,
+            PowerSave: PowerSaveMode::Auto,
+            PowerSaveSpinBudget: 50,
+            HighResTimerWindow: 0,
+            IOReapLag: 0,
+            IOThreadNice: 0,
+            IoBackend: IoBackendMode::Auto,
+            ShadowStack: false,
+            SandboxIdentity: false,
+            SandboxIdentityTtl: 3600,
+            Deterministic: false,
+            DeterministicSeed: 0,
+            DeterministicTickNs: 1000,
+            UringRecvPoolBufs: 0,
+            UringRecvPoolRing: true,
+            HostResolver: false,
+            UserBufPoolMB: 0,
+            UserBufMaxMB: 64,
+            UserBufGroupMB: 0,
+            UnixAbstract: UnixAbstractMode::Sandbox,
         }
     }
 }
@@ -113,8 +573,191 @@ impl Default for LogLevel {
     }
 }
 
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum UnimplementedSyscallMode {
+    // stop the sandbox
+    Panic,
+    // fail the syscall with ENOSYS
+    Enosys,
+    // fail the syscall with ENOSYS and send SIGSYS to the caller, like a seccomp trap
+    Sigsys,
+}
+
+impl Default for UnimplementedSyscallMode {
+    fn default() -> Self {
+        return Self::Panic
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum PowerSaveMode {
+    // follow the host cpufreq governor
+    Auto,
+    On,
+    Off,
+}
+
+impl Default for PowerSaveMode {
+    fn default() -> Self {
+        return Self::Auto
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum UnixAbstractMode {
+    Sandbox,
+    Host,
+}
+
+impl Default for UnixAbstractMode {
+    fn default() -> Self {
+        return Self::Sandbox
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum IoBackendMode {
+    // io_uring when the host supports it, otherwise epoll
+    Auto,
+    Uring,
+    Epoll,
+}
+
+impl Default for IoBackendMode {
+    fn default() -> Self {
+        return Self::Auto
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum LogType {
     Sync,
     Async,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_default() {
+        let mut config = Config::default();
+        assert_eq!(config.Validate(), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn test_validate_derived() {
+        let mut config = Config::default();
+        config.DedicateUring = 0;
+        assert_eq!(config.Validate().unwrap().len(), 1);
+        assert!(!config.AsyncAccept);
+    }
+
+    #[test]
+    fn test_validate_rdma_feature() {
+        let mut config = Config::default();
+        config.EnableRDMA = true;
+        let notes = config.Validate().unwrap();
+        assert_eq!(config.EnableRDMA, cfg!(feature = "rdma"));
+        assert_eq!(notes.iter().any(|n| n.contains("EnableRDMA")), !cfg!(feature = "rdma"));
+    }
+
+    #[test]
+    fn test_validate_err() {
+        let mut config = Config::default();
+        config.UringIO = false;
+        config.EnableRDMA = true;
+        config.UringSize = 48;
+        config.UserBufMaxMB = 8192;
+        config.UserBufGroupMB = 1;
+        config.VcpuCount = MAX_VCPU_COUNT + 1;
+        match config.Validate() {
+            Err(Error::Common(e)) => {
+                assert!(e.contains("VcpuCount"));
+                assert!(e.contains("EnableRDMA"));
+                assert!(e.contains("UringSize"));
+                assert!(e.contains("UserBufMaxMB"));
+                assert!(e.contains("UserBufGroupMB"));
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_spin_budget() {
+        let mut config = Config::default();
+        config.PowerSave = PowerSaveMode::Off;
+        assert_eq!(config.IOSpinNs(), 10_000_000);
+        assert_eq!(config.VcpuSpinNs(), 500_000);
+        config.HighResTimerWindow = 2000;
+        assert_eq!(config.HighResTimerNs(), 2_000_000);
+        config.IOReapLag = 200;
+        assert_eq!(config.IOReapLagNs(), 200_000);
+
+        config.PowerSave = PowerSaveMode::On;
+        assert_eq!(config.IOSpinNs(), 50_000);
+        assert_eq!(config.VcpuSpinNs(), 50_000);
+        assert_eq!(config.HighResTimerNs(), 0);
+    }
+
+    #[test]
+    fn test_apply_io_backend() {
+        let mut config = Config::default();
+        assert_eq!(config.ApplyIoBackend(false), Ok(Vec::new()));
+        assert_eq!(config.DedicateUring, 1);
+
+        // the dedicated uring is dropped and AsyncAccept follows it
+        assert_eq!(config.ApplyIoBackend(true).unwrap().len(), 2);
+        assert_eq!(config.DedicateUring, 0);
+        assert!(!config.AsyncAccept);
+        assert!(config.UringIO);
+    }
+
+    #[test]
+    fn test_validate_deterministic() {
+        let mut config = Config::default();
+        config.Deterministic = true;
+        config.TimeSlice = 10_000;
+        // TimeSlice, UringIO and AsyncAccept which requires UringIO
+        assert_eq!(config.Validate().unwrap().len(), 3);
+        assert_eq!(config.TimeSlice, 0);
+        assert!(!config.UringIO);
+
+        config.EnableRDMA = true;
+        assert!(config.Validate().is_err());
+        config.EnableRDMA = false;
+
+        config.DeterministicTickNs = 0;
+        assert!(config.Validate().is_err());
+    }
+
+    #[test]
+    fn test_recv_pool() {
+        let mut config = Config::default();
+        config.UringRecvPoolBufs = 1024;
+        assert_eq!(config.Validate(), Ok(Vec::new()));
+
+        // the host uring without the buffer selection
+        let ops = (1u64 << IORING_OP_READ) | (1 << IORING_OP_WRITE) | (1 << IORING_OP_SEND)
+            | (1 << IORING_OP_RECV) | (1 << IORING_OP_ACCEPT);
+        assert_eq!(config.ApplyUringOps(ops).unwrap().len(), 1);
+        assert_eq!(config.UringRecvPoolBufs, 0);
+
+        config.UringRecvPoolBufs = RECV_POOL_MAX_BUFS;
+        assert!(config.Validate().is_err());
+    }
+
+    #[test]
+    fn test_async_log() {
+        let mut config = Config::default();
+        config.LogType = LogType::Async;
+        let ops = (1u64 << IORING_OP_READ) | (1 << IORING_OP_WRITE) | (1 << IORING_OP_SEND)
+            | (1 << IORING_OP_RECV) | (1 << IORING_OP_ACCEPT);
+        config.ApplyUringOps(ops).unwrap();
+        assert!(config.Async());
+
+        // the 5.4 host uring has no IORING_OP_WRITE
+        let ops = (1u64 << IORING_OP_ACCEPT) | (1 << IORING_OP_READV) | (1 << IORING_OP_WRITEV);
+        config.ApplyUringOps(ops).unwrap();
+        assert!(config.SyncPrint());
+    }
+}
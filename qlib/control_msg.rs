@@ -118,6 +118,41 @@ pub enum Payload {
     CreateSubContainer(CreateArgs),
     StartSubContainer(StartArgs),
     WaitAll,
+    Debug,
+    ProfileStart(u64),
+    ProfileStop,
+    ShmAttach(ShmAttach),
+    InjectFile(InjectFileArgs),
+    SockStats,
+    RDMAStats,
+}
+
+// ShmArgs is the shared memory request of the control socket, it attaches the segment of the
+// token as name
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShmArgs {
+    pub name: String,
+    pub token: String,
+}
+
+// ShmAttach makes the host file hostfd of the segment appear as /dev/quark-shm/<name>
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShmAttach {
+    pub name: String,
+    pub token: String,
+    pub hostfd: i32,
+}
+
+// InjectFileArgs writes the content of the host file fds[0] to the absolute path in the sandbox,
+// the target is replaced atomically with rename so the readers see either the old or the new file.
+// The fd is the host fd in the request and the hostfd of the IO_MGR in the payload.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct InjectFileArgs {
+    pub path: String,
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub fds: Vec<i32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
@@ -145,6 +180,64 @@ pub enum UCallResp {
     CreateSubContainerResp,
     StartSubContainerResp,
     WaitAllResp(WaitAllResp),
+    DebugResp(String),
+    ProfileStartResp,
+    ProfileStopResp(ProfileResult),
+    ShmAttachResp(String),
+    InjectFileResp,
+    SockStatsResp(SockStatsInfo),
+    RDMAStatsResp(Vec<RDMAConnStatsInfo>),
+}
+
+// SockStatsInfo is the socket statistics of the sandbox since the boot, the in use sockets are
+// the host inet sockets of the app
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct SockStatsInfo {
+    pub tcpInuse: u64,
+    pub udpInuse: u64,
+    pub bytesIn: u64,
+    pub bytesOut: u64,
+    pub packetsIn: u64,
+    pub packetsOut: u64,
+    pub retransmits: u64,
+    pub acceptOverflows: u64,
+    // the RDMA connections which fall back to the tcp path and the ones reset by the RDMA errors
+    pub rdmaFallbacks: u64,
+    pub rdmaResets: u64,
+    // the host fd events passed to the waiters and the ones coalesced with the pending ones
+    pub fdNotifies: u64,
+    pub fdNotifiesCoalesced: u64,
+}
+
+// RDMAConnStatsInfo is the counters of the RDMA data path of a live socket, fd is the host fd
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RDMAConnStatsInfo {
+    pub fd: i32,
+    pub udp: bool,
+    // the connection has fallen back to the host socket
+    pub fallback: bool,
+    pub writesPosted: u64,
+    pub bytesWritten: u64,
+    pub writeCompletions: u64,
+    pub recvCompletions: u64,
+    pub freespaceStalls: u64,
+    pub retries: u64,
+    pub qpErrors: u64,
+}
+
+// ProfileStack is one sampled stack, frames[0] is the interrupted rip and the rest are
+// the return addresses of the kernel or user stack.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileStack {
+    pub user: bool,
+    pub frames: Vec<u64>,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ProfileResult {
+    pub stacks: Vec<ProfileStack>,
+    pub dropped: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
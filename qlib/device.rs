@@ -29,6 +29,7 @@ pub static PROC_DEVICE : Singleton<Arc<QMutex<Device>>> = Singleton::<Arc<QMutex
 pub static SHM_DEVICE : Singleton<Arc<QMutex<Device>>> = Singleton::<Arc<QMutex<Device>>>::New();
 pub static SYS_DEVICE : Singleton<Arc<QMutex<Device>>> = Singleton::<Arc<QMutex<Device>>>::New();
 pub static TMPFS_DEVICE : Singleton<Arc<QMutex<Device>>> = Singleton::<Arc<QMutex<Device>>>::New();
+pub static CGROUP_DEVICE : Singleton<Arc<QMutex<Device>>> = Singleton::<Arc<QMutex<Device>>>::New();
 
 pub unsafe fn InitSingleton() {
     SIMPLE_DEVICES.Init(QMutex::new(Registry::New()));
@@ -40,6 +41,7 @@ pub unsafe fn InitSingleton() {
     SHM_DEVICE.Init(NewAnonDevice());
     SYS_DEVICE.Init(NewAnonDevice());
     TMPFS_DEVICE.Init(NewAnonDevice());
+    CGROUP_DEVICE.Init(NewAnonDevice());
 }
 
 // TTYAUX_MAJOR is the major device number for alternate TTY devices.
@@ -0,0 +1,485 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+
+use super::mutex::*;
+use super::bytestream::*;
+use super::linux_def::*;
+use super::common::*;
+use super::socket_buf::*;
+
+// the max payload of a udp datagram
+pub const DGRAM_MAX_SIZE: usize = 65536;
+// the buffer for the control messages of a received datagram, e.g. IP_PKTINFO and the timestamps
+pub const DGRAM_CONTROL_SIZE: usize = 256;
+// the bytes of the received datagrams held in the guest and of the datagrams waiting for the
+// host send, same as the default net.core.rmem_default/wmem_default
+pub const DGRAM_RECV_LIMIT: usize = 208 * 1024;
+pub const DGRAM_SEND_LIMIT: usize = 208 * 1024;
+
+#[derive(Default, Clone)]
+pub struct Datagram {
+    // the sender address of the received datagram, the destination of the sent one, empty
+    // for the connected socket
+    pub addr: Vec<u8>,
+    pub data: Vec<u8>,
+    pub control: Vec<u8>,
+}
+
+// the size of sockaddr_in6, the largest inet address
+pub const RDMA_DGRAM_ADDR_SIZE: usize = 28;
+pub const RDMA_DGRAM_HDR_SIZE: usize = mem::size_of::<RDMADgramHdr>();
+
+// RDMADgramHdr is the header of a datagram in the byte streams of the RDMA udp socket. The
+// datagrams received by qvisor from the UD queue pair or the host socket are written to the
+// read buf, the ones sent by the guest to the write buf. A datagram is written as one record.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct RDMADgramHdr {
+    pub len: u32,
+    pub addrLen: u32,
+    pub addr: [u8; RDMA_DGRAM_ADDR_SIZE],
+}
+
+impl RDMADgramHdr {
+    pub fn New(addr: &[u8], len: usize) -> Self {
+        let addrLen = core::cmp::min(addr.len(), RDMA_DGRAM_ADDR_SIZE);
+        let mut hdr = Self {
+            len: len as u32,
+            addrLen: addrLen as u32,
+            addr: [0; RDMA_DGRAM_ADDR_SIZE],
+        };
+
+        hdr.addr[..addrLen].copy_from_slice(&addr[..addrLen]);
+        return hdr
+    }
+
+    pub fn Addr(&self) -> Vec<u8> {
+        let addrLen = core::cmp::min(self.addrLen as usize, RDMA_DGRAM_ADDR_SIZE);
+        return self.addr[..addrLen].to_vec()
+    }
+
+    pub fn AsBytes(&self) -> &[u8] {
+        let ptr = self as *const _ as *const u8;
+        return unsafe { core::slice::from_raw_parts(ptr, RDMA_DGRAM_HDR_SIZE) }
+    }
+}
+
+// PushDgram writes the datagram to the stream, it fails with EAGAIN when the record doesn't
+// fit. ret: whether the stream was empty
+pub fn PushDgram(stream: &mut ByteStream, addr: &[u8], data: &[u8]) -> Result<bool> {
+    let size = RDMA_DGRAM_HDR_SIZE + data.len();
+    if stream.AvailableSpace() < size {
+        return Err(Error::SysError(SysErr::EAGAIN))
+    }
+
+    let mut record = Vec::with_capacity(size);
+    record.extend_from_slice(RDMADgramHdr::New(addr, data.len()).AsBytes());
+    record.extend_from_slice(data);
+    // the record is published by one write so the reader never sees a part of it
+    let (empty, _) = stream.write(&record)?;
+    return Ok(empty)
+}
+
+// PopDgram reads the next datagram of the stream
+pub fn PopDgram(stream: &mut ByteStream) -> Option<Datagram> {
+    if stream.AvailableDataSize() < RDMA_DGRAM_HDR_SIZE {
+        return None
+    }
+
+    let mut hdr = RDMADgramHdr::default();
+    let ptr = &mut hdr as *mut _ as *mut u8;
+    let hdrBytes = unsafe { core::slice::from_raw_parts_mut(ptr, RDMA_DGRAM_HDR_SIZE) };
+    stream.read(hdrBytes).ok()?;
+
+    let mut data = Vec::with_capacity(hdr.len as usize);
+    data.resize(hdr.len as usize, 0);
+    stream.read(&mut data).ok()?;
+    return Some(Datagram {
+        addr: hdr.Addr(),
+        data: data,
+        control: Vec::new(),
+    })
+}
+
+#[derive(Default)]
+pub struct DgramBuffIntern {
+    pub recvQueue: VecDeque<Datagram>,
+    pub recvBytes: usize,
+    // there is a recvmsg of the socket in the uring
+    pub recvArmed: bool,
+
+    pub sendQueue: VecDeque<Datagram>,
+    // bytes of the queued datagrams and the one in the uring
+    pub sendBytes: usize,
+    // there is a sendmsg of the socket in the uring
+    pub sending: bool,
+
+    // errno of the failed uring recvmsg/sendmsg, e.g. ECONNREFUSED of the connected socket
+    pub error: i32,
+    // shutdown(SHUT_RD) or the socket is closed, the recvmsg is not armed any more
+    pub rClosed: bool,
+
+    // the byte streams shared with qvisor when the datagrams go by RDMA, there is no recvmsg
+    // and sendmsg in the uring then
+    pub rdma: Option<Arc<SocketBuff>>,
+}
+
+impl DgramBuffIntern {
+    // PullRDMA moves the datagrams received by qvisor to the recv queue
+    fn PullRDMA(&mut self) {
+        let rdma = match &self.rdma {
+            None => return,
+            Some(r) => r.clone(),
+        };
+
+        if self.error == 0 {
+            self.error = rdma.ConsumeErr();
+        }
+
+        let mut readBuf = rdma.readBuf.lock();
+        while self.recvBytes < DGRAM_RECV_LIMIT {
+            let d = match PopDgram(&mut readBuf) {
+                None => break,
+                Some(d) => d,
+            };
+
+            // the datagrams received after shutdown(SHUT_RD) are dropped
+            if !self.rClosed {
+                self.recvBytes += d.data.len();
+                self.recvQueue.push_back(d);
+            }
+        }
+    }
+}
+
+// DgramBuff is the socket buffer of the udp socket. Unlike the SocketBuff byte stream, it
+// keeps the message boundary and the sender address of each datagram. The datagrams are
+// received by one recvmsg in the uring at a time, which is armed again while the queued bytes
+// are below DGRAM_RECV_LIMIT. The datagrams are sent by one sendmsg in the uring at a time in
+// the order they are written.
+#[derive(Default)]
+pub struct DgramBuff(QMutex<DgramBuffIntern>);
+
+impl fmt::Debug for DgramBuff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = self.0.lock();
+        write!(f, "recv {}/{} armed {} send {}/{} sending {} error {} rClosed {}",
+               b.recvQueue.len(), b.recvBytes, b.recvArmed,
+               b.sendQueue.len(), b.sendBytes, b.sending, b.error, b.rClosed)
+    }
+}
+
+impl DgramBuff {
+    pub fn NewRDMA(rdma: Arc<SocketBuff>) -> Self {
+        let mut b = DgramBuffIntern::default();
+        b.rdma = Some(rdma);
+        return Self(QMutex::new(b))
+    }
+
+    pub fn RDMA(&self) -> Option<Arc<SocketBuff>> {
+        return self.0.lock().rdma.clone()
+    }
+
+    pub fn Events(&self) -> EventMask {
+        let mut b = self.0.lock();
+        b.PullRDMA();
+        let mut event = EventMask::default();
+        if b.recvQueue.len() > 0 {
+            event |= EVENT_IN;
+        }
+
+        if b.rClosed {
+            event |= EVENT_IN | EVENT_RDHUP;
+        }
+
+        let writable = match &b.rdma {
+            None => b.sendBytes < DGRAM_SEND_LIMIT,
+            Some(rdma) => rdma.writeBuf.lock().AvailableSpace() > RDMA_DGRAM_HDR_SIZE,
+        };
+
+        if writable {
+            event |= EVENT_OUT;
+        }
+
+        if b.error != 0 {
+            event |= EVENT_ERR;
+        }
+
+        return event
+    }
+
+    pub fn ConsumeErr(&self) -> i32 {
+        let mut b = self.0.lock();
+        b.PullRDMA();
+        let err = b.error;
+        b.error = 0;
+        return err
+    }
+
+    // ArmRecv returns whether the caller has to start the recvmsg
+    pub fn ArmRecv(&self) -> bool {
+        let mut b = self.0.lock();
+        if b.rdma.is_some() || b.recvArmed || b.rClosed || b.error != 0 || b.recvBytes >= DGRAM_RECV_LIMIT {
+            return false
+        }
+
+        b.recvArmed = true;
+        return true
+    }
+
+    // Produce queues the received datagram, ret: (whether the socket becomes readable,
+    // whether the recvmsg goes on)
+    pub fn Produce(&self, d: Datagram) -> (bool, bool) {
+        let mut b = self.0.lock();
+        // the recvmsg is woken by the host shutdown
+        if b.rClosed {
+            b.recvArmed = false;
+            return (false, false)
+        }
+
+        let trigger = b.recvQueue.len() == 0;
+        b.recvBytes += d.data.len();
+        b.recvQueue.push_back(d);
+
+        let rearm = b.recvBytes < DGRAM_RECV_LIMIT;
+        if !rearm {
+            b.recvArmed = false;
+        }
+
+        return (trigger, rearm)
+    }
+
+    // RecvFail stops the recvmsg on error, it is armed again after the error is consumed
+    pub fn RecvFail(&self, errno: i32) {
+        let mut b = self.0.lock();
+        b.recvArmed = false;
+        if b.error == 0 && !b.rClosed {
+            b.error = errno;
+        }
+    }
+
+    // Recv takes the first received datagram, it stays in the queue with MSG_PEEK
+    pub fn Recv(&self, peek: bool) -> Result<Datagram> {
+        let mut b = self.0.lock();
+        b.PullRDMA();
+        if peek {
+            match b.recvQueue.front() {
+                Some(d) => return Ok(d.clone()),
+                None => (),
+            }
+        } else {
+            match b.recvQueue.pop_front() {
+                Some(d) => {
+                    b.recvBytes -= d.data.len();
+                    return Ok(d)
+                }
+                None => (),
+            }
+        }
+
+        if b.error != 0 {
+            let err = b.error;
+            b.error = 0;
+            return Err(Error::SysError(err))
+        }
+
+        // read returns 0 after shutdown(SHUT_RD)
+        if b.rClosed {
+            return Ok(Datagram::default())
+        }
+
+        return Err(Error::SysError(SysErr::EAGAIN))
+    }
+
+    // ShutdownRead stops the recvmsg, the host socket has to be shut down for the one in the uring
+    pub fn ShutdownRead(&self) {
+        self.0.lock().rClosed = true;
+    }
+
+    // NextSize is the FIONREAD of the udp socket: the size of the first datagram
+    pub fn NextSize(&self) -> usize {
+        let mut b = self.0.lock();
+        b.PullRDMA();
+        match b.recvQueue.front() {
+            Some(d) => return d.data.len(),
+            None => return 0,
+        }
+    }
+
+    // Reserve takes the send buffer space of the datagram of len bytes. The pending error fails
+    // the send once as the host socket does.
+    pub fn Reserve(&self, len: usize) -> Result<()> {
+        let mut b = self.0.lock();
+        if b.error != 0 {
+            let err = b.error;
+            b.error = 0;
+            return Err(Error::SysError(err))
+        }
+
+        // a datagram larger than the limit is taken when the queue is empty
+        if b.sendBytes > 0 && b.sendBytes + len > DGRAM_SEND_LIMIT {
+            return Err(Error::SysError(SysErr::EAGAIN))
+        }
+
+        b.sendBytes += len;
+        return Ok(())
+    }
+
+    // SendRDMA writes the datagram to the write buf of qvisor, it fails with EAGAIN when the
+    // buf is full. ret: whether qvisor has to be notified
+    pub fn SendRDMA(&self, d: &Datagram) -> Result<bool> {
+        let mut b = self.0.lock();
+        b.PullRDMA();
+        if b.error != 0 {
+            let err = b.error;
+            b.error = 0;
+            return Err(Error::SysError(err))
+        }
+
+        let rdma = match &b.rdma {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(r) => r.clone(),
+        };
+
+        let mut writeBuf = rdma.writeBuf.lock();
+        // the datagram larger than the empty buf can never be sent
+        if RDMA_DGRAM_HDR_SIZE + d.data.len() > writeBuf.BufSize() {
+            return Err(Error::SysError(SysErr::EMSGSIZE))
+        }
+
+        return PushDgram(&mut writeBuf, &d.addr, &d.data)
+    }
+
+    // Queue queues the datagram of the reserved space, it returns the datagram when the caller
+    // has to start the sendmsg
+    pub fn Queue(&self, d: Datagram) -> Option<Datagram> {
+        let mut b = self.0.lock();
+        if b.sending {
+            b.sendQueue.push_back(d);
+            return None
+        }
+
+        b.sending = true;
+        return Some(d)
+    }
+
+    // SendDone takes the result of the sendmsg of len bytes, ret: (whether the socket becomes
+    // writable, the next datagram to send)
+    pub fn SendDone(&self, len: usize, result: i32) -> (bool, Option<Datagram>) {
+        let mut b = self.0.lock();
+        let writable = b.sendBytes < DGRAM_SEND_LIMIT;
+        b.sendBytes -= len;
+        // the datagram is dropped when the host socket buffer is full, as the network would
+        if result < 0 && result != -SysErr::EAGAIN && result != -SysErr::ENOBUFS && b.error == 0 {
+            b.error = -result;
+        }
+
+        let next = b.sendQueue.pop_front();
+        if next.is_none() {
+            b.sending = false;
+        }
+
+        return (!writable && b.sendBytes < DGRAM_SEND_LIMIT, next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use super::*;
+
+    fn Dgram(len: usize) -> Datagram {
+        return Datagram {
+            addr: vec![2, 0, 0, 53],
+            data: vec![0; len],
+            control: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_rdma_dgram_hdr() {
+        let addr = [2u8, 0, 0, 53, 10, 0, 0, 1];
+        let hdr = RDMADgramHdr::New(&addr, 100);
+        assert_eq!(hdr.len, 100);
+        assert_eq!(hdr.Addr(), addr.to_vec());
+        assert_eq!(hdr.AsBytes().len(), RDMA_DGRAM_HDR_SIZE);
+        assert_eq!(RDMA_DGRAM_HDR_SIZE, 36);
+
+        // the address is cut to the size of sockaddr_in6
+        let hdr = RDMADgramHdr::New(&[1u8; 40], 0);
+        assert_eq!(hdr.Addr().len(), RDMA_DGRAM_ADDR_SIZE);
+    }
+
+    #[test]
+    fn test_message_boundary() {
+        let buf = DgramBuff::default();
+        assert!(buf.ArmRecv());
+        assert!(!buf.ArmRecv());
+        assert_eq!(buf.Produce(Dgram(10)), (true, true));
+        assert_eq!(buf.Produce(Dgram(20)), (false, true));
+        assert_eq!(buf.NextSize(), 10);
+        assert_eq!(buf.Recv(true).unwrap().data.len(), 10);
+        assert_eq!(buf.Recv(false).unwrap().data.len(), 10);
+        assert_eq!(buf.Recv(false).unwrap().data.len(), 20);
+        assert!(buf.Recv(false).is_err());
+    }
+
+    #[test]
+    fn test_recv_limit() {
+        let buf = DgramBuff::default();
+        assert!(buf.ArmRecv());
+        let n = DGRAM_RECV_LIMIT / DGRAM_MAX_SIZE + 1;
+        for i in 0..n {
+            let (_, rearm) = buf.Produce(Dgram(DGRAM_MAX_SIZE));
+            assert_eq!(rearm, i + 1 < n);
+        }
+
+        assert!(!buf.ArmRecv());
+        buf.Recv(false).unwrap();
+        assert!(buf.ArmRecv());
+    }
+
+    #[test]
+    fn test_send_order_and_error() {
+        let buf = DgramBuff::default();
+        buf.Reserve(1).unwrap();
+        assert!(buf.Queue(Dgram(1)).is_some());
+        buf.Reserve(2).unwrap();
+        assert!(buf.Queue(Dgram(2)).is_none());
+        let (_, next) = buf.SendDone(1, -SysErr::ECONNREFUSED);
+        assert_eq!(next.unwrap().data.len(), 2);
+        let (_, next) = buf.SendDone(2, 2);
+        assert!(next.is_none());
+
+        assert_eq!(buf.Events() & EVENT_ERR, EVENT_ERR);
+        assert!(buf.Reserve(1).is_err());
+        buf.Reserve(1).unwrap();
+        assert!(buf.Queue(Dgram(1)).is_some());
+
+        assert!(buf.Reserve(DGRAM_SEND_LIMIT).is_err());
+        assert!(!buf.SendDone(1, 1).0);
+
+        // the datagram as large as the limit is taken by the empty buffer
+        buf.Reserve(DGRAM_SEND_LIMIT).unwrap();
+        assert!(buf.Queue(Dgram(DGRAM_SEND_LIMIT)).is_some());
+        assert_eq!(buf.Events() & EVENT_OUT, 0);
+        assert!(buf.SendDone(DGRAM_SEND_LIMIT, DGRAM_SEND_LIMIT as i32).0);
+    }
+}
@@ -21,6 +21,7 @@ use super::super::config::*;
 use super::super::qmsg::*;
 use super::super::linux_def::*;
 use super::super::socket_buf::*;
+use super::super::vcpu_mgr::VcpuMask;
 use super::guestfdnotifier::*;
 use super::task::*;
 use super::asm::*;
@@ -77,6 +78,15 @@ impl HostSpace {
         return HostSpace::Call(&mut msg, false) as i64;
     }
 
+    pub fn ReopenFd(fd: i32, flags: i32) -> i64 {
+        let mut msg = Msg::ReopenFd(ReopenFd {
+            fd,
+            flags,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
     pub fn Sysinfo(addr: u64) -> i64 {
         let mut msg = Msg::Sysinfo(Sysinfo {
             addr,
@@ -85,6 +95,74 @@ impl HostSpace {
         return HostSpace::Call(&mut msg, false) as i64;
     }
 
+    pub fn ReadEtcFile(idx: usize, buf: u64, len: usize) -> i64 {
+        let mut msg = Msg::ReadEtcFile(ReadEtcFile {
+            idx,
+            buf,
+            len,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn ReadRouteDump(msgType: u16, buf: u64, len: usize) -> i64 {
+        let mut msg = Msg::ReadRouteDump(ReadRouteDump {
+            msgType,
+            buf,
+            len,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn ReadSandboxIdentity(buf: u64, len: usize) -> i64 {
+        let mut msg = Msg::ReadSandboxIdentity(ReadSandboxIdentity {
+            buf,
+            len,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn ResolveHost(name: &str, family: i32, buf: u64, len: usize) -> i64 {
+        let mut msg = Msg::ResolveHost(ResolveHost {
+            name: name.as_ptr() as u64,
+            nameLen: name.len(),
+            family,
+            buf,
+            len,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn LoadCompatProfiles(addr: u64, len: usize) -> i64 {
+        let mut msg = Msg::LoadCompatProfiles(LoadCompatProfiles {
+            addr,
+            len,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn LoadNatRules(addr: u64, len: usize) -> i64 {
+        let mut msg = Msg::LoadNatRules(LoadNatRules {
+            addr,
+            len,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn LoadNetPolicy(addr: u64, len: usize) -> i64 {
+        let mut msg = Msg::LoadNetPolicy(LoadNetPolicy {
+            addr,
+            len,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
     pub fn EventfdWrite(fd: i32) -> i64 {
         let mut msg = Msg::EventfdWrite(EventfdWrite {
             fd,
@@ -127,7 +205,7 @@ impl HostSpace {
         return HostSpace::HCall(&mut msg, false) as i64;
     }
 
-    pub fn TlbShootdown(vcpuMask: u64) -> i64 {
+    pub fn TlbShootdown(vcpuMask: VcpuMask) -> i64 {
         let mut msg = Msg::TlbShootdown(TlbShootdown {
             vcpuMask: vcpuMask
         });
@@ -214,6 +292,53 @@ impl HostSpace {
         return HostSpace::HCall(&mut msg, false) as i64;
     }
 
+    // LoopbackListen returns the hostfd of the unix listener of the cross sandbox fast path
+    pub fn LoopbackListen(fd: i32) -> i64 {
+        let mut msg = Msg::LoopbackListen(LoopbackListen {
+            fd,
+        });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    pub fn LoopbackAccept(fd: i32, addr: u64, addrlen: u64) -> i64 {
+        let mut msg = Msg::LoopbackAccept(LoopbackAccept {
+            fd,
+            addr,
+            addrlen,
+        });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    // LoopbackShm returns the hostfd of the shared memory of the loopback connection, ENOENT
+    // when the socket is not one
+    pub fn LoopbackShm(fd: i32) -> i64 {
+        let mut msg = Msg::LoopbackShm(LoopbackShm {
+            fd,
+        });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    // SockRetrans returns the sum of the retransmits of the live host tcp sockets
+    pub fn SockRetrans() -> i64 {
+        let mut msg = Msg::SockRetrans(SockRetrans {});
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
+    // GetAcceptPeerInfo fills the AcceptPeerInfo at info, it is called by the async accept
+    // without task context
+    pub fn GetAcceptPeerInfo(fd: i32, info: u64) -> i64 {
+        let mut msg = Msg::GetAcceptPeerInfo(GetAcceptPeerInfo {
+            fd,
+            info,
+        });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
     pub fn IOConnect(fd: i32, addr: u64, addrlen: u32) -> i64 {
         let mut msg = Msg::IOConnect(IOConnect {
             fd,
@@ -262,6 +387,39 @@ impl HostSpace {
         return HostSpace::Call(&mut msg, false) as i64;
     }
 
+    pub fn IORecvMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let mut msg = Msg::IORecvMMsg(IORecvMMsg {
+            fd,
+            msgvec,
+            vlen,
+            flags,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn IOSendMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let mut msg = Msg::IOSendMMsg(IOSendMMsg {
+            fd,
+            msgvec,
+            vlen,
+            flags,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
+    pub fn SendFile(outfd: i32, infd: i32, offset: i64, count: u64) -> i64 {
+        let mut msg = Msg::SendFile(SendFile {
+            outfd,
+            infd,
+            offset,
+            count,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
     pub fn GetTimeOfDay(tv: u64, tz: u64) -> i64 {
         let mut msg = Msg::GetTimeOfDay(GetTimeOfDay {
             tv,
@@ -526,6 +684,17 @@ impl HostSpace {
         return HostSpace::Call(&mut msg, false) as i64;
     }
 
+    pub fn SocketPair(domain: i32, type_: i32, protocol: i32, socketVect: u64) -> i64 {
+        let mut msg = Msg::SocketPair(SocketPair {
+            domain,
+            type_,
+            protocol,
+            socketVect,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
     pub fn GetSockName(sockfd: i32, addr: u64, addrlen: u64) -> i64 {
         let mut msg = Msg::GetSockName(GetSockName {
             sockfd,
@@ -603,6 +772,15 @@ impl HostSpace {
         return HostSpace::Call(&mut msg, false) as i64;
     }
 
+    pub fn RDMAUdpSocket(sockfd: i32, socketBuf: Arc<SocketBuff>) -> i64 {
+        let mut msg = Msg::RDMAUdpSocket(RDMAUdpSocket {
+            sockfd,
+            socketBuf,
+        });
+
+        return HostSpace::Call(&mut msg, false) as i64;
+    }
+
     pub fn RDMANotify(sockfd: i32, typ: RDMANotifyType) -> i64 {
         let mut msg = Msg::RDMANotify(RDMANotify {
             sockfd,
@@ -755,6 +933,17 @@ impl HostSpace {
         return HostSpace::Call(&mut msg, false) as i64;
     }
 
+    pub fn IoUringRegisterBufRing(idx: usize, ringAddr: u64, entries: u32, bgid: u16) -> i64 {
+        let mut msg = Msg::IoUringRegisterBufRing(IoUringRegisterBufRing {
+            idx,
+            ringAddr,
+            entries,
+            bgid,
+        });
+
+        return HostSpace::HCall(&mut msg, false) as i64;
+    }
+
     pub fn Chown(pathname: u64, owner: u32, group: u32) -> i64 {
         let mut msg = Msg::Chown(Chown {
             pathname,
@@ -893,6 +1082,20 @@ impl HostSpace {
         super::SHARESPACE.AQCall(&msg);
     }
 
+    // hand the host side close over to the qcall thread so that the vcpu won't be blocked
+    // by the host close, e.g. flush of the host file or unix socket peer release.
+    // The fd stays in the closing state of GUEST_NOTIFIER until the host reuses it.
+    pub fn AsyncClose(fd: i32) {
+        // the count goes first, the closing set is cleared when there is no pending close
+        super::SHARESPACE.IncrPendingClose();
+        SetClosing(fd);
+        let msg = HostOutputMsg::CloseAsync(CloseAsync {
+            fd,
+        });
+
+        super::SHARESPACE.AQCall(&msg);
+    }
+
     pub fn SyncPrint(level: DebugLevel, str: &str) {
         let msg = Print {
             level,
@@ -906,7 +1109,7 @@ impl HostSpace {
         let bytes = str.as_bytes();
         let trigger = super::SHARESPACE.Log(bytes);
         if trigger {
-            super::IOURING.LogFlush();
+            super::ASYNC_PROCESS.Queue(super::kernel::async_process::AsyncWork::LogFlush);
         }
     }
 
@@ -14,4 +14,5 @@
 
 pub mod context;
 pub mod arch_x86;
-pub mod signal;
\ No newline at end of file
+pub mod signal;
+pub mod shadow_stack;
\ No newline at end of file
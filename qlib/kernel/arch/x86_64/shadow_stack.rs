@@ -0,0 +1,144 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+
+use super::super::super::asm::*;
+
+// user mode CET MSRs
+pub const MSR_IA32_U_CET: u32 = 0x6a0;
+pub const MSR_IA32_PL3_SSP: u32 = 0x6a7;
+
+pub const CET_SHSTK_EN: u64 = 1 << 0;
+pub const CET_WRSS_EN: u64 = 1 << 1;
+
+// arch_prctl codes, from uapi/asm/prctl.h
+pub const ARCH_SHSTK_ENABLE: u64 = 0x5001;
+pub const ARCH_SHSTK_DISABLE: u64 = 0x5002;
+pub const ARCH_SHSTK_LOCK: u64 = 0x5003;
+pub const ARCH_SHSTK_UNLOCK: u64 = 0x5004;
+pub const ARCH_SHSTK_STATUS: u64 = 0x5005;
+
+// ARCH_SHSTK_* features
+pub const ARCH_SHSTK_SHSTK: u64 = 1 << 0;
+pub const ARCH_SHSTK_WRSS: u64 = 1 << 1;
+
+// the shadow stack entries with bit 63 are the data pushed by the kernel, e.g. the signal
+// restore token, which can't be used as return address
+pub const SHSTK_DATA_BIT: u64 = 1 << 63;
+
+// the size limit of the shadow stack, as linux adjust_shstk_size
+pub const SHSTK_MAX_SIZE: u64 = 1 << 32;
+
+// the vcpu supports the user shadow stack, CR4.CET is set by the vmm
+pub static SHADOW_STACK : AtomicBool = AtomicBool::new(false);
+
+// InitShadowStack checks the shadow stack support with CPUID.(EAX=07H, ECX=0):ECX[7]
+pub fn InitShadowStack(enable: bool) {
+    let (_, _, ecx, _) = AsmHostID(7, 0);
+    SHADOW_STACK.store(enable && ecx & (1 << 7) != 0, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn HasShadowStack() -> bool {
+    return SHADOW_STACK.load(Ordering::Relaxed)
+}
+
+// ShadowStack is the CET shadow stack state of a task. The ssp of the running task is kept in
+// IA32_PL3_SSP, it is saved in the context when the task is switched out.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ShadowStack {
+    // ARCH_SHSTK_* features enabled
+    pub features: u64,
+    // ARCH_SHSTK_* features locked by ARCH_SHSTK_LOCK
+    pub locked: u64,
+    // the shadow stack mapped for the task, 0 when it is the parent's one, e.g. vfork child
+    pub base: u64,
+    pub size: u64,
+    pub ssp: u64,
+}
+
+impl ShadowStack {
+    #[inline]
+    pub fn Enabled(&self) -> bool {
+        return self.features & ARCH_SHSTK_SHSTK != 0
+    }
+
+    pub fn Cet(&self) -> u64 {
+        let mut cet = 0;
+        if self.features & ARCH_SHSTK_SHSTK != 0 {
+            cet |= CET_SHSTK_EN;
+        }
+
+        if self.features & ARCH_SHSTK_WRSS != 0 {
+            cet |= CET_WRSS_EN;
+        }
+
+        return cet
+    }
+
+    // Save reads the ssp of the current task
+    #[inline]
+    pub fn Save(&mut self) {
+        if HasShadowStack() && self.Enabled() {
+            self.ssp = ReadMsr(MSR_IA32_PL3_SSP);
+        }
+    }
+
+    // Restore loads the state to the vcpu, the MSRs are written even the shadow stack is
+    // disabled to clear the ones of the last task
+    #[inline]
+    pub fn Restore(&self) {
+        if !HasShadowStack() {
+            return
+        }
+
+        WriteMsr(MSR_IA32_U_CET, self.Cet());
+        WriteMsr(MSR_IA32_PL3_SSP, self.ssp);
+    }
+}
+
+// RestoreTokenTarget returns the ssp saved in the signal restore token at ssp, as linux
+// shstk_pop_sigframe the token must be data and point above itself
+pub fn RestoreTokenTarget(ssp: u64, token: u64) -> Option<u64> {
+    if ssp & 0x7 != 0 || token & SHSTK_DATA_BIT == 0 {
+        return None
+    }
+
+    let target = token & !SHSTK_DATA_BIT;
+    if target & 0x7 != 0 || target <= ssp {
+        return None
+    }
+
+    return Some(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_token_target() {
+        let ssp = 0x7fff_0000_0ff8;
+        assert_eq!(RestoreTokenTarget(ssp, (ssp + 16) | SHSTK_DATA_BIT), Some(ssp + 16));
+        // return address instead of token
+        assert_eq!(RestoreTokenTarget(ssp, ssp + 16), None);
+        // token below the ssp
+        assert_eq!(RestoreTokenTarget(ssp, (ssp - 8) | SHSTK_DATA_BIT), None);
+        // unaligned
+        assert_eq!(RestoreTokenTarget(ssp, (ssp + 12) | SHSTK_DATA_BIT), None);
+        assert_eq!(RestoreTokenTarget(ssp + 4, (ssp + 16) | SHSTK_DATA_BIT), None);
+    }
+}
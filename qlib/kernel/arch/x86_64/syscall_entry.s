@@ -20,6 +20,7 @@
 .globl machine_check_handler
 .globl simd_fp_handler
 .globl virtualization_handler
+.globl control_protection_handler
 .globl security_handler
 
 .extern syscall_handler, CopyData,
@@ -401,6 +402,9 @@ simd_fp_handler:
 virtualization_handler:
     HandlerWithoutErrorCode VirtualizationHandler
 
+control_protection_handler:
+    HandlerWithErrorCode ControlProtectionHandler
+
 security_handler:
     HandlerWithoutErrorCode SecurityHandler
 
@@ -32,6 +32,23 @@ pub fn ReadMsr(msr: u32) -> u64 {
     ((high as u64) << 32) | (low as u64)
 }
 
+// the FSGSBASE instructions, they are only valid with CR4.FSGSBASE
+#[inline]
+pub fn WriteFsBase(addr: u64) {
+    unsafe {
+        llvm_asm!("wrfsbase $0" :: "r" (addr) : "memory" : "volatile");
+    }
+}
+
+#[inline]
+pub fn ReadFsBase() -> u64 {
+    let addr: u64;
+    unsafe {
+        llvm_asm!("rdfsbase $0" : "=r" (addr) : : "memory" : "volatile");
+    }
+    return addr;
+}
+
 #[inline]
 pub fn SwapGs() {
     unsafe {
@@ -28,7 +28,12 @@ use super::super::SetWaitContainerfd;
 use super::super::WaitContainerfd;
 use super::super::IOURING;
 use super::super::SHARESPACE;
+use super::super::profiler::PROFILER;
+use super::super::fs::dev::quark_shm::*;
 use super::process::*;
+use super::debug::*;
+use super::inject::*;
+use super::super::socket::hostinet::stats::INET_SOCKETS;
 
 pub fn ControllerProcessHandler() -> Result<()> {
     let task = Task::Current();
@@ -180,6 +185,45 @@ pub fn ControlMsgHandler(fd: *const u8) {
         Payload::WaitAll => {
             SetWaitContainerfd(fd);
         }
+        Payload::Debug => {
+            let kernel = LOADER.Lock(task).unwrap().kernel.clone();
+            let report = DebugReport(&kernel);
+            WriteControlMsgResp(fd, &UCallResp::DebugResp(report));
+        }
+        Payload::ProfileStart(hz) => {
+            PROFILER.Start(hz);
+            WriteControlMsgResp(fd, &UCallResp::ProfileStartResp);
+        }
+        Payload::ProfileStop => {
+            let result = PROFILER.Stop();
+            WriteControlMsgResp(fd, &UCallResp::ProfileStopResp(result));
+        }
+        Payload::ShmAttach(attach) => {
+            match AttachSegment(task, &attach.name, attach.hostfd) {
+                Ok(()) => {
+                    WriteControlMsgResp(fd, &UCallResp::ShmAttachResp(attach.token));
+                }
+                Err(e) => {
+                    WriteControlMsgResp(fd, &UCallResp::UCallRespErr(format!("{:?}", e)));
+                }
+            }
+        }
+        Payload::InjectFile(args) => {
+            match InjectFile(task, &args) {
+                Ok(()) => {
+                    WriteControlMsgResp(fd, &UCallResp::InjectFileResp);
+                }
+                Err(e) => {
+                    WriteControlMsgResp(fd, &UCallResp::UCallRespErr(format!("{:?}", e)));
+                }
+            }
+        }
+        Payload::SockStats => {
+            WriteControlMsgResp(fd, &UCallResp::SockStatsResp(INET_SOCKETS.Stats()));
+        }
+        Payload::RDMAStats => {
+            WriteControlMsgResp(fd, &UCallResp::RDMAStatsResp(INET_SOCKETS.RDMAStats()));
+        }
     }
 
     // free curent task in the waitfn context
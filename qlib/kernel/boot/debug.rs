@@ -0,0 +1,115 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::collections::btree_set::BTreeSet;
+use core::sync::atomic::Ordering;
+
+use super::super::kernel::kernel::*;
+use super::super::socket::hostinet::socket::*;
+use super::super::IOURING;
+use super::super::SHARESPACE;
+
+// DebugReport is the human readable sandbox state returned to "quark debug"
+pub fn DebugReport(k: &Kernel) -> String {
+    let mut str = format!("== boot\nvm init {} us, kernel boot to exec {} us\n",
+                          SHARESPACE.vmInitTime.load(Ordering::Relaxed),
+                          SHARESPACE.kernelBootTime.load(Ordering::Relaxed));
+    str += &SchedulerReport();
+    str += &TaskReport(k);
+    str += &UringReport();
+    str += &format!("== wakeup\n{}", SHARESPACE.wakeupModerator.Report());
+    str += &MemoryReport();
+    return str
+}
+
+fn SchedulerReport() -> String {
+    let scheduler = &SHARESPACE.scheduler;
+    let mut str = format!("== scheduler\ntasks {} ready {} halted vcpus {} wait mask {:x?}\n",
+                          scheduler.taskCnt.load(Ordering::Relaxed),
+                          scheduler.GlobalReadyTaskCnt(),
+                          scheduler.haltVcpuCnt.load(Ordering::Relaxed),
+                          scheduler.vcpuWaitMask.Load().words);
+    for i in 0..scheduler.vcpuCnt {
+        str += &format!("vcpu {}: {} ready {}\n", i, scheduler.ReadyTaskCnt(i), scheduler.queue[i].ToString());
+    }
+
+    return str
+}
+
+// TaskReport lists the threads of the sandbox and the hostinet sockets in their fd tables
+fn TaskReport(k: &Kernel) -> String {
+    let root = k.TaskSet().Root();
+    let mut tasks = String::from("== tasks\n");
+    let mut sockets = String::from("== sockets\n");
+    // the threads of a process share the fd table in most cases, report each table once
+    let mut fdTbls = BTreeSet::new();
+
+    for tg in root.ThreadGroups() {
+        let pid = root.IDOfThreadGroup(&tg);
+        if pid == 0 {
+            continue;
+        }
+
+        let threads: Vec<_> = tg.lock().tasks.iter().cloned().collect();
+        for thread in &threads {
+            let (name, taskId, exitState, stopped, fdTbl) = {
+                let t = thread.lock();
+                (t.name.to_string(), t.taskId, t.exitState, t.stop.is_some(), t.fdTbl.clone())
+            };
+
+            tasks += &format!("pid {} tid {} task {:x} {} {:?} stopped {}\n",
+                              pid, root.IDOfTask(thread), taskId, name, exitState, stopped);
+
+            if !fdTbls.insert(fdTbl.ID()) {
+                continue;
+            }
+
+            let fdTbl = fdTbl.lock();
+            for fd in fdTbl.GetFDs() {
+                let file = match fdTbl.Get(fd) {
+                    Err(_) => continue,
+                    Ok((file, _)) => file,
+                };
+
+                let fops = file.FileOp.clone();
+                if let Some(sock) = fops.as_any().downcast_ref::<SocketOperations>() {
+                    sockets += &format!("pid {} fd {} {}\n", pid, fd, sock.DebugString());
+                }
+            }
+        }
+    }
+
+    return tasks + &sockets
+}
+
+fn UringReport() -> String {
+    let mut str = String::from("== uring\n");
+    for (i, uring) in IOURING.IOUrings().iter().enumerate() {
+        let sq = uring.sq.lock();
+        let cq = uring.cq.lock();
+        str += &format!("uring {}: sq {}/{} cq {}/{} pending {}\n", i,
+                        sq.len(), sq.capacity(), cq.len(), cq.capacity(),
+                        uring.pendingCnt.load(Ordering::Relaxed));
+    }
+
+    let asyncMgr = &IOURING.asyncMgr;
+    str += &format!("async ops {}/{}\n", asyncMgr.ops.len() - asyncMgr.ids.lock().len(), asyncMgr.ops.len());
+    return str
+}
+
+fn MemoryReport() -> String {
+    return format!("== memory\n{}\n", ::AllocatorPrint(0))
+}
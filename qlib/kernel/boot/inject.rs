@@ -0,0 +1,138 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use super::super::super::auth::*;
+use super::super::super::auth::id::*;
+use super::super::super::common::*;
+use super::super::super::control_msg::*;
+use super::super::super::linux_def::*;
+use super::super::super::path::*;
+use super::super::fd::*;
+use super::super::fs::dirent::*;
+use super::super::fs::file::*;
+use super::super::fs::flags::*;
+use super::super::task::*;
+use super::super::Kernel::HostSpace;
+use super::super::LOADER;
+
+// the max size of the injected file, the content is staged in the kernel heap
+pub const INJECT_FILE_MAX_SIZE: usize = 16 << 20;
+
+static INJECT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// InjectFile creates or replaces args.path in the sandbox root fs with the content of the host
+// file args.fds[0]. The content is written to a temp file in the same dir which is renamed over
+// the target, the hostfd is closed before return.
+pub fn InjectFile(task: &mut Task, args: &InjectFileArgs) -> Result<()> {
+    if args.fds.len() != 1 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let hostfd = args.fds[0];
+    let data = ReadHostFile(hostfd);
+    HostSpace::Close(hostfd);
+    let data = data?;
+
+    if !IsAbs(&args.path) {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let (dir, name) = SplitLast(&args.path);
+    match name {
+        "" | "." | ".." => return Err(Error::SysError(SysErr::EINVAL)),
+        _ => (),
+    }
+
+    let kernel = LOADER.Lock(task)?.kernel.clone();
+    task.creds = Credentials::NewRootCredentials(kernel.RootUserNamespace());
+
+    let mns = kernel.mounts.read().clone().unwrap();
+    let root = mns.Root();
+    let mut remainTraversals = MAX_SYMLINK_TRAVERSALS;
+    let parent = mns.FindDirent(task, &root, None, dir, &mut remainTraversals, true)?;
+    if !parent.Inode().StableAttr().IsDir() {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    let tmpName = format!(".quark-inject-{}", INJECT_SEQ.fetch_add(1, Ordering::Relaxed));
+    let flags = FileFlags {
+        Write: true,
+        LargeFile: true,
+        ..Default::default()
+    };
+
+    let perms = FilePermissions::FromMode(FileMode(args.mode));
+    let file = parent.Create(task, &root, &tmpName, &flags, &perms)?;
+
+    let ret = WriteInjectFile(task, &file, args, &data)
+        .and_then(|_| Dirent::Rename(task, &root, &parent, &tmpName, &parent, name));
+    if ret.is_err() {
+        parent.Remove(task, &root, &tmpName, false).ok();
+        return ret
+    }
+
+    // The rename queued IN_MOVED_TO of the name to the watches of the dir, the watches of the
+    // replaced file get IN_DELETE_SELF.
+
+    info!("InjectFile {} of {} bytes", &args.path, data.len());
+    return Ok(())
+}
+
+fn ReadHostFile(hostfd: i32) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let buf = vec![0u8; MemoryDef::PAGE_SIZE as usize * 16];
+    loop {
+        let iovs = [IoVec::NewFromSlice(&buf)];
+        let cnt = IOReadAt(hostfd, &iovs, data.len() as u64)? as usize;
+        if cnt == 0 {
+            return Ok(data)
+        }
+
+        if data.len() + cnt > INJECT_FILE_MAX_SIZE {
+            return Err(Error::SysError(SysErr::EFBIG))
+        }
+
+        data.extend_from_slice(&buf[..cnt]);
+    }
+}
+
+fn WriteInjectFile(task: &Task, file: &File, args: &InjectFileArgs, data: &[u8]) -> Result<()> {
+    let mut offset = 0;
+    while offset < data.len() {
+        let iovs = [IoVec::NewFromSlice(&data[offset..])];
+        let cnt = file.Pwritev(task, &iovs, offset as i64)?;
+        if cnt <= 0 {
+            return Err(Error::SysError(SysErr::EIO))
+        }
+
+        offset += cnt as usize;
+    }
+
+    file.Fsync(task, 0, FILE_MAX_OFFSET, SyncType::SyncAll)?;
+
+    let mut inode = file.Dirent.Inode();
+    inode.SetOwner(task, &file.Dirent, &FileOwner {
+        UID: KUID(args.uid),
+        GID: KGID(args.gid),
+    })?;
+
+    // the mode is set again as the owner change drops the set-id bits
+    inode.SetPermissions(task, &file.Dirent, FilePermissions::FromMode(FileMode(args.mode)));
+    return Ok(())
+}
@@ -40,6 +40,8 @@ use super::super::threadmgr::thread::*;
 use super::super::threadmgr::thread_group::*;
 use super::super::fs::host::tty::*;
 use super::super::fs::mount::*;
+use super::super::fs::etc::*;
+use super::super::fs::procfs::sys::sysctl::*;
 use super::super::kernel::waiter::qlock::*;
 use super::fs::*;
 
@@ -378,6 +380,8 @@ impl LoaderInternal {
         *SHARESPACE.kernel.lock() = Some(kernel.clone());
 
         let rootMounts = BootInitRootFs(Task::Current(), &process.Root).expect("in loader::New, InitRootfs fail");
+        MountEtcFiles(Task::Current(), &rootMounts, &process.EtcFiles);
+        SYSCTL.Init(&process.Sysctls);
         *kernel.mounts.write() = Some(rootMounts);
 
         info!("after BootInitRootFs");
@@ -19,4 +19,6 @@ pub mod specutils;
 pub mod loader;
 pub mod controller;
 pub mod process;
+pub mod debug;
+pub mod inject;
 
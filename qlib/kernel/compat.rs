@@ -0,0 +1,224 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Compatibility profiles: the per application quirks which let the known problematic apps run
+// before the proper implementation is there. The profiles are loaded by the host from
+// COMPAT_FILE and are selected at exec by the FNV-1a 64 hash of the executable, e.g.
+//
+// [{"Name": "app", "Hash": "cbf29ce484222325", "Quirks": [
+//     {"Syscall": 16, "Args": [null, 21505], "Ret": 0},
+//     {"Sysctl": "/proc/sys/net/core/somaxconn", "Value": "4096\n"}]}]
+//
+// The syscall quirk returns Ret (-errno for error) instead of running the syscall when the set
+// Args match, the sysctl quirk makes the open of the /proc/sys file return Value.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::super::mutex::*;
+
+pub const COMPAT_FILE: &str = "/etc/quark/compat.json";
+pub const COMPAT_FILE_MAX: usize = 64 * 1024;
+
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+pub const FNV_PRIME: u64 = 0x100000001b3;
+// the max executables whose hash is cached, the oldest one is dropped when it is full
+pub const EXEC_HASH_CACHE_MAX: usize = 64;
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct CompatQuirk {
+    #[serde(default)]
+    pub Syscall: Option<u64>,
+    #[serde(default)]
+    pub Args: Vec<Option<u64>>,
+    #[serde(default)]
+    pub Ret: i64,
+    #[serde(default)]
+    pub Sysctl: Option<String>,
+    #[serde(default)]
+    pub Value: String,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct CompatProfile {
+    pub Name: String,
+    // hex of the executable hash
+    pub Hash: String,
+    pub Quirks: Vec<CompatQuirk>,
+}
+
+// FnvHash continues the FNV-1a 64 hash with data, the hash starts with FNV_OFFSET
+pub fn FnvHash(hash: u64, data: &[u8]) -> u64 {
+    let mut hash = hash;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    return hash
+}
+
+// ExecHash is the content hash of the executable inode, it is valid while the mtime and the
+// size of the inode are the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecHash {
+    pub dev: u64,
+    pub ino: u64,
+    pub mtime: i64,
+    pub size: i64,
+    pub hash: u64,
+}
+
+pub struct CompatDb {
+    pub profiles: QRwLock<Vec<(u64, CompatProfile)>>,
+    pub hashes: QMutex<Vec<ExecHash>>,
+}
+
+pub static COMPAT_DB: CompatDb = CompatDb::New();
+
+impl CompatDb {
+    pub const fn New() -> Self {
+        return Self {
+            profiles: QRwLock::new(Vec::new()),
+            hashes: QMutex::new(Vec::new()),
+        }
+    }
+
+    pub fn Load(&self, profiles: Vec<CompatProfile>) {
+        let mut db = self.profiles.write();
+        for p in profiles {
+            match u64::from_str_radix(p.Hash.trim_start_matches("0x"), 16) {
+                Err(_) => error!("compat profile {}: invalid hash {}", p.Name, p.Hash),
+                Ok(hash) => {
+                    info!("compat profile {} is loaded for hash {:016x}", p.Name, hash);
+                    db.push((hash, p));
+                }
+            }
+        }
+    }
+
+    pub fn IsEmpty(&self) -> bool {
+        return self.profiles.read().len() == 0
+    }
+
+    // Lookup returns the profile id of the executable hash
+    pub fn Lookup(&self, hash: u64) -> Option<usize> {
+        return self.profiles.read().iter().position(|(h, _)| *h == hash)
+    }
+
+    // CachedHash returns the hash of the executable inode if it is not changed since it is hashed
+    pub fn CachedHash(&self, dev: u64, ino: u64, mtime: i64, size: i64) -> Option<u64> {
+        return self.hashes.lock().iter()
+            .find(|e| e.dev == dev && e.ino == ino && e.mtime == mtime && e.size == size)
+            .map(|e| e.hash)
+    }
+
+    pub fn CacheHash(&self, entry: ExecHash) {
+        let mut hashes = self.hashes.lock();
+        hashes.retain(|e| e.dev != entry.dev || e.ino != entry.ino);
+        if hashes.len() >= EXEC_HASH_CACHE_MAX {
+            hashes.remove(0);
+        }
+
+        hashes.push(entry);
+    }
+
+    pub fn SyscallQuirk(&self, id: usize, nr: u64, args: &[u64]) -> Option<i64> {
+        let db = self.profiles.read();
+        for q in &db[id].1.Quirks {
+            if q.Syscall != Some(nr) {
+                continue;
+            }
+
+            let matched = q.Args.iter().zip(args.iter()).all(|(want, arg)| match want {
+                None => true,
+                Some(want) => *want == *arg,
+            });
+
+            if matched {
+                return Some(q.Ret)
+            }
+        }
+
+        return None
+    }
+
+    pub fn Sysctl(&self, id: usize, path: &str) -> Option<String> {
+        let db = self.profiles.read();
+        for q in &db[id].1.Quirks {
+            if q.Sysctl.as_ref().map(|s| s.as_str()) == Some(path) {
+                return Some(q.Value.clone())
+            }
+        }
+
+        return None
+    }
+
+    pub fn Name(&self, id: usize) -> String {
+        return self.profiles.read()[id].1.Name.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_fnv() {
+        assert_eq!(FnvHash(FNV_OFFSET, b""), FNV_OFFSET);
+        assert_eq!(FnvHash(FNV_OFFSET, b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(FnvHash(FnvHash(FNV_OFFSET, b"foo"), b"bar"), FnvHash(FNV_OFFSET, b"foobar"));
+    }
+
+    #[test]
+    fn test_quirks() {
+        let db = CompatDb::New();
+        let profiles: Vec<CompatProfile> = serde_json::from_str(r#"[{"Name": "app", "Hash": "0x1f", "Quirks": [
+            {"Syscall": 16, "Args": [null, 21505], "Ret": 0},
+            {"Sysctl": "/proc/sys/net/core/somaxconn", "Value": "4096\n"}]}]"#).unwrap();
+        db.Load(profiles);
+
+        let id = db.Lookup(0x1f).unwrap();
+        assert_eq!(db.Lookup(0x20), None);
+        assert_eq!(db.SyscallQuirk(id, 16, &vec![3, 21505, 0]), Some(0));
+        assert_eq!(db.SyscallQuirk(id, 16, &vec![3, 21506, 0]), None);
+        assert_eq!(db.SyscallQuirk(id, 17, &vec![3, 21505, 0]), None);
+        assert_eq!(db.Sysctl(id, "/proc/sys/net/core/somaxconn"), Some(String::from("4096\n")));
+        assert_eq!(db.Sysctl(id, "/proc/sys/net/core/rmem_max"), None);
+    }
+
+    #[test]
+    fn test_hash_cache() {
+        let db = CompatDb::New();
+        db.CacheHash(ExecHash { dev: 1, ino: 2, mtime: 100, size: 10, hash: 0x1f });
+        assert_eq!(db.CachedHash(1, 2, 100, 10), Some(0x1f));
+        assert_eq!(db.CachedHash(1, 2, 101, 10), None);
+        assert_eq!(db.CachedHash(1, 2, 100, 11), None);
+        assert_eq!(db.CachedHash(1, 3, 100, 10), None);
+
+        // the rewritten executable replaces the old entry
+        db.CacheHash(ExecHash { dev: 1, ino: 2, mtime: 101, size: 10, hash: 0x20 });
+        assert_eq!(db.CachedHash(1, 2, 100, 10), None);
+        assert_eq!(db.CachedHash(1, 2, 101, 10), Some(0x20));
+        assert_eq!(db.hashes.lock().len(), 1);
+
+        for i in 0..EXEC_HASH_CACHE_MAX as u64 {
+            db.CacheHash(ExecHash { dev: 2, ino: i, mtime: 0, size: 0, hash: i });
+        }
+        assert_eq!(db.hashes.lock().len(), EXEC_HASH_CACHE_MAX);
+        assert_eq!(db.CachedHash(1, 2, 101, 10), None);
+        assert_eq!(db.CachedHash(2, 0, 0, 0), Some(0));
+    }
+}
@@ -15,6 +15,8 @@
 use alloc::vec::Vec;
 
 use super::Kernel::HostSpace;
+use super::guestfdnotifier::ClearNotified;
+use super::task::HostErr;
 use super::super::common::*;
 use super::super::linux_def::*;
 use super::super::mem::io::*;
@@ -77,7 +79,10 @@ pub fn IORead(fd: i32, buf: &[IoVec]) -> Result<i64> {
     let ret = HostSpace::IORead(fd, iovsAddr, iovcnt);
 
     if ret < 0 {
-        return Err(Error::SysError(-ret as i32))
+        if ret == -SysErr::EAGAIN as i64 {
+            ClearNotified(fd, EVENT_IN);
+        }
+        return Err(HostErr("IORead", fd, -ret as i32))
     }
 
     return Ok(ret)
@@ -97,7 +102,7 @@ pub fn IOReadAt(fd: i32, buf: &[IoVec], offset: u64) -> Result<i64> {
     let ret = HostSpace::IOReadAt(fd, iovsAddr, iovcnt, offset);
 
     if ret < 0 {
-        return Err(Error::SysError(-ret as i32))
+        return Err(HostErr("IOReadAt", fd, -ret as i32))
     }
 
     return Ok(ret)
@@ -117,7 +122,10 @@ pub fn IOTTYRead(fd: i32, buf: &[IoVec]) -> Result<i64> {
     let ret = HostSpace::IOTTYRead(fd, iovsAddr, iovcnt);
 
     if ret < 0 {
-        return Err(Error::SysError(-ret as i32))
+        if ret == -SysErr::EAGAIN as i64 {
+            ClearNotified(fd, EVENT_IN);
+        }
+        return Err(HostErr("IOTTYRead", fd, -ret as i32))
     }
 
     return Ok(ret)
@@ -133,7 +141,10 @@ pub fn IOWrite(fd: i32, buf: &[IoVec]) -> Result<i64> {
     let ret = HostSpace::IOWrite(fd, iovsAddr, iovcnt);
 
     if ret < 0 {
-        return Err(Error::SysError(-ret as i32))
+        if ret == -SysErr::EAGAIN as i64 {
+            ClearNotified(fd, EVENT_OUT);
+        }
+        return Err(HostErr("IOWrite", fd, -ret as i32))
     }
 
     return Ok(ret)
@@ -152,7 +163,7 @@ pub fn IOWriteAt(fd: i32, buf: &[IoVec], offset: u64) -> Result<i64> {
     let ret = HostSpace::IOWriteAt(fd, iovsAddr, iovcnt, offset);
 
     if ret < 0 {
-        return Err(Error::SysError(-ret as i32))
+        return Err(HostErr("IOWriteAt", fd, -ret as i32))
     }
 
     return Ok(ret)
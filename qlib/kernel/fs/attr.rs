@@ -46,6 +46,7 @@ pub enum InodeFileType {
     SimpleFileInode,
     SymlinkNode,
     DirNode,
+    CgroupDir,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -0,0 +1,396 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The control files of the cgroup directories. The values written by the cgroup managers,
+// e.g. the memory.max set by runc, are checked and kept in memory, the limits are not enforced
+// inside the sandbox.
+
+use core::any::Any;
+use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use crate::qlib::mutex::*;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::auth::*;
+use super::super::super::super::device::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::task::*;
+use super::super::fsutil::file::*;
+use super::super::fsutil::inode::simple_file_inode::*;
+use super::super::host::hostinodeop::*;
+use super::super::attr::*;
+use super::super::file::*;
+use super::super::flags::*;
+use super::super::dentry::*;
+use super::super::dirent::*;
+use super::super::mount::*;
+use super::super::inode::*;
+
+// CGROUP_WRITE_MAX is the max size of a value written to a control file
+pub const CGROUP_WRITE_MAX: usize = 4096;
+
+// CGROUP_CONTROLLERS are the controllers listed in cgroup.controllers
+pub const CGROUP_CONTROLLERS: [&str; 3] = ["cpu", "memory", "pids"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgroupControlType {
+    // the pids of the cgroup, one per line
+    Procs,
+    // the enabled controllers, "+name" enables one and "-name" disables it
+    Controllers,
+    // an integer or "max"
+    Limit,
+    // "$MAX $PERIOD" of cpu.max, the max is an integer or "max"
+    CpuMax,
+    // an integer in [1, 10000]
+    Weight,
+    // 0 or 1
+    Bool,
+    ReadOnly,
+}
+
+pub struct CgroupControlDef {
+    pub name: &'static str,
+    pub typ: CgroupControlType,
+    pub default: &'static str,
+    // the root cgroup has no limits, only the files with root set
+    pub root: bool,
+}
+
+pub const CGROUP_CONTROLS: [CgroupControlDef; 20] = [
+    CgroupControlDef { name: "cgroup.controllers", typ: CgroupControlType::ReadOnly, default: "cpu memory pids", root: true },
+    CgroupControlDef { name: "cgroup.events", typ: CgroupControlType::ReadOnly, default: "populated 0\nfrozen 0", root: false },
+    CgroupControlDef { name: "cgroup.freeze", typ: CgroupControlType::Bool, default: "0", root: false },
+    CgroupControlDef { name: "cgroup.max.depth", typ: CgroupControlType::Limit, default: "max", root: true },
+    CgroupControlDef { name: "cgroup.max.descendants", typ: CgroupControlType::Limit, default: "max", root: true },
+    CgroupControlDef { name: "cgroup.procs", typ: CgroupControlType::Procs, default: "", root: true },
+    CgroupControlDef { name: "cgroup.subtree_control", typ: CgroupControlType::Controllers, default: "", root: true },
+    CgroupControlDef { name: "cgroup.threads", typ: CgroupControlType::Procs, default: "", root: true },
+    CgroupControlDef { name: "cgroup.type", typ: CgroupControlType::ReadOnly, default: "domain", root: false },
+    CgroupControlDef { name: "cpu.max", typ: CgroupControlType::CpuMax, default: "max 100000", root: false },
+    CgroupControlDef { name: "cpu.stat", typ: CgroupControlType::ReadOnly, default: "usage_usec 0\nuser_usec 0\nsystem_usec 0", root: true },
+    CgroupControlDef { name: "cpu.weight", typ: CgroupControlType::Weight, default: "100", root: false },
+    CgroupControlDef { name: "memory.current", typ: CgroupControlType::ReadOnly, default: "0", root: false },
+    CgroupControlDef { name: "memory.high", typ: CgroupControlType::Limit, default: "max", root: false },
+    CgroupControlDef { name: "memory.low", typ: CgroupControlType::Limit, default: "0", root: false },
+    CgroupControlDef { name: "memory.max", typ: CgroupControlType::Limit, default: "max", root: false },
+    CgroupControlDef { name: "memory.min", typ: CgroupControlType::Limit, default: "0", root: false },
+    CgroupControlDef { name: "memory.swap.max", typ: CgroupControlType::Limit, default: "max", root: false },
+    CgroupControlDef { name: "pids.current", typ: CgroupControlType::ReadOnly, default: "0", root: false },
+    CgroupControlDef { name: "pids.max", typ: CgroupControlType::Limit, default: "max", root: false },
+];
+
+fn ParseLimit(value: &str) -> Result<()> {
+    if value == "max" || value.parse::<u64>().is_ok() {
+        return Ok(())
+    }
+
+    return Err(Error::SysError(SysErr::EINVAL))
+}
+
+// ParseCgroupControl checks the value written to the control file and returns the new content
+// of the file, pid is the thread group of the writer for the pid 0 written to cgroup.procs
+pub fn ParseCgroupControl(typ: CgroupControlType, current: &str, value: &str, pid: i32) -> Result<String> {
+    let value = value.trim();
+    match typ {
+        CgroupControlType::Procs => {
+            let mut p = match value.parse::<i32>() {
+                Ok(p) if p >= 0 => p,
+                _ => return Err(Error::SysError(SysErr::EINVAL)),
+            };
+
+            if p == 0 {
+                p = pid;
+            }
+
+            let p = p.to_string();
+            if current.lines().any(|l| l == p) {
+                return Ok(current.to_string())
+            }
+
+            if current.len() == 0 {
+                return Ok(p)
+            }
+
+            return Ok(current.to_string() + "\n" + &p)
+        }
+        CgroupControlType::Controllers => {
+            let mut enabled: Vec<&str> = current.split_whitespace().collect();
+            for tok in value.split_whitespace() {
+                let (enable, name) = match tok.as_bytes()[0] {
+                    b'+' => (true, &tok[1..]),
+                    b'-' => (false, &tok[1..]),
+                    _ => return Err(Error::SysError(SysErr::EINVAL)),
+                };
+
+                if !CGROUP_CONTROLLERS.contains(&name) {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                enabled.retain(|c| *c != name);
+                if enable {
+                    enabled.push(name);
+                }
+            }
+
+            let enabled: Vec<&str> = CGROUP_CONTROLLERS.iter()
+                .filter(|c| enabled.contains(c))
+                .map(|c| *c)
+                .collect();
+            return Ok(enabled.join(" "))
+        }
+        CgroupControlType::Limit => {
+            ParseLimit(value)?;
+            return Ok(value.to_string())
+        }
+        CgroupControlType::CpuMax => {
+            let fields: Vec<&str> = value.split_whitespace().collect();
+            if fields.len() == 0 || fields.len() > 2 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            ParseLimit(fields[0])?;
+            let period = if fields.len() == 2 {
+                fields[1]
+            } else {
+                current.split_whitespace().nth(1).unwrap_or("100000")
+            };
+
+            if period.parse::<u64>().is_err() {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            return Ok(fields[0].to_string() + " " + period)
+        }
+        CgroupControlType::Weight => {
+            match value.parse::<u64>() {
+                Ok(w) if w >= 1 && w <= 10000 => return Ok(value.to_string()),
+                _ => return Err(Error::SysError(SysErr::EINVAL)),
+            }
+        }
+        CgroupControlType::Bool => {
+            if value != "0" && value != "1" {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            return Ok(value.to_string())
+        }
+        CgroupControlType::ReadOnly => return Err(Error::SysError(SysErr::EINVAL)),
+    }
+}
+
+pub struct CgroupControl {
+    pub typ: CgroupControlType,
+    pub value: Arc<QMutex<String>>,
+}
+
+impl SimpleFileTrait for CgroupControl {
+    fn GetFile(&self, _task: &Task, _dir: &Inode, dirent: &Dirent, flags: FileFlags) -> Result<File> {
+        let fops = CgroupFileOperations {
+            typ: self.typ,
+            value: self.value.clone(),
+        };
+
+        let file = File::New(dirent, &flags, fops);
+        return Ok(file);
+    }
+
+    // the writes replace the value, the truncate of the open for write is a no-op
+    fn Truncate(&self, _task: &Task, _size: i64) -> Result<()> {
+        return Ok(())
+    }
+}
+
+pub fn NewCgroupControlFile(task: &Task, def: &CgroupControlDef, owner: &FileOwner, msrc: &Arc<QMutex<MountSource>>) -> Inode {
+    let mode = if def.typ == CgroupControlType::ReadOnly {
+        0o444
+    } else {
+        0o644
+    };
+
+    let v = SimpleFileInode::New(task,
+                                 owner,
+                                 &FilePermissions::FromMode(FileMode(mode)),
+                                 FSMagic::CGROUP2_SUPER_MAGIC,
+                                 false,
+                                 CgroupControl {
+                                     typ: def.typ,
+                                     value: Arc::new(QMutex::new(def.default.to_string())),
+                                 });
+
+    let deviceId = CGROUP_DEVICE.lock().DeviceID();
+    let inodeId = CGROUP_DEVICE.lock().NextIno();
+    let attr = StableAttr {
+        Type: InodeType::SpecialFile,
+        DeviceId: deviceId,
+        InodeId: inodeId,
+        BlockSize: MemoryDef::PAGE_SIZE as i64,
+        DeviceFileMajor: 0,
+        DeviceFileMinor: 0,
+    };
+
+    return Inode::New(&Arc::new(v), msrc, &attr)
+}
+
+pub struct CgroupFileOperations {
+    pub typ: CgroupControlType,
+    pub value: Arc<QMutex<String>>,
+}
+
+impl Waitable for CgroupFileOperations {
+    fn Readiness(&self, _task: &Task,mask: EventMask) -> EventMask {
+        return mask
+    }
+
+    fn EventRegister(&self, _task: &Task,_e: &WaitEntry, _mask: EventMask) {
+    }
+
+    fn EventUnregister(&self, _task: &Task,_e: &WaitEntry) {
+    }
+}
+
+impl SpliceOperations for CgroupFileOperations {}
+
+impl FileOperations for CgroupFileOperations {
+    fn as_any(&self) -> &Any {
+        return self
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::CgroupFileOperations
+    }
+
+    fn Seekable(&self) -> bool {
+        return true;
+    }
+
+    fn Seek(&self, task: &Task, f: &File, whence: i32, current: i64, offset: i64) -> Result<i64> {
+        return SeekWithDirCursor(task, f, whence, current, offset, None)
+    }
+
+    fn ReadDir(&self, _task: &Task, _f: &File, _offset: i64, _serializer: &mut DentrySerializer) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], offset: i64, _blocking: bool) -> Result<i64> {
+        if offset < 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mut buf = self.value.lock().clone();
+        if buf.len() > 0 {
+            buf += "\n";
+        }
+
+        if offset as usize > buf.len() {
+            return Ok(0)
+        }
+
+        let n = task.CopyDataOutToIovs(&buf.as_bytes()[offset as usize ..], dsts)?;
+        return Ok(n as i64)
+    }
+
+    // each write is one value whatever the offset, as the kernfs files of linux
+    fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+        let size = IoVec::NumBytes(srcs);
+        if size > CGROUP_WRITE_MAX {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mut buf: Vec<u8> = Vec::with_capacity(size);
+        buf.resize(size, 0);
+        let n = task.CopyDataInFromIovs(&mut buf, srcs)?;
+        let value = match core::str::from_utf8(&buf[..n]) {
+            Err(_) => return Err(Error::SysError(SysErr::EINVAL)),
+            Ok(v) => v,
+        };
+
+        let pid = task.Thread().ThreadGroup().ID();
+        let mut current = self.value.lock();
+        *current = ParseCgroupControl(self.typ, &current, value, pid)?;
+        return Ok(n as i64)
+    }
+
+    fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
+        let n = self.WriteAt(task, f, srcs, 0, false)?;
+        return Ok((n, 0))
+    }
+
+    fn Fsync(&self, _task: &Task, _f: &File, _start: i64, _end: i64, _syncType: SyncType) -> Result<()> {
+        return Ok(())
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(())
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        let inode = f.Dirent.Inode();
+        return inode.UnstableAttr(task);
+    }
+
+    fn Ioctl(&self, _task: &Task, _f: &File, _fd: i32, _request: u64, _val: u64) -> Result<()> {
+        return Err(Error::SysError(SysErr::ENOTTY))
+    }
+
+    fn IterateDir(&self, _task: &Task, _d: &Dirent, _dirCtx: &mut DirCtx, _offset: i32) -> (i32, Result<i64>) {
+        return (0, Err(Error::SysError(SysErr::ENOTDIR)))
+    }
+
+    fn Mappable(&self) -> Result<HostInodeOp> {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+}
+
+impl SockOperations for CgroupFileOperations {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cgroup_procs() {
+        let procs = ParseCgroupControl(CgroupControlType::Procs, "", "12\n", 1).unwrap();
+        assert_eq!(procs, "12");
+        let procs = ParseCgroupControl(CgroupControlType::Procs, &procs, "0", 7).unwrap();
+        assert_eq!(procs, "12\n7");
+        assert_eq!(ParseCgroupControl(CgroupControlType::Procs, &procs, "12", 1).unwrap(), procs);
+        assert!(ParseCgroupControl(CgroupControlType::Procs, "", "-1", 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_cgroup_controllers() {
+        let enabled = ParseCgroupControl(CgroupControlType::Controllers, "", "+pids +cpu", 1).unwrap();
+        assert_eq!(enabled, "cpu pids");
+        let enabled = ParseCgroupControl(CgroupControlType::Controllers, &enabled, "-cpu +memory", 1).unwrap();
+        assert_eq!(enabled, "memory pids");
+        assert!(ParseCgroupControl(CgroupControlType::Controllers, "", "+io", 1).is_err());
+        assert!(ParseCgroupControl(CgroupControlType::Controllers, "", "cpu", 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_cgroup_limits() {
+        assert_eq!(ParseCgroupControl(CgroupControlType::Limit, "max", "1048576\n", 1).unwrap(), "1048576");
+        assert_eq!(ParseCgroupControl(CgroupControlType::Limit, "0", "max", 1).unwrap(), "max");
+        assert!(ParseCgroupControl(CgroupControlType::Limit, "max", "-1", 1).is_err());
+        assert_eq!(ParseCgroupControl(CgroupControlType::CpuMax, "max 100000", "50000", 1).unwrap(), "50000 100000");
+        assert_eq!(ParseCgroupControl(CgroupControlType::CpuMax, "max 100000", "max 20000", 1).unwrap(), "max 20000");
+        assert!(ParseCgroupControl(CgroupControlType::Weight, "100", "0", 1).is_err());
+        assert!(ParseCgroupControl(CgroupControlType::Bool, "0", "2", 1).is_err());
+        assert!(ParseCgroupControl(CgroupControlType::ReadOnly, "0", "1", 1).is_err());
+    }
+}
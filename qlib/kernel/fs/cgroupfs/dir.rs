@@ -0,0 +1,257 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use alloc::sync::Arc;
+use crate::qlib::mutex::*;
+use core::any::Any;
+use alloc::collections::btree_map::BTreeMap;
+
+use super::super::super::socket::unix::transport::unix::*;
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::auth::*;
+use super::super::super::super::device::*;
+use super::super::super::kernel::time::*;
+use super::super::super::task::*;
+use super::super::attr::*;
+use super::super::mount::*;
+use super::super::flags::*;
+use super::super::file::*;
+use super::super::inode::*;
+use super::super::dirent::*;
+use super::super::host::hostinodeop::*;
+use super::super::ramfs::dir::*;
+use super::control::*;
+
+pub const CGROUP_FSINFO : FsInfo = FsInfo {
+    Type: FSMagic::CGROUP2_SUPER_MAGIC,
+    TotalBlocks: 0,
+    FreeBlocks: 0,
+    TotalFiles: 0,
+    FreeFiles: 0,
+};
+
+// NewCgroupDir creates the cgroup directory with its control files
+pub fn NewCgroupDir(task: &Task,
+                    owner: &FileOwner,
+                    perms: &FilePermissions,
+                    msrc: &Arc<QMutex<MountSource>>,
+                    root: bool) -> Inode {
+    let mut contents = BTreeMap::new();
+    for def in &CGROUP_CONTROLS {
+        if root && !def.root {
+            continue;
+        }
+
+        contents.insert(def.name.to_string(), NewCgroupControlFile(task, def, owner, msrc));
+    }
+
+    let d = CgroupDir(Dir::New(task, contents, owner, perms));
+    d.0.write().CreateOps = CreateOps {
+        NewDir: Some(NewCgroupDirFn),
+        ..Default::default()
+    };
+
+    let deviceId = CGROUP_DEVICE.lock().DeviceID();
+    let inodeId = CGROUP_DEVICE.lock().NextIno();
+    let attr = StableAttr {
+        Type: InodeType::Directory,
+        DeviceId: deviceId,
+        InodeId: inodeId,
+        BlockSize: MemoryDef::PAGE_SIZE as i64,
+        DeviceFileMajor: 0,
+        DeviceFileMinor: 0,
+    };
+
+    return Inode::New(&Arc::new(d), msrc, &attr);
+}
+
+fn NewCgroupDirFn(task: &Task, dir: &Inode, perms: &FilePermissions) -> Result<Inode> {
+    let msrc = dir.lock().MountSource.clone();
+    return Ok(NewCgroupDir(task, &task.FileOwner(), perms, &msrc, false))
+}
+
+// CgroupDir is a cgroup, mkdir creates the child cgroup and the files are the control files
+pub struct CgroupDir(pub Dir);
+
+impl CgroupDir {
+    pub fn HasChildCgroups(&self) -> bool {
+        return self.0.read().children.values().any(|i| i.StableAttr().IsDir())
+    }
+}
+
+impl InodeOperations for CgroupDir {
+    fn as_any(&self) -> &Any {
+        return self
+    }
+
+    fn IopsType(&self) -> IopsType {
+        return IopsType::CgroupDir;
+    }
+
+    fn InodeType(&self) -> InodeType {
+        return self.0.InodeType();
+    }
+
+    fn InodeFileType(&self) -> InodeFileType{
+        return InodeFileType::CgroupDir;
+    }
+
+    fn WouldBlock(&self) -> bool {
+        return self.0.WouldBlock();
+    }
+
+    fn Lookup(&self, task: &Task, dir: &Inode, name: &str) -> Result<Dirent> {
+        return self.0.Lookup(task, dir, name)
+    }
+
+    fn Create(&self, task: &Task, dir: &mut Inode, name: &str, flags: &FileFlags, perm: &FilePermissions) -> Result<File> {
+        return self.0.Create(task, dir, name, flags, perm)
+    }
+
+    fn CreateDirectory(&self, task: &Task, dir: &mut Inode, name: &str, perm: &FilePermissions) -> Result<()> {
+        return self.0.CreateDirectory(task, dir, name, perm)
+    }
+
+    fn CreateLink(&self, task: &Task, dir: &mut Inode, oldname: &str, newname: &str) -> Result<()> {
+        return self.0.CreateLink(task, dir, oldname, newname)
+    }
+
+    fn CreateHardLink(&self, _task: &Task, _dir: &mut Inode, _target: &Inode, _name: &str) -> Result<()> {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    fn CreateFifo(&self, task: &Task, dir: &mut Inode, name: &str, perm: &FilePermissions) -> Result<()> {
+        return self.0.CreateFifo(task, dir, name, perm)
+    }
+
+    // the control files go away with the cgroup only
+    fn Remove(&self, _task: &Task, _dir: &mut Inode, _name: &str) -> Result<()> {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    // The cgroup is removed with its control files when it has no child cgroup. The processes
+    // written to cgroup.procs are not tracked after they exit, so they don't keep the cgroup.
+    fn RemoveDirectory(&self, task: &Task, _dir: &mut Inode, name: &str) -> Result<()> {
+        if name.len() > NAME_MAX {
+            return Err(Error::SysError(SysErr::ENAMETOOLONG))
+        }
+
+        let mut d = self.0.write();
+        let child = d.walk(name)?;
+        let op = child.lock().InodeOp.clone();
+        match op.as_any().downcast_ref::<CgroupDir>() {
+            None => return Err(Error::SysError(SysErr::ENOTDIR)),
+            Some(cgroup) => {
+                if cgroup.HasChildCgroups() {
+                    return Err(Error::SysError(SysErr::EBUSY))
+                }
+            }
+        }
+
+        d.removeChild(task, name)?;
+        return Ok(())
+    }
+
+    fn Rename(&self, _task: &Task, _dir: &mut Inode, _oldParent: &Inode, _oldname: &str, _newParent: &Inode, _newname: &str, _replacement: bool) -> Result<()> {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    fn Bind(&self, task: &Task, dir: &Inode, name: &str, data: &BoundEndpoint, perms: &FilePermissions) -> Result<Dirent> {
+        return self.0.Bind(task, dir, name, data, perms)
+    }
+
+    fn BoundEndpoint(&self, task: &Task, inode: &Inode, path: &str) -> Option<BoundEndpoint> {
+        return self.0.BoundEndpoint(task, inode, path)
+    }
+
+    fn GetFile(&self, task: &Task, dir: &Inode, dirent: &Dirent, flags: FileFlags) -> Result<File> {
+        return self.0.GetFile(task, dir, dirent, flags)
+    }
+
+    fn UnstableAttr(&self, task: &Task, dir: &Inode) -> Result<UnstableAttr> {
+        return self.0.UnstableAttr(task, dir)
+    }
+
+    fn Getxattr(&self, dir: &Inode, name: &str) -> Result<String> {
+        return self.0.Getxattr(dir, name)
+    }
+
+    fn Setxattr(&self, dir: &mut Inode, name: &str, value: &str) -> Result<()> {
+        return self.0.Setxattr(dir, name, value)
+    }
+
+    fn Listxattr(&self, dir: &Inode) -> Result<Vec<String>> {
+        return self.0.Listxattr(dir)
+    }
+
+    fn Check(&self, task: &Task, inode: &Inode, reqPerms: &PermMask) -> Result<bool> {
+        return self.0.Check(task, inode, reqPerms)
+    }
+
+    fn SetPermissions(&self, task: &Task, dir: &mut Inode, f: FilePermissions) -> bool {
+        return self.0.SetPermissions(task, dir, f)
+    }
+
+    fn SetOwner(&self, task: &Task, dir: &mut Inode, owner: &FileOwner) -> Result<()> {
+        return self.0.SetOwner(task, dir, owner)
+    }
+
+    fn SetTimestamps(&self, task: &Task, dir: &mut Inode, ts: &InterTimeSpec) -> Result<()> {
+        return self.0.SetTimestamps(task, dir, ts)
+    }
+
+    fn Truncate(&self, task: &Task, dir: &mut Inode, size: i64) -> Result<()> {
+        return self.0.Truncate(task, dir, size)
+    }
+
+    fn Allocate(&self, task: &Task, dir: &mut Inode, offset: i64, length: i64) -> Result<()> {
+        return self.0.Allocate(task, dir, offset, length)
+    }
+
+    fn ReadLink(&self, task: &Task,dir: &Inode) -> Result<String> {
+        return self.0.ReadLink(task, dir)
+    }
+
+    fn GetLink(&self, task: &Task, dir: &Inode) -> Result<Dirent> {
+        return self.0.GetLink(task, dir)
+    }
+
+    fn AddLink(&self, task: &Task) {
+        return self.0.AddLink(task)
+    }
+
+    fn DropLink(&self, task: &Task) {
+        return self.0.DropLink(task)
+    }
+
+    fn IsVirtual(&self) -> bool {
+        return self.0.IsVirtual()
+    }
+
+    fn Sync(&self) -> Result<()> {
+        return self.0.Sync()
+    }
+
+    fn StatFS(&self, _task: &Task) -> Result<FsInfo> {
+        return Ok(CGROUP_FSINFO)
+    }
+
+    fn Mappable(&self) -> Result<HostInodeOp> {
+        return self.0.Mappable()
+    }
+}
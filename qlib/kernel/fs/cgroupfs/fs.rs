@@ -0,0 +1,70 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use crate::qlib::mutex::*;
+use alloc::string::String;
+use alloc::string::ToString;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::auth::*;
+use super::super::super::task::*;
+use super::super::filesystems::*;
+use super::super::inode::*;
+use super::super::mount::*;
+use super::dir::*;
+
+// CGROUP_ROOT is the root of the cgroup2 hierarchy. There is no cgroup namespace, all the
+// mounts of cgroup2 share the hierarchy.
+pub static CGROUP_ROOT: QMutex<Option<Inode>> = QMutex::new(None);
+
+pub struct CgroupFileSystem {}
+
+impl Filesystem for CgroupFileSystem {
+    fn Name(&self) -> String {
+        return "cgroup2".to_string();
+    }
+
+    fn Flags(&self) -> FilesystemFlags {
+        return 0;
+    }
+
+    // the mount options, e.g. nsdelegate, only change the delegation rules and are ignored
+    fn Mount(&mut self, task: &Task, _device: &str, flags: &MountSourceFlags, _data: &str) -> Result<Inode> {
+        info!("cgroup2 file system mount ...");
+
+        let mut root = CGROUP_ROOT.lock();
+        if let Some(inode) = root.as_ref() {
+            return Ok(inode.clone())
+        }
+
+        let msrc = MountSource::NewCachingMountSource(self, flags);
+        let inode = NewCgroupDir(task,
+                                 &ROOT_OWNER,
+                                 &FilePermissions::FromMode(FileMode(0o755)),
+                                 &Arc::new(QMutex::new(msrc)),
+                                 true);
+        *root = Some(inode.clone());
+        return Ok(inode)
+    }
+
+    fn AllowUserMount(&self) -> bool {
+        return true;
+    }
+
+    fn AllowUserList(&self) -> bool {
+        return true;
+    }
+}
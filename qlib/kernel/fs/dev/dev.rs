@@ -32,6 +32,7 @@ use super::zero::*;
 use super::full::*;
 use super::random::*;
 use super::tty::*;
+use super::quark_shm::*;
 
 const MEM_DEV_MAJOR: u16 = 1;
 
@@ -221,6 +222,23 @@ fn NewSymlink(task: &Task, target: &str, msrc: &Arc<QMutex<MountSource>>) -> Ino
     return Inode(Arc::new(QMutex::new(inodeInternal)))
 }
 
+// NewDeviceNode returns the inode of the character device created by mknod, None when the
+// device is not one of the standard devices of /dev
+pub fn NewDeviceNode(task: &Task, major: u16, minor: u32, owner: &FileOwner, mode: &FileMode, msrc: &Arc<QMutex<MountSource>>) -> Option<Inode> {
+    let inode = match (major, minor) {
+        (MEM_DEV_MAJOR, NULL_DEV_MINOR) => NewNullDevice(&Arc::new(NullDevice::New(task, owner, mode)), msrc),
+        (MEM_DEV_MAJOR, ZERO_DEV_MINOR) => NewZeroDevice(&Arc::new(ZeroDevice::New(task, owner, mode)), msrc),
+        (MEM_DEV_MAJOR, FULL_DEV_MINOR) => NewFullDevice(&Arc::new(FullDevice::New(task, owner, mode)), msrc),
+        (MEM_DEV_MAJOR, RANDOM_DEV_MINOR) | (MEM_DEV_MAJOR, URANDOM_DEV_MINOR) => {
+            NewRandomDevice(&Arc::new(RandomDevice::New(task, owner, mode)), msrc, minor)
+        }
+        (TTYAUX_MAJOR, 0) => NewTTYDevice(&Arc::new(TTYDevice::New(task, owner, mode)), msrc),
+        _ => return None,
+    };
+
+    return Some(inode)
+}
+
 pub fn NewDev(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
     let mut contents = BTreeMap::new();
 
@@ -260,6 +278,9 @@ pub fn NewDev(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
     let ttyDevice = TTYDevice::New(task, &ROOT_OWNER, &FileMode(0o0666));
     contents.insert("tty".to_string(), NewTTYDevice(&Arc::new(ttyDevice), msrc));
 
+    // the shared memory segments of "quark shm"
+    contents.insert("quark-shm".to_string(), NewQuarkShmDir(task, msrc));
+
     let iops = Dir::New(task, contents, &ROOT_OWNER, &FilePermissions::FromMode(FileMode(0o0555)));
 
     let deviceId = DEV_DEVICE.lock().id.DeviceID();
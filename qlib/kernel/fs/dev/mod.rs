@@ -19,6 +19,7 @@ pub mod random;
 pub mod full;
 pub mod fs;
 pub mod tty;
+pub mod quark_shm;
 
 use alloc::sync::Arc;
 use crate::qlib::mutex::*;
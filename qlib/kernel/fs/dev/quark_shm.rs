@@ -0,0 +1,112 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use alloc::sync::Weak;
+use crate::qlib::mutex::*;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::device::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::auth::*;
+use super::super::super::uid::NewUID;
+use super::super::super::task::*;
+use super::super::super::Kernel::HostSpace;
+use super::super::mount::*;
+use super::super::inode::*;
+use super::super::attr::*;
+use super::super::filesystems::*;
+use super::super::ramfs::dir::*;
+use super::super::host::fs::*;
+use super::super::host::util::*;
+
+// the shared memory segments attached by the host, each /dev mount has a quark-shm dir. The
+// dirs are kept weak so that they go away with their /dev mounts.
+pub struct QuarkShm {
+    pub segments: Vec<(String, Inode)>,
+    pub dirs: Vec<Weak<QRwLock<DirInternal>>>,
+}
+
+pub static QUARK_SHM: QMutex<QuarkShm> = QMutex::new(QuarkShm {
+    segments: Vec::new(),
+    dirs: Vec::new(),
+});
+
+// NewQuarkShmDir returns the /dev/quark-shm dir with the attached segments
+pub fn NewQuarkShmDir(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
+    let mut shm = QUARK_SHM.lock();
+    let mut contents = BTreeMap::new();
+    for (name, inode) in &shm.segments {
+        contents.insert(name.to_string(), inode.clone());
+    }
+
+    let dir = Dir::New(task, contents, &ROOT_OWNER, &FilePermissions::FromMode(FileMode(0o0555)));
+    shm.dirs.retain(|d| d.strong_count() > 0);
+    shm.dirs.push(Arc::downgrade(&dir.0));
+
+    let deviceId = DEV_DEVICE.lock().id.DeviceID();
+    let inodeId = DEV_DEVICE.lock().NextIno();
+
+    let stableAttr = StableAttr {
+        Type: InodeType::Directory,
+        DeviceId: deviceId,
+        InodeId: inodeId,
+        BlockSize: MemoryDef::PAGE_SIZE as i64,
+        DeviceFileMajor: 0,
+        DeviceFileMinor: 0,
+    };
+
+    let inodeInternal = InodeIntern {
+        UniqueId: NewUID(),
+        InodeOp: Arc::new(dir),
+        StableAttr: stableAttr,
+        LockCtx: LockCtx::default(),
+        MountSource: msrc.clone(),
+        Overlay: None,
+    };
+
+    return Inode(Arc::new(QMutex::new(inodeInternal)))
+}
+
+// AttachSegment adds the host file hostfd of the segment as /dev/quark-shm/<name>, the hostfd
+// is closed on failure
+pub fn AttachSegment(task: &Task, name: &str, hostfd: i32) -> Result<()> {
+    let mut shm = QUARK_SHM.lock();
+    if shm.segments.iter().any(|(n, _)| n == name) {
+        HostSpace::Close(hostfd);
+        return Err(Error::SysError(SysErr::EEXIST))
+    }
+
+    let mut fstat = LibcStat::default();
+    let ret = Fstat(hostfd, &mut fstat) as i32;
+    if ret < 0 {
+        HostSpace::Close(hostfd);
+        return Err(Error::SysError(-ret as i32))
+    }
+
+    let msrc = MountSource::NewHostMountSource(&"/".to_string(), &ROOT_OWNER, &WhitelistFileSystem::New(), &MountSourceFlags::default(), false);
+    let mut inode = Inode::NewHostInode(&Arc::new(QMutex::new(msrc)), hostfd, &fstat, true)?;
+    for dir in &shm.dirs {
+        if let Some(dir) = dir.upgrade() {
+            Dir(dir).AddChild(task, name, &mut inode);
+        }
+    }
+
+    shm.segments.push((name.to_string(), inode));
+    return Ok(())
+}
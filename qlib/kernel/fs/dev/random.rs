@@ -26,6 +26,7 @@ use super::super::super::super::auth::*;
 use super::super::super::super::linux_def::*;
 use super::super::super::task::*;
 use super::super::super::kernel::time::*;
+use super::super::super::kernel::deterministic::*;
 use super::super::super::kernel::waiter::*;
 use super::super::super::Kernel;
 use super::super::super::super::mem::seq::*;
@@ -265,7 +266,7 @@ impl FileOperations for RandomFileOperations {
 
     fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
         let len = IoVec::NumBytes(dsts);
-        let buf = DataBuff::New(len);
+        let buf = DataBuff::NewUserIO(len)?;
 
         let mut ioReader = RandomReader {};
         let mut reader = FromIOReader {
@@ -318,6 +319,11 @@ pub struct RandomReader {}
 
 impl IOReader for RandomReader {
     fn Read(&mut self, buf: &mut [u8]) -> Result<i64> {
+        if IsDeterministic() {
+            Task::Current().DeterministicRandom(buf);
+            return Ok(buf.len() as i64)
+        }
+
         let res = Kernel::HostSpace::GetRandom(&buf[0] as *const _ as u64, buf.len() as u64, 0);
         if res < 0 {
             return Err(Error::SysError(-res as i32))
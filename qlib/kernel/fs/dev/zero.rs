@@ -251,7 +251,7 @@ impl FileOperations for ZeroFileOperations {
 
     fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
         let size = IoVec::NumBytes(dsts);
-        let mut buf = DataBuff::New(size);
+        let mut buf = DataBuff::NewUserIO(size)?;
         buf.Zero();
 
         let done = task.CopyDataOutToIovs(&buf.buf, dsts)?;
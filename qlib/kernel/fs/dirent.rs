@@ -35,6 +35,9 @@ use super::flags::*;
 use super::file::*;
 use super::dentry::*;
 use super::mount::*;
+use super::attr::*;
+use super::inotify::*;
+use super::super::super::linux::inotify::*;
 
 pub static RENAME : Singleton<RwLock<()>> = Singleton::<RwLock<()>>::New();
 pub unsafe fn InitSingleton() {
@@ -425,10 +428,13 @@ impl Dirent {
         self.AddChild(&child);
         child.ExtendReference();
 
+        InotifyNotify(&inode, name, IN_CREATE, 0);
         return Ok(file)
     }
 
-    fn genericCreate(&self, task: &Task, root: &Dirent, name: &str, create: &mut FnMut() -> Result<()>) -> Result<()> {
+    // genericCreate runs the create and queues the inotify events of the new child to the watches
+    // of the directory
+    fn genericCreate(&self, task: &Task, root: &Dirent, name: &str, events: u32, create: &mut FnMut() -> Result<()>) -> Result<()> {
         let _a = RENAME.write();
 
         if self.exists(task, root, name) {
@@ -456,11 +462,13 @@ impl Dirent {
             (self.0).0.lock().Children.remove(name);
         }
 
-        return create()
+        create()?;
+        InotifyNotify(&inode, name, events, 0);
+        return Ok(())
     }
 
     pub fn CreateLink(&self, task: &Task, root: &Dirent, oldname: &str, newname: &str) -> Result<()> {
-        return self.genericCreate(task, root, newname, &mut || -> Result<()> {
+        return self.genericCreate(task, root, newname, IN_CREATE, &mut || -> Result<()> {
             let mut inode = self.Inode();
             return inode.CreateLink(task, self, oldname, newname)
         });
@@ -477,13 +485,30 @@ impl Dirent {
             return Err(Error::SysError(SysErr::EPERM))
         }
 
-        return self.genericCreate(task, root, name, &mut || -> Result<()> {
+        self.genericCreate(task, root, name, IN_CREATE, &mut || -> Result<()> {
             return inode.CreateHardLink(task, self, &target, name)
+        })?;
+
+        // the link count of the target changed
+        InotifyNotify(&targetInode, "", IN_ATTRIB, 0);
+        return Ok(())
+    }
+
+    // CreateDevice adds the device node made by mknod, only the tmpfs directories keep them
+    pub fn CreateDevice(&self, task: &Task, root: &Dirent, name: &str, device: &Inode) -> Result<()> {
+        let mut inode = self.Inode();
+        if inode.lock().Overlay.is_some() || inode.lock().InodeOp.InodeFileType() != InodeFileType::TmpfsDir {
+            return Err(Error::SysError(SysErr::EPERM))
+        }
+
+        let op = inode.lock().InodeOp.clone();
+        return self.genericCreate(task, root, name, IN_CREATE, &mut || -> Result<()> {
+            return op.CreateHardLink(task, &mut inode, device, name)
         });
     }
 
     pub fn CreateDirectory(&self, task: &Task, root: &Dirent, name: &str, perms: &FilePermissions) -> Result<()> {
-        return self.genericCreate(task, root, name, &mut || -> Result<()> {
+        return self.genericCreate(task, root, name, IN_CREATE | IN_ISDIR, &mut || -> Result<()> {
             let mut inode = self.Inode();
             let ret = inode.CreateDirectory(task, self, name, perms);
             return ret;
@@ -491,7 +516,7 @@ impl Dirent {
     }
 
     pub fn Bind(&self, task: &Task, root: &Dirent, name: &str, data: &BoundEndpoint, perms: &FilePermissions) -> Result<Dirent> {
-        let result = self.genericCreate(task, root, name, &mut || -> Result<()> {
+        let result = self.genericCreate(task, root, name, IN_CREATE, &mut || -> Result<()> {
             let inode = self.Inode();
             let childDir = inode.Bind(task, name, data, perms)?;
             self.AddChild(&childDir);
@@ -512,7 +537,7 @@ impl Dirent {
     }
 
     pub fn CreateFifo(&self, task: &Task, root: &Dirent, name: &str, perms: &FilePermissions) -> Result<()> {
-        return self.genericCreate(task, root, name, &mut || -> Result<()> {
+        return self.genericCreate(task, root, name, IN_CREATE, &mut || -> Result<()> {
             let mut inode = self.Inode();
             return inode.CreateFifo(task, self, name, perms)
         });
@@ -653,6 +678,11 @@ impl Dirent {
         (self.0).0.lock().Children.remove(name);
         child.DropExtendedReference();
 
+        // the link count of the child changed
+        InotifyNotify(&childInode, "", IN_ATTRIB, 0);
+        InotifyNotify(&inode, name, IN_DELETE, 0);
+        InotifyDeleted(task, &childInode);
+
         return Ok(())
     }
 
@@ -687,6 +717,9 @@ impl Dirent {
 
         child.DropExtendedReference();
 
+        InotifyNotify(&inode, name, IN_DELETE | IN_ISDIR, 0);
+        InotifyDeleted(task, &childInode);
+
         return Ok(())
     }
 
@@ -748,7 +781,7 @@ impl Dirent {
             renamedInode.CheckPermission(task, &PermMask { write: true, execute: false, read: false })?;
         }
 
-        let exist;
+        let mut exist = None;
         match newParent.walk(task, root, newName) {
             Ok(replaced) => {
                 newParent.mayDelete(task, &replaced)?;
@@ -771,18 +804,16 @@ impl Dirent {
                 replaced.DropExtendedReference();
                 replaced.flush();
 
-                exist = true;
-            }
-            Err(Error::SysError(SysErr::ENOENT)) => {
-                exist = false;
+                exist = Some(replacedInode);
             }
+            Err(Error::SysError(SysErr::ENOENT)) => (),
             Err(e) => {
                 return Err(e)
             }
         }
 
         let mut newInode = renamed.Inode();
-        newInode.Rename(task, oldParent, &renamed, newParent, newName, exist)?;
+        newInode.Rename(task, oldParent, &renamed, newParent, newName, exist.is_some())?;
         (renamed.0).0.lock().Name = newName.to_string();
 
         (newParent.0).0.lock().Children.remove(newName);
@@ -793,6 +824,7 @@ impl Dirent {
         renamed.DropExtendedReference();
         renamed.flush();
 
+        InotifyRename(task, &oldParent.Inode(), oldName, &newParent.Inode(), newName, &renamedInode, exist);
         return Ok(())
     }
 
@@ -818,7 +850,7 @@ impl Dirent {
             renamedInode.CheckPermission(task, &PermMask { write: true, execute: false, read: false })?;
         }
 
-        let exist;
+        let mut exist = None;
         match parent.walk(task, root, newName) {
             Ok(replaced) => {
                 parent.mayDelete(task, &replaced)?;
@@ -841,28 +873,29 @@ impl Dirent {
                 replaced.DropExtendedReference();
                 replaced.flush();
 
-                exist = true;
-            }
-            Err(Error::SysError(SysErr::ENOENT)) => {
-                exist = false;
+                exist = Some(replacedInode);
             }
+            Err(Error::SysError(SysErr::ENOENT)) => (),
             Err(e) => {
                 return Err(e)
             }
         }
 
         let mut newInode = renamed.Inode();
-        newInode.Rename(task, parent, &renamed, parent, newName, exist)?;
+        newInode.Rename(task, parent, &renamed, parent, newName, exist.is_some())?;
 
         (renamed.0).0.lock().Name = newName.to_string();
 
-        let mut p = (parent.0).0.lock();
-        p.Children.remove(oldName);
-        p.Children.insert(newName.to_string(), Arc::downgrade(&renamed.0));
+        {
+            let mut p = (parent.0).0.lock();
+            p.Children.remove(oldName);
+            p.Children.insert(newName.to_string(), Arc::downgrade(&renamed.0));
+        }
 
         renamed.DropExtendedReference();
         renamed.flush();
 
+        InotifyRename(task, &inode, oldName, &inode, newName, &renamedInode, exist);
         return Ok(())
     }
 
@@ -0,0 +1,132 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::qlib::mutex::*;
+
+use super::super::super::common::*;
+use super::super::super::linux_def::*;
+use super::super::super::auth::*;
+use super::super::super::device::*;
+use super::super::task::*;
+use super::super::Kernel::HostSpace;
+use super::fsutil::file::readonly_file::*;
+use super::fsutil::inode::simple_file_inode::*;
+use super::attr::*;
+use super::file::*;
+use super::flags::*;
+use super::dirent::*;
+use super::mount::*;
+use super::inode::*;
+
+// The network identity files of the sandbox. They are generated by the host from the runtime
+// configuration (the hostname and the files which kubelet/docker give for the bind mounts) and
+// read through the host on each open, so a change of the pod dns config is seen by the next open.
+pub const ETC_FILES: [&str; 3] = ["/etc/hostname", "/etc/hosts", "/etc/resolv.conf"];
+// the files larger than ETC_FILE_MAX fail to open with EFBIG
+pub const ETC_FILE_MAX: usize = 1024 * 1024;
+
+pub fn EtcFileIdx(path: &str) -> Option<usize> {
+    return ETC_FILES.iter().position(|p| *p == path)
+}
+
+pub struct EtcFileData {
+    pub idx: usize,
+}
+
+impl EtcFileData {
+    pub fn GenSnapshot(&self, _task: &Task) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(4096);
+        buf.resize(4096, 0);
+        loop {
+            let ret = HostSpace::ReadEtcFile(self.idx, &mut buf[0] as * mut _ as u64, buf.len());
+            if ret < 0 {
+                return Err(Error::SysError(-ret as i32))
+            }
+
+            let len = ret as usize;
+            if len <= buf.len() {
+                buf.truncate(len);
+                return Ok(buf)
+            }
+
+            // the file is larger than the buffer, retry with its size
+            buf.resize(len, 0);
+        }
+    }
+}
+
+impl SimpleFileTrait for EtcFileData {
+    fn GetFile(&self, task: &Task, _dir: &Inode, dirent: &Dirent, flags: FileFlags) -> Result<File> {
+        let fops = NewSnapshotReadonlyFileOperations(self.GenSnapshot(task)?);
+        let file = File::New(dirent, &flags, fops);
+        return Ok(file);
+    }
+}
+
+pub fn NewEtcFile(task: &Task, idx: usize) -> Inode {
+    let iops = SimpleFileInode::New(task,
+                                    &ROOT_OWNER,
+                                    &FilePermissions::FromMode(FileMode(0o644)),
+                                    FSMagic::ANON_INODE_FS_MAGIC,
+                                    false,
+                                    EtcFileData { idx: idx });
+
+    let deviceId = PSEUDO_DEVICE.lock().id.DeviceID();
+    let inodeId = PSEUDO_DEVICE.lock().NextIno();
+
+    let sattr = StableAttr {
+        Type: InodeType::RegularFile,
+        DeviceId: deviceId,
+        InodeId: inodeId,
+        BlockSize: 4096,
+        DeviceFileMajor: 0,
+        DeviceFileMinor: 0,
+    };
+
+    return Inode::New(&Arc::new(iops),
+                      &Arc::new(QMutex::new(MountSource::NewPseudoMountSource())),
+                      &sattr);
+}
+
+// MountEtcFiles mounts the generated files over the ones of the root fs, the files missing in
+// the root fs are skipped.
+pub fn MountEtcFiles(task: &Task, mns: &MountNs, paths: &[String]) {
+    let root = mns.Root();
+    for path in paths {
+        let idx = match EtcFileIdx(path) {
+            None => {
+                info!("ignoring unknown etc file {}", path);
+                continue;
+            }
+            Some(idx) => idx,
+        };
+
+        let mut maxTraversals = 0;
+        let dirent = match mns.FindDirent(task, &root, Some(root.clone()), path, &mut maxTraversals, true) {
+            Err(e) => {
+                info!("ignoring etc file {}, lookup fail with {:?}", path, e);
+                continue;
+            }
+            Ok(d) => d,
+        };
+
+        match mns.Mount(&dirent, &NewEtcFile(task, idx)) {
+            Err(e) => error!("mount generated {} fail with {:?}", path, e),
+            Ok(()) => info!("Mounted generated {}", path),
+        }
+    }
+}
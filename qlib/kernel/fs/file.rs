@@ -99,6 +99,73 @@ pub enum SyncType {
     SyncBackingStorage,
 }
 
+// RecvMsgResult is (receive bytes, msgFlags, (senderAddr, senderAddrLen), controlMessages)
+pub type RecvMsgResult = (i64, i32, Option<(SockAddr, usize)>, Vec<u8>);
+
+// MMsgRecv is a message of recvmmsg
+pub struct MMsgRecv {
+    pub dsts: Vec<IoVec>,
+    pub senderRequested: bool,
+    pub controlDataLen: usize,
+}
+
+// MMsgSend is a message of sendmmsg, the name and control of msgHdr point to the copies in
+// name and control
+pub struct MMsgSend {
+    pub srcs: Vec<IoVec>,
+    pub msgHdr: MsgHdr,
+    pub name: Vec<u8>,
+    pub control: Vec<u8>,
+}
+
+pub fn RecvMMsgOneByOne<T: SockOperations + ?Sized>(sock: &T, task: &Task, msgs: &mut [MMsgRecv], flags: i32, deadline: Option<Time>) -> Result<Vec<RecvMsgResult>> {
+    let waitForOne = flags & MsgType::MSG_WAITFORONE != 0;
+    let mut flags = flags & !MsgType::MSG_WAITFORONE;
+    let mut rets = Vec::with_capacity(msgs.len());
+    for msg in msgs.iter_mut() {
+        let ret = if flags & MsgType::MSG_ERRQUEUE != 0 {
+            sock.RecvErrQueue(task, &mut msg.dsts, flags, msg.senderRequested, msg.controlDataLen)
+        } else {
+            sock.RecvMsg(task, &mut msg.dsts, flags, deadline, msg.senderRequested, msg.controlDataLen)
+        };
+
+        match ret {
+            Err(e) => {
+                if rets.len() > 0 {
+                    break;
+                }
+
+                return Err(e)
+            }
+            Ok(ret) => rets.push(ret),
+        }
+
+        if waitForOne {
+            flags |= MsgType::MSG_DONTWAIT;
+        }
+    }
+
+    return Ok(rets)
+}
+
+pub fn SendMMsgOneByOne<T: SockOperations + ?Sized>(sock: &T, task: &Task, msgs: &mut [MMsgSend], flags: i32, deadline: Option<Time>) -> Result<Vec<i64>> {
+    let mut rets = Vec::with_capacity(msgs.len());
+    for msg in msgs.iter_mut() {
+        match sock.SendMsg(task, &msg.srcs, flags, &mut msg.msgHdr, deadline) {
+            Err(e) => {
+                if rets.len() > 0 {
+                    break;
+                }
+
+                return Err(e)
+            }
+            Ok(n) => rets.push(n),
+        }
+    }
+
+    return Ok(rets)
+}
+
 pub trait SockOperations: Sync + Send {
     fn Connect(&self, _task: &Task, _socketaddr: &[u8], _blocking: bool) -> Result<i64> {
         return Err(Error::SysError(SysErr::ENOTSOCK))
@@ -146,6 +213,26 @@ pub trait SockOperations: Sync + Send {
         return Err(Error::SysError(SysErr::ENOTSOCK))
     }
 
+    // RecvErrQueue is the recvmsg with MSG_ERRQUEUE, it dequeues an error of the socket error
+    // queue without blocking
+    fn RecvErrQueue(&self, _task: &Task, _dst: &mut [IoVec], _flags: i32, _senderRequested: bool, _controlDataLen: usize) -> Result<RecvMsgResult> {
+        // Pretend we have an empty error queue.
+        return Err(Error::SysError(SysErr::EAGAIN))
+    }
+
+    // RecvMMsg receives the messages in order until one fails, the error is returned only when
+    // no message is received. The messages after the first one don't block with MSG_WAITFORONE.
+    // The sockets which can get a batch of messages at once override it.
+    fn RecvMMsg(&self, task: &Task, msgs: &mut [MMsgRecv], flags: i32, deadline: Option<Time>) -> Result<Vec<RecvMsgResult>> {
+        return RecvMMsgOneByOne(self, task, msgs, flags, deadline)
+    }
+
+    // SendMMsg sends the messages in order until one fails and returns the sent bytes of each
+    // sent message, the error is returned only when no message is sent
+    fn SendMMsg(&self, task: &Task, msgs: &mut [MMsgSend], flags: i32, deadline: Option<Time>) -> Result<Vec<i64>> {
+        return SendMMsgOneByOne(self, task, msgs, flags, deadline)
+    }
+
     fn SetRecvTimeout(&self, _nanoseconds: i64) {
         return
     }
@@ -220,9 +307,14 @@ pub enum FileOpsType {
     Writer,
     SocketOperations,
     UnixSocketOperations,
+    NetlinkSocketOperations,
     ReadonlyFileOperations,
     DynamicDirFileOperations,
     SignalOperation,
+    SysctlFileOperations,
+    ResolverFileOperations,
+    CgroupFileOperations,
+    InotifyOperations,
 }
 
 pub trait FileOperations: Sync + Send + Waitable + SockOperations + SpliceOperations {
@@ -135,3 +135,37 @@ pub struct SettableFileFlags {
     pub Async: bool,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_flags() {
+        let f = FileFlags::FromFlags((Flags::O_RDWR | Flags::O_SYNC | Flags::O_APPEND) as u32);
+        assert!(f.Read && f.Write && f.Append);
+        // O_SYNC implies O_DSYNC
+        assert!(f.Sync && f.DSync);
+        assert_eq!(f.ToLinux() & Flags::O_ACCMODE, Flags::O_RDWR);
+        assert!(f.ToLinux() & Flags::O_DSYNC != 0);
+
+        let f = FileFlags::FromFlags(Flags::O_WRONLY as u32);
+        assert!(!f.Read && f.Write);
+        assert_eq!(f.ToLinux() & Flags::O_ACCMODE, Flags::O_WRONLY);
+
+        let f = FileFlags::FromFlags((Flags::O_RDONLY | Flags::O_NONBLOCK) as u32);
+        assert!(f.Read && !f.Write);
+        let s = f.SettableFileFlags();
+        assert!(s.NonBlocking && !s.Append);
+    }
+
+    #[test]
+    fn test_file_flags_from_fcntl() {
+        // the invalid access mode is neither read nor write
+        let f = FileFlags::FromFcntl(Flags::O_ACCMODE as u32);
+        assert!(!f.Read && !f.Write);
+
+        let f = FileFlags::FromFcntl((Flags::O_RDWR | Flags::O_DIRECT) as u32);
+        assert!(f.Read && f.Write && f.Direct);
+    }
+}
@@ -35,7 +35,13 @@ use super::super::super::host::hostinodeop::*;
 pub trait SimpleFileTrait : Send + Sync {
     fn GetFile(&self, _task: &Task, _dir: &Inode, _dirent: &Dirent, _flags: FileFlags) -> Result<File> {
         return Err(Error::SysError(SysErr::ENXIO))
-    }}
+    }
+
+    // Truncate is called by the open with O_TRUNC
+    fn Truncate(&self, _task: &Task, _size: i64) -> Result<()> {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+}
 
 pub struct SimpleFileNode {}
 
@@ -183,8 +189,8 @@ impl <T: 'static + SimpleFileTrait> InodeOperations for SimpleFileInode <T> {
         return Err(Error::SysError(SysErr::ENOLINK))
     }
 
-    fn Truncate(&self, _task: &Task, _dir: &mut Inode, _size: i64) -> Result<()> {
-        return Err(Error::SysError(SysErr::EINVAL))
+    fn Truncate(&self, task: &Task, _dir: &mut Inode, size: i64) -> Result<()> {
+        return self.read().data.Truncate(task, size)
     }
 
     fn IsVirtual(&self) -> bool {
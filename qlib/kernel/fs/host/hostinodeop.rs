@@ -44,6 +44,7 @@ use super::super::attr::*;
 use super::*;
 use super::util::*;
 use super::hostfileop::*;
+use super::writeback::*;
 use super::super::file::*;
 use super::super::inode::*;
 use super::super::dirent::*;
@@ -178,6 +179,7 @@ pub struct HostInodeOpIntern {
     pub mappable: Option<Mappable>,
     pub bufWriteLock: QAsyncLock,
     pub hasMappable: bool,
+    pub writeback: Arc<WritebackState>,
 }
 
 impl Default for HostInodeOpIntern {
@@ -194,6 +196,7 @@ impl Default for HostInodeOpIntern {
             size: 0,
             bufWriteLock: QAsyncLock::default(),
             hasMappable: false,
+            writeback: Arc::new(WritebackState::default()),
         }
     }
 }
@@ -215,7 +218,11 @@ impl Drop for HostInodeOpIntern {
         }
 
         RemoveFD(self.HostFd);
-        HostSpace::Close(self.HostFd);
+        if SHARESPACE.AsyncClose() {
+            HostSpace::AsyncClose(self.HostFd);
+        } else {
+            HostSpace::Close(self.HostFd);
+        }
     }
 }
 
@@ -233,6 +240,7 @@ impl HostInodeOpIntern {
             size: fstat.st_size,
             bufWriteLock: QAsyncLock::default(),
             hasMappable: false,
+            writeback: Arc::new(WritebackState::default()),
         };
 
         if ret.CanMap() {
@@ -392,6 +400,7 @@ impl HostInodeOpIntern {
             if ret < 0 {
                 return Err(Error::SysError(-ret))
             }
+            self.writeback.Dirty();
         }
 
         if mask.AccessTime || mask.ModificationTime {
@@ -428,6 +437,7 @@ impl HostInodeOpIntern {
             return Err(Error::SysError(-ret))
         }
 
+        self.writeback.Dirty();
         Ok(())
     }
 
@@ -615,33 +625,34 @@ impl HostInodeOp {
         let hostIops = self.clone();
 
         let size = IoVec::NumBytes(dsts);
-        let buf = DataBuff::New(size);
+        let inodeType = self.InodeType();
+
+        // the mmap read copies from the mapped file, it needs no buf
+        if inodeType == InodeType::RegularFile && SHARESPACE.config.read().MmapRead  {
+            let mut intern = self.lock();
+            if offset > intern.size {
+                return Ok(0)
+            }
+
+            let end = Self::ReadEndOffset(offset, size as i64, intern.size);
+            if end == offset  {
+                return Ok(0)
+            }
+
+            let srcIovs = intern.MapInternal(task, &Range::New(offset as u64, (end - offset) as u64))?;
+            let count = task.CopyIovsOutToIovs(&srcIovs, dsts)?;
+
+            return Ok(count as i64)
+        }
 
+        let buf = DataBuff::NewUserIO(size)?;
         let iovs = buf.Iovs();
-        let inodeType = self.InodeType();
 
         if inodeType != InodeType::RegularFile && inodeType != InodeType::CharacterDevice {
             let ret = IORead(hostIops.HostFd(), &iovs)?;
             task.CopyDataOutToIovs(&buf.buf[0..ret as usize], dsts)?;
             return Ok(ret as i64)
         } else {
-            if inodeType == InodeType::RegularFile && SHARESPACE.config.read().MmapRead  {
-                let mut intern = self.lock();
-                if offset > intern.size {
-                    return Ok(0)
-                }
-
-                let end = Self::ReadEndOffset(offset, size as i64, intern.size);
-                if end == offset  {
-                    return Ok(0)
-                }
-
-                let srcIovs = intern.MapInternal(task, &Range::New(offset as u64, (end - offset) as u64))?;
-                let count = task.CopyIovsOutToIovs(&srcIovs, dsts)?;
-
-                return Ok(count as i64)
-            }
-
             if SHARESPACE.config.read().UringIO {
                 if self.BufWriteEnable() {
                     // try to gain the lock once, release immediately
@@ -686,11 +697,24 @@ impl HostInodeOp {
         return self.lock().bufWriteLock.clone();
     }
 
+    pub fn Writeback(&self) -> Arc<WritebackState> {
+        return self.lock().writeback.clone();
+    }
+
+    // WaitBufWrite waits for the buffered writes issued before, the file may be mapped after them
+    // so that BufWriteEnable is false
+    pub fn WaitBufWrite(&self, task: &Task) {
+        if SHARESPACE.config.read().FileBufWrite {
+            // try to gain the lock once, release immediately
+            self.BufWriteLock().Lock(task);
+        }
+    }
+
     pub fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], offset: i64, _blocking: bool) -> Result<i64> {
         let hostIops = self.clone();
 
         let size = IoVec::NumBytes(srcs);
-        let mut buf = DataBuff::New(size);
+        let mut buf = DataBuff::NewUserIO(size)?;
         let iovs = buf.Iovs();
 
         task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
@@ -707,10 +731,11 @@ impl HostInodeOp {
             };
 
             if SHARESPACE.config.read().UringIO {
+                let bufWrite = self.BufWriteEnable();
                 let ret =
-                    if self.BufWriteEnable() {
+                    if bufWrite {
                         let lock = self.BufWriteLock().Lock(task);
-                        let count = IOURING.BufFileWrite(hostIops.HostFd(), buf, offset, lock);
+                        let count = IOURING.BufFileWrite(hostIops.HostFd(), buf, offset, lock, self.Writeback());
                         count
                     } else {
                         IOURING.Write(task,
@@ -729,6 +754,11 @@ impl HostInodeOp {
                         hostIops.UpdateMaxLen(offset + ret);
                     }
 
+                    // the buffered write marks the file dirty when it completes
+                    if !bufWrite {
+                        hostIops.Writeback().Dirty();
+                    }
+
                     return Ok(ret as i64)
                 }
 
@@ -741,6 +771,7 @@ impl HostInodeOp {
                 Err(e) => return Err(e),
                 Ok(ret) => {
                     hostIops.UpdateMaxLen(offset + ret);
+                    hostIops.Writeback().Dirty();
                     return Ok(ret)
                 }
             }
@@ -753,7 +784,7 @@ impl HostInodeOp {
         let inodeType = hostIops.InodeType();
         if inodeType == InodeType::RegularFile || inodeType == InodeType::SpecialFile {
             let size = IoVec::NumBytes(srcs);
-            let mut buf = DataBuff::New(size);
+            let mut buf = DataBuff::NewUserIO(size)?;
 
             task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
             let iovs = buf.Iovs();
@@ -766,6 +797,8 @@ impl HostInodeOp {
                 return Err(Error::SysError(-count as i32))
             }
 
+            hostIops.Writeback().Dirty();
+
             return Ok((count, len))
         } else {
             let n = self.WriteAt(task, f, srcs, 0, true)?;
@@ -781,22 +814,22 @@ impl HostInodeOp {
             false
         };
 
-        let ret = if SHARESPACE.config.read().UringIO && self.InodeType() == InodeType::RegularFile {
-            if self.BufWriteEnable() {
-                // try to gain the lock once, release immediately
-                self.BufWriteLock().Lock(task);
-            }
+        // barrier: the writes before the fsync are done on the host before it is synced
+        self.WaitBufWrite(task);
+        let writeback = self.Writeback();
+        let gen = writeback.StartSync();
 
+        // the writes through the shared mappings are not tracked
+        if datasync && !self.lock().hasMappable && writeback.Clean(gen) {
+            return Ok(())
+        }
+
+        let ret = if SHARESPACE.config.read().UringIO && self.InodeType() == InodeType::RegularFile {
             IOURING.Fsync(task,
                           fd,
                           datasync
             )
         } else {
-            if self.BufWriteEnable() {
-                // try to gain the lock once, release immediately
-                self.BufWriteLock().Lock(task);
-            }
-
             if datasync {
                 HostSpace::FDataSync(fd)
             } else {
@@ -804,6 +837,7 @@ impl HostInodeOp {
             }
         };
 
+        let ret = writeback.FinishSync(gen, ret);
         if ret < 0 {
             return Err(Error::SysError(-ret as i32))
         }
@@ -1205,6 +1239,8 @@ impl InodeOperations for HostInodeOp {
             }
         }
 
+        // the buffered writes before the truncate must not extend the file after it
+        self.WaitBufWrite(task);
         let ret = Ftruncate(self.HostFd(), size);
 
         if ret < 0 {
@@ -1212,17 +1248,20 @@ impl InodeOperations for HostInodeOp {
         }
 
         self.lock().size = size;
+        self.Writeback().Dirty();
 
         return Ok(())
     }
 
-    fn Allocate(&self, _task: &Task, _dir: &mut Inode, offset: i64, length: i64) -> Result<()> {
+    fn Allocate(&self, task: &Task, _dir: &mut Inode, offset: i64, length: i64) -> Result<()> {
+        self.WaitBufWrite(task);
         let ret = Fallocate(self.HostFd(), 0, offset, length);
 
         if ret < 0 {
             return Err(Error::SysError(-ret as i32))
         }
 
+        self.Writeback().Dirty();
         return Ok(())
     }
 
@@ -17,6 +17,7 @@ pub mod dirent;
 pub mod hostinodeop;
 pub mod hostfileop;
 pub mod tty;
+pub mod writeback;
 pub mod ioctl;
 pub mod socket_iovec;
 pub mod fs;
@@ -0,0 +1,170 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicI32;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+// WritebackState tracks the writes of a host file against the fsync barrier. The fsync holds
+// the buffered write lock first, so that all the writes before it are done on the host, then it
+// syncs the host file and reports the errors of the buffered writes which the write calls
+// couldn't return. The fdatasync of a file which is not written since the last successful sync
+// doesn't go to the host.
+#[derive(Default, Debug)]
+pub struct WritebackState {
+    // bumped when a write, truncate or allocate of the file completes on the host
+    gen: AtomicU64,
+    // the gen covered by the last successful sync
+    syncedGen: AtomicU64,
+    // errno of the first failed buffered write which is not reported by fsync yet
+    err: AtomicI32,
+}
+
+impl WritebackState {
+    pub fn Dirty(&self) {
+        self.gen.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn SetError(&self, errno: i32) {
+        self.err.compare_exchange(0, errno, Ordering::SeqCst, Ordering::SeqCst).ok();
+        self.Dirty();
+    }
+
+    // StartSync returns the gen the sync covers, it is called after the buffered writes are done
+    pub fn StartSync(&self) -> u64 {
+        return self.gen.load(Ordering::SeqCst)
+    }
+
+    // Clean returns whether all the writes up to gen are synced
+    pub fn Clean(&self, gen: u64) -> bool {
+        return self.err.load(Ordering::SeqCst) == 0 && self.syncedGen.load(Ordering::SeqCst) >= gen
+    }
+
+    // FinishSync takes the result of the host sync and returns the result of the guest fsync
+    pub fn FinishSync(&self, gen: u64, ret: i64) -> i64 {
+        if ret < 0 {
+            return ret
+        }
+
+        // the error is reported once, same as linux
+        let err = self.err.swap(0, Ordering::SeqCst);
+        if err != 0 {
+            return -err as i64
+        }
+
+        self.syncedGen.fetch_max(gen, Ordering::SeqCst);
+        return 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+
+    // SimFile is a file on a disk losing the unsynced writes on power cut
+    struct SimFile {
+        durable: Vec<u8>,
+        cache: Vec<(usize, u8)>,
+        wb: WritebackState,
+        syncs: usize,
+    }
+
+    impl SimFile {
+        fn New(size: usize) -> Self {
+            let mut durable = Vec::new();
+            durable.resize(size, 0);
+            return Self {
+                durable: durable,
+                cache: Vec::new(),
+                wb: WritebackState::default(),
+                syncs: 0,
+            }
+        }
+
+        // Write is the completion of a buffered write, errno is the host write result
+        fn Write(&mut self, offset: usize, val: u8, errno: i32) {
+            if errno != 0 {
+                self.wb.SetError(errno);
+                return
+            }
+
+            self.cache.push((offset, val));
+            self.wb.Dirty();
+        }
+
+        fn Sync(&mut self, datasync: bool) -> i64 {
+            let gen = self.wb.StartSync();
+            if datasync && self.wb.Clean(gen) {
+                return 0
+            }
+
+            self.syncs += 1;
+            for (offset, val) in self.cache.drain(..) {
+                self.durable[offset] = val;
+            }
+
+            return self.wb.FinishSync(gen, 0)
+        }
+
+        fn PowerCut(&mut self) {
+            self.cache.clear();
+        }
+    }
+
+    #[test]
+    fn test_fsync_barrier() {
+        let mut f = SimFile::New(4);
+        f.Write(0, 1, 0);
+        f.Write(1, 2, 0);
+        assert_eq!(f.Sync(false), 0);
+        f.Write(2, 3, 0);
+        f.PowerCut();
+
+        // the writes before the fsync survive, the one after it is lost
+        assert_eq!(&f.durable[..], &[1, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_write_error() {
+        let mut f = SimFile::New(4);
+        f.Write(0, 1, 5);
+        f.Write(1, 2, 0);
+
+        // the lost write fails the fsync once
+        assert_eq!(f.Sync(true), -5);
+        assert_eq!(f.Sync(true), 0);
+        f.PowerCut();
+        assert_eq!(&f.durable[..], &[0, 2, 0, 0]);
+    }
+
+    #[test]
+    fn test_fdatasync_fast_path() {
+        let mut f = SimFile::New(4);
+        f.Write(0, 1, 0);
+        assert_eq!(f.Sync(true), 0);
+        assert_eq!(f.Sync(true), 0);
+        assert_eq!(f.syncs, 1);
+
+        f.Write(1, 2, 0);
+        assert_eq!(f.Sync(true), 0);
+        assert_eq!(f.syncs, 2);
+
+        // fsync always goes to the host
+        assert_eq!(f.Sync(false), 0);
+        assert_eq!(f.syncs, 3);
+        f.PowerCut();
+        assert_eq!(&f.durable[..], &[1, 2, 0, 0]);
+    }
+}
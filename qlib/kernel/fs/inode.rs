@@ -107,7 +107,8 @@ pub enum IopsType {
     PipeIops,
     DirNode,
     SymlinkNode,
-    SimpleFileInode
+    SimpleFileInode,
+    CgroupDir,
 }
 
 pub trait InodeOperations: Sync + Send {
@@ -0,0 +1,590 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::qlib::mutex::*;
+use core::any::Any;
+use core::sync::atomic::AtomicI32;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+
+use super::super::super::common::*;
+use super::super::super::linux_def::*;
+use super::super::super::linux::inotify::*;
+use super::super::task::*;
+use super::super::kernel::waiter::*;
+
+use super::attr::*;
+use super::anon::*;
+use super::file::*;
+use super::flags::*;
+use super::inode::*;
+use super::dirent::*;
+use super::dentry::*;
+use super::host::hostinodeop::*;
+
+// the size of struct inotify_event without the name
+pub const INOTIFY_EVENT_BASE_SIZE: usize = 16;
+
+// the default of /proc/sys/fs/inotify/max_queued_events
+pub const INOTIFY_MAX_QUEUED_EVENTS: usize = 16384;
+
+// the default of /proc/sys/fs/inotify/max_user_watches
+pub const INOTIFY_MAX_WATCHES: usize = 8192;
+
+// the watches of the inodes by the unique id of the inode, the count is checked first so that
+// the file operations don't take the lock when nothing is watched
+pub static INOTIFY_WATCHES: QMutex<Option<BTreeMap<u64, Vec<Arc<Watch>>>>> = QMutex::new(None);
+pub static INOTIFY_WATCH_CNT: AtomicUsize = AtomicUsize::new(0);
+
+static INOTIFY_COOKIE: AtomicU32 = AtomicU32::new(0);
+
+// NewInotifyCookie returns the cookie which pairs the IN_MOVED_FROM and IN_MOVED_TO of a rename
+pub fn NewInotifyCookie() -> u32 {
+    return INOTIFY_COOKIE.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+// InotifyNotify queues the events to the watches of the inode, name is the name of the child
+// when the inode is the parent directory of the subject
+pub fn InotifyNotify(inode: &Inode, name: &str, events: u32, cookie: u32) {
+    if INOTIFY_WATCH_CNT.load(Ordering::Acquire) == 0 {
+        return
+    }
+
+    let watches = match INOTIFY_WATCHES.lock().as_ref().and_then(|m| m.get(&inode.ID())) {
+        None => return,
+        Some(w) => w.clone(),
+    };
+
+    for w in &watches {
+        w.Notify(name, events, cookie);
+    }
+}
+
+// InotifyUnlinked removes the watches of the inode which has no link left, IN_DELETE_SELF is
+// queued before IN_IGNORED
+pub fn InotifyUnlinked(inode: &Inode) {
+    if INOTIFY_WATCH_CNT.load(Ordering::Acquire) == 0 {
+        return
+    }
+
+    let watches = match INOTIFY_WATCHES.lock().as_mut().and_then(|m| m.remove(&inode.ID())) {
+        None => return,
+        Some(w) => w,
+    };
+
+    INOTIFY_WATCH_CNT.fetch_sub(watches.len(), Ordering::AcqRel);
+    for w in &watches {
+        w.Notify("", IN_DELETE_SELF, 0);
+        if let Some(owner) = w.owner.upgrade() {
+            owner.watches.lock().remove(&w.wd);
+            owner.Queue(InotifyEvent::New(w.wd, IN_IGNORED, 0, ""));
+        }
+    }
+}
+
+// InotifyDeleted removes the watches of the inode which lost a link if it has no link left
+pub fn InotifyDeleted(task: &Task, inode: &Inode) {
+    if INOTIFY_WATCH_CNT.load(Ordering::Acquire) == 0 {
+        return
+    }
+
+    if !inode.StableAttr().IsDir() {
+        match inode.UnstableAttr(task) {
+            Ok(attr) if attr.Links > 0 => return,
+            _ => (),
+        }
+    }
+
+    InotifyUnlinked(inode);
+}
+
+// InotifyRename queues the events of the rename, the IN_MOVED_FROM and IN_MOVED_TO are paired by
+// the cookie and the replaced inode is deleted
+pub fn InotifyRename(task: &Task, oldParent: &Inode, oldName: &str, newParent: &Inode, newName: &str,
+                     renamed: &Inode, replaced: Option<Inode>) {
+    if INOTIFY_WATCH_CNT.load(Ordering::Acquire) == 0 {
+        return
+    }
+
+    let isDir = if renamed.StableAttr().IsDir() {
+        IN_ISDIR
+    } else {
+        0
+    };
+
+    let cookie = NewInotifyCookie();
+    InotifyNotify(oldParent, oldName, IN_MOVED_FROM | isDir, cookie);
+    InotifyNotify(newParent, newName, IN_MOVED_TO | isDir, cookie);
+    InotifyNotify(renamed, "", IN_MOVE_SELF | isDir, 0);
+
+    if let Some(r) = replaced {
+        InotifyDeleted(task, &r);
+    }
+}
+
+impl Dirent {
+    // InotifyEvent queues the events of the dirent to the watches of its parent and of itself,
+    // the parent is notified first like linux
+    pub fn InotifyEvent(&self, events: u32, cookie: u32) {
+        if INOTIFY_WATCH_CNT.load(Ordering::Acquire) == 0 {
+            return
+        }
+
+        let inode = self.Inode();
+        let mut events = events;
+        if inode.StableAttr().IsDir() {
+            events |= IN_ISDIR;
+        }
+
+        let (parent, name) = {
+            let d = (self.0).0.lock();
+            (d.Parent.clone(), d.Name.to_string())
+        };
+
+        if let Some(p) = parent {
+            InotifyNotify(&p.Inode(), &name, events, cookie);
+        }
+
+        InotifyNotify(&inode, "", events, cookie);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InotifyEvent {
+    pub wd: i32,
+    pub mask: u32,
+    pub cookie: u32,
+    pub name: String,
+}
+
+impl InotifyEvent {
+    pub fn New(wd: i32, mask: u32, cookie: u32, name: &str) -> Self {
+        return Self {
+            wd: wd,
+            mask: mask,
+            cookie: cookie,
+            name: name.to_string(),
+        }
+    }
+
+    // NameLen returns the len field of struct inotify_event, the name is nul terminated and
+    // padded to the multiple of the base size
+    pub fn NameLen(&self) -> usize {
+        if self.name.len() == 0 {
+            return 0
+        }
+
+        let len = self.name.len() + 1;
+        return (len + INOTIFY_EVENT_BASE_SIZE - 1) / INOTIFY_EVENT_BASE_SIZE * INOTIFY_EVENT_BASE_SIZE
+    }
+
+    pub fn Size(&self) -> usize {
+        return INOTIFY_EVENT_BASE_SIZE + self.NameLen()
+    }
+
+    pub fn Serialize(&self, buf: &mut Vec<u8>) {
+        let end = buf.len() + self.Size();
+        buf.extend_from_slice(&self.wd.to_ne_bytes());
+        buf.extend_from_slice(&self.mask.to_ne_bytes());
+        buf.extend_from_slice(&self.cookie.to_ne_bytes());
+        buf.extend_from_slice(&(self.NameLen() as u32).to_ne_bytes());
+        buf.extend_from_slice(self.name.as_bytes());
+        buf.resize(end, 0);
+    }
+}
+
+// InotifyEvents is the event queue of an inotify instance
+#[derive(Debug, Default)]
+pub struct InotifyEvents {
+    pub events: VecDeque<InotifyEvent>,
+    // the bytes of the queued events
+    pub size: usize,
+}
+
+impl InotifyEvents {
+    // Push queues the event, it returns false when the event is dropped. "If successive output
+    // inotify events produced on the inotify file descriptor are identical (same wd, mask,
+    // cookie, and name), then they are coalesced into a single event" and a full queue gets one
+    // IN_Q_OVERFLOW event - inotify(7)
+    pub fn Push(&mut self, event: InotifyEvent, max: usize) -> bool {
+        let mut event = event;
+        if let Some(last) = self.events.back() {
+            if *last == event {
+                return false
+            }
+
+            if self.events.len() >= max {
+                if last.mask == IN_Q_OVERFLOW {
+                    return false
+                }
+
+                event = InotifyEvent::New(-1, IN_Q_OVERFLOW, 0, "");
+            }
+        }
+
+        self.size += event.Size();
+        self.events.push_back(event);
+        return true
+    }
+
+    // Take serializes the events which fit in the buffer of the size, the buffer has to hold the
+    // first event at least
+    pub fn Take(&mut self, size: usize) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        while let Some(event) = self.events.front() {
+            if buf.len() + event.Size() > size {
+                if buf.len() == 0 {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                break;
+            }
+
+            event.Serialize(&mut buf);
+            self.size -= event.Size();
+            self.events.pop_front();
+        }
+
+        if buf.len() == 0 {
+            return Err(Error::SysError(SysErr::EAGAIN))
+        }
+
+        return Ok(buf)
+    }
+}
+
+// Watch is the watch of an inotify instance on an inode
+pub struct Watch {
+    pub owner: Weak<InotifyInternal>,
+    pub wd: i32,
+    // the unique id of the watched inode
+    pub target: u64,
+    pub mask: AtomicU32,
+}
+
+impl Watch {
+    pub fn Notify(&self, name: &str, events: u32, cookie: u32) {
+        let mask = self.mask.load(Ordering::Relaxed);
+        let matched = mask & events & IN_ALL_EVENTS;
+        if matched == 0 {
+            return
+        }
+
+        let owner = match self.owner.upgrade() {
+            None => return,
+            Some(o) => o,
+        };
+
+        owner.Queue(InotifyEvent::New(self.wd, matched | (events & IN_ISDIR), cookie, name));
+        if mask & IN_ONESHOT != 0 {
+            owner.RemoveWatch(self.wd).ok();
+        }
+    }
+}
+
+pub struct InotifyInternal {
+    pub queue: Queue,
+    pub events: QMutex<InotifyEvents>,
+    // the watches of the instance by the watch descriptor
+    pub watches: QMutex<BTreeMap<i32, Arc<Watch>>>,
+    pub nextWd: AtomicI32,
+}
+
+impl Drop for InotifyInternal {
+    fn drop(&mut self) {
+        let watches: Vec<Arc<Watch>> = self.watches.lock().values().cloned().collect();
+        for w in &watches {
+            UnregisterWatch(w);
+        }
+    }
+}
+
+fn UnregisterWatch(w: &Arc<Watch>) -> bool {
+    let mut all = INOTIFY_WATCHES.lock();
+    let all = match all.as_mut() {
+        None => return false,
+        Some(m) => m,
+    };
+
+    let list = match all.get_mut(&w.target) {
+        None => return false,
+        Some(l) => l,
+    };
+
+    let cnt = list.len();
+    list.retain(|x| !Arc::ptr_eq(x, w));
+    let removed = list.len() != cnt;
+    if list.len() == 0 {
+        all.remove(&w.target);
+    }
+
+    if removed {
+        INOTIFY_WATCH_CNT.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    return removed
+}
+
+impl InotifyInternal {
+    pub fn Queue(&self, event: InotifyEvent) {
+        if self.events.lock().Push(event, INOTIFY_MAX_QUEUED_EVENTS) {
+            self.queue.Notify(EVENT_IN);
+        }
+    }
+
+    pub fn RemoveWatch(&self, wd: i32) -> Result<()> {
+        let w = match self.watches.lock().remove(&wd) {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(w) => w,
+        };
+
+        UnregisterWatch(&w);
+        self.Queue(InotifyEvent::New(wd, IN_IGNORED, 0, ""));
+        return Ok(())
+    }
+}
+
+pub fn NewInotify(task: &Task) -> File {
+    let inode = NewAnonInode(task);
+    let dirent = Dirent::New(&inode, "anon_inode:[inotify]");
+
+    let internal = InotifyInternal {
+        queue: Queue::default(),
+        events: QMutex::new(InotifyEvents::default()),
+        watches: QMutex::new(BTreeMap::new()),
+        nextWd: AtomicI32::new(1),
+    };
+
+    return File::New(&dirent, &FileFlags {
+        Read: true,
+        ..Default::default()
+    }, InotifyOperations(Arc::new(internal)));
+}
+
+#[derive(Clone)]
+pub struct InotifyOperations(pub Arc<InotifyInternal>);
+
+impl InotifyOperations {
+    // AddWatch watches the inode, the mask of the existing watch of the instance on the inode
+    // is replaced or, with IN_MASK_ADD, extended
+    pub fn AddWatch(&self, inode: &Inode, mask: u32) -> Result<i32> {
+        let target = inode.ID();
+        let mut watches = self.0.watches.lock();
+        for w in watches.values() {
+            if w.target == target {
+                if mask & IN_MASK_ADD != 0 {
+                    w.mask.fetch_or(mask & !IN_MASK_ADD, Ordering::Relaxed);
+                } else {
+                    w.mask.store(mask, Ordering::Relaxed);
+                }
+
+                return Ok(w.wd)
+            }
+        }
+
+        if INOTIFY_WATCH_CNT.load(Ordering::Acquire) >= INOTIFY_MAX_WATCHES {
+            return Err(Error::SysError(SysErr::ENOSPC))
+        }
+
+        let wd = self.0.nextWd.fetch_add(1, Ordering::Relaxed);
+        let w = Arc::new(Watch {
+            owner: Arc::downgrade(&self.0),
+            wd: wd,
+            target: target,
+            mask: AtomicU32::new(mask & !IN_MASK_ADD),
+        });
+
+        watches.insert(wd, w.clone());
+        INOTIFY_WATCHES.lock().get_or_insert_with(BTreeMap::new).entry(target).or_insert(Vec::new()).push(w);
+        INOTIFY_WATCH_CNT.fetch_add(1, Ordering::AcqRel);
+        return Ok(wd)
+    }
+
+    pub fn RemoveWatch(&self, wd: i32) -> Result<()> {
+        return self.0.RemoveWatch(wd)
+    }
+}
+
+impl Waitable for InotifyOperations {
+    fn Readiness(&self, _task: &Task, mask: EventMask) -> EventMask {
+        if self.0.events.lock().events.len() > 0 {
+            return mask & EVENT_IN
+        }
+
+        return 0
+    }
+
+    fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
+        self.0.queue.EventRegister(task, e, mask)
+    }
+
+    fn EventUnregister(&self, task: &Task, e: &WaitEntry) {
+        self.0.queue.EventUnregister(task, e)
+    }
+}
+
+impl SpliceOperations for InotifyOperations {}
+
+impl FileOperations for InotifyOperations {
+    fn as_any(&self) -> &Any {
+        return self
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::InotifyOperations
+    }
+
+    fn Seekable(&self) -> bool {
+        return false;
+    }
+
+    fn Seek(&self, _task: &Task, _f: &File, _whence: i32, _current: i64, _offset: i64) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ESPIPE))
+    }
+
+    fn ReadDir(&self, _task: &Task, _f: &File, _offset: i64, _serializer: &mut DentrySerializer) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+        let size = IoVec::NumBytes(dsts);
+        if size < INOTIFY_EVENT_BASE_SIZE {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let buf = self.0.events.lock().Take(size)?;
+        task.CopyDataOutToIovs(&buf, dsts)?;
+        return Ok(buf.len() as i64)
+    }
+
+    fn WriteAt(&self, _task: &Task, _f: &File, _srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+        return Err(Error::SysError(SysErr::EBADF))
+    }
+
+    fn Append(&self, _task: &Task, _f: &File, _srcs: &[IoVec]) -> Result<(i64, i64)> {
+        return Err(Error::SysError(SysErr::EBADF))
+    }
+
+    fn Fsync(&self, _task: &Task, _f: &File, _start: i64, _end: i64, _syncType: SyncType) -> Result<()> {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(())
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        let inode = f.Dirent.Inode();
+        return inode.UnstableAttr(task);
+    }
+
+    fn Ioctl(&self, task: &Task, _f: &File, _fd: i32, request: u64, val: u64) -> Result<()> {
+        if request == IoCtlCmd::FIONREAD {
+            let v = self.0.events.lock().size as i32;
+            task.CopyOutObj(&v, val)?;
+            return Ok(())
+        }
+
+        return Err(Error::SysError(SysErr::ENOTTY))
+    }
+
+    fn IterateDir(&self, _task: &Task, _d: &Dirent, _dirCtx: &mut DirCtx, _offset: i32) -> (i32, Result<i64>) {
+        return (0, Err(Error::SysError(SysErr::ENOTDIR)))
+    }
+
+    fn Mappable(&self) -> Result<HostInodeOp> {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+}
+
+impl SockOperations for InotifyOperations {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inotify_event_serialize() {
+        let e = InotifyEvent::New(3, IN_MOVED_TO, 7, "file");
+        assert_eq!(e.NameLen(), 16);
+        assert_eq!(e.Size(), 32);
+
+        let mut buf = Vec::new();
+        e.Serialize(&mut buf);
+        assert_eq!(buf.len(), 32);
+        assert_eq!(&buf[0..4], &3i32.to_ne_bytes());
+        assert_eq!(&buf[4..8], &IN_MOVED_TO.to_ne_bytes());
+        assert_eq!(&buf[8..12], &7u32.to_ne_bytes());
+        assert_eq!(&buf[12..16], &16u32.to_ne_bytes());
+        assert_eq!(&buf[16..20], b"file");
+        assert!(buf[20..].iter().all(|b| *b == 0));
+
+        // the name of 15 bytes and its nul fill the 16 bytes, the one of 16 bytes takes 32
+        assert_eq!(InotifyEvent::New(1, IN_CREATE, 0, "0123456789abcde").NameLen(), 16);
+        assert_eq!(InotifyEvent::New(1, IN_CREATE, 0, "0123456789abcdef").NameLen(), 32);
+        assert_eq!(InotifyEvent::New(1, IN_DELETE_SELF, 0, "").Size(), INOTIFY_EVENT_BASE_SIZE);
+    }
+
+    #[test]
+    fn test_inotify_events_coalesce_and_overflow() {
+        let mut q = InotifyEvents::default();
+        assert!(q.Push(InotifyEvent::New(1, IN_MODIFY, 0, "a"), 3));
+        assert!(!q.Push(InotifyEvent::New(1, IN_MODIFY, 0, "a"), 3));
+        assert!(q.Push(InotifyEvent::New(1, IN_MODIFY, 0, "b"), 3));
+        assert!(q.Push(InotifyEvent::New(1, IN_MODIFY, 0, "a"), 3));
+        assert_eq!(q.events.len(), 3);
+
+        // the full queue takes one overflow event and drops the others
+        assert!(q.Push(InotifyEvent::New(1, IN_CREATE, 0, "c"), 3));
+        assert!(!q.Push(InotifyEvent::New(1, IN_DELETE, 0, "c"), 3));
+        assert_eq!(q.events.len(), 4);
+        assert_eq!(q.events.back().unwrap(), &InotifyEvent::New(-1, IN_Q_OVERFLOW, 0, ""));
+        assert_eq!(q.size, 3 * 32 + 16);
+    }
+
+    #[test]
+    fn test_inotify_events_take() {
+        let mut q = InotifyEvents::default();
+        assert_eq!(q.Take(4096), Err(Error::SysError(SysErr::EAGAIN)));
+
+        let from = InotifyEvent::New(1, IN_MOVED_FROM, 9, "old");
+        let to = InotifyEvent::New(1, IN_MOVED_TO, 9, "new");
+        q.Push(from.clone(), INOTIFY_MAX_QUEUED_EVENTS);
+        q.Push(to.clone(), INOTIFY_MAX_QUEUED_EVENTS);
+
+        // the buffer smaller than the first event fails, a partial event is never returned
+        assert_eq!(q.Take(31), Err(Error::SysError(SysErr::EINVAL)));
+
+        let buf = q.Take(48).unwrap();
+        let mut expect = Vec::new();
+        from.Serialize(&mut expect);
+        assert_eq!(buf, expect);
+        assert_eq!(q.size, 32);
+
+        let buf = q.Take(4096).unwrap();
+        let mut expect = Vec::new();
+        to.Serialize(&mut expect);
+        assert_eq!(buf, expect);
+        assert_eq!(q.size, 0);
+        assert!(q.events.is_empty());
+    }
+}
@@ -494,4 +494,55 @@ pub fn ComputeRange(start: i64, length: i64, offset: i64) -> Result<Range> {
     };
 
     return Ok(Range::New(offset as u64, len))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region_locks() {
+        let mut l = LocksInternal::default();
+
+        // the readers share the region, the writer conflicts with them
+        assert!(l.Lock(1, LockType::ReadLock, &Range::New(0, 100)));
+        assert!(l.Lock(2, LockType::ReadLock, &Range::New(50, 100)));
+        assert!(!l.Lock(3, LockType::WriteLock, &Range::New(90, 20)));
+        assert!(l.Lock(3, LockType::WriteLock, &Range::New(150, 10)));
+
+        // the read lock is upgraded only where the uid is the only reader
+        assert!(!l.Lock(1, LockType::WriteLock, &Range::New(0, 60)));
+        assert!(l.Lock(1, LockType::WriteLock, &Range::New(0, 50)));
+        assert!(!l.CanLock(2, LockType::ReadLock, &Range::New(0, 10)));
+        assert!(l.CanLock(1, LockType::ReadLock, &Range::New(0, 10)));
+
+        // the unlock of a reader keeps the other readers of the region
+        l.Unlock(2, &Range::New(50, 100));
+        assert!(l.Lock(3, LockType::WriteLock, &Range::New(100, 50)));
+        assert!(!l.CanLock(3, LockType::WriteLock, &Range::New(60, 10)));
+
+        l.Unlock(1, &Range::New(0, 100));
+        assert!(l.CanLock(3, LockType::WriteLock, &Range::New(0, 100)));
+        assert!(!l.CanLock(4, LockType::ReadLock, &Range::New(100, 1)));
+
+        // the empty region is always locked
+        assert!(l.Lock(4, LockType::WriteLock, &Range::New(120, 0)));
+    }
+
+    #[test]
+    fn test_compute_range() {
+        let r = ComputeRange(10, 20, 100).unwrap();
+        assert_eq!((r.Start(), r.Len()), (110, 20));
+
+        // the negative length locks the bytes before the start
+        let r = ComputeRange(0, -10, 100).unwrap();
+        assert_eq!((r.Start(), r.Len()), (90, 10));
+
+        // zero length locks to the end of the file
+        let r = ComputeRange(0, 0, 100).unwrap();
+        assert_eq!((r.Start(), r.Len()), (100, MAX_RANGE));
+
+        assert!(ComputeRange(-200, 10, 100).is_err());
+        assert!(ComputeRange(0, -200, 100).is_err());
+    }
+}
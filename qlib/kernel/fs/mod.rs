@@ -36,7 +36,15 @@ pub mod procfs;
 pub mod sys;
 pub mod anon;
 pub mod timerfd;
+pub mod inotify;
 pub mod tmpfs;
+pub mod cgroupfs;
+pub mod etc;
+
+use alloc::sync::Arc;
+use crate::qlib::mutex::*;
+
+use self::filesystems::*;
 
 pub fn Init() {
     self::tty::Init();
@@ -44,4 +52,6 @@ pub fn Init() {
     self::procfs::Init();
     self::sys::Init();
     self::tmpfs::Init();
+    self::cgroupfs::Init();
+    RegisterFilesystem(&Arc::new(QMutex::new(self::mount_overlay::OverLayFileSystem {})));
 }
\ No newline at end of file
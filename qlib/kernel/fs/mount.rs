@@ -201,7 +201,8 @@ impl MountNs {
         };
 
         let prev = match &orig.lock().prev {
-            None => panic!("cannot unmount initial dirent"),
+            // the initial mount of the mount point can't be unmounted
+            None => return Err(Error::SysError(SysErr::EINVAL)),
             Some(prev) => prev.clone(),
         };
 
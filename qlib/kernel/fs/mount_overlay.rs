@@ -15,15 +15,19 @@
 use alloc::string::String;
 use alloc::string::ToString;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use crate::qlib::mutex::*;
 use core::any::Any;
 
 use super::super::super::common::*;
+use super::super::super::linux_def::*;
 use super::super::task::*;
 use super::mount::*;
 use super::inode::*;
 use super::dirent::*;
 use super::filesystems::*;
+use super::overlay::*;
+use super::host::fs::*;
 
 pub struct OverlayMountSourceOperations {
     pub upper: Arc<QMutex<MountSource>>,
@@ -118,23 +122,81 @@ impl MountSourceOperations for OverlayMountSourceOperations {
     }
 }
 
+// the directory of the lowerdir and upperdir mount options
+fn overlayDir(task: &Task, path: &str) -> Result<Inode> {
+    let root = task.fsContext.RootDirectory();
+    let cwd = task.fsContext.WorkDirectory();
+    let mut remainingTraversals = MAX_SYMLINK_TRAVERSALS;
+    let d = task.mountNS.FindDirent(task, &root, Some(cwd), path, &mut remainingTraversals, true)?;
+    let inode = d.Inode();
+    if !inode.StableAttr().IsDir() {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    return Ok(inode)
+}
+
 pub struct OverLayFileSystem {}
 
 impl Filesystem for OverLayFileSystem {
     fn Name(&self) -> String {
-        return "overlayfs".to_string();
+        return "overlay".to_string();
     }
 
     fn Flags(&self) -> FilesystemFlags {
         return 0;
     }
 
-    fn Mount(&mut self, _task: &Task, _device: &str, _flags: &MountSourceFlags, _date: &str) -> Result<Inode> {
-        panic!("overlayFilesystem.Mount should not be called!");
+    // Mount stacks the lowerdir layers, the first one on top, and puts the upperdir over them.
+    // The upperdir can't be in another overlay and the whiteouts are the trusted.overlay.whiteout
+    // xattrs, the workdir is not used. Without upperdir the overlay is read only.
+    fn Mount(&mut self, task: &Task, _device: &str, flags: &MountSourceFlags, data: &str) -> Result<Inode> {
+        info!("overlay file system mount ...");
+
+        let mut options = WhitelistFileSystem::GenericMountSourceOptions(data);
+        let lowerdir = match options.remove("lowerdir") {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(l) => l,
+        };
+
+        let upperdir = options.remove("upperdir");
+        options.remove("workdir");
+        if options.len() > 0 {
+            info!("ignoring the overlay mount options: {:?}", options);
+        }
+
+        let mut layers = Vec::new();
+        for path in lowerdir.split(':') {
+            layers.push(overlayDir(task, path)?);
+        }
+
+        let lowerFlags = MountSourceFlags {
+            ReadOnly: true,
+            ..*flags
+        };
+
+        let mut lower = layers.pop().unwrap();
+        while let Some(layer) = layers.pop() {
+            lower = NewOverlayRoot(task, &layer, &lower, &lowerFlags)?;
+        }
+
+        match upperdir {
+            None => {
+                if lower.lock().Overlay.is_none() {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                return Ok(lower)
+            }
+            Some(upperdir) => {
+                let upper = overlayDir(task, &upperdir)?;
+                return NewOverlayRoot(task, &upper, &lower, flags)
+            }
+        }
     }
 
     fn AllowUserMount(&self) -> bool {
-        return false;
+        return true;
     }
 
     fn AllowUserList(&self) -> bool {
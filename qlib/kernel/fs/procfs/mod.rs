@@ -28,6 +28,9 @@ pub mod mounts;
 pub mod stat;
 pub mod sys;
 pub mod meminfo;
+pub mod sandbox_identity;
+pub mod resolver;
+pub mod net;
 
 use alloc::sync::Arc;
 use crate::qlib::mutex::*;
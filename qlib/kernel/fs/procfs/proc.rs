@@ -51,8 +51,11 @@ use super::uptime::*;
 use super::cpuinfo::*;
 use super::filesystems::*;
 use super::loadavg::*;
+use super::sandbox_identity::*;
+use super::resolver::*;
 use super::mounts::*;
 use super::stat::*;
+use super::net::*;
 
 pub struct ProcNodeInternal {
     pub kernel: Kernel,
@@ -119,6 +122,7 @@ pub fn NewProc(task: &Task, msrc: &Arc<QMutex<MountSource>>, cgroupControllers:
     contents.insert("filesystems".to_string(), NewFileSystem(task, msrc));
     contents.insert("loadavg".to_string(), NewLoadAvg(task, msrc));
     contents.insert("mounts".to_string(), NewMounts(task, msrc));
+    contents.insert("net".to_string(), NewNet(task, msrc));
     contents.insert("self".to_string(), NewProcessSelf(task, &pidns, msrc));
     contents.insert("stat".to_string(), NewStatData(task, msrc));
     contents.insert("thread-self".to_string(), NewThreadSelf(task, &pidns, msrc));
@@ -128,6 +132,14 @@ pub fn NewProc(task: &Task, msrc: &Arc<QMutex<MountSource>>, cgroupControllers:
         contents.insert("meminfo".to_string(), NewMeminfo(task, msrc));
     }
 
+    if SHARESPACE.config.read().SandboxIdentity {
+        contents.insert("sandbox_identity".to_string(), NewSandboxIdentity(task, msrc));
+    }
+
+    if SHARESPACE.config.read().HostResolver {
+        contents.insert("quark_resolver".to_string(), NewResolver(task, msrc));
+    }
+
     contents.insert("sys".to_string(), NewSys(task, msrc));
 
     let iops = Dir::New(task, contents, &ROOT_OWNER, &FilePermissions::FromMode(FileMode(0o0555)));
@@ -0,0 +1,270 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// /proc/quark_resolver looks up the host names with the resolver of qvisor, for the nss module
+// in nss/ which replaces the dns lookup of glibc. The app writes "<family> <name>", the family
+// is inet, inet6 or any, and reads the addresses of the name on the same fd, one "<family>
+// <addr>" line each. The write fails with ENOENT when the name doesn't exist and EAGAIN when
+// the dns servers don't answer.
+
+use core::any::Any;
+use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::qlib::mutex::*;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::auth::*;
+use super::super::super::super::qmsg::qcall::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::task::*;
+use super::super::super::Kernel::HostSpace;
+use super::super::fsutil::inode::simple_file_inode::*;
+use super::super::host::hostinodeop::*;
+use super::super::attr::*;
+use super::super::file::*;
+use super::super::flags::*;
+use super::super::dentry::*;
+use super::super::dirent::*;
+use super::super::mount::*;
+use super::super::inode::*;
+use super::inode::*;
+
+// the max size of the lookup request, the family and a host name of 253 bytes
+pub const RESOLVER_WRITE_MAX: usize = 512;
+
+// the max addresses returned for a name
+pub const RESOLVER_MAX_ADDRS: usize = 64;
+
+// ParseResolverRequest returns the address family and the name of the request
+pub fn ParseResolverRequest(req: &str) -> Result<(i32, &str)> {
+    let mut fields = req.split_whitespace();
+    let family = match fields.next() {
+        Some("inet") => AFType::AF_INET,
+        Some("inet6") => AFType::AF_INET6,
+        Some("any") => AFType::AF_UNSPEC,
+        _ => return Err(Error::SysError(SysErr::EINVAL)),
+    };
+
+    let name = match fields.next() {
+        Some(n) if n.len() <= 253 => n,
+        _ => return Err(Error::SysError(SysErr::EINVAL)),
+    };
+
+    if fields.next().is_some() {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    return Ok((family as i32, name))
+}
+
+// FormatResolvedAddrs prints the addresses, the ipv6 ones without the zero compression which
+// inet_pton still accepts
+pub fn FormatResolvedAddrs(addrs: &[ResolvedAddr]) -> String {
+    let mut buf = String::new();
+    for addr in addrs {
+        if addr.family == AFType::AF_INET as u32 {
+            let a = &addr.addr;
+            buf += &format!("inet {}.{}.{}.{}\n", a[0], a[1], a[2], a[3]);
+        } else {
+            let groups: Vec<String> = addr.addr.chunks(2)
+                .map(|g| format!("{:x}", (g[0] as u16) << 8 | g[1] as u16))
+                .collect();
+            buf += &format!("inet6 {}\n", groups.join(":"));
+        }
+    }
+
+    return buf
+}
+
+pub fn NewResolver(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
+    let v = SimpleFileInode::New(task,
+                                 &ROOT_OWNER,
+                                 &FilePermissions::FromMode(FileMode(0o666)),
+                                 FSMagic::PROC_SUPER_MAGIC,
+                                 false,
+                                 ResolverFileTrait {});
+    return NewProcInode(&Arc::new(v), msrc, InodeType::SpecialFile, None)
+}
+
+pub struct ResolverFileTrait {}
+
+impl SimpleFileTrait for ResolverFileTrait {
+    fn GetFile(&self, _task: &Task, _dir: &Inode, dirent: &Dirent, flags: FileFlags) -> Result<File> {
+        let fops = ResolverFileOperations {
+            result: QMutex::new(Vec::new()),
+        };
+
+        let file = File::New(dirent, &flags, fops);
+        return Ok(file);
+    }
+}
+
+pub struct ResolverFileOperations {
+    // the addresses of the last lookup on the fd
+    pub result: QMutex<Vec<u8>>,
+}
+
+impl ResolverFileOperations {
+    pub fn Resolve(&self, req: &str) -> Result<()> {
+        let (family, name) = ParseResolverRequest(req)?;
+        let mut addrs = Vec::with_capacity(RESOLVER_MAX_ADDRS);
+        addrs.resize(RESOLVER_MAX_ADDRS, ResolvedAddr::default());
+        let ret = HostSpace::ResolveHost(name,
+                                         family,
+                                         &mut addrs[0] as *mut _ as u64,
+                                         addrs.len() * core::mem::size_of::<ResolvedAddr>());
+        if ret < 0 {
+            return Err(Error::SysError(-ret as i32))
+        }
+
+        addrs.truncate(ret as usize);
+        *self.result.lock() = FormatResolvedAddrs(&addrs).into_bytes();
+        return Ok(())
+    }
+}
+
+impl Waitable for ResolverFileOperations {
+    fn Readiness(&self, _task: &Task,mask: EventMask) -> EventMask {
+        return mask
+    }
+
+    fn EventRegister(&self, _task: &Task,_e: &WaitEntry, _mask: EventMask) {
+    }
+
+    fn EventUnregister(&self, _task: &Task,_e: &WaitEntry) {
+    }
+}
+
+impl SpliceOperations for ResolverFileOperations {}
+
+impl FileOperations for ResolverFileOperations {
+    fn as_any(&self) -> &Any {
+        return self
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::ResolverFileOperations
+    }
+
+    fn Seekable(&self) -> bool {
+        return true;
+    }
+
+    // the offset is in the result of the last lookup, SEEK_END is the end of it
+    fn Seek(&self, _task: &Task, _f: &File, whence: i32, current: i64, offset: i64) -> Result<i64> {
+        let base = match whence {
+            SeekWhence::SEEK_SET => 0,
+            SeekWhence::SEEK_CUR => current,
+            SeekWhence::SEEK_END => self.result.lock().len() as i64,
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        };
+
+        match base.checked_add(offset) {
+            Some(off) if off >= 0 => return Ok(off),
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        }
+    }
+
+    fn ReadDir(&self, _task: &Task, _f: &File, _offset: i64, _serializer: &mut DentrySerializer) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], offset: i64, _blocking: bool) -> Result<i64> {
+        if offset < 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let result = self.result.lock();
+        if offset as usize > result.len() {
+            return Ok(0)
+        }
+
+        let n = task.CopyDataOutToIovs(&result[offset as usize ..], dsts)?;
+        return Ok(n as i64)
+    }
+
+    fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+        // each write is a whole request, the offset is ignored as the result is read from 0
+        let size = IoVec::NumBytes(srcs);
+        if size > RESOLVER_WRITE_MAX {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mut buf: Vec<u8> = Vec::with_capacity(size);
+        buf.resize(size, 0);
+        let n = task.CopyDataInFromIovs(&mut buf, srcs)?;
+        let req = match core::str::from_utf8(&buf[..n]) {
+            Err(_) => return Err(Error::SysError(SysErr::EINVAL)),
+            Ok(v) => v,
+        };
+
+        self.Resolve(req)?;
+        return Ok(n as i64)
+    }
+
+    fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
+        let n = self.WriteAt(task, f, srcs, 0, false)?;
+        return Ok((n, 0))
+    }
+
+    fn Fsync(&self, _task: &Task, _f: &File, _start: i64, _end: i64, _syncType: SyncType) -> Result<()> {
+        return Ok(())
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(())
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        let inode = f.Dirent.Inode();
+        return inode.UnstableAttr(task);
+    }
+
+    fn Ioctl(&self, _task: &Task, _f: &File, _fd: i32, _request: u64, _val: u64) -> Result<()> {
+        return Err(Error::SysError(SysErr::ENOTTY))
+    }
+
+    fn IterateDir(&self, _task: &Task, _d: &Dirent, _dirCtx: &mut DirCtx, _offset: i32) -> (i32, Result<i64>) {
+        return (0, Err(Error::SysError(SysErr::ENOTDIR)))
+    }
+
+    fn Mappable(&self) -> Result<HostInodeOp> {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+}
+
+impl SockOperations for ResolverFileOperations {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolver_request() {
+        assert_eq!(ParseResolverRequest("inet6 db.svc\n").unwrap(), (AFType::AF_INET6 as i32, "db.svc"));
+        assert!(ParseResolverRequest("unix db").is_err());
+        assert!(ParseResolverRequest("any").is_err());
+        assert!(ParseResolverRequest("any a b").is_err());
+
+        let mut v4 = ResolvedAddr { family: AFType::AF_INET as u32, ..Default::default() };
+        v4.addr[..4].copy_from_slice(&[10, 0, 0, 1]);
+        let mut v6 = ResolvedAddr { family: AFType::AF_INET6 as u32, ..Default::default() };
+        v6.addr[0] = 0x20;
+        v6.addr[1] = 0x01;
+        v6.addr[15] = 0x01;
+        assert_eq!(FormatResolvedAddrs(&[v4, v6]), "inet 10.0.0.1\ninet6 2001:0:0:0:0:0:0:1\n");
+    }
+}
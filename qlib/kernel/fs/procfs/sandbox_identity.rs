@@ -0,0 +1,81 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use crate::qlib::mutex::*;
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::auth::*;
+use super::super::super::task::*;
+use super::super::super::Kernel::HostSpace;
+use super::super::fsutil::file::readonly_file::*;
+use super::super::fsutil::inode::simple_file_inode::*;
+use super::super::attr::*;
+use super::super::file::*;
+use super::super::flags::*;
+use super::super::dirent::*;
+use super::super::mount::*;
+use super::super::inode::*;
+use super::inode::*;
+
+// /proc/sandbox_identity is the identity token of the sandbox signed by qvisor, a JWT with the
+// sandbox id, the image digest and the hash of the bundle config
+pub fn NewSandboxIdentity(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
+    let v = NewSandboxIdentitySimpleFileInode(task, &ROOT_OWNER, &FilePermissions::FromMode(FileMode(0o444)), FSMagic::PROC_SUPER_MAGIC);
+    return NewProcInode(&Arc::new(v), msrc, InodeType::SpecialFile, None)
+}
+
+pub fn NewSandboxIdentitySimpleFileInode(task: &Task,
+                                         owner: &FileOwner,
+                                         perms: &FilePermissions,
+                                         typ: u64)
+                                         -> SimpleFileInode<SandboxIdentityData> {
+    let fs = SandboxIdentityData{};
+    return SimpleFileInode::New(task, owner, perms, typ, false, fs)
+}
+
+pub struct SandboxIdentityData {
+}
+
+impl SandboxIdentityData {
+    pub fn GenSnapshot(&self, _task: &Task) -> Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(4096);
+        buf.resize(4096, 0);
+        loop {
+            let ret = HostSpace::ReadSandboxIdentity(&mut buf[0] as *mut _ as u64, buf.len());
+            if ret < 0 {
+                return Err(Error::SysError(-ret as i32))
+            }
+
+            let len = ret as usize;
+            if len <= buf.len() {
+                buf.truncate(len);
+                return Ok(buf)
+            }
+
+            // the token is larger than the buffer, retry with its size
+            buf.resize(len, 0);
+        }
+    }
+}
+
+impl SimpleFileTrait for SandboxIdentityData {
+    fn GetFile(&self, task: &Task, _dir: &Inode, dirent: &Dirent, flags: FileFlags) -> Result<File> {
+        let fops = NewSnapshotReadonlyFileOperations(self.GenSnapshot(task)?);
+        let file = File::New(dirent, &flags, fops);
+        return Ok(file);
+    }
+}
@@ -368,7 +368,7 @@ impl FileOperations for SeqFileOperations {
 
     fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], offset: i64, _blocking: bool) -> Result<i64> {
         let size = IoVec::NumBytes(dsts);
-        let dataBuf = DataBuff::New(size);
+        let dataBuf = DataBuff::NewUserIO(size)?;
         let bs = dataBuf.BlockSeq();
 
         let mut file = self.seqFile.write();
@@ -13,4 +13,5 @@
 // limitations under the License.
 
 pub mod vm;
-pub mod sys;
\ No newline at end of file
+pub mod sys;
+pub mod sysctl;
\ No newline at end of file
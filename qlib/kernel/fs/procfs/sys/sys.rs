@@ -31,6 +31,7 @@ use super::super::super::ramfs::dir::*;
 use super::super::dir_proc::*;
 use super::super::inode::*;
 use super::vm::vm::*;
+use super::sysctl::*;
 
 // ProcSysDirNode represents a /proc/sys directory.
 pub struct ProcSysDirNode {
@@ -49,6 +50,9 @@ impl DirDataNode for ProcSysDirNode {
 pub fn NewSys(task: &Task, msrc: &Arc<QMutex<MountSource>>) -> Inode {
     let mut contents = BTreeMap::new();
     contents.insert("vm".to_string(), NewVm(task, msrc));
+    for dir in SysctlTopDirs() {
+        contents.insert(dir.to_string(), NewSysctlDir(task, dir, msrc));
+    }
 
     let taskDir = DirNode {
         dir: Dir::New(task, contents, &ROOT_OWNER, &FilePermissions::FromMode(FileMode(0o0555))),
@@ -0,0 +1,434 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The sysctl table of the sandbox. The commonly read kernel and networking keys are served
+// from the table with the linux default values. The writable keys are the ones the sandbox
+// applies, they are set by the sysctls of the container spec and can be changed by the app, e.g.
+// a server which raises somaxconn at startup, and the change is seen by the whole sandbox. The
+// other keys are handled by the host and are read only.
+
+use core::any::Any;
+use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use alloc::collections::btree_map::BTreeMap;
+use crate::qlib::mutex::*;
+
+use super::super::super::super::super::common::*;
+use super::super::super::super::super::linux_def::*;
+use super::super::super::super::super::auth::*;
+use super::super::super::super::kernel::waiter::*;
+use super::super::super::super::task::*;
+use super::super::super::fsutil::inode::simple_file_inode::*;
+use super::super::super::host::hostinodeop::*;
+use super::super::super::attr::*;
+use super::super::super::file::*;
+use super::super::super::flags::*;
+use super::super::super::dentry::*;
+use super::super::super::dirent::*;
+use super::super::super::mount::*;
+use super::super::super::inode::*;
+use super::super::super::ramfs::dir::*;
+use super::super::dir_proc::*;
+use super::super::inode::*;
+use super::sys::*;
+
+// SYSCTL_WRITE_MAX is the max size of a value written to a sysctl file
+pub const SYSCTL_WRITE_MAX: usize = 256;
+
+pub struct SysctlDef {
+    // the sysctl key, e.g. net.core.somaxconn
+    pub key: &'static str,
+    pub default: &'static str,
+    // the number of the integer fields of the value
+    pub fields: usize,
+    pub writable: bool,
+}
+
+pub const SYSCTL_DEFS: [SysctlDef; 15] = [
+    SysctlDef { key: "fs.file-max", default: "9223372036854775807", fields: 1, writable: false },
+    SysctlDef { key: "fs.nr_open", default: "1048576", fields: 1, writable: false },
+    SysctlDef { key: "kernel.pid_max", default: "4194304", fields: 1, writable: false },
+    SysctlDef { key: "kernel.threads-max", default: "4194304", fields: 1, writable: false },
+    SysctlDef { key: "net.core.rmem_max", default: "212992", fields: 1, writable: true },
+    // the listen backlog cap of the sandbox, 1024 as before the table rather than 4096 of linux
+    SysctlDef { key: "net.core.somaxconn", default: "1024", fields: 1, writable: true },
+    SysctlDef { key: "net.core.wmem_max", default: "212992", fields: 1, writable: true },
+    SysctlDef { key: "net.ipv4.ip_local_port_range", default: "32768\t60999", fields: 2, writable: false },
+    SysctlDef { key: "net.ipv4.tcp_fin_timeout", default: "60", fields: 1, writable: false },
+    SysctlDef { key: "net.ipv4.tcp_keepalive_time", default: "7200", fields: 1, writable: false },
+    SysctlDef { key: "net.ipv4.tcp_max_syn_backlog", default: "1024", fields: 1, writable: false },
+    SysctlDef { key: "net.ipv4.tcp_mem", default: "94389\t125854\t188778", fields: 3, writable: false },
+    SysctlDef { key: "net.ipv4.tcp_rmem", default: "4096\t131072\t6291456", fields: 3, writable: false },
+    SysctlDef { key: "net.ipv4.tcp_tw_reuse", default: "2", fields: 1, writable: false },
+    SysctlDef { key: "net.ipv4.tcp_wmem", default: "4096\t16384\t4194304", fields: 3, writable: false },
+];
+
+pub fn SysctlIdx(key: &str) -> Option<usize> {
+    return SYSCTL_DEFS.iter().position(|d| d.key == key)
+}
+
+// ParseSysctl checks the value of the sysctl and returns it in the /proc/sys format
+pub fn ParseSysctl(def: &SysctlDef, value: &str) -> Result<String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    if fields.len() != def.fields {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    for f in &fields {
+        if f.parse::<i64>().is_err() {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+    }
+
+    return Ok(fields.join("\t"))
+}
+
+pub struct Sysctl {
+    // the values by the index of SYSCTL_DEFS, empty before Init
+    pub values: QRwLock<Vec<String>>,
+}
+
+pub static SYSCTL: Sysctl = Sysctl::New();
+
+impl Sysctl {
+    pub const fn New() -> Self {
+        return Self {
+            values: QRwLock::new(Vec::new()),
+        }
+    }
+
+    fn Defaults() -> Vec<String> {
+        return SYSCTL_DEFS.iter().map(|d| d.default.to_string()).collect()
+    }
+
+    // Init sets the values of the sandbox from the sysctls of the spec, the read only keys are
+    // left to the host
+    pub fn Init(&self, sysctls: &BTreeMap<String, String>) {
+        let mut values = Self::Defaults();
+        for (key, value) in sysctls {
+            let key = key.replace("/", ".");
+            let idx = match SysctlIdx(&key) {
+                Some(idx) if SYSCTL_DEFS[idx].writable => idx,
+                _ => {
+                    info!("ignoring unsupported sysctl {}", key);
+                    continue;
+                }
+            };
+
+            match ParseSysctl(&SYSCTL_DEFS[idx], value) {
+                Err(_) => error!("ignoring invalid sysctl {}={}", key, value),
+                Ok(v) => values[idx] = v,
+            }
+        }
+
+        *self.values.write() = values;
+    }
+
+    pub fn Get(&self, idx: usize) -> String {
+        let values = self.values.read();
+        if values.len() == 0 {
+            return SYSCTL_DEFS[idx].default.to_string()
+        }
+
+        return values[idx].clone()
+    }
+
+    pub fn Set(&self, idx: usize, value: &str) -> Result<()> {
+        let def = &SYSCTL_DEFS[idx];
+        if !def.writable {
+            return Err(Error::SysError(SysErr::EPERM))
+        }
+
+        let value = ParseSysctl(def, value)?;
+        let mut values = self.values.write();
+        if values.len() == 0 {
+            *values = Self::Defaults();
+        }
+
+        info!("sysctl {} is set to {}", def.key, &value);
+        values[idx] = value;
+        return Ok(())
+    }
+
+    // Fields returns the integer fields of the value of the key
+    pub fn Fields(&self, key: &str) -> Vec<i64> {
+        let idx = SysctlIdx(key).expect("unknown sysctl");
+        return self.Get(idx).split_whitespace().map(|f| f.parse::<i64>().unwrap()).collect()
+    }
+
+    // intField returns the value of the key with one field in the range of i32
+    fn intField(&self, key: &str) -> i32 {
+        let val = self.Fields(key)[0];
+        if val < 0 {
+            return 0
+        }
+
+        if val > i32::MAX as i64 {
+            return i32::MAX
+        }
+
+        return val as i32
+    }
+
+    pub fn Somaxconn(&self) -> i32 {
+        return self.intField("net.core.somaxconn")
+    }
+
+    // RmemMax is the cap of SO_RCVBUF, SO_RCVBUFFORCE is not capped
+    pub fn RmemMax(&self) -> i32 {
+        return self.intField("net.core.rmem_max")
+    }
+
+    // WmemMax is the cap of SO_SNDBUF, SO_SNDBUFFORCE is not capped
+    pub fn WmemMax(&self) -> i32 {
+        return self.intField("net.core.wmem_max")
+    }
+}
+
+pub struct SysctlFileTrait {
+    pub idx: usize,
+}
+
+impl SimpleFileTrait for SysctlFileTrait {
+    fn GetFile(&self, _task: &Task, _dir: &Inode, dirent: &Dirent, flags: FileFlags) -> Result<File> {
+        let fops = SysctlFileOperations {
+            idx: self.idx,
+        };
+
+        let file = File::New(dirent, &flags, fops);
+        return Ok(file);
+    }
+}
+
+pub fn NewSysctlFile(task: &Task, idx: usize, msrc: &Arc<QMutex<MountSource>>) -> Inode {
+    let mode = if SYSCTL_DEFS[idx].writable {
+        0o644
+    } else {
+        0o444
+    };
+
+    let v = SimpleFileInode::New(task,
+                                 &ROOT_OWNER,
+                                 &FilePermissions::FromMode(FileMode(mode)),
+                                 FSMagic::PROC_SUPER_MAGIC,
+                                 false,
+                                 SysctlFileTrait {
+                                     idx: idx,
+                                 });
+    return NewProcInode(&Arc::new(v), msrc, InodeType::SpecialFile, None)
+}
+
+// NewSysctlDir creates the /proc/sys directory of the keys with the prefix, e.g. "net.core"
+pub fn NewSysctlDir(task: &Task, prefix: &str, msrc: &Arc<QMutex<MountSource>>) -> Inode {
+    let mut contents = BTreeMap::new();
+    let prefix = prefix.to_string() + ".";
+    for idx in 0..SYSCTL_DEFS.len() {
+        let key = SYSCTL_DEFS[idx].key;
+        if !key.starts_with(&prefix) {
+            continue;
+        }
+
+        let name = &key[prefix.len()..];
+        match name.find('.') {
+            None => {
+                contents.insert(name.to_string(), NewSysctlFile(task, idx, msrc));
+            }
+            Some(n) => {
+                let dir = &name[..n];
+                if !contents.contains_key(dir) {
+                    let subPrefix = prefix.clone() + dir;
+                    contents.insert(dir.to_string(), NewSysctlDir(task, &subPrefix, msrc));
+                }
+            }
+        }
+    }
+
+    let dir = DirNode {
+        dir: Dir::New(task, contents, &ROOT_OWNER, &FilePermissions::FromMode(FileMode(0o0555))),
+        data: ProcSysDirNode {
+        }
+    };
+
+    return NewProcInode(&Arc::new(dir), msrc, InodeType::SpecialDirectory, None)
+}
+
+// SysctlTopDirs returns the top level directories of the sysctl table, e.g. "net"
+pub fn SysctlTopDirs() -> Vec<&'static str> {
+    let mut dirs: Vec<&'static str> = Vec::new();
+    for def in &SYSCTL_DEFS {
+        let dir = def.key.split('.').next().unwrap();
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+
+    return dirs
+}
+
+pub struct SysctlFileOperations {
+    pub idx: usize,
+}
+
+impl Waitable for SysctlFileOperations {
+    fn Readiness(&self, _task: &Task,mask: EventMask) -> EventMask {
+        return mask
+    }
+
+    fn EventRegister(&self, _task: &Task,_e: &WaitEntry, _mask: EventMask) {
+    }
+
+    fn EventUnregister(&self, _task: &Task,_e: &WaitEntry) {
+    }
+}
+
+impl SpliceOperations for SysctlFileOperations {}
+
+impl FileOperations for SysctlFileOperations {
+    fn as_any(&self) -> &Any {
+        return self
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::SysctlFileOperations
+    }
+
+    fn Seekable(&self) -> bool {
+        return true;
+    }
+
+    // SEEK_END is the end of the current value
+    fn Seek(&self, _task: &Task, _f: &File, whence: i32, current: i64, offset: i64) -> Result<i64> {
+        let base = match whence {
+            SeekWhence::SEEK_SET => 0,
+            SeekWhence::SEEK_CUR => current,
+            SeekWhence::SEEK_END => SYSCTL.Get(self.idx).len() as i64 + 1,
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        };
+
+        match base.checked_add(offset) {
+            Some(off) if off >= 0 => return Ok(off),
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        }
+    }
+
+    fn ReadDir(&self, _task: &Task, _f: &File, _offset: i64, _serializer: &mut DentrySerializer) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], offset: i64, _blocking: bool) -> Result<i64> {
+        if offset < 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let buf = SYSCTL.Get(self.idx) + "\n";
+        if offset as usize > buf.len() {
+            return Ok(0)
+        }
+
+        let n = task.CopyDataOutToIovs(&buf.as_bytes()[offset as usize ..], dsts)?;
+        return Ok(n as i64)
+    }
+
+    fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], offset: i64, _blocking: bool) -> Result<i64> {
+        // as the strict sysctl writes of linux, the value is written from the start in one write
+        if offset != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let size = IoVec::NumBytes(srcs);
+        if size > SYSCTL_WRITE_MAX {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mut buf: Vec<u8> = Vec::with_capacity(size);
+        buf.resize(size, 0);
+        let n = task.CopyDataInFromIovs(&mut buf, srcs)?;
+        let value = match core::str::from_utf8(&buf[..n]) {
+            Err(_) => return Err(Error::SysError(SysErr::EINVAL)),
+            Ok(v) => v,
+        };
+
+        SYSCTL.Set(self.idx, value)?;
+        return Ok(n as i64)
+    }
+
+    fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
+        let n = self.WriteAt(task, f, srcs, 0, false)?;
+        return Ok((n, 0))
+    }
+
+    fn Fsync(&self, _task: &Task, _f: &File, _start: i64, _end: i64, _syncType: SyncType) -> Result<()> {
+        return Ok(())
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(())
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        let inode = f.Dirent.Inode();
+        return inode.UnstableAttr(task);
+    }
+
+    fn Ioctl(&self, _task: &Task, _f: &File, _fd: i32, _request: u64, _val: u64) -> Result<()> {
+        return Err(Error::SysError(SysErr::ENOTTY))
+    }
+
+    fn IterateDir(&self, _task: &Task, _d: &Dirent, _dirCtx: &mut DirCtx, _offset: i32) -> (i32, Result<i64>) {
+        return (0, Err(Error::SysError(SysErr::ENOTDIR)))
+    }
+
+    fn Mappable(&self) -> Result<HostInodeOp> {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+}
+
+impl SockOperations for SysctlFileOperations {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_parse_sysctl() {
+        let range = &SYSCTL_DEFS[SysctlIdx("net.ipv4.ip_local_port_range").unwrap()];
+        assert_eq!(ParseSysctl(range, "1024 65535\n").unwrap(), "1024\t65535");
+        assert!(ParseSysctl(range, "1024").is_err());
+        assert!(ParseSysctl(range, "1024 abc").is_err());
+        assert_eq!(SysctlIdx("net.core.nonexist"), None);
+    }
+
+    #[test]
+    fn test_sysctl() {
+        let sysctl = Sysctl::New();
+        let mut sysctls = BTreeMap::new();
+        sysctls.insert("net/core/somaxconn".to_string(), "128".to_string());
+        sysctls.insert("net.core.rmem_max".to_string(), "bad".to_string());
+        sysctls.insert("net.ipv4.tcp_rmem".to_string(), "4096 4096 4096".to_string());
+        sysctl.Init(&sysctls);
+
+        assert_eq!(sysctl.Somaxconn(), 128);
+        assert_eq!(sysctl.RmemMax(), 212992);
+        // the read only keys are left to the host
+        assert_eq!(sysctl.Fields("net.ipv4.tcp_rmem"), vec![4096, 131072, 6291456]);
+
+        let idx = SysctlIdx("net.core.somaxconn").unwrap();
+        sysctl.Set(idx, "65535\n").unwrap();
+        assert_eq!(sysctl.Somaxconn(), 65535);
+        assert!(sysctl.Set(SysctlIdx("kernel.pid_max").unwrap(), "100").is_err());
+    }
+}
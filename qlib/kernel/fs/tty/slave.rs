@@ -318,10 +318,10 @@ impl FileOperations for SlaveFileOperations {
 
     fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
         let size = IoVec::NumBytes(srcs);
-        let mut buf = DataBuff::New(size);
+        let mut buf = DataBuff::NewUserIO(size)?;
         task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
 
-        let res = self.d.read().t.ld.lock().OutputQueueWrite(task, &mut buf.buf[0..size as usize])?;
+        let res = self.d.read().t.ld.lock().OutputQueueWrite(task, &mut buf.buf)?;
         return Ok(res)
     }
 
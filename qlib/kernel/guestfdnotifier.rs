@@ -14,9 +14,11 @@
 
 use alloc::sync::Arc;
 use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
 use crate::qlib::mutex::*;
 use core::ops::Deref;
 use core::fmt;
+use core::sync::atomic::Ordering;
 
 use super::Kernel::HostSpace;
 use super::kernel::waiter::*;
@@ -37,12 +39,37 @@ pub fn RemoveFD(fd: i32) {
     GUEST_NOTIFIER.RemoveFD(fd);
 }
 
+// MoveFD sends the events of the fd to the queue instead of the one of its inode
+pub fn MoveFD(fd: i32, queue: &Queue) {
+    GUEST_NOTIFIER.MoveFD(fd, queue);
+}
+
+pub fn SetClosing(fd: i32) {
+    GUEST_NOTIFIER.SetClosing(fd);
+}
+
+pub fn IsClosing(fd: i32) -> bool {
+    return GUEST_NOTIFIER.IsClosing(fd);
+}
+
 pub fn UpdateFD(fd: i32) -> Result<()> {
     return GUEST_NOTIFIER.UpdateFD(fd);
 }
 
 pub fn NonBlockingPoll(fd: i32, mask: EventMask) -> EventMask {
-    return HostSpace::NonBlockingPoll(fd, mask) as EventMask
+    let ready = HostSpace::NonBlockingPoll(fd, mask) as EventMask;
+    ClearNotified(fd, mask & !ready);
+    return ready
+}
+
+// ClearNotified is called when the guest finds the events are not ready any more (e.g. EAGAIN),
+// the next host notification of the events has to be delivered
+pub fn ClearNotified(fd: i32, mask: EventMask) {
+    if mask == 0 {
+        return
+    }
+
+    GUEST_NOTIFIER.ClearNotified(fd, mask);
 }
 
 pub fn Notify(fd: i32, mask: EventMask) {
@@ -53,6 +80,9 @@ pub fn Notify(fd: i32, mask: EventMask) {
 pub struct FdWaitIntern {
     pub queue: Queue,
     pub mask: EventMask,
+    // the events notified to the queue and not consumed by the guest yet. The host notification
+    // of these events is dropped so that only the transitions wake up the guest.
+    pub notified: EventMask,
 }
 
 impl fmt::Debug for FdWaitIntern {
@@ -78,7 +108,8 @@ impl FdWaitInfo {
     pub fn New(queue: Queue, mask: EventMask) -> Self {
         let intern = FdWaitIntern {
             queue,
-            mask
+            mask,
+            notified: 0,
         };
 
         return Self(Arc::new(QMutex::new(intern)))
@@ -89,6 +120,8 @@ impl FdWaitInfo {
         let mask = {
             let mut fi = self.lock();
 
+            // the waiters are changed, the new waiter has to get the next notification
+            fi.notified = 0;
             let mask = fi.queue.Events();
 
             if fi.mask == 0 {
@@ -121,8 +154,9 @@ impl FdWaitInfo {
 
     pub fn UpdateFDSync(&self, fd: i32) -> Result<()> {
         let mask = {
-            let fi = self.lock();
+            let mut fi = self.lock();
 
+            fi.notified = 0;
             let mask = fi.queue.Events();
             if mask == fi.mask {
                 return Ok(())
@@ -135,8 +169,25 @@ impl FdWaitInfo {
     }
 
     pub fn Notify(&self, mask: EventMask) {
-        let queue = self.lock().queue.clone();
-        queue.Notify(EventMaskFromLinux(mask as u32));
+        let mask = EventMaskFromLinux(mask as u32);
+        let (queue, mask) = {
+            let mut fi = self.lock();
+            let edge = mask & !fi.notified;
+            fi.notified |= mask;
+            (fi.queue.clone(), edge)
+        };
+
+        if mask == 0 {
+            SHARESPACE.fdNotifyCoalescedCnt.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+
+        SHARESPACE.fdNotifyCnt.fetch_add(1, Ordering::Relaxed);
+        queue.Notify(mask);
+    }
+
+    pub fn ClearNotified(&self, mask: EventMask) {
+        self.lock().notified &= !mask;
     }
 
     fn waitfd(fd: i32, mask: EventMask) -> Result<()> {
@@ -152,9 +203,26 @@ pub struct GuestNotifierInternal {
     // fdMap maps file descriptors to their notification queues and waiting
     // status.
     fdMap: BTreeMap<i32, FdWaitInfo>,
+
+    // closing holds the host fds whose close has been sent to the host asynchronously.
+    // The fd leaves the closing state when the host returns the same fd number for a new file
+    // or when the host has done all the async closes.
+    closing: BTreeSet<i32>,
     pub epollfd: i32,
 }
 
+impl GuestNotifierInternal {
+    // IsClosing returns whether the async close of the fd is not done by the host yet.
+    // pendingClose: the async closes not done by the host
+    pub fn IsClosing(&mut self, fd: i32, pendingClose: u64) -> bool {
+        if pendingClose == 0 && self.closing.len() > 0 {
+            self.closing.clear();
+        }
+
+        return self.closing.contains(&fd)
+    }
+}
+
 #[repr(C)]
 #[repr(packed)]
 #[derive(Default, Copy, Clone, Debug)]
@@ -184,6 +252,7 @@ impl GuestNotifier {
     pub fn New() -> Self {
         let internal = GuestNotifierInternal {
             fdMap: BTreeMap::new(),
+            closing: BTreeSet::new(),
             epollfd: 0,
         };
 
@@ -277,6 +346,9 @@ impl GuestNotifier {
             panic!("GUEST_NOTIFIER::AddFD fd {} added twice", fd);
         }
 
+        // the host has finished the previous async close of the fd
+        n.closing.remove(&fd);
+
         let waitinfo = FdWaitInfo::New(queue.clone(), 0);
         n.fdMap.insert(fd, waitinfo.clone());
         HostSpace::UpdateWaitInfo(fd, waitinfo);
@@ -287,7 +359,35 @@ impl GuestNotifier {
         n.fdMap.remove(&fd);
     }
 
+    pub fn MoveFD(&self, fd: i32, queue: &Queue) {
+        let mut n = self.lock();
+        // the host keeps waiting for the events of the fd it has been asked for
+        let mask = n.fdMap.get(&fd).map(|fi| fi.lock().mask).unwrap_or(0);
+        let waitinfo = FdWaitInfo::New(queue.clone(), mask);
+        n.fdMap.insert(fd, waitinfo.clone());
+        HostSpace::UpdateWaitInfo(fd, waitinfo);
+    }
+
+    pub fn SetClosing(&self, fd: i32) {
+        self.lock().closing.insert(fd);
+    }
+
+    pub fn IsClosing(&self, fd: i32) -> bool {
+        return self.lock().IsClosing(fd, SHARESPACE.PendingCloseCnt());
+    }
+
+    pub fn ClearNotified(&self, fd: i32, mask: EventMask) {
+        match self.FdWaitInfo(fd) {
+            None => (),
+            Some(fi) => fi.ClearNotified(mask),
+        }
+    }
+
     pub fn Notify(&self, fd: i32, mask: EventMask) {
+        if self.IsClosing(fd) {
+            return
+        }
+
         let fi = match self.FdWaitInfo(fd) {
             None => return,
             Some(fi) => fi
@@ -295,4 +395,22 @@ impl GuestNotifier {
 
         fi.Notify(mask);
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_closing() {
+        let mut n = GuestNotifier::New().0.into_inner();
+        n.closing.insert(3);
+        n.closing.insert(4);
+        assert!(n.IsClosing(3, 2));
+        assert!(!n.IsClosing(5, 2));
+
+        // the set is cleared when the host has done all the async closes
+        assert!(!n.IsClosing(3, 0));
+        assert_eq!(n.closing.len(), 0);
+    }
 }
\ No newline at end of file
@@ -34,7 +34,9 @@ impl GuestAllocator {
     }
 
     pub fn Print(&self, class: usize) -> String {
-        return format!("GuestAllocator {} {:x}", class, HEAP_ADDR);
+        let allocator = self.Allocator();
+        return format!("GuestAllocator {} {:x} heap total {} free {}", class, HEAP_ADDR,
+                       allocator.total.load(Ordering::Relaxed), allocator.free.load(Ordering::Relaxed));
     }
 }
 
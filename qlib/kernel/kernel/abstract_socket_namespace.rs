@@ -16,6 +16,8 @@ use alloc::sync::Arc;
 use crate::qlib::mutex::*;
 use alloc::collections::btree_map::BTreeMap;
 use core::ops::Deref;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
 use alloc::vec::Vec;
 
 use super::super::socket::unix::transport::unix::*;
@@ -36,6 +38,28 @@ pub fn Bind(name: Vec<u8>, ep: &BoundEndpoint) -> Result<()> {
     return ABSTRACT_SOCKET.Bind(name, ep);
 }
 
+pub fn Unbind(name: &Vec<u8>) {
+    ABSTRACT_SOCKET.lock().remove(name);
+}
+
+// the autobind names are a nul and 5 hex digits as linux
+pub const AUTOBIND_NAMES: u32 = 0x100000;
+
+static AUTOBIND_ORDERNUM: AtomicU32 = AtomicU32::new(0);
+
+pub fn AutoBindName(ordernum: u32) -> Vec<u8> {
+    let mut name = Vec::with_capacity(6);
+    name.push(0);
+    name.extend_from_slice(format!("{:05x}", ordernum % AUTOBIND_NAMES).as_bytes());
+    return name
+}
+
+// NextAutoBindName returns the next candidate of the autobind, the caller tries another one
+// when it is in use
+pub fn NextAutoBindName() -> Vec<u8> {
+    return AutoBindName(AUTOBIND_ORDERNUM.fetch_add(1, Ordering::Relaxed))
+}
+
 #[derive(Clone, Default)]
 pub struct AbstractSocketNamespace(Arc<QMutex<BTreeMap<Vec<u8>, BoundEndpointWeak>>>);
 
@@ -92,4 +116,15 @@ impl AbstractSocketNamespace {
         a.insert(name, ep.Downgrade());
         return Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_autobind_name() {
+        assert_eq!(AutoBindName(0), b"\000000".to_vec());
+        assert_eq!(AutoBindName(0xabc), b"\000abc".to_vec());
+        assert_eq!(AutoBindName(AUTOBIND_NAMES + 1), b"\000001".to_vec());
+    }
+}
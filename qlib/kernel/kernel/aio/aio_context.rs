@@ -128,6 +128,7 @@ impl MemoryManager {
             Perms: AccessType::ReadOnly(),
             MaxPerms: AccessType::ReadOnly(),
             GrowsDown: false,
+            ShadowStack: false,
             Precommit: false,
             MLockMode: MLockMode::default(),
             Kernel: false,
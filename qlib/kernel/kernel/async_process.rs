@@ -13,19 +13,212 @@
 // limitations under the License.
 
 use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering;
 
 use super::super::TSC;
 use super::super::LoadVcpuFreq;
+use super::super::ASYNC_PROCESS;
+use super::super::IOURING;
+use super::super::KERNEL_STACK_ALLOCATOR;
 use super::super::task::*;
 use super::timer::timer::*;
 use super::super::super::mutex::*;
 use super::super::super::linux::time::*;
+use super::super::socket::hostinet::idle::*;
+use super::super::socket::hostinet::stats::LogRDMAStats;
 use super::kernel::*;
 
+// The background work of the kernel runs on the vcpus polling the async msgs, it is in classes
+// of priority: the timers, the deferred frees and the writeback. A poll runs at most the budget
+// of each class, so one class can't monopolize the vcpu and the lower classes run on each poll.
+// The work waiting longer than the max delay of its class is run on the syscall return of any
+// vcpu, so it isn't starved when the vcpus are busy. The timer tick has no delay, it runs on the
+// first syscall return after it is due. The host io thread runs the clock tick directly before
+// it sleeps, the queued work runs in the guest.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsyncClass {
+    Timer = 0,
+    Free,
+    Writeback,
+}
+
+pub const ASYNC_CLASS_COUNT: usize = 3;
+
+// the work run per poll and the max delay in ms of each class
+const ASYNC_CLASS_BUDGET: [u32; ASYNC_CLASS_COUNT] = [8, 2, 4];
+const ASYNC_CLASS_MAX_DELAY_MS: [i64; ASYNC_CLASS_COUNT] = [0, 100, 20];
+
+// AsyncWork is the queued background work, a work queued again before it runs is run once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AsyncWork {
+    // notify the cpu clock ticker and check the clock set
+    ClockTick = 0,
+    // shrink the bufs of the idle uring sockets
+    ReclaimIdleSocketBufs = 1,
+    // free the kernel stacks of the tasks switched out for the last time
+    FreeStacks = 2,
+    // write the kernel log buffer to the log file
+    LogFlush = 3,
+    // log the summary of the RDMA counters
+    LogRDMAStats = 4,
+}
+
+impl AsyncWork {
+    pub fn Class(&self) -> AsyncClass {
+        match self {
+            Self::ClockTick => AsyncClass::Timer,
+            Self::ReclaimIdleSocketBufs => AsyncClass::Free,
+            Self::FreeStacks => AsyncClass::Free,
+            Self::LogFlush => AsyncClass::Writeback,
+            Self::LogRDMAStats => AsyncClass::Writeback,
+        }
+    }
+
+    pub fn FromBit(bit: u32) -> Option<Self> {
+        match bit {
+            0 => Some(Self::ClockTick),
+            1 => Some(Self::ReclaimIdleSocketBufs),
+            2 => Some(Self::FreeStacks),
+            3 => Some(Self::LogFlush),
+            4 => Some(Self::LogRDMAStats),
+            _ => None,
+        }
+    }
+
+    pub fn Run(&self) {
+        match self {
+            Self::ClockTick => ASYNC_PROCESS.ClockTick(),
+            Self::ReclaimIdleSocketBufs => ReclaimIdleSocketBufs(),
+            Self::FreeStacks => {
+                DEFERRED_STACKS.Free(|stack| {
+                    KERNEL_STACK_ALLOCATOR.Free(stack).unwrap();
+                })
+            }
+            Self::LogFlush => IOURING.LogFlush(),
+            Self::LogRDMAStats => LogRDMAStats(),
+        }
+    }
+}
+
+pub static DEFERRED_STACKS: DeferredStacks = DeferredStacks::New();
+
+// DeferredStacks is the list of the kernel stacks to free, it is linked through the first word
+// of the stacks so that the push doesn't allocate
+pub struct DeferredStacks {
+    pub head: AtomicU64,
+}
+
+impl DeferredStacks {
+    pub const fn New() -> Self {
+        return Self {
+            head: AtomicU64::new(0),
+        }
+    }
+
+    // Push adds the stack, the stack is not used after it
+    pub fn Push(&self, stack: u64) {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            unsafe {
+                *(stack as *mut u64) = head;
+            }
+
+            match self.head.compare_exchange(head, stack, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(h) => head = h,
+            }
+        }
+    }
+
+    // Free takes all the stacks and frees them with free
+    pub fn Free(&self, mut free: impl FnMut(u64)) {
+        let mut stack = self.head.swap(0, Ordering::AcqRel);
+        while stack != 0 {
+            let next = unsafe {
+                *(stack as *const u64)
+            };
+            free(stack);
+            stack = next;
+        }
+    }
+}
+
+// the queue is a bitmap of the work so that the host can queue without allocation
+pub struct AsyncQueue {
+    pub pending: AtomicU64,
+    // the tsc when the queue became non empty
+    pub since: AtomicI64,
+}
+
+impl AsyncQueue {
+    pub const fn New() -> Self {
+        return Self {
+            pending: AtomicU64::new(0),
+            since: AtomicI64::new(0),
+        }
+    }
+
+    // Push queues the work at curr, it returns whether the queue was empty
+    pub fn Push(&self, work: AsyncWork, curr: i64) -> bool {
+        let prev = self.pending.fetch_or(1 << work as u32, Ordering::AcqRel);
+        if prev == 0 {
+            self.since.store(curr, Ordering::Release);
+        }
+
+        return prev == 0
+    }
+
+    // Deadline returns when the oldest queued work is overdue, None when the queue is empty
+    pub fn Deadline(&self, curr: i64, delay: i64) -> Option<i64> {
+        if self.pending.load(Ordering::Acquire) == 0 {
+            return None
+        }
+
+        // the since of the work being queued is not stored yet
+        let since = match self.since.load(Ordering::Acquire) {
+            0 => curr,
+            since => since,
+        };
+
+        return Some(since + delay)
+    }
+
+    // Take takes the first queued work
+    pub fn Take(&self) -> Option<AsyncWork> {
+        loop {
+            let pending = self.pending.load(Ordering::Acquire);
+            if pending == 0 {
+                return None
+            }
+
+            let bit = pending.trailing_zeros();
+            let prev = self.pending.fetch_and(!(1 << bit), Ordering::AcqRel);
+            if prev & (1 << bit) == 0 {
+                // taken by another vcpu
+                continue;
+            }
+
+            if prev == 1 << bit {
+                self.since.store(0, Ordering::Release);
+            }
+
+            return AsyncWork::FromBit(bit)
+        }
+    }
+}
+
+pub fn DelayCycles(class: usize) -> i64 {
+    ASYNC_CLASS_MAX_DELAY_MS[class] * LoadVcpuFreq() / 1000
+}
+
 pub struct AsyncProcess {
     pub lastTsc: AtomicI64,
-    pub lastProcessTime: QMutex<i64>
+    pub lastProcessTime: QMutex<i64>,
+    pub queues: [AsyncQueue; ASYNC_CLASS_COUNT],
+    // the tsc when the oldest queued work is overdue, i64::MAX when there is none
+    pub deadline: AtomicI64,
 }
 
 const TSC_GAP : i64 = 2_000_000; // for 2 GHZ process, it is 1 ms
@@ -40,6 +233,8 @@ impl AsyncProcess {
         return Self {
             lastTsc: AtomicI64::new(0),
             lastProcessTime: QMutex::new(0),
+            queues: [AsyncQueue::New(), AsyncQueue::New(), AsyncQueue::New()],
+            deadline: AtomicI64::new(i64::MAX),
         }
     }
 
@@ -49,7 +244,68 @@ impl AsyncProcess {
         self.lastTsc.store(curr, Ordering::SeqCst);
     }
 
+    // Queue queues the work to run on the next poll or at the latest after the max delay of
+    // its class, it can be called on the host
+    pub fn Queue(&self, work: AsyncWork) {
+        let class = work.Class() as usize;
+        let curr = TSC.Rdtsc();
+        self.queues[class].Push(work, curr);
+        self.deadline.fetch_min(curr + DelayCycles(class), Ordering::AcqRel);
+    }
+
     pub fn Process(&self) {
+        self.QueueTimers(TSC.Rdtsc());
+        self.ProcessQueues();
+    }
+
+    // ProcessOverdue runs the background work which waits longer than its max delay, it is
+    // called on the syscall return
+    #[inline]
+    pub fn ProcessOverdue(&self) {
+        let curr = TSC.Rdtsc();
+        self.QueueTimers(curr);
+        if curr >= self.deadline.load(Ordering::Relaxed) {
+            self.ProcessQueues();
+        }
+    }
+
+    // QueueTimers queues the clock tick when the last one is older than the tsc gap
+    #[inline]
+    pub fn QueueTimers(&self, curr: i64) {
+        if curr - self.lastTsc.load(Ordering::Relaxed) > TSC_GAP {
+            self.Queue(AsyncWork::ClockTick);
+        }
+    }
+
+    // ProcessQueues runs the budget of each class in the priority order
+    pub fn ProcessQueues(&self) {
+        if self.deadline.load(Ordering::Relaxed) == i64::MAX {
+            return
+        }
+
+        // the work queued after the reset lowers the deadline again
+        self.deadline.store(i64::MAX, Ordering::SeqCst);
+        let curr = TSC.Rdtsc();
+        for class in 0..ASYNC_CLASS_COUNT {
+            let queue = &self.queues[class];
+            for _ in 0..ASYNC_CLASS_BUDGET[class] {
+                match queue.Take() {
+                    None => break,
+                    Some(work) => work.Run(),
+                }
+            }
+
+            if let Some(deadline) = queue.Deadline(curr, DelayCycles(class)) {
+                self.deadline.fetch_min(deadline, Ordering::AcqRel);
+            }
+        }
+    }
+
+    // ClockTick is the work of the timer class, the tick held by Atomically is notified on the
+    // next one
+    pub fn ClockTick(&self) {
+        super::timer::CheckClockSet();
+
         let curr = TSC.Rdtsc();
         if curr - self.lastTsc.load(Ordering::Relaxed) > TSC_GAP {
             self.lastTsc.store(curr, Ordering::Relaxed);
@@ -72,3 +328,89 @@ impl AsyncProcess {
         f();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+
+    #[test]
+    fn test_async_queue_take() {
+        let queue = AsyncQueue::New();
+        assert_eq!(queue.Take(), None);
+
+        assert!(queue.Push(AsyncWork::LogRDMAStats, 100));
+        assert!(!queue.Push(AsyncWork::LogFlush, 200));
+        // the work queued again before it runs is run once
+        assert!(!queue.Push(AsyncWork::LogRDMAStats, 300));
+        assert_eq!(queue.since.load(Ordering::Relaxed), 100);
+
+        // the work is taken in the order of the bits
+        assert_eq!(queue.Take(), Some(AsyncWork::LogFlush));
+        assert_eq!(queue.since.load(Ordering::Relaxed), 100);
+        assert_eq!(queue.Take(), Some(AsyncWork::LogRDMAStats));
+        assert_eq!(queue.since.load(Ordering::Relaxed), 0);
+        assert_eq!(queue.Take(), None);
+
+        // the queue emptied starts over
+        assert!(queue.Push(AsyncWork::LogFlush, 400));
+        assert_eq!(queue.since.load(Ordering::Relaxed), 400);
+    }
+
+    #[test]
+    fn test_async_queue_deadline() {
+        let queue = AsyncQueue::New();
+        assert_eq!(queue.Deadline(1000, 50), None);
+
+        queue.Push(AsyncWork::FreeStacks, 100);
+        assert_eq!(queue.Deadline(1000, 50), Some(150));
+        queue.Push(AsyncWork::ReclaimIdleSocketBufs, 900);
+        assert_eq!(queue.Deadline(1000, 50), Some(150));
+
+        // the work left after a take keeps the deadline of the oldest one
+        queue.Take();
+        assert_eq!(queue.Deadline(1000, 50), Some(150));
+        queue.Take();
+        assert_eq!(queue.Deadline(1000, 50), None);
+
+        // the pending work whose since is not stored yet is due from now
+        queue.pending.store(1 << AsyncWork::FreeStacks as u32, Ordering::Relaxed);
+        assert_eq!(queue.Deadline(1000, 50), Some(1050));
+    }
+
+    #[test]
+    fn test_async_work_class() {
+        for bit in 0..64 {
+            match AsyncWork::FromBit(bit) {
+                None => assert!(bit > AsyncWork::LogRDMAStats as u32),
+                Some(work) => assert_eq!(work as u32, bit),
+            }
+        }
+
+        // the timer tick is not delayed
+        assert_eq!(AsyncWork::ClockTick.Class(), AsyncClass::Timer);
+        assert_eq!(ASYNC_CLASS_MAX_DELAY_MS[AsyncClass::Timer as usize], 0);
+        assert_eq!(AsyncWork::FreeStacks.Class(), AsyncClass::Free);
+        assert_eq!(AsyncWork::LogFlush.Class(), AsyncClass::Writeback);
+    }
+
+    #[test]
+    fn test_deferred_stacks() {
+        let stacks = DeferredStacks::New();
+        let mut mem: Vec<u64> = vec![0; 3];
+        let addrs: Vec<u64> = mem.iter_mut().map(|m| m as *mut u64 as u64).collect();
+
+        for addr in &addrs {
+            stacks.Push(*addr);
+        }
+
+        let mut freed = Vec::new();
+        stacks.Free(|stack| freed.push(stack));
+        freed.reverse();
+        assert_eq!(freed, addrs);
+
+        let mut freed = Vec::new();
+        stacks.Free(|stack| freed.push(stack));
+        assert!(freed.is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicU64;
 use alloc::sync::Arc;
 use core::ops::Deref;
@@ -8,6 +9,8 @@ use super::super::super::task_mgr::*;
 //use super::super::taskMgr::*;
 
 use super::super::super::linux_def::QOrdering;
+use super::super::taskMgr;
+use super::super::SHARESPACE;
 
 #[derive(Clone)]
 pub struct MultiWait(Arc<MultiWaitIntern>);
@@ -29,6 +32,8 @@ impl Deref for MultiWait {
 pub struct MultiWaitIntern {
     pub count: AtomicU64,
     pub taskId: TaskId,
+    // the waiting task is switched out and is scheduled by the last Done
+    pub blocked: AtomicBool,
 }
 
 impl MultiWaitIntern {
@@ -36,6 +41,7 @@ impl MultiWaitIntern {
         return Self {
             count: AtomicU64::new(1),
             taskId: taskId,
+            blocked: AtomicBool::new(false),
         }
     }
 
@@ -44,15 +50,27 @@ impl MultiWaitIntern {
     }
 
     pub fn Done(&self) -> u64 {
-        let ret = self.count.fetch_sub(1, QOrdering::ACQUIRE) - 1;
-        /*if ret == 0 {
-            ScheduleQ(self.taskId);
-        }*/
+        let ret = self.count.fetch_sub(1, QOrdering::SEQ_CST) - 1;
+        if ret == 0 && self.blocked.load(QOrdering::SEQ_CST) {
+            taskMgr::ScheduleQ(self.taskId);
+        }
 
         return ret;
     }
 
     pub fn Wait(&self) {
+        // In the power save mode the task waits like a futex: it gives up the vcpu, which can
+        // then block in the host, and the last Done schedules it. The blocked flag is set before
+        // the count of the waiter is dropped so that the Done reaching 0 sees it.
+        if SHARESPACE.PowerSave() {
+            self.blocked.store(true, QOrdering::SEQ_CST);
+            if self.count.fetch_sub(1, QOrdering::SEQ_CST) - 1 != 0 {
+                taskMgr::Wait();
+            }
+
+            return
+        }
+
         self.Done();
         //Wait();
         while self.count.load(QOrdering::ACQUIRE) != 0 {
@@ -0,0 +1,146 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use super::super::super::config::*;
+use super::super::super::mutex::*;
+use super::super::super::linux::time::*;
+
+// The deterministic mode of Config::Deterministic. The apps read the virtual clock instead of
+// the host time, it advances by the tick at each syscall and each rdtsc, so the budget is per
+// event, not per instruction, and jumps to the deadline when a timed wait times out. The vdso
+// is kept unready so that clock_gettime enters the kernel, and CR4.TSD makes the rdtsc of the
+// apps fault so that it reads the virtual clock as a 1GHz tsc.
+// It is an aid to reproduce the runs, not a replay: the timed waits still expire by the host
+// clock, the IO completes in the host order and the timestamps of the files and the interval
+// timers follow the host time.
+pub static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+
+static TICK_NS: AtomicI64 = AtomicI64::new(0);
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+// the virtual monotonic time in ns
+static VIRTUAL_NS: AtomicI64 = AtomicI64::new(0);
+
+// the virtual realtime at boot, 2000-01-01T00:00:00Z
+pub const DETERMINISTIC_EPOCH: i64 = 946_684_800 * SECOND;
+
+pub fn InitDeterministic(config: &Config) {
+    TICK_NS.store(config.DeterministicTickNs as i64, Ordering::Relaxed);
+    SEED.store(config.DeterministicSeed, Ordering::Relaxed);
+    DETERMINISTIC.store(config.Deterministic, Ordering::Release);
+}
+
+#[inline]
+pub fn IsDeterministic() -> bool {
+    return DETERMINISTIC.load(Ordering::Relaxed)
+}
+
+// DeterministicTick advances the virtual clock at the syscall entry
+#[inline]
+pub fn DeterministicTick() {
+    if IsDeterministic() {
+        VIRTUAL_NS.fetch_add(TICK_NS.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+// DeterministicAdvanceTo moves the virtual clock to the monotonic time ns if it is behind
+pub fn DeterministicAdvanceTo(ns: i64) {
+    VIRTUAL_NS.fetch_max(ns, Ordering::Relaxed);
+}
+
+// DeterministicNow returns the virtual time of the clock in ns, the cpu clocks follow the
+// monotonic one
+pub fn DeterministicNow(clockId: i32) -> i64 {
+    let now = VIRTUAL_NS.load(Ordering::Relaxed);
+    match clockId {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE | CLOCK_REALTIME_ALARM => return DETERMINISTIC_EPOCH + now,
+        _ => return now,
+    }
+}
+
+// SplitMix64 is the generator of the random stream, it is not for cryptography
+pub fn SplitMix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    return z ^ (z >> 31)
+}
+
+// ThreadSeed returns the initial random state of the thread tid, the threads have their own
+// streams so that the values don't depend on the order they call getrandom
+pub fn ThreadSeed(tid: i32) -> u64 {
+    let mut state = SEED.load(Ordering::Relaxed) ^ ((tid as u32 as u64) << 32);
+    return SplitMix64(&mut state)
+}
+
+// DeterministicTsc returns the virtual tsc of the emulated rdtsc, the tick keeps the spin
+// loops on rdtsc moving forward
+pub fn DeterministicTsc() -> u64 {
+    DeterministicTick();
+    return VIRTUAL_NS.load(Ordering::Relaxed) as u64
+}
+
+// the random stream of the kernel itself, Random() may run out of the task context
+static KERNEL_RAND: QMutex<Option<u64>> = QMutex::new(None);
+
+pub fn DeterministicKernelRandom(buf: &mut [u8]) {
+    let mut rand = KERNEL_RAND.lock();
+    let state = rand.get_or_insert_with(|| {
+        let mut state = SEED.load(Ordering::Relaxed) ^ 0xffff_ffff_0000_0000;
+        SplitMix64(&mut state)
+    });
+    DeterministicFill(state, buf);
+}
+
+pub fn DeterministicFill(state: &mut u64, buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let v = SplitMix64(state).to_le_bytes();
+        chunk.copy_from_slice(&v[..chunk.len()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splitmix64() {
+        let mut state = 0;
+        assert_eq!(SplitMix64(&mut state), 0xe220a8397b1dcdaf);
+        assert_eq!(SplitMix64(&mut state), 0x6e789e6aa1b965f4);
+    }
+
+    #[test]
+    fn test_deterministic_fill() {
+        let mut s1 = 0;
+        let mut b1 = [0u8; 13];
+        DeterministicFill(&mut s1, &mut b1);
+        assert_eq!(b1[..8], 0xe220a8397b1dcdafu64.to_le_bytes());
+        assert_eq!(b1[8..], 0x6e789e6aa1b965f4u64.to_le_bytes()[..5]);
+
+        // the reads of multiple of 8 bytes continue the stream
+        let mut s2 = 0;
+        let mut b2 = [0u8; 16];
+        DeterministicFill(&mut s2, &mut b2[..8]);
+        DeterministicFill(&mut s2, &mut b2[8..]);
+        assert_eq!(b1[..8], b2[..8]);
+        assert_eq!(b1[8..], b2[8..13]);
+    }
+}
@@ -16,6 +16,7 @@ use alloc::sync::Arc;
 use alloc::vec::Vec;
 use crate::qlib::mutex::*;
 use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
 use core::any::Any;
 use core::ops::Deref;
 use alloc::string::String;
@@ -35,6 +36,8 @@ use super::super::super::fs::anon::*;
 use super::super::waiter::*;
 use super::epoll_entry::*;
 use super::epoll_list::*;
+use super::epoll_ring::*;
+use super::super::super::SHARESPACE;
 
 pub static CYCLE_MU : Singleton<QMutex<()>> = Singleton::<QMutex<()>>::New();
 pub unsafe fn InitSingleton() {
@@ -93,13 +96,20 @@ pub struct EventPollInternal {
     pub files: QMutex<BTreeMap<FileIdentifier, PollEntry>>,
 
     pub lists: QMutex<PollLists>,
+
+    // the ready entries are batched in the epoll ring of the waiting task, see EpollRing
+    pub batch: bool,
+    pub ring: QMutex<Option<Arc<TaskEpollRing>>>,
 }
 
 // NewEventPoll allocates and initializes a new event poll object.
 pub fn NewEventPoll(task: &Task) -> File {
     let inode = NewAnonInode(task);
     let dirent = Dirent::New(&inode, "anon_inode:[eventpoll]");
-    let epoll = EventPoll::default();
+    let epoll = EventPoll(Arc::new(EventPollInternal {
+        batch: SHARESPACE.EpollWakeupBatch(),
+        ..Default::default()
+    }));
     return File::New(&dirent, &FileFlags::default(), epoll);
 }
 
@@ -113,9 +123,62 @@ impl PartialEq for EventPoll {
 }
 
 impl EventPoll {
+    pub fn Ring(&self) -> Option<Arc<TaskEpollRing>> {
+        return self.ring.lock().clone()
+    }
+
+    // AttachRing batches the ready entries in the ring of the task until DetachRing, it returns
+    // false when the epoll doesn't batch or another task has attached its ring
+    pub fn AttachRing(&self, ring: &Arc<TaskEpollRing>) -> bool {
+        if !self.batch {
+            return false
+        }
+
+        let mut r = self.ring.lock();
+        if r.is_some() {
+            return false
+        }
+
+        ring.Activate();
+        *r = Some(ring.clone());
+        return true
+    }
+
+    pub fn DetachRing(&self) {
+        let mut lists = self.lists.lock();
+        let ring = match self.ring.lock().take() {
+            None => return,
+            Some(r) => r,
+        };
+
+        let entries = ring.Deactivate();
+        Self::MoveReady(&mut lists, entries);
+    }
+
+    // DrainRing moves the entries batched in the ring to the ready list
+    fn DrainRing(&self, lists: &mut PollLists) {
+        if let Some(ring) = self.Ring() {
+            Self::MoveReady(lists, ring.Take());
+        }
+    }
+
+    fn MoveReady(lists: &mut PollLists, entries: VecDeque<PollEntry>) {
+        for entry in entries {
+            // the entry removed or updated after it was queued
+            if entry.lock().state != PollEntryState::Queued {
+                continue;
+            }
+
+            lists.waitingList.Remove(&entry);
+            lists.readyList.PushBack(&entry);
+            entry.lock().state = PollEntryState::Ready;
+        }
+    }
+
     // eventsAvailable determines if 'e' has events available for delivery.
     pub fn EventsAvailable(&self, task: &Task) -> bool {
         let mut lists = self.lists.lock();
+        self.DrainRing(&mut lists);
         let mut it = lists.readyList.Front();
         while it.is_some() {
             let entry = it.unwrap();
@@ -147,6 +210,7 @@ impl EventPoll {
 
     pub fn ReadEvents(&self, task: &Task, max: i32) -> Vec<Event> {
         let mut lists = self.lists.lock();
+        self.DrainRing(&mut lists);
 
         let mut local = PollEntryList::default();
         let mut ret = Vec::new();
@@ -361,11 +425,13 @@ impl EventPoll {
             let state = entry.lock().state;
             let list = match state {
                 PollEntryState::Ready => &mut lists.readyList,
-                PollEntryState::Waiting => &mut lists.waitingList,
+                PollEntryState::Waiting | PollEntryState::Queued => &mut lists.waitingList,
                 PollEntryState::Disabled => &mut lists.disabledList,
             };
 
             list.Remove(&entry);
+            // the copy in the epoll ring is skipped
+            entry.lock().state = PollEntryState::Disabled;
         }
 
         entry.lock().flags = flags;
@@ -403,11 +469,13 @@ impl EventPoll {
             let state = entry.lock().state;
             let list = match state {
                 PollEntryState::Ready => &mut lists.readyList,
-                PollEntryState::Waiting => &mut lists.waitingList,
+                PollEntryState::Waiting | PollEntryState::Queued => &mut lists.waitingList,
                 PollEntryState::Disabled => &mut lists.disabledList,
             };
 
             list.Remove(&entry);
+            // the copy in the epoll ring is skipped
+            entry.lock().state = PollEntryState::Disabled;
         }
 
         // Remove file from map, and drop weak reference.
@@ -56,6 +56,8 @@ impl Eq for FileIdentifier {}
 pub enum PollEntryState {
     Ready,
     Waiting,
+    // ready and pushed to the epoll ring of the waiting task, it is still in the waiting list
+    Queued,
     Disabled,
 }
 
@@ -84,13 +86,33 @@ pub struct PollEntry(pub Arc<QMutex<PollEntryInternal>>);
 impl PollEntry {
     pub fn CallBack(&self) {
         let epoll = self.lock().epoll.clone();
+        if let Some(ring) = epoll.Ring() {
+            {
+                let mut e = self.lock();
+                if e.state != PollEntryState::Waiting {
+                    return
+                }
+                e.state = PollEntryState::Queued;
+            }
+
+            match ring.Push(self.clone()) {
+                Ok(wake) => {
+                    if wake {
+                        epoll.queue.Notify(EVENT_IN);
+                    }
+                    return
+                }
+                // the ring is full or detached, the entry goes to the ready list
+                Err(_) => (),
+            }
+        }
+
         let mut lists = epoll.lists.lock();
 
         let state = self.SetReady();
-        if state == PollEntryState::Waiting {
+        if state == PollEntryState::Waiting || state == PollEntryState::Queued {
             lists.waitingList.Remove(self);
             lists.readyList.PushBack(self);
-
             epoll.queue.Notify(EVENT_IN);
         }
     }
@@ -0,0 +1,111 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::vec_deque::VecDeque;
+use crate::qlib::mutex::*;
+
+use super::epoll_entry::*;
+
+// the entries batched in the ring of one task, the entries ready after the ring is full go to
+// the ready list of the epoll directly
+pub const EPOLL_RING_SIZE: usize = 1024;
+
+pub type TaskEpollRing = EpollRing<PollEntry>;
+
+pub struct EpollRingIntern<T> {
+    pub entries: VecDeque<T>,
+    // the ring is attached to the epoll the task is waiting on
+    pub active: bool,
+}
+
+// EpollRing is the ready ring of the task waiting in epoll_wait. The readiness callbacks of the
+// epoll entries push to the ring of the waiting task without the lock of the epoll lists, and
+// only the push to the empty ring wakes up the task. The task moves the whole ring to the ready
+// list when it reads the events, so the events arriving before it runs cost one wakeup.
+pub struct EpollRing<T> {
+    pub intern: QMutex<EpollRingIntern<T>>,
+}
+
+impl<T> EpollRing<T> {
+    pub fn New() -> Self {
+        return Self {
+            intern: QMutex::new(EpollRingIntern {
+                entries: VecDeque::new(),
+                active: false,
+            })
+        }
+    }
+
+    pub fn Activate(&self) {
+        self.intern.lock().active = true;
+    }
+
+    // Deactivate detaches the ring and returns the entries not read yet
+    pub fn Deactivate(&self) -> VecDeque<T> {
+        let mut intern = self.intern.lock();
+        intern.active = false;
+        return core::mem::replace(&mut intern.entries, VecDeque::new())
+    }
+
+    // Push returns whether the waiter has to be woken up, or gives the entry back when the ring
+    // is detached or full
+    pub fn Push(&self, entry: T) -> core::result::Result<bool, T> {
+        let mut intern = self.intern.lock();
+        if !intern.active || intern.entries.len() >= EPOLL_RING_SIZE {
+            return Err(entry)
+        }
+
+        intern.entries.push_back(entry);
+        return Ok(intern.entries.len() == 1)
+    }
+
+    pub fn Take(&self) -> VecDeque<T> {
+        return core::mem::replace(&mut self.intern.lock().entries, VecDeque::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoll_ring_push() {
+        let ring = EpollRing::New();
+        // the detached ring takes nothing
+        assert_eq!(ring.Push(1), Err(1));
+
+        ring.Activate();
+        assert_eq!(ring.Push(1), Ok(true));
+        assert_eq!(ring.Push(2), Ok(false));
+        assert_eq!(ring.Take(), VecDeque::from(vec![1, 2]));
+
+        // the first push after the read wakes up the waiter again
+        assert_eq!(ring.Push(3), Ok(true));
+        assert_eq!(ring.Deactivate(), VecDeque::from(vec![3]));
+        assert_eq!(ring.Push(4), Err(4));
+    }
+
+    #[test]
+    fn test_epoll_ring_full() {
+        let ring = EpollRing::New();
+        ring.Activate();
+        for i in 0..EPOLL_RING_SIZE {
+            assert_eq!(ring.Push(i), Ok(i == 0));
+        }
+
+        assert_eq!(ring.Push(EPOLL_RING_SIZE), Err(EPOLL_RING_SIZE));
+        assert_eq!(ring.Take().len(), EPOLL_RING_SIZE);
+        assert_eq!(ring.Push(0), Ok(true));
+    }
+}
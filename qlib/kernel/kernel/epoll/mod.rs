@@ -14,4 +14,5 @@
 
 pub mod epoll_entry;
 pub mod epoll;
-pub mod epoll_list;
\ No newline at end of file
+pub mod epoll_list;
+pub mod epoll_ring;
\ No newline at end of file
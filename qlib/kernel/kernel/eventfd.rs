@@ -205,7 +205,8 @@ impl FileOperations for EventOperations {
             return Err(Error::SysError(SysErr::EINVAL))
         }
 
-        let buf = DataBuff::New(size);
+        // only the 8 bytes counter is copied whatever the buffer size
+        let buf = DataBuff::New(8);
         self.Read(task, buf.BlockSeq())?;
         task.CopyDataOutToIovs(&buf.buf, dsts)?;
         return Ok(8)
@@ -217,7 +218,7 @@ impl FileOperations for EventOperations {
             return Err(Error::SysError(SysErr::EINVAL))
         }
 
-        let mut buf = DataBuff::New(size);
+        let mut buf = DataBuff::New(8);
         task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
 
         self.Write(task, buf.BlockSeq())?;
@@ -20,6 +20,7 @@ use core::ops::Deref;
 
 use super::super::super::linux_def::*;
 use super::super::super::common::*;
+use super::super::super::linux::inotify::*;
 use super::super::fs::file::*;
 use super::super::uid::*;
 
@@ -296,7 +297,10 @@ impl FDTableInternal {
 
         match file {
             None => return None,
-            Some(f) => return Some(f.file)
+            Some(f) => {
+                inotifyFileClose(&f.file);
+                return Some(f.file)
+            }
         }
     }
 
@@ -327,6 +331,12 @@ impl FDTableInternal {
     }
 }
 
-pub fn inotifyFileClose(_f: &File) {
-    //todo: will implement it later
+pub fn inotifyFileClose(f: &File) {
+    let ev = if f.Flags().Write {
+        IN_CLOSE_WRITE
+    } else {
+        IN_CLOSE_NOWRITE
+    };
+
+    f.Dirent.InotifyEvent(ev, 0);
 }
\ No newline at end of file
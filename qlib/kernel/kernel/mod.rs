@@ -37,4 +37,5 @@ pub mod platform;
 pub mod aio;
 pub mod signalfd;
 pub mod async_wait;
-pub mod async_process;
\ No newline at end of file
+pub mod async_process;
+pub mod deterministic;
@@ -32,6 +32,7 @@ use super::super::super::super::common::*;
 use super::super::super::super::linux_def::*;
 use super::super::super::super::device::*;
 use super::super::super::super::mem::seq::*;
+use super::super::super::super::mem::block::Iovs;
 use super::super::waiter::cond::*;
 use super::buffer::*;
 use super::node::*;
@@ -420,6 +421,41 @@ impl Pipe {
         return p.Write(task, src, self.atomicIOBytes)
     }
 
+    // WriteFromIovs writes the app data of srcs into the pipe. The user buf is shorter than srcs
+    // when the user buf pool is under pressure, the rest is written with more bufs until the
+    // pipe is full so that the blocking write is not cut short.
+    pub fn WriteFromIovs(&self, task: &Task, srcs: &[IoVec]) -> Result<usize> {
+        let len = IoVec::NumBytes(srcs);
+        let mut count = 0;
+        let mut srcs = srcs;
+        let mut tmp;
+        loop {
+            let ret = DataBuff::NewUserIO(len - count).and_then(|mut buf| {
+                task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
+                let n = self.Write(task, BlockSeq::New(&buf.buf))?;
+                Ok((n, buf.buf.len()))
+            });
+
+            let (n, size) = match ret {
+                Err(e) => {
+                    if count > 0 {
+                        return Ok(count)
+                    }
+                    return Err(e)
+                }
+                Ok(ret) => ret,
+            };
+
+            count += n;
+            if n < size || count == len {
+                return Ok(count)
+            }
+
+            tmp = Iovs(srcs).DropFirst(n);
+            srcs = &tmp;
+        }
+    }
+
     // rOpen signals a new reader of the pipe.
     pub fn ROpen(&self) {
         self.readers.fetch_add(1, Ordering::SeqCst);
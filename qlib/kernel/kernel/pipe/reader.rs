@@ -76,7 +76,7 @@ impl FileOperations for Reader {
         //error!("pipe reader readat id {}, reader is {}", self.pipe.Uid(), self.pipe.Readers());
 
         let size = IoVec::NumBytes(dsts);
-        let buf = DataBuff::New(size);
+        let buf = DataBuff::NewUserIO(size)?;
         let bs = BlockSeq::New(&buf.buf);
         let n = self.pipe.Read(task, bs)?;
         if n > 0 {
@@ -91,7 +91,7 @@ impl FileOperations for Reader {
     fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
         //error!("pipe reader WriteAt id {}, writers is {}", self.pipe.Uid(), self.pipe.Writers());
         let size = IoVec::NumBytes(srcs);
-        let mut buf = DataBuff::New(size);
+        let mut buf = DataBuff::NewUserIO(size)?;
         task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
         let n = self.pipe.Write(task, buf.BlockSeq())?;
         if n > 0 {
@@ -84,7 +84,7 @@ impl FileOperations for ReaderWriter {
 
     fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
         let size = IoVec::NumBytes(dsts);
-        let buf = DataBuff::New(size);
+        let buf = DataBuff::NewUserIO(size)?;
         let bs = BlockSeq::New(&buf.buf);
         let n = self.pipe.Read(task, bs)?;
         if n > 0 {
@@ -97,11 +97,7 @@ impl FileOperations for ReaderWriter {
     }
 
     fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
-        let size = IoVec::NumBytes(srcs);
-        let mut buf = DataBuff::New(size);
-        task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
-        let srcs = BlockSeq::New(&buf.buf);
-        let n = self.pipe.Write(task, srcs)?;
+        let n = self.pipe.WriteFromIovs(task, srcs)?;
         if n > 0 {
             self.pipe.Notify(EVENT_IN)
         }
@@ -99,12 +99,7 @@ impl FileOperations for Writer {
 
     fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
         //error!("pipe writer WriteAt id {}, writers is {}", self.pipe.Uid(), self.pipe.Writers());
-        let size = IoVec::NumBytes(srcs);
-        let mut buf = DataBuff::New(size);
-        task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
-        let srcs = BlockSeq::New(&buf.buf);
-
-        let n = match self.pipe.Write(task, srcs) {
+        let n = match self.pipe.WriteFromIovs(task, srcs) {
             Err(e) => {
                 //info!("Pipe::WriteAt 2.... e is {:?}", e);
                 return Err(e);
@@ -117,7 +112,7 @@ impl FileOperations for Writer {
             return Ok(n as i64)
         }
 
-        if srcs.NumBytes() == 0 {
+        if IoVec::NumBytes(srcs) == 0 {
             return Ok(0)
         }
 
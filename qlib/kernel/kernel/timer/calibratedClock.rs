@@ -262,6 +262,12 @@ impl CalibratedClocks {
         return (monotonicParams, true, realtimeParams, true)
     }
 
+    // ResetRealtime drops the realtime calibration after the reference clock is set,
+    // the next Update takes the new reference time as is
+    pub fn ResetRealtime(&self) {
+        self.realtime.reset("Realtime clock is set.");
+    }
+
     pub fn GetTime(&self, id: ClockID) -> Result<i64> {
         match id {
             MONOTONIC => self.monotonic.GetTime(),
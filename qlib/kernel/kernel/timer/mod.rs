@@ -27,6 +27,11 @@ use self::timer::*;
 use super::super::super::singleton::*;
 use super::super::super::object_ref::*;
 use super::super::SHARESPACE;
+use super::super::TSC;
+use super::super::Kernel::HostSpace;
+use super::super::super::vcpu_mgr::CPULocal;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
 
 pub static TIME_KEEPER: TimerKeeperRef = TimerKeeperRef::New();
 
@@ -68,10 +73,117 @@ pub fn MonotonicNow() -> i64 {
     return TIME_KEEPER.GetTime(MONOTONIC).expect("MonotonicNow fail");
 }
 
+// the TSC cycles between 2 host clock reads of the SpinTimer, about 10us at 2GHz
+const SPIN_CHECK_CYCLES: i64 = 20_000;
+
+// SpinTimer measures the spin budget of an idle loop with the host monotonic clock. MonotonicNow
+// is calibrated with a fixed TSC frequency, which is wrong when the TSC rate follows the cpu
+// frequency, so the TSC only paces the host clock reads and the budget expires by the host clock.
+pub struct SpinTimer {
+    budget: i64,
+    // the host monotonic time of the start, 0 means it is taken at the next check
+    start: i64,
+    lastCheck: i64,
+}
+
+impl SpinTimer {
+    pub fn New(budget: i64) -> Self {
+        return Self {
+            budget: budget,
+            start: 0,
+            lastCheck: TSC.Rdtsc(),
+        }
+    }
+
+    // Reset restarts the budget, the host clock is read at the next check so that a busy loop
+    // doesn't exit to the host on every reset
+    pub fn Reset(&mut self) {
+        self.start = 0;
+        self.lastCheck = TSC.Rdtsc();
+    }
+
+    pub fn Expired(&mut self) -> bool {
+        let tsc = TSC.Rdtsc();
+        if tsc - self.lastCheck < SPIN_CHECK_CYCLES {
+            return false
+        }
+        self.lastCheck = tsc;
+
+        let now = match HostSpace::KernelGetTime(MONOTONIC) {
+            Ok(now) => now,
+            // the spin stops instead of running without a clock
+            Err(_) => return true,
+        };
+
+        if self.start == 0 {
+            self.start = now;
+        }
+
+        return now - self.start >= self.budget
+    }
+}
+
 pub fn Timeout() {
     TIMER_STORE.Trigger();
 }
 
+// the vcpu polling the timers due in the high resolution window, only one idle vcpu does it
+static HIGHRES_POLLER: AtomicU64 = AtomicU64::new(u64::MAX);
+
+// HighResTimerPoll returns whether the idle vcpu keeps polling for the next timer. The timer
+// expiring within HighResTimerWindow is fired by the vcpu at its expire, instead of by the uring
+// timeout which the sleeping vcpus only see after the host wakes them up.
+pub fn HighResTimerPoll(now: i64) -> bool {
+    let window = SHARESPACE.config.read().HighResTimerNs();
+    if window == 0 {
+        return false
+    }
+
+    let cpu = CPULocal::CpuId() as u64;
+    let next = match TIMER_STORE.NextExpire() {
+        None => return HIGHRES_POLLER.load(Ordering::Relaxed) == cpu,
+        Some(next) => next,
+    };
+
+    if next == 0 || next - now > window {
+        HighResTimerRelease();
+        return false
+    }
+
+    if HIGHRES_POLLER.load(Ordering::Relaxed) != cpu
+        && HIGHRES_POLLER.compare_exchange(u64::MAX, cpu, Ordering::Acquire, Ordering::Relaxed).is_err() {
+        return false
+    }
+
+    if next <= now {
+        TIMER_STORE.Trigger();
+    }
+
+    return true
+}
+
+// HighResTimerRelease lets the other vcpus poll the timers when the vcpu stops polling
+pub fn HighResTimerRelease() {
+    let cpu = CPULocal::CpuId() as u64;
+    HIGHRES_POLLER.compare_exchange(cpu, u64::MAX, Ordering::Release, Ordering::Relaxed).ok();
+}
+
+// ClockSet makes the kernel follow the host CLOCK_REALTIME step: the realtime clock is
+// recalibrated and the realtime timers (e.g. absolute timerfd and clock_nanosleep) are re-armed
+// against the new time.
+pub fn ClockSet() {
+    info!("host realtime clock is set, the realtime timers are re-armed");
+    TIME_KEEPER.ClockSet();
+    TIMER_STORE.RearmRealtime();
+}
+
+// CheckClockSet handles the host clock set notified through the share space
+pub fn CheckClockSet() {
+    if SHARESPACE.TakeClockSet() {
+        ClockSet();
+    }
+}
+
 pub type ClockID = i32;
 
 pub const REALTIME: ClockID = 0;
@@ -21,6 +21,7 @@ use super::super::super::super::common::*;
 use super::super::super::super::linux::time::*;
 //use super::super::super::super::perf_tunning::*;
 use super::super::vdso::*;
+use super::super::deterministic::*;
 use super::calibratedClock::*;
 use super::timer::Clock;
 use super::timer::*;
@@ -69,6 +70,14 @@ impl TimeKeeper {
         self.write().Update();
     }
 
+    // ClockSet recalibrates the realtime clock and the VDSO parameters after the host
+    // realtime clock is set
+    pub fn ClockSet(&self) {
+        let mut internal = self.write();
+        internal.clocks.ResetRealtime();
+        internal.Update();
+    }
+
     pub fn GetTime(&self, c: ClockID) -> Result<i64> {
         return self.read().GetTime(c)
     }
@@ -146,8 +155,10 @@ impl TimeKeeperInternal {
         assert!(self.inited, "TimeKeeper not inited");
         let (monotonicParams, monotonicOk, realtimeParams, realtimeOk) = self.clocks.Update();
 
+        // the vdso params stay unready in the deterministic mode, the apps get the virtual clock
+        // from the syscall
         let mut p = VdsoParams::default();
-        if monotonicOk {
+        if monotonicOk && !IsDeterministic() {
             p.monotonicReady = 1;
             p.monotonicBaseCycles = monotonicParams.BaseCycles;
             p.monotonicBaseRef = monotonicParams.BaseRef + self.monotonicOffset;
@@ -156,7 +167,7 @@ impl TimeKeeperInternal {
 
         //error!("TimeKeeperInternal::Update monotonicParams is {:?}", &monotonicParams);
 
-        if realtimeOk {
+        if realtimeOk && !IsDeterministic() {
             p.realtimeReady = 1;
             p.realtimeBaseCycles = realtimeParams.BaseCycles;
             p.realtimeBaseRef = realtimeParams.BaseRef;
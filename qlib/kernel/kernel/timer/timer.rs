@@ -30,6 +30,9 @@ use super::super::time::*;
 use super::super::super::fs::timerfd::*;
 use super::super::timer::TimerUpdater;
 use super::super::posixtimer::*;
+use super::super::super::socket::hostinet::cork::CorkTimerListener;
+use super::super::super::socket::hostinet::idle::IdleReclaimListener;
+use super::super::super::socket::hostinet::stats::RDMAStatsListener;
 use super::timekeeper::*;
 use super::timer_store::*;
 use super::*;
@@ -85,6 +88,14 @@ impl Clock {
             Self::Dummy  => panic!("Clock::Dummy WallTimeUntil..."),
         }
     }
+
+    // IsRealtime returns whether the clock is CLOCK_REALTIME, which could be set
+    pub fn IsRealtime(&self) -> bool {
+        match self {
+            Self::TimeKeeperClock(ref c) => c.c == REALTIME,
+            _ => false,
+        }
+    }
 }
 
 pub struct ClockEventsQueue {
@@ -105,7 +116,10 @@ pub enum TimerListener {
     DummyTimerListener(DummyTimerListener),
     WaitEntryListener(WaitEntryListener),
     ITimerRealListener(Arc<ITimerRealListener>),
-    KernelCPUClockTicker(Arc<KernelCPUClockTicker>)
+    KernelCPUClockTicker(Arc<KernelCPUClockTicker>),
+    CorkTimerListener(Arc<CorkTimerListener>),
+    IdleReclaimListener(Arc<IdleReclaimListener>),
+    RDMAStatsListener(Arc<RDMAStatsListener>),
 }
 
 impl fmt::Debug for TimerListener {
@@ -118,6 +132,9 @@ impl fmt::Debug for TimerListener {
             Self::WaitEntryListener(_) => f.debug_struct("WaitEntryListener").finish(),
             Self::ITimerRealListener(_) => f.debug_struct("ITimerRealListener").finish(),
             Self::KernelCPUClockTicker(_)  => f.debug_struct("KernelCPUClockTicker").finish(),
+            Self::CorkTimerListener(_) => f.debug_struct("CorkTimerListener").finish(),
+            Self::IdleReclaimListener(_) => f.debug_struct("IdleReclaimListener").finish(),
+            Self::RDMAStatsListener(_) => f.debug_struct("RDMAStatsListener").finish(),
         }
     }
 }
@@ -139,6 +156,9 @@ impl TimerListener {
             Self::WaitEntryListener(tl) => tl.Notify(exp),
             Self::ITimerRealListener(tl) => tl.Notify(exp),
             Self::KernelCPUClockTicker(tl)  => tl.Notify(exp),
+            Self::CorkTimerListener(tl) => tl.Notify(exp),
+            Self::IdleReclaimListener(tl) => tl.Notify(exp),
+            Self::RDMAStatsListener(tl) => tl.Notify(exp),
         }
     }
 
@@ -151,6 +171,9 @@ impl TimerListener {
             Self::WaitEntryListener(tl) => tl.Destroy(),
             Self::ITimerRealListener(tl) => tl.Destroy(),
             Self::KernelCPUClockTicker(tl)  => tl.Destroy(),
+            Self::CorkTimerListener(tl) => tl.Destroy(),
+            Self::IdleReclaimListener(tl) => tl.Destroy(),
+            Self::RDMAStatsListener(tl) => tl.Destroy(),
         }
     }
 }
@@ -361,7 +384,7 @@ impl TimerInternal {
         return ret;
     }
 
-    fn NextExpire(&mut self) -> i64 {
+    pub fn NextExpire(&mut self) -> i64 {
         if self.setting.Enabled {
             let now = self.clock.Now();
             let expire = self.setting.Next;
@@ -717,3 +740,44 @@ impl TimerListenerTrait for ITimerRealListener {
 
     fn Destroy(&self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_setting_at() {
+        // one shot
+        let s = Setting::FromSpecAt(100, 0, Time(1000)).unwrap();
+        assert!(s.Enabled);
+        assert_eq!(s.Next.0, 1100);
+        let (s, exp) = s.At(Time(1050));
+        assert_eq!(exp, 0);
+        let (s, exp) = s.At(Time(1100));
+        assert_eq!(exp, 1);
+        assert!(!s.Enabled);
+        assert_eq!(s.At(Time(2000)).1, 0);
+
+        // the periods missed are counted as expirations
+        let s = Setting::FromSpecAt(100, 50, Time(1000)).unwrap();
+        let (s, exp) = s.At(Time(1230));
+        assert_eq!(exp, 3);
+        assert_eq!(s.Next.0, 1250);
+        assert_eq!(SpecFromSetting(Time(1230), s), (20, 50));
+    }
+
+    #[test]
+    fn test_setting_from_spec() {
+        // zero disarms the timer, negative is invalid
+        let s = Setting::FromSpecAt(0, 50, Time(1000)).unwrap();
+        assert!(!s.Enabled);
+        assert_eq!(SpecFromSetting(Time(1000), s), (0, 50));
+        assert!(Setting::FromSpecAt(-1, 0, Time(1000)).is_err());
+
+        let s = Setting::FromAbsSpec(Time(5000), 0).unwrap();
+        assert!(s.Enabled);
+        assert_eq!(s.Next.0, 5000);
+        assert!(!Setting::FromAbsSpec(Time(0), 0).unwrap().Enabled);
+        assert!(Setting::FromAbsSpec(Time(-1), 0).is_err());
+    }
+}
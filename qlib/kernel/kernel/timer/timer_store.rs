@@ -73,6 +73,12 @@ impl TimerStore {
         return self as * const _ as u64;
     }
 
+    // NextExpire returns the expire of the first timer, 0 when there is none. It is None when
+    // the store is being changed.
+    pub fn NextExpire(&self) -> Option<i64> {
+        return self.try_lock().map(|ts| ts.nextExpire)
+    }
+
     pub fn ResetTimer(&self, timer: &Timer, timeout: i64) {
         let mut ts = self.lock();
         ts.ResetTimer(timer, timeout);
@@ -85,6 +91,28 @@ impl TimerStore {
         ts.RemoveTimer(timer);
         ts.Trigger();
     }
+
+    // RearmRealtime recomputes the expire of the realtime timers after the clock is set,
+    // the expire is the monotonic time computed with the realtime before the set.
+    pub fn RearmRealtime(&self) {
+        let mut ts = self.lock();
+        let timers: Vec<Timer> = ts.timerSeq.values()
+            .filter(|t| t.lock().clock.IsRealtime())
+            .cloned()
+            .collect();
+
+        for timer in &timers {
+            let delta = timer.lock().NextExpire();
+            ts.ResetTimer(timer, delta);
+        }
+
+        // some timers could be moved later
+        ts.nextExpire = match ts.timerSeq.first_key_value() {
+            None => 0,
+            Some((tu, _)) => tu.expire,
+        };
+        ts.Trigger();
+    }
 }
 
 #[derive(Default)]
@@ -202,4 +230,64 @@ impl TimerStoreIntern {
 
         return Some(timer);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // insert adds the timer expiring at expire as ResetTimer does, without the clock
+    fn insert(ts: &mut TimerStoreIntern, expire: i64) -> Timer {
+        let timer = Timer::Dummy();
+        timer.lock().Expire = expire;
+        if ts.nextExpire == 0 || ts.nextExpire > expire {
+            ts.nextExpire = expire;
+        }
+
+        let tu = timer.lock().TimerUnit();
+        ts.timerSeq.insert(tu, timer.clone());
+        return timer
+    }
+
+    fn id(timer: &Timer) -> u64 {
+        return timer.lock().Id
+    }
+
+    #[test]
+    fn test_timer_store_order() {
+        let mut ts = TimerStoreIntern::default();
+        let t3 = insert(&mut ts, 3000);
+        let t1 = insert(&mut ts, 1000);
+        let t2 = insert(&mut ts, 2000);
+        assert_eq!(ts.nextExpire, 1000);
+
+        // nothing is due before the first expire
+        assert!(ts.GetFirst(999).is_none());
+        assert_eq!(id(&ts.GetFirst(2500).unwrap()), id(&t1));
+        assert_eq!(ts.nextExpire, 2000);
+        assert_eq!(id(&ts.GetFirst(2500).unwrap()), id(&t2));
+        assert_eq!(ts.nextExpire, 3000);
+        assert!(ts.GetFirst(2500).is_none());
+
+        // the removed timer is not fired
+        assert!(ts.RemoveTimer(&t3));
+        assert!(ts.GetFirst(4000).is_none());
+        assert_eq!(ts.timerSeq.len(), 0);
+    }
+
+    #[test]
+    fn test_timer_store_same_expire() {
+        let mut ts = TimerStoreIntern::default();
+        let t1 = insert(&mut ts, 1000);
+        let t2 = insert(&mut ts, 1000);
+
+        // the timers of the same expire fire in the order they are created
+        assert_eq!(id(&ts.GetFirst(1000).unwrap()), id(&t1));
+        assert_eq!(ts.nextExpire, 1000);
+        assert_eq!(id(&ts.GetFirst(1000).unwrap()), id(&t2));
+        assert_eq!(ts.nextExpire, 0);
+        assert!(ts.GetFirst(i64::MAX).is_none());
+
+        // the timer not in the store is not removed
+        assert!(!ts.RemoveTimer(&Timer::Dummy()));
+    }
+}
@@ -31,6 +31,7 @@ use super::super::task::*;
 use super::super::kernel::timer::*;
 use super::super::kernel_util::*;
 use super::super::memmgr::*;
+use super::super::compat::*;
 //use super::super::memmgr::mm::*;
 use super::interpreter::*;
 
@@ -185,6 +186,44 @@ pub fn LoadExecutable(task: &mut Task, filename: &str, argv: &mut Vec<String>) -
     return Err(Error::SysError(SysErr::ENOEXEC));
 }
 
+// ExecutableHash returns the FNV-1a 64 hash of the executable content, the key of the compat profile.
+// The hash is cached by the inode and rehashed when the mtime or the size changes.
+pub fn ExecutableHash(task: &mut Task, executable: &Dirent) -> Result<u64> {
+    let inode = executable.Inode();
+    let sattr = inode.StableAttr();
+    let uattr = inode.UnstableAttr(task)?;
+    let mtime = uattr.ModificationTime.0;
+    if let Some(hash) = COMPAT_DB.CachedHash(sattr.DeviceId, sattr.InodeId, mtime, uattr.Size) {
+        return Ok(hash)
+    }
+
+    let file = inode.GetFile(task, executable, &FileFlags { Read: true, ..Default::default() })?;
+    let mut buf = Vec::with_capacity(MemoryDef::PAGE_SIZE as usize * 16);
+    buf.resize(MemoryDef::PAGE_SIZE as usize * 16, 0);
+
+    let mut hash = FNV_OFFSET;
+    let mut offset = 0;
+    loop {
+        let n = ReadAll(task, &file, &mut buf, offset)?;
+        if n == 0 {
+            break;
+        }
+
+        hash = FnvHash(hash, &buf[..n]);
+        offset += n as u64;
+    }
+
+    info!("executable {} hash is {:016x}", executable.MyFullName(), hash);
+    COMPAT_DB.CacheHash(ExecHash {
+        dev: sattr.DeviceId,
+        ino: sattr.InodeId,
+        mtime: mtime,
+        size: uattr.Size,
+        hash: hash,
+    });
+    return Ok(hash)
+}
+
 pub const DEFAULT_STACK_SOFT_LIMIT : u64 = 8 *1024 *1024;
 
 pub fn CreateStack(task: &Task) -> Result<Range> {
@@ -222,6 +261,13 @@ pub fn Load(task: &mut Task, filename: &str, argv: &mut Vec<String>, envv: &[Str
 
     task.mm.BrkSetup(e);
     task.mm.SetExecutable(&executable);
+    if !COMPAT_DB.IsEmpty() {
+        let id = ExecutableHash(task, &executable).map(|hash| COMPAT_DB.Lookup(hash)).unwrap_or(None);
+        if let Some(id) = id {
+            info!("exec {} with compat profile {}", filename, COMPAT_DB.Name(id));
+        }
+        task.mm.SetCompatProfile(id);
+    }
 
     let mut name = Base(&filename);
     if name.len() > TASK_COMM_LEN - 1 {
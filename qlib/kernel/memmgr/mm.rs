@@ -19,8 +19,6 @@ use alloc::string::String;
 use alloc::string::ToString;
 use x86_64::structures::paging::PageTableFlags;
 use alloc::vec::Vec;
-use core::sync::atomic::AtomicU64;
-use core::sync::atomic::Ordering;
 
 use crate::qlib::mutex::*;
 
@@ -33,7 +31,8 @@ use super::super::super::common::*;
 use super::super::super::linux_def::*;
 use super::super::super::range::*;
 use super::super::super::addr::*;
-use super::super::super::vcpu_mgr::CPULocal;
+use super::super::super::vcpu_mgr::*;
+use super::super::super::MAX_VCPU_COUNT;
 use super::super::stack::*;
 use super::super::super::auxv::*;
 use super::super::task::*;
@@ -104,6 +103,9 @@ pub struct MMMetadata {
     // is not nil, it holds a reference on the Dirent.
     pub executable: Option<Dirent>,
 
+    // compatProfile is the id of the compat profile of the executable in COMPAT_DB
+    pub compatProfile: Option<usize>,
+
     // dumpability describes if and how this MemoryManager may be dumped to
     // userspace.
     //
@@ -136,8 +138,8 @@ pub struct MemoryManagerInternal {
     pub inited: bool,
 
     // store whether the vcpu are working on the memory manager
-    pub vcpuMapping: AtomicU64,
-    pub tlbShootdownMask: AtomicU64,
+    pub vcpuMapping: AtomicVcpuMask,
+    pub tlbShootdownMask: AtomicVcpuMask,
 
     pub mappingLock: Arc<QMutex<()>>,
     pub mapping: QMutex<MMMapping>,
@@ -199,6 +201,7 @@ impl MemoryManager {
             maxPerms: AccessType::ReadWrite(),
             private: true,
             growsDown: false,
+            shadowStack: false,
             dontfork: false,
             mlockMode: MLockMode::MlockNone,
             kernel: true,
@@ -230,6 +233,7 @@ impl MemoryManager {
             envv: Range::default(),
             auxv: Vec::new(),
             executable: None,
+            compatProfile: None,
             dumpability: NOT_DUMPABLE,
         };
 
@@ -257,8 +261,8 @@ impl MemoryManager {
         let internal = MemoryManagerInternal {
             uid: NewUID(),
             inited: true,
-            vcpuMapping: AtomicU64::new(0),
-            tlbShootdownMask: AtomicU64::new(0),
+            vcpuMapping: AtomicVcpuMask::default(),
+            tlbShootdownMask: AtomicVcpuMask::default(),
             mappingLock: Arc::new(QMutex::new(())),
             mapping: QMutex::new(mapping),
             pagetable: QRwLock::new(pagetable),
@@ -279,42 +283,42 @@ impl MemoryManager {
     }
 
     pub fn MaskTlbShootdown(&self, vcpuId: u64) {
-        self.tlbShootdownMask.fetch_or(1 << vcpuId, Ordering::Release);
+        self.tlbShootdownMask.Set(vcpuId as usize);
     }
 
-    pub fn TlbShootdownMask(&self) -> u64 {
-        return self.tlbShootdownMask.load(Ordering::Acquire);
+    pub fn TlbShootdownMask(&self) -> VcpuMask {
+        return self.tlbShootdownMask.Load();
     }
 
     pub fn ClearTlbShootdownMask(&self) {
-        self.tlbShootdownMask.store(0, Ordering::Release);
+        self.tlbShootdownMask.ClearAll();
     }
 
     pub fn SetVcpu(&self, vcpu: usize) {
-        assert!(vcpu < 64);
-        self.vcpuMapping.fetch_or(1<<vcpu, Ordering::Release);
+        assert!(vcpu < MAX_VCPU_COUNT);
+        self.vcpuMapping.Set(vcpu);
     }
 
     pub fn TlbShootdown(&self) {
         if self.pagetable.read().pt.TlbShootdown() {
             let mask = self.GetVcpuMapping();
-            if mask > 0 {
+            if !mask.IsEmpty() {
                 //error!("TlbShootdownVcpuMask ... {:x}", mask);
 
                 self.ClearTlbShootdownMask();
                 // todo: wait for all the vcpu finishing the TLS shootdown?
                 // the current kvm_interrupt can't interrupt some vcpu, why?
-                HostSpace::TlbShootdown(mask) as u64;
-                //mask = HostSpace::TlbShootdown(mask) as u64;
+                HostSpace::TlbShootdown(mask);
+                //HostSpace::TlbShootdown(mask);
 
                 /*loop {
                     // wait all other vcpu finish tlb clear
-                    let waitMask = mask & !self.TlbShootdownMask();
-                    if waitMask == 0 {
+                    let waitMask = mask.AndNot(&self.TlbShootdownMask());
+                    if waitMask.IsEmpty() {
                         break;
                     }
 
-                    mask = HostSpace::TlbShootdown(waitMask) as u64;
+                    HostSpace::TlbShootdown(waitMask);
                     error!("wait for {:b}", mask);
                     //core::hint::spin_loop();
                 }*/
@@ -323,14 +327,15 @@ impl MemoryManager {
     }
 
     pub fn ClearVcpu(&self, vcpu: usize) {
-        assert!(vcpu < 64);
-        self.vcpuMapping.fetch_and(!(1<<vcpu), Ordering::Release);
+        assert!(vcpu < MAX_VCPU_COUNT);
+        self.vcpuMapping.Clear(vcpu);
     }
 
-    pub fn GetVcpuMapping(&self) -> u64 {
-        let mask = self.vcpuMapping.load(Ordering::Acquire);
-        let vcpu = GetVcpuId();
-        return mask & !(1<<vcpu);
+    // GetVcpuMapping returns the other vcpus working on the memory manager
+    pub fn GetVcpuMapping(&self) -> VcpuMask {
+        let mut mask = self.vcpuMapping.Load();
+        mask.Clear(GetVcpuId());
+        return mask
     }
 
     pub fn VcpuEnter(&self) {
@@ -419,7 +424,7 @@ impl MemoryManager {
 
     pub fn HandleTlbShootdown(&self) {
         let vcpId = CPULocal::CpuId() as u64;
-        if self.TlbShootdownMask() & (1<<vcpId) != 0 {
+        if self.tlbShootdownMask.IsSet(vcpId as usize) {
             let curr = super::super::super::super::asm::CurrentCr3();
             PageTables::Switch(curr);
             self.MaskTlbShootdown(vcpId);
@@ -603,6 +608,14 @@ impl MemoryManager {
         self.metadata.lock().executable = Some(dirent.clone());
     }
 
+    pub fn SetCompatProfile(&self, id: Option<usize>) {
+        self.metadata.lock().compatProfile = id;
+    }
+
+    pub fn CompatProfile(&self) -> Option<usize> {
+        return self.metadata.lock().compatProfile
+    }
+
     //remove all the user vmas, used for execve
     pub fn Clear(&self) -> Result<()> {
         let _ml = self.MappingWriteLock();
@@ -958,7 +971,9 @@ impl MemoryManager {
 
                 let phyAddr = super::super::PAGE_MGR.AllocPage(true).unwrap();
                 let writeable = vma.effectivePerms.Write();
-                if writeable {
+                if vma.shadowStack {
+                    self.MapPageShadowStackLocked(pageAddr, phyAddr);
+                } else if writeable {
                     self.MapPageWriteLocked(pageAddr, phyAddr, exec);
                 } else {
                     self.MapPageReadLocked(pageAddr, phyAddr, exec);
@@ -980,6 +995,11 @@ impl MemoryManager {
         pt.pt.MapPage(Addr(vAddr), Addr(pAddr), PageOpts::New(true, false, exec).Val(), &*PAGE_MGR).unwrap();
     }
 
+    pub fn MapPageShadowStackLocked(&self, vAddr: u64, pAddr: u64) {
+        let pt = self.pagetable.write();
+        pt.pt.MapPage(Addr(vAddr), Addr(pAddr), PageOpts::UserShadowStack().Val(), &*PAGE_MGR).unwrap();
+    }
+
     pub fn EnableWriteLocked(&self, addr: u64, exec: bool) {
         let pt = self.pagetable.write();
         pt.pt.SetPageFlags(Addr(addr), PageOpts::New(true, true, exec).Val());
@@ -1010,6 +1030,63 @@ impl MemoryManager {
         }
     }
 
+    // ShadowStackCopyOnWriteLocked is the copy on write of the shadow stack page, which is
+    // readonly and clean after fork or mprotect
+    pub fn ShadowStackCopyOnWriteLocked(&self, pageAddr: u64) {
+        let (phyAddr, dirty) = {
+            let pt = self.pagetable.read();
+            let entry = pt.pt.VirtualToEntry(pageAddr).expect(&format!("addr is {:x}", pageAddr));
+            (entry.addr().as_u64(), entry.flags().contains(PageTableFlags::DIRTY))
+        };
+
+        if dirty {
+            // another thread has cow, return
+            Invlpg(pageAddr);
+            return;
+        }
+
+        let refCount = super::super::PAGE_MGR.GetRef(phyAddr)
+            .expect(&format!("ShadowStackCopyOnWrite PAGE_MGR GetRef addr {:x} fail", phyAddr));
+
+        if refCount == 1 {
+            self.MapPageShadowStackLocked(pageAddr, phyAddr);
+        } else {
+            let page = { super::super::PAGE_MGR.AllocPage(true).unwrap() };
+            CopyPage(page, phyAddr);
+            self.MapPageShadowStackLocked(pageAddr, page);
+            super::super::PAGE_MGR.DerefPage(page);
+        }
+    }
+
+    // ShadowStackWrite writes the data to the shadow stack of the task, as the WRUSS instruction
+    pub fn ShadowStackWrite(&self, task: &Task, addr: u64, data: u64) -> Result<()> {
+        if addr & 0x7 != 0 {
+            return Err(Error::SysError(SysErr::EFAULT))
+        }
+
+        let _ml = self.MappingWriteLock();
+
+        let (vma, range) = match self.GetVmaAndRangeLocked(addr) {
+            None => return Err(Error::SysError(SysErr::EFAULT)),
+            Some(data) => data
+        };
+
+        if !vma.shadowStack {
+            return Err(Error::SysError(SysErr::EFAULT))
+        }
+
+        let pageAddr = Addr(addr).RoundDown()?.0;
+        self.InstallPageLocked(task, &vma, pageAddr, &range)?;
+        self.ShadowStackCopyOnWriteLocked(pageAddr);
+
+        let (phyAddr, _) = self.VirtualToPhyLocked(addr)?;
+        unsafe {
+            *(phyAddr as *mut u64) = data;
+        }
+
+        return Ok(())
+    }
+
     pub fn CopyOnWrite(&self, pageAddr: u64, vma: &VMA) {
         let _ml = self.MappingWriteLock();
 
@@ -1279,6 +1356,7 @@ impl MemoryManager {
             meta2.argv = meta1.argv;
             meta2.envv = meta1.envv;
             meta2.executable = meta1.executable.clone();
+            meta2.compatProfile = meta1.compatProfile;
 
             while srcvseg.Ok() {
                 let mut vma = srcvseg.Value();
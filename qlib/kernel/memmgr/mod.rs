@@ -132,6 +132,10 @@ pub struct MMapOpts {
     // downward on guard page faults.
     pub GrowsDown: bool,
 
+    // ShadowStack is true if the mapping is a CET shadow stack, its pages are mapped readonly
+    // and dirty so that only the shadow stack accesses can write them.
+    pub ShadowStack: bool,
+
     // Precommit is true if the platform should eagerly commit resources to the
     // mapping (see platform.AddressSpace.MapFile).
     pub Precommit: bool,
@@ -163,6 +167,7 @@ impl MMapOpts {
             Private: false,
             VDSO: false,
             GrowsDown: false,
+            ShadowStack: false,
             Precommit: false,
             MLockMode: MLockMode::default(),
             Kernel: false,
@@ -185,6 +190,7 @@ impl MMapOpts {
             Private: false,
             VDSO: false,
             GrowsDown: false,
+            ShadowStack: false,
             Precommit: false,
             MLockMode: MLockMode::default(),
             Kernel: false,
@@ -127,6 +127,7 @@ impl MemoryManager {
             Private: true,
             VDSO: false,
             GrowsDown: true,
+            ShadowStack: false,
             Precommit: false,
             MLockMode: MLockMode::default(),
             Kernel: false,
@@ -241,6 +242,7 @@ impl MemoryManager {
                 Private: vma.private,
                 VDSO: false,
                 GrowsDown: vma.growsDown,
+                ShadowStack: vma.shadowStack,
                 Precommit: false,
                 MLockMode: MLockMode::default(),
                 Kernel: false,
@@ -581,6 +583,7 @@ impl MemoryManager {
                 Private: true,
                 VDSO: false,
                 GrowsDown: false,
+                ShadowStack: false,
                 Precommit: false,
                 MLockMode: MLockMode::default(),
                 Kernel: false,
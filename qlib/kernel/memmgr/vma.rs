@@ -267,6 +267,7 @@ impl MemoryManager {
             maxPerms: opts.MaxPerms,
             private: opts.Private,
             growsDown: opts.GrowsDown,
+            shadowStack: opts.ShadowStack,
             dontfork: false,
             mlockMode: opts.MLockMode,
             kernel: opts.Kernel,
@@ -334,6 +335,9 @@ pub struct VMA {
     // metag, none of which we currently support.
     pub growsDown: bool,
 
+    // shadowStack is true if this is a CET shadow stack mapping.
+    pub shadowStack: bool,
+
     // dontfork is the MADV_DONTFORK setting for this vma configured by madvise().
     pub dontfork: bool,
 
@@ -359,6 +363,7 @@ impl fmt::Debug for VMA {
             .field("maxPerms", &self.maxPerms)
             .field("private", &self.private)
             .field("growsDown", &self.growsDown)
+            .field("shadowStack", &self.shadowStack)
             .field("kernel", &self.kernel)
             .field("hint", &self.hint)
             .finish()
@@ -376,6 +381,7 @@ impl VMA {
             maxPerms: self.maxPerms,
             private: self.private,
             growsDown: self.growsDown,
+            shadowStack: self.shadowStack,
             dontfork: self.dontfork,
             mlockMode: self.mlockMode,
             kernel: self.kernel,
@@ -488,6 +494,7 @@ impl AreaValue for VMA {
             vma1.effectivePerms != vma2.effectivePerms ||
             vma1.private != vma2.private ||
             vma1.growsDown != vma2.growsDown ||
+            vma1.shadowStack != vma2.shadowStack ||
             vma1.dontfork != vma2.dontfork ||
             vma1.mlockMode != vma2.mlockMode ||
             vma1.kernel != vma2.kernel ||
@@ -39,6 +39,11 @@ pub mod vcpu;
 pub mod version;
 pub mod loader;
 pub mod guestfdnotifier;
+pub mod profiler;
+pub mod compat;
+pub mod syscall_hooks;
+#[cfg(any(test, feature = "host-test"))]
+pub mod test_util;
 
 use core::sync::atomic::AtomicI32;
 use core::sync::atomic::AtomicI64;
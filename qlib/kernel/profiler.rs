@@ -0,0 +1,227 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Sampling profiler of the guest. The host interrupts the running vcpus every sample
+// interval (ShareSpace::profileInterval), the interrupt handler captures the kernel or user
+// stack of the interrupted context through the frame pointers. Every vcpu has its own sample
+// buffer, allocated when the profile starts, so no allocation is done in the interrupt and the
+// buffer lock is only shared with Start/Stop.
+
+use alloc::vec::Vec;
+use alloc::collections::btree_map::BTreeMap;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use super::super::mutex::*;
+use super::super::control_msg::*;
+use super::super::linux_def::*;
+use super::super::pagetable::*;
+use super::super::MAX_VCPU_COUNT;
+use super::SignalDef::PtRegs;
+use super::SHARESPACE;
+
+pub const PROFILE_MAX_DEPTH: usize = 32;
+pub const PROFILE_MAX_SAMPLES: usize = 8192;
+
+pub struct ProfileSample {
+    pub user: bool,
+    pub depth: usize,
+    pub frames: [u64; PROFILE_MAX_DEPTH],
+}
+
+const EMPTY_BUF: QMutex<Vec<ProfileSample>> = QMutex::new(Vec::new());
+
+pub struct Profiler {
+    pub enabled: AtomicBool,
+    // the samples dropped because the vcpu buffer is full
+    pub dropped: AtomicU64,
+    // the sample buffer of each vcpu
+    pub bufs: [QMutex<Vec<ProfileSample>>; MAX_VCPU_COUNT],
+}
+
+pub static PROFILER: Profiler = Profiler::New();
+
+impl Profiler {
+    pub const fn New() -> Self {
+        return Self {
+            enabled: AtomicBool::new(false),
+            dropped: AtomicU64::new(0),
+            bufs: [EMPTY_BUF; MAX_VCPU_COUNT],
+        }
+    }
+
+    // Start allocates the sample buffers and asks the host to sample every 1/hz second
+    pub fn Start(&self, hz: u64) {
+        let hz = if hz == 0 || hz > 10000 { 99 } else { hz };
+        let vcpuCnt = SHARESPACE.scheduler.vcpuCnt;
+
+        // The interrupt of this vcpu takes the buffer lock only when the profiler is enabled,
+        // so it is disabled before the buffers are replaced.
+        self.enabled.store(false, Ordering::SeqCst);
+        for i in 0..vcpuCnt {
+            let buf = Vec::with_capacity(PROFILE_MAX_SAMPLES);
+            *self.bufs[i].lock() = buf;
+        }
+
+        self.dropped.store(0, Ordering::SeqCst);
+        self.enabled.store(true, Ordering::SeqCst);
+        SHARESPACE.profileInterval.store(1_000_000 / hz, Ordering::SeqCst);
+    }
+
+    // Stop stops the sampling and returns the samples aggregated by stack
+    pub fn Stop(&self) -> ProfileResult {
+        SHARESPACE.profileInterval.store(0, Ordering::SeqCst);
+        self.enabled.store(false, Ordering::SeqCst);
+
+        let mut stacks: BTreeMap<(bool, Vec<u64>), u64> = BTreeMap::new();
+        for i in 0..SHARESPACE.scheduler.vcpuCnt {
+            let buf = core::mem::replace(&mut *self.bufs[i].lock(), Vec::new());
+            for sample in &buf {
+                let key = (sample.user, sample.frames[0..sample.depth].to_vec());
+                *stacks.entry(key).or_insert(0) += 1;
+            }
+        }
+
+        let mut ret = Vec::with_capacity(stacks.len());
+        for ((user, frames), count) in stacks {
+            ret.push(ProfileStack {
+                user: user,
+                frames: frames,
+                count: count,
+            })
+        }
+
+        return ProfileResult {
+            stacks: ret,
+            dropped: self.dropped.load(Ordering::SeqCst),
+        }
+    }
+
+    // Sample is called by the vcpu interrupt handler
+    pub fn Sample(&self, vcpuId: usize, ptRegs: &PtRegs) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return
+        }
+
+        // Start/Stop on another vcpu holds the lock only to swap the buffer. Start/Stop on this
+        // vcpu disables the profiler before the lock, so the lock is never held by the
+        // interrupted context.
+        let mut buf = match self.bufs.get(vcpuId) {
+            None => return,
+            Some(buf) => buf.lock(),
+        };
+
+        // the buffer was taken by Stop after the enabled check
+        if buf.capacity() < PROFILE_MAX_SAMPLES {
+            return
+        }
+
+        if buf.len() >= PROFILE_MAX_SAMPLES {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return
+        }
+
+        let mut sample = ProfileSample {
+            user: ptRegs.ss & 0x3 != 0,
+            depth: 1,
+            frames: [0; PROFILE_MAX_DEPTH],
+        };
+        sample.frames[0] = ptRegs.rip;
+
+        if sample.user {
+            sample.depth = WalkUserStack(ptRegs.rbp, &mut sample.frames[1..]) + 1;
+        } else {
+            sample.depth = WalkKernelStack(ptRegs.rbp, &mut sample.frames[1..]) + 1;
+        }
+
+        buf.push(sample);
+    }
+}
+
+// WalkKernelStack follows the frame pointer chain inside the kernel stack of rbp
+fn WalkKernelStack(mut rbp: u64, frames: &mut [u64]) -> usize {
+    let stackSize = MemoryDef::DEFAULT_STACK_SIZE;
+    let low = rbp & !(stackSize - 1);
+    let high = low + stackSize;
+
+    let mut depth = 0;
+    while depth < frames.len() {
+        if rbp < low || rbp + 16 > high || rbp & 0x7 != 0 {
+            break;
+        }
+
+        let (next, rip) = unsafe {
+            (*(rbp as * const u64), *((rbp + 8) as * const u64))
+        };
+
+        if rip == 0 {
+            break;
+        }
+
+        frames[depth] = rip;
+        depth += 1;
+
+        // the stack grows down, the caller frame is always at higher address
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+
+    return depth
+}
+
+// ReadUser reads the u64 at the user address through the page table of the vcpu, the user
+// memory is not accessed directly as the page could be not mapped. The page table pages and
+// the physical pages are all guest memory, so a concurrent munmap only gives a wrong frame.
+fn ReadUser(pt: &PageTables, addr: u64) -> Option<u64> {
+    if addr == 0 || addr + 8 > MemoryDef::LOWER_TOP || addr & 0x7 != 0 {
+        return None
+    }
+
+    match pt.VirtualToPhy(addr) {
+        Err(_) => return None,
+        Ok((phyAddr, _)) => return Some(unsafe { *(phyAddr as * const u64) }),
+    }
+}
+
+// WalkUserStack follows the frame pointer chain of the user stack, it stops at the first frame
+// which is not mapped. The binaries built without the frame pointer give the leaf only.
+fn WalkUserStack(mut rbp: u64, frames: &mut [u64]) -> usize {
+    let pt = PageTables::Init(PageTables::CurrentCr3());
+
+    let mut depth = 0;
+    while depth < frames.len() {
+        let next = match ReadUser(&pt, rbp) {
+            None => break,
+            Some(next) => next,
+        };
+
+        let rip = match ReadUser(&pt, rbp + 8) {
+            None | Some(0) => break,
+            Some(rip) => rip,
+        };
+
+        frames[depth] = rip;
+        depth += 1;
+
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+
+    return depth
+}
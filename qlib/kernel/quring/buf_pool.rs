@@ -0,0 +1,285 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering;
+
+use super::super::super::linux_def::*;
+use super::super::super::mutex::*;
+use super::super::super::uring::sys::sys::io_uring_buf;
+use super::super::super::uring::sys::sys::IORING_OP_PROVIDE_BUFFERS;
+use super::super::Kernel::HostSpace;
+use super::super::IOURING;
+use super::super::SHARESPACE;
+use super::uring_async::*;
+
+// The receive pool of the uring socket reads. Each uring gets UringRecvPoolBufs bufs of
+// RECV_POOL_BUF_SIZE in the buffer group RECV_POOL_BGID with IORING_OP_PROVIDE_BUFFERS, the
+// socket recv with IOSQE_BUFFER_SELECT takes one of them when the data arrives instead of
+// holding the free space of its read buf while it waits. The data stays in the buf till the app
+// reads it, the read buf of the socket is shrunk and only takes the data when the pool is used
+// up. The consumed buf is given back to its uring.
+//
+// When the host uring can register a provided buffer ring, the bufs are given back by adding
+// them to the ring of the uring, which the host kernel reads at the recv, instead of submitting
+// a provide_buffers request for each of them.
+
+pub const RECV_POOL_BGID: u16 = 1;
+pub const RECV_POOL_BUF_SIZE: usize = 16 * 1024;
+
+// the bids of all the urings are in one u16 space
+pub const RECV_POOL_MAX_BUFS: usize = 1 << 16;
+
+// the tail of the buffer ring is the resv of its first io_uring_buf
+const BUF_RING_TAIL_OFFSET: u64 = 14;
+
+static RECV_BUF_POOL: spin::Once<Option<RecvBufPool>> = spin::Once::new();
+
+pub struct RecvBufPool {
+    pub buf: DataBuff,
+    // the bufs of each uring, the bids of the uring i are i * bufCount..(i + 1) * bufCount
+    pub bufCount: usize,
+    // the buffer ring of each uring, None when the bufs are provided with the requests
+    pub rings: Vec<Option<BufRing>>,
+    // the bufs given back to each uring without a buffer ring. They are released under the
+    // lock of the async op slot or of the socket, the provide_buffers requests are submitted
+    // by ProvidePending after it.
+    pub pending: Vec<QMutex<Vec<u16>>>,
+}
+
+impl RecvBufPool {
+    // Init provides the bufs to the urings with the first uring socket. The pool is not used
+    // when it is not configured or the host uring has no buffer selection.
+    pub fn Init() -> Option<&'static Self> {
+        let pool = RECV_BUF_POOL.call_once(|| {
+            let bufCount = SHARESPACE.config.read().UringRecvPoolBufs;
+            let uringCount = IOURING.UringCount();
+            if bufCount == 0 || !IOURING.OpSupported(IORING_OP_PROVIDE_BUFFERS) {
+                return None
+            }
+
+            let useRing = SHARESPACE.config.read().UringRecvPoolRing;
+            let mut pool = Self {
+                buf: DataBuff::New(bufCount * uringCount * RECV_POOL_BUF_SIZE),
+                bufCount: bufCount,
+                rings: Vec::with_capacity(uringCount),
+                pending: Vec::with_capacity(uringCount),
+            };
+
+            for idx in 0..uringCount {
+                let ring = if useRing { BufRing::Register(idx, bufCount) } else { None };
+                pool.rings.push(ring);
+                pool.pending.push(QMutex::new(Vec::new()));
+
+                let bid = idx * bufCount;
+                match &pool.rings[idx] {
+                    Some(ring) => {
+                        for i in bid..bid + bufCount {
+                            ring.Add(pool.Addr(i as u16), i as u16);
+                        }
+                        ring.Publish();
+                    }
+                    None => {
+                        let op = AsyncProvideBuffers::New(pool.Addr(bid as u16), bufCount as u16, bid as u16);
+                        IOURING.AUCallOnUring(idx, AsyncOps::AsyncProvideBuffers(op));
+                    }
+                }
+            }
+
+            info!("uring recv pool: {} bufs of {} bytes for each of {} urings, {} buffer rings",
+                  bufCount, RECV_POOL_BUF_SIZE, uringCount, pool.rings.iter().filter(|r| r.is_some()).count());
+            Some(pool)
+        });
+
+        return pool.as_ref()
+    }
+
+    // Get returns the pool once it is provided to the urings
+    pub fn Get() -> Option<&'static Self> {
+        match RECV_BUF_POOL.get() {
+            Some(pool) => return pool.as_ref(),
+            None => return None,
+        }
+    }
+
+    pub fn Addr(&self, bid: u16) -> u64 {
+        return self.buf.Ptr() + (bid as usize * RECV_POOL_BUF_SIZE) as u64
+    }
+
+    // Provide gives the buf back to the uring it was provided to, the uring without a buffer ring
+    // gets it with the next ProvidePending
+    pub fn Provide(&self, bid: u16) {
+        let idx = BidUring(bid, self.bufCount);
+        if let Some(ring) = &self.rings[idx] {
+            ring.Add(self.Addr(bid), bid);
+            ring.Publish();
+            return
+        }
+
+        self.pending[idx].lock().push(bid);
+    }
+
+    // Release is the release of the pooled data of the socket buf
+    pub fn Release(bid: u16) {
+        if let Some(pool) = Self::Get() {
+            pool.Provide(bid);
+        }
+    }
+
+    // ProvidePending submits the provide_buffers requests of the bufs given back, the caller
+    // holds no async op slot lock. The consecutive bids go in one request.
+    pub fn ProvidePending() {
+        let pool = match Self::Get() {
+            None => return,
+            Some(pool) => pool,
+        };
+
+        for idx in 0..pool.pending.len() {
+            let mut bids = {
+                let mut pending = pool.pending[idx].lock();
+                if pending.len() == 0 {
+                    continue
+                }
+
+                core::mem::replace(&mut *pending, Vec::new())
+            };
+
+            for (bid, count) in BidRuns(&mut bids) {
+                let op = AsyncProvideBuffers::New(pool.Addr(bid), count, bid);
+                IOURING.AUCallOnUring(idx, AsyncOps::AsyncProvideBuffers(op));
+            }
+        }
+    }
+}
+
+// BidRuns sorts the bids and returns the runs of the consecutive ones as (first bid, count)
+pub fn BidRuns(bids: &mut Vec<u16>) -> Vec<(u16, u16)> {
+    bids.sort_unstable();
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for &bid in bids.iter() {
+        if let Some(last) = runs.last_mut() {
+            if last.0 as usize + last.1 as usize == bid as usize {
+                last.1 += 1;
+                continue
+            }
+        }
+
+        runs.push((bid, 1));
+    }
+
+    return runs
+}
+
+// BufRing is the provided buffer ring of a uring. The guest adds the bufs at the tail and the
+// host kernel takes them from the head at the recv, the ring holds all the bufs of the uring so
+// the tail never passes the head.
+pub struct BufRing {
+    pub mem: DataBuff,
+    // the page aligned start of the ring in mem
+    pub addr: u64,
+    pub entries: u16,
+    // the tail of the added bufs, they are visible to the host after Publish
+    pub tail: QMutex<u16>,
+}
+
+impl BufRing {
+    // Register sets up the ring of the uring idx, None when the host can't register it
+    pub fn Register(idx: usize, bufCount: usize) -> Option<Self> {
+        let entries = BufRingEntries(bufCount);
+        let size = entries * core::mem::size_of::<io_uring_buf>();
+        let mut mem = DataBuff::New(size + MemoryDef::PAGE_SIZE as usize);
+        mem.Zero();
+        let addr = (mem.Ptr() + MemoryDef::PAGE_SIZE - 1) & !(MemoryDef::PAGE_SIZE - 1);
+
+        let ret = HostSpace::IoUringRegisterBufRing(idx, addr, entries as u32, RECV_POOL_BGID);
+        if ret < 0 {
+            info!("uring {} recv pool: buffer ring is not registered {}, the bufs are provided with the requests", idx, ret);
+            return None
+        }
+
+        return Some(Self {
+            mem: mem,
+            addr: addr,
+            entries: entries as u16,
+            tail: QMutex::new(0),
+        })
+    }
+
+    // Add writes the buf at the tail, the kernel doesn't see it before Publish
+    pub fn Add(&self, addr: u64, bid: u16) {
+        let mut tail = self.tail.lock();
+        let idx = (*tail & (self.entries - 1)) as u64;
+        let entry = (self.addr + idx * core::mem::size_of::<io_uring_buf>() as u64) as * mut io_uring_buf;
+
+        // the resv of the first entry is the tail, it is left to Publish
+        unsafe {
+            (*entry).addr = addr;
+            (*entry).len = RECV_POOL_BUF_SIZE as u32;
+            (*entry).bid = bid;
+        }
+
+        *tail = tail.wrapping_add(1);
+    }
+
+    // Publish makes the added bufs visible to the host kernel
+    pub fn Publish(&self) {
+        let tail = self.tail.lock();
+        let shared = unsafe {
+            &*((self.addr + BUF_RING_TAIL_OFFSET) as * const AtomicU16)
+        };
+
+        shared.store(*tail, Ordering::Release);
+    }
+}
+
+// BufRingEntries returns the size of the ring holding bufCount bufs, it is a power of 2
+pub fn BufRingEntries(bufCount: usize) -> usize {
+    return bufCount.next_power_of_two()
+}
+
+// BidUring returns the uring of the buf, the recv may complete on another uring than the
+// sharded one when the submission queue of the latter is full
+pub fn BidUring(bid: u16, bufCount: usize) -> usize {
+    return bid as usize / bufCount
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bid_uring() {
+        assert_eq!(BidUring(0, 1024), 0);
+        assert_eq!(BidUring(1023, 1024), 0);
+        assert_eq!(BidUring(1024, 1024), 1);
+        assert_eq!(BidUring(u16::MAX, RECV_POOL_MAX_BUFS / 8), 7);
+    }
+
+    #[test]
+    fn test_buf_ring_entries() {
+        assert_eq!(core::mem::size_of::<io_uring_buf>(), 16);
+        assert_eq!(BufRingEntries(1), 1);
+        assert_eq!(BufRingEntries(1000), 1024);
+        assert_eq!(BufRingEntries(1024), 1024);
+        assert_eq!(BufRingEntries(RECV_POOL_MAX_BUFS / 8), 8192);
+    }
+
+    #[test]
+    fn test_bid_runs() {
+        assert_eq!(BidRuns(&mut Vec::new()), Vec::new());
+        assert_eq!(BidRuns(&mut vec![7, 3, 5, 4, 8, 10]), vec![(3, 3), (7, 2), (10, 1)]);
+        assert_eq!(BidRuns(&mut vec![u16::MAX, u16::MAX - 1]), vec![(u16::MAX - 1, 2)]);
+    }
+}
@@ -15,5 +15,7 @@
 pub mod uring_mgr;
 pub mod uring_op;
 pub mod uring_async;
+pub mod uring_sched;
+pub mod buf_pool;
 
 pub use uring_mgr::*;
\ No newline at end of file
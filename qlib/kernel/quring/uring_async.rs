@@ -14,6 +14,7 @@
 
 use alloc::vec::Vec;
 use alloc::sync::Arc;
+use alloc::boxed::Box;
 use alloc::collections::vec_deque::VecDeque;
 use core::marker::Send;
 use crate::qlib::mutex::*;
@@ -27,6 +28,7 @@ use super::super::super::uring::squeue;
 use super::super::super::uring::opcode::*;
 use super::super::super::uring::opcode;
 use super::super::super::socket_buf::*;
+use super::super::super::dgram_buf::*;
 use super::super::kernel::waiter::*;
 use super::super::socket::hostinet::socket::*;
 use super::super::fs::file::*;
@@ -34,10 +36,16 @@ use super::super::task::*;
 use super::super::kernel::aio::aio_context::*;
 use super::super::kernel::eventfd::*;
 use super::super::IOURING;
+use super::super::Kernel::HostSpace;
 use super::super::kernel::timer;
 use super::super::kernel::async_wait::*;
 use super::super::SHARESPACE;
 use super::super::kernel::waiter::qlock::*;
+use super::uring_sched::*;
+use super::buf_pool::*;
+use super::super::super::uring::sys::sys::IORING_CQE_F_BUFFER;
+use super::super::super::uring::sys::sys::IORING_CQE_BUFFER_SHIFT;
+use super::super::fs::host::writeback::*;
 //use super::super::guestfdnotifier::GUEST_NOTIFIER;
 
 #[repr(align(128))]
@@ -64,6 +72,10 @@ pub enum AsyncOps {
     AsyncEpollCtl(AsyncEpollCtl),
     AsyncSend(AsyncSend),
     PollHostEpollWait(PollHostEpollWait),
+    AsyncDgramRecv(AsyncDgramRecv),
+    AsyncDgramSend(AsyncDgramSend),
+    AsyncIdleCancel(AsyncIdleCancel),
+    AsyncProvideBuffers(AsyncProvideBuffers),
     None,
 }
 
@@ -92,13 +104,17 @@ impl AsyncOps {
             AsyncOps::AsyncEpollCtl(ref msg) => return msg.SEntry(),
             AsyncOps::AsyncSend(ref msg) => return msg.SEntry(),
             AsyncOps::PollHostEpollWait(ref msg) => return msg.SEntry(),
+            AsyncOps::AsyncDgramRecv(ref msg) => return msg.SEntry(),
+            AsyncOps::AsyncDgramSend(ref msg) => return msg.SEntry(),
+            AsyncOps::AsyncIdleCancel(ref msg) => return msg.SEntry(),
+            AsyncOps::AsyncProvideBuffers(ref msg) => return msg.SEntry(),
             AsyncOps::None => ()
         };
 
         panic!("AsyncOps::None SEntry fail")
     }
 
-    pub fn Process(&mut self, result: i32, id: usize) -> bool {
+    pub fn Process(&mut self, result: i32, flags: u32, id: usize) -> bool {
         let ret = match self {
             AsyncOps::AsyncTimeout(ref mut msg) => msg.Process(result),
             AsyncOps::AsyncTimerRemove(ref mut msg) => msg.Process(result),
@@ -108,7 +124,7 @@ impl AsyncOps {
             AsyncOps::AsycnSendMsg(ref mut msg) => msg.Process(result),
             AsyncOps::AsycnRecvMsg(ref mut msg) => msg.Process(result),
             AsyncOps::AsyncFiletWrite(ref mut msg) => msg.Process(result),
-            AsyncOps::AsyncFileRead(ref mut msg) => msg.Process(result),
+            AsyncOps::AsyncFileRead(ref mut msg) => msg.Process(result, flags),
             AsyncOps::AIOWrite(ref mut msg) => msg.Process(result),
             AsyncOps::AIORead(ref mut msg) => msg.Process(result),
             AsyncOps::AIOFsync(ref mut msg) => msg.Process(result),
@@ -122,6 +138,10 @@ impl AsyncOps {
             AsyncOps::AsyncEpollCtl(ref mut msg) => msg.Process(result),
             AsyncOps::AsyncSend(ref mut msg) => msg.Process(result),
             AsyncOps::PollHostEpollWait(ref mut msg) => msg.Process(result),
+            AsyncOps::AsyncDgramRecv(ref mut msg) => msg.Process(result),
+            AsyncOps::AsyncDgramSend(ref mut msg) => msg.Process(result),
+            AsyncOps::AsyncIdleCancel(ref mut msg) => msg.Process(result),
+            AsyncOps::AsyncProvideBuffers(ref mut msg) => msg.Process(result),
             AsyncOps::None => {
                 //panic!("AsyncOps::None SEntry fail")
                 panic!("AsyncOps::None SEntry fail result {} id {}", result, id);
@@ -160,11 +180,45 @@ impl AsyncOps {
             AsyncOps::AsyncEpollCtl(_) => return 20,
             AsyncOps::AsyncSend(_) => return 21,
             AsyncOps::PollHostEpollWait(_) => return 22,
+            AsyncOps::AsyncDgramRecv(_) => return 23,
+            AsyncOps::AsyncDgramSend(_) => return 24,
+            AsyncOps::AsyncIdleCancel(_) => return 25,
+            AsyncOps::AsyncProvideBuffers(_) => return 26,
             AsyncOps::None => ()
         };
 
         return 0;
     }
+
+    // Class is the submission priority of the op, the socket and user syscall ops are foreground
+    // and the tty reads interactive
+    pub fn Class(&self) -> UringClass {
+        match self {
+            AsyncOps::AsyncBufWrite(_) => return UringClass::Background,
+            AsyncOps::AsyncLogFlush(_) => return UringClass::Background,
+            // a user waits for the tty input
+            AsyncOps::AsyncFileRead(ref msg) if !msg.isSocket => return UringClass::Interactive,
+            _ => return UringClass::Foreground,
+        }
+    }
+
+    // Busy returns whether the op completes without an event from outside of the sandbox, e.g. a
+    // file write. The reads of the sockets and the ttys, the accepts and the timers wait for one.
+    pub fn Busy(&self) -> bool {
+        match self {
+            AsyncOps::AsyncTimeout(_) => return false,
+            AsyncOps::AsycnRecvMsg(_) => return false,
+            AsyncOps::AsyncFileRead(_) => return false,
+            AsyncOps::AsyncRawTimeout(_) => return false,
+            AsyncOps::AsyncLinkTimeout(_) => return false,
+            AsyncOps::UnblockBlockPollAdd(_) => return false,
+            AsyncOps::AsyncAccept(_) => return false,
+            AsyncOps::PollHostEpollWait(_) => return false,
+            AsyncOps::AsyncDgramRecv(_) => return false,
+            AsyncOps::None => return false,
+            _ => return true,
+        }
+    }
 }
 
 #[derive(Default)]
@@ -210,6 +264,15 @@ impl UringAsyncMgr {
         self.ids.lock().push_back(id as u16);
     }
 
+    // Busy returns whether any op in flight is busy
+    pub fn Busy(&self) -> bool {
+        if self.ids.lock().len() == self.ops.len() {
+            return false
+        }
+
+        return self.ops.iter().any(|op| op.lock().Busy())
+    }
+
     pub fn SetOps(&self, id : usize, ops: AsyncOps) -> squeue::Entry {
         *self.ops[id].lock() = ops;
         return self.ops[id]
@@ -444,30 +507,60 @@ pub struct AsyncBufWrite {
     pub fd: i32,
     pub buf: DataBuff,
     pub offset: i64,
+    // bytes written by the previous rounds of the short write
+    pub done: usize,
     pub lockGuard: QAsyncLockGuard,
+    pub writeback: Arc<WritebackState>,
 }
 
 impl AsyncBufWrite {
     pub fn SEntry(&self) -> squeue::Entry {
         //let op = Write::new(types::Fd(self.fd), self.addr as * const u8, self.len as u32);
-        let op = opcode::Write::new(types::Fd(self.fd), self.buf.Ptr() as * const u8, self.buf.Len() as u32)
-            .offset(self.offset);
+        // offset -1 is the current file offset
+        let offset = if self.offset < 0 {
+            self.offset
+        } else {
+            self.offset + self.done as i64
+        };
+
+        let op = opcode::Write::new(types::Fd(self.fd), (self.buf.Ptr() + self.done as u64) as * const u8, (self.buf.Len() - self.done) as u32)
+            .offset(offset);
 
         return op.build()
             .flags(squeue::Flags::FIXED_FILE);
     }
 
     pub fn Process(&mut self, result: i32) -> bool {
-        assert!(result as usize == self.buf.Len());
+        // the write returned to the guest already, the error is reported by the next fsync
+        if result <= 0 {
+            let errno = if result == 0 {
+                SysErr::EIO
+            } else {
+                -result
+            };
+
+            error!("AsyncBufWrite fd {} offset {} fail with {}", self.fd, self.offset, errno);
+            self.writeback.SetError(errno);
+            return false
+        }
+
+        self.done += result as usize;
+        if self.done < self.buf.Len() {
+            return true
+        }
+
+        self.writeback.Dirty();
         return false
     }
 
-    pub fn New(fd: i32, buf: DataBuff, offset: i64, lockGuard: QAsyncLockGuard) -> Self {
+    pub fn New(fd: i32, buf: DataBuff, offset: i64, lockGuard: QAsyncLockGuard, writeback: Arc<WritebackState>) -> Self {
         return Self {
             fd,
             buf,
             offset,
-            lockGuard
+            done: 0,
+            lockGuard,
+            writeback,
         }
     }
 }
@@ -488,11 +581,15 @@ impl AsyncLogFlush {
     }
 
     pub fn Process(&mut self, result: i32) -> bool {
-       if result <= 0 {
-            panic!("AsyncLogFlush fail {}/{}", result, self.fd)
-        }
+        // the failed flush can't be logged, the pending log is dropped so that the log buffer
+        // doesn't get full
+        let cnt = if result <= 0 {
+            self.len
+        } else {
+            result as usize
+        };
 
-        let (addr, len) = SHARESPACE.ConsumeAndGetAvailableWriteBuf(result as usize);
+        let (addr, len) = SHARESPACE.ConsumeAndGetAvailableWriteBuf(cnt);
 
         if addr == 0 {
             return false;
@@ -522,17 +619,26 @@ pub struct AsyncSend {
 
     // keep the socket in the async ops to avoid socket before send finish
     pub ops: SocketOperations,
+    // the send is linked to the file read of sendfile
+    pub linked: bool,
 }
 
 impl AsyncSend {
     pub fn SEntry(&self) -> squeue::Entry {
         //let op = Write::new(types::Fd(self.fd), self.addr as * const u8, self.len as u32);
-        let op = opcode::Send::new(types::Fd(self.fd), self.addr as * const u8, self.len as u32);
+        let op = opcode::Send::new(types::Fd(self.fd), self.addr as * const u8, self.len as u32)
+            .flags(MsgType::MSG_NOSIGNAL);
         return op.build()
             .flags(squeue::Flags::FIXED_FILE);
     }
 
     pub fn Process(&mut self, result: i32) -> bool {
+        // the short file read cancels the linked send, sendfile sends the data read
+        if result == -SysErr::ECANCELED && self.linked {
+            return false;
+        }
+        self.linked = false;
+
         if result < 0 {
             self.buf.SetErr(-result);
             self.queue.Notify(EventMaskFromLinux((EVENT_ERR | EVENT_IN) as u32));
@@ -540,15 +646,11 @@ impl AsyncSend {
             //return true;
         }
 
-        // EOF
-        // to debug
+        // the host socket takes no data, the write side is treated as shut down and the
+        // writers get EPIPE
         if result == 0 {
             self.buf.SetWClosed();
-            if self.buf.ProduceReadBuf(0) {
-                self.queue.Notify(EventMaskFromLinux(EVENT_OUT as u32));
-            } else {
-                self.queue.Notify(EventMaskFromLinux(EVENT_HUP as u32));
-            }
+            self.queue.Notify(EventMaskFromLinux(self.buf.Events() as u32));
             return false
         }
 
@@ -558,6 +660,11 @@ impl AsyncSend {
         }
 
         if addr == 0 {
+            // the SHUT_WR waiting for the data sends the FIN now
+            if self.buf.TakeFinPending() {
+                HostSpace::ShutdownAsync(self.fd, LibcConst::SHUT_WR as i32);
+            }
+
             if self.buf.PendingWriteShutdown() {
                 self.queue.Notify(EVENT_PENDING_SHUTDOWN);
             }
@@ -565,6 +672,12 @@ impl AsyncSend {
             return false;
         }
 
+        // socket is closed with zero linger timeout, drop the unsent data.
+        // the ops will be dropped and the host socket is reset by host close.
+        if self.buf.WriteAbort() {
+            return false;
+        }
+
         self.addr = addr;
         self.len = len;
 
@@ -578,9 +691,16 @@ impl AsyncSend {
             buf,
             addr,
             len,
-            ops: ops.clone()
+            ops: ops.clone(),
+            linked: false,
         }
     }
+
+    pub fn NewLinked(fd: i32, queue: Queue, buf: Arc<SocketBuff>, addr: u64, len: usize, ops: &SocketOperations) -> Self {
+        let mut send = Self::New(fd, queue, buf, addr, len, ops);
+        send.linked = true;
+        return send
+    }
 }
 
 pub struct AsyncFiletWrite {
@@ -669,6 +789,16 @@ impl AsyncAccept {
     }
 
     pub fn Process(&mut self, result: i32) -> bool {
+        if result < 0 && IsTransientAcceptErr(-result) {
+            let (trigger, hasSpace) = self.acceptQueue.lock().EnqError(-result);
+            if trigger {
+                self.queue.Notify(EventMaskFromLinux(EVENT_IN as u32));
+            }
+            self.len = 16;
+
+            return hasSpace;
+        }
+
         if result < 0 {
             self.acceptQueue.lock().SetErr(-result);
             self.queue.Notify(EventMaskFromLinux((EVENT_ERR | EVENT_IN) as u32));
@@ -676,11 +806,16 @@ impl AsyncAccept {
         }
 
         NewSocket(result);
-        let sockBuf = Arc::new(SocketBuff::default());
-        let (trigger, hasSpace) = self.acceptQueue.lock().EnqSocket(result, self.addr, self.len, sockBuf);
+        let sockBuf = Arc::new(self.acceptQueue.lock().NewSocketBuff());
+        let peerInfo = FetchAcceptPeerInfo(result);
+        let (trigger, hasSpace) = self.acceptQueue.lock().EnqSocket(result, self.addr, self.len, sockBuf, peerInfo);
         if trigger {
             self.queue.Notify(EventMaskFromLinux(EVENT_IN as u32));
         }
+
+        if !hasSpace {
+            SHARESPACE.sockStats.AcceptOverflow();
+        }
         self.len = 16;
 
         return hasSpace;
@@ -704,10 +839,20 @@ pub struct AsyncFileRead {
     pub addr: u64,
     pub len: usize,
     pub isSocket: bool,
+    // the socket recv takes a buf of the receive pool at the completion
+    pub pooled: bool,
 }
 
 impl AsyncFileRead {
     pub fn SEntry(&self) -> squeue::Entry {
+        if self.pooled {
+            let len = self.buf.PooledRecvLen(RECV_POOL_BUF_SIZE);
+            let op = Recv::new(types::Fd(self.fd), core::ptr::null_mut(), len as u32)
+                .buf_group(RECV_POOL_BGID);
+            return op.build()
+                .flags(squeue::Flags::FIXED_FILE | squeue::Flags::BUFFER_SELECT);
+        }
+
         if self.isSocket {
             let op = Recv::new(types::Fd(self.fd), self.addr as * mut u8, self.len as u32);
             return op.build()
@@ -719,24 +864,73 @@ impl AsyncFileRead {
             .flags(squeue::Flags::FIXED_FILE);
     }
 
-    pub fn Process(&mut self, result: i32) -> bool {
+    pub fn Process(&mut self, result: i32, flags: u32) -> bool {
+        // the reads go to the host socket for kTLS RX
+        if self.isSocket && self.buf.HostRead() {
+            return self.Stop(result, flags);
+        }
+
+        if self.pooled {
+            // the pool is used up, the read goes to the read buf grown back to its size
+            if result == -SysErr::ENOBUFS {
+                self.buf.PoolRegrowRead();
+                let (addr, len) = self.buf.GetFreeReadBuf();
+                self.addr = addr;
+                self.len = len;
+                self.pooled = false;
+                return true;
+            }
+
+            if flags & IORING_CQE_F_BUFFER != 0 {
+                let bid = (flags >> IORING_CQE_BUFFER_SHIFT) as u16;
+                let pool = RecvBufPool::Get().expect("AsyncFileRead: no recv pool");
+                if result <= 0 {
+                    pool.Provide(bid);
+                } else {
+                    return self.ProcessPooled(PooledChunk {
+                        bid: bid,
+                        addr: pool.Addr(bid),
+                        len: result as usize,
+                    });
+                }
+            }
+        }
+
+        // the read of the idle socket is canceled to shrink the read buf, it restarts on the
+        // shrunk one
+        if result == -SysErr::ECANCELED && self.isSocket {
+            if let Some((addr, len)) = self.buf.IdleShrinkRead() {
+                self.addr = addr;
+                self.len = len;
+                return true;
+            }
+        }
+
+        if self.isSocket {
+            self.buf.CancelIdleShrink();
+        }
+
         if result < 0 {
             self.buf.SetErr(-result);
             self.queue.Notify(EventMaskFromLinux((EVENT_ERR | EVENT_IN) as u32));
             return false;
         }
 
-        // EOF
+        // EOF, the readers get the data left and then EOF
         if result == 0 {
             self.buf.SetRClosed();
-            if self.buf.HasReadData() {
-                self.queue.Notify(EventMaskFromLinux(EVENT_IN as u32));
-            } else {
-                self.queue.Notify(EventMaskFromLinux(EVENT_HUP as u32)) ;
-            }
+            self.queue.Notify(EventMaskFromLinux(self.buf.ReadClosedEvents() as u32));
             return false
         }
 
+        // the uring completion time is used as the software receive timestamp
+        if self.isSocket {
+            if self.buf.RxStampWanted() {
+                self.buf.SetRxTimestamp(timer::RealNow());
+            }
+            self.buf.Touch();
+        }
+
         let (trigger, addr, len) = self.buf.ProduceAndGetFreeReadBuf(result as usize);
         if trigger {
             self.queue.Notify(EventMaskFromLinux(EVENT_IN as u32));
@@ -748,18 +942,73 @@ impl AsyncFileRead {
 
         self.addr = addr;
         self.len = len;
+        self.pooled = self.Pooled();
         return true;
     }
 
+    // Stop keeps the data of the last read and stops the read, the error and EOF are left to the
+    // host reads
+    fn Stop(&mut self, result: i32, flags: u32) -> bool {
+        if self.isSocket && result > 0 && self.buf.RxStampWanted() {
+            self.buf.SetRxTimestamp(timer::RealNow());
+        }
+
+        if self.pooled && flags & IORING_CQE_F_BUFFER != 0 {
+            let bid = (flags >> IORING_CQE_BUFFER_SHIFT) as u16;
+            let pool = RecvBufPool::Get().expect("AsyncFileRead: no recv pool");
+            if result <= 0 {
+                pool.Provide(bid);
+            } else {
+                self.buf.ProducePooled(PooledChunk {
+                    bid: bid,
+                    addr: pool.Addr(bid),
+                    len: result as usize,
+                }, RecvBufPool::Release);
+            }
+        } else if !self.pooled && result > 0 {
+            self.buf.ProduceReadBuf(result as usize);
+        }
+
+        self.buf.SetReadStopped();
+        self.queue.Notify(EventMaskFromLinux(EVENT_IN as u32));
+        return false;
+    }
+
+    // ProcessPooled queues the data left in the receive pool buf for the readers
+    fn ProcessPooled(&mut self, chunk: PooledChunk) -> bool {
+        self.buf.CancelIdleShrink();
+        if self.buf.RxStampWanted() {
+            self.buf.SetRxTimestamp(timer::RealNow());
+        }
+        self.buf.Touch();
+
+        let (trigger, more) = self.buf.ProducePooled(chunk, RecvBufPool::Release);
+        if trigger {
+            self.queue.Notify(EventMaskFromLinux(EVENT_IN as u32));
+        }
+
+        return more;
+    }
+
+    // Pooled returns whether the next socket read takes a receive pool buf, the data can't go to
+    // the pool when the read buf has data
+    fn Pooled(&self) -> bool {
+        return self.isSocket && RecvBufPool::Get().is_some() && self.buf.PooledRecvLen(1) > 0
+    }
+
     pub fn New(fd: i32, queue: Queue, buf: Arc<SocketBuff>, addr: u64, len: usize, isSocket: bool) -> Self {
-        return Self {
+        let mut op = Self {
             fd,
             queue,
             buf,
             addr,
             len,
-            isSocket
-        }
+            isSocket,
+            pooled: false,
+        };
+
+        op.pooled = op.Pooled();
+        return op
     }
 }
 
@@ -783,15 +1032,24 @@ impl Deref for AsycnSendMsg {
 impl AsycnSendMsg {
     pub fn SEntry(&self) -> squeue::Entry {
         let intern = self.lock();
-        let op = SendMsg::new(types::Fd(intern.fd), &intern.msg as * const _ as * const u64);
+        let op = SendMsg::new(types::Fd(intern.fd), &intern.msg as * const _ as * const u64)
+            .flags(MsgType::MSG_NOSIGNAL as u32);
 
         return op.build()
             .flags(squeue::Flags::FIXED_FILE);
     }
 
     pub fn Process(&mut self, result: i32) -> bool {
-        let intern = self.lock();
-        let buf = intern.ops.SocketBuf();
+        let mut intern = self.lock();
+        let buf = match intern.ops.SocketBuf() {
+            Ok(buf) => buf,
+            Err(_) => {
+                // the waiters of the socket get the error instead of waiting for the op
+                intern.ops.Notify(EVENT_ERR | EVENT_OUT);
+                return false
+            }
+        };
+
         if result < 0 {
             buf.SetErr(-result);
             intern.ops.Notify(EVENT_ERR | EVENT_IN);
@@ -812,13 +1070,13 @@ impl AsycnSendMsg {
             intern.ops.Notify(EVENT_OUT);
         }
 
-        let (addr, cnt) = intern.ops.SocketBuf().GetAvailableWriteIovs();
+        let (addr, cnt) = buf.GetAvailableWriteIovs();
         if cnt == 0 {
             return false;
         }
 
         //let sendMsgOp = AsycnSendMsg::New(intern.fd, &intern.ops);
-        self.lock().SetIovs(addr, cnt);
+        intern.SetIovs(addr, cnt);
 
         return true
     }
@@ -874,8 +1132,16 @@ impl AsycnRecvMsg {
     }
 
     pub fn Process(&mut self, result: i32) -> bool {
-        let intern = self.lock();
-        let buf = intern.ops.SocketBuf();
+        let mut intern = self.lock();
+        let buf = match intern.ops.SocketBuf() {
+            Ok(buf) => buf,
+            Err(_) => {
+                // the waiters of the socket get the error instead of waiting for the op
+                intern.ops.Notify(EVENT_ERR | EVENT_IN);
+                return false
+            }
+        };
+
         if result < 0 {
             buf.SetErr(-result);
             intern.ops.Notify(EVENT_ERR | EVENT_IN);
@@ -885,19 +1151,20 @@ impl AsycnRecvMsg {
         // EOF
         if result == 0 {
             buf.SetRClosed();
-            if buf.ProduceReadBuf(0) {
-                intern.ops.Notify(EVENT_IN);
-            }
+            intern.ops.Notify(buf.ReadClosedEvents());
             return false
         }
 
+        if buf.RxStampWanted() {
+            buf.SetRxTimestamp(timer::RealNow());
+        }
         if buf.ProduceReadBuf(result as usize) {
             intern.ops.Notify(EVENT_IN);
         }
 
         //let recvMsgOp = AsycnRecvMsg::New(intern.fd, &intern.ops);
-        let (addr, cnt) = intern.ops.SocketBuf().GetFreeReadIovs();
-        self.lock().SetIovs(addr, cnt);
+        let (addr, cnt) = buf.GetFreeReadIovs();
+        intern.SetIovs(addr, cnt);
 
         return true
     }
@@ -930,6 +1197,236 @@ impl AsycnRecvMsgIntern {
     }
 }
 
+// DgramMsg is the msghdr of the uring recvmsg/sendmsg pointing to the buffers of the datagram,
+// it is boxed so that the host sees the same address after the op is moved
+pub struct DgramMsg {
+    pub hdr: MsgHdr,
+    pub iov: IoVec,
+    pub dgram: Datagram,
+}
+
+impl DgramMsg {
+    pub fn New(dgram: Datagram) -> Box<Self> {
+        let mut msg = Box::new(Self {
+            hdr: MsgHdr::default(),
+            iov: IoVec::default(),
+            dgram: dgram,
+        });
+
+        msg.Reset();
+        return msg
+    }
+
+    pub fn NewRecv() -> Box<Self> {
+        let dgram = Datagram {
+            addr: vec![0; SIZEOF_SOCKADDR],
+            data: vec![0; DGRAM_MAX_SIZE],
+            control: vec![0; DGRAM_CONTROL_SIZE],
+        };
+
+        return Self::New(dgram)
+    }
+
+    // Reset sets the msghdr again, the recvmsg updates the name and control lengths
+    pub fn Reset(&mut self) {
+        let dgram = &mut self.dgram;
+        self.iov = IoVec {
+            start: dgram.data.as_mut_ptr() as u64,
+            len: dgram.data.len(),
+        };
+
+        self.hdr = MsgHdr::default();
+        self.hdr.iov = &self.iov as *const _ as u64;
+        self.hdr.iovLen = 1;
+        if dgram.addr.len() > 0 {
+            self.hdr.msgName = dgram.addr.as_mut_ptr() as u64;
+            self.hdr.nameLen = dgram.addr.len() as u32;
+        }
+
+        if dgram.control.len() > 0 {
+            self.hdr.msgControl = dgram.control.as_mut_ptr() as u64;
+            self.hdr.msgControlLen = dgram.control.len();
+        }
+    }
+
+    // Received copies out the datagram of len bytes got by the recvmsg
+    pub fn Received(&self, len: usize) -> Datagram {
+        let dgram = &self.dgram;
+        let nameLen = core::cmp::min(self.hdr.nameLen as usize, dgram.addr.len());
+        let controlLen = core::cmp::min(self.hdr.msgControlLen, dgram.control.len());
+        return Datagram {
+            addr: dgram.addr[..nameLen].to_vec(),
+            data: dgram.data[..len].to_vec(),
+            control: dgram.control[..controlLen].to_vec(),
+        }
+    }
+}
+
+pub struct AsyncDgramRecv {
+    pub fd: i32,
+    pub queue: Queue,
+    pub buf: Arc<DgramBuff>,
+    pub msg: Box<DgramMsg>,
+}
+
+impl AsyncDgramRecv {
+    pub fn SEntry(&self) -> squeue::Entry {
+        let op = RecvMsg::new(types::Fd(self.fd), &self.msg.hdr as *const _ as *const u64);
+
+        return op.build()
+            .flags(squeue::Flags::FIXED_FILE);
+    }
+
+    pub fn Process(&mut self, result: i32) -> bool {
+        if result < 0 {
+            self.buf.RecvFail(-result);
+            self.queue.Notify(EventMaskFromLinux((EVENT_ERR | EVENT_IN) as u32));
+            return false;
+        }
+
+        // the zero length datagram is not EOF
+        let dgram = self.msg.Received(result as usize);
+        let (trigger, rearm) = self.buf.Produce(dgram);
+        if trigger {
+            self.queue.Notify(EventMaskFromLinux(EVENT_IN as u32));
+        }
+
+        if !rearm {
+            return false
+        }
+
+        self.msg.Reset();
+        return true
+    }
+
+    pub fn New(fd: i32, queue: Queue, buf: Arc<DgramBuff>) -> Self {
+        return Self {
+            fd,
+            queue,
+            buf,
+            msg: DgramMsg::NewRecv(),
+        }
+    }
+}
+
+// AsyncIdleCancel cancels the uring read of the idle socket so that its read buf is shrunk
+pub struct AsyncIdleCancel {
+    pub fd: i32,
+    pub userData: u64,
+    pub buf: Arc<SocketBuff>,
+}
+
+impl AsyncIdleCancel {
+    pub fn New(fd: i32, userData: u64, buf: Arc<SocketBuff>) -> Self {
+        return Self {
+            fd,
+            userData,
+            buf,
+        }
+    }
+
+    pub fn SEntry(&self) -> squeue::Entry {
+        let op = opcode::AsyncCancel::new(self.userData);
+
+        // the cancel only finds the read in the same uring
+        return op.build()
+            .shard_fd(self.fd);
+    }
+
+    pub fn Process(&mut self, result: i32) -> bool {
+        // the read is not in the uring, e.g. it completes before the cancel
+        if result == -SysErr::ENOENT {
+            self.buf.CancelIdleShrink();
+        }
+
+        return false
+    }
+}
+
+// AsyncProvideBuffers gives the bufs of the receive pool to the uring it is submitted to
+pub struct AsyncProvideBuffers {
+    pub addr: u64,
+    pub nbufs: u16,
+    pub bid: u16,
+}
+
+impl AsyncProvideBuffers {
+    pub fn New(addr: u64, nbufs: u16, bid: u16) -> Self {
+        return Self {
+            addr,
+            nbufs,
+            bid,
+        }
+    }
+
+    pub fn SEntry(&self) -> squeue::Entry {
+        let op = opcode::ProvideBuffers::new(self.addr as * mut u8, RECV_POOL_BUF_SIZE as i32, self.nbufs, RECV_POOL_BGID, self.bid);
+        return op.build();
+    }
+
+    pub fn Process(&mut self, result: i32) -> bool {
+        if result < 0 {
+            error!("AsyncProvideBuffers bid {}/{} fail {}", self.bid, self.nbufs, result);
+        }
+
+        return false
+    }
+}
+
+pub struct AsyncDgramSend {
+    pub fd: i32,
+    pub queue: Queue,
+    pub buf: Arc<DgramBuff>,
+    pub msg: Box<DgramMsg>,
+
+    // keep the socket in the async ops so that the queued datagrams are sent after close
+    pub ops: SocketOperations,
+}
+
+impl AsyncDgramSend {
+    pub fn SEntry(&self) -> squeue::Entry {
+        let op = SendMsg::new(types::Fd(self.fd), &self.msg.hdr as *const _ as *const u64)
+            .flags(MsgType::MSG_NOSIGNAL as u32);
+
+        return op.build()
+            .flags(squeue::Flags::FIXED_FILE);
+    }
+
+    pub fn Process(&mut self, result: i32) -> bool {
+        let (writable, next) = self.buf.SendDone(self.msg.dgram.data.len(), result);
+        let mut mask = 0;
+        if writable {
+            mask |= EVENT_OUT;
+        }
+
+        if self.buf.Events() & EVENT_ERR != 0 {
+            mask |= EVENT_ERR;
+        }
+
+        if mask != 0 {
+            self.queue.Notify(EventMaskFromLinux(mask as u32));
+        }
+
+        match next {
+            None => return false,
+            Some(dgram) => {
+                self.msg = DgramMsg::New(dgram);
+                return true
+            }
+        }
+    }
+
+    pub fn New(fd: i32, queue: Queue, buf: Arc<DgramBuff>, dgram: Datagram, ops: &SocketOperations) -> Self {
+        return Self {
+            fd,
+            queue,
+            buf,
+            msg: DgramMsg::New(dgram),
+            ops: ops.clone(),
+        }
+    }
+}
+
 pub struct AIOWrite {
     pub fd: i32,
     pub buf: DataBuff,
@@ -943,10 +1440,9 @@ pub struct AIOWrite {
 
 impl AIOWrite {
     pub fn NewWrite(task: &Task, ctx: AIOContext, cb: &IOCallback, cbAddr: u64, eventfops: Option<EventOperations>) -> Result<Self> {
-        let vec = task.CopyInVec(cb.buf, cb.bytes as usize)?;
-        let buf = DataBuff {
-            buf: vec
-        };
+        let mut buf = DataBuff::NewUserIO(cb.bytes as usize)?;
+        let srcs = [IoVec::NewFromAddr(cb.buf, buf.Len())];
+        task.CopyDataInFromIovs(&mut buf.buf, &srcs)?;
 
         return Ok(Self {
             fd: cb.fd as i32,
@@ -962,7 +1458,7 @@ impl AIOWrite {
     pub fn NewWritev(task: &Task, ctx: AIOContext, cb: &IOCallback, cbAddr: u64, eventfops: Option<EventOperations>) -> Result<Self> {
         let srcs = task.IovsFromAddr(cb.buf, cb.bytes as usize)?;
         let size = IoVec::NumBytes(&srcs);
-        let mut buf = DataBuff::New(size);
+        let mut buf = DataBuff::NewUserIO(size)?;
         task.CopyDataInFromIovs(&mut buf.buf, &srcs)?;
 
         return Ok(Self {
@@ -1027,7 +1523,7 @@ impl AIORead {
         let iov = IoVec::NewFromAddr(cb.buf, cb.bytes as usize);
 
         let iovs = vec![iov];
-        let buf = DataBuff::New(cb.bytes as usize);
+        let buf = DataBuff::NewUserIO(cb.bytes as usize)?;
 
         return Ok(Self {
             fd: cb.fd as i32,
@@ -1045,7 +1541,7 @@ impl AIORead {
     pub fn NewReadv(task: &Task, ctx: AIOContext, cb: &IOCallback, cbAddr: u64, eventfops: Option<EventOperations>) -> Result<Self> {
         let iovs = task.IovsFromAddr(cb.buf, cb.bytes as usize)?;
         let size = IoVec::NumBytes(&iovs);
-        let buf = DataBuff::New(size as usize);
+        let buf = DataBuff::NewUserIO(size as usize)?;
 
         return Ok(Self {
             fd: cb.fd as i32,
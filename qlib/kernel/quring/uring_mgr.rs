@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use core::sync::atomic;
+use core::sync::atomic::AtomicI64;
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
@@ -28,21 +29,79 @@ pub use super::super::super::uring::cqueue;
 pub use super::super::super::uring::squeue::SubmissionQueue;
 pub use super::super::super::uring::*;
 use super::super::fs::file::*;
+use super::super::fd::IOReadAt;
 
 use super::super::super::uring::util::*;
 use super::super::super::linux_def::*;
 use super::super::super::vcpu_mgr::*;
 use super::super::super::socket_buf::*;
+use super::super::super::dgram_buf::*;
 use super::super::super::super::kernel_def::*;
 use super::super::kernel::waiter::*;
 use super::super::socket::hostinet::socket::*;
+use super::super::socket::hostinet::idle::*;
 use super::super::Kernel::HostSpace;
 use super::super::kernel::async_wait::*;
 use super::super::IOURING;
 use super::super::SHARESPACE;
+use super::super::TSC;
+use super::super::Tsc;
+use super::super::super::metric::*;
+use super::super::super::singleton::*;
 use super::uring_op::*;
+use super::super::super::uring::sys::sys::IORING_OP_WRITE;
 use super::uring_async::*;
 use super::super::kernel::waiter::qlock::*;
+use super::uring_sched::*;
+use super::buf_pool::*;
+use super::super::fs::host::writeback::*;
+
+// the completions the vcpus reap when the host io thread falls behind, the lag is the time the
+// io thread hasn't checked the uring, i.e. the bound of how late the completions wake the waiters
+pub static VCPU_REAPS: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static VCPU_REAP_COMPLETIONS: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+pub static VCPU_REAP_LAG: Singleton<Arc<U64Metric>> = Singleton::<Arc<U64Metric>>::New();
+
+pub unsafe fn InitSingleton() {
+    VCPU_REAPS.Init(NewU64Metric("/uring/vcpu_reaps", false,
+        "Number of times a vcpu reaped the uring completions for the lagging io thread."));
+    VCPU_REAP_COMPLETIONS.Init(NewU64Metric("/uring/vcpu_reap_completions", false,
+        "Number of uring completions reaped by the vcpus."));
+    VCPU_REAP_LAG.Init(NewU64Metric("/uring/vcpu_reap_lag_us", false,
+        "Total time in us the io thread had not checked the uring when a vcpu reaped it."));
+    NewMetric("/uring/wakeups", false,
+        "Number of uring reaps of the completions seen by the vcpus before.",
+        Arc::new(UringWakeupMetric::Count));
+    NewMetric("/uring/wakeup_latency_us", false,
+        "Total time in us from when the vcpus see the completions to the reap waking up the waiters.",
+        Arc::new(UringWakeupMetric::Latency));
+    NewMetric("/uring/wakeup_latency_max_us", false,
+        "Max time in us from when the vcpus see the completions to the reap waking up the waiters.",
+        Arc::new(UringWakeupMetric::MaxLatency));
+}
+
+// UringWakeupMetric is the completion-to-wakeup latency kept in the urings of the share space,
+// the completions are seen by the vcpus when the reap of the lagging urings is enabled
+pub enum UringWakeupMetric {
+    Count,
+    Latency,
+    MaxLatency,
+}
+
+impl Metric for UringWakeupMetric {
+    fn Value(&self) -> u64 {
+        let mut val = 0;
+        for uring in IOURING.IOUrings() {
+            match self {
+                Self::Count => val += uring.wakeups.load(Ordering::Relaxed),
+                Self::Latency => val += Tsc::Scale(uring.wakeupCycles.load(Ordering::Relaxed) as i64) as u64,
+                Self::MaxLatency => val = core::cmp::max(val, Tsc::Scale(uring.wakeupMaxCycles.load(Ordering::Relaxed) as i64) as u64),
+            }
+        }
+
+        return val
+    }
+}
 
 pub fn QUringTrigger() -> usize {
     return IOURING.DrainCompletionQueue();
@@ -83,6 +142,17 @@ impl IoUring {
     pub fn SubmitAndWait(&self, idx: usize, _want: usize) -> Result<usize> {
         let dedicateUring = SHARESPACE.config.read().DedicateUring;
         if dedicateUring == 0 {
+            if idx > 0 {
+                // the uring is served by its own io thread, wake it up if it is blocked.
+                // pair with the ioThreadWaiting store then pendingCnt load in the io thread
+                self.pendingCnt.fetch_add(1, Ordering::SeqCst);
+                if self.ioThreadWaiting.load(Ordering::SeqCst) {
+                    UringWake(idx, 0);
+                }
+
+                return Ok(0);
+            }
+
             self.pendingCnt.fetch_add(1, Ordering::Release);
 
             if SHARESPACE.HostProcessor() == 0 {
@@ -140,6 +210,13 @@ pub struct QUring {
     pub uringsAddr: AtomicU64,
     pub asyncMgr: UringAsyncMgr,
     pub uringCount: AtomicUsize,
+    // the tasks waiting for the UCall completion
+    pub syncInflight: AtomicUsize,
+    pub sched: UringScheduler,
+    // bitmap of the IORING_OP_* supported by the host kernel, probed by the host at init
+    pub supportedOps: AtomicU64,
+    // IOReapLag in ns, it is checked on every syscall return
+    pub reapLagNs: AtomicI64,
 }
 
 impl QUring {
@@ -149,7 +226,11 @@ impl QUring {
         let ret = QUring {
             asyncMgr: UringAsyncMgr::New(size),
             uringsAddr: AtomicU64::new(0),
-            uringCount: AtomicUsize::new(0)
+            uringCount: AtomicUsize::new(0),
+            syncInflight: AtomicUsize::new(0),
+            sched: UringScheduler::default(),
+            supportedOps: AtomicU64::new(u64::MAX),
+            reapLagNs: AtomicI64::new(0),
         };
 
         return ret;
@@ -178,6 +259,20 @@ impl QUring {
         return self.uringCount.load(atomic::Ordering::Relaxed)
     }
 
+    pub fn SetSupportedOps(&self, ops: u64) {
+        self.supportedOps.store(ops, atomic::Ordering::SeqCst);
+    }
+
+    pub fn SetReapLag(&self, ns: i64) {
+        self.reapLagNs.store(ns, atomic::Ordering::SeqCst);
+    }
+
+    // OpSupported checks whether the host uring supports the opcode, the features built on
+    // the unsupported opcodes fall back to the host calls or are turned off
+    pub fn OpSupported(&self, opcode: u32) -> bool {
+        return opcode < 64 && self.supportedOps.load(atomic::Ordering::Relaxed) & (1 << opcode) != 0
+    }
+
     pub fn TimerRemove(&self, task: &Task, userData: u64) -> i64 {
         let msg = UringOp::TimerRemove(TimerRemoveOp{
             userData: userData,
@@ -267,7 +362,8 @@ impl QUring {
 
     pub fn LogFlush(&self) {
         let uringPrint = super::super::SHARESPACE.config.read().Async();
-        if !uringPrint {
+        // the config is switched to the sync log when the host uring has no write
+        if !uringPrint || !self.OpSupported(IORING_OP_WRITE) {
             return
         }
 
@@ -341,6 +437,14 @@ impl QUring {
     }
 
     pub fn BufSockInit(fd: i32, queue: Queue, buf: Arc<SocketBuff>, isSocket: bool) -> Result<()> {
+        if isSocket {
+            StartIdleReclaim();
+            // the data of the uring reads stays in the receive pool bufs
+            if RecvBufPool::Init().is_some() {
+                buf.PoolShrinkRead();
+            }
+        }
+
         let (addr, len) = buf.GetFreeReadBuf();
         let readop = AsyncFileRead::New(fd, queue, buf, addr, len, isSocket);
 
@@ -349,8 +453,27 @@ impl QUring {
         return Ok(())
     }
 
+    // CancelSocketRead cancels the uring read of the socket buf, it returns false when the read
+    // is not in the uring, e.g. it is stopped on the full read buf
+    pub fn CancelSocketRead(fd: i32, buf: &Arc<SocketBuff>) -> bool {
+        for (idx, op) in IOURING.asyncMgr.ops.iter().enumerate() {
+            let ops = op.lock();
+            match *ops {
+                AsyncOps::AsyncFileRead(ref read) if read.isSocket && Arc::ptr_eq(&read.buf, buf) => {
+                    // the slot is locked until the cancel is submitted like the idle reclaim
+                    let cancel = AsyncIdleCancel::New(fd, idx as u64, buf.clone());
+                    IOURING.AUCall(AsyncOps::AsyncIdleCancel(cancel));
+                    return true
+                }
+                _ => (),
+            }
+        }
+
+        return false
+    }
+
     pub fn RingFileWrite(task: &Task, fd: i32, queue: Queue, buf: Arc<SocketBuff>, srcs: &[IoVec], fops: Arc<FileOperations>, lockGuard: QAsyncLockGuard) -> Result<i64> {
-        let (count, writeBuf) = buf.Writev(task, srcs)?;
+        let (count, writeBuf) = buf.Writev(task, srcs, false)?;
 
         if let Some((addr, len)) = writeBuf {
             let writeop = AsyncFiletWrite::New(fd, queue, buf, addr, len, fops, lockGuard);
@@ -361,22 +484,158 @@ impl QUring {
         return Ok(count as i64)
     }
 
-    pub fn SocketSend(task: &Task, fd: i32, queue: Queue, buf: Arc<SocketBuff>, srcs: &[IoVec], ops: &SocketOperations)-> Result<i64> {
-        let (count, writeBuf) = buf.Writev(task, srcs)?;
+    // SocketSend writes the data to the write buf and starts the AsyncSend when the buf was empty.
+    // more: MSG_MORE, the data is held as with TCP_CORK until it is flushed.
+    pub fn SocketSend(task: &Task, fd: i32, queue: Queue, buf: Arc<SocketBuff>, srcs: &[IoVec], ops: &SocketOperations, more: bool)-> Result<i64> {
+        let cork = more || ops.Corked();
+        // MSG_MORE/TCP_CORK still hold the data with TCP_NODELAY, same as linux
+        let direct = if !cork && buf.FlushPolicy() == SendFlushPolicy::Immediate {
+            buf.WriteDirect(task, fd, &queue, srcs)?
+        } else {
+            None
+        };
 
-        if let Some((addr, len)) = writeBuf {
-            let writeop = AsyncSend::New(fd, queue, buf, addr, len, ops);
+        let res = match direct {
+            Some(r) => Ok(r),
+            None => buf.Writev(task, srcs, cork),
+        };
+
+        return Self::SocketSendQueued(fd, queue, buf, res, IoVec::NumBytes(srcs), ops, cork, more)
+    }
+
+    // SocketSendFile sends the host file range as SocketSend, the file data is read to the write
+    // buf by the uring read without going through the guest memory. The send of the empty write
+    // buf is linked to the read so that the host starts it when the read is done.
+    pub fn SocketSendFile(task: &Task, fd: i32, queue: Queue, buf: Arc<SocketBuff>, hostfd: i32, offset: i64, len: usize, ops: &SocketOperations) -> Result<i64> {
+        let cork = ops.Corked();
+        let uringIO = SHARESPACE.config.read().UringIO;
+        let res = match buf.ReserveFileRead(len, uringIO && !cork) {
+            Err(e) => Err(e),
+            Ok((addr, size, linked)) => {
+                let mut ret = if !uringIO {
+                    -SysErr::EINVAL as i64
+                } else if linked {
+                    let read = UringOp::Read(ReadOp {
+                        fd: hostfd,
+                        addr: addr,
+                        len: size as u32,
+                        offset: offset,
+                    });
+                    let send = AsyncSend::NewLinked(fd, queue.clone(), buf.clone(), addr, size, ops);
+                    IOURING.UCallLinked(task, read, AsyncOps::AsyncSend(send))
+                } else {
+                    IOURING.Read(task, hostfd, addr, size as u32, offset)
+                };
+
+                // the tmpfs file of the host can't be read by the uring, same as the file read
+                if ret == -SysErr::EINVAL as i64 {
+                    let iovs = [IoVec::NewFromAddr(addr, size)];
+                    ret = match IOReadAt(hostfd, &iovs, offset as u64) {
+                        Err(Error::SysError(e)) => -e as i64,
+                        Err(_) => -SysErr::EIO as i64,
+                        Ok(cnt) => cnt,
+                    };
+                }
+
+                let cnt = if ret < 0 { 0 } else { ret as usize };
+                let writeBuf = buf.CommitFileRead(size, cnt, linked, cork);
+                // the writers which got EAGAIN during the read
+                queue.Notify(EventMaskFromLinux(EVENT_OUT as u32));
+                if ret < 0 {
+                    Err(Error::SysError(-ret as i32))
+                } else {
+                    Ok((cnt, writeBuf))
+                }
+            }
+        };
 
-            IOURING.AUCall(AsyncOps::AsyncSend(writeop));
+        if let Ok((0, _)) = res {
+            return Ok(0)
+        }
+
+        return Self::SocketSendQueued(fd, queue, buf, res, len, ops, cork, false)
+    }
+
+    // SocketSendQueued starts the AsyncSend for the data queued in the write buf, size is the
+    // size requested by the app. The send of the corked data is held by the write, it is started
+    // here when the held data is full or the socket is not corked any more.
+    fn SocketSendQueued(fd: i32, queue: Queue, buf: Arc<SocketBuff>, res: Result<(usize, Option<(u64, usize)>)>,
+                        size: usize, ops: &SocketOperations, cork: bool, more: bool) -> Result<i64> {
+        let (count, writeBuf) = match res {
+            Err(Error::SysError(SysErr::EAGAIN)) => {
+                // the write buf could be full of the held data
+                ops.FlushCork();
+                buf.AutoTuneWrite(fd);
+                return Err(Error::SysError(SysErr::EAGAIN))
+            }
+            r => r?,
+        };
+
+        if count < size {
+            buf.AutoTuneWrite(fd);
+        }
+
+        match writeBuf {
+            Some((addr, len)) => {
+                Self::SocketSendStart(fd, queue, buf, addr, len, ops);
+            }
+            None => {
+                if !buf.SendHeld() {
+                    return Ok(count as i64)
+                }
+
+                if !cork || buf.CorkFull() {
+                    ops.FlushCork();
+                } else {
+                    ops.ArmCorkTimer();
+                    // the socket uncorked after cork was read has found no held send to flush
+                    if !more && !ops.Corked() {
+                        ops.FlushCork();
+                    }
+                }
+            }
         }
 
         return Ok(count as i64)
     }
 
+    pub fn SocketSendStart(fd: i32, queue: Queue, buf: Arc<SocketBuff>, addr: u64, len: usize, ops: &SocketOperations) {
+        let writeop = AsyncSend::New(fd, queue, buf, addr, len, ops);
+
+        IOURING.AUCall(AsyncOps::AsyncSend(writeop));
+    }
+
+    // DgramRecvStart starts the recvmsg of the udp socket when the buffer has space for it
+    pub fn DgramRecvStart(fd: i32, queue: Queue, buf: Arc<DgramBuff>) {
+        if !buf.ArmRecv() {
+            return
+        }
+
+        let recvop = AsyncDgramRecv::New(fd, queue, buf);
+        IOURING.AUCall(AsyncOps::AsyncDgramRecv(recvop));
+    }
+
+    // DgramSend queues the datagram of the reserved space and starts the sendmsg when there is
+    // none in the uring
+    pub fn DgramSend(fd: i32, queue: Queue, buf: Arc<DgramBuff>, dgram: Datagram, ops: &SocketOperations) {
+        if let Some(dgram) = buf.Queue(dgram) {
+            let sendop = AsyncDgramSend::New(fd, queue, buf, dgram, ops);
+            IOURING.AUCall(AsyncOps::AsyncDgramSend(sendop));
+        }
+    }
+
     pub fn RingFileRead(task: &Task, fd: i32, queue: Queue, buf: Arc<SocketBuff>, dsts: &mut [IoVec], isSocket: bool) -> Result<i64> {
         let (trigger, cnt) = buf.Readv(task, dsts)?;
 
-        if trigger {
+        // the stopped read of the host read socket is not restarted
+        if trigger && !buf.HostRead() {
+            // the uring read is stopped on the full read buf or on the receive window full of the
+            // pooled data, it can grow before the restart. The buf shrunk by the idle reclaim
+            // grows back to its size first.
+            if isSocket && !buf.IdleRegrowRead() {
+                buf.AutoTuneRead(fd);
+            }
+
             let (addr, len) = buf.GetFreeReadBuf();
             let readop = AsyncFileRead::New(fd, queue, buf, addr, len, isSocket);
 
@@ -386,9 +645,9 @@ impl QUring {
         return Ok(cnt as i64)
     }
 
-    pub fn BufFileWrite(&self, fd: i32, buf: DataBuff, offset: i64, lockGuard: QAsyncLockGuard) -> i64 {
+    pub fn BufFileWrite(&self, fd: i32, buf: DataBuff, offset: i64, lockGuard: QAsyncLockGuard, writeback: Arc<WritebackState>) -> i64 {
         let len = buf.Len() as i64;
-        let writeop = AsyncBufWrite::New(fd, buf, offset, lockGuard);
+        let writeop = AsyncBufWrite::New(fd, buf, offset, lockGuard, writeback);
 
         IOURING.AUCall(AsyncOps::AsyncBufWrite(writeop));
         return len
@@ -401,6 +660,7 @@ impl QUring {
 
         let data = cqe.user_data();
         let ret = cqe.result();
+        let flags = cqe.flags();
 
         // the taskid should be larger than 0x1000 (4K)
         if data > 0x10000 {
@@ -413,16 +673,36 @@ impl QUring {
             ScheduleQ(call.taskId);
         } else {
             let idx = data as usize;
-            let mut ops = self.asyncMgr.ops[idx].lock();
-            //error!("uring process2: call is {:?}, idx {}", ops.Type(), idx);
+            let class = {
+                let mut ops = self.asyncMgr.ops[idx].lock();
+                //error!("uring process2: call is {:?}, idx {}", ops.Type(), idx);
 
-            let rerun = ops.Process(ret, idx);
-            if super::super::Shutdown() {
-                return
-            }
-            if !rerun {
-                *ops = AsyncOps::None;
-                self.asyncMgr.FreeSlot(idx);
+                let rerun = ops.Process(ret, flags, idx);
+                if super::super::Shutdown() {
+                    return
+                }
+
+                if rerun {
+                    None
+                } else {
+                    let class = ops.Class();
+                    *ops = AsyncOps::None;
+                    self.asyncMgr.FreeSlot(idx);
+                    Some(class)
+                }
+            };
+
+            // the receive pool bufs given back by the op are provided out of the slot lock
+            RecvBufPool::ProvidePending();
+
+            let class = match class {
+                None => return,
+                Some(class) => class,
+            };
+
+            if class.Held() {
+                self.sched.Complete();
+                self.SubmitDeferred();
             }
         }
 
@@ -436,15 +716,58 @@ impl QUring {
             msg: msg,
         };
 
+        self.syncInflight.fetch_add(1, Ordering::SeqCst);
         {
             self.UringCall(&call);
         }
 
         Wait();
+        self.syncInflight.fetch_sub(1, Ordering::SeqCst);
 
         return call.ret as i64;
     }
 
+    // UCallLinked is the UCall of msg with the async op linked to it, the op is started by the
+    // host when msg is done in full and completes with ECANCELED otherwise
+    pub fn UCallLinked(&self, task: &Task, msg: UringOp, ops: AsyncOps) -> i64 {
+        let call = UringCall {
+            taskId: task.GetTaskId(),
+            ret: 0,
+            msg: msg,
+        };
+
+        let index = loop {
+            match self.asyncMgr.AllocSlot() {
+                None => {
+                    self.asyncMgr.Print();
+                    print!("UCallLinked async slots usage up...");
+                },
+                Some(idx) => break idx,
+            }
+        };
+
+        let entry1 = call.SEntry().user_data(call.Ptr());
+        let entry2 = self.asyncMgr.SetOps(index, ops);
+
+        self.syncInflight.fetch_add(1, Ordering::SeqCst);
+        self.AUringCallLinked(entry1, entry2);
+
+        Wait();
+        self.syncInflight.fetch_sub(1, Ordering::SeqCst);
+
+        return call.ret as i64;
+    }
+
+    pub fn SyncInflight(&self) -> usize {
+        return self.syncInflight.load(Ordering::SeqCst)
+    }
+
+    // AsyncBusy returns whether an async op which doesn't wait for an outside event is in flight
+    // or held by the scheduler
+    pub fn AsyncBusy(&self) -> bool {
+        return self.sched.deferred.load(Ordering::SeqCst) > 0 || self.asyncMgr.Busy()
+    }
+
     pub fn AUCallDirect(&self, ops: &AsyncOps, id: usize) {
         let entry = ops.SEntry().user_data(id as u64);
         self.AUringCall(entry)
@@ -467,11 +790,82 @@ impl QUring {
             }
         }
 
+        let class = ops.Class();
         let entry = self.asyncMgr.SetOps(index, ops);
-        self.AUringCall(entry);
+        if !class.Held() {
+            self.AUringCall(entry);
+        } else {
+            self.ScheduleCall(class, entry);
+        }
+
         return index as usize;
     }
 
+    // AUCallOnUring submits the op to the uring idx instead of the sharded one, e.g. the bufs
+    // provided to the buffer group of the uring
+    pub fn AUCallOnUring(&self, idx: usize, ops: AsyncOps) {
+        let index = loop {
+            match self.asyncMgr.AllocSlot() {
+                None => {
+                    self.asyncMgr.Print();
+                    print!("AUCallOnUring async slots usage up...");
+                },
+                Some(idx) => break idx,
+            }
+        };
+
+        let entry = self.asyncMgr.SetOps(index, ops);
+        loop {
+            {
+                let mut s = self.IOUrings()[idx].sq.lock();
+                if s.freeSlot() < Self::SUBMISSION_QUEUE_FREE_COUNT {
+                    UringWake(idx, 1);
+                    print!("AUCallOnUring: submission full... idx {}", idx);
+                    continue;
+                }
+
+                unsafe {
+                    match s.push(entry) {
+                        Ok(_) => (),
+                        Err(_) => panic!("AUCallOnUring submission queue is full"),
+                    }
+                }
+            }
+
+            self.IOUrings()[idx].Submit(idx).expect("QUringIntern::submit fail");
+            return;
+        }
+    }
+
+    // ScheduleCall submits the readahead or background entry, it is held while
+    // UringBackgroundInflight of them are in flight or the submission queue is short of free slots
+    pub fn ScheduleCall(&self, class: UringClass, entry: squeue::Entry) {
+        if SHARESPACE.config.read().UringBackgroundInflight == 0 {
+            self.sched.Submitted();
+            self.AUringCall(entry);
+            return
+        }
+
+        self.sched.Defer(class, entry);
+        self.SubmitDeferred();
+    }
+
+    // SubmitDeferred submits the held entries which fit in
+    pub fn SubmitDeferred(&self) {
+        let (limit, reserve) = {
+            let config = SHARESPACE.config.read();
+            // a quarter of the submission queue is kept for the foreground entries
+            (config.UringBackgroundInflight as usize, config.UringSize / 4)
+        };
+
+        while let Some((class, entry)) = self.sched.Next(limit) {
+            if !self.TryAUringCall(&entry, Self::SUBMISSION_QUEUE_FREE_COUNT + reserve) {
+                self.sched.Requeue(class, entry);
+                return
+            }
+        }
+    }
+
     pub fn AUCallLinked(&self, ops1: AsyncOps, ops2: AsyncOps) {
         let index1;
 
@@ -536,10 +930,114 @@ impl QUring {
         return false;
     }
 
+    // DrainCompletionQueueOne processes the completions of one uring, it is used by the io thread
+    // of the uring so that the completion is handled by the thread which submitted the request
+    pub fn DrainCompletionQueueOne(&self, idx: usize) -> usize {
+        let count = self.DrainUring(idx);
+
+        // the entries held for the short submission queue
+        if count > 0 {
+            self.SubmitDeferred();
+        }
+
+        return count;
+    }
+
+    // SharedUring returns whether the uring is served by the kernel io thread, the others are
+    // drained by their own io thread
+    pub fn SharedUring(&self, idx: usize) -> bool {
+        return !self.IOUrings()[idx].ownIOThread.load(Ordering::Acquire)
+    }
+
+    // DrainSharedCompletionQueue processes the completions of the urings served by the kernel
+    // io thread
+    pub fn DrainSharedCompletionQueue(&self) -> usize {
+        let mut count = 0;
+        for idx in 0..self.UringCount() {
+            if !self.SharedUring(idx) {
+                continue;
+            }
+
+            count += self.DrainUring(idx);
+        }
+
+        if count > 0 {
+            self.SubmitDeferred();
+        }
+
+        return count;
+    }
+
+    fn DrainUring(&self, idx: usize) -> usize {
+        let mut count = 0;
+        loop {
+            if super::super::Shutdown() {
+                return count;
+            }
+
+            let cqe = {
+                let mut c = self.IOUrings()[idx].cq.lock();
+                c.next()
+            };
+
+            match cqe {
+                None => break,
+                Some(cqe) => {
+                    count += 1;
+                    self.Process(&cqe);
+                }
+            }
+        }
+
+        if count > 0 {
+            self.IOUrings()[idx].CompletionReaped(TSC.Rdtsc());
+        }
+
+        return count;
+    }
+
+    // ReapLagging processes the completions of the urings whose host io thread hasn't checked
+    // them for IOReapLag, so the waiters are woken up by the vcpu instead of waiting for the io
+    // thread starved by the busy vcpus. One vcpu takes over a lagging uring at a time.
+    pub fn ReapLagging(&self) -> usize {
+        let lag = self.reapLagNs.load(Ordering::Relaxed);
+        if lag == 0 {
+            return 0
+        }
+
+        let mut count = 0;
+        let now = TSC.Rdtsc();
+        for idx in 0..self.UringCount() {
+            let uring = &self.IOUrings()[idx];
+            uring.CompletionSeen(now);
+            let last = uring.hostReapTsc.load(Ordering::Relaxed);
+            let behind = Tsc::Scale(now - last) * 1000;
+            if behind < lag || !uring.HasCompleteEntry() {
+                continue
+            }
+
+            if uring.hostReapTsc.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+                continue
+            }
+
+            let cnt = self.DrainCompletionQueueOne(idx);
+            if cnt > 0 {
+                VCPU_REAPS.Incr();
+                VCPU_REAP_COMPLETIONS.IncrBy(cnt as u64);
+                VCPU_REAP_LAG.IncrBy((behind / 1000) as u64);
+            }
+
+            count += cnt;
+        }
+
+        return count
+    }
+
     pub fn DrainCompletionQueue(&self) -> usize {
         let mut count = 0;
         for i in 0..self.UringCount() {
             let idx = (i + CPULocal::CpuId()) % self.UringCount();
+            let start = count;
             loop {
                 if super::super::Shutdown() {
                     return 0;
@@ -558,6 +1056,14 @@ impl QUring {
                     }
                 }
             }
+
+            if count > start {
+                self.IOUrings()[idx].CompletionReaped(TSC.Rdtsc());
+            }
+        }
+
+        if count > 0 {
+            self.SubmitDeferred();
         }
 
         return count;
@@ -570,14 +1076,27 @@ impl QUring {
         return CPULocal::NextUringIdx(cnt);
     }
 
+    // ShardIdx returns the first uring tried for the entry. The requests of one fd go to the
+    // same uring (and io thread) so that they are submitted in order.
+    pub fn ShardIdx(&self, entry: &squeue::Entry) -> usize {
+        let fd = entry.get_fd();
+        if fd < 0 {
+            return 0;
+        }
+
+        return fd as usize % self.UringCount();
+    }
+
     pub fn UringCall(&self, call: &UringCall) {
         let entry = call.SEntry();
         let entry = entry
             .user_data(call.Ptr());
 
         //let idx = Self::NextUringIdx(1) % self.UringCount();
+        let start = self.ShardIdx(&entry);
         loop {
-            for idx in 0..self.UringCount() {
+            for i in 0..self.UringCount() {
+                let idx = (start + i) % self.UringCount();
                 {
                     let mut s = self.IOUrings()[idx].sq.lock();
 
@@ -604,9 +1123,11 @@ impl QUring {
 
    pub fn AUringCall(&self, entry: squeue::Entry) {
         //let idx = Self::NextUringIdx(1) % self.UringCount();
+        let start = self.ShardIdx(&entry);
 
         loop {
-            for idx in 0..self.UringCount() {
+            for i in 0..self.UringCount() {
+                let idx = (start + i) % self.UringCount();
                 {
                     let mut s = self.IOUrings()[idx].sq.lock();
                     if s.freeSlot() < Self::SUBMISSION_QUEUE_FREE_COUNT {
@@ -629,11 +1150,39 @@ impl QUring {
         }
     }
 
+    // TryAUringCall submits the entry when a uring has freeCount free slots
+    pub fn TryAUringCall(&self, entry: &squeue::Entry, freeCount: usize) -> bool {
+        let start = self.ShardIdx(entry);
+        for i in 0..self.UringCount() {
+            let idx = (start + i) % self.UringCount();
+            {
+                let mut s = self.IOUrings()[idx].sq.lock();
+                if s.freeSlot() < freeCount {
+                    continue;
+                }
+
+                unsafe {
+                    match s.push(entry.clone()) {
+                        Ok(_) => (),
+                        Err(_) => panic!("TryAUringCall submission queue is full"),
+                    }
+                }
+            }
+
+            self.IOUrings()[idx].Submit(idx).expect("QUringIntern::submit fail");
+            return true;
+        }
+
+        return false
+    }
+
     pub fn AUringCallLinked(&self, entry1: squeue::Entry, entry2: squeue::Entry) {
         //let idx = Self::NextUringIdx(2) % self.UringCount();
+        let start = self.ShardIdx(&entry1);
 
         loop {
-            for idx in 0..self.UringCount() {
+            for i in 0..self.UringCount() {
+                let idx = (start + i) % self.UringCount();
                 {
                     let mut s = self.IOUrings()[idx].sq.lock();
                     if s.freeSlot() < Self::SUBMISSION_QUEUE_FREE_COUNT + 1 {
@@ -665,3 +1214,49 @@ impl QUring {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use super::*;
+    use super::super::super::super::uring::opcode::Fsync;
+    use super::super::super::super::uring::opcode::Nop;
+    use super::super::super::super::uring::opcode::types;
+
+    fn TestQUring(cnt: usize) -> QUring {
+        let mut urings = Vec::new();
+        for _ in 0..cnt {
+            urings.push(IoUring::default());
+        }
+
+        // the fds of the default urings are not closed on drop, so the urings are leaked
+        let urings: &'static Vec<IoUring> = Box::leak(Box::new(urings));
+        let quring = QUring::New(4);
+        quring.uringsAddr.store(urings as * const _ as u64, Ordering::Relaxed);
+        quring.uringCount.store(cnt, Ordering::Relaxed);
+        return quring
+    }
+
+    #[test]
+    fn test_shard_idx() {
+        let quring = TestQUring(3);
+        assert_eq!(quring.ShardIdx(&Fsync::new(types::Fd(4)).build()), 1);
+        assert_eq!(quring.ShardIdx(&Fsync::new(types::Fd(7)).build()), 1);
+        assert_eq!(quring.ShardIdx(&Fsync::new(types::Fd(9)).build()), 0);
+        // the entry without fd goes to the uring of the kernel io thread
+        assert_eq!(quring.ShardIdx(&Nop::new().build()), 0);
+    }
+
+    #[test]
+    fn test_shared_uring() {
+        let quring = TestQUring(3);
+        assert!(quring.SharedUring(0) && quring.SharedUring(1) && quring.SharedUring(2));
+
+        // the uring with its own io thread is not drained by the kernel io thread
+        quring.IOUrings()[1].ownIOThread.store(true, Ordering::Release);
+        quring.IOUrings()[2].ownIOThread.store(true, Ordering::Release);
+        assert!(quring.SharedUring(0));
+        assert!(!quring.SharedUring(1));
+        assert!(!quring.SharedUring(2));
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use alloc::collections::vec_deque::VecDeque;
+use crate::qlib::mutex::*;
+
+use super::super::super::uring::squeue;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UringClass {
+    // the input of the ttys which a user waits for, it is submitted at once
+    Interactive,
+    // the user syscalls and the socket IO, they are submitted at once
+    Foreground,
+    // the data read before the guest asks for it
+    Readahead,
+    // the buffered file writeback and the log flush
+    Background,
+}
+
+impl UringClass {
+    // Held returns whether the submission of the class may be held back by the scheduler
+    pub fn Held(&self) -> bool {
+        match self {
+            UringClass::Interactive | UringClass::Foreground => return false,
+            UringClass::Readahead | UringClass::Background => return true,
+        }
+    }
+}
+
+// UringScheduler holds the readahead and background submissions back so that they don't take
+// the submission queue slots and the host io threads from the foreground ones during bursts.
+// The held entries are submitted in order when the in flight ones complete, the readahead ones
+// before the background ones.
+#[derive(Default)]
+pub struct UringScheduler {
+    readahead: QMutex<VecDeque<squeue::Entry>>,
+    background: QMutex<VecDeque<squeue::Entry>>,
+    // number of the held entries
+    pub deferred: AtomicUsize,
+    // number of the readahead and background entries submitted and not completed
+    pub inflight: AtomicUsize,
+}
+
+impl UringScheduler {
+    fn Queue(&self, class: UringClass) -> &QMutex<VecDeque<squeue::Entry>> {
+        match class {
+            UringClass::Readahead => return &self.readahead,
+            _ => return &self.background,
+        }
+    }
+
+    pub fn Defer(&self, class: UringClass, entry: squeue::Entry) {
+        self.Queue(class).lock().push_back(entry);
+        self.deferred.fetch_add(1, Ordering::SeqCst);
+    }
+
+    // Next takes the next held entry if less than limit entries are in flight, the entry is
+    // counted as in flight
+    pub fn Next(&self, limit: usize) -> Option<(UringClass, squeue::Entry)> {
+        if self.deferred.load(Ordering::SeqCst) == 0 {
+            return None
+        }
+
+        if self.inflight.fetch_add(1, Ordering::SeqCst) >= limit {
+            self.inflight.fetch_sub(1, Ordering::SeqCst);
+            return None
+        }
+
+        for class in &[UringClass::Readahead, UringClass::Background] {
+            if let Some(entry) = self.Queue(*class).lock().pop_front() {
+                self.deferred.fetch_sub(1, Ordering::SeqCst);
+                return Some((*class, entry))
+            }
+        }
+
+        self.inflight.fetch_sub(1, Ordering::SeqCst);
+        return None
+    }
+
+    // Requeue puts back the entry taken by Next which can't be submitted now
+    pub fn Requeue(&self, class: UringClass, entry: squeue::Entry) {
+        self.Queue(class).lock().push_front(entry);
+        self.deferred.fetch_add(1, Ordering::SeqCst);
+        self.inflight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn Submitted(&self) {
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn Complete(&self) {
+        self.inflight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
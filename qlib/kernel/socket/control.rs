@@ -175,6 +175,8 @@ pub fn PadBytes<'a> (len: usize, dst: &'a mut [u8]) -> &'a mut [u8] {
 pub const SCM_RIGHTS      : i32 = 0x1;
 pub const SCM_CREDENTIALS : i32 = 0x2;
 pub const SCM_TIMESTAMP   : i32 = SO_TIMESTAMP;
+pub const SCM_TIMESTAMPNS : i32 = SO_TIMESTAMPNS;
+pub const SCM_TIMESTAMPING: i32 = SO_TIMESTAMPING;
 pub const SCM_TCP_INQ     : i32 = 0x24; // /* Notify bytes available to read as a cmsg on read */
 
 // A ControlMessageHeader is the header for a socket control message.
@@ -423,7 +425,7 @@ pub fn AlignSlice<'a>(buf: &'a mut [u8], align: usize) -> &'a mut [u8] {
 }
 
 #[derive(Debug, Default, Clone)]
-pub struct ControlMessageTimeStamp(Timeval);
+pub struct ControlMessageTimeStamp(pub Timeval);
 
 impl ControlMessage for ControlMessageTimeStamp {
     fn CMsgLevel(&self) -> i32 {
@@ -473,6 +475,111 @@ impl ControlMessage for ControlMessageTimeStamp {
     }
 }
 
+// A ControlMessageTimeStampNs is an SCM_TIMESTAMPNS socket control message.
+#[derive(Debug, Default, Clone)]
+pub struct ControlMessageTimeStampNs(pub Timespec);
+
+impl ControlMessage for ControlMessageTimeStampNs {
+    fn CMsgLevel(&self) -> i32 {
+        return SOL_SOCKET
+    }
+
+    fn Len(&self) -> usize {
+        let headerLen = CMsgAlign(mem::size_of::<ControlMessageHeader>());
+        let bodyLen = mem::size_of_val(&self.0);
+        return headerLen + bodyLen;
+    }
+
+    fn CMsgType(&self) -> i32 {
+        return SCM_TIMESTAMPNS;
+    }
+
+    fn EncodeInto<'a> (&self, buf: &'a mut [u8], flags: i32) -> (&'a mut [u8], i32) {
+        let space = AlignDown(buf.len(), 4);
+        let mut flags = flags;
+
+        if space < mem::size_of::<ControlMessageHeader>() {
+            flags |= MsgType::MSG_CTRUNC;
+            return (buf, flags)
+        }
+
+        let length = 2 * 8 + mem::size_of::<ControlMessageHeader>();
+        if length > space {
+            flags |= MsgType::MSG_CTRUNC;
+            return (buf, flags)
+        }
+
+        let cmsg = ControlMessageHeader {
+            Length: self.Len() as _,
+            Level: self.CMsgLevel(),
+            Type: self.CMsgType(),
+        };
+
+        let buf = CopyBytes(&cmsg, buf);
+        let buf = CopyBytes(&self.0, buf);
+
+        let aligned = AlignUp(length, ALIGNMENT) - length;
+        if aligned > buf.len() {
+            return (buf, flags)
+        }
+
+        return (&mut buf[aligned..], flags)
+    }
+}
+
+// A ControlMessageTimeStamping is an SCM_TIMESTAMPING socket control message.
+// It represents struct scm_timestamping, only the software timestamp ts[0] is filled.
+#[derive(Debug, Default, Clone)]
+pub struct ControlMessageTimeStamping(pub [Timespec; 3]);
+
+impl ControlMessage for ControlMessageTimeStamping {
+    fn CMsgLevel(&self) -> i32 {
+        return SOL_SOCKET
+    }
+
+    fn Len(&self) -> usize {
+        let headerLen = CMsgAlign(mem::size_of::<ControlMessageHeader>());
+        let bodyLen = mem::size_of_val(&self.0);
+        return headerLen + bodyLen;
+    }
+
+    fn CMsgType(&self) -> i32 {
+        return SCM_TIMESTAMPING;
+    }
+
+    fn EncodeInto<'a> (&self, buf: &'a mut [u8], flags: i32) -> (&'a mut [u8], i32) {
+        let space = AlignDown(buf.len(), 4);
+        let mut flags = flags;
+
+        if space < mem::size_of::<ControlMessageHeader>() {
+            flags |= MsgType::MSG_CTRUNC;
+            return (buf, flags)
+        }
+
+        let length = 3 * 2 * 8 + mem::size_of::<ControlMessageHeader>();
+        if length > space {
+            flags |= MsgType::MSG_CTRUNC;
+            return (buf, flags)
+        }
+
+        let cmsg = ControlMessageHeader {
+            Length: self.Len() as _,
+            Level: self.CMsgLevel(),
+            Type: self.CMsgType(),
+        };
+
+        let buf = CopyBytes(&cmsg, buf);
+        let buf = CopyBytes(&self.0, buf);
+
+        let aligned = AlignUp(length, ALIGNMENT) - length;
+        if aligned > buf.len() {
+            return (buf, flags)
+        }
+
+        return (&mut buf[aligned..], flags)
+    }
+}
+
 pub type AlignedOfCmsgData = usize;
 
 // Round `len` up to meet the platform's required alignment for
@@ -642,6 +749,39 @@ pub fn Parse(buf : &[u8]) -> Result<ControlMessages> {
     return Ok(ret)
 }
 
+// MapRights maps the fds of the SCM_RIGHTS messages in the control data in place, the other
+// messages are kept as they are. It translates the fds between the guest and the host for the
+// sendmsg/recvmsg of the host socket. The message truncated by MSG_CTRUNC only has the fds
+// which fit.
+pub fn MapRights(buf: &mut [u8], mut f: impl FnMut(i32) -> Result<i32>) -> Result<()> {
+    let mut i = 0;
+    while i + SIZE_OF_CONTROL_MESSAGE_HEADER <= buf.len() {
+        let h = unsafe {
+            *(buf[i..i + SIZE_OF_CONTROL_MESSAGE_HEADER].as_ptr() as * const ControlMessageHeader)
+        };
+
+        let length = h.Length as usize;
+        if length < SIZE_OF_CONTROL_MESSAGE_HEADER || length > buf.len() - i {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        if h.Level == LibcConst::SOL_SOCKET as i32 && h.Type == SCM_RIGHTS {
+            let numRights = (length - SIZE_OF_CONTROL_MESSAGE_HEADER) / SIZE_OF_CONTROL_MESSAGE_RIGHT;
+            for j in 0..numRights {
+                let off = i + SIZE_OF_CONTROL_MESSAGE_HEADER + j * SIZE_OF_CONTROL_MESSAGE_RIGHT;
+                let mut fd = [0u8; SIZE_OF_CONTROL_MESSAGE_RIGHT];
+                fd.copy_from_slice(&buf[off..off + SIZE_OF_CONTROL_MESSAGE_RIGHT]);
+                let fd = f(i32::from_ne_bytes(fd))?;
+                buf[off..off + SIZE_OF_CONTROL_MESSAGE_RIGHT].copy_from_slice(&fd.to_ne_bytes());
+            }
+        }
+
+        i += AlignUp(length, ALIGNMENT);
+    }
+
+    return Ok(())
+}
+
 pub fn MakeCreds(task: &Task, _cred: Option<BoundEndpoint>) -> Option<ScmCredentials> {
     //TODO: this is duplicating the function of scmCredentials::new, refactoring this
     /*let cr = match cred {
@@ -670,4 +810,31 @@ pub fn NewControlMessage(task: &Task, cred: Option<BoundEndpoint>, rights: Optio
         Credentials: MakeCreds(task, cred),
         Rights: rights,
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_map_rights() {
+        let mut buf = vec![0u8; 2 * CMsgSpace(8)];
+        let rights = ControlMessageRights(vec![3, 4]);
+        let (rest, flags) = rights.EncodeInto(&mut buf[..], 0);
+        assert_eq!((rest.len(), flags), (CMsgSpace(8), 0));
+
+        let mut fds = Vec::new();
+        MapRights(&mut buf[..CMsgSpace(8)], |fd| {
+            fds.push(fd);
+            Ok(fd + 10)
+        }).unwrap();
+        assert_eq!(fds, vec![3, 4]);
+
+        let off = SIZE_OF_CONTROL_MESSAGE_HEADER;
+        assert_eq!(&buf[off..off + 8], &[13, 0, 0, 0, 14, 0, 0, 0]);
+
+        // the header longer than the buffer
+        buf[0] = 0xff;
+        assert!(MapRights(&mut buf[..], |fd| Ok(fd)).is_err());
+    }
+}
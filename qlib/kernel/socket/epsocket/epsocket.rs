@@ -84,6 +84,7 @@ pub enum SockOptResult {
     Ucred(Ucred),
     Linger(Linger),
     Timeval(Timeval),
+    Label(&'static [u8]),
 }
 
 impl SockOptResult {
@@ -136,6 +137,14 @@ impl SockOptResult {
                  }
                  return Ok(core::mem::size_of::<Timeval>())
              }
+             SockOptResult::Label(v) => {
+                 if buf.len() < v.len() {
+                     return Err(Error::SysError(SysErr::ERANGE))
+                 }
+
+                 buf[..v.len()].copy_from_slice(v);
+                 return Ok(v.len())
+             }
          }
     }
 }
@@ -160,6 +169,10 @@ pub const SIZEOF_ICMPV6_FILTER      : usize = 0x20;
 pub const SIZEOF_UCRED             : usize = 0xc;
 pub const SIZEOF_TCPINFO           : usize = 0x68;
 
+// the SO_PEERSEC label of the peer, there is no security module in the sandbox and the tasks
+// are reported as unconfined
+pub const PEER_SEC_LABEL: &[u8] = b"unconfined";
+
 #[repr(C)]
 #[derive(Default, Copy, Clone, Debug)]
 pub struct Ucred {
@@ -225,11 +238,17 @@ pub fn GetSockOptSocket(task: &Task,
             }
 
             let tcred = task.Creds();
-
-            let pid = task.Thread().ThreadGroup().ID();
             let userns = tcred.lock().UserNamespace.clone();
-            let uid = tcred.lock().EffectiveKUID.In(&userns).OrOverflow();
-            let gid = tcred.lock().EffectiveKGID.In(&userns).OrOverflow();
+
+            // same as linux, the listening socket reports its own credentials
+            let peer = match ep.PeerCred() {
+                Some(peer) => peer,
+                None => return Err(Error::SysError(SysErr::ENOTCONN)),
+            };
+
+            let pid = peer.pid;
+            let uid = peer.uid.In(&userns).OrOverflow();
+            let gid = peer.gid.In(&userns).OrOverflow();
 
             let ucred = Ucred {
                 Pid: pid,
@@ -239,6 +258,17 @@ pub fn GetSockOptSocket(task: &Task,
 
             return Ok(SockOptResult::Ucred(ucred))
         }
+        LibcConst::SO_PEERSEC => {
+            if family != AFType::AF_UNIX {
+                return Err(Error::SysError(SysErr::ENOPROTOOPT))
+            }
+
+            if ep.PeerCred().is_none() {
+                return Err(Error::SysError(SysErr::ENOTCONN))
+            }
+
+            return Ok(SockOptResult::Label(PEER_SEC_LABEL))
+        }
         LibcConst::SO_PASSCRED => {
             if outlen < SIZEOF_I32 {
                 return Err(Error::SysError(SysErr::EINVAL))
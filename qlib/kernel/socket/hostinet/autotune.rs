@@ -0,0 +1,98 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::linux_def::*;
+use super::super::super::super::socket_buf::*;
+use super::super::super::super::linux::time::{MILLISECOND, SECOND};
+use super::super::super::kernel::timer::MonotonicNow;
+use super::super::super::Kernel::HostSpace;
+use super::super::super::SHARESPACE;
+
+// Autotuning of the ring bufs of the uring socket: the fixed ring caps the throughput of the
+// long fat pipe, so the ring grows when the app moves more than half of it in one rtt of the
+// host connection. The growth of the sandbox is capped by SocketBufAutoTuneMB.
+
+// the host rtt is fetched at most once a second
+pub const AUTOTUNE_RTT_REFRESH: i64 = SECOND;
+// the rtt when the host doesn't give one
+pub const AUTOTUNE_DEFAULT_RTT: i64 = MILLISECOND;
+
+// tcpi_rtt of struct tcp_info in micro sec
+const TCP_INFO_RTT_OFFSET: usize = 68;
+
+// HostTcpRtt returns the smoothed rtt in ns of the host tcp socket
+pub fn HostTcpRtt(fd: i32) -> Option<i64> {
+    let mut info = [0u8; TCP_INFO_RTT_OFFSET + 4];
+    let mut len = info.len() as u32;
+    let res = HostSpace::GetSockOpt(fd, LibcConst::SOL_TCP as i32, LibcConst::TCP_INFO as i32,
+                                    &mut info[0] as *mut _ as u64, &mut len as *mut _ as u64);
+    if res < 0 || (len as usize) < info.len() {
+        return None
+    }
+
+    let mut rtt = [0u8; 4];
+    rtt.copy_from_slice(&info[TCP_INFO_RTT_OFFSET..]);
+    let rtt = u32::from_ne_bytes(rtt) as i64 * 1000;
+    if rtt == 0 {
+        return None
+    }
+
+    return Some(rtt)
+}
+
+// AutoTuneLimit returns the max bytes of the growth of the sandbox, 0 disables the autotuning
+pub fn AutoTuneLimit() -> usize {
+    return SHARESPACE.config.read().SocketBufAutoTuneMB << 20
+}
+
+impl SocketBuff {
+    fn TuneRtt(&self, fd: i32, now: i64) -> i64 {
+        let rtt = self.rtt.load(Ordering::Relaxed);
+        if rtt != 0 && now - self.rttTime.load(Ordering::Relaxed) < AUTOTUNE_RTT_REFRESH {
+            return rtt
+        }
+
+        let rtt = HostTcpRtt(fd).unwrap_or(AUTOTUNE_DEFAULT_RTT);
+        self.rtt.store(rtt, Ordering::Relaxed);
+        self.rttTime.store(now, Ordering::Relaxed);
+        return rtt
+    }
+
+    // AutoTuneRead is called by the reader after it consumes the full read buf and before it
+    // restarts the uring read
+    pub fn AutoTuneRead(&self, fd: i32) {
+        let limit = AutoTuneLimit();
+        if limit == 0 || !self.RcvAutoTune() {
+            return
+        }
+
+        let now = MonotonicNow();
+        let rtt = self.TuneRtt(fd, now);
+        self.TuneReadBuf(now, rtt, limit);
+    }
+
+    // AutoTuneWrite is called by the writer when the write buf is full
+    pub fn AutoTuneWrite(&self, fd: i32) {
+        let limit = AutoTuneLimit();
+        if limit == 0 || !self.SndAutoTune() {
+            return
+        }
+
+        let now = MonotonicNow();
+        let rtt = self.TuneRtt(fd, now);
+        self.TuneWriteBuf(now, rtt, limit);
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::linux::netdevice::*;
+use super::super::super::super::linux::socket::*;
+use super::super::super::task::*;
+use super::super::super::Kernel::HostSpace;
+use super::super::netlink::route::*;
+
+// BindDeviceOpt is the device requested by SO_BINDTODEVICE or SO_BINDTOIFINDEX
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindDeviceOpt {
+    Name(Vec<u8>),
+    Index(i32),
+}
+
+impl BindDeviceOpt {
+    // Parse reads the option the same as linux sock_setbindtodevice/sock_bindtoindex.
+    // Return None if the option is not a bind device option.
+    pub fn Parse(level: i32, name: i32, opt: &[u8]) -> Result<Option<Self>> {
+        if level != SOL_SOCKET {
+            return Ok(None)
+        }
+
+        match name {
+            SO_BINDTODEVICE => {
+                // the name is truncated to IFNAMSIZ - 1 and ends at the first nul
+                let opt = &opt[..core::cmp::min(opt.len(), IFNAMSIZ - 1)];
+                let end = opt.iter().position(|c| *c == 0).unwrap_or(opt.len());
+                return Ok(Some(Self::Name(opt[..end].to_vec())))
+            }
+            SO_BINDTOIFINDEX => {
+                if opt.len() < 4 {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                let index = i32::from_ne_bytes([opt[0], opt[1], opt[2], opt[3]]);
+                if index < 0 {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                return Ok(Some(Self::Index(index)))
+            }
+            _ => return Ok(None),
+        }
+    }
+
+    // Index looks up the device in the guest links, 0 unbinds the socket
+    pub fn Index(&self) -> Result<i32> {
+        match self {
+            Self::Name(name) if name.len() == 0 => return Ok(0),
+            Self::Name(name) => return LinkIndex(name),
+            Self::Index(0) => return Ok(0),
+            Self::Index(index) => {
+                LinkName(*index)?;
+                return Ok(*index)
+            }
+        }
+    }
+}
+
+// BindDevice checks the bind device request of the socket bound to boundIndex and returns the
+// guest index of the device with the SO_BINDTODEVICE value of the host socket. The host socket
+// is bound by the name of the guest link, the host index of the interface may differ from the
+// guest one when the interface is recreated after the sandbox start.
+pub fn BindDevice(task: &Task, opt: &BindDeviceOpt, boundIndex: i32) -> Result<(i32, Vec<u8>)> {
+    let index = opt.Index()?;
+
+    // same as linux, an unprivileged socket can be bound only once
+    if boundIndex != 0 && !task.Creds().HasCapability(Capability::CAP_NET_RAW) {
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    if index == 0 {
+        return Ok((0, Vec::new()))
+    }
+
+    return Ok((index, LinkName(index)?))
+}
+
+// HostIfIndex translates the index of the guest link to the index of the host interface with
+// the same name, the host indexes change when the interfaces are recreated after the sandbox start
+pub fn HostIfIndex(hostfd: i32, index: i32) -> Result<i32> {
+    if index == 0 {
+        return Ok(0)
+    }
+
+    let name = LinkName(index)?;
+    if name.len() >= IFNAMSIZ {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+
+    let mut ifr = IFReq::default();
+    ifr.IFName[..name.len()].copy_from_slice(&name);
+    let res = HostSpace::IoCtl(hostfd, LibcConst::SIOCGIFINDEX, &mut ifr as *const _ as u64);
+    if res < 0 {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+
+    return Ok(i32::from_ne_bytes([ifr.Data[0], ifr.Data[1], ifr.Data[2], ifr.Data[3]]))
+}
+
+// BoundDevice answers the getsockopt of the bound device with the guest name of the link
+pub fn BoundDevice(name: i32, boundIndex: i32, opt: &mut [u8]) -> Result<Option<usize>> {
+    match name {
+        SO_BINDTODEVICE => {
+            if boundIndex == 0 {
+                return Ok(Some(0))
+            }
+
+            let dev = LinkName(boundIndex)?;
+            if opt.len() < IFNAMSIZ {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            opt[..dev.len()].copy_from_slice(&dev);
+            opt[dev.len()] = 0;
+            return Ok(Some(dev.len() + 1))
+        }
+        SO_BINDTOIFINDEX => {
+            if opt.len() < 4 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            opt[..4].copy_from_slice(&boundIndex.to_ne_bytes());
+            return Ok(Some(4))
+        }
+        _ => return Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use super::*;
+
+    #[test]
+    fn test_bind_device_opt() {
+        assert_eq!(BindDeviceOpt::Parse(SOL_SOCKET, SO_BINDTODEVICE, b"eth0\0xx").unwrap(),
+                   Some(BindDeviceOpt::Name(b"eth0".to_vec())));
+        assert_eq!(BindDeviceOpt::Parse(SOL_SOCKET, SO_BINDTODEVICE, b"").unwrap(),
+                   Some(BindDeviceOpt::Name(vec![])));
+        assert_eq!(BindDeviceOpt::Parse(SOL_SOCKET, SO_BINDTODEVICE, b"0123456789abcdefgh").unwrap(),
+                   Some(BindDeviceOpt::Name(b"0123456789abcde".to_vec())));
+        assert_eq!(BindDeviceOpt::Parse(SOL_SOCKET, SO_BINDTOIFINDEX, &3i32.to_ne_bytes()).unwrap(),
+                   Some(BindDeviceOpt::Index(3)));
+        assert!(BindDeviceOpt::Parse(SOL_SOCKET, SO_BINDTOIFINDEX, &(-1i32).to_ne_bytes()).is_err());
+        assert!(BindDeviceOpt::Parse(SOL_SOCKET, SO_BINDTOIFINDEX, &[1]).is_err());
+        assert_eq!(BindDeviceOpt::Parse(SOL_IP, SO_BINDTODEVICE, b"eth0").unwrap(), None);
+    }
+}
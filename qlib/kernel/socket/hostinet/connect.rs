@@ -0,0 +1,249 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicI32;
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+
+// ConnectState tracks the guest view of a host socket connect.
+// The host socket is the source of truth, the guest state is used to decide
+// when the socket is connected and the SocketBufType has to be switched (PostConnect)
+// so that it is done exactly once, whatever order the host results arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectState {
+    // no connect has been issued or the last connect failed
+    Init,
+    // host connect returned EINPROGRESS, the result is not sampled yet
+    Connecting,
+    // connect finished and PostConnect has been done
+    Connected,
+}
+
+// ConnectAction is what the socket has to do after a connect step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectAction {
+    // connect is finished, the caller has to do PostConnect and return the res
+    PostConnect(i64),
+    // host connect succeeded on a connected socket, e.g. connect of UDP socket again
+    Done,
+    // connect is in progress, the caller has to wait for the socket writable and sample SO_ERROR
+    Wait,
+    // return the errno to the application
+    Fail(i32),
+}
+
+// the state of the nonblocking connect of the buffered socket, a positive value is the errno of
+// the failed connect which is not reported yet
+pub const ASYNC_CONNECT_NONE: i32 = 0;
+// the host connect is in progress, the socket buffer is set up when it finishes
+pub const ASYNC_CONNECT_PENDING: i32 = -1;
+// the connect finished, the next connect returns 0
+pub const ASYNC_CONNECT_DONE: i32 = -2;
+
+// TakeAsyncConnect returns the result of the connect finished by PollConnect once, as linux
+// reports it to the next connect. The success is only taken by the connect.
+pub fn TakeAsyncConnect(asyncConnect: &AtomicI32, connect: bool) -> Option<Result<i64>> {
+    let res = asyncConnect.load(Ordering::Acquire);
+    if res == ASYNC_CONNECT_NONE || res == ASYNC_CONNECT_PENDING || (res == ASYNC_CONNECT_DONE && !connect) {
+        return None
+    }
+
+    if asyncConnect.compare_exchange(res, ASYNC_CONNECT_NONE, Ordering::AcqRel, Ordering::Acquire).is_err() {
+        return None
+    }
+
+    if res == ASYNC_CONNECT_DONE {
+        return Some(Ok(0))
+    }
+
+    return Some(Err(Error::SysError(res)))
+}
+
+impl Default for ConnectState {
+    fn default() -> Self {
+        return Self::Init
+    }
+}
+
+impl ConnectState {
+    // OnHostConnect handles the return value of the host connect.
+    // blocking: whether the caller is able to wait for the connect result.
+    pub fn OnHostConnect(&mut self, res: i32, blocking: bool) -> ConnectAction {
+        if res == 0 {
+            if *self == Self::Connected {
+                return ConnectAction::Done
+            }
+
+            // simultaneous open and self-connect could finish the connect immediately
+            return self.Finish(0);
+        }
+
+        let errno = -res;
+        match errno {
+            SysErr::EINPROGRESS => {
+                *self = Self::Connecting;
+                if blocking {
+                    return ConnectAction::Wait
+                }
+
+                return ConnectAction::Fail(errno)
+            }
+            SysErr::EALREADY => {
+                // previous nonblocking connect is still ongoing
+                if blocking && *self == Self::Connecting {
+                    return ConnectAction::Wait
+                }
+
+                return ConnectAction::Fail(errno)
+            }
+            SysErr::EISCONN => {
+                // the host connect finished without the guest sampling it,
+                // linux returns 0 for the first connect after the async connect finished
+                if *self == Self::Connecting {
+                    return self.Finish(0);
+                }
+
+                return ConnectAction::Fail(errno)
+            }
+            _ => {
+                // the connect failed, e.g. ECONNREFUSED. The socket could connect again.
+                if *self != Self::Connected {
+                    *self = Self::Init;
+                }
+
+                return ConnectAction::Fail(errno)
+            }
+        }
+    }
+
+    // OnSoError handles the SO_ERROR sampled after the socket is ready.
+    // SO_ERROR has to be sampled only after the socket is writable or has error,
+    // otherwise 0 means the connect is still in progress.
+    pub fn OnSoError(&mut self, soError: i32) -> ConnectAction {
+        if soError != 0 {
+            *self = Self::Init;
+            return ConnectAction::Fail(soError)
+        }
+
+        return self.Finish(0);
+    }
+
+    fn Finish(&mut self, res: i64) -> ConnectAction {
+        if *self == Self::Connected {
+            return ConnectAction::Fail(SysErr::EISCONN)
+        }
+
+        *self = Self::Connected;
+        return ConnectAction::PostConnect(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_immediate() {
+        let mut state = ConnectState::default();
+        assert_eq!(state.OnHostConnect(0, true), ConnectAction::PostConnect(0));
+        assert_eq!(state, ConnectState::Connected);
+        // connect again on connected socket
+        assert_eq!(state.OnHostConnect(-SysErr::EISCONN, true), ConnectAction::Fail(SysErr::EISCONN));
+        // connect of udp socket could succeed again
+        assert_eq!(state.OnHostConnect(0, true), ConnectAction::Done);
+    }
+
+    #[test]
+    fn test_connect_nonblocking_then_complete() {
+        let mut state = ConnectState::default();
+        assert_eq!(state.OnHostConnect(-SysErr::EINPROGRESS, false), ConnectAction::Fail(SysErr::EINPROGRESS));
+        assert_eq!(state.OnHostConnect(-SysErr::EALREADY, false), ConnectAction::Fail(SysErr::EALREADY));
+        assert_eq!(state, ConnectState::Connecting);
+        // second connect after the host side finished
+        assert_eq!(state.OnHostConnect(0, false), ConnectAction::PostConnect(0));
+        assert_eq!(state, ConnectState::Connected);
+    }
+
+    #[test]
+    fn test_connect_nonblocking_isconn() {
+        let mut state = ConnectState::default();
+        state.OnHostConnect(-SysErr::EINPROGRESS, false);
+        assert_eq!(state.OnHostConnect(-SysErr::EISCONN, false), ConnectAction::PostConnect(0));
+        assert_eq!(state.OnHostConnect(-SysErr::EISCONN, false), ConnectAction::Fail(SysErr::EISCONN));
+    }
+
+    #[test]
+    fn test_connect_refused() {
+        let mut state = ConnectState::default();
+        assert_eq!(state.OnHostConnect(-SysErr::EINPROGRESS, true), ConnectAction::Wait);
+        assert_eq!(state.OnSoError(SysErr::ECONNREFUSED), ConnectAction::Fail(SysErr::ECONNREFUSED));
+        assert_eq!(state, ConnectState::Init);
+
+        // closed port with nonblocking connect, the error is returned by the next connect
+        assert_eq!(state.OnHostConnect(-SysErr::EINPROGRESS, false), ConnectAction::Fail(SysErr::EINPROGRESS));
+        assert_eq!(state.OnHostConnect(-SysErr::ECONNREFUSED, false), ConnectAction::Fail(SysErr::ECONNREFUSED));
+        assert_eq!(state, ConnectState::Init);
+    }
+
+    #[test]
+    fn test_connect_self() {
+        // simultaneous open (e.g. self connect) finish with SO_ERROR 0 after writable
+        let mut state = ConnectState::default();
+        assert_eq!(state.OnHostConnect(-SysErr::EINPROGRESS, true), ConnectAction::Wait);
+        assert_eq!(state.OnSoError(0), ConnectAction::PostConnect(0));
+        assert_eq!(state, ConnectState::Connected);
+    }
+
+    #[test]
+    fn test_async_connect_pending() {
+        let asyncConnect = AtomicI32::new(ASYNC_CONNECT_NONE);
+        assert_eq!(TakeAsyncConnect(&asyncConnect, true), None);
+
+        // the connect in progress isn't reported
+        asyncConnect.store(ASYNC_CONNECT_PENDING, Ordering::Release);
+        assert_eq!(TakeAsyncConnect(&asyncConnect, true), None);
+        assert_eq!(TakeAsyncConnect(&asyncConnect, false), None);
+        assert_eq!(asyncConnect.load(Ordering::Acquire), ASYNC_CONNECT_PENDING);
+    }
+
+    #[test]
+    fn test_async_connect_done() {
+        let asyncConnect = AtomicI32::new(ASYNC_CONNECT_DONE);
+
+        // the success is kept for the next connect, e.g. the read doesn't take it
+        assert_eq!(TakeAsyncConnect(&asyncConnect, false), None);
+        assert_eq!(TakeAsyncConnect(&asyncConnect, true), Some(Ok(0)));
+        assert_eq!(asyncConnect.load(Ordering::Acquire), ASYNC_CONNECT_NONE);
+        assert_eq!(TakeAsyncConnect(&asyncConnect, true), None);
+    }
+
+    #[test]
+    fn test_async_connect_refused() {
+        // the nonblocking connect to the closed port is finished by the poll of the app
+        let mut state = ConnectState::default();
+        assert_eq!(state.OnHostConnect(-SysErr::EINPROGRESS, false), ConnectAction::Fail(SysErr::EINPROGRESS));
+        let asyncConnect = AtomicI32::new(ASYNC_CONNECT_PENDING);
+        match state.OnSoError(SysErr::ECONNREFUSED) {
+            ConnectAction::Fail(errno) => asyncConnect.store(errno, Ordering::Release),
+            a => panic!("unexpected {:?}", a),
+        }
+
+        // the error is reported once to the first call, the connect or not
+        assert_eq!(TakeAsyncConnect(&asyncConnect, false), Some(Err(Error::SysError(SysErr::ECONNREFUSED))));
+        assert_eq!(TakeAsyncConnect(&asyncConnect, true), None);
+        assert_eq!(state, ConnectState::Init);
+    }
+}
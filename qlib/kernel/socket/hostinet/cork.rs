@@ -0,0 +1,39 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Weak;
+
+use super::super::super::kernel::timer::timer::*;
+use super::super::super::super::linux::time::MILLISECOND;
+use super::socket::*;
+
+// TCP_CORK/MSG_MORE on the buffered socket: the data written to the empty write buffer is held
+// instead of starting the AsyncSend, it is sent when the socket is uncorked, the held data reaches
+// CORK_MAX_BYTES or CORK_TIMEOUT expires (same as the 200ms of linux).
+pub const CORK_TIMEOUT: i64 = 200 * MILLISECOND;
+
+// CorkTimerListener flushes the held data when the cork timer expires
+pub struct CorkTimerListener {
+    pub sock: Weak<SocketOperationsIntern>,
+}
+
+impl TimerListenerTrait for CorkTimerListener {
+    fn Notify(&self, _exp: u64) {
+        if let Some(sock) = SocketOperations::Upgrade(&self.sock) {
+            sock.FlushCork();
+        }
+    }
+
+    fn Destroy(&self) {}
+}
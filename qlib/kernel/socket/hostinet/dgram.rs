@@ -0,0 +1,209 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::slice;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::dgram_buf::*;
+use super::super::super::kernel::time::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::quring::QUring;
+use super::super::super::task::*;
+use super::super::super::tcpip::tcpip::*;
+use super::super::super::tcpip::sockaddr::*;
+use super::ephemeral::*;
+use super::rdma_socket::*;
+use super::socket::*;
+
+// the max udp payload, larger datagrams fail with EMSGSIZE as the host socket does
+pub const UDP_MAX_PAYLOAD_V4: usize = 65507;
+pub const UDP_MAX_PAYLOAD_V6: usize = 65527;
+
+impl SocketOperations {
+    fn DgramTryRecv(&self, buf: &Arc<DgramBuff>, peek: bool) -> Result<Datagram> {
+        let ret = buf.Recv(peek);
+        // the recvmsg stops when the buffer is full or fails, it goes on after the datagram
+        // or the error is taken
+        QUring::DgramRecvStart(self.fd, self.queue.clone(), buf.clone());
+        return ret
+    }
+
+    // DgramRecv is the recvmsg of the udp socket with the DgramBuff
+    pub fn DgramRecv(&self, task: &Task, buf: &Arc<DgramBuff>, dsts: &mut [IoVec], flags: i32, deadline: Option<Time>, senderRequested: bool, controlDataLen: usize)
+        -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)> {
+        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_PEEK | MsgType::MSG_TRUNC | MsgType::MSG_CTRUNC | MsgType::MSG_WAITALL) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let peek = flags & MsgType::MSG_PEEK != 0;
+        let dgram = match self.DgramTryRecv(buf, peek) {
+            Err(Error::SysError(SysErr::EAGAIN)) if flags & MsgType::MSG_DONTWAIT == 0 => {
+                let general = task.blocker.generalEntry.clone();
+                self.EventRegister(task, &general, EVENT_READ);
+                defer!(self.EventUnregister(task, &general));
+
+                loop {
+                    match self.DgramTryRecv(buf, peek) {
+                        Err(Error::SysError(SysErr::EAGAIN)) => (),
+                        r => break r?,
+                    }
+
+                    self.CheckClosed()?;
+                    match task.blocker.BlockWithMonoTimer(true, deadline) {
+                        Err(Error::ErrInterrupted) => {
+                            return Err(Error::SysError(SysErr::ERESTARTSYS));
+                        }
+                        Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                            return Err(Error::SysError(SysErr::EAGAIN));
+                        }
+                        Err(e) => {
+                            return Err(e);
+                        }
+                        _ => ()
+                    }
+                }
+            }
+            r => r?,
+        };
+
+        let len = IoVec::NumBytes(dsts);
+        let mut count = core::cmp::min(len, dgram.data.len());
+        task.CopyDataOutToIovs(&dgram.data[..count], dsts)?;
+
+        let mut retFlags = 0;
+        if dgram.data.len() > len {
+            retFlags |= MsgType::MSG_TRUNC;
+            // MSG_TRUNC returns the real length of the datagram
+            if flags & MsgType::MSG_TRUNC != 0 {
+                count = dgram.data.len();
+            }
+        }
+
+        let senderAddr = if senderRequested {
+            HostSockAddr(&dgram.addr, dgram.addr.len())
+        } else {
+            None
+        };
+
+        let mut controlData = dgram.control;
+        if controlData.len() > controlDataLen {
+            controlData.truncate(controlDataLen);
+            retFlags |= MsgType::MSG_CTRUNC;
+        }
+
+        return Ok((count as i64, retFlags, senderAddr, controlData))
+    }
+
+    // DgramSend is the sendmsg of the udp socket with the DgramBuff. The datagram is copied to
+    // the buffer and sent by the uring, the error of the host sendmsg is reported by the next
+    // send or SO_ERROR as linux does for the ICMP errors.
+    pub fn DgramSend(&self, task: &Task, buf: &Arc<DgramBuff>, srcs: &[IoVec], flags: i32, msgHdr: &MsgHdr, deadline: Option<Time>) -> Result<i64> {
+        // MSG_MORE doesn't cork the datagram, it is sent at once
+        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_FASTOPEN | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let len = IoVec::NumBytes(srcs);
+        let maxLen = if self.family == AFType::AF_INET {
+            UDP_MAX_PAYLOAD_V4
+        } else {
+            UDP_MAX_PAYLOAD_V6
+        };
+
+        if len > maxLen {
+            return Err(Error::SysError(SysErr::EMSGSIZE))
+        }
+
+        let addr = if msgHdr.msgName != 0 && msgHdr.nameLen > 0 {
+            // sendto of the unbound udp socket
+            ImplicitBind(self.fd, self.family, self.stype)?;
+            unsafe {
+                slice::from_raw_parts(msgHdr.msgName as *const u8, msgHdr.nameLen as usize).to_vec()
+            }
+        } else {
+            if self.GetRemoteAddr().is_none() {
+                return Err(Error::SysError(SysErr::EDESTADDRREQ))
+            }
+
+            Vec::new()
+        };
+
+        let control = if msgHdr.msgControl != 0 && msgHdr.msgControlLen > 0 {
+            unsafe {
+                slice::from_raw_parts(msgHdr.msgControl as *const u8, msgHdr.msgControlLen).to_vec()
+            }
+        } else {
+            Vec::new()
+        };
+
+        let mut data = Vec::with_capacity(len);
+        data.resize(len, 0);
+        task.CopyDataInFromIovs(&mut data, srcs)?;
+
+        let dgram = Datagram {
+            addr: addr,
+            data: data,
+            control: control,
+        };
+
+        // the datagram of the RDMA udp socket is written to the buf of qvisor at once
+        let rdma = buf.RDMA().is_some();
+        let take = || -> Result<()> {
+            if rdma {
+                return RDMA::DgramSend(self.fd, buf, &dgram)
+            }
+
+            return buf.Reserve(len)
+        };
+
+        match take() {
+            Err(Error::SysError(SysErr::EAGAIN)) if flags & MsgType::MSG_DONTWAIT == 0 => {
+                let general = task.blocker.generalEntry.clone();
+                self.EventRegister(task, &general, EVENT_WRITE);
+                defer!(self.EventUnregister(task, &general));
+
+                loop {
+                    match take() {
+                        Err(Error::SysError(SysErr::EAGAIN)) => (),
+                        r => break r?,
+                    }
+
+                    self.CheckClosed()?;
+                    match task.blocker.BlockWithMonoTimer(true, deadline) {
+                        Err(Error::ErrInterrupted) => {
+                            return Err(Error::SysError(SysErr::ERESTARTSYS));
+                        }
+                        Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                            return Err(Error::SysError(SysErr::EWOULDBLOCK));
+                        }
+                        Err(e) => {
+                            return Err(e);
+                        }
+                        _ => ()
+                    }
+                }
+            }
+            r => r?,
+        }
+
+        if !rdma {
+            QUring::DgramSend(self.fd, self.queue.clone(), buf.clone(), dgram, self);
+        }
+
+        return Ok(len as i64)
+    }
+}
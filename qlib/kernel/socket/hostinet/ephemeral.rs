@@ -0,0 +1,245 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::Kernel;
+use super::super::super::SHARESPACE;
+use super::super::super::tcpip::tcpip::*;
+
+// The hostinet sockets share the host network namespace, so all the sandboxes on the
+// host share the host ephemeral port range. When the sandbox has its own range
+// (EphemeralPortStart/EphemeralPortEnd), the guest binds the unbound socket to a port of the
+// range before the host would do the implicit bind in connect/sendto/listen.
+
+// the next port offset to try, it rotates so that a recently closed port is not reused at once
+static PORT_HINT: AtomicU32 = AtomicU32::new(0);
+
+// EphemeralPortRange returns the sandbox ephemeral port range, None if the host allocates the port
+pub fn EphemeralPortRange() -> Option<(u16, u16)> {
+    let config = SHARESPACE.config.read();
+    let start = config.EphemeralPortStart;
+    let end = config.EphemeralPortEnd;
+    if start == 0 || end < start {
+        return None
+    }
+
+    return Some((start, end))
+}
+
+// NeedPortAllocation returns whether the socket port is allocated by the guest
+pub fn NeedPortAllocation(family: i32, stype: i32) -> bool {
+    if family != AFType::AF_INET && family != AFType::AF_INET6 {
+        return false
+    }
+
+    if stype != SockType::SOCK_STREAM && stype != SockType::SOCK_DGRAM {
+        return false
+    }
+
+    return EphemeralPortRange().is_some()
+}
+
+fn SetPort(sockaddr: &mut [u8], port: u16) {
+    // sin_port and sin6_port are at the same offset in network byte order
+    let p = htons(port);
+    sockaddr[2] = p as u8;
+    sockaddr[3] = (p >> 8) as u8;
+}
+
+fn GetPort(sockaddr: &[u8]) -> u16 {
+    return ntohs(sockaddr[2] as u16 | (sockaddr[3] as u16) << 8)
+}
+
+// BindEphemeral binds the host socket to the address with a port of the sandbox range.
+// exhausted is the errno when all the ports are in use, linux returns EADDRINUSE for bind
+// and EADDRNOTAVAIL for the implicit bind of connect.
+pub fn BindEphemeral(fd: i32, sockaddr: &mut [u8], exhausted: i32) -> Result<i64> {
+    let (start, end) = match EphemeralPortRange() {
+        None => return Err(Error::SysError(SysErr::EINVAL)),
+        Some(r) => r,
+    };
+
+    let count = end as u32 - start as u32 + 1;
+    let offset = PORT_HINT.fetch_add(1, Ordering::Relaxed) % count;
+    for i in 0..count {
+        let port = start as u32 + (offset + i) % count;
+        SetPort(sockaddr, port as u16);
+        let res = Kernel::HostSpace::Bind(fd, &sockaddr[0] as *const _ as u64, sockaddr.len() as u32, 0);
+        if res == 0 {
+            PORT_HINT.store(offset + i + 1, Ordering::Relaxed);
+            return Ok(0)
+        }
+
+        // the port is used by this or another sandbox, try the next one
+        if res != -SysErr::EADDRINUSE as i64 {
+            return Err(Error::SysError(-res as i32))
+        }
+    }
+
+    return Err(Error::SysError(exhausted))
+}
+
+// ImplicitBind binds the socket to an ephemeral port if it is not bound yet
+pub fn ImplicitBind(fd: i32, family: i32, stype: i32) -> Result<()> {
+    if !NeedPortAllocation(family, stype) {
+        return Ok(())
+    }
+
+    let mut addr = [0u8; SOCK_ADDR_INET6_SIZE];
+    let len = addr.len() as i32;
+    let res = Kernel::HostSpace::GetSockName(fd, &mut addr[0] as *mut _ as u64, &len as *const _ as u64);
+    if res < 0 {
+        return Err(Error::SysError(-res as i32))
+    }
+
+    if GetPort(&addr) != 0 {
+        return Ok(())
+    }
+
+    // the host returns the wildcard address of the unbound socket
+    let len = if family == AFType::AF_INET {
+        SOCK_ADDR_INET_SIZE
+    } else {
+        SOCK_ADDR_INET6_SIZE
+    };
+
+    BindEphemeral(fd, &mut addr[..len], SysErr::EADDRNOTAVAIL)?;
+    return Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::AtomicU16;
+
+    use super::*;
+    use super::super::super::super::super::config::*;
+    use super::super::super::super::super::qmsg::*;
+    use super::super::super::super::test_util;
+
+    // the port the mock host socket is bound to, the ports under MOCK_FREE_PORT are in use
+    static MOCK_BOUND_PORT: AtomicU16 = AtomicU16::new(0);
+    static MOCK_FREE_PORT: AtomicU16 = AtomicU16::new(0);
+
+    fn mockHost(msg: &mut Msg) -> u64 {
+        match msg {
+            Msg::IOBind(msg) => {
+                let addr = unsafe { core::slice::from_raw_parts(msg.addr as *const u8, msg.addrlen as usize) };
+                let port = GetPort(addr);
+                if port < MOCK_FREE_PORT.load(Ordering::SeqCst) {
+                    return -SysErr::EADDRINUSE as i64 as u64
+                }
+
+                MOCK_BOUND_PORT.store(port, Ordering::SeqCst);
+                return 0
+            }
+            Msg::GetSockName(msg) => {
+                let addr = unsafe { core::slice::from_raw_parts_mut(msg.addr as *mut u8, SOCK_ADDR_INET_SIZE) };
+                addr[0..2].copy_from_slice(&(AFType::AF_INET as u16).to_ne_bytes());
+                SetPort(addr, MOCK_BOUND_PORT.load(Ordering::SeqCst));
+                return 0
+            }
+            _ => return -SysErr::EACCES as i64 as u64,
+        }
+    }
+
+    fn setRange(start: u16, end: u16) {
+        let mut config = Config::default();
+        config.EphemeralPortStart = start;
+        config.EphemeralPortEnd = end;
+        test_util::SetConfig(config);
+    }
+
+    fn inetAddr() -> [u8; SOCK_ADDR_INET_SIZE] {
+        let mut addr = [0u8; SOCK_ADDR_INET_SIZE];
+        addr[0..2].copy_from_slice(&(AFType::AF_INET as u16).to_ne_bytes());
+        return addr
+    }
+
+    #[test]
+    fn test_port_range() {
+        let _l = test_util::Lock();
+        setRange(0, 0);
+        assert_eq!(EphemeralPortRange(), None);
+        assert!(!NeedPortAllocation(AFType::AF_INET, SockType::SOCK_STREAM));
+
+        setRange(40010, 40000);
+        assert_eq!(EphemeralPortRange(), None);
+
+        setRange(40000, 40010);
+        assert_eq!(EphemeralPortRange(), Some((40000, 40010)));
+        assert!(NeedPortAllocation(AFType::AF_INET, SockType::SOCK_STREAM));
+        assert!(NeedPortAllocation(AFType::AF_INET6, SockType::SOCK_DGRAM));
+        assert!(!NeedPortAllocation(AFType::AF_UNIX, SockType::SOCK_STREAM));
+        assert!(!NeedPortAllocation(AFType::AF_INET, SockType::SOCK_RAW));
+        test_util::SetConfig(Config::default());
+    }
+
+    #[test]
+    fn test_bind_ephemeral() {
+        let _l = test_util::Lock();
+        test_util::SetMockHostCall(mockHost);
+        setRange(40000, 40003);
+
+        // the ports in use are skipped whatever the hint is
+        MOCK_FREE_PORT.store(40003, Ordering::SeqCst);
+        let mut addr = inetAddr();
+        assert_eq!(BindEphemeral(1, &mut addr, SysErr::EADDRINUSE), Ok(0));
+        assert_eq!(GetPort(&addr), 40003);
+        assert_eq!(MOCK_BOUND_PORT.load(Ordering::SeqCst), 40003);
+
+        // all the ports are in use
+        MOCK_FREE_PORT.store(40004, Ordering::SeqCst);
+        let res = BindEphemeral(1, &mut addr, SysErr::EADDRNOTAVAIL);
+        assert_eq!(res, Err(Error::SysError(SysErr::EADDRNOTAVAIL)));
+        test_util::SetConfig(Config::default());
+    }
+
+    #[test]
+    fn test_implicit_bind() {
+        let _l = test_util::Lock();
+        test_util::SetMockHostCall(mockHost);
+        setRange(40000, 40003);
+        MOCK_FREE_PORT.store(0, Ordering::SeqCst);
+
+        // the unbound socket gets a port of the range
+        MOCK_BOUND_PORT.store(0, Ordering::SeqCst);
+        assert_eq!(ImplicitBind(1, AFType::AF_INET, SockType::SOCK_STREAM), Ok(()));
+        let port = MOCK_BOUND_PORT.load(Ordering::SeqCst);
+        assert!(port >= 40000 && port <= 40003);
+
+        // the bound socket keeps its port
+        MOCK_BOUND_PORT.store(50000, Ordering::SeqCst);
+        assert_eq!(ImplicitBind(1, AFType::AF_INET, SockType::SOCK_STREAM), Ok(()));
+        assert_eq!(MOCK_BOUND_PORT.load(Ordering::SeqCst), 50000);
+
+        // the socket not allocated by the guest isn't bound
+        MOCK_BOUND_PORT.store(0, Ordering::SeqCst);
+        assert_eq!(ImplicitBind(1, AFType::AF_UNIX, SockType::SOCK_STREAM), Ok(()));
+        assert_eq!(MOCK_BOUND_PORT.load(Ordering::SeqCst), 0);
+        test_util::SetConfig(Config::default());
+    }
+
+    #[test]
+    fn test_port_offset() {
+        let mut addr = [0u8; SOCK_ADDR_INET_SIZE];
+        SetPort(&mut addr, 32768);
+        assert_eq!(addr[2], 0x80);
+        assert_eq!(addr[3], 0x00);
+        assert_eq!(GetPort(&addr), 32768);
+    }
+}
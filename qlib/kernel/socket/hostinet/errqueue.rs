@@ -0,0 +1,116 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux::socket::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::fs::file::*;
+use super::super::super::guestfdnotifier::*;
+use super::super::super::task::*;
+use super::super::super::tcpip::sockaddr::*;
+use super::super::super::Kernel::HostSpace;
+use super::socket::*;
+
+// IsRecvErrOpt returns whether the socket option is IP_RECVERR or IPV6_RECVERR
+pub fn IsRecvErrOpt(level: i32, name: i32) -> bool {
+    return (level == SOL_IP && name as u64 == LibcConst::IP_RECVERR)
+        || (level == SOL_IPV6 && name as u64 == LibcConst::IPV6_RECVERR)
+}
+
+// The error queue of the socket is the one of the host socket. The ICMP errors and the
+// SO_EE_* completions are queued by the host, and are read with the host recvmsg.
+impl SocketOperations {
+    pub fn RecvErrEnabled(&self) -> bool {
+        return self.recvErr.load(Ordering::Relaxed)
+    }
+
+    // SetRecvErr records IP_RECVERR/IPV6_RECVERR after it is set on the host socket
+    pub fn SetRecvErr(&self, opt: &[u8]) {
+        // same as linux, the option can be passed as a byte
+        let val = if opt.len() >= 4 {
+            unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            }
+        } else if opt.len() > 0 {
+            opt[0] as i32
+        } else {
+            0
+        };
+
+        self.recvErr.store(val != 0, Ordering::Relaxed);
+    }
+
+    // ErrQueueReadiness returns EVENT_ERR when the host error queue is not empty. The readiness
+    // of the buffered socket comes from the guest buffer, so the host socket is polled for it.
+    pub fn ErrQueueReadiness(&self, mask: EventMask) -> EventMask {
+        if mask & EVENT_ERR == 0 || !self.RecvErrEnabled() {
+            return 0
+        }
+
+        return NonBlockingPoll(self.fd, EVENT_ERR)
+    }
+
+    // HostRecvErrQueue dequeues an error from the host error queue. Same as linux, it never
+    // blocks, the data is the packet which caused the error and the control data has the
+    // sock_extended_err of the host.
+    pub fn HostRecvErrQueue(&self, task: &Task, dsts: &mut [IoVec], flags: i32, senderRequested: bool, controlDataLen: usize) -> Result<RecvMsgResult> {
+        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_PEEK | MsgType::MSG_TRUNC | MsgType::MSG_CTRUNC | MsgType::MSG_ERRQUEUE | MsgType::MSG_CMSG_CLOEXEC) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        // there is no fd in the control data of the error queue
+        let flags = flags & !MsgType::MSG_CMSG_CLOEXEC;
+
+        // the packet which caused the error can't be cut
+        let buf = DataBuff::NewUser(IoVec::NumBytes(dsts))?;
+        let iov = buf.IoVec();
+
+        let mut msgHdr = MsgHdr::default();
+        msgHdr.iov = &iov as *const _ as u64;
+        msgHdr.iovLen = 1;
+
+        let mut addr : [u8; SIZEOF_SOCKADDR] = [0; SIZEOF_SOCKADDR];
+        if senderRequested {
+            msgHdr.msgName = &mut addr[0] as * mut _ as u64;
+            msgHdr.nameLen = SIZEOF_SOCKADDR as u32;
+        }
+
+        let mut controlVec: Vec<u8> = vec![0; controlDataLen];
+        if controlDataLen > 0 {
+            msgHdr.msgControl = &mut controlVec[0] as *mut _ as u64;
+            msgHdr.msgControlLen = controlDataLen;
+        }
+
+        let res = HostSpace::IORecvMsg(self.fd, &mut msgHdr as *mut _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
+        if res < 0 {
+            return Err(HostErr("IORecvMsg", self.fd, -res as i32))
+        }
+
+        let count = core::cmp::min(res as usize, buf.Len());
+        task.CopyDataOutToIovs(&buf.buf[0..count], dsts)?;
+
+        // the offender address, it is empty when the error is generated locally
+        let senderAddr = if senderRequested {
+            HostSockAddr(&addr, msgHdr.nameLen as usize)
+        } else {
+            None
+        };
+
+        controlVec.resize(msgHdr.msgControlLen, 0);
+        return Ok((res as i64, msgHdr.msgFlags, senderAddr, controlVec))
+    }
+}
@@ -0,0 +1,86 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+
+use super::super::super::super::linux::time::SECOND;
+use super::super::super::super::uring::sys::sys::IORING_OP_ASYNC_CANCEL;
+use super::super::super::kernel::async_process::*;
+use super::super::super::kernel::timer::timer::*;
+use super::super::super::kernel::timer::MONOTONIC_CLOCK;
+use super::super::super::quring::uring_async::*;
+use super::super::super::ASYNC_PROCESS;
+use super::super::super::IOURING;
+use super::super::super::SHARESPACE;
+
+// Idle reclaim of the ring bufs of the uring socket: the bufs of the connection without data
+// transfer for SocketBufIdleSec are shrunk to SOCKET_BUF_MIN_PAGES so that the long lived idle
+// connections don't pin the full bufs. They grow back to their size on the next activity.
+
+static IDLE_RECLAIM: spin::Once<Timer> = spin::Once::new();
+
+// StartIdleReclaim starts the periodic idle scan with the first uring socket
+pub fn StartIdleReclaim() {
+    let period = SHARESPACE.config.read().SocketBufIdleSec as i64 * SECOND;
+    // the shrink cancels the pending read of the socket
+    if period == 0 || !IOURING.OpSupported(IORING_OP_ASYNC_CANCEL) {
+        return
+    }
+
+    IDLE_RECLAIM.call_once(|| {
+        let listener = TimerListener::IdleReclaimListener(Arc::new(IdleReclaimListener {}));
+        Timer::Period(&MONOTONIC_CLOCK, listener, period)
+    });
+}
+
+pub struct IdleReclaimListener {}
+
+impl TimerListenerTrait for IdleReclaimListener {
+    fn Notify(&self, _exp: u64) {
+        // the scan goes through all the async ops, it runs as the background work of the vcpus
+        // instead of on the thread firing the timer
+        ASYNC_PROCESS.Queue(AsyncWork::ReclaimIdleSocketBufs);
+    }
+
+    fn Destroy(&self) {}
+}
+
+// ReclaimIdleSocketBufs shrinks the bufs of the uring sockets idle for a scan period. The read
+// buf of the socket always has a uring read unless it is full, the read is canceled and its
+// completion shrinks the buf before the restart. The slot locked by the io path is skipped,
+// the socket in use isn't idle.
+pub fn ReclaimIdleSocketBufs() {
+    for (idx, op) in IOURING.asyncMgr.ops.iter().enumerate() {
+        let ops = match op.try_lock() {
+            None => continue,
+            Some(ops) => ops,
+        };
+        let read = match *ops {
+            AsyncOps::AsyncFileRead(ref read) if read.isSocket => read,
+            _ => continue,
+        };
+
+        if !read.buf.IdleScan() {
+            continue;
+        }
+
+        read.buf.IdleShrinkWrite();
+        if read.buf.StartIdleShrink() {
+            // the slot is locked until the cancel is submitted, the op reusing the slot after the
+            // read completes is behind the cancel in the uring
+            let cancel = AsyncIdleCancel::New(read.fd, idx as u64, read.buf.clone());
+            IOURING.AUCall(AsyncOps::AsyncIdleCancel(cancel));
+        }
+    }
+}
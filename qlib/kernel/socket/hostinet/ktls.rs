@@ -0,0 +1,260 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::common::*;
+use super::super::super::super::socket_buf::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::fs::file::*;
+use super::super::super::guestfdnotifier::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::task::*;
+use super::super::super::quring::QUring;
+use super::super::super::Kernel::HostSpace;
+use super::socket::*;
+use super::sendfile::*;
+
+// kTLS passthrough: the app sets the "tls" ULP on the connected tcp socket and pushes the crypto
+// state of each direction with setsockopt(SOL_TLS, TLS_TX/TLS_RX), the TLS records are then
+// processed by the host kernel.
+//
+// The buffered socket sends its write buffer with plain host writes, which the host encrypts
+// once TLS_TX is set, so the plaintext buffered before it is flushed first. Its reads are done
+// ahead into the read buffer, TLS_RX stops the uring read and the reads go to the host socket.
+// The records read ahead before it can't be decrypted by the host, TLS_RX fails with EBUSY then
+// and the app decrypts in the user space. The rdma socket doesn't send the data with the host
+// socket, it refuses kTLS.
+
+pub const TLS_TX: i32 = 1;
+pub const TLS_RX: i32 = 2;
+
+pub const TLS_1_2_VERSION: u16 = 0x0303;
+pub const TLS_1_3_VERSION: u16 = 0x0304;
+
+pub const TLS_CIPHER_AES_GCM_128: u16 = 51;
+pub const TLS_CIPHER_AES_GCM_256: u16 = 52;
+pub const TLS_CIPHER_AES_CCM_128: u16 = 53;
+pub const TLS_CIPHER_CHACHA20_POLY1305: u16 = 54;
+pub const TLS_CIPHER_SM4_GCM: u16 = 55;
+pub const TLS_CIPHER_SM4_CCM: u16 = 56;
+pub const TLS_CIPHER_ARIA_GCM_128: u16 = 57;
+pub const TLS_CIPHER_ARIA_GCM_256: u16 = 58;
+
+pub const TLS_ULP_NAME: &[u8] = b"tls";
+
+// the kTLS state of the host socket
+pub const KTLS_ULP: u32 = 1 << 0;
+pub const KTLS_TX: u32 = 1 << 1;
+pub const KTLS_RX: u32 = 1 << 2;
+
+// TlsCryptoInfoLen returns the size of the tls12_crypto_info struct of the cipher in opt, same
+// as linux the option has to be the whole struct of the cipher
+pub fn TlsCryptoInfoLen(opt: &[u8]) -> Result<usize> {
+    if opt.len() < 4 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let version = u16::from_ne_bytes([opt[0], opt[1]]);
+    let cipher = u16::from_ne_bytes([opt[2], opt[3]]);
+    if version != TLS_1_2_VERSION && version != TLS_1_3_VERSION {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    // the header, iv, key, salt and rec_seq of the cipher
+    let len = match cipher {
+        TLS_CIPHER_AES_GCM_128 | TLS_CIPHER_AES_CCM_128 | TLS_CIPHER_SM4_GCM |
+        TLS_CIPHER_SM4_CCM | TLS_CIPHER_ARIA_GCM_128 => 4 + 8 + 16 + 4 + 8,
+        TLS_CIPHER_AES_GCM_256 | TLS_CIPHER_ARIA_GCM_256 => 4 + 8 + 32 + 4 + 8,
+        TLS_CIPHER_CHACHA20_POLY1305 => 4 + 12 + 32 + 8,
+        _ => return Err(Error::SysError(SysErr::EINVAL)),
+    };
+
+    if opt.len() != len {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    return Ok(len)
+}
+
+// IsTlsUlp returns whether the option sets the tls ULP, the name may end with a nul
+pub fn IsTlsUlp(level: i32, name: i32, opt: &[u8]) -> bool {
+    if level as u64 != LibcConst::SOL_TCP || name as u64 != LibcConst::TCP_ULP {
+        return false
+    }
+
+    let end = opt.iter().position(|c| *c == 0).unwrap_or(opt.len());
+    return &opt[..end] == TLS_ULP_NAME
+}
+
+impl SocketOperations {
+    pub fn KtlsTx(&self) -> bool {
+        return self.ktls.load(Ordering::Relaxed) & KTLS_TX != 0
+    }
+
+    // KtlsSetSockOpt sets the tls ULP and the crypto state on the host socket, it returns None
+    // for the other options
+    pub fn KtlsSetSockOpt(&self, task: &Task, level: i32, name: i32, opt: &[u8]) -> Result<Option<i64>> {
+        let flag = if IsTlsUlp(level, name, opt) {
+            // the deferred TCP_FASTOPEN_CONNECT socket is not connected on the host yet
+            if self.FastOpenPending() {
+                return Err(Error::SysError(SysErr::ENOTCONN))
+            }
+
+            KTLS_ULP
+        } else if level as u64 == LibcConst::SOL_TLS && (name == TLS_TX || name == TLS_RX) {
+            TlsCryptoInfoLen(opt)?;
+            if name == TLS_TX { KTLS_TX } else { KTLS_RX }
+        } else {
+            return Ok(None)
+        };
+
+        match self.SocketBufType() {
+            SocketBufType::RDMA(_) => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+            SocketBufType::Uring(buf) if flag == KTLS_RX => self.StopUringRead(task, &buf)?,
+            SocketBufType::Uring(_) if flag == KTLS_TX => self.DrainWriteBuf(task)?,
+            _ => (),
+        }
+
+        let res = HostSpace::SetSockOpt(self.fd, level, name, &opt[0] as *const _ as u64, opt.len() as u32);
+        if res < 0 {
+            return Err(HostErr("SetSockOpt", self.fd, -res as i32))
+        }
+
+        self.ktls.fetch_or(flag, Ordering::Relaxed);
+        return Ok(Some(0))
+    }
+
+    // KtlsRxStarted returns whether the reads of the buffered socket are moved to the host socket
+    pub fn KtlsRxStarted(&self) -> bool {
+        match self.SocketBufType() {
+            SocketBufType::Uring(buf) => return buf.HostRead(),
+            _ => return false,
+        }
+    }
+
+    // KtlsHostRead returns whether the read goes to the host socket, the data read ahead is read
+    // from the read buffer first
+    pub fn KtlsHostRead(&self) -> bool {
+        match self.SocketBufType() {
+            SocketBufType::Uring(buf) => return buf.HostRead() && !buf.HasReadData(),
+            _ => return false,
+        }
+    }
+
+    // StopUringRead moves the reads to the host socket and waits until the uring read stops, it
+    // fails when the data read ahead is not read by the app yet
+    pub fn StopUringRead(&self, task: &Task, buf: &Arc<SocketBuff>) -> Result<()> {
+        if buf.SetHostRead() && !QUring::CancelSocketRead(self.fd, buf) {
+            // the read is stopped on the full read buffer, EOF or the error
+            buf.SetReadStopped();
+        }
+
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_IN);
+        defer!(self.EventUnregister(task, &general));
+
+        while !buf.ReadStopped() {
+            task.blocker.BlockWithMonoTimer(true, None)?;
+        }
+
+        if buf.HasReadData() {
+            return Err(Error::SysError(SysErr::EBUSY))
+        }
+
+        return Ok(())
+    }
+
+    // DrainWriteBuf waits until the data of the write buffer is sent by the host socket
+    pub fn DrainWriteBuf(&self, task: &Task) -> Result<()> {
+        self.FlushCork();
+        let buf = self.SocketBuf()?;
+        if !buf.HasWriteData() {
+            return Ok(())
+        }
+
+        // the pending shutdown asks the AsyncSend to notify when the buffer is drained, it is
+        // kept when the socket is being shut down
+        let pending = buf.PendingWriteShutdown();
+        buf.SetPendingWriteShutdown();
+        defer!(if !pending { buf.ClearPendingWriteShutdown() });
+
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_PENDING_SHUTDOWN | EVENT_ERR | EVENT_HUP);
+        defer!(self.EventUnregister(task, &general));
+
+        while buf.HasWriteData() {
+            let err = buf.Error();
+            if err != 0 {
+                return Err(Error::SysError(err))
+            }
+
+            task.blocker.BlockWithMonoTimer(true, None)?;
+        }
+
+        return Ok(())
+    }
+
+    // KtlsSendFile sends the host file with the host sendfile, so the file data is encrypted by
+    // the host kernel without copying through the guest. It returns ENOSYS for the copy of
+    // Splice when the socket or the file doesn't qualify.
+    pub fn KtlsSendFile(&self, task: &Task, src: &File, opts: &SpliceOpts) -> Result<i64> {
+        if !self.KtlsTx() || self.SocketBufEnabled() {
+            return Err(Error::SysError(SysErr::ENOSYS))
+        }
+
+        let hostiops = match HostSendFileSrc(task, src, opts) {
+            None => return Err(Error::SysError(SysErr::ENOSYS)),
+            Some(h) => h,
+        };
+
+        let res = HostSpace::SendFile(self.fd, hostiops.HostFd(), opts.SrcStart, opts.Length as u64);
+        if res < 0 {
+            if res == -SysErr::EAGAIN as i64 {
+                ClearNotified(self.fd, EVENT_OUT);
+            }
+
+            return Err(HostErr("SendFile", self.fd, -res as i32))
+        }
+
+        return Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tls_crypto_info_len() {
+        let mut info = [0u8; 56];
+        info[..2].copy_from_slice(&TLS_1_3_VERSION.to_ne_bytes());
+        info[2..4].copy_from_slice(&TLS_CIPHER_AES_GCM_128.to_ne_bytes());
+        assert_eq!(TlsCryptoInfoLen(&info[..40]).unwrap(), 40);
+        assert!(TlsCryptoInfoLen(&info[..56]).is_err());
+        info[2..4].copy_from_slice(&TLS_CIPHER_CHACHA20_POLY1305.to_ne_bytes());
+        assert_eq!(TlsCryptoInfoLen(&info).unwrap(), 56);
+        info[2..4].copy_from_slice(&50u16.to_ne_bytes());
+        assert!(TlsCryptoInfoLen(&info).is_err());
+        info[..2].copy_from_slice(&0x0302u16.to_ne_bytes());
+        assert!(TlsCryptoInfoLen(&info).is_err());
+        assert!(TlsCryptoInfoLen(&info[..3]).is_err());
+
+        assert!(IsTlsUlp(LibcConst::SOL_TCP as i32, LibcConst::TCP_ULP as i32, b"tls\0"));
+        assert!(IsTlsUlp(LibcConst::SOL_TCP as i32, LibcConst::TCP_ULP as i32, b"tls"));
+        assert!(!IsTlsUlp(LibcConst::SOL_TCP as i32, LibcConst::TCP_ULP as i32, b"espintcp"));
+        assert!(!IsTlsUlp(LibcConst::SOL_TLS as i32, LibcConst::TCP_ULP as i32, b"tls"));
+    }
+}
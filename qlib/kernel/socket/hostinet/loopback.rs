@@ -0,0 +1,261 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+use crate::qlib::mutex::*;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::loopback_buf::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::guestfdnotifier::*;
+use super::super::super::data_buff::*;
+use super::super::super::task::*;
+use super::super::super::fd::*;
+use super::super::super::Kernel::HostSpace;
+
+// LoopbackSock is the TCP connection to another sandbox on the host. The data goes through the
+// ring pair of the shared memory, the host unix socket of the connection only carries the shared
+// memory fd and the doorbells: a byte is written to it when the ring of the peer turns from empty
+// to non empty, or from full to not full. The doorbells of the two directions are both EVENT_IN
+// of the host socket.
+pub struct LoopbackSock {
+    fd: i32,
+    client: bool,
+    // the shared memory of the server is received with the first doorbell of the client
+    buf: QMutex<Option<Arc<LoopbackBuf>>>,
+    // the peer has closed the host socket
+    peerClosed: AtomicBool,
+    // SHUT_RD of this side
+    rclosed: AtomicBool,
+}
+
+impl Drop for LoopbackSock {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.lock().take() {
+            HostSpace::MUnmap(buf.Addr(), LOOPBACK_SHM_SIZE);
+        }
+    }
+}
+
+impl LoopbackSock {
+    // Connected returns the loopback connection of the connected socket, None when the host
+    // connected it over the host TCP stack
+    pub fn Connected(fd: i32) -> Result<Option<Arc<Self>>> {
+        let shm = HostSpace::LoopbackShm(fd) as i32;
+        if shm == -SysErr::ENOENT {
+            return Ok(None)
+        }
+
+        if shm < 0 {
+            return Err(Error::SysError(-shm))
+        }
+
+        let sock = Self::New(fd, true);
+        *sock.buf.lock() = Some(Self::Map(shm, true)?);
+        return Ok(Some(Arc::new(sock)))
+    }
+
+    pub fn Accepted(fd: i32) -> Arc<Self> {
+        return Arc::new(Self::New(fd, false))
+    }
+
+    fn New(fd: i32, client: bool) -> Self {
+        return Self {
+            fd: fd,
+            client: client,
+            buf: QMutex::new(None),
+            peerClosed: AtomicBool::new(false),
+            rclosed: AtomicBool::new(false),
+        }
+    }
+
+    fn Map(shm: i32, client: bool) -> Result<Arc<LoopbackBuf>> {
+        let prot = (MmapProt::PROT_READ | MmapProt::PROT_WRITE) as i32;
+        let addr = HostSpace::MMapFile(LOOPBACK_SHM_SIZE, shm, 0, prot);
+        HostSpace::Close(shm);
+        if addr < 0 {
+            return Err(Error::SysError(-addr as i32))
+        }
+
+        return Ok(Arc::new(LoopbackBuf::New(addr as u64, client)))
+    }
+
+    // Buf returns the ring pair, the server gets it from the host socket when the first doorbell
+    // arrives
+    fn Buf(&self) -> Result<Arc<LoopbackBuf>> {
+        let mut buf = self.buf.lock();
+        if let Some(b) = buf.as_ref() {
+            return Ok(b.clone())
+        }
+
+        let shm = HostSpace::LoopbackShm(self.fd) as i32;
+        if shm < 0 {
+            if shm == -SysErr::EAGAIN {
+                ClearNotified(self.fd, EVENT_IN);
+            }
+            return Err(Error::SysError(-shm))
+        }
+
+        let b = Self::Map(shm, self.client)?;
+        *buf = Some(b.clone());
+        return Ok(b)
+    }
+
+    // Ring wakes the peer, a full host socket means the peer has doorbells to read already
+    fn Ring(&self) {
+        let buf = DataBuff::New(1);
+        IOWrite(self.fd, &buf.Iovs()).ok();
+    }
+
+    // Drain reads the doorbells, it returns whether there are any. The doorbell of one direction
+    // may be read by the waiter of the other one, so the queue is notified.
+    fn Drain(&self, queue: &Queue) -> bool {
+        if self.buf.lock().is_none() {
+            return false
+        }
+
+        let buf = DataBuff::New(64);
+        let mut rung = false;
+        loop {
+            match IORead(self.fd, &buf.Iovs()) {
+                Ok(0) | Err(Error::SysError(SysErr::ECONNRESET)) => {
+                    self.peerClosed.store(true, Ordering::Release);
+                    rung = true;
+                    break;
+                }
+                Ok(_) => rung = true,
+                Err(_) => break,
+            }
+        }
+
+        if rung {
+            queue.Notify(EVENT_IN | EVENT_OUT);
+        }
+
+        return rung
+    }
+
+    pub fn PeerClosed(&self) -> bool {
+        return self.peerClosed.load(Ordering::Acquire)
+    }
+
+    pub fn AvailableDataSize(&self) -> usize {
+        match self.buf.lock().as_ref() {
+            None => return 0,
+            Some(b) => return b.AvailableDataSize(),
+        }
+    }
+
+    pub fn Readv(&self, task: &Task, queue: &Queue, dsts: &mut [IoVec], peek: bool) -> Result<i64> {
+        if self.rclosed.load(Ordering::Acquire) {
+            return Ok(0)
+        }
+
+        let buf = self.Buf()?;
+        loop {
+            match buf.Read(peek, |src| task.mm.CopyIovsOutFromIovs(task, src, dsts)) {
+                Ok((wasFull, cnt)) => {
+                    if wasFull {
+                        self.Ring();
+                    }
+                    return Ok(cnt as i64)
+                }
+                Err(Error::SysError(SysErr::EAGAIN)) => {
+                    // the data written before the close is read first
+                    if self.PeerClosed() {
+                        return Ok(0)
+                    }
+
+                    if !self.Drain(queue) {
+                        return Err(Error::SysError(SysErr::EAGAIN))
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn Writev(&self, task: &Task, queue: &Queue, srcs: &[IoVec]) -> Result<i64> {
+        let buf = self.Buf()?;
+        loop {
+            if self.PeerClosed() {
+                return Err(Error::SysError(SysErr::EPIPE))
+            }
+
+            match buf.Write(|dst| task.mm.CopyIovsOutToIovs(task, srcs, dst)) {
+                Ok((wasEmpty, cnt)) => {
+                    if wasEmpty {
+                        self.Ring();
+                    }
+                    return Ok(cnt as i64)
+                }
+                Err(Error::SysError(SysErr::EAGAIN)) => {
+                    if !self.Drain(queue) {
+                        return Err(Error::SysError(SysErr::EAGAIN))
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Events returns the readiness of the rings, the doorbells are read when the events asked
+    // are not ready so that the next doorbell is notified
+    pub fn Events(&self, queue: &Queue, mask: EventMask) -> EventMask {
+        let buf = match self.Buf() {
+            Ok(b) => b,
+            Err(Error::SysError(SysErr::EAGAIN)) => return 0,
+            Err(_) => return EVENT_ERR | EVENT_HUP,
+        };
+
+        let mut event = buf.Events();
+        if mask & (EVENT_IN | EVENT_OUT) & !event != 0 && self.Drain(queue) {
+            event = buf.Events();
+        }
+
+        if self.PeerClosed() {
+            event |= EVENT_IN | EVENT_OUT | EVENT_HUP;
+        }
+
+        if self.rclosed.load(Ordering::Acquire) {
+            event |= EVENT_IN;
+        }
+
+        return event
+    }
+
+    // Shutdown shuts down the rings, it returns false when the server doesn't have the shared
+    // memory yet and the write side is shut down with the host socket instead
+    pub fn Shutdown(&self, shutRead: bool, shutWrite: bool) -> bool {
+        if shutRead {
+            self.rclosed.store(true, Ordering::Release);
+        }
+
+        if !shutWrite {
+            return true
+        }
+
+        match self.Buf() {
+            Ok(buf) => {
+                buf.ShutdownWrite();
+                self.Ring();
+                return true
+            }
+            Err(_) => return false,
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::fs::file::*;
+use super::super::super::guestfdnotifier::*;
+use super::super::super::kernel::time::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::task::*;
+use super::super::super::tcpip::sockaddr::*;
+use super::super::super::Kernel::HostSpace;
+use super::ephemeral::*;
+use super::rights::*;
+use super::socket::*;
+
+// HostMMsgBuf is the kernel buffer of a message of the host recvmmsg/sendmmsg
+struct HostMMsgBuf {
+    buf: DataBuff,
+    iov: IoVec,
+    addr: [u8; SIZEOF_SOCKADDR],
+    control: Vec<u8>,
+}
+
+impl HostMMsgBuf {
+    fn New(buf: DataBuff, controlLen: usize) -> Self {
+        let iov = buf.IoVec();
+        let mut control = Vec::with_capacity(controlLen);
+        control.resize(controlLen, 0);
+        return Self {
+            buf: buf,
+            iov: iov,
+            addr: [0; SIZEOF_SOCKADDR],
+            control: control,
+        }
+    }
+}
+
+impl SocketOperations {
+    // BlockForHost waits for the host socket to be ready for mask after the host call fails with
+    // EWOULDBLOCK
+    fn BlockForHost(&self, task: &Task, mask: EventMask, deadline: Option<Time>) -> Result<()> {
+        ClearNotified(self.fd, mask);
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, mask);
+        defer!(self.EventUnregister(task, &general));
+        self.CheckClosed()?;
+        match task.blocker.BlockWithMonoTimer(true, deadline) {
+            Err(Error::ErrInterrupted) => {
+                return Err(Error::SysError(SysErr::ERESTARTSYS));
+            }
+            Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                return Err(Error::SysError(SysErr::EAGAIN));
+            }
+            Err(e) => {
+                return Err(e);
+            }
+            _ => return Ok(())
+        }
+    }
+
+    // HostRecvMMsgOnce receives the ready messages with one host recvmmsg, it blocks until there
+    // is one without MSG_DONTWAIT
+    fn HostRecvMMsgOnce(&self, task: &Task, msgs: &mut [MMsgRecv], flags: i32, deadline: Option<Time>) -> Result<Vec<RecvMsgResult>> {
+        let mut bufs = Vec::with_capacity(msgs.len());
+        for m in msgs.iter() {
+            let buf = self.NewRecvBuf(IoVec::NumBytes(&m.dsts))?;
+            bufs.push(HostMMsgBuf::New(buf, m.controlDataLen));
+        }
+
+        let mut hdrs: Vec<MMsgHdr> = Vec::with_capacity(msgs.len());
+        for (i, b) in bufs.iter_mut().enumerate() {
+            let mut hdr = MMsgHdr::default();
+            hdr.msgHdr.iov = &b.iov as *const _ as u64;
+            hdr.msgHdr.iovLen = 1;
+            if msgs[i].senderRequested {
+                hdr.msgHdr.msgName = &mut b.addr[0] as *mut _ as u64;
+                hdr.msgHdr.nameLen = SIZEOF_SOCKADDR as u32;
+            }
+
+            if b.control.len() > 0 {
+                hdr.msgHdr.msgControl = &mut b.control[0] as *mut _ as u64;
+                hdr.msgHdr.msgControlLen = b.control.len();
+            }
+
+            hdrs.push(hdr);
+        }
+
+        // the host fds of SCM_RIGHTS are owned by the guest files, see RightsFromHost
+        let cloexec = flags & MsgType::MSG_CMSG_CLOEXEC != 0;
+        let hostFlags = flags | MsgType::MSG_CMSG_CLOEXEC | MsgType::MSG_DONTWAIT;
+        let mut res = HostSpace::IORecvMMsg(self.fd, &mut hdrs[0] as *mut _ as u64, hdrs.len() as u32, hostFlags) as i32;
+        while res == -SysErr::EWOULDBLOCK && flags & MsgType::MSG_DONTWAIT == 0 {
+            self.BlockForHost(task, EVENT_READ, deadline)?;
+            res = HostSpace::IORecvMMsg(self.fd, &mut hdrs[0] as *mut _ as u64, hdrs.len() as u32, hostFlags) as i32;
+        }
+
+        if res < 0 {
+            return Err(HostErr("IORecvMMsg", self.fd, -res as i32))
+        }
+
+        let mut rightsFlags = Vec::with_capacity(res as usize);
+        for i in 0..res as usize {
+            let len = hdrs[i].msgHdr.msgControlLen;
+            rightsFlags.push(RightsFromHost(task, &mut bufs[i].control[..len], cloexec));
+        }
+
+        let mut rets = Vec::with_capacity(res as usize);
+        for i in 0..res as usize {
+            let hdr = &hdrs[i].msgHdr;
+            let b = &mut bufs[i];
+
+            // the length of the truncated datagram is the real one with MSG_TRUNC
+            let len = hdrs[i].msgLen as usize;
+            let count = core::cmp::min(len, b.buf.Len());
+            task.CopyDataOutToIovs(&b.buf.buf[0..count], &msgs[i].dsts)?;
+
+            let senderAddr = if msgs[i].senderRequested {
+                HostSockAddr(&b.addr, hdr.nameLen as usize)
+            } else {
+                None
+            };
+
+            let mut control = core::mem::replace(&mut b.control, Vec::new());
+            control.resize(hdr.msgControlLen, 0);
+            rets.push((len as i64, (hdr.msgFlags & !MsgType::MSG_CTRUNC) | rightsFlags[i], senderAddr, control));
+        }
+
+        return Ok(rets)
+    }
+
+    // HostRecvMMsg is the recvmmsg of the socket without guest buffer, the ready messages are
+    // received by one host call instead of a hostcall per message
+    pub fn HostRecvMMsg(&self, task: &Task, msgs: &mut [MMsgRecv], flags: i32, deadline: Option<Time>) -> Result<Vec<RecvMsgResult>> {
+        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_PEEK | MsgType::MSG_TRUNC | MsgType::MSG_CTRUNC | MsgType::MSG_WAITALL | MsgType::MSG_WAITFORONE | MsgType::MSG_CMSG_CLOEXEC) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let waitForOne = flags & MsgType::MSG_WAITFORONE != 0;
+        let mut flags = flags & !MsgType::MSG_WAITFORONE;
+        let mut rets = Vec::with_capacity(msgs.len());
+        while rets.len() < msgs.len() {
+            let start = rets.len();
+            match self.HostRecvMMsgOnce(task, &mut msgs[start..], flags, deadline) {
+                Err(e) => {
+                    if rets.len() > 0 {
+                        break;
+                    }
+
+                    return Err(e)
+                }
+                Ok(r) => rets.extend(r),
+            }
+
+            if waitForOne {
+                flags |= MsgType::MSG_DONTWAIT;
+            }
+        }
+
+        return Ok(rets)
+    }
+
+    // HostSendMMsg is the sendmmsg of the socket without guest buffer, the messages are sent by
+    // one host call as long as the host socket takes them
+    pub fn HostSendMMsg(&self, task: &Task, msgs: &mut [MMsgSend], flags: i32, deadline: Option<Time>) -> Result<Vec<i64>> {
+        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_FASTOPEN | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        // the messages up to the first denied destination are sent, same as the host error
+        let mut cnt = msgs.len();
+        for i in 0..msgs.len() {
+            if let Err(e) = self.CheckSendPolicy(task, &msgs[i].srcs, &msgs[i].msgHdr) {
+                if i == 0 {
+                    return Err(e)
+                }
+                cnt = i;
+                break;
+            }
+        }
+        let msgs = &msgs[..cnt];
+
+        let mut bufs = Vec::with_capacity(msgs.len());
+        for m in msgs.iter() {
+            let mut b = HostMMsgBuf::New(DataBuff::NewUser(IoVec::NumBytes(&m.srcs))?, 0);
+            task.CopyDataInFromIovs(&mut b.buf.buf, &m.srcs)?;
+            bufs.push(b);
+        }
+
+        let mut hdrs: Vec<MMsgHdr> = Vec::with_capacity(msgs.len());
+        for (i, b) in bufs.iter().enumerate() {
+            let mut hdr = MMsgHdr {
+                msgHdr: msgs[i].msgHdr,
+                msgLen: 0,
+            };
+            hdr.msgHdr.iov = &b.iov as *const _ as u64;
+            hdr.msgHdr.iovLen = 1;
+            hdr.msgHdr.msgFlags = 0;
+            hdrs.push(hdr);
+        }
+
+        // the rights hold the host fds of SCM_RIGHTS until the host sendmmsg returns
+        let mut rights = Vec::new();
+        for hdr in hdrs.iter() {
+            rights.push(RightsToHost(task, &hdr.msgHdr)?);
+        }
+
+        // sendto of the unbound udp socket
+        if self.stype == SockType::SOCK_DGRAM && hdrs.iter().any(|h| h.msgHdr.msgName != 0) {
+            ImplicitBind(self.fd, self.family, self.stype)?;
+        }
+
+        let mut rets = Vec::with_capacity(msgs.len());
+        while rets.len() < hdrs.len() {
+            let start = rets.len();
+            let res = HostSpace::IOSendMMsg(self.fd, &mut hdrs[start] as *mut _ as u64, (hdrs.len() - start) as u32, flags | MsgType::MSG_DONTWAIT | MsgType::MSG_NOSIGNAL) as i32;
+            if res == -SysErr::EWOULDBLOCK && flags & MsgType::MSG_DONTWAIT == 0 {
+                match self.BlockForHost(task, EVENT_WRITE, deadline) {
+                    Err(e) => {
+                        if rets.len() > 0 {
+                            break;
+                        }
+
+                        return Err(e)
+                    }
+                    Ok(()) => continue,
+                }
+            }
+
+            if res < 0 {
+                if rets.len() > 0 {
+                    break;
+                }
+
+                return Err(HostErr("IOSendMMsg", self.fd, -res as i32))
+            }
+
+            for i in start..start + res as usize {
+                rets.push(hdrs[i].msgLen as i64);
+            }
+        }
+
+        return Ok(rets)
+    }
+}
@@ -15,6 +15,23 @@
 pub mod socket;
 pub mod socket_buf;
 pub mod rdma_socket;
+pub mod connect;
+pub mod multicast;
+pub mod bind_device;
+pub mod ephemeral;
+pub mod cork;
+pub mod nat;
+pub mod policy;
+pub mod ktls;
+pub mod sendfile;
+pub mod dgram;
+pub mod mmsg;
+pub mod errqueue;
+pub mod autotune;
+pub mod idle;
+pub mod rights;
+pub mod stats;
+pub mod loopback;
 
 pub fn Init() {
     self::socket::Init();
@@ -0,0 +1,546 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::btree_set::BTreeSet;
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::linux::socket::*;
+
+// MulticastMembership is one multicast group joined by the socket. The interface is
+// either the guest interface index or the interface address, the index is translated
+// to the host one only when the option is passed to the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MulticastMembership {
+    pub group: [u8; 16],
+    pub ifAddr: [u8; 4],
+    pub ifIndex: i32,
+}
+
+impl MulticastMembership {
+    // parse the ip_mreq/ip_mreqn of IP_ADD_MEMBERSHIP/IP_DROP_MEMBERSHIP
+    pub fn NewV4(opt: &[u8]) -> Result<Self> {
+        let mreqn = if opt.len() >= SIZEOF_IP_MREQN {
+            unsafe {
+                core::ptr::read_unaligned(&opt[0] as * const _ as u64 as * const IPMreqn)
+            }
+        } else if opt.len() >= SIZEOF_IP_MREQ {
+            let mreq = unsafe {
+                core::ptr::read_unaligned(&opt[0] as * const _ as u64 as * const IPMreq)
+            };
+
+            IPMreqn {
+                MulticastAddr: mreq.MulticastAddr,
+                InterfaceAddr: mreq.InterfaceAddr,
+                InterfaceIndex: 0,
+            }
+        } else {
+            return Err(Error::SysError(SysErr::EINVAL))
+        };
+
+        // 224.0.0.0/4
+        if mreqn.MulticastAddr[0] & 0xf0 != 0xe0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mut group = [0; 16];
+        group[..4].copy_from_slice(&mreqn.MulticastAddr);
+        return Ok(Self {
+            group: group,
+            ifAddr: mreqn.InterfaceAddr,
+            ifIndex: mreqn.InterfaceIndex,
+        })
+    }
+
+    // parse the ipv6_mreq of IPV6_ADD_MEMBERSHIP/IPV6_DROP_MEMBERSHIP
+    pub fn NewV6(opt: &[u8]) -> Result<Self> {
+        if opt.len() < SIZEOF_IPV6_MREQ {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mreq = unsafe {
+            core::ptr::read_unaligned(&opt[0] as * const _ as u64 as * const IPv6Mreq)
+        };
+
+        // ff00::/8
+        if mreq.MulticastAddr[0] != 0xff {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        return Ok(Self {
+            group: mreq.MulticastAddr,
+            ifAddr: [0; 4],
+            ifIndex: mreq.InterfaceIndex,
+        })
+    }
+}
+
+// MulticastOp is the membership change requested by a setsockopt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MulticastOp {
+    Join(MulticastMembership),
+    Leave(MulticastMembership),
+}
+
+impl MulticastOp {
+    // Parse validates the membership setsockopt before it is passed to the host.
+    // Return None if the option is not a membership option.
+    pub fn Parse(level: i32, name: i32, opt: &[u8]) -> Result<Option<Self>> {
+        let op = match (level as u64, name as u64) {
+            (LibcConst::SOL_IP, LibcConst::IP_ADD_MEMBERSHIP) => Self::Join(MulticastMembership::NewV4(opt)?),
+            (LibcConst::SOL_IP, LibcConst::IP_DROP_MEMBERSHIP) => Self::Leave(MulticastMembership::NewV4(opt)?),
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_ADD_MEMBERSHIP) => Self::Join(MulticastMembership::NewV6(opt)?),
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_DROP_MEMBERSHIP) => Self::Leave(MulticastMembership::NewV6(opt)?),
+            _ => return Ok(None),
+        };
+
+        return Ok(Some(op))
+    }
+}
+
+// MulticastOpt is the multicast send option requested by a setsockopt. The interface index
+// of IP_MULTICAST_IF/IPV6_MULTICAST_IF is the guest one, see HostIfIndexOpt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MulticastOpt {
+    IfV4(IPMreqn),
+    TtlV4(i32),
+    LoopV4(bool),
+    IfV6(i32),
+    HopsV6(i32),
+    LoopV6(bool),
+}
+
+impl MulticastOpt {
+    // Parse validates the option the same as linux do_ip_setsockopt/do_ipv6_setsockopt.
+    // Return None if the option is not a multicast send option.
+    pub fn Parse(level: i32, name: i32, opt: &[u8]) -> Result<Option<Self>> {
+        let o = match (level as u64, name as u64) {
+            (LibcConst::SOL_IP, LibcConst::IP_MULTICAST_IF) => Self::IfV4(ParseMulticastIf(opt)?),
+            (LibcConst::SOL_IP, LibcConst::IP_MULTICAST_TTL) => {
+                // -1 is the default ttl
+                let val = match IPOptVal(opt)? {
+                    -1 => 1,
+                    v if v < 0 || v > 255 => return Err(Error::SysError(SysErr::EINVAL)),
+                    v => v,
+                };
+
+                Self::TtlV4(val)
+            }
+            (LibcConst::SOL_IP, LibcConst::IP_MULTICAST_LOOP) => Self::LoopV4(IPOptVal(opt)? != 0),
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_MULTICAST_IF) => {
+                let val = IPv6OptVal(opt)?;
+                if val < 0 {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                Self::IfV6(val)
+            }
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_MULTICAST_HOPS) => {
+                let val = match IPv6OptVal(opt)? {
+                    -1 => 1,
+                    v if v < -1 || v > 255 => return Err(Error::SysError(SysErr::EINVAL)),
+                    v => v,
+                };
+
+                Self::HopsV6(val)
+            }
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_MULTICAST_LOOP) => {
+                let val = IPv6OptVal(opt)?;
+                if val != 0 && val != 1 {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                Self::LoopV6(val == 1)
+            }
+            _ => return Ok(None),
+        };
+
+        return Ok(Some(o))
+    }
+}
+
+// ParseMulticastIf parses the in_addr, ip_mreq or ip_mreqn of IP_MULTICAST_IF, the interface
+// address of ip_mreq is its second field
+pub fn ParseMulticastIf(opt: &[u8]) -> Result<IPMreqn> {
+    let mut mreqn = IPMreqn::default();
+    if opt.len() >= SIZEOF_IP_MREQN {
+        mreqn = unsafe {
+            core::ptr::read_unaligned(&opt[0] as * const _ as u64 as * const IPMreqn)
+        };
+    } else if opt.len() >= SIZEOF_IP_MREQ {
+        mreqn.InterfaceAddr.copy_from_slice(&opt[4..8]);
+    } else if opt.len() >= 4 {
+        mreqn.InterfaceAddr.copy_from_slice(&opt[..4]);
+    } else {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    if mreqn.InterfaceIndex < 0 {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+
+    return Ok(mreqn)
+}
+
+// IfIndexOffset returns the offset of the interface index in the multicast option, None if the
+// option doesn't carry one
+pub fn IfIndexOffset(level: i32, name: i32, optLen: usize) -> Option<usize> {
+    match (level as u64, name as u64) {
+        (LibcConst::SOL_IP, LibcConst::IP_ADD_MEMBERSHIP) |
+        (LibcConst::SOL_IP, LibcConst::IP_DROP_MEMBERSHIP) |
+        (LibcConst::SOL_IP, LibcConst::IP_MULTICAST_IF) if optLen >= SIZEOF_IP_MREQN => return Some(8),
+        (LibcConst::SOL_IPV6, LibcConst::IPV6_ADD_MEMBERSHIP) |
+        (LibcConst::SOL_IPV6, LibcConst::IPV6_DROP_MEMBERSHIP) if optLen >= SIZEOF_IPV6_MREQ => return Some(16),
+        (LibcConst::SOL_IPV6, LibcConst::IPV6_MULTICAST_IF) if optLen >= 4 => return Some(0),
+        _ => return None,
+    }
+}
+
+// HostIfIndexOpt returns the option with the interface index at offset translated by hostIndex
+// from the guest index to the host one
+pub fn HostIfIndexOpt(opt: &[u8], offset: usize, hostIndex: &mut dyn FnMut(i32) -> Result<i32>) -> Result<Vec<u8>> {
+    let mut hostOpt = opt.to_vec();
+    let index = i32::from_ne_bytes([opt[offset], opt[offset+1], opt[offset+2], opt[offset+3]]);
+    let index = hostIndex(index)?;
+    hostOpt[offset..offset+4].copy_from_slice(&index.to_ne_bytes());
+    return Ok(hostOpt)
+}
+
+// IPOptVal reads the int or the unsigned char value of the SOL_IP option
+fn IPOptVal(opt: &[u8]) -> Result<i32> {
+    if opt.len() >= 4 {
+        return Ok(i32::from_ne_bytes([opt[0], opt[1], opt[2], opt[3]]))
+    }
+
+    if opt.len() >= 1 {
+        return Ok(opt[0] as i32)
+    }
+
+    return Err(Error::SysError(SysErr::EINVAL))
+}
+
+// IPv6OptVal reads the int value of the SOL_IPV6 option, a shorter value is not allowed
+fn IPv6OptVal(opt: &[u8]) -> Result<i32> {
+    if opt.len() < 4 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    return Ok(i32::from_ne_bytes([opt[0], opt[1], opt[2], opt[3]]))
+}
+
+// MulticastOpts is the guest copy of the multicast send options of the socket, it is updated
+// after the host setsockopt succeeds and answers the getsockopt
+#[derive(Debug, Clone, Copy)]
+pub struct MulticastOpts {
+    pub ifAddr: [u8; 4],
+    pub ifIndex: i32,
+    pub ttl: i32,
+    pub loopV4: bool,
+    pub ifIndexV6: i32,
+    pub hops: i32,
+    pub loopV6: bool,
+}
+
+impl Default for MulticastOpts {
+    fn default() -> Self {
+        return Self {
+            ifAddr: [0; 4],
+            ifIndex: 0,
+            ttl: 1,
+            loopV4: true,
+            ifIndexV6: 0,
+            hops: 1,
+            loopV6: true,
+        }
+    }
+}
+
+impl MulticastOpts {
+    pub fn Apply(&mut self, o: &MulticastOpt) {
+        match *o {
+            MulticastOpt::IfV4(mreqn) => {
+                self.ifAddr = mreqn.InterfaceAddr;
+                self.ifIndex = mreqn.InterfaceIndex;
+            }
+            MulticastOpt::TtlV4(v) => self.ttl = v,
+            MulticastOpt::LoopV4(v) => self.loopV4 = v,
+            MulticastOpt::IfV6(v) => self.ifIndexV6 = v,
+            MulticastOpt::HopsV6(v) => self.hops = v,
+            MulticastOpt::LoopV6(v) => self.loopV6 = v,
+        }
+    }
+
+    // Get writes the option to opt the same as linux do_ip_getsockopt/do_ipv6_getsockopt and
+    // returns the length. Return None if the option is not a multicast send option.
+    pub fn Get(&self, level: i32, name: i32, opt: &mut [u8]) -> Result<Option<usize>> {
+        let val = match (level as u64, name as u64) {
+            (LibcConst::SOL_IP, LibcConst::IP_MULTICAST_IF) => {
+                let n = core::cmp::min(opt.len(), 4);
+                opt[..n].copy_from_slice(&self.ifAddr[..n]);
+                return Ok(Some(n))
+            }
+            (LibcConst::SOL_IP, LibcConst::IP_MULTICAST_TTL) => self.ttl,
+            (LibcConst::SOL_IP, LibcConst::IP_MULTICAST_LOOP) => self.loopV4 as i32,
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_MULTICAST_IF) => self.ifIndexV6,
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_MULTICAST_HOPS) => self.hops,
+            (LibcConst::SOL_IPV6, LibcConst::IPV6_MULTICAST_LOOP) => self.loopV6 as i32,
+            _ => return Ok(None),
+        };
+
+        if level as u64 == LibcConst::SOL_IPV6 {
+            if opt.len() < 4 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+        } else if opt.len() < 4 && opt.len() > 0 && val >= 0 && val <= 255 {
+            // the short buffer gets an unsigned char
+            opt[0] = val as u8;
+            return Ok(Some(1))
+        }
+
+        let n = core::cmp::min(opt.len(), 4);
+        opt[..n].copy_from_slice(&val.to_ne_bytes()[..n]);
+        return Ok(Some(n))
+    }
+}
+
+// MulticastGroups is the guest copy of the socket memberships. The host socket does the
+// IGMP/MLD and the limit check (igmp_max_memberships), the guest copy is only updated
+// after the host setsockopt succeeds.
+#[derive(Debug, Default)]
+pub struct MulticastGroups {
+    pub groups: BTreeSet<MulticastMembership>,
+}
+
+impl MulticastGroups {
+    // Check fails the membership change the same as linux before it reaches the host: joining
+    // a group twice on the interface is EADDRINUSE, leaving a group not joined is EADDRNOTAVAIL
+    pub fn Check(&self, op: &MulticastOp) -> Result<()> {
+        match op {
+            MulticastOp::Join(m) => {
+                if self.groups.contains(m) {
+                    return Err(Error::SysError(SysErr::EADDRINUSE))
+                }
+            }
+            MulticastOp::Leave(m) => {
+                if self.Find(m).is_none() {
+                    return Err(Error::SysError(SysErr::EADDRNOTAVAIL))
+                }
+            }
+        }
+
+        return Ok(())
+    }
+
+    pub fn Apply(&mut self, op: &MulticastOp) {
+        match op {
+            MulticastOp::Join(m) => {
+                self.groups.insert(*m);
+            }
+            MulticastOp::Leave(m) => {
+                if let Some(joined) = self.Find(m) {
+                    self.groups.remove(&joined);
+                }
+            }
+        }
+    }
+
+    // Find returns the membership dropped by the leave, linux drops the membership of the group
+    // on any interface when no interface is given
+    fn Find(&self, m: &MulticastMembership) -> Option<MulticastMembership> {
+        if m.ifIndex == 0 && m.ifAddr == [0; 4] {
+            return self.groups.iter().find(|x| x.group == m.group).cloned()
+        }
+
+        return self.groups.get(m).cloned()
+    }
+
+    pub fn Count(&self) -> usize {
+        return self.groups.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_mreq() {
+        let opt = [224, 0, 0, 251, 0, 0, 0, 0];
+        let op = MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_ADD_MEMBERSHIP as i32, &opt).unwrap();
+        let mut groups = MulticastGroups::default();
+        groups.Apply(op.as_ref().unwrap());
+        assert_eq!(groups.Count(), 1);
+
+        let op = MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_DROP_MEMBERSHIP as i32, &opt).unwrap();
+        groups.Apply(op.as_ref().unwrap());
+        assert_eq!(groups.Count(), 0);
+
+        // leaving a group not joined
+        assert!(groups.Check(op.as_ref().unwrap()) == Err(Error::SysError(SysErr::EADDRNOTAVAIL)));
+
+        // unicast group and short option
+        let opt = [10, 0, 0, 1, 0, 0, 0, 0];
+        assert!(MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_ADD_MEMBERSHIP as i32, &opt).is_err());
+        assert!(MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_ADD_MEMBERSHIP as i32, &opt[..4]).is_err());
+    }
+
+    #[test]
+    fn test_check_membership() {
+        let mut groups = MulticastGroups::default();
+        let eth1 = [224, 0, 0, 251, 0, 0, 0, 0, 1, 0, 0, 0];
+        let join = MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_ADD_MEMBERSHIP as i32, &eth1).unwrap().unwrap();
+        assert!(groups.Check(&join).is_ok());
+        groups.Apply(&join);
+        assert!(groups.Check(&join) == Err(Error::SysError(SysErr::EADDRINUSE)));
+
+        // the same group on another interface is another membership
+        let eth2 = [224, 0, 0, 251, 0, 0, 0, 0, 2, 0, 0, 0];
+        let join2 = MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_ADD_MEMBERSHIP as i32, &eth2).unwrap().unwrap();
+        assert!(groups.Check(&join2).is_ok());
+        groups.Apply(&join2);
+
+        let leave2 = MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_DROP_MEMBERSHIP as i32, &eth2).unwrap().unwrap();
+        groups.Apply(&leave2);
+        assert!(groups.Check(&leave2) == Err(Error::SysError(SysErr::EADDRNOTAVAIL)));
+
+        // no interface leaves the membership on any interface
+        let any = [224, 0, 0, 251, 0, 0, 0, 0];
+        let leave = MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_DROP_MEMBERSHIP as i32, &any).unwrap().unwrap();
+        assert!(groups.Check(&leave).is_ok());
+        groups.Apply(&leave);
+        assert_eq!(groups.Count(), 0);
+    }
+
+    #[test]
+    fn test_parse_ipv6_mreq() {
+        let mut opt = [0u8; SIZEOF_IPV6_MREQ];
+        opt[0] = 0xff;
+        opt[1] = 0x02;
+        opt[15] = 0xfb;
+        let op = MulticastOp::Parse(LibcConst::SOL_IPV6 as i32, LibcConst::IPV6_ADD_MEMBERSHIP as i32, &opt).unwrap();
+        assert!(op.is_some());
+
+        opt[0] = 0xfe;
+        assert!(MulticastOp::Parse(LibcConst::SOL_IPV6 as i32, LibcConst::IPV6_ADD_MEMBERSHIP as i32, &opt).is_err());
+
+        // other options are passed through
+        assert_eq!(MulticastOp::Parse(LibcConst::SOL_IP as i32, LibcConst::IP_MULTICAST_LOOP as i32, &opt).unwrap(), None);
+    }
+
+    #[test]
+    fn test_multicast_if() {
+        let ip = LibcConst::SOL_IP as i32;
+        let name = LibcConst::IP_MULTICAST_IF as i32;
+        let mut opts = MulticastOpts::default();
+
+        // in_addr, ip_mreq and ip_mreqn
+        let o = MulticastOpt::Parse(ip, name, &[192, 168, 0, 1]).unwrap().unwrap();
+        opts.Apply(&o);
+        assert_eq!(opts.ifAddr, [192, 168, 0, 1]);
+
+        let o = MulticastOpt::Parse(ip, name, &[224, 0, 0, 251, 10, 0, 0, 1]).unwrap().unwrap();
+        opts.Apply(&o);
+        assert_eq!(opts.ifAddr, [10, 0, 0, 1]);
+
+        let o = MulticastOpt::Parse(ip, name, &[0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0]).unwrap().unwrap();
+        opts.Apply(&o);
+        assert_eq!((opts.ifAddr, opts.ifIndex), ([0; 4], 2));
+
+        let mut out = [0u8; 4];
+        assert_eq!(opts.Get(ip, name, &mut out).unwrap(), Some(4));
+        assert_eq!(out, [0; 4]);
+
+        // shorter than in_addr
+        assert!(MulticastOpt::Parse(ip, name, &[]).is_err());
+        assert!(MulticastOpt::Parse(ip, name, &[192, 168]).is_err());
+    }
+
+    #[test]
+    fn test_host_ifindex_opt() {
+        let ip = LibcConst::SOL_IP as i32;
+        let ip6 = LibcConst::SOL_IPV6 as i32;
+        let mut translate = |index: i32| -> Result<i32> {
+            match index {
+                2 => Ok(7),
+                _ => Err(Error::SysError(SysErr::ENODEV)),
+            }
+        };
+
+        // ip_mreqn, the ip_mreq and in_addr select the interface by the address
+        let mreqn = [224, 0, 0, 251, 0, 0, 0, 0, 2, 0, 0, 0];
+        let offset = IfIndexOffset(ip, LibcConst::IP_ADD_MEMBERSHIP as i32, mreqn.len()).unwrap();
+        let hostOpt = HostIfIndexOpt(&mreqn, offset, &mut translate).unwrap();
+        assert_eq!(hostOpt, [224, 0, 0, 251, 0, 0, 0, 0, 7, 0, 0, 0]);
+        assert_eq!(IfIndexOffset(ip, LibcConst::IP_MULTICAST_IF as i32, SIZEOF_IP_MREQ), None);
+
+        let mut mreq6 = [0u8; SIZEOF_IPV6_MREQ];
+        mreq6[16] = 3;
+        let offset = IfIndexOffset(ip6, LibcConst::IPV6_ADD_MEMBERSHIP as i32, mreq6.len()).unwrap();
+        assert!(HostIfIndexOpt(&mreq6, offset, &mut translate).is_err());
+
+        let offset = IfIndexOffset(ip6, LibcConst::IPV6_MULTICAST_IF as i32, 4).unwrap();
+        assert_eq!(HostIfIndexOpt(&2i32.to_ne_bytes(), offset, &mut translate).unwrap(), 7i32.to_ne_bytes());
+        assert_eq!(IfIndexOffset(ip6, LibcConst::IPV6_MULTICAST_HOPS as i32, 4), None);
+    }
+
+    #[test]
+    fn test_parse_unaligned() {
+        // the option buffer of the setsockopt is not aligned for the i32 index
+        let mut buf = [0u8; SIZEOF_IP_MREQN + 1];
+        buf[1..].copy_from_slice(&[224, 0, 0, 251, 0, 0, 0, 0, 2, 0, 0, 0]);
+        let mreqn = ParseMulticastIf(&buf[1..]).unwrap();
+        assert_eq!(mreqn.InterfaceIndex, 2);
+
+        let m = MulticastMembership::NewV4(&buf[1..]).unwrap();
+        assert_eq!(m.ifIndex, 2);
+    }
+
+    #[test]
+    fn test_multicast_ttl_loop() {
+        let ip = LibcConst::SOL_IP as i32;
+        let ttl = LibcConst::IP_MULTICAST_TTL as i32;
+        let mut opts = MulticastOpts::default();
+
+        // int and unsigned char values, -1 is the default
+        opts.Apply(&MulticastOpt::Parse(ip, ttl, &255i32.to_ne_bytes()).unwrap().unwrap());
+        assert_eq!(opts.ttl, 255);
+        opts.Apply(&MulticastOpt::Parse(ip, ttl, &[4]).unwrap().unwrap());
+        assert_eq!(opts.ttl, 4);
+        opts.Apply(&MulticastOpt::Parse(ip, ttl, &(-1i32).to_ne_bytes()).unwrap().unwrap());
+        assert_eq!(opts.ttl, 1);
+        assert!(MulticastOpt::Parse(ip, ttl, &256i32.to_ne_bytes()).is_err());
+        assert!(MulticastOpt::Parse(ip, ttl, &[]).is_err());
+
+        let mut out = [0u8; 4];
+        assert_eq!(opts.Get(ip, ttl, &mut out[..1]).unwrap(), Some(1));
+        assert_eq!(out[0], 1);
+
+        let lp = LibcConst::IP_MULTICAST_LOOP as i32;
+        opts.Apply(&MulticastOpt::Parse(ip, lp, &[0]).unwrap().unwrap());
+        assert_eq!(opts.Get(ip, lp, &mut out).unwrap(), Some(4));
+        assert_eq!(i32::from_ne_bytes(out), 0);
+
+        // the ipv6 options take an int
+        let ip6 = LibcConst::SOL_IPV6 as i32;
+        let hops = LibcConst::IPV6_MULTICAST_HOPS as i32;
+        assert!(MulticastOpt::Parse(ip6, hops, &[4]).is_err());
+        assert!(MulticastOpt::Parse(ip6, LibcConst::IPV6_MULTICAST_LOOP as i32, &2i32.to_ne_bytes()).is_err());
+        opts.Apply(&MulticastOpt::Parse(ip6, hops, &(-1i32).to_ne_bytes()).unwrap().unwrap());
+        assert_eq!(opts.hops, 1);
+        assert!(opts.Get(ip6, hops, &mut out[..1]).is_err());
+
+        assert_eq!(MulticastOpt::Parse(ip, LibcConst::IP_ADD_MEMBERSHIP as i32, &[0; 8]).unwrap(), None);
+    }
+}
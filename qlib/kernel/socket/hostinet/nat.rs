@@ -0,0 +1,684 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// netfilter-lite: the nat table emulation of the hostinet sockets. The sidecar of a service mesh
+// (e.g. istio) programs iptables REDIRECT rules which the host can't apply to the sandbox, as the
+// host sees all the sandbox traffic from one process. The rules are loaded in the iptables-save
+// format from NAT_RULES_FILE and applied to the IPv4 TCP connections of the guest:
+//
+// . OUTPUT: connect rewrites the destination with the REDIRECT/DNAT target, the owner match
+//   uses the effective uid/gid of the connecting task.
+// . PREROUTING: accept hands the connection over to the guest listener of the REDIRECT port.
+//
+// The original destination is kept in the conntrack table for getsockopt SO_ORIGINAL_DST and
+// getpeername. The interface matches only distinguish lo (the loopback addresses) from the
+// others.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::mutex::*;
+use super::super::super::Kernel::HostSpace;
+use super::socket::*;
+
+pub const NAT_RULES_FILE: &str = "/etc/quark/iptables-nat.rules";
+pub const NAT_RULES_MAX: usize = 64 * 1024;
+
+pub const SIZEOF_SOCKADDR_INET: usize = 16;
+pub const LOOPBACK_ADDR: u32 = 0x7f000001;
+
+// the max depth of the user chain jumps
+const MAX_JUMP_DEPTH: usize = 16;
+// the max connections kept in the conntrack table, the oldest one is evicted
+const MAX_CONNTRACK: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NatTarget {
+    Accept,
+    Return,
+    Jump(String),
+    Redirect(u16),
+    Dnat(u32, Option<u16>),
+}
+
+impl Default for NatTarget {
+    fn default() -> Self {
+        return Self::Accept
+    }
+}
+
+// the matches are (negated, value...)
+#[derive(Debug, Clone, Default)]
+pub struct NatRule {
+    pub proto: Option<(bool, String)>,
+    pub src: Option<(bool, u32, u32)>,
+    pub dst: Option<(bool, u32, u32)>,
+    pub inIface: Option<(bool, String)>,
+    pub outIface: Option<(bool, String)>,
+    pub sport: Option<(bool, u16, u16)>,
+    pub dport: Option<(bool, u16, u16)>,
+    pub uid: Option<(bool, u32)>,
+    pub gid: Option<(bool, u32)>,
+    pub target: NatTarget,
+}
+
+// NatConn is the connection which goes through the nat chains
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NatConn {
+    pub src: u32,
+    pub sport: u16,
+    pub dst: u32,
+    pub dport: u16,
+    pub uid: u32,
+    pub gid: u32,
+    // the connection is from the guest (OUTPUT), not to the guest (PREROUTING)
+    pub output: bool,
+}
+
+pub fn IsLoopback(addr: u32) -> bool {
+    return addr >> 24 == 127
+}
+
+fn IfaceMatch(name: &str, addr: u32) -> bool {
+    if name == "lo" {
+        return IsLoopback(addr)
+    }
+
+    return !IsLoopback(addr)
+}
+
+impl NatRule {
+    pub fn Match(&self, conn: &NatConn) -> bool {
+        if let Some((neg, proto)) = &self.proto {
+            let tcp = proto == "tcp" || proto == "6" || proto == "all";
+            if tcp == *neg {
+                return false
+            }
+        }
+
+        let addrs = [(&self.src, conn.src), (&self.dst, conn.dst)];
+        for (m, addr) in &addrs {
+            if let Some((neg, net, mask)) = m {
+                if (*addr & *mask == *net) == *neg {
+                    return false
+                }
+            }
+        }
+
+        let ports = [(&self.sport, conn.sport), (&self.dport, conn.dport)];
+        for (m, port) in &ports {
+            if let Some((neg, start, end)) = m {
+                if (*start <= *port && *port <= *end) == *neg {
+                    return false
+                }
+            }
+        }
+
+        if let Some((neg, iface)) = &self.inIface {
+            if conn.output || IfaceMatch(iface, conn.src) == *neg {
+                return false
+            }
+        }
+
+        if let Some((neg, iface)) = &self.outIface {
+            if !conn.output || IfaceMatch(iface, conn.dst) == *neg {
+                return false
+            }
+        }
+
+        // the owner match is only valid in OUTPUT
+        let owners = [(&self.uid, conn.uid), (&self.gid, conn.gid)];
+        for (m, id) in &owners {
+            if let Some((neg, want)) = m {
+                if !conn.output || (*id == *want) == *neg {
+                    return false
+                }
+            }
+        }
+
+        return true
+    }
+}
+
+fn ParseAddr(s: &str) -> Result<u32> {
+    let mut addr: u32 = 0;
+    let mut cnt = 0;
+    for part in s.split('.') {
+        let b = part.parse::<u8>().map_err(|_| Error::Common(format!("invalid address {}", s)))?;
+        addr = addr << 8 | b as u32;
+        cnt += 1;
+    }
+
+    if cnt != 4 {
+        return Err(Error::Common(format!("invalid address {}", s)))
+    }
+
+    return Ok(addr)
+}
+
+// ParseNet parses addr[/prefixlen], it returns (net, mask)
+fn ParseNet(s: &str) -> Result<(u32, u32)> {
+    let (addr, prefix) = match s.find('/') {
+        None => (s, 32),
+        Some(n) => {
+            let prefix = s[n + 1..].parse::<u32>().map_err(|_| Error::Common(format!("invalid net {}", s)))?;
+            if prefix > 32 {
+                return Err(Error::Common(format!("invalid net {}", s)))
+            }
+            (&s[..n], prefix)
+        }
+    };
+
+    let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix) };
+    return Ok((ParseAddr(addr)? & mask, mask))
+}
+
+fn ParsePort(s: &str) -> Result<u16> {
+    return s.parse::<u16>().map_err(|_| Error::Common(format!("invalid port {}", s)))
+}
+
+// ParsePortRange parses port or start:end
+fn ParsePortRange(s: &str) -> Result<(u16, u16)> {
+    match s.find(':') {
+        None => {
+            let port = ParsePort(s)?;
+            return Ok((port, port))
+        }
+        Some(n) => return Ok((ParsePort(&s[..n])?, ParsePort(&s[n + 1..])?)),
+    }
+}
+
+// Tokenize splits the rule line, the double quoted words (e.g. of --comment) are one token
+fn Tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut cur = String::new();
+    let mut quoted = false;
+    let mut inToken = false;
+    for c in line.chars() {
+        if c == '"' {
+            quoted = !quoted;
+            inToken = true;
+        } else if c.is_whitespace() && !quoted {
+            if inToken {
+                tokens.push(core::mem::replace(&mut cur, String::new()));
+                inToken = false;
+            }
+        } else {
+            cur.push(c);
+            inToken = true;
+        }
+    }
+
+    if inToken {
+        tokens.push(cur);
+    }
+
+    return tokens
+}
+
+// ParseRule parses the rule after "-A", it returns (chain, rule)
+pub fn ParseRule(tokens: &[String]) -> Result<(String, NatRule)> {
+    if tokens.len() == 0 {
+        return Err(Error::Common("missing chain".to_string()))
+    }
+
+    let chain = tokens[0].to_string();
+    let mut rule = NatRule::default();
+    let mut target: Option<String> = None;
+    let mut toPorts: Option<u16> = None;
+    let mut toDest: Option<(u32, Option<u16>)> = None;
+    let mut neg = false;
+    let mut i = 1;
+    while i < tokens.len() {
+        let opt = tokens[i].as_str();
+        if opt == "!" {
+            neg = true;
+            i += 1;
+            continue;
+        }
+
+        let val = match tokens.get(i + 1) {
+            None => return Err(Error::Common(format!("missing value of {}", opt))),
+            Some(v) => v.as_str(),
+        };
+
+        match opt {
+            "-p" | "--protocol" => rule.proto = Some((neg, val.to_string())),
+            "-s" | "--source" => {
+                let (net, mask) = ParseNet(val)?;
+                rule.src = Some((neg, net, mask));
+            }
+            "-d" | "--destination" => {
+                let (net, mask) = ParseNet(val)?;
+                rule.dst = Some((neg, net, mask));
+            }
+            "-i" | "--in-interface" => rule.inIface = Some((neg, val.to_string())),
+            "-o" | "--out-interface" => rule.outIface = Some((neg, val.to_string())),
+            "--sport" | "--source-port" => {
+                let (start, end) = ParsePortRange(val)?;
+                rule.sport = Some((neg, start, end));
+            }
+            "--dport" | "--destination-port" => {
+                let (start, end) = ParsePortRange(val)?;
+                rule.dport = Some((neg, start, end));
+            }
+            "--uid-owner" => {
+                let uid = val.parse::<u32>().map_err(|_| Error::Common(format!("invalid uid {}", val)))?;
+                rule.uid = Some((neg, uid));
+            }
+            "--gid-owner" => {
+                let gid = val.parse::<u32>().map_err(|_| Error::Common(format!("invalid gid {}", val)))?;
+                rule.gid = Some((neg, gid));
+            }
+            // the match modules (tcp, owner, comment) need no handling
+            "-m" | "--match" | "--comment" => (),
+            "-j" | "--jump" => target = Some(val.to_string()),
+            "--to-ports" => {
+                // the first port of the range
+                let port = val.split('-').next().unwrap();
+                toPorts = Some(ParsePort(port)?);
+            }
+            "--to-destination" => {
+                let dest = match val.find(':') {
+                    None => (ParseAddr(val)?, None),
+                    Some(n) => (ParseAddr(&val[..n])?, Some(ParsePort(&val[n + 1..])?)),
+                };
+                toDest = Some(dest);
+            }
+            _ => return Err(Error::Common(format!("unsupported option {}", opt))),
+        }
+
+        neg = false;
+        i += 2;
+    }
+
+    rule.target = match target.as_ref().map(|t| t.as_str()) {
+        None | Some("ACCEPT") => NatTarget::Accept,
+        Some("RETURN") => NatTarget::Return,
+        Some("REDIRECT") => match toPorts {
+            None => return Err(Error::Common("REDIRECT without --to-ports".to_string())),
+            Some(port) => NatTarget::Redirect(port),
+        },
+        Some("DNAT") => match toDest {
+            None => return Err(Error::Common("DNAT without --to-destination".to_string())),
+            Some((addr, port)) => NatTarget::Dnat(addr, port),
+        },
+        Some(chain) => NatTarget::Jump(chain.to_string()),
+    };
+
+    return Ok((chain, rule))
+}
+
+// ParseIptablesSave parses the nat table of the iptables-save output. The invalid rules are
+// skipped with error, so that the rest of the table still works.
+pub fn ParseIptablesSave(text: &str) -> BTreeMap<String, Vec<NatRule>> {
+    let mut chains: BTreeMap<String, Vec<NatRule>> = BTreeMap::new();
+    let mut inNat = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.len() == 0 || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('*') {
+            inNat = line == "*nat";
+            continue;
+        }
+
+        if !inNat || line == "COMMIT" {
+            continue;
+        }
+
+        if line.starts_with(':') {
+            // the chain declaration, e.g. ":ISTIO_OUTPUT - [0:0]"
+            let name = line[1..].split_whitespace().next().unwrap_or("");
+            chains.entry(name.to_string()).or_insert(Vec::new());
+            continue;
+        }
+
+        let tokens = Tokenize(line);
+        if tokens.len() == 0 || tokens[0] != "-A" {
+            error!("nat: ignoring unsupported line {}", line);
+            continue;
+        }
+
+        match ParseRule(&tokens[1..]) {
+            Err(e) => error!("nat: ignoring rule {}: {:?}", line, e),
+            Ok((chain, rule)) => chains.entry(chain).or_insert(Vec::new()).push(rule),
+        }
+    }
+
+    return chains
+}
+
+enum ChainVerdict {
+    // the connection is not rewritten
+    Accept,
+    // the end of the user chain or RETURN, the calling chain continues
+    Return,
+    Rewrite(u32, u16),
+}
+
+fn EvalChain(chains: &BTreeMap<String, Vec<NatRule>>, name: &str, conn: &NatConn, depth: usize) -> ChainVerdict {
+    if depth > MAX_JUMP_DEPTH {
+        error!("nat: chain {} is too deep", name);
+        return ChainVerdict::Accept
+    }
+
+    let rules = match chains.get(name) {
+        None => return ChainVerdict::Return,
+        Some(r) => r,
+    };
+
+    for rule in rules {
+        if !rule.Match(conn) {
+            continue;
+        }
+
+        match &rule.target {
+            NatTarget::Accept => return ChainVerdict::Accept,
+            NatTarget::Return => return ChainVerdict::Return,
+            NatTarget::Redirect(port) => {
+                let addr = if conn.output { LOOPBACK_ADDR } else { conn.dst };
+                return ChainVerdict::Rewrite(addr, *port)
+            }
+            NatTarget::Dnat(addr, port) => {
+                return ChainVerdict::Rewrite(*addr, port.unwrap_or(conn.dport))
+            }
+            NatTarget::Jump(chain) => match EvalChain(chains, chain, conn, depth + 1) {
+                ChainVerdict::Return => (),
+                v => return v,
+            },
+        }
+    }
+
+    return ChainVerdict::Return
+}
+
+// EvalNat returns the new destination of the connection, None if it is not rewritten
+pub fn EvalNat(chains: &BTreeMap<String, Vec<NatRule>>, conn: &NatConn) -> Option<(u32, u16)> {
+    let chain = if conn.output { "OUTPUT" } else { "PREROUTING" };
+    match EvalChain(chains, chain, conn, 0) {
+        ChainVerdict::Rewrite(addr, port) => {
+            if addr == conn.dst && port == conn.dport {
+                return None
+            }
+            return Some((addr, port))
+        }
+        _ => return None,
+    }
+}
+
+// SockAddrV4 returns the (addr, port) of the AF_INET sockaddr
+pub fn SockAddrV4(sockaddr: &[u8]) -> Option<(u32, u16)> {
+    if sockaddr.len() < 8 || sockaddr[0] as i32 | (sockaddr[1] as i32) << 8 != AFType::AF_INET {
+        return None
+    }
+
+    let port = (sockaddr[2] as u16) << 8 | sockaddr[3] as u16;
+    let addr = u32::from_be_bytes([sockaddr[4], sockaddr[5], sockaddr[6], sockaddr[7]]);
+    return Some((addr, port))
+}
+
+pub fn NewSockAddrV4(addr: u32, port: u16) -> [u8; SIZEOF_SOCKADDR_INET] {
+    let mut sockaddr = [0; SIZEOF_SOCKADDR_INET];
+    sockaddr[0] = AFType::AF_INET as u8;
+    sockaddr[1] = (AFType::AF_INET >> 8) as u8;
+    sockaddr[2..4].copy_from_slice(&port.to_be_bytes());
+    sockaddr[4..8].copy_from_slice(&addr.to_be_bytes());
+    return sockaddr
+}
+
+// HostSockAddrV4 returns the local or peer IPv4 address of the host socket
+pub fn HostSockAddrV4(fd: i32, peer: bool) -> Option<(u32, u16)> {
+    let sockaddr = [0u8; SIZEOF_SOCKADDR_INET];
+    let len = sockaddr.len() as i32;
+    let res = if peer {
+        HostSpace::GetPeerName(fd, &sockaddr[0] as *const _ as u64, &len as *const _ as u64)
+    } else {
+        HostSpace::GetSockName(fd, &sockaddr[0] as *const _ as u64, &len as *const _ as u64)
+    };
+
+    if res < 0 {
+        return None
+    }
+
+    return SockAddrV4(&sockaddr)
+}
+
+#[derive(Default)]
+pub struct Conntrack {
+    // the client (addr, port) of the connection -> (the original destination, seq)
+    pub conns: BTreeMap<(u32, u16), ((u32, u16), u64)>,
+    // the entries in the add order, the seq tells whether the entry is still the same one
+    pub order: VecDeque<((u32, u16), u64)>,
+    pub seq: u64,
+}
+
+impl Conntrack {
+    pub fn Add(&mut self, client: (u32, u16), origDst: (u32, u16)) {
+        self.seq += 1;
+        self.conns.insert(client, (origDst, self.seq));
+        self.order.push_back((client, self.seq));
+
+        while self.order.len() > MAX_CONNTRACK {
+            let (old, seq) = self.order.pop_front().unwrap();
+            if self.conns.get(&old).map(|e| e.1) == Some(seq) {
+                self.conns.remove(&old);
+            }
+        }
+    }
+
+    pub fn Get(&self, client: (u32, u16)) -> Option<(u32, u16)> {
+        return self.conns.get(&client).map(|e| e.0)
+    }
+
+    // Remove drops the entry of the client address which is reused by a new connection
+    pub fn Remove(&mut self, client: (u32, u16)) {
+        self.conns.remove(&client);
+    }
+}
+
+pub struct NatTable {
+    // None when there is no rule file
+    pub chains: QRwLock<Option<BTreeMap<String, Vec<NatRule>>>>,
+    pub conntrack: QMutex<Option<Conntrack>>,
+    // the listening sockets by port, for the PREROUTING REDIRECT
+    pub listeners: QMutex<Option<BTreeMap<u16, Weak<SocketOperationsIntern>>>>,
+}
+
+pub static NAT: NatTable = NatTable::New();
+
+impl NatTable {
+    pub const fn New() -> Self {
+        return Self {
+            chains: QRwLock::new(None),
+            conntrack: QMutex::new(None),
+            listeners: QMutex::new(None),
+        }
+    }
+
+    pub fn Load(&self, text: &str) {
+        let chains = ParseIptablesSave(text);
+        let rules: usize = chains.values().map(|r| r.len()).sum();
+        info!("nat: {} rules in {} chains are loaded", rules, chains.len());
+        *self.chains.write() = Some(chains);
+        *self.conntrack.lock() = Some(Conntrack::default());
+        *self.listeners.lock() = Some(BTreeMap::new());
+    }
+
+    pub fn Enabled(&self) -> bool {
+        return self.chains.read().is_some()
+    }
+
+    pub fn Eval(&self, conn: &NatConn) -> Option<(u32, u16)> {
+        match self.chains.read().as_ref() {
+            None => return None,
+            Some(chains) => return EvalNat(chains, conn),
+        }
+    }
+
+    pub fn Track(&self, client: (u32, u16), origDst: (u32, u16)) {
+        if let Some(ct) = self.conntrack.lock().as_mut() {
+            ct.Add(client, origDst);
+        }
+    }
+
+    pub fn Untrack(&self, client: (u32, u16)) {
+        if let Some(ct) = self.conntrack.lock().as_mut() {
+            ct.Remove(client);
+        }
+    }
+
+    pub fn OrigDst(&self, client: (u32, u16)) -> Option<(u32, u16)> {
+        return self.conntrack.lock().as_ref().and_then(|ct| ct.Get(client))
+    }
+
+    pub fn AddListener(&self, port: u16, sock: Weak<SocketOperationsIntern>) {
+        if let Some(listeners) = self.listeners.lock().as_mut() {
+            listeners.insert(port, sock);
+        }
+    }
+
+    pub fn Listener(&self, port: u16) -> Option<SocketOperations> {
+        let mut listeners = self.listeners.lock();
+        let listeners = listeners.as_mut()?;
+        let sock = match listeners.get(&port) {
+            None => return None,
+            Some(s) => SocketOperations::Upgrade(s),
+        };
+
+        if sock.is_none() {
+            listeners.remove(&port);
+        }
+
+        return sock
+    }
+}
+
+// LoadNatRules loads the nat rules from the host
+pub fn LoadNatRules() {
+    let mut buf: Vec<u8> = Vec::with_capacity(NAT_RULES_MAX);
+    buf.resize(NAT_RULES_MAX, 0);
+    let ret = HostSpace::LoadNatRules(&mut buf[0] as * mut _ as u64, buf.len());
+    if ret <= 0 {
+        if ret < 0 {
+            error!("load nat rules fail with errno {}", -ret);
+        }
+        return
+    }
+
+    match core::str::from_utf8(&buf[0..ret as usize]) {
+        Err(_) => error!("invalid nat rules {}", NAT_RULES_FILE),
+        Ok(text) => NAT.Load(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISTIO_RULES: &str = r#"
+# Generated by iptables-save
+*mangle
+:PREROUTING ACCEPT [0:0]
+-A PREROUTING -p tcp -j MARK --set-mark 1
+COMMIT
+*nat
+:PREROUTING ACCEPT [0:0]
+:OUTPUT ACCEPT [0:0]
+:ISTIO_INBOUND - [0:0]
+:ISTIO_IN_REDIRECT - [0:0]
+:ISTIO_OUTPUT - [0:0]
+:ISTIO_REDIRECT - [0:0]
+-A PREROUTING -p tcp -j ISTIO_INBOUND
+-A OUTPUT -p tcp -j ISTIO_OUTPUT
+-A ISTIO_INBOUND -p tcp -m tcp --dport 15008 -j RETURN
+-A ISTIO_INBOUND -p tcp -m tcp --dport 15090 -j RETURN
+-A ISTIO_INBOUND -p tcp -j ISTIO_IN_REDIRECT
+-A ISTIO_IN_REDIRECT -p tcp -j REDIRECT --to-ports 15006
+-A ISTIO_OUTPUT -s 127.0.0.6/32 -o lo -j RETURN
+-A ISTIO_OUTPUT -o lo -m owner ! --uid-owner 1337 -m comment --comment "app to app" -j RETURN
+-A ISTIO_OUTPUT -m owner --uid-owner 1337 -j RETURN
+-A ISTIO_OUTPUT -d 127.0.0.1/32 -j RETURN
+-A ISTIO_OUTPUT -d 10.96.0.10/32 -p tcp --dport 53 -j DNAT --to-destination 127.0.0.1:15053
+-A ISTIO_OUTPUT -j ISTIO_REDIRECT
+-A ISTIO_REDIRECT -p tcp -j REDIRECT --to-ports 15001
+-A ISTIO_REDIRECT -p tcp -m set --match-set foo dst -j RETURN
+COMMIT
+"#;
+
+    fn Conn(dst: &str, dport: u16, uid: u32, output: bool) -> NatConn {
+        return NatConn {
+            src: ParseAddr("10.0.0.5").unwrap(),
+            sport: 40000,
+            dst: ParseAddr(dst).unwrap(),
+            dport: dport,
+            uid: uid,
+            gid: uid,
+            output: output,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let chains = ParseIptablesSave(ISTIO_RULES);
+        assert!(chains.get("MARK").is_none());
+        assert_eq!(chains["PREROUTING"].len(), 1);
+        assert_eq!(chains["ISTIO_OUTPUT"].len(), 6);
+        // the rule with the unsupported --match-set is skipped
+        assert_eq!(chains["ISTIO_REDIRECT"].len(), 1);
+        assert_eq!(chains["ISTIO_REDIRECT"][0].target, NatTarget::Redirect(15001));
+        assert_eq!(chains["ISTIO_OUTPUT"][1].uid, Some((true, 1337)));
+        assert_eq!(ParseNet("10.1.2.3/8").unwrap(), (0x0a000000, 0xff000000));
+        assert_eq!(Tokenize(r#"-A X --comment "a b" -j RETURN"#).len(), 5);
+    }
+
+    #[test]
+    fn test_eval() {
+        let chains = ParseIptablesSave(ISTIO_RULES);
+        let loopback = LOOPBACK_ADDR;
+        // the app traffic goes to the outbound listener of the sidecar
+        assert_eq!(EvalNat(&chains, &Conn("10.1.0.7", 80, 1000, true)), Some((loopback, 15001)));
+        // the sidecar traffic is not redirected
+        assert_eq!(EvalNat(&chains, &Conn("10.1.0.7", 80, 1337, true)), None);
+        assert_eq!(EvalNat(&chains, &Conn("127.0.0.1", 8080, 1000, true)), None);
+        assert_eq!(EvalNat(&chains, &Conn("10.96.0.10", 53, 1000, true)), Some((loopback, 15053)));
+        // the inbound traffic goes to the inbound listener
+        assert_eq!(EvalNat(&chains, &Conn("10.0.0.5", 8080, 0, false)), Some((ParseAddr("10.0.0.5").unwrap(), 15006)));
+        assert_eq!(EvalNat(&chains, &Conn("10.0.0.5", 15008, 0, false)), None);
+    }
+
+    #[test]
+    fn test_sockaddr() {
+        let sockaddr = NewSockAddrV4(LOOPBACK_ADDR, 15001);
+        assert_eq!(SockAddrV4(&sockaddr), Some((LOOPBACK_ADDR, 15001)));
+        assert_eq!(SockAddrV4(&sockaddr[..4]), None);
+
+        let mut ct = Conntrack::default();
+        ct.Add((1, 2), (3, 4));
+        assert_eq!(ct.Get((1, 2)), Some((3, 4)));
+        assert_eq!(ct.Get((1, 3)), None);
+        ct.Remove((1, 2));
+        assert_eq!(ct.Get((1, 2)), None);
+        ct.Add((1, 2), (5, 6));
+        assert_eq!(ct.Get((1, 2)), Some((5, 6)));
+    }
+}
@@ -0,0 +1,654 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The network policy of the sandbox. The sandboxes share the host network namespace, so the host
+// firewall can't tell their traffic apart. The policy is set with the OCI annotation
+// dev.quark.net.policy and checked by the hostinet sockets before the host calls: connect and
+// the destination of sendto/sendmsg are checked with the connect rules, bind with the bind rules.
+// The rules are separated by ';' or new lines, each one is
+//
+//     <allow|deny> <connect|bind> <addr[/prefixlen]|any> [port|start-end]
+//
+// The first matching rule is applied and the address which no rule matches is allowed, e.g.
+// "allow connect 10.0.0.0/8 443; deny connect any" only allows the https connections in 10/8.
+// The denied call fails with EPERM, the same as a DROP rule of the iptables OUTPUT chain. The
+// IPv4 address is matched as the IPv4-mapped IPv6 address, so "any" covers both families and
+// an IPv4 rule also applies to the IPv4-mapped destination of an IPv6 socket.
+//
+// The packet sockets and the raw sockets with the IP header have no destination address, the
+// destination in the IP header of the packet is checked with the connect rules, and the packets
+// other than IP, e.g. arp, are allowed. The inet sockets received by SCM_RIGHTS are checked
+// when they are imported: the connected one with the connect rules of its peer, the bound one
+// with the bind rules of its local address.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::mutex::*;
+use super::super::super::Kernel::HostSpace;
+
+pub const NET_POLICY_ANNOTATION: &str = "dev.quark.net.policy";
+pub const NET_POLICY_MAX: usize = 64 * 1024;
+
+// the prefix of the IPv4-mapped IPv6 addresses, ::ffff:0:0/96
+const V4_MAPPED: u128 = 0xffff << 32;
+
+pub const ETH_P_IP: u16 = 0x0800;
+pub const ETH_P_IPV6: u16 = 0x86dd;
+const ETH_P_8021Q: u16 = 0x8100;
+const ETH_P_8021AD: u16 = 0x88a8;
+
+// the bytes at the head of the packet copied to find its destination: the link header, the IP
+// header with the options or the extension headers and the ports
+pub const PACKET_POLICY_HDR: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyOp {
+    Connect,
+    Bind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyRule {
+    pub action: PolicyAction,
+    pub op: PolicyOp,
+    // the network in the IPv6 address space
+    pub net: u128,
+    pub prefix: u32,
+    pub ports: (u16, u16),
+}
+
+impl PolicyRule {
+    pub fn Match(&self, op: PolicyOp, addr: u128, port: u16) -> bool {
+        if self.op != op {
+            return false
+        }
+
+        let mask = if self.prefix == 0 { 0 } else { u128::MAX << (128 - self.prefix) };
+        return addr & mask == self.net && self.ports.0 <= port && port <= self.ports.1
+    }
+}
+
+fn ParseV4(s: &str) -> Option<u32> {
+    let mut addr: u32 = 0;
+    let mut cnt = 0;
+    for part in s.split('.') {
+        addr = addr << 8 | part.parse::<u8>().ok()? as u32;
+        cnt += 1;
+    }
+
+    if cnt != 4 {
+        return None
+    }
+
+    return Some(addr)
+}
+
+// ParseV6 parses the text form of RFC 4291 with the :: and the IPv4 tail
+fn ParseV6(s: &str) -> Option<u128> {
+    let (head, tail) = match s.find("::") {
+        None => (s, None),
+        Some(n) => (&s[..n], Some(&s[n + 2..])),
+    };
+
+    let groups = |part: &str, last: bool| -> Option<Vec<u16>> {
+        let mut groups = Vec::new();
+        if part.len() == 0 {
+            return Some(groups)
+        }
+
+        let fields: Vec<&str> = part.split(':').collect();
+        for (i, f) in fields.iter().enumerate() {
+            if last && i == fields.len() - 1 && f.contains('.') {
+                let v4 = ParseV4(f)?;
+                groups.push((v4 >> 16) as u16);
+                groups.push(v4 as u16);
+            } else if f.len() == 0 || f.len() > 4 {
+                return None
+            } else {
+                groups.push(u16::from_str_radix(f, 16).ok()?);
+            }
+        }
+
+        return Some(groups)
+    };
+
+    let mut all = groups(head, tail.is_none())?;
+    match tail {
+        None => {
+            if all.len() != 8 {
+                return None
+            }
+        }
+        Some(tail) => {
+            let tail = groups(tail, true)?;
+            if all.len() + tail.len() > 7 {
+                return None
+            }
+
+            all.resize(8 - tail.len(), 0);
+            all.extend_from_slice(&tail);
+        }
+    }
+
+    return Some(all.iter().fold(0u128, |a, g| a << 16 | *g as u128))
+}
+
+// ParsePolicyNet parses addr[/prefixlen] or any to the network in the IPv6 address space
+fn ParsePolicyNet(s: &str) -> Option<(u128, u32)> {
+    if s == "any" {
+        return Some((0, 0))
+    }
+
+    let (addr, prefix) = match s.find('/') {
+        None => (s, None),
+        Some(n) => (&s[..n], Some(s[n + 1..].parse::<u32>().ok()?)),
+    };
+
+    let (addr, prefix) = match ParseV4(addr) {
+        Some(v4) => {
+            let prefix = prefix.unwrap_or(32);
+            if prefix > 32 {
+                return None
+            }
+            (V4_MAPPED | v4 as u128, prefix + 96)
+        }
+        None => {
+            let prefix = prefix.unwrap_or(128);
+            if prefix > 128 {
+                return None
+            }
+            (ParseV6(addr)?, prefix)
+        }
+    };
+
+    let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix) };
+    return Some((addr & mask, prefix))
+}
+
+fn ParsePolicyPorts(s: &str) -> Option<(u16, u16)> {
+    match s.find('-') {
+        None => {
+            let port = s.parse::<u16>().ok()?;
+            return Some((port, port))
+        }
+        Some(n) => {
+            let start = s[..n].parse::<u16>().ok()?;
+            let end = s[n + 1..].parse::<u16>().ok()?;
+            if end < start {
+                return None
+            }
+            return Some((start, end))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NetPolicy {
+    pub rules: Vec<PolicyRule>,
+}
+
+impl NetPolicy {
+    pub fn Parse(text: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for line in text.split(|c| c == ';' || c == '\n') {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() == 0 || fields[0].starts_with('#') {
+                continue;
+            }
+
+            let err = || Error::Common(format!("invalid net policy rule {:?}", line.trim()));
+            if fields.len() < 3 || fields.len() > 4 {
+                return Err(err())
+            }
+
+            let action = match fields[0] {
+                "allow" => PolicyAction::Allow,
+                "deny" => PolicyAction::Deny,
+                _ => return Err(err()),
+            };
+
+            let op = match fields[1] {
+                "connect" => PolicyOp::Connect,
+                "bind" => PolicyOp::Bind,
+                _ => return Err(err()),
+            };
+
+            let (net, prefix) = ParsePolicyNet(fields[2]).ok_or_else(err)?;
+            let ports = match fields.get(3) {
+                None => (0, u16::MAX),
+                Some(p) => ParsePolicyPorts(p).ok_or_else(err)?,
+            };
+
+            rules.push(PolicyRule {
+                action: action,
+                op: op,
+                net: net,
+                prefix: prefix,
+                ports: ports,
+            });
+        }
+
+        return Ok(Self {
+            rules: rules,
+        })
+    }
+
+    pub fn Allowed(&self, op: PolicyOp, addr: u128, port: u16) -> bool {
+        for rule in &self.rules {
+            if rule.Match(op, addr, port) {
+                return rule.action == PolicyAction::Allow
+            }
+        }
+
+        return true
+    }
+
+    // PacketAllowed checks the destination of the packet, the truncated one can't be checked
+    pub fn PacketAllowed(&self, dst: Option<PacketDst>) -> bool {
+        match dst {
+            None => return false,
+            Some(PacketDst::NotIp) => return true,
+            Some(PacketDst::Ip(addr, port)) => return self.Allowed(PolicyOp::Connect, addr, port),
+        }
+    }
+
+    // ImportAllowed checks the inet socket received by SCM_RIGHTS with its peer and local
+    // addresses, the empty ones are not known
+    pub fn ImportAllowed(&self, peer: &[u8], local: &[u8]) -> bool {
+        if let Some((addr, port)) = PolicyAddr(peer) {
+            return self.Allowed(PolicyOp::Connect, addr, port)
+        }
+
+        match PolicyAddr(local) {
+            Some((addr, port)) if port != 0 => return self.Allowed(PolicyOp::Bind, addr, port),
+            _ => return true,
+        }
+    }
+}
+
+// PacketDst is the destination of the packet sent by the packet socket or the raw socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDst {
+    NotIp,
+    Ip(u128, u16),
+}
+
+// TransportPort returns the destination port in the transport header l4 of protocol proto,
+// 0 for the protocols without port. None when the header is truncated.
+fn TransportPort(proto: u8, l4: &[u8]) -> Option<u16> {
+    match proto as i32 {
+        // tcp, udp, dccp, sctp and udplite
+        6 | 17 | 33 | 132 | 136 => {
+            if l4.len() < 4 {
+                return None
+            }
+            return Some(u16::from_be_bytes([l4[2], l4[3]]))
+        }
+        _ => return Some(0),
+    }
+}
+
+// FrameDst returns the destination of the ethernet frame of the SOCK_RAW packet socket
+pub fn FrameDst(frame: &[u8]) -> Option<PacketDst> {
+    let mut off = 12;
+    loop {
+        if frame.len() < off + 2 {
+            return None
+        }
+
+        let proto = u16::from_be_bytes([frame[off], frame[off + 1]]);
+        off += 2;
+        match proto {
+            // the vlan tag is followed by the ethertype of the payload
+            ETH_P_8021Q | ETH_P_8021AD => off += 2,
+            _ => return IpPacketDst(proto, &frame[off..]),
+        }
+    }
+}
+
+// VersionProto returns the ethertype by the IP version of the packet, for the SOCK_DGRAM packet
+// socket sending without the address
+pub fn VersionProto(pkt: &[u8]) -> u16 {
+    match pkt.get(0).map(|b| b >> 4) {
+        Some(4) => return ETH_P_IP,
+        Some(6) => return ETH_P_IPV6,
+        _ => return 0,
+    }
+}
+
+// IpPacketDst returns the destination of the network packet of ethertype proto
+pub fn IpPacketDst(proto: u16, pkt: &[u8]) -> Option<PacketDst> {
+    match proto {
+        ETH_P_IP => {
+            if pkt.len() < 20 || pkt[0] >> 4 != 4 {
+                return None
+            }
+
+            let ihl = (pkt[0] & 0xf) as usize * 4;
+            if ihl < 20 || pkt.len() < ihl {
+                return None
+            }
+
+            let addr = u32::from_be_bytes([pkt[16], pkt[17], pkt[18], pkt[19]]);
+            // the fragments after the first one have no transport header
+            let fragOff = u16::from_be_bytes([pkt[6], pkt[7]]) & 0x1fff;
+            let port = if fragOff != 0 {
+                0
+            } else {
+                TransportPort(pkt[9], &pkt[ihl..])?
+            };
+
+            return Some(PacketDst::Ip(V4_MAPPED | addr as u128, port))
+        }
+        ETH_P_IPV6 => {
+            if pkt.len() < 40 || pkt[0] >> 4 != 6 {
+                return None
+            }
+
+            let mut addr = [0u8; 16];
+            addr.copy_from_slice(&pkt[24..40]);
+            let addr = u128::from_be_bytes(addr);
+
+            let mut next = pkt[6];
+            let mut off = 40;
+            loop {
+                match next {
+                    // hop-by-hop, routing and destination options
+                    0 | 43 | 60 => {
+                        if pkt.len() < off + 2 {
+                            return None
+                        }
+                        next = pkt[off];
+                        off += (pkt[off + 1] as usize + 1) * 8;
+                    }
+                    // authentication header
+                    51 => {
+                        if pkt.len() < off + 2 {
+                            return None
+                        }
+                        next = pkt[off];
+                        off += (pkt[off + 1] as usize + 2) * 4;
+                    }
+                    // fragment
+                    44 => {
+                        if pkt.len() < off + 8 {
+                            return None
+                        }
+                        let fragOff = u16::from_be_bytes([pkt[off + 2], pkt[off + 3]]) >> 3;
+                        if fragOff != 0 {
+                            return Some(PacketDst::Ip(addr, 0))
+                        }
+                        next = pkt[off];
+                        off += 8;
+                    }
+                    _ => {
+                        if pkt.len() < off {
+                            return None
+                        }
+                        let port = TransportPort(next, &pkt[off..])?;
+                        return Some(PacketDst::Ip(addr, port))
+                    }
+                }
+            }
+        }
+        _ => return Some(PacketDst::NotIp),
+    }
+}
+
+// PolicyAddr returns the address in the IPv6 address space and the port of the inet sockaddr
+pub fn PolicyAddr(sockaddr: &[u8]) -> Option<(u128, u16)> {
+    if sockaddr.len() < 2 {
+        return None
+    }
+
+    let family = u16::from_ne_bytes([sockaddr[0], sockaddr[1]]) as i32;
+    if family == AFType::AF_INET && sockaddr.len() >= 8 {
+        let port = u16::from_be_bytes([sockaddr[2], sockaddr[3]]);
+        let addr = u32::from_be_bytes([sockaddr[4], sockaddr[5], sockaddr[6], sockaddr[7]]);
+        return Some((V4_MAPPED | addr as u128, port))
+    }
+
+    if family == AFType::AF_INET6 && sockaddr.len() >= 24 {
+        let port = u16::from_be_bytes([sockaddr[2], sockaddr[3]]);
+        let mut addr = [0u8; 16];
+        addr.copy_from_slice(&sockaddr[8..24]);
+        return Some((u128::from_be_bytes(addr), port))
+    }
+
+    return None
+}
+
+pub static NET_POLICY: QRwLock<Option<NetPolicy>> = QRwLock::new(None);
+
+pub fn NetPolicyEnabled() -> bool {
+    return NET_POLICY.read().is_some()
+}
+
+// CheckNetPolicy fails the connect or bind to sockaddr with EPERM when the policy denies it
+pub fn CheckNetPolicy(op: PolicyOp, sockaddr: &[u8]) -> Result<()> {
+    let policy = NET_POLICY.read();
+    let policy = match policy.as_ref() {
+        None => return Ok(()),
+        Some(p) => p,
+    };
+
+    let (addr, port) = match PolicyAddr(sockaddr) {
+        None => return Ok(()),
+        Some(a) => a,
+    };
+
+    if !policy.Allowed(op, addr, port) {
+        info!("net policy denies {:?} to {:x}:{}", op, addr, port);
+        return Err(Error::SysError(SysErr::EPERM))
+    }
+
+    return Ok(())
+}
+
+// CheckPacketPolicy fails the packet to dst with EPERM when the policy denies it
+pub fn CheckPacketPolicy(dst: Option<PacketDst>) -> Result<()> {
+    let policy = NET_POLICY.read();
+    match policy.as_ref() {
+        Some(p) if !p.PacketAllowed(dst) => {
+            info!("net policy denies packet to {:x?}", dst);
+            return Err(Error::SysError(SysErr::EPERM))
+        }
+        _ => return Ok(()),
+    }
+}
+
+// CheckImportPolicy fails the import of the inet socket with EPERM when the policy denies its
+// peer or local address
+pub fn CheckImportPolicy(peer: &[u8], local: &[u8]) -> Result<()> {
+    let policy = NET_POLICY.read();
+    match policy.as_ref() {
+        Some(p) if !p.ImportAllowed(peer, local) => {
+            info!("net policy denies the imported socket");
+            return Err(Error::SysError(SysErr::EPERM))
+        }
+        _ => return Ok(()),
+    }
+}
+
+// LoadNetPolicy loads the policy of the sandbox from the host, it is validated by the host
+pub fn LoadNetPolicy() {
+    let mut buf: Vec<u8> = Vec::with_capacity(NET_POLICY_MAX);
+    buf.resize(NET_POLICY_MAX, 0);
+    let ret = HostSpace::LoadNetPolicy(&mut buf[0] as * mut _ as u64, buf.len());
+    if ret <= 0 {
+        if ret < 0 {
+            error!("load net policy fail with errno {}", -ret);
+        }
+        return
+    }
+
+    let text = String::from_utf8_lossy(&buf[0..ret as usize]);
+    match NetPolicy::Parse(&text) {
+        Err(e) => error!("load net policy fail with error {:?}", e),
+        Ok(policy) => {
+            info!("net policy: {} rules", policy.rules.len());
+            *NET_POLICY.write() = Some(policy);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn V4(s: &str, port: u16) -> (u128, u16) {
+        return (V4_MAPPED | ParseV4(s).unwrap() as u128, port)
+    }
+
+    #[test]
+    fn test_parse_v6() {
+        assert_eq!(ParseV6("::"), Some(0));
+        assert_eq!(ParseV6("::1"), Some(1));
+        assert_eq!(ParseV6("fd00::"), Some(0xfd00 << 112));
+        assert_eq!(ParseV6("2001:db8:0:0:0:0:0:1"), ParseV6("2001:db8::1"));
+        assert_eq!(ParseV6("::ffff:10.0.0.1"), Some(V4_MAPPED | 0x0a000001));
+        assert_eq!(ParseV6("1::2::3"), None);
+        assert_eq!(ParseV6("1:2:3:4:5:6:7"), None);
+        assert_eq!(ParseV6("1:2:3:4:5:6:7::8"), None);
+        assert_eq!(ParseV6("12345::"), None);
+    }
+
+    #[test]
+    fn test_net_policy() {
+        let policy = NetPolicy::Parse("# metadata\ndeny connect 169.254.169.254;\n\
+                                       allow connect 10.0.0.0/8 443; allow connect fd00::/8 8000-8999;\
+                                       deny connect any\n deny bind any 1-1023").unwrap();
+        assert_eq!(policy.rules.len(), 5);
+
+        let (a, p) = V4("10.1.2.3", 443);
+        assert!(policy.Allowed(PolicyOp::Connect, a, p));
+        let (a, p) = V4("10.1.2.3", 80);
+        assert!(!policy.Allowed(PolicyOp::Connect, a, p));
+        let (a, p) = V4("169.254.169.254", 443);
+        assert!(!policy.Allowed(PolicyOp::Connect, a, p));
+        assert!(policy.Allowed(PolicyOp::Connect, ParseV6("fd12::1").unwrap(), 8080));
+        assert!(!policy.Allowed(PolicyOp::Connect, ParseV6("fe80::1").unwrap(), 8080));
+
+        let (a, p) = V4("0.0.0.0", 80);
+        assert!(!policy.Allowed(PolicyOp::Bind, a, p));
+        let (a, p) = V4("0.0.0.0", 0);
+        assert!(policy.Allowed(PolicyOp::Bind, a, p));
+
+        assert!(NetPolicy::Parse("allow connect 10.0.0.0/33").is_err());
+        assert!(NetPolicy::Parse("allow listen any").is_err());
+        assert!(NetPolicy::Parse("deny connect any 90-80").is_err());
+    }
+
+    #[test]
+    fn test_policy_addr() {
+        let mut v4 = [0u8; 16];
+        v4[..2].copy_from_slice(&(AFType::AF_INET as u16).to_ne_bytes());
+        v4[2..4].copy_from_slice(&443u16.to_be_bytes());
+        v4[4..8].copy_from_slice(&[10, 0, 0, 1]);
+        assert_eq!(PolicyAddr(&v4), Some(V4("10.0.0.1", 443)));
+
+        // the IPv4-mapped destination of the IPv6 socket is matched as IPv4
+        let mut v6 = [0u8; 28];
+        v6[..2].copy_from_slice(&(AFType::AF_INET6 as u16).to_ne_bytes());
+        v6[2..4].copy_from_slice(&443u16.to_be_bytes());
+        v6[18..20].copy_from_slice(&[0xff, 0xff]);
+        v6[20..24].copy_from_slice(&[10, 0, 0, 1]);
+        assert_eq!(PolicyAddr(&v6), Some(V4("10.0.0.1", 443)));
+        assert_eq!(PolicyAddr(&v6[..10]), None);
+    }
+
+    fn V4Packet(dst: [u8; 4], proto: u8, port: u16) -> Vec<u8> {
+        let mut pkt = vec![0u8; 24];
+        pkt[0] = 0x45;
+        pkt[9] = proto;
+        pkt[16..20].copy_from_slice(&dst);
+        pkt[22..24].copy_from_slice(&port.to_be_bytes());
+        return pkt
+    }
+
+    #[test]
+    fn test_packet_dst() {
+        let pkt = V4Packet([10, 0, 0, 1], 6, 443);
+        let (a, p) = V4("10.0.0.1", 443);
+        assert_eq!(IpPacketDst(ETH_P_IP, &pkt), Some(PacketDst::Ip(a, p)));
+        assert_eq!(VersionProto(&pkt), ETH_P_IP);
+        assert_eq!(IpPacketDst(ETH_P_IP, &pkt[..22]), None);
+        assert_eq!(IpPacketDst(ETH_P_IP, &V4Packet([10, 0, 0, 1], 1, 0)[..20]), Some(PacketDst::Ip(a, 0)));
+
+        // the ethernet frame with a vlan tag
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&ETH_P_8021Q.to_be_bytes());
+        frame.extend_from_slice(&[0, 1]);
+        frame.extend_from_slice(&ETH_P_IP.to_be_bytes());
+        frame.extend_from_slice(&pkt);
+        assert_eq!(FrameDst(&frame), Some(PacketDst::Ip(a, p)));
+        assert_eq!(FrameDst(&frame[..13]), None);
+
+        // arp
+        let mut arp = vec![0u8; 12];
+        arp.extend_from_slice(&[0x08, 0x06, 0, 1]);
+        assert_eq!(FrameDst(&arp), Some(PacketDst::NotIp));
+
+        // udp after the hop-by-hop options
+        let mut v6 = vec![0u8; 40];
+        v6[0] = 0x60;
+        v6[6] = 0;
+        v6[24] = 0xfd;
+        v6.extend_from_slice(&[17, 0, 0, 0, 0, 0, 0, 0]);
+        v6.extend_from_slice(&[0, 0, 0, 53]);
+        assert_eq!(IpPacketDst(ETH_P_IPV6, &v6), Some(PacketDst::Ip(0xfd << 120, 53)));
+        assert_eq!(IpPacketDst(ETH_P_IPV6, &v6[..50]), None);
+    }
+
+    #[test]
+    fn test_packet_policy() {
+        let policy = NetPolicy::Parse("allow connect 10.0.0.0/8 443; deny connect any").unwrap();
+        let (a, p) = V4("10.0.0.1", 443);
+        assert!(policy.PacketAllowed(Some(PacketDst::Ip(a, p))));
+        let (a, p) = V4("192.168.0.1", 443);
+        assert!(!policy.PacketAllowed(Some(PacketDst::Ip(a, p))));
+        assert!(policy.PacketAllowed(Some(PacketDst::NotIp)));
+        assert!(!policy.PacketAllowed(None));
+    }
+
+    #[test]
+    fn test_import_policy() {
+        let policy = NetPolicy::Parse("allow connect 10.0.0.0/8; deny connect any; deny bind any 1-1023").unwrap();
+        let addr = |ip: [u8; 4], port: u16| -> Vec<u8> {
+            let mut a = vec![0u8; 16];
+            a[..2].copy_from_slice(&(AFType::AF_INET as u16).to_ne_bytes());
+            a[2..4].copy_from_slice(&port.to_be_bytes());
+            a[4..8].copy_from_slice(&ip);
+            a
+        };
+
+        // the connected socket is checked by its peer
+        assert!(policy.ImportAllowed(&addr([10, 0, 0, 1], 80), &addr([172, 17, 0, 2], 40000)));
+        assert!(!policy.ImportAllowed(&addr([8, 8, 8, 8], 53), &addr([172, 17, 0, 2], 40000)));
+
+        // the listening socket by its local address
+        assert!(!policy.ImportAllowed(&[], &addr([0, 0, 0, 0], 80)));
+        assert!(policy.ImportAllowed(&[], &addr([0, 0, 0, 0], 8080)));
+        assert!(policy.ImportAllowed(&[], &addr([0, 0, 0, 0], 0)));
+    }
+}
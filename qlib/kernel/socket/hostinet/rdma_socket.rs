@@ -4,6 +4,7 @@ use super::super::super::super::common::*;
 use super::super::super::super::linux_def::*;
 use super::super::super::super::qmsg::qcall::*;
 use super::super::super::super::socket_buf::*;
+use super::super::super::super::dgram_buf::*;
 use super::super::super::task::*;
 use super::super::super::Kernel::HostSpace;
 //use super::super::super::kernel::waiter::*;
@@ -22,7 +23,7 @@ impl RDMA {
 
     pub fn Read(task: &Task, fd: i32, buf: Arc<SocketBuff>, dsts: &mut [IoVec]) -> Result<i64> {
         let (trigger, cnt) = buf.Readv(task, dsts)?;
-        if !RDMA_ENABLE {
+        if buf.RDMAFallback() {
             if trigger {
                 HostSpace::RDMANotify(fd, RDMANotifyType::Read);
             }
@@ -38,11 +39,33 @@ impl RDMA {
         return Ok(cnt as i64)
     }
 
+    // NewDgramBuf hands the byte streams of the udp socket to qvisor, which sends the datagrams
+    // by the UD queue pair or the host socket and receives from both
+    pub fn NewDgramBuf(fd: i32) -> Result<Arc<DgramBuff>> {
+        let pages = SocketBufPages(DGRAM_RECV_LIMIT);
+        let socketBuf = Arc::new(SocketBuff::InitWithSize(pages, pages));
+        let res = HostSpace::RDMAUdpSocket(fd, socketBuf.clone());
+        if res < 0 {
+            return Err(Error::SysError(-res as i32))
+        }
+
+        return Ok(Arc::new(DgramBuff::NewRDMA(socketBuf)))
+    }
+
+    // DgramSend writes the datagram to the write buf, qvisor is notified when the buf was empty
+    pub fn DgramSend(fd: i32, buf: &DgramBuff, dgram: &Datagram) -> Result<()> {
+        if buf.SendRDMA(dgram)? {
+            HostSpace::RDMANotify(fd, RDMANotifyType::UDWrite);
+        }
+
+        return Ok(())
+    }
+
     //todo: put ops: &SocketOperations in the write request to make the socket won't be closed before write is finished
     pub fn Write(task: &Task, fd: i32, buf: Arc<SocketBuff>, srcs: &[IoVec]/*, ops: &SocketOperations*/) -> Result<i64> {
-        let (count, writeBuf) = buf.Writev(task, srcs)?;
+        let (count, writeBuf) = buf.Writev(task, srcs, false)?;
         if writeBuf.is_some() {
-            if RDMA_ENABLE {
+            if !buf.RDMAFallback() {
                 HostSpace::RDMANotify(fd, RDMANotifyType::RDMAWrite);
             } else {
                 HostSpace::RDMANotify(fd, RDMANotifyType::Write);
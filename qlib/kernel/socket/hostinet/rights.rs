@@ -0,0 +1,204 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::slice;
+use alloc::vec::Vec;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::linux::socket::*;
+use super::super::super::fs::file::*;
+use super::super::super::fs::host::hostinodeop::*;
+use super::super::super::kernel::fd_table::*;
+use super::super::super::task::*;
+use super::super::super::Kernel::GetSockOptI32;
+use super::super::super::Kernel::HostSpace;
+use super::super::control::*;
+use super::policy::*;
+use super::socket::*;
+
+// SCM_RIGHTS of the host socket: the host kernel passes the host fds, so the guest fds are
+// translated to the host fds of their files before the host sendmsg, and the host fds received
+// by the host recvmsg are imported as new guest files. Same as the files opened from the host,
+// the offset of the received file is not shared with the sender.
+
+// HostFdOfFile returns the host fd of the file, None for the file only in the guest
+pub fn HostFdOfFile(file: &File) -> Option<i32> {
+    let inode = file.Dirent.Inode();
+    let iops = inode.lock().InodeOp.clone();
+    return iops.as_any().downcast_ref::<HostInodeOp>().map(|h| h.HostFd())
+}
+
+// HostRights keeps the host fds of SCM_RIGHTS open until the host sendmsg returns: the files
+// own their host fds and the reopened fds are closed on drop
+#[derive(Default)]
+pub struct HostRights {
+    pub files: Vec<File>,
+    pub reopened: Vec<i32>,
+}
+
+impl Drop for HostRights {
+    fn drop(&mut self) {
+        for fd in &self.reopened {
+            HostSpace::Close(*fd);
+        }
+    }
+}
+
+// AccessMode returns O_RDONLY, O_WRONLY or O_RDWR of the guest file
+fn AccessMode(file: &File) -> i32 {
+    let flags = file.Flags();
+    if flags.Read && flags.Write {
+        return Flags::O_RDWR
+    } else if flags.Write {
+        return Flags::O_WRONLY
+    }
+
+    return Flags::O_RDONLY
+}
+
+// HostFdForSend returns the host fd passed for the file. The host fd of the file may have more
+// access than the guest file, e.g. the host file is opened for write as another guest file of
+// the inode writes it, so the host file is reopened with the access mode of the guest file.
+// The reopened fd is pushed to rights.
+fn HostFdForSend(file: &File, hostfd: i32, rights: &mut HostRights) -> Result<i32> {
+    if file.Dirent.Inode().StableAttr().IsSocket() {
+        return Ok(hostfd)
+    }
+
+    let mode = AccessMode(file);
+    let hostFlags = HostSpace::Fcntl(hostfd, Cmd::F_GETFL, 0);
+    if hostFlags < 0 {
+        return Err(Error::SysError(-hostFlags as i32))
+    }
+
+    if hostFlags as i32 & Flags::O_ACCMODE == mode {
+        return Ok(hostfd)
+    }
+
+    let fd = HostSpace::ReopenFd(hostfd, mode);
+    if fd < 0 {
+        return Err(Error::SysError(-fd as i32))
+    }
+
+    rights.reopened.push(fd as i32);
+    return Ok(fd as i32)
+}
+
+// RightsToHost translates the guest fds of SCM_RIGHTS in the control data of msgHdr to the host
+// fds in place. The control data is the kernel copy of sendmsg. The returned rights keep the host
+// fds open until the host sendmsg returns.
+pub fn RightsToHost(task: &Task, msgHdr: &MsgHdr) -> Result<HostRights> {
+    let mut rights = HostRights::default();
+    if msgHdr.msgControl == 0 || msgHdr.msgControlLen == 0 {
+        return Ok(rights)
+    }
+
+    let control = unsafe {
+        slice::from_raw_parts_mut(msgHdr.msgControl as *mut u8, msgHdr.msgControlLen)
+    };
+
+    MapRights(control, |fd| {
+        let file = task.GetFile(fd)?;
+        // the file only in the guest, e.g. the guest pipe, can't go through the host socket
+        let hostfd = match HostFdOfFile(&file) {
+            None => return Err(Error::SysError(SysErr::EOPNOTSUPP)),
+            Some(hostfd) => hostfd,
+        };
+
+        let hostfd = HostFdForSend(&file, hostfd, &mut rights)?;
+        rights.files.push(file);
+        return Ok(hostfd)
+    })?;
+
+    return Ok(rights)
+}
+
+// HostSockName returns the peer or local address of the host socket, empty when it has none
+fn HostSockName(fd: i32, peer: bool) -> Vec<u8> {
+    let mut buf = [0u8; 128];
+    let mut len = buf.len() as i32;
+    let res = if peer {
+        HostSpace::GetPeerName(fd, &mut buf[0] as *mut _ as u64, &mut len as *mut _ as u64)
+    } else {
+        HostSpace::GetSockName(fd, &mut buf[0] as *mut _ as u64, &mut len as *mut _ as u64)
+    };
+
+    if res < 0 {
+        return Vec::new()
+    }
+
+    return buf[..core::cmp::min(len as usize, buf.len())].to_vec()
+}
+
+// ImportHostFd creates the guest file of the host fd received by SCM_RIGHTS, the file owns the
+// host fd
+fn ImportHostFd(task: &Task, fd: i32) -> Result<File> {
+    let stype = match GetSockOptI32(fd, SOL_SOCKET, SO_TYPE) {
+        Err(Error::SysError(SysErr::ENOTSOCK)) => {
+            return File::NewFileFromFd(task, fd, &task.FileOwner(), false)
+        }
+        Err(e) => return Err(e),
+        Ok(stype) => stype,
+    };
+
+    // the O_NONBLOCK of the sender is not known as the host fds are always nonblocking
+    let family = GetSockOptI32(fd, SOL_SOCKET, SO_DOMAIN)?;
+
+    // the inet socket connected or bound out of the sandbox skips the policy of the connect
+    // and bind, it is checked when it comes in
+    if (family == AFType::AF_INET || family == AFType::AF_INET6) && NetPolicyEnabled() {
+        CheckImportPolicy(&HostSockName(fd, true), &HostSockName(fd, false))?;
+    }
+
+    return newSocketFile(task, family, fd, stype, false, SocketBufType::NoTCP, None, None)
+}
+
+// RightsFromHost installs the host fds of SCM_RIGHTS in the control data received by the host
+// recvmsg as guest fds in place. Same as linux, the fds which fail to be installed are dropped
+// and it returns MSG_CTRUNC for them.
+pub fn RightsFromHost(task: &Task, control: &mut [u8], cloexec: bool) -> i32 {
+    let mut flags = 0;
+    let res = MapRights(control, |hostfd| {
+        // the host fds after the failed one are closed
+        if flags != 0 {
+            HostSpace::Close(hostfd);
+            return Ok(-1)
+        }
+
+        let file = match ImportHostFd(task, hostfd) {
+            Err(_) => {
+                HostSpace::Close(hostfd);
+                flags = MsgType::MSG_CTRUNC;
+                return Ok(-1)
+            }
+            Ok(file) => file,
+        };
+
+        // the host fd is closed with the file
+        match task.NewFDFrom(0, &file, &FDFlags { CloseOnExec: cloexec }) {
+            Err(_) => {
+                flags = MsgType::MSG_CTRUNC;
+                return Ok(-1)
+            }
+            Ok(fd) => return Ok(fd),
+        }
+    });
+
+    if res.is_err() {
+        flags = MsgType::MSG_CTRUNC;
+    }
+
+    return flags
+}
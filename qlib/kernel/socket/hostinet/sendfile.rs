@@ -0,0 +1,82 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::socket_buf::*;
+use super::super::super::fs::attr::*;
+use super::super::super::fs::file::*;
+use super::super::super::fs::host::hostinodeop::*;
+use super::super::super::task::*;
+use super::super::super::quring::QUring;
+use super::socket::*;
+
+// sendfile and splice from a host file to the socket. The uring socket reads the file range to
+// its write buf with the uring read and the buf is sent by the AsyncSend as the written data,
+// the socket with kTLS TX sends it with the host sendfile. The others fall back to the copy of
+// Splice through the guest memory.
+
+// HostSendFileSrc returns the host inode of the regular file src, None when the file data is
+// not in the host file
+pub fn HostSendFileSrc(task: &Task, src: &File, opts: &SpliceOpts) -> Option<HostInodeOp> {
+    if opts.Dup || opts.DstOffset {
+        return None
+    }
+
+    let inode = src.Dirent.Inode();
+    let iops = inode.lock().InodeOp.clone();
+    let hostiops = match iops.as_any().downcast_ref::<HostInodeOp>() {
+        None => return None,
+        Some(h) => h.clone(),
+    };
+
+    if hostiops.InodeType() != InodeType::RegularFile {
+        return None
+    }
+
+    // the buffered writes of the file are done on the host first
+    if hostiops.BufWriteEnable() {
+        hostiops.BufWriteLock().Lock(task);
+    }
+
+    return Some(hostiops)
+}
+
+impl SocketOperations {
+    // SendFile sends the host file src to the socket without copying the data through the guest
+    // memory, it returns ENOSYS for the copy of Splice when the socket or the file doesn't
+    // qualify
+    pub fn SendFile(&self, task: &Task, src: &File, opts: &SpliceOpts) -> Result<i64> {
+        match self.SocketBufType() {
+            SocketBufType::Uring(buf) => return self.RingSendFile(task, buf, src, opts),
+            _ => return self.KtlsSendFile(task, src, opts),
+        }
+    }
+
+    fn RingSendFile(&self, task: &Task, buf: Arc<SocketBuff>, src: &File, opts: &SpliceOpts) -> Result<i64> {
+        let hostiops = match HostSendFileSrc(task, src, opts) {
+            None => return Err(Error::SysError(SysErr::ENOSYS)),
+            Some(h) => h,
+        };
+
+        if opts.Length <= 0 {
+            return Ok(0)
+        }
+
+        return QUring::SocketSendFile(task, self.fd, self.queue.clone(), buf, hostiops.HostFd(),
+                                      opts.SrcStart, opts.Length as usize, self)
+    }
+}
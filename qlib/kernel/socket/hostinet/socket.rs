@@ -13,11 +13,17 @@
 // limitations under the License.
 
 use alloc::sync::Arc;
+use alloc::sync::Weak;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use alloc::string::String;
 use core::any::Any;
 use core::sync::atomic::AtomicI64;
 use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI32;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering;
 use core::ptr;
 use core::ops::Deref;
@@ -35,8 +41,10 @@ use super::super::super::fs::dentry::*;
 use super::super::super::fs::dirent::*;
 use super::super::super::fs::attr::*;
 use super::super::super::fs::host::hostinodeop::*;
+use super::super::super::fs::procfs::sys::sysctl::SYSCTL;
 use super::super::super::kernel::fd_table::*;
 use super::super::super::kernel::waiter::*;
+use super::super::super::kernel::waiter::lock::*;
 use super::super::super::kernel::async_wait::*;
 use super::super::super::kernel::time::*;
 use super::super::super::super::common::*;
@@ -47,21 +55,113 @@ use super::super::super::Kernel;
 use super::super::super::IOURING;
 use super::super::super::quring::QUring;
 use super::super::super::Kernel::HostSpace;
+use super::super::super::Kernel::GetSockOptI32;
 use super::super::super::super::linux_def::*;
 use super::super::super::super::socket_buf::*;
+use super::super::super::super::dgram_buf::*;
 use super::super::super::fd::*;
 use super::super::super::tcpip::tcpip::*;
+use super::super::super::tcpip::sockaddr::*;
 use super::super::super::SHARESPACE;
 use super::super::super::super::linux::time::Timeval;
-use super::super::control::ControlMessageTCPInq;
+use super::super::super::super::linux::time::Timespec;
+use super::super::super::super::linux::socket::*;
 use super::rdma_socket::*;
+use super::multicast::*;
+use super::bind_device::*;
+use super::ephemeral::*;
+use super::connect::*;
+use super::stats::*;
+use super::cork::*;
+use super::errqueue::*;
+use super::rights::*;
+use super::nat::*;
+use super::policy::*;
+use super::ktls::*;
+use super::loopback::*;
+use super::super::super::kernel::timer::timer::*;
+use super::super::super::kernel::timer::MONOTONIC_CLOCK;
+use super::super::epsocket::epsocket::Linger;
+use super::super::super::kernel::timer::MonotonicNow;
+use super::super::super::taskMgr::CondResched;
+use super::super::super::super::linux::time::SECOND;
+
+// IsSockBufSizeOpt returns whether the SOL_SOCKET option sets the size of the socket buffer
+pub fn IsSockBufSizeOpt(name: i32) -> bool {
+    return name == SO_RCVBUF || name == SO_RCVBUFFORCE || name == SO_SNDBUF || name == SO_SNDBUFFORCE
+}
+
+// NormalizeInetAddr returns the address of the family as the host getsockname/getpeername
+// gives it: sin_zero, sin6_flowinfo and the sin6_scope_id of the non link-local address are 0.
+// It is None for the address which is not inet or has no port.
+pub fn NormalizeInetAddr(family: i32, addr: &[u8]) -> Option<Vec<u8>> {
+    let len = match family {
+        AFType::AF_INET => SocketSize::SIZEOF_SOCKADDR_INET4,
+        AFType::AF_INET6 => SocketSize::SIZEOF_SOCKADDR_INET6,
+        _ => return None,
+    };
+
+    if addr.len() < len || u16::from_ne_bytes([addr[0], addr[1]]) as i32 != family {
+        return None
+    }
+
+    // the port is not assigned yet
+    if addr[2] == 0 && addr[3] == 0 {
+        return None
+    }
+
+    let mut addr = addr[..len].to_vec();
+    if family == AFType::AF_INET {
+        for b in &mut addr[8..16] {
+            *b = 0;
+        }
+    } else {
+        for b in &mut addr[4..8] {
+            *b = 0;
+        }
+
+        if !IsLinkLocal(&addr[8..24]) {
+            for b in &mut addr[24..28] {
+                *b = 0;
+            }
+        }
+    }
+
+    return Some(addr)
+}
+
+// IsWildcardAddr returns whether the ip of the normalized inet address is INADDR_ANY/in6addr_any
+pub fn IsWildcardAddr(addr: &[u8]) -> bool {
+    if addr.len() == SocketSize::SIZEOF_SOCKADDR_INET4 {
+        return addr[4..8].iter().all(|b| *b == 0)
+    }
+
+    return addr[8..24].iter().all(|b| *b == 0)
+}
+
+// FetchAcceptPeerInfo gets the peer info of the accepted connection when AcceptPeerInfo is
+// enabled, the later getpeername, SO_PEERCRED and TCP_INFO are answered without host calls
+pub fn FetchAcceptPeerInfo(fd: i32) -> Option<Box<AcceptPeerInfo>> {
+    if !SHARESPACE.config.read().AcceptPeerInfo {
+        return None
+    }
+
+    let mut info = Box::new(AcceptPeerInfo::default());
+    let ret = HostSpace::GetAcceptPeerInfo(fd, &mut *info as *mut _ as u64);
+    if ret < 0 {
+        return None
+    }
+
+    return Some(info)
+}
 
-fn newSocketFile(task: &Task, family: i32, fd: i32, stype: i32, nonblock: bool, socketBuf: SocketBufType, addr: Option<Vec<u8>>) -> Result<File> {
+pub fn newSocketFile(task: &Task, family: i32, fd: i32, stype: i32, nonblock: bool, socketBuf: SocketBufType, addr: Option<Vec<u8>>, peerInfo: Option<Box<AcceptPeerInfo>>) -> Result<File> {
     let dirent = NewSocketDirent(task, SOCKET_DEVICE.clone(), fd)?;
     let inode = dirent.Inode();
     let iops = inode.lock().InodeOp.clone();
     let hostiops = iops.as_any().downcast_ref::<HostInodeOp>().unwrap();
     let s = SocketOperations::New(family, fd, stype, hostiops.Queue(), hostiops.clone(), socketBuf, addr)?;
+    *s.acceptPeer.lock() = peerInfo;
 
     Ok(File::New(&dirent,
               &FileFlags { NonBlocking: nonblock, Read: true, Write: true, ..Default::default() },
@@ -80,6 +180,8 @@ pub enum SocketBufType {
     TCPNormalData,      // Common TCP socket
     Uring(Arc<SocketBuff>),
     RDMA(Arc<SocketBuff>),
+    Dgram(Arc<DgramBuff>),          // UDP socket with the datagrams received and sent by the uring
+    Loopback(Arc<LoopbackSock>),    // TCP socket to another sandbox on the host over the shared memory
 }
 
 impl fmt::Debug for SocketBufType {
@@ -94,49 +196,84 @@ impl fmt::Debug for SocketBufType {
             Self::TCPNormalData => write!(f, "SocketBufType::TCPNormalData"),
             Self::Uring(_) => write!(f, "SocketBufType::Uring"),
             Self::RDMA(_) => write!(f, "SocketBufType::RDMA"),
+            Self::Dgram(_) => write!(f, "SocketBufType::Dgram"),
+            Self::Loopback(_) => write!(f, "SocketBufType::Loopback"),
         }
     }
 }
 
+// the unexpected socket calls are logged at most once per SOCK_ERR_LOG_INTERVAL
+pub const SOCK_ERR_LOG_INTERVAL: i64 = SECOND;
+static SOCK_ERR_LAST_LOG: AtomicI64 = AtomicI64::new(0);
+static SOCK_ERR_SUPPRESSED: AtomicU64 = AtomicU64::new(0);
+
+// LogSockErr logs the unexpected socket call with rate limit, the suppressed logs are counted
+// in the next one
+pub fn LogSockErr(args: fmt::Arguments) {
+    let now = MonotonicNow();
+    let last = SOCK_ERR_LAST_LOG.load(Ordering::Relaxed);
+    if (last != 0 && now - last < SOCK_ERR_LOG_INTERVAL)
+        || SOCK_ERR_LAST_LOG.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+        SOCK_ERR_SUPPRESSED.fetch_add(1, Ordering::Relaxed);
+        return
+    }
+
+    let suppressed = SOCK_ERR_SUPPRESSED.swap(0, Ordering::Relaxed);
+    error!("hostinet: {} ({} suppressed)", args, suppressed);
+}
+
+// SockStateErr is the error of the socket call in a state it doesn't expect. It is a bug of the
+// socket state machine which the debug build asserts, the release build fails the call with
+// EINVAL instead of panicking the sandbox.
+pub fn SockStateErr(op: &str, fd: i32, typ: &SocketBufType) -> Error {
+    LogSockErr(format_args!("{} of socket fd {} with unexpected {:?}", op, fd, typ));
+    debug_assert!(false, "{} of socket fd {} with unexpected {:?}", op, fd, typ);
+    return Error::SysError(SysErr::EINVAL)
+}
+
 impl SocketBufType {
-    pub fn Accept(&self, socketBuf: Arc<SocketBuff>) -> Self {
+    pub fn Accept(&self, socketBuf: Arc<SocketBuff>) -> Result<Self> {
         match self {
             SocketBufType::TCPNormalServer => {
-                return SocketBufType::TCPNormalData
+                return Ok(SocketBufType::TCPNormalData)
             },
             SocketBufType::TCPUringlServer(_) => {
-                return SocketBufType::Uring(socketBuf)
+                return Ok(SocketBufType::Uring(socketBuf))
             },
             SocketBufType::TCPRDMAServer(_) => {
-                return SocketBufType::RDMA(socketBuf)
+                return Ok(SocketBufType::RDMA(socketBuf))
             }
             _ => {
-                panic!("SocketBufType::Accept unexpect type {:?}", self)
+                return Err(SockStateErr("Accept", -1, self))
             }
         }
     }
 
-    pub fn Connect(&self) -> Self {
+    // rcvBuf/sndBuf: SO_RCVBUF/SO_SNDBUF of the socket, 0 when it is not set
+    pub fn Connect(&self, rcvBuf: usize, sndBuf: usize) -> Result<Self> {
         match self {
             Self::TCPInit => {
-                return self.ConnectType()
+                return Ok(self.ConnectType(rcvBuf, sndBuf))
             }
             // in bazel, there is UDP socket also call connect
             Self::NoTCP => {
-                return Self::NoTCP
+                return Ok(Self::NoTCP)
+            }
+            Self::Dgram(_) => {
+                return Ok(self.clone())
             }
             _ => {
-                panic!("SocketBufType::Connect unexpect type {:?}", self)
+                return Err(SockStateErr("Connect", -1, self))
             }
         }
     }
 
-    fn ConnectType(&self) -> Self {
+    fn ConnectType(&self, rcvBuf: usize, sndBuf: usize) -> Self {
         if SHARESPACE.config.read().EnableRDMA {
-            let socketBuf = Arc::new(SocketBuff::Init(MemoryDef::DEFAULT_BUF_PAGE_COUNT));
+            let socketBuf = Arc::new(SocketBuff::InitWithSize(SocketBufPages(rcvBuf), SocketBufPages(sndBuf)));
             return Self::RDMA(socketBuf)
         } else if SHARESPACE.config.read().UringIO {
-            let socketBuf = Arc::new(SocketBuff::Init(MemoryDef::DEFAULT_BUF_PAGE_COUNT));
+            let socketBuf = Arc::new(SocketBuff::InitWithSize(SocketBufPages(rcvBuf), SocketBufPages(sndBuf)));
             return Self::Uring(socketBuf)
         } else {
             return Self::TCPNormalData
@@ -152,10 +289,86 @@ pub struct SocketOperationsIntern {
     pub fd: i32,
     pub queue: Queue,
     pub remoteAddr: QMutex<Option<SockAddr>>,
+    // the normalized local inet address, getsockname asks the host when it is not known
+    pub localAddr: QMutex<Option<Vec<u8>>>,
     pub socketBuf: QMutex<SocketBufType>,
     pub enableAsyncAccept: AtomicBool,
     pub hostops: HostInodeOp,
+    pub connectState: QMutex<ConnectState>,
+    pub linger: QMutex<Linger>,
+    pub multicast: QMutex<MulticastGroups>,
+    pub multicastOpts: QMutex<MulticastOpts>,
+    // the guest index of the device bound by SO_BINDTODEVICE/SO_BINDTOIFINDEX, 0 if not bound
+    pub bindDevice: AtomicI32,
+    // the last fd of the socket is closed while there are tasks in the socket call
+    pub closed: AtomicBool,
+    // number of tasks in the socket recv/send/accept/connect
+    pub inflight: AtomicI32,
+    // the waiters of EVENT_PRI, the host fd of the buffered socket has to be polled for the OOB
+    // data while there are any
+    pub oobWaiters: AtomicUsize,
+    // IP_RECVERR/IPV6_RECVERR is set, the host error queue is polled for the buffered socket
+    pub recvErr: AtomicBool,
     passInq: AtomicBool,
+    // IP_PKTINFO or IP_RECVTOS is set on the buffered socket
+    passPktOptions: AtomicBool,
+    // the IP_PKTINFO/IP_TOS cmsgs of the connection, fetched once from the host
+    pktOptions: QMutex<Option<Vec<u8>>>,
+    // SO_TIMESTAMP or SO_TIMESTAMPNS when the receive timestamp is enabled, otherwise 0
+    passTimestamp: AtomicI32,
+    timestampingFlags: AtomicU32,
+    // SO_RCVLOWAT set before the socket buffer is created, e.g. on the listening socket
+    rcvLowat: AtomicI32,
+    // SO_RCVBUF/SO_SNDBUF set before the socket buffer is created, 0 when it is not set
+    rcvBuf: AtomicI32,
+    sndBuf: AtomicI32,
+    // the peer info fetched with the accepted connection
+    acceptPeer: QMutex<Option<Box<AcceptPeerInfo>>>,
+    // TCP_CORK of the buffered socket
+    cork: AtomicBool,
+    // TCP_NODELAY, it selects the SendFlushPolicy of the socket buffer
+    noDelay: AtomicBool,
+    corkTimer: QMutex<Option<Timer>>,
+    // hostfd of the unix listener which takes the connections from the other sandboxes on the
+    // host, -1 when the loopback fast path is not enabled for the listener
+    loopbackFd: AtomicI32,
+    // TCP_FASTOPEN_CONNECT of the buffered socket
+    fastOpenConnect: AtomicBool,
+    // the destination of the connect deferred by TCP_FASTOPEN_CONNECT to the first write
+    fastOpenDst: QMutex<Option<Vec<u8>>>,
+    // KTLS_* of the ULP and the crypto state set on the host socket
+    pub ktls: AtomicU32,
+    // the kTLS record of the buffered socket is sent after the data of the write buffer and
+    // before the data written after it
+    pub ktlsSend: QLock,
+    // ASYNC_CONNECT_* or the errno of the nonblocking connect finished by PollConnect
+    asyncConnect: AtomicI32,
+    // the destination of the nonblocking connect in progress of the buffered socket
+    connectDst: QMutex<Option<Vec<u8>>>,
+}
+
+impl Drop for SocketOperationsIntern {
+    fn drop(&mut self) {
+        let lfd = self.loopbackFd.load(Ordering::Relaxed);
+        if lfd >= 0 {
+            RemoveFD(lfd);
+            HostSpace::Close(lfd);
+        }
+
+        let rdma = match &*self.socketBuf.lock() {
+            SocketBufType::RDMA(_) => true,
+            SocketBufType::Dgram(buf) => buf.RDMA().is_some(),
+            _ => false,
+        };
+
+        if rdma {
+            INET_SOCKETS.RemoveRDMA(self.fd);
+        }
+
+        if self.family == AFType::AF_INET || self.family == AFType::AF_INET6 {
+            INET_SOCKETS.Remove(self.stype);
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -163,20 +376,22 @@ pub struct SocketOperations(Arc<SocketOperationsIntern>);
 
 impl SocketOperations {
     pub fn New(family: i32, fd: i32, stype: i32, queue: Queue, hostops: HostInodeOp, socketBuf: SocketBufType, addr: Option<Vec<u8>>) -> Result<Self> {
-        let addr = match addr {
-            None => None,
-            Some(v) => {
-                if v.len() == 0 {
-                    None
-                } else {
-                    Some(GetAddr(v[0] as i16, &v[0..v.len()]).unwrap())
-                }
-            }
-        };
+        // the peer address returned by the host accept
+        let addr = addr.and_then(|v| HostSockAddr(&v, v.len())).map(|(a, _)| a);
 
         match &socketBuf {
             SocketBufType::Uring(ref buf) => {
-                QUring::BufSockInit(fd, queue.clone(), buf.clone(), true).unwrap();
+                QUring::BufSockInit(fd, queue.clone(), buf.clone(), true)?;
+            }
+            SocketBufType::Dgram(ref buf) => {
+                match buf.RDMA() {
+                    // the datagrams are received by qvisor
+                    Some(rdma) => INET_SOCKETS.AddRDMA(fd, true, &rdma),
+                    None => QUring::DgramRecvStart(fd, queue.clone(), buf.clone()),
+                }
+            }
+            SocketBufType::RDMA(ref buf) => {
+                INET_SOCKETS.AddRDMA(fd, false, buf);
             }
             _ => ()
         }
@@ -189,12 +404,44 @@ impl SocketOperations {
             fd,
             queue,
             remoteAddr: QMutex::new(addr),
+            localAddr: QMutex::new(None),
             socketBuf: QMutex::new(socketBuf.clone()),
             enableAsyncAccept: AtomicBool::new(false),
             hostops: hostops,
-            passInq: AtomicBool::new(false)
+            connectState: QMutex::new(ConnectState::default()),
+            linger: QMutex::new(Linger::default()),
+            multicast: QMutex::new(MulticastGroups::default()),
+            multicastOpts: QMutex::new(MulticastOpts::default()),
+            bindDevice: AtomicI32::new(0),
+            closed: AtomicBool::new(false),
+            inflight: AtomicI32::new(0),
+            oobWaiters: AtomicUsize::new(0),
+            recvErr: AtomicBool::new(false),
+            passInq: AtomicBool::new(false),
+            passPktOptions: AtomicBool::new(false),
+            pktOptions: QMutex::new(None),
+            passTimestamp: AtomicI32::new(0),
+            timestampingFlags: AtomicU32::new(0),
+            rcvLowat: AtomicI32::new(1),
+            rcvBuf: AtomicI32::new(0),
+            sndBuf: AtomicI32::new(0),
+            acceptPeer: QMutex::new(None),
+            cork: AtomicBool::new(false),
+            noDelay: AtomicBool::new(false),
+            corkTimer: QMutex::new(None),
+            loopbackFd: AtomicI32::new(-1),
+            fastOpenConnect: AtomicBool::new(false),
+            fastOpenDst: QMutex::new(None),
+            ktls: AtomicU32::new(0),
+            ktlsSend: QLock::default(),
+            asyncConnect: AtomicI32::new(ASYNC_CONNECT_NONE),
+            connectDst: QMutex::new(None),
         };
 
+        if family == AFType::AF_INET || family == AFType::AF_INET6 {
+            INET_SOCKETS.Add(stype);
+        }
+
         let ret = Self(Arc::new(ret));
         return Ok(ret)
     }
@@ -204,32 +451,105 @@ impl SocketOperations {
         ai.len = ai.addr.data.len() as _;
         let res = Kernel::HostSpace::IOAccept(self.fd, &ai.addr as * const _ as u64, &ai.len as * const _ as u64) as i32;
         if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            if res == -SysErr::EAGAIN {
+                ClearNotified(self.fd, EVENT_IN);
+            }
+            return Err(HostErr("IOAccept", self.fd, -res as i32))
         }
 
         ai.fd = res;
         return Ok(ai);
     }
 
-    fn prepareControlMessage(&self, controlDataLen: usize) -> (i32, Vec<u8>) {
+    // PktOptions returns the IP_PKTINFO/IP_TOS cmsgs of the buffered socket. Linux tcp doesn't
+    // pass them with the data, it reports the ones of the connection by IP_PKTOPTIONS.
+    fn PktOptions(&self) -> Vec<u8> {
+        let mut cached = self.pktOptions.lock();
+        if let Some(opts) = cached.as_ref() {
+            return opts.clone()
+        }
+
+        let mut opts: Vec<u8> = vec![0; 128];
+        let mut optLen = opts.len();
+        let res = HostSpace::GetSockOpt(self.fd, SOL_IP, LibcConst::IP_PKTOPTIONS as i32,
+                                        &mut opts[0] as *mut _ as u64, &mut optLen as *mut _ as u64);
+        if res < 0 {
+            return Vec::new()
+        }
+
+        opts.truncate(optLen);
+        *cached = Some(opts.clone());
+        return opts
+    }
+
+    // prepareControlMessage returns the cmsgs of the data read from the socket buffer, peeked is
+    // the data read by MSG_PEEK
+    fn prepareControlMessage(&self, controlDataLen: usize, peeked: usize) -> (i32, Vec<u8>) {
         // shortcut for no controldata wanted
         if controlDataLen == 0 {
             return (0, Vec::new())
         }
 
         let mut controlData: Vec<u8> = vec![0; controlDataLen];
-        if self.passInq.load(Ordering::Relaxed) {
-            let inqMessage = ControlMessageTCPInq {
-                Size: self.SocketBuf().readBuf.lock().AvailableDataSize() as u32
-            };
+        let mut flags = 0;
+        let remainSize = {
+            let mut buf = &mut controlData[..];
+            let sockBuf = self.StreamBuf();
+            if self.passInq.load(Ordering::Relaxed) {
+                let inqMessage = ControlMessageTCPInq {
+                    Size: sockBuf.as_ref().map(|b| b.ReadableSize()).unwrap_or(0) as u32
+                };
 
-            let (remaining, updated_flags) = inqMessage.EncodeInto(&mut controlData[..], 0);
-            let remainSize = remaining.len();
-            controlData.resize(controlDataLen - remainSize, 0);
-            return (updated_flags, controlData)
-        } else {
-            return (0, Vec::new())
-        }
+                let (remaining, updated_flags) = inqMessage.EncodeInto(buf, flags);
+                buf = remaining;
+                flags = updated_flags;
+            }
+
+            if self.passPktOptions.load(Ordering::Relaxed) && sockBuf.is_some() {
+                let opts = self.PktOptions();
+                if opts.len() <= buf.len() {
+                    buf[..opts.len()].copy_from_slice(&opts);
+                    buf = &mut buf[opts.len()..];
+                } else {
+                    flags |= MsgType::MSG_CTRUNC;
+                }
+            }
+
+            let timestamp = sockBuf.map(|b| b.RxTimestamp(peeked)).unwrap_or(0);
+            if timestamp != 0 {
+                match self.passTimestamp.load(Ordering::Relaxed) {
+                    SO_TIMESTAMP => {
+                        let msg = ControlMessageTimeStamp(Timeval::FromNs(timestamp));
+                        let (remaining, updated_flags) = msg.EncodeInto(buf, flags);
+                        buf = remaining;
+                        flags = updated_flags;
+                    }
+                    SO_TIMESTAMPNS => {
+                        let msg = ControlMessageTimeStampNs(Timespec::FromNs(timestamp));
+                        let (remaining, updated_flags) = msg.EncodeInto(buf, flags);
+                        buf = remaining;
+                        flags = updated_flags;
+                    }
+                    _ => ()
+                }
+
+                let timestamping = self.timestampingFlags.load(Ordering::Relaxed);
+                if timestamping & SOF_TIMESTAMPING_RX_SOFTWARE != 0
+                    && timestamping & SOF_TIMESTAMPING_SOFTWARE != 0 {
+                    let mut ts = [Timespec::default(); 3];
+                    ts[0] = Timespec::FromNs(timestamp);
+                    let msg = ControlMessageTimeStamping(ts);
+                    let (remaining, updated_flags) = msg.EncodeInto(buf, flags);
+                    buf = remaining;
+                    flags = updated_flags;
+                }
+            }
+
+            buf.len()
+        };
+
+        controlData.resize(controlDataLen - remainSize, 0);
+        return (flags, controlData)
     }
 
     pub fn AsyncAcceptEnabled(&self) -> bool {
@@ -240,11 +560,83 @@ impl SocketOperations {
         return self.socketBuf.lock().clone();
     }
 
-    pub fn SocketBuf(&self) -> Arc<SocketBuff> {
+    pub fn SocketBuf(&self) -> Result<Arc<SocketBuff>> {
+        match self.SocketBufType() {
+            SocketBufType::Uring(b) => return Ok(b),
+            SocketBufType::RDMA(b) => return Ok(b),
+            t => return Err(SockStateErr("SocketBuf", self.fd, &t)),
+        }
+    }
+
+    // StreamBuf returns the socket buffer of the buffered stream socket
+    pub fn StreamBuf(&self) -> Option<Arc<SocketBuff>> {
+        match self.SocketBufType() {
+            SocketBufType::Uring(b) | SocketBufType::RDMA(b) => return Some(b),
+            _ => return None,
+        }
+    }
+
+    // CheckSendPolicy checks the destination of the message with the net policy: the address of
+    // the inet socket, or the IP header in the packet of the packet socket and the raw socket
+    // with IP_HDRINCL
+    pub fn CheckSendPolicy(&self, task: &Task, srcs: &[IoVec], msgHdr: &MsgHdr) -> Result<()> {
+        let inet = self.family == AFType::AF_INET || self.family == AFType::AF_INET6;
+        if msgHdr.msgName != 0 && inet {
+            let name = unsafe {
+                core::slice::from_raw_parts(msgHdr.msgName as *const u8, msgHdr.nameLen as usize)
+            };
+            CheckNetPolicy(PolicyOp::Connect, name)?;
+        }
+
+        let packet = self.family == AFType::AF_PACKET || self.stype == SocketType::SOCK_PACKET;
+        if !(packet || (inet && self.stype == SockType::SOCK_RAW)) || !NetPolicyEnabled() {
+            return Ok(())
+        }
+
+        let mut hdr = [0u8; PACKET_POLICY_HDR];
+        let n = task.CopyDataInFromIovs(&mut hdr, srcs)?;
+        let pkt = &hdr[..n];
+        let dst = if packet && self.stype != SockType::SOCK_DGRAM {
+            FrameDst(pkt)
+        } else if packet {
+            // the protocol of sockaddr_ll, or the one of the IP version
+            let proto = if msgHdr.msgName != 0 && msgHdr.nameLen >= 4 {
+                let name = unsafe {
+                    core::slice::from_raw_parts(msgHdr.msgName as *const u8, 4)
+                };
+                u16::from_be_bytes([name[2], name[3]])
+            } else {
+                VersionProto(pkt)
+            };
+            IpPacketDst(proto, pkt)
+        } else {
+            let (level, name, proto) = if self.family == AFType::AF_INET {
+                (SOL_IP, LibcConst::IP_HDRINCL as i32, ETH_P_IP)
+            } else {
+                (SOL_IPV6, LibcConst::IPV6_HDRINCL as i32, ETH_P_IPV6)
+            };
+
+            // without the IP header the destination is the address checked above
+            if GetSockOptI32(self.fd, level, name).unwrap_or(0) == 0 {
+                return Ok(())
+            }
+            IpPacketDst(proto, pkt)
+        };
+
+        return CheckPacketPolicy(dst)
+    }
+
+    pub fn DgramBuf(&self) -> Option<Arc<DgramBuff>> {
+        match self.SocketBufType() {
+            SocketBufType::Dgram(b) => return Some(b),
+            _ => return None,
+        }
+    }
+
+    pub fn LoopbackSock(&self) -> Option<Arc<LoopbackSock>> {
         match self.SocketBufType() {
-            SocketBufType::Uring(b) => return b,
-            SocketBufType::RDMA(b) => return b,
-            _ => panic!("SocketBufType::None has no SockBuff"),
+            SocketBufType::Loopback(s) => return Some(s),
+            _ => return None,
         }
     }
 
@@ -252,10 +644,229 @@ impl SocketOperations {
         match self.SocketBufType() {
             SocketBufType::Uring(_) => return true,
             SocketBufType::RDMA(_) => return true,
+            SocketBufType::Loopback(_) => return true,
             _ => false,
         }
     }
 
+    // SockBufOptInGuest returns whether the options bound to the socket buffer (SO_RCVLOWAT,
+    // TCP_CORK) are handled by the guest, including the socket which gets the
+    // buffer when it is connected or accepted
+    pub fn SockBufOptInGuest(&self) -> bool {
+        match self.SocketBufType() {
+            SocketBufType::Uring(_) | SocketBufType::RDMA(_) |
+            SocketBufType::TCPUringlServer(_) | SocketBufType::TCPRDMAServer(_) => return true,
+            SocketBufType::TCPInit => {
+                let config = SHARESPACE.config.read();
+                return config.UringIO || config.EnableRDMA
+            }
+            _ => return false,
+        }
+    }
+
+    // SockBufSize returns the size of the guest read buf with SO_RCVBUF or the write buf with
+    // SO_SNDBUF, it is the size of the buffer to be created before the socket is connected
+    fn SockBufSize(&self, name: i32) -> usize {
+        let rcv = name == SO_RCVBUF || name == SO_RCVBUFFORCE;
+        if let Some(buf) = self.StreamBuf() {
+            if rcv {
+                return buf.RcvBufSize()
+            }
+
+            return buf.SndBufSize()
+        }
+
+        let val = if rcv {
+            self.rcvBuf.load(Ordering::Relaxed)
+        } else {
+            self.sndBuf.load(Ordering::Relaxed)
+        };
+        return (SocketBufPages(val as usize) * MemoryDef::PAGE_SIZE) as usize
+    }
+
+    // InitSockBufOpts sets the low watermark and the flush policy of the new socket buffer
+    pub fn InitSockBufOpts(&self, buf: &SocketBuff) {
+        buf.SetRcvLowat(self.rcvLowat.load(Ordering::Relaxed) as usize);
+        buf.SetNoDelay(self.noDelay.load(Ordering::Relaxed));
+        // same as linux, SO_RCVBUF/SO_SNDBUF turn off the autotuning
+        buf.SetAutoTune(self.rcvBuf.load(Ordering::Relaxed) == 0, self.sndBuf.load(Ordering::Relaxed) == 0);
+        buf.SetRxStampWanted(self.RxStampWanted());
+    }
+
+    // RxStampWanted returns whether SO_TIMESTAMP, SO_TIMESTAMPNS or SO_TIMESTAMPING asks for the
+    // software receive timestamp
+    fn RxStampWanted(&self) -> bool {
+        return self.passTimestamp.load(Ordering::Relaxed) != 0
+            || self.timestampingFlags.load(Ordering::Relaxed) & SOF_TIMESTAMPING_RX_SOFTWARE != 0
+    }
+
+    pub fn Upgrade(sock: &Weak<SocketOperationsIntern>) -> Option<Self> {
+        return sock.upgrade().map(|s| Self(s))
+    }
+
+    pub fn Downgrade(&self) -> Weak<SocketOperationsIntern> {
+        return Arc::downgrade(&self.0)
+    }
+
+    pub fn OobWait(&self) -> bool {
+        return self.oobWaiters.load(Ordering::Relaxed) > 0
+    }
+
+    // NewRecvBuf allocates the buf of a host receive. A datagram which doesn't fit the buf is
+    // truncated, so only the stream socket may get a buf shorter than the size.
+    pub fn NewRecvBuf(&self, size: usize) -> Result<DataBuff> {
+        if self.stype == SockType::SOCK_STREAM {
+            return DataBuff::NewUserIO(size)
+        }
+
+        return DataBuff::NewUser(size)
+    }
+
+    fn NatEnabled(&self) -> bool {
+        return NAT.Enabled() && self.family == AFType::AF_INET && self.stype == SockType::SOCK_STREAM
+    }
+
+    // NatOutput returns the destination rewritten by the OUTPUT nat rules
+    fn NatOutput(&self, task: &Task, sockaddr: &[u8]) -> Option<[u8; SIZEOF_SOCKADDR_INET]> {
+        if !self.NatEnabled() {
+            return None
+        }
+
+        let (dst, dport) = SockAddrV4(sockaddr)?;
+        let (src, sport) = HostSockAddrV4(self.fd, false).unwrap_or((0, 0));
+        let owner = task.FileOwner();
+        let conn = NatConn {
+            src: src,
+            sport: sport,
+            dst: dst,
+            dport: dport,
+            uid: owner.UID.0,
+            gid: owner.GID.0,
+            output: true,
+        };
+
+        let (addr, port) = NAT.Eval(&conn)?;
+        return Some(NewSockAddrV4(addr, port))
+    }
+
+    // NatOrigDst returns the original destination of the redirected connection. The accepted
+    // connection is tracked by its peer and the connecting one by its local address.
+    fn NatOrigDst(&self) -> Option<(u32, u16)> {
+        let orig = HostSockAddrV4(self.fd, true).and_then(|peer| NAT.OrigDst(peer));
+        if orig.is_some() {
+            return orig
+        }
+
+        return HostSockAddrV4(self.fd, false).and_then(|local| NAT.OrigDst(local))
+    }
+
+    // NatPrerouting hands the accepted connection over to the guest listener of the PREROUTING
+    // REDIRECT port, it returns the connection when it is not taken
+    fn NatPrerouting(&self, item: AcceptItem) -> Option<AcceptItem> {
+        if !self.NatEnabled() || self.AcceptQueue().is_none() || item.loopback {
+            return Some(item)
+        }
+
+        let (src, sport) = match SockAddrV4(&item.addr.data) {
+            None => return Some(item),
+            Some(a) => a,
+        };
+
+        let (dst, dport) = match HostSockAddrV4(item.fd, false) {
+            None => return Some(item),
+            Some(a) => a,
+        };
+
+        let conn = NatConn {
+            src: src,
+            sport: sport,
+            dst: dst,
+            dport: dport,
+            output: false,
+            ..Default::default()
+        };
+
+        let port = match NAT.Eval(&conn) {
+            None => {
+                // the client address may be of an old redirected connection
+                NAT.Untrack((src, sport));
+                return Some(item)
+            }
+            Some((_, port)) => port,
+        };
+
+        let queue = match NAT.Listener(port).and_then(|l| l.AcceptQueue().map(|q| (l, q))) {
+            None => {
+                info!("nat: no listener of port {} for the connection to port {}", port, dport);
+                NAT.Untrack((src, sport));
+                return Some(item)
+            }
+            Some(q) => q,
+        };
+
+        let (listener, queue) = queue;
+        NAT.Track((src, sport), (dst, dport));
+        let full = queue.lock().EnqRedirected(item);
+        if let Some(item) = full {
+            // the listener has its backlog full, the connection is dropped
+            info!("nat: the listener of port {} is full, drop the connection to port {}", port, dport);
+            NAT.Untrack((src, sport));
+            HostSpace::Close(item.fd);
+            return None
+        }
+
+        listener.Notify(EVENT_IN);
+        return None
+    }
+
+    // the cork is ordered with the held send, see QUring::SocketSendQueued
+    pub fn Corked(&self) -> bool {
+        return self.cork.load(Ordering::SeqCst)
+    }
+
+    // ArmCorkTimer starts the timer to send the held data when the socket is not uncorked in time
+    pub fn ArmCorkTimer(&self) {
+        let mut timer = self.corkTimer.lock();
+        if timer.is_none() {
+            let listener = CorkTimerListener {
+                sock: Arc::downgrade(&self.0),
+            };
+            *timer = Some(Timer::New(&MONOTONIC_CLOCK, TimerListener::CorkTimerListener(Arc::new(listener))));
+        }
+
+        // the held data waits CORK_TIMEOUT at most, the later corked writes don't push it back
+        let timer = timer.as_ref().unwrap();
+        if timer.Get().1.Enabled {
+            return
+        }
+
+        let now = MONOTONIC_CLOCK.Now();
+        timer.Swap(&Setting {
+            Enabled: true,
+            Period: 0,
+            Next: now.Add(CORK_TIMEOUT),
+        });
+    }
+
+    // FlushCork starts sending the data held by TCP_CORK/MSG_MORE
+    pub fn FlushCork(&self) {
+        let buf = match self.SocketBufType() {
+            SocketBufType::Uring(buf) => buf,
+            _ => return,
+        };
+
+        if !buf.ReleaseSend() {
+            return
+        }
+
+        let (addr, len) = buf.GetAvailableWriteBuf();
+        if len == 0 {
+            return
+        }
+
+        QUring::SocketSendStart(self.fd, self.queue.clone(), buf, addr, len, self);
+    }
+
     pub fn AcceptQueue(&self) -> Option<AcceptQueue> {
         match self.SocketBufType() {
             SocketBufType::TCPUringlServer(q) => return Some(q.clone()),
@@ -264,24 +875,39 @@ impl SocketOperations {
         }
     }
 
-    pub fn PostConnect(&self, task: &Task) {
-         let socketBuf = self.SocketBufType().Connect();
+    pub fn PostConnect(&self, task: &Task) -> Result<()> {
+        // the host connects to the listener of another sandbox on the host over the shared memory
+        if SHARESPACE.config.read().LoopbackFastPath && self.family == AFType::AF_INET
+            && self.stype == SockType::SOCK_STREAM && matches!(self.SocketBufType(), SocketBufType::TCPInit) {
+            if let Some(sock) = LoopbackSock::Connected(self.fd)? {
+                *self.socketBuf.lock() = SocketBufType::Loopback(sock);
+                return Ok(())
+            }
+        }
+
+        let socketBuf = self.SocketBufType().Connect(self.rcvBuf.load(Ordering::Relaxed) as usize,
+                                                     self.sndBuf.load(Ordering::Relaxed) as usize)?;
         *self.socketBuf.lock() = socketBuf.clone();
 
         match socketBuf {
             SocketBufType::RDMA(buf) => {
-                assert!((self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
+                debug_assert!((self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
                     && self.stype == SockType::SOCK_STREAM, "family {}, stype {}", self.family, self.stype);
+                self.InitSockBufOpts(&buf);
+                INET_SOCKETS.AddRDMA(self.fd, false, &buf);
                 HostSpace::PostRDMAConnect(task, self.fd, buf);
             }
             SocketBufType::Uring(buf) => {
-                assert!((self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
+                debug_assert!((self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
                     && self.stype == SockType::SOCK_STREAM, "family {}, stype {}", self.family, self.stype);
-                QUring::BufSockInit(self.fd, self.queue.clone(), buf, true).unwrap();
+                self.InitSockBufOpts(&buf);
+                QUring::BufSockInit(self.fd, self.queue.clone(), buf, true)?;
             }
             _ => ()
         }
 
+        return Ok(())
+
 
         /*assert!((self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
             && self.stype == SockType::SOCK_STREAM, "family {}, stype {}", self.family, self.stype);
@@ -297,22 +923,72 @@ impl SocketOperations {
     }
 
     pub fn AcceptData(&self) -> Result<AcceptItem> {
+        match self.AcceptHostData() {
+            Err(Error::SysError(SysErr::EAGAIN)) => return self.LoopbackAccept(),
+            ret => return ret,
+        }
+    }
+
+    // LoopbackAccept accepts a connection of another sandbox on the host, the host gives the
+    // inet address of the client
+    fn LoopbackAccept(&self) -> Result<AcceptItem> {
+        let lfd = self.loopbackFd.load(Ordering::Relaxed);
+        if lfd < 0 {
+            return Err(Error::SysError(SysErr::EAGAIN))
+        }
+
+        let mut ai = AcceptItem::default();
+        ai.len = ai.addr.data.len() as _;
+        let res = HostSpace::LoopbackAccept(lfd, &ai.addr as * const _ as u64, &ai.len as * const _ as u64) as i32;
+        if res < 0 {
+            if res == -SysErr::EAGAIN {
+                ClearNotified(lfd, EVENT_IN);
+            }
+            return Err(Error::SysError(-res))
+        }
+
+        ai.fd = res;
+        ai.loopback = true;
+        return Ok(ai)
+    }
+
+    fn LoopbackReadiness(&self, mask: EventMask) -> EventMask {
+        let lfd = self.loopbackFd.load(Ordering::Relaxed);
+        if lfd < 0 || mask & EVENT_IN == 0 {
+            return 0
+        }
+
+        return NonBlockingPoll(lfd, EVENT_IN)
+    }
+
+    fn AcceptHostData(&self) -> Result<AcceptItem> {
         let sockBufType = self.socketBuf.lock().clone();
-        match sockBufType {
+        let item = match sockBufType {
             SocketBufType::TCPNormalServer => {
                 return self.IOAccept()
             }
             SocketBufType::TCPUringlServer(ref queue) => {
-                return IOURING.Accept(self.fd, &self.queue, queue)
+                IOURING.Accept(self.fd, &self.queue, queue)?
             }
             SocketBufType::TCPRDMAServer(ref queue) => {
-                return RDMA::Accept(self.fd, queue)
+                RDMA::Accept(self.fd, queue)?
             }
             _ => {
                 error!("SocketBufType invalid accept {:?}", sockBufType);
                 return Err(Error::SysError(SysErr::EINVAL))
             }
+        };
+
+        let err = item.Err();
+        if err != 0 {
+            if item.fd >= 0 {
+                HostSpace::Close(item.fd);
+            }
+
+            return Err(Error::SysError(err))
         }
+
+        return Ok(item)
     }
 
     pub fn ReadFromBuf(&self, task: &Task, sockBufType: SocketBufType, dsts: &mut [IoVec]) -> Result<i64> {
@@ -325,83 +1001,277 @@ impl SocketOperations {
                 let ret = RDMA::Read(task, self.fd, socketBuf, dsts);
                 return ret;
             }
+            SocketBufType::Loopback(sock) => {
+                return sock.Readv(task, &self.queue, dsts, false)
+            }
             t => {
-                panic!("ReadFromBuf get type {:?}", t);
+                return Err(SockStateErr("ReadFromBuf", self.fd, &t))
             }
         }
     }
 
-    pub fn WriteToBuf(&self, task: &Task, sockBufType: SocketBufType, srcs: &[IoVec]) -> Result<i64> {
+    // PeekFromBuf reads the socket buffer without consuming the data, it is the same for the
+    // uring and the rdma buffer as the read ring is only advanced by the consumer
+    pub fn PeekFromBuf(&self, task: &Task, sockBufType: SocketBufType, dsts: &mut [IoVec]) -> Result<i64> {
         match sockBufType {
-            SocketBufType::Uring(socketBuf) => {
-                let ret = QUring::SocketSend(task, self.fd, self.queue.clone(), socketBuf, srcs, self)?;
-                return Ok(ret);
+            SocketBufType::Uring(socketBuf) | SocketBufType::RDMA(socketBuf) => {
+                let ret = socketBuf.Peekv(task, dsts)?;
+                return Ok(ret as i64);
             }
-            SocketBufType::RDMA(socketBuf) => {
-                let ret = RDMA::Write(task, self.fd, socketBuf, srcs);
-                return ret;
+            SocketBufType::Loopback(sock) => {
+                return sock.Readv(task, &self.queue, dsts, true)
             }
             t => {
-                panic!("ReadFromBuf get type {:?}", t);
+                return Err(SockStateErr("PeekFromBuf", self.fd, &t))
             }
         }
     }
-}
-
-impl Deref for SocketOperations {
-    type Target = Arc<SocketOperationsIntern>;
-
-    fn deref(&self) -> &Arc<SocketOperationsIntern> {
-        &self.0
-    }
-}
-
-impl SocketOperations {
-    pub fn SetRemoteAddr(&self, addr: Vec<u8>) -> Result<()> {
-        let addr = GetAddr(addr[0] as i16, &addr[0..addr.len()])?;
-
-        *self.remoteAddr.lock() = Some(addr);
-        return Ok(())
-    }
 
-    pub fn GetRemoteAddr(&self) -> Option<Vec<u8>> {
-        return match *self.remoteAddr.lock() {
-            None => None,
-            Some(ref v) => Some(v.ToVec().unwrap()),
+    // RecvPeek is the recvmsg with MSG_PEEK of the buffered socket, it waits for the data without
+    // MSG_DONTWAIT and returns the data in the buffer at that point
+    fn RecvPeek(&self, task: &Task, dsts: &mut [IoVec], flags: i32, deadline: Option<Time>, controlDataLen: usize) -> Result<RecvMsgResult> {
+        let socketType = self.SocketBufType();
+        match self.PeekFromBuf(task, socketType.clone(), dsts) {
+            Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                if flags & MsgType::MSG_DONTWAIT != 0 {
+                    return Err(Error::SysError(SysErr::EWOULDBLOCK))
+                }
+            }
+            Err(e) => return Err(e),
+            Ok(n) => {
+                let (retFlags, controlData) = self.prepareControlMessage(controlDataLen, n as usize);
+                return Ok((n, retFlags, None, controlData))
+            }
         }
-    }
-}
 
-pub const SIZEOF_SOCKADDR: usize = SocketSize::SIZEOF_SOCKADDR_INET6;
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_READ);
+        defer!(self.EventUnregister(task, &general));
+
+        loop {
+            // check the buffer again after the registration to not miss the notification
+            match self.PeekFromBuf(task, socketType.clone(), dsts) {
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => (),
+                Err(e) => return Err(e),
+                Ok(n) => {
+                    let (retFlags, controlData) = self.prepareControlMessage(controlDataLen, n as usize);
+                    return Ok((n, retFlags, None, controlData))
+                }
+            }
+
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(Error::SysError(SysErr::EBADF));
+            }
+
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN));
+                }
+                Err(Error::ErrInterrupted) => {
+                    return Err(Error::SysError(SysErr::ERESTARTSYS));
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => ()
+            }
+        }
+    }
+
+    // more: MSG_MORE, more data is coming so that the data could be held
+    pub fn WriteToBuf(&self, task: &Task, sockBufType: SocketBufType, srcs: &[IoVec], more: bool) -> Result<i64> {
+        match sockBufType {
+            SocketBufType::Uring(socketBuf) => {
+                let ret = QUring::SocketSend(task, self.fd, self.queue.clone(), socketBuf, srcs, self, more)?;
+                return Ok(ret);
+            }
+            SocketBufType::RDMA(socketBuf) => {
+                let ret = RDMA::Write(task, self.fd, socketBuf, srcs);
+                return ret;
+            }
+            SocketBufType::Loopback(sock) => {
+                return sock.Writev(task, &self.queue, srcs)
+            }
+            t => {
+                return Err(SockStateErr("WriteToBuf", self.fd, &t))
+            }
+        }
+    }
+}
+
+impl Deref for SocketOperations {
+    type Target = Arc<SocketOperationsIntern>;
+
+    fn deref(&self) -> &Arc<SocketOperationsIntern> {
+        &self.0
+    }
+}
+
+impl SocketOperations {
+    // DebugString is the socket table entry of the sandbox debug report
+    pub fn DebugString(&self) -> String {
+        let mut str = format!("hostfd {} family {} type {} send {} recv {} inflight {}",
+                              self.fd, self.family, self.stype,
+                              self.send.load(Ordering::Relaxed),
+                              self.recv.load(Ordering::Relaxed),
+                              self.inflight.load(Ordering::Relaxed));
+        let socketBuf = self.socketBuf.lock();
+        match &*socketBuf {
+            SocketBufType::Uring(buf) | SocketBufType::RDMA(buf) => {
+                str += &format!(" {:?} {}", &*socketBuf, buf.DebugString());
+            }
+            SocketBufType::Dgram(buf) => {
+                str += &format!(" {:?} {:?}", &*socketBuf, buf);
+            }
+            SocketBufType::TCPUringlServer(queue) | SocketBufType::TCPRDMAServer(queue) => {
+                let queue = queue.lock();
+                str += &format!(" {:?} acceptq {}/{} paused {} total {}",
+                                &*socketBuf, queue.queue.len(), queue.highWatermark, queue.paused, queue.total);
+            }
+            t => {
+                str += &format!(" {:?}", t);
+            }
+        }
+
+        return str
+    }
+
+    // LastFdClosed returns whether the File is only referenced by the closing fd and
+    // the tasks in the socket calls, i.e. no fd refers to the socket any more.
+    pub fn LastFdClosed(&self, f: &File) -> bool {
+        let inflight = self.inflight.load(Ordering::SeqCst) as usize;
+        return Arc::strong_count(&f.0) <= inflight + 1
+    }
+
+    // WakeOnClose wakes up all the tasks blocked on the socket, they return EBADF
+    pub fn WakeOnClose(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.queue.Notify(EVENTMASK_ALL);
+    }
+
+    pub fn CheckClosed(&self) -> Result<()> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Error::SysError(SysErr::EBADF))
+        }
+
+        return Ok(())
+    }
+
+    pub fn SetRemoteAddr(&self, addr: Vec<u8>) -> Result<()> {
+        // connect with AF_UNSPEC dissolves the association of the udp socket
+        if addr.len() >= 2 && u16::from_ne_bytes([addr[0], addr[1]]) as i32 == AFType::AF_UNSPEC {
+            *self.remoteAddr.lock() = None;
+            return Ok(())
+        }
+
+        let addr = ParseSockAddr(&addr)?;
+
+        *self.remoteAddr.lock() = Some(addr);
+        return Ok(())
+    }
+
+    // CachedPeerAddr returns the peer inet address known by the guest. It is None when the
+    // connection may be closed, the host returns ENOTCONN for it. The reset of the tcp
+    // connection is only seen by the guest through the stream buf.
+    fn CachedPeerAddr(&self) -> Option<Vec<u8>> {
+        match self.StreamBuf() {
+            Some(buf) => {
+                if buf.Error() != 0 || (buf.RClosed() && buf.WClosed()) {
+                    return None
+                }
+            }
+            None => {
+                if self.stype == SockType::SOCK_STREAM {
+                    return None
+                }
+            }
+        }
+
+        let addr = self.GetRemoteAddr()?;
+        return NormalizeInetAddr(self.family, &addr)
+    }
+
+    pub fn GetRemoteAddr(&self) -> Option<Vec<u8>> {
+        return match *self.remoteAddr.lock() {
+            None => None,
+            Some(ref v) => Some(v.ToVec().unwrap()),
+        }
+    }
+}
+
+pub const SIZEOF_SOCKADDR: usize = SocketSize::SIZEOF_SOCKADDR_INET6;
 
 impl Waitable for SocketOperations {
     fn AsyncReadiness(&self, _task: &Task, mask: EventMask, wait: &MultiWait) -> Future<EventMask> {
-        if self.SocketBufEnabled() {
+        if self.FastOpenPending() {
+            let future = Future::New(0 as EventMask);
+            future.Set(Ok(EVENT_OUT & mask));
+            return future;
+        }
+
+        if let Some(buf) = self.StreamBuf() {
             let future = Future::New(0 as EventMask);
-            let ret = self.SocketBuf().Events() & mask;
+            let ret = buf.Events() & mask;
             future.Set(Ok(ret));
             //wait.Done();
             return future;
         };
 
+        if let Some(buf) = self.DgramBuf() {
+            let future = Future::New(0 as EventMask);
+            future.Set(Ok(buf.Events() & mask));
+            return future;
+        }
+
+        if let Some(sock) = self.LoopbackSock() {
+            let future = Future::New(0 as EventMask);
+            future.Set(Ok(sock.Events(&self.queue, mask) & mask));
+            return future;
+        }
 
         let fd = self.fd;
         let future = IOURING.UnblockPollAdd(fd, mask as u32, wait);
         return future;
     }
 
-    fn Readiness(&self, _task: &Task, mask: EventMask) -> EventMask {
-        if self.SocketBufEnabled() {
-            return self.SocketBuf().Events() & mask
+    fn Readiness(&self, task: &Task, mask: EventMask) -> EventMask {
+        // same as linux, the socket of the deferred connect is writable
+        if self.FastOpenPending() {
+            return EVENT_OUT & mask
+        }
+
+        if let Err(e) = self.PollConnect(task) {
+            LogSockErr(format_args!("PollConnect of socket fd {} fail with error {:?}", self.fd, e));
+        }
+
+        if let Some(buf) = self.StreamBuf() {
+            let mut event = buf.Events();
+            if buf.HostRead() && !buf.HasReadData() {
+                event = (event & !EVENT_IN) | NonBlockingPoll(self.fd, EVENT_IN | EVENT_RDHUP);
+            }
+            // the OOB data is not read to the socket buffer, it stays in the host socket
+            if mask & EVENT_PRI != 0 && self.OobWait() {
+                event |= NonBlockingPoll(self.fd, EVENT_PRI);
+            }
+            event |= self.ErrQueueReadiness(mask);
+            return event & mask
         };
 
+        if let Some(buf) = self.DgramBuf() {
+            return (buf.Events() | self.ErrQueueReadiness(mask)) & mask
+        }
+
+        if let Some(sock) = self.LoopbackSock() {
+            return sock.Events(&self.queue, mask) & mask
+        }
+
         match self.AcceptQueue() {
-            Some(q) => return q.lock().Events() & mask,
+            Some(q) => return (q.lock().Events() | self.LoopbackReadiness(mask)) & mask,
             None => ()
         }
 
         let fd = self.fd;
-        return NonBlockingPoll(fd, mask);
+        return NonBlockingPoll(fd, mask) | self.LoopbackReadiness(mask);
 
         /*let mv = MultiWait::New(task.GetTaskIdQ());
         error!("Readiness 1");
@@ -423,21 +1293,67 @@ impl Waitable for SocketOperations {
     }
 
     fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
+        // the PRI waiters are counted whatever the socket buffer is, it may change before the
+        // waiter is unregistered
+        if mask & EVENT_PRI != 0 {
+            self.oobWaiters.fetch_add(1, Ordering::Relaxed);
+        }
+
         let queue = self.queue.clone();
+        if let Some(sock) = self.LoopbackSock() {
+            // the doorbell of the space is EVENT_IN of the host socket too
+            let hostMask = if mask & EVENT_OUT != 0 { mask | EVENT_IN } else { mask };
+            queue.EventRegister(task, e, hostMask);
+            UpdateFD(self.fd).unwrap();
+
+            // the doorbell read before the registration is not notified again
+            let ready = sock.Events(&self.queue, mask) & mask;
+            if ready != 0 {
+                queue.Notify(ready);
+            }
+            return
+        }
+
         queue.EventRegister(task, e, mask);
         let fd = self.fd;
-        if !self.SocketBufEnabled() && self.AcceptQueue().is_none() {
+        if self.SocketBufEnabled() {
+            if self.OobWait() || self.RecvErrEnabled() || self.KtlsRxStarted() {
+                UpdateFD(fd).unwrap();
+            }
+        } else if self.AcceptQueue().is_none() && (self.DgramBuf().is_none() || self.RecvErrEnabled()) {
             UpdateFD(fd).unwrap();
         };
+
+        let lfd = self.loopbackFd.load(Ordering::Relaxed);
+        if lfd >= 0 {
+            UpdateFD(lfd).unwrap();
+        }
     }
 
     fn EventUnregister(&self, task: &Task, e: &WaitEntry) {
+        let oobWaiter = e.Mask() & EVENT_PRI != 0;
+        if oobWaiter {
+            self.oobWaiters.fetch_sub(1, Ordering::Relaxed);
+        }
+
         let queue = self.queue.clone();
         queue.EventUnregister(task, e);
         let fd = self.fd;
-        if !self.SocketBufEnabled() && self.AcceptQueue().is_none() {
+        if self.LoopbackSock().is_some() {
+            UpdateFD(fd).unwrap();
+        } else if self.SocketBufEnabled() {
+            // the host fd stops polling EVENT_PRI with the last PRI waiter
+            if oobWaiter || self.OobWait() || self.RecvErrEnabled() || self.KtlsRxStarted() {
+                UpdateFD(fd).unwrap();
+            }
+        } else if self.AcceptQueue().is_none() && (self.DgramBuf().is_none() || self.RecvErrEnabled()) {
             UpdateFD(fd).unwrap();
         };
+
+        let lfd = self.loopbackFd.load(Ordering::Relaxed);
+        if lfd >= 0 {
+            UpdateFD(lfd).unwrap();
+        }
     }
 }
 
@@ -446,50 +1362,98 @@ pub fn HostIoctlIFReq(task: &Task, hostfd: i32, request: u64, addr: u64) -> Resu
     let mut ifr : IFReq = task.CopyInObj(addr)?;
     let res = HostSpace::IoCtl(hostfd, request, &mut ifr as *const _ as u64);
     if res < 0 {
-        return Err(Error::SysError(-res as i32))
+        return Err(HostErr("IoCtl", hostfd, -res as i32))
     }
 
     task.CopyOutObj(&ifr, addr)?;
     return Ok(())
 }
 
-pub fn HostIoctlIFConf(task: &Task, hostfd: i32, request: u64, addr: u64) -> Result<()> {
-    let mut ifc : IFConf = task.CopyInObj(addr)?;
+// the ifreqs more than the list size got from the host, so that the interfaces added before
+// the list is got again are still returned
+pub const IFCONF_SLACK: usize = 16;
+
+// IFConfBufLen returns the size of the buffer to get the list of size bytes for the app buffer
+// of userLen bytes. Same as linux, only the whole ifreqs are filled and a negative length gets
+// nothing.
+pub fn IFConfBufLen(userLen: i32, size: usize) -> usize {
+    let ifreq = core::mem::size_of::<IFReq>();
+    if userLen <= 0 {
+        return 0
+    }
 
-    const MAX_LEN : usize = 64 * 0x1000; // 256 KB
+    let len = core::cmp::min(userLen as usize, size + IFCONF_SLACK * ifreq);
+    return len / ifreq * ifreq
+}
 
-    // todo: how to handle very large ifconf?
-    let len = if MAX_LEN > ifc.Len as usize {
-        ifc.Len as usize
-    } else {
-        MAX_LEN
-    };
+// HostIFConfLen gets the size of the whole ifconf list of the host
+fn HostIFConfLen(hostfd: i32, request: u64) -> Result<usize> {
+    let mut ifr = IFConf::default();
+    let res = HostSpace::IoCtl(hostfd, request, &mut ifr as *const _ as u64);
+    if res < 0 {
+        return Err(HostErr("IoCtl", hostfd, -res as i32))
+    }
 
-    let buf = DataBuff::New(len);
+    return Ok(ifr.Len as usize)
+}
 
-    let mut ifr = IFConf {
-        Len: len as i32,
-        ..Default::default()
-    };
+// HostIoctlIFConf gets the ifconf list of the host. The app without the buffer gets the size of
+// the list. The buffer of the host call is sized with the list size instead of the app buffer,
+// and it is got again when the list grows over the slack.
+pub fn HostIoctlIFConf(task: &Task, hostfd: i32, request: u64, addr: u64) -> Result<()> {
+    let mut ifc : IFConf = task.CopyInObj(addr)?;
 
-    if ifc.Ptr != 0 {
-        ifr.Ptr = buf.Ptr();
+    let mut size = HostIFConfLen(hostfd, request)?;
+    if ifc.Ptr == 0 {
+        ifc.Len = size as i32;
+        task.CopyOutObj(&ifc, addr)?;
+        return Ok(())
     }
 
-    let res = HostSpace::IoCtl(hostfd, request, &mut ifr as *const _ as u64);
-    if res < 0 {
-        return Err(Error::SysError(-res as i32))
-    }
+    loop {
+        let len = IFConfBufLen(ifc.Len, size);
+        if len == 0 {
+            ifc.Len = 0;
+            break;
+        }
+
+        let buf = DataBuff::New(len);
+        let mut ifr = IFConf {
+            Len: len as i32,
+            Ptr: buf.Ptr(),
+            ..Default::default()
+        };
+
+        let res = HostSpace::IoCtl(hostfd, request, &mut ifr as *const _ as u64);
+        if res < 0 {
+            return Err(HostErr("IoCtl", hostfd, -res as i32))
+        }
 
-    task.mm.CopyDataOut(task, ifr.Ptr, ifc.Ptr, ifr.Len as usize)?;
+        // the full buffer smaller than the app one may miss the new interfaces
+        if ifr.Len as usize == len && len < ifc.Len as usize {
+            let newSize = HostIFConfLen(hostfd, request)?;
+            if newSize > size {
+                size = newSize;
+                continue;
+            }
+        }
 
-    ifc.Len = ifr.Len;
+        task.mm.CopyDataOut(task, ifr.Ptr, ifc.Ptr, ifr.Len as usize)?;
+        ifc.Len = ifr.Len;
+        break;
+    }
 
     task.CopyOutObj(&ifc, addr)?;
     return Ok(())
 }
 
-impl SpliceOperations for SocketOperations {}
+impl SpliceOperations for SocketOperations {
+    fn ReadFrom(&self, task: &Task, _file: &File, src: &File, opts: &SpliceOpts) -> Result<i64> {
+        let n = self.SendFile(task, src, opts)?;
+        SHARESPACE.sockStats.Send(n);
+        return Ok(n)
+    }
+}
 
 impl FileOperations for SocketOperations {
     fn as_any(&self) -> &Any {
@@ -512,46 +1476,92 @@ impl FileOperations for SocketOperations {
         return Err(Error::SysError(SysErr::ENOTDIR))
     }
 
-    fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
-        let sockBufType = self.socketBuf.lock().clone();
-        match sockBufType {
+    fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, blocking: bool) -> Result<i64> {
+        // the deferred connect is done without data before the socket waits for the peer
+        let flags = if blocking { 0 } else { MsgType::MSG_DONTWAIT };
+        if let Some(ret) = self.DeferredConnect(task, &[], flags, &MsgHdr::default(), None) {
+            ret?;
+        }
+
+        self.PollConnect(task)?;
+        // the data after the one read ahead is decrypted by the host for kTLS RX
+        let sockBufType = if self.KtlsHostRead() {
+            SocketBufType::TCPNormalData
+        } else {
+            self.socketBuf.lock().clone()
+        };
+
+        let ret = match sockBufType {
             SocketBufType::Uring(socketBuf) => {
-                let ret = QUring::RingFileRead(task, self.fd, self.queue.clone(), socketBuf, dsts, true)?;
-                return Ok(ret);
+                QUring::RingFileRead(task, self.fd, self.queue.clone(), socketBuf, dsts, true)?
             }
             SocketBufType::RDMA(socketBuf) => {
-                let ret = RDMA::Read(task, self.fd, socketBuf, dsts);
-                return ret;
+                RDMA::Read(task, self.fd, socketBuf, dsts)?
+            }
+            SocketBufType::Loopback(sock) => {
+                sock.Readv(task, &self.queue, dsts, false)?
+            }
+            SocketBufType::Dgram(buf) => {
+                let (n, _, _, _) = self.DgramRecv(task, &buf, dsts, MsgType::MSG_DONTWAIT, None, false, 0)?;
+                n
             }
             _ => {
                 let size = IoVec::NumBytes(dsts);
-                let buf = DataBuff::New(size);
+                let buf = self.NewRecvBuf(size)?;
                 let iovs = buf.Iovs();
                 let ret = IORead(self.fd, &iovs)?;
                 task.CopyDataOutToIovs(&buf.buf[0..ret as usize], dsts)?;
-                return Ok(ret);
+                ret
             }
-        }
+        };
+
+        SHARESPACE.sockStats.Recv(ret);
+        return Ok(ret)
     }
 
-    fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+    fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, blocking: bool) -> Result<i64> {
+        let flags = if blocking { 0 } else { MsgType::MSG_DONTWAIT };
+        if let Some(ret) = self.DeferredConnect(task, srcs, flags, &MsgHdr::default(), None) {
+            let n = ret?;
+            SHARESPACE.sockStats.Send(n);
+            return Ok(n)
+        }
+
+        self.PollConnect(task)?;
+        // the packet written to the packet socket or the raw socket
+        self.CheckSendPolicy(task, srcs, &MsgHdr::default())?;
+        let _ktlsSend = if self.KtlsTx() && self.SocketBufEnabled() {
+            Some(self.ktlsSend.Lock(task)?)
+        } else {
+            None
+        };
+
         let sockBufType = self.socketBuf.lock().clone();
-        match sockBufType {
+        let ret = match sockBufType {
             SocketBufType::Uring(socketBuf) => {
-                return QUring::SocketSend(task, self.fd, self.queue.clone(), socketBuf, srcs, self)
+                QUring::SocketSend(task, self.fd, self.queue.clone(), socketBuf, srcs, self, false)?
             }
             SocketBufType::RDMA(socketBuf) => {
-                let ret = RDMA::Write(task, self.fd, socketBuf, srcs)?;
-                return Ok(ret);
+                RDMA::Write(task, self.fd, socketBuf, srcs)?
+            }
+            SocketBufType::Loopback(sock) => {
+                sock.Writev(task, &self.queue, srcs)?
+            }
+            SocketBufType::Dgram(buf) => {
+                self.DgramSend(task, &buf, srcs, MsgType::MSG_DONTWAIT, &MsgHdr::default(), None)?
             }
             _ => {
+                // the datagram can't be cut
                 let size = IoVec::NumBytes(srcs);
-                let mut buf = DataBuff::New(size);
+                let mut buf = DataBuff::NewUser(size)?;
                 let iovs = buf.Iovs();
                 task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
-                return IOWrite(self.fd, &iovs);
+                IOWrite(self.fd, &iovs)?
             }
-        }
+        };
+
+        SHARESPACE.sockStats.Send(ret);
+        return Ok(ret)
     }
 
     fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
@@ -563,7 +1573,32 @@ impl FileOperations for SocketOperations {
         return Err(Error::SysError(SysErr::EINVAL))
     }
 
-    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+    fn Flush(&self, task: &Task, f: &File) -> Result<()> {
+        if !self.LastFdClosed(f) {
+            return Ok(())
+        }
+
+        if self.inflight.load(Ordering::SeqCst) > 0 {
+            self.WakeOnClose();
+        }
+
+        if let Some(timer) = self.corkTimer.lock().take() {
+            timer.Destroy();
+        }
+
+        // the recvmsg in the uring holds the host socket and its port until it is woken up
+        if let Some(buf) = self.DgramBuf() {
+            buf.ShutdownRead();
+            HostSpace::Shutdown(self.fd, LibcConst::SHUT_RD as i32);
+        }
+
+        // SO_LINGER only takes effect when the last fd of the socket is closed
+        if self.SocketBufEnabled() {
+            // the held data is sent before close
+            self.FlushCork();
+            self.Linger(task);
+        }
+
         return Ok(())
     }
 
@@ -601,15 +1636,23 @@ impl FileOperations for SocketOperations {
                 return Ok(())
             }
             LibcConst::TIOCINQ => {
-                if self.SocketBufEnabled() {
-                    let v =  self.SocketBuf().readBuf.lock().AvailableDataSize() as i32;
+                if let Some(buf) = self.DgramBuf() {
+                    let v = buf.NextSize() as i32;
+                    task.CopyOutObj(&v, val)?;
+                    return Ok(())
+                } else if let Some(buf) = self.StreamBuf() {
+                    let v = buf.ReadableSize() as i32;
+                    task.CopyOutObj(&v, val)?;
+                    return Ok(())
+                } else if let Some(sock) = self.LoopbackSock() {
+                    let v = sock.AvailableDataSize() as i32;
                     task.CopyOutObj(&v, val)?;
                     return Ok(())
                 } else {
                     let tmp: i32 = 0;
                     let res = Kernel::HostSpace::IoCtl(self.fd, request, &tmp as *const _ as u64);
                     if res < 0 {
-                        return Err(Error::SysError(-res as i32))
+                        return Err(HostErr("IoCtl", self.fd, -res as i32))
                     }
                     task.CopyOutObj(&tmp, val)?;
                     return Ok(())
@@ -619,7 +1662,7 @@ impl FileOperations for SocketOperations {
                 let tmp: i32 = 0;
                 let res = Kernel::HostSpace::IoCtl(self.fd, request, &tmp as *const _ as u64);
                 if res < 0 {
-                    return Err(Error::SysError(-res as i32))
+                    return Err(HostErr("IoCtl", self.fd, -res as i32))
                 }
                 task.CopyOutObj(&tmp, val)?;
                 return Ok(())
@@ -638,84 +1681,83 @@ impl FileOperations for SocketOperations {
 
 impl SocketOperations {
     //pub fn ConnectIntern(fd: i32, addr: u64, addrlen: u32) -> i64 {}
-}
-
-impl SockOperations for SocketOperations {
-    fn Connect(&self, task: &Task, sockaddr: &[u8], blocking: bool) -> Result<i64> {
-        let mut socketaddr = sockaddr;
 
-        if (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
-            && socketaddr.len() > SIZEOF_SOCKADDR {
-            socketaddr = &socketaddr[..SIZEOF_SOCKADDR]
+    // Linger handles the SO_LINGER of socket buffer on close.
+    // The unsent data in the write buffer is flushed by the in flight send which holds the socket.
+    // The close waits for the write buffer drained until the timeout, same as linux the
+    // connection is reset when the data is not drained in time or the timeout is zero. The host
+    // socket gets the linger of the result so that its close never blocks the host thread.
+    fn Linger(&self, task: &Task) {
+        let linger = *self.linger.lock();
+        if linger.OnOff == 0 {
+            return
         }
 
-        let res = Kernel::HostSpace::IOConnect(self.fd, &socketaddr[0] as *const _ as u64, socketaddr.len() as u32) as i32;
-        if res == 0 {
-            self.SetRemoteAddr(socketaddr.to_vec())?;
-            self.PostConnect(task);
+        let buf = match self.StreamBuf() {
+            None => return,
+            Some(buf) => buf,
+        };
 
-            return Ok(0)
+        // a negative timeout is a huge one for linux
+        if linger.Linger != 0 && self.WaitWriteDrained(task, &buf, linger.Linger as u32 as i64 * SECOND) {
+            self.SetHostLinger(Linger::default());
+            return
         }
 
-        let blocking = if blocking {
-            true
-        } else {
-            // in order to enable uring buff, have to do block accept
-            if SHARESPACE.config.read().UringIO
-                && (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
-                && self.stype == SockType::SOCK_STREAM {
-                true
-            } else {
-                false
-            }
+        self.AbortWrite(&buf);
+    }
 
-            //false
-        };
+    // WaitWriteDrained waits for the write buffer sent until the timeout, it returns false when
+    // the data is left
+    fn WaitWriteDrained(&self, task: &Task, buf: &Arc<SocketBuff>, timeout: i64) -> bool {
+        if !buf.HasWriteData() {
+            return true
+        }
 
-        if res != 0 {
-            if -res != SysErr::EINPROGRESS || !blocking {
-                return Err(Error::SysError(-res))
-            }
+        buf.SetPendingWriteShutdown();
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_PENDING_SHUTDOWN | EVENT_ERR | EVENT_HUP);
+        defer!(self.EventUnregister(task, &general));
 
-            //todo: which one is more efficent?
-            let general = task.blocker.generalEntry.clone();
-            self.EventRegister(task, &general, EVENT_OUT);
-            defer!(self.EventUnregister(task, &general));
+        let deadline = Some(Time(MonotonicNow() + timeout));
+        while buf.HasWriteData() {
+            // the data can't be sent after the send error
+            if buf.Error() != 0 {
+                return false
+            }
 
-            if self.Readiness(task, EVENT_OUT) == 0 {
-                match task.blocker.BlockWithMonoTimer(true, None) {
-                    Err(Error::ErrInterrupted) => {
-                        return Err(Error::SysError(SysErr::ERESTARTSYS));
-                    }
-                    Err(e) => {
-                        error!("connect error {:?}", &e);
-                        return Err(e);
-                    }
-                    _ => ()
-                }
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(_) => return false,
+                _ => ()
             }
         }
 
-        let mut val: i32 = 0;
-        let len: i32 = 4;
-        let res = HostSpace::GetSockOpt(self.fd, LibcConst::SOL_SOCKET as i32, LibcConst::SO_ERROR as i32, &mut val as *mut i32 as u64, &len as *const i32 as u64) as i32;
+        return true
+    }
 
-        if res < 0 {
-            return Err(Error::SysError(-res))
-        }
+    // AbortWrite drops the unsent data of the write buffer and resets the connection with the
+    // host close
+    fn AbortWrite(&self, buf: &Arc<SocketBuff>) {
+        buf.SetWriteAbort();
+        self.SetHostLinger(Linger { OnOff: 1, Linger: 0 });
 
-        if val != 0 {
-            return Err(Error::SysError(val as i32))
+        // the send in flight may wait for the window of the peer forever, the shutdown fails it
+        // so that the socket is released and closed
+        if buf.HasWriteData() {
+            HostSpace::Shutdown(self.fd, LibcConst::SHUT_WR as i32);
         }
+    }
 
-
-        self.SetRemoteAddr(socketaddr.to_vec())?;
-        self.PostConnect(task);
-
-        return Ok(0)
+    fn SetHostLinger(&self, linger: Linger) {
+        let res = HostSpace::SetSockOpt(self.fd, LibcConst::SOL_SOCKET as i32, LibcConst::SO_LINGER as i32,
+                                        &linger as *const _ as u64, SocketSize::SIZEOF_LINGER as u32);
+        if res < 0 {
+            error!("SetHostLinger fd {} fail {}", self.fd, -res);
+        }
     }
 
-    fn Accept(&self, task: &Task, addr: &mut [u8], addrlen: &mut u32, flags: i32, blocking: bool) -> Result<i64> {
+    // WaitAcceptItem gets the next connection of the listening socket
+    fn WaitAcceptItem(&self, task: &Task, blocking: bool) -> Result<AcceptItem> {
         let mut acceptItem = AcceptItem::default();
         if !blocking {
             let ai = self.AcceptData();
@@ -749,6 +1791,7 @@ impl SockOperations for SocketOperations {
                         break;
                     }
                 }
+                self.CheckClosed()?;
                 match task.blocker.BlockWithMonoTimer(true, None) {
                     Err(e) => {
                         return Err(e);
@@ -758,28 +1801,816 @@ impl SockOperations for SocketOperations {
             }
         }
 
-        let mut len : usize = acceptItem.addr.data.len();
-        if addr.len() > 0 {
-            len = core::cmp::min(core::cmp::min(acceptItem.len as usize, addr.len()), acceptItem.addr.data.len());
-            for i in 0..len {
-                addr[i] = acceptItem.addr.data[i];
-            }
+        return Ok(acceptItem)
+    }
 
-            *addrlen = len as u32;
+    // UringConnect returns whether the connected socket gets the uring socket buffer
+    fn UringConnect(&self) -> bool {
+        return SHARESPACE.config.read().UringIO
+            && (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
+            && self.stype == SockType::SOCK_STREAM
+    }
+
+    // DeferConnect keeps the nonblocking connect in progress of the buffered socket, the socket
+    // buffer is set up by PollConnect after the host socket is writable
+    fn DeferConnect(&self, action: ConnectAction, socketaddr: &[u8]) {
+        if action != ConnectAction::Fail(SysErr::EINPROGRESS) || !self.UringConnect() {
+            return
         }
 
-        let fd = acceptItem.fd;
+        *self.connectDst.lock() = Some(socketaddr.to_vec());
+        self.asyncConnect.store(ASYNC_CONNECT_PENDING, Ordering::Release);
+    }
 
-        let remoteAddr = &acceptItem.addr.data[0..len];
-        //let sockBuf = self.ConfigSocketBufType();
-        let sockBuf = self.SocketBufType().Accept(acceptItem.sockBuf.clone());
+    // PollConnect finishes the nonblocking connect once the host socket is writable or gets
+    // error, it is done by the socket call which comes first, e.g. the poll of the app. The
+    // result is kept for the app as the host SO_ERROR is cleared when it is sampled.
+    pub fn PollConnect(&self, task: &Task) -> Result<()> {
+        if self.asyncConnect.load(Ordering::Acquire) != ASYNC_CONNECT_PENDING {
+            return Ok(())
+        }
 
-        let file = newSocketFile(task,
-                                 self.family,
-                                 fd as i32,
-                                 self.stype,
+        if NonBlockingPoll(self.fd, EVENT_OUT | EVENT_ERR | EVENT_HUP) == 0 {
+            return Ok(())
+        }
+
+        if self.asyncConnect.compare_exchange(ASYNC_CONNECT_PENDING, ASYNC_CONNECT_NONE,
+                                              Ordering::AcqRel, Ordering::Acquire).is_err() {
+            return Ok(())
+        }
+
+        let dst = self.connectDst.lock().take();
+        let action = {
+            let mut state = self.connectState.lock();
+            // the blocking connect of another task has finished it
+            if *state != ConnectState::Connecting {
+                return Ok(())
+            }
+
+            let soError = self.HostSoError()?;
+            state.OnSoError(soError)
+        };
+
+        match action {
+            ConnectAction::PostConnect(_) => {
+                if let Some(dst) = dst {
+                    self.SetRemoteAddr(dst)?;
+                }
+
+                self.PostConnect(task)?;
+                self.asyncConnect.store(ASYNC_CONNECT_DONE, Ordering::Release);
+            }
+            ConnectAction::Fail(errno) => {
+                self.asyncConnect.store(errno, Ordering::Release);
+            }
+            _ => (),
+        }
+
+        return Ok(())
+    }
+
+    // TakeConnectResult returns the result of the connect finished by PollConnect once
+    fn TakeConnectResult(&self, connect: bool) -> Option<Result<i64>> {
+        return TakeAsyncConnect(&self.asyncConnect, connect)
+    }
+
+    // HostSoError samples the SO_ERROR of the host socket, it is cleared by the read
+    fn HostSoError(&self) -> Result<i32> {
+        let mut val: i32 = 0;
+        let len: i32 = 4;
+        let res = HostSpace::GetSockOpt(self.fd, LibcConst::SOL_SOCKET as i32, LibcConst::SO_ERROR as i32, &mut val as *mut i32 as u64, &len as *const i32 as u64) as i32;
+
+        if res < 0 {
+            return Err(HostErr("GetSockOpt", self.fd, -res))
+        }
+
+        return Ok(val)
+    }
+
+    // PrepareConnect binds the socket before the host connect and returns the destination
+    // rewritten by the nat rules
+    fn PrepareConnect(&self, task: &Task, socketaddr: &[u8]) -> Result<Option<[u8; SIZEOF_SOCKADDR_INET]>> {
+        if *self.connectState.lock() == ConnectState::Init {
+            ImplicitBind(self.fd, self.family, self.stype)?;
+        }
+
+        // the local ip is chosen by the route of the peer
+        *self.localAddr.lock() = None;
+
+        // netfilter-lite OUTPUT, the host connects to the rewritten destination while the guest
+        // still sees the original one
+        return Ok(self.NatOutput(task, socketaddr))
+    }
+
+    fn NatTrackConnect(&self, natAddr: &Option<[u8; SIZEOF_SOCKADDR_INET]>, socketaddr: &[u8], res: i32) {
+        if !self.NatEnabled() || (res != 0 && res != -SysErr::EINPROGRESS) {
+            return
+        }
+
+        if let Some(local) = HostSockAddrV4(self.fd, false) {
+            match (natAddr, SockAddrV4(socketaddr)) {
+                (Some(_), Some(orig)) => NAT.Track(local, orig),
+                // the local port may be of an old redirected connection
+                _ => NAT.Untrack(local),
+            }
+        }
+    }
+
+    // FastOpen is the sendto/sendmsg with MSG_FASTOPEN of the unconnected tcp socket. The host
+    // sendmsg connects the socket and puts the data in the SYN when it has the cookie of the
+    // peer, so the connect and the first write are one host call. The socket finishes the
+    // connect as connect does, e.g. the buffered socket gets the uring socket buffer after it.
+    fn FastOpen(&self, task: &Task, socketaddr: &[u8], srcs: &[IoVec], flags: i32, msgHdr: &MsgHdr, deadline: Option<Time>) -> Result<i64> {
+        let socketaddr = &socketaddr[..core::cmp::min(socketaddr.len(), SIZEOF_SOCKADDR)];
+        let blocking = flags & MsgType::MSG_DONTWAIT == 0;
+        let natAddr = self.PrepareConnect(task, socketaddr)?;
+        let hostAddr: &[u8] = match &natAddr {
+            None => socketaddr,
+            Some(addr) => &addr[..],
+        };
+
+        let size = IoVec::NumBytes(srcs);
+        let mut buf = DataBuff::NewUserIO(size)?;
+        let iovs = buf.Iovs();
+        task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
+
+        let mut hdr = *msgHdr;
+        hdr.msgName = if hostAddr.len() == 0 { 0 } else { &hostAddr[0] as *const _ as u64 };
+        hdr.nameLen = hostAddr.len() as u32;
+        hdr.iov = if size == 0 { ptr::null::<IoVec>() as u64 } else { &iovs[0] as *const _ as u64 };
+        hdr.iovLen = iovs.len();
+        hdr.msgFlags = 0;
+
+        // the host connect doesn't wait, the sent data is counted when the connect is done
+        let hostFlags = MsgType::MSG_FASTOPEN | MsgType::MSG_DONTWAIT | MsgType::MSG_NOSIGNAL;
+        let res = Kernel::HostSpace::IOSendMsg(self.fd, &hdr as *const _ as u64, hostFlags, false) as i32;
+        let connectRes = if res >= 0 { -SysErr::EINPROGRESS } else { res };
+        self.NatTrackConnect(&natAddr, socketaddr, connectRes);
+
+        let action = self.connectState.lock().OnHostConnect(connectRes, blocking);
+        self.DeferConnect(action, socketaddr);
+        match action {
+            ConnectAction::Wait => {
+                self.WaitConnect(task, socketaddr)?;
+            }
+            // same as linux, the nonblocking socket returns the data in the SYN or EINPROGRESS
+            ConnectAction::Fail(SysErr::EINPROGRESS) if res > 0 => return Ok(res as i64),
+            _ => {
+                self.FinishConnect(task, socketaddr, action)?;
+            }
+        }
+
+        // the data not in the SYN is sent on the connected socket
+        let sent = core::cmp::max(res, 0) as usize;
+        if sent == size {
+            return Ok(sent as i64)
+        }
+
+        let rest = Iovs(srcs).DropFirst(sent);
+        let mut restHdr = MsgHdr::default();
+        match self.SendMsgIntern(task, &rest, flags & !MsgType::MSG_FASTOPEN, &mut restHdr, deadline) {
+            Ok(n) => return Ok((sent as i64) + n),
+            Err(_) if sent > 0 => return Ok(sent as i64),
+            Err(e) => return Err(e),
+        }
+    }
+
+    // FastOpenPending returns whether the socket has the connect deferred by TCP_FASTOPEN_CONNECT
+    pub fn FastOpenPending(&self) -> bool {
+        return self.fastOpenConnect.load(Ordering::Relaxed) && self.fastOpenDst.lock().is_some()
+    }
+
+    // DeferredConnect does the connect deferred by TCP_FASTOPEN_CONNECT with the first data
+    // written to the socket, it is None when there is no deferred connect
+    fn DeferredConnect(&self, task: &Task, srcs: &[IoVec], flags: i32, msgHdr: &MsgHdr, deadline: Option<Time>) -> Option<Result<i64>> {
+        if !self.fastOpenConnect.load(Ordering::Relaxed) {
+            return None
+        }
+
+        let dst = self.fastOpenDst.lock().take()?;
+        let mut hdr = *msgHdr;
+        hdr.msgName = 0;
+        hdr.nameLen = 0;
+        return Some(self.FastOpen(task, &dst, srcs, flags, &hdr, deadline))
+    }
+
+    // WaitConnect waits for the host connect in progress and finishes the guest connect
+    fn WaitConnect(&self, task: &Task, socketaddr: &[u8]) -> Result<i64> {
+        //todo: which one is more efficent?
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_WRITE);
+        defer!(self.EventUnregister(task, &general));
+
+        // SO_ERROR is 0 before the connect finishes, it has to be sampled after the socket
+        // is writable or gets error. Otherwise a spurious wakeup is taken as connected.
+        while self.Readiness(task, EVENT_WRITE) == 0 {
+            self.CheckClosed()?;
+            match task.blocker.BlockWithMonoTimer(true, None) {
+                Err(Error::ErrInterrupted) => {
+                    return Err(Error::SysError(SysErr::ERESTARTSYS));
+                }
+                Err(e) => {
+                    error!("connect error {:?}", &e);
+                    return Err(e);
+                }
+                _ => ()
+            }
+        }
+
+        // the nonblocking connect before may be finished by PollConnect in the wait
+        if let Some(ret) = self.TakeConnectResult(true) {
+            return ret
+        }
+
+        self.asyncConnect.compare_exchange(ASYNC_CONNECT_PENDING, ASYNC_CONNECT_NONE,
+                                           Ordering::AcqRel, Ordering::Acquire).ok();
+        let val = self.HostSoError()?;
+        let action = self.connectState.lock().OnSoError(val);
+        return self.FinishConnect(task, socketaddr, action);
+    }
+
+    fn FinishConnect(&self, task: &Task, socketaddr: &[u8], action: ConnectAction) -> Result<i64> {
+        match action {
+            ConnectAction::PostConnect(ret) => {
+                self.SetRemoteAddr(socketaddr.to_vec())?;
+                self.PostConnect(task)?;
+                return Ok(ret)
+            }
+            ConnectAction::Done => {
+                self.SetRemoteAddr(socketaddr.to_vec())?;
+                return Ok(0)
+            }
+            ConnectAction::Fail(errno) => {
+                return Err(Error::SysError(errno))
+            }
+            ConnectAction::Wait => {
+                LogSockErr(format_args!("FinishConnect of socket fd {} gets unexpected ConnectAction::Wait", self.fd));
+                debug_assert!(false, "FinishConnect gets unexpected ConnectAction::Wait");
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+        }
+    }
+
+    // RecvMsgIntern and SendMsgIntern are RecvMsg and SendMsg without counting the data in the
+    // socket stats
+    fn RecvMsgIntern(&self, task: &Task, dsts: &mut [IoVec], flags: i32, deadline: Option<Time>, senderRequested: bool, controlDataLen: usize)
+        -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)>  {
+
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+        defer!(self.inflight.fetch_sub(1, Ordering::SeqCst));
+
+        //let family = self.family;
+        //let stype = self.stype;
+
+        //error!("RecvMsg ... host socket  fd {} {}/{}/{}/{}", self.fd, flags & MsgType::MSG_DONTWAIT, self.SocketBufEnabled(), family, stype);
+        if let Some(ret) = self.DeferredConnect(task, &[], flags & MsgType::MSG_DONTWAIT, &MsgHdr::default(), deadline) {
+            ret?;
+        }
+
+        self.PollConnect(task)?;
+
+        if let Some(buf) = self.DgramBuf() {
+            return self.DgramRecv(task, &buf, dsts, flags, deadline, senderRequested, controlDataLen)
+        }
+
+        if self.SocketBufEnabled() && !self.KtlsHostRead() {
+            if flags & MsgType::MSG_PEEK != 0 {
+                return self.RecvPeek(task, dsts, flags, deadline, controlDataLen)
+            }
+
+            let len = IoVec::NumBytes(dsts);
+            let mut iovs = dsts;
+            // a blocking read returns when SO_RCVLOWAT bytes are read
+            let target = self.StreamBuf().map(|buf| buf.ReadTarget(len)).unwrap_or(1) as i64;
+
+            let mut count = 0;
+            let mut tmp;
+            let socketType = self.SocketBufType();
+
+            loop {
+                match self.ReadFromBuf(task, socketType.clone(), iovs) {
+                    Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                        if flags & MsgType::MSG_DONTWAIT != 0 {
+                            if count > 0 {
+                                let (retFlags, controlData) = self.prepareControlMessage(controlDataLen, 0);
+                                return Ok((count as i64, retFlags, None, controlData))
+                            }
+
+                            return Err(Error::SysError(SysErr::EWOULDBLOCK))
+                        }
+
+                        break;
+                    }
+                    Err(e) => {
+                        if count > 0 {
+                            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen, 0);
+                            return Ok((count as i64, retFlags, None, controlData))
+                        }
+                        return Err(e)
+                    },
+                    Ok(n) => {
+                        if n == 0 {
+                            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen, 0);
+                            return Ok((count, retFlags, None, controlData))
+                        }
+
+                        count += n;
+                        if count == len as i64 {
+                            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen, 0);
+                            return Ok((count as i64, retFlags, None, controlData))
+                        }
+
+                        tmp = Iovs(iovs).DropFirst(n as usize);
+                        iovs = &mut tmp;
+                        // a large read drains the buffer which is refilled by the host meanwhile
+                        CondResched();
+                    }
+                }
+            }
+
+            let general = task.blocker.generalEntry.clone();
+            self.EventRegister(task, &general, EVENT_READ);
+            defer!(self.EventUnregister(task, &general));
+
+            'main: loop {
+                loop {
+                    match self.ReadFromBuf(task, socketType.clone(), iovs) {
+                        Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                            if count > 0 && count >= target {
+                                break 'main;
+                            }
+
+                            // the data read ahead is drained, the rest is in the host socket
+                            if self.KtlsHostRead() {
+                                if count > 0 {
+                                    break 'main;
+                                }
+                                return self.RecvMsgIntern(task, iovs, flags, deadline, senderRequested, controlDataLen)
+                            }
+                            break;
+                        },
+                        Err(e) => {
+                            if count > 0 {
+                                break 'main;
+                            }
+                            return Err(e);
+                        }
+                        Ok(n) => {
+                            if n == 0 {
+                                break 'main;
+                            }
+
+                            count += n;
+                            if count == len as i64 {
+                                break 'main;
+                            }
+
+                            tmp = Iovs(iovs).DropFirst(n as usize);
+                            iovs = &mut tmp;
+                            CondResched();
+                        }
+                    };
+                }
+
+                if self.closed.load(Ordering::SeqCst) {
+                    if count > 0 {
+                        break 'main;
+                    }
+                    return Err(Error::SysError(SysErr::EBADF));
+                }
+
+                match task.blocker.BlockWithMonoTimer(true, deadline) {
+                    Err(e) => {
+                        if count > 0 {
+                            break 'main;
+                        }
+                        match e {
+                            Error::SysError(SysErr::ETIMEDOUT) => {
+                                return Err(Error::SysError(SysErr::EAGAIN));
+                            }
+                            Error::ErrInterrupted => {
+                                return Err(Error::SysError(SysErr::ERESTARTSYS));
+                            }
+                            _ => {
+                                return Err(e);
+                            }
+                        }
+                    }
+                    _ => ()
+                }
+            }
+
+            let senderAddr = match self.remoteAddr.lock().as_ref() {
+                Some(addr) if senderRequested => Some((addr.clone(), addr.Len())),
+                _ => None,
+            };
+
+            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen, 0);
+            return Ok((count as i64, retFlags, senderAddr, controlData))
+        }
+
+        if flags & MsgType::MSG_ERRQUEUE != 0 {
+            return self.HostRecvErrQueue(task, dsts, flags, senderRequested, controlDataLen)
+        }
+
+        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_PEEK | MsgType::MSG_TRUNC | MsgType::MSG_CTRUNC | MsgType::MSG_WAITALL | MsgType::MSG_CMSG_CLOEXEC) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        // the host fds of SCM_RIGHTS are always close on exec in the host, they are owned by the
+        // guest files
+        let cloexec = flags & MsgType::MSG_CMSG_CLOEXEC != 0;
+        let flags = flags | MsgType::MSG_CMSG_CLOEXEC;
+
+        /* 
+        if IoVec::NumBytes(dsts) == 0 {
+            return Ok((0, 0, None, SCMControlMessages::default()))
+        }
+        */
+
+        /*defer!(task.GetMut().iovs.clear());
+        task.V2PIovs(dsts, true, &mut task.GetMut().iovs)?;
+        let iovs = &mut task.GetMut().iovs;*/
+
+        let size = IoVec::NumBytes(dsts);
+        let buf = self.NewRecvBuf(size)?;
+        let iovs = buf.Iovs();
+
+        let mut msgHdr = MsgHdr::default();
+        if IoVec::NumBytes(dsts) != 0 {
+            msgHdr.iov = &iovs[0] as *const _ as u64;
+        } else {
+            msgHdr.iov = ptr::null::<IoVec>() as u64;
+        }
+        
+        msgHdr.iovLen = iovs.len();
+
+        let mut addr : [u8; SIZEOF_SOCKADDR] = [0; SIZEOF_SOCKADDR];
+        if senderRequested {
+            msgHdr.msgName = &mut addr[0] as * mut _ as u64;
+            msgHdr.nameLen = SIZEOF_SOCKADDR as u32;
+        }
+
+        let mut controlVec: Vec<u8> = vec![0; controlDataLen];
+        msgHdr.msgControlLen = controlDataLen;
+        if msgHdr.msgControlLen != 0 {
+            msgHdr.msgControl = &mut controlVec[0] as *mut _ as u64;
+        } else {
+            msgHdr.msgControl = ptr::null::<u8>() as u64;
+        }
+
+        let mut res = Kernel::HostSpace::IORecvMsg(self.fd, &mut msgHdr as *mut _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
+        while res == -SysErr::EWOULDBLOCK && flags & MsgType::MSG_DONTWAIT == 0 {
+            ClearNotified(self.fd, EVENT_IN);
+            let general = task.blocker.generalEntry.clone();
+
+            self.EventRegister(task, &general, EVENT_READ);
+            defer!(self.EventUnregister(task, &general));
+            self.CheckClosed()?;
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::ErrInterrupted) => {
+                    return Err(Error::SysError(SysErr::ERESTARTSYS));
+                }
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN));
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => ()
+            }
+
+            res = Kernel::HostSpace::IORecvMsg(self.fd, &mut msgHdr as *mut _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
+        }
+
+        if res < 0 {
+            return Err(HostErr("IORecvMsg", self.fd, -res as i32))
+        }
+
+        // the received host fds are installed first so that they are not leaked on the error
+        controlVec.resize(msgHdr.msgControlLen, 0);
+        let mut msgFlags = msgHdr.msgFlags & !MsgType::MSG_CTRUNC;
+        msgFlags |= RightsFromHost(task, &mut controlVec, cloexec);
+
+        // for tcp connect, recvmsg get nameLen=0 msg
+        let senderAddr = if senderRequested {
+            HostSockAddr(&addr, msgHdr.nameLen as usize)
+        } else {
+            None
+        };
+
+        // res is the full datagram length with MSG_TRUNC
+        let n = core::cmp::min(res as usize, buf.Len());
+        task.CopyDataOutToIovs(&buf.buf[0..n], dsts)?;
+        return Ok((res as i64, msgFlags, senderAddr, controlVec))
+    }
+
+    fn SendMsgIntern(&self, task: &Task, srcs: &[IoVec], flags: i32, msgHdr: &mut MsgHdr, deadline: Option<Time>) -> Result<i64> {
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+        defer!(self.inflight.fetch_sub(1, Ordering::SeqCst));
+
+        // the destination of sendto, e.g. udp or the tcp fast open, is checked as connect
+        self.CheckSendPolicy(task, srcs, msgHdr)?;
+
+        if let Some(ret) = self.DeferredConnect(task, srcs, flags & !MsgType::MSG_FASTOPEN, msgHdr, deadline) {
+            return ret
+        }
+
+        self.PollConnect(task)?;
+
+        // MSG_FASTOPEN connects the tcp socket, it fails as connect on the connected one
+        if flags & MsgType::MSG_FASTOPEN != 0 && self.stype == SockType::SOCK_STREAM
+            && (self.family == AFType::AF_INET || self.family == AFType::AF_INET6) {
+            let state = *self.connectState.lock();
+            match state {
+                ConnectState::Connected => return Err(Error::SysError(SysErr::EISCONN)),
+                ConnectState::Connecting => return Err(Error::SysError(SysErr::EALREADY)),
+                ConnectState::Init if msgHdr.msgName != 0 => {
+                    let name = unsafe {
+                        core::slice::from_raw_parts(msgHdr.msgName as *const u8, msgHdr.nameLen as usize)
+                    };
+                    return self.FastOpen(task, name, srcs, flags, msgHdr, deadline)
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(buf) = self.DgramBuf() {
+            return self.DgramSend(task, &buf, srcs, flags, msgHdr, deadline)
+        }
+
+        // the kTLS record type is in the control message, the record is sent by the host sendmsg
+        // after the data of the write buffer
+        let ktlsRecord = msgHdr.msgControl != 0 && self.KtlsTx() && self.SocketBufEnabled();
+        let _ktlsSend = if self.KtlsTx() && self.SocketBufEnabled() {
+            Some(self.ktlsSend.Lock(task)?)
+        } else {
+            None
+        };
+
+        if ktlsRecord {
+            self.DrainWriteBuf(task)?;
+        }
+
+        if self.SocketBufEnabled() && !ktlsRecord {
+            // same as linux, the address of the connected stream socket is ignored. The control
+            // messages are not passed with the data of the socket buffer.
+            if msgHdr.msgControl != 0 && msgHdr.msgControlLen != 0 {
+                LogSockErr(format_args!("sendmsg of buffered socket fd {} with control message is not supported", self.fd));
+                return Err(Error::SysError(SysErr::EOPNOTSUPP))
+            }
+
+            let len = Iovs(srcs).Count();
+            let mut count = 0;
+            let mut srcs = srcs;
+            let mut tmp;
+            let socketType = self.SocketBufType();
+            let more = flags & MsgType::MSG_MORE != 0;
+            loop {
+                loop {
+                    match self.WriteToBuf(task, socketType.clone(), srcs, more) {
+                        Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                            if count > 0 {
+                                return Ok(count)
+                            }
+
+                            if flags & MsgType::MSG_DONTWAIT != 0 {
+                                return Err(Error::SysError(SysErr::EWOULDBLOCK))
+                            }
+
+                            break;
+                        }
+                        Err(e) => {
+                            if count > 0 {
+                                return Ok(count)
+                            }
+
+                            return Err(e)
+                        },
+                        Ok(n) => {
+                            count += n;
+                            if count == len as i64 {
+                                return Ok(count)
+                            }
+                            tmp = Iovs(srcs).DropFirst(n as usize);
+                            srcs = &mut tmp;
+                        },
+                    }
+                }
+
+                let general = task.blocker.generalEntry.clone();
+                self.EventRegister(task, &general, EVENT_WRITE);
+                defer!(self.EventUnregister(task, &general));
+
+                if self.closed.load(Ordering::SeqCst) {
+                    if count > 0 {
+                        return Ok(count)
+                    }
+                    return Err(Error::SysError(SysErr::EBADF));
+                }
+
+                match task.blocker.BlockWithMonoTimer(true, deadline) {
+                    Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                        if count > 0 {
+                            return Ok(count)
+                        }
+                        return Err(Error::SysError(SysErr::EWOULDBLOCK));
+                    }
+                    Err(e) => {
+                        if count > 0 {
+                            return Ok(count)
+                        }
+                        return Err(e);
+                    }
+                    _ => ()
+                }
+            }
+
+        }
+
+        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_FASTOPEN | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+        
+        /*defer!(task.GetMut().iovs.clear());
+        task.V2PIovs(srcs, false, &mut task.GetMut().iovs)?;
+        let iovs = &task.GetMut().iovs;*/
+
+        let size = IoVec::NumBytes(srcs);
+        let mut buf = DataBuff::NewUser(size)?;
+        let iovs = buf.Iovs();
+
+        task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
+
+        if IoVec::NumBytes(srcs) != 0 {
+            msgHdr.iov = &iovs[0] as *const _ as u64;
+        } else {
+            msgHdr.iov = ptr::null::<IoVec>() as u64;
+        }
+        msgHdr.iovLen = iovs.len();
+        msgHdr.msgFlags = 0;
+
+        // the files hold the host fds of SCM_RIGHTS until the host sendmsg returns
+        let _rights = RightsToHost(task, msgHdr)?;
+
+        // sendto of the unbound udp socket
+        if msgHdr.msgName != 0 && self.stype == SockType::SOCK_DGRAM {
+            ImplicitBind(self.fd, self.family, self.stype)?;
+        }
+
+        // SIGPIPE is raised by the guest syscall, the host send mustn't raise it for the sandbox
+        let flags = flags | MsgType::MSG_NOSIGNAL;
+        let mut res = Kernel::HostSpace::IOSendMsg(self.fd, msgHdr as *const _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
+        while res == -SysErr::EWOULDBLOCK && flags & MsgType::MSG_DONTWAIT == 0 {
+            ClearNotified(self.fd, EVENT_OUT);
+            let general = task.blocker.generalEntry.clone();
+
+            self.EventRegister(task, &general, EVENT_WRITE);
+            defer!(self.EventUnregister(task, &general));
+            self.CheckClosed()?;
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => ()
+            }
+
+            res = Kernel::HostSpace::IOSendMsg(self.fd, msgHdr as *const _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
+        }
+
+        if res < 0 {
+            return Err(HostErr("IOSendMsg", self.fd, -res as i32))
+        }
+
+        return Ok(res as i64)
+    }
+}
+
+impl SockOperations for SocketOperations {
+    fn Connect(&self, task: &Task, sockaddr: &[u8], blocking: bool) -> Result<i64> {
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+        defer!(self.inflight.fetch_sub(1, Ordering::SeqCst));
+
+        let mut socketaddr = sockaddr;
+
+        if (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
+            && socketaddr.len() > SIZEOF_SOCKADDR {
+            socketaddr = &socketaddr[..SIZEOF_SOCKADDR]
+        }
+
+        // the policy is checked with the destination of the app, before the nat rewrites it
+        CheckNetPolicy(PolicyOp::Connect, socketaddr)?;
+
+        // the connect of TCP_FASTOPEN_CONNECT is done with the first write, the socket is taken
+        // as connected until then as linux does
+        if self.fastOpenConnect.load(Ordering::Relaxed) {
+            if self.FastOpenPending() {
+                return Err(Error::SysError(SysErr::EISCONN))
+            }
+
+            if *self.connectState.lock() == ConnectState::Init {
+                ImplicitBind(self.fd, self.family, self.stype)?;
+                self.SetRemoteAddr(socketaddr.to_vec())?;
+                *self.fastOpenDst.lock() = Some(socketaddr.to_vec());
+                return Ok(0)
+            }
+        }
+
+        // the result of the nonblocking connect goes to the next connect
+        self.PollConnect(task)?;
+        if let Some(ret) = self.TakeConnectResult(true) {
+            return ret
+        }
+
+        let natAddr = self.PrepareConnect(task, socketaddr)?;
+        let hostAddr: &[u8] = match &natAddr {
+            None => socketaddr,
+            Some(addr) => &addr[..],
+        };
+
+        let res = Kernel::HostSpace::IOConnect(self.fd, &hostAddr[0] as *const _ as u64, hostAddr.len() as u32) as i32;
+        self.NatTrackConnect(&natAddr, socketaddr, res);
+
+        let action = self.connectState.lock().OnHostConnect(res, blocking);
+        self.DeferConnect(action, socketaddr);
+        match action {
+            ConnectAction::Wait => return self.WaitConnect(task, socketaddr),
+            _ => return self.FinishConnect(task, socketaddr, action),
+        }
+    }
+
+    fn Accept(&self, task: &Task, addr: &mut [u8], addrlen: &mut u32, flags: i32, blocking: bool) -> Result<i64> {
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+        defer!(self.inflight.fetch_sub(1, Ordering::SeqCst));
+
+        let mut acceptItem = loop {
+            let item = self.WaitAcceptItem(task, blocking)?;
+            match self.NatPrerouting(item) {
+                // the connection is redirected to another listener
+                None => continue,
+                Some(item) => break item,
+            }
+        };
+
+        if addr.len() > 0 {
+            let len = core::cmp::min(core::cmp::min(acceptItem.len as usize, addr.len()), acceptItem.addr.data.len());
+            for i in 0..len {
+                addr[i] = acceptItem.addr.data[i];
+            }
+
+            *addrlen = len as u32;
+        }
+
+        let fd = acceptItem.fd;
+
+        // the async accept fetches the peer info when the connection is queued
+        let mut peerInfo = match acceptItem.peerInfo.take() {
+            None if !acceptItem.loopback => FetchAcceptPeerInfo(fd),
+            info => info,
+        };
+
+        // the peer address of the host accept rather than the part copied to the app
+        let remoteAddr = match peerInfo.as_mut().and_then(|info| info.TakePeerAddr(self.family)) {
+            Some(addr) => addr,
+            None => {
+                let len = core::cmp::min(acceptItem.len as usize, acceptItem.addr.data.len());
+                acceptItem.addr.data[0..len].to_vec()
+            }
+        };
+
+        //let sockBuf = self.ConfigSocketBufType();
+        let sockBuf = if acceptItem.loopback {
+            SocketBufType::Loopback(LoopbackSock::Accepted(fd))
+        } else {
+            match self.SocketBufType().Accept(acceptItem.sockBuf.clone()) {
+                Ok(sockBuf) => sockBuf,
+                Err(e) => {
+                    HostSpace::Close(fd);
+                    return Err(e)
+                }
+            }
+        };
+        // the accepted socket inherits the low watermarks of the listening socket
+        if !acceptItem.loopback {
+            self.InitSockBufOpts(&acceptItem.sockBuf);
+        }
+
+        let file = newSocketFile(task,
+                                 self.family,
+                                 fd as i32,
+                                 self.stype,
                                  flags & SocketFlags::SOCK_NONBLOCK != 0,
-                                 sockBuf, Some(remoteAddr.to_vec()))?;
+                                 sockBuf, Some(remoteAddr), peerInfo)?;
+
+        // the local address of the connection is the one of the listener bound to a specific ip
+        let localAddr = self.localAddr.lock().clone();
+        if let Some(local) = localAddr {
+            if let Some(sock) = file.FileOp.as_any().downcast_ref::<SocketOperations>() {
+                if !IsWildcardAddr(&local) {
+                    *sock.localAddr.lock() = Some(local);
+                }
+            }
+        }
 
         let fdFlags = FDFlags {
             CloseOnExec: flags & SocketFlags::SOCK_CLOEXEC != 0
@@ -802,11 +2633,24 @@ impl SockOperations for SocketOperations {
             info!("unix socket bind ... path is {:?}", alloc::string::String::from_utf8(path));
         }*/
 
+        CheckNetPolicy(PolicyOp::Bind, socketaddr)?;
+
+        // bind to port 0 gets the port from the sandbox ephemeral port range
+        if NeedPortAllocation(self.family, self.stype) && socketaddr.len() >= 4
+            && socketaddr[2] == 0 && socketaddr[3] == 0 {
+            let mut addr = socketaddr.to_vec();
+            let res = BindEphemeral(self.fd, &mut addr, SysErr::EADDRINUSE)?;
+            *self.localAddr.lock() = NormalizeInetAddr(self.family, &addr);
+            return Ok(res)
+        }
+
         let res = Kernel::HostSpace::Bind(self.fd, &socketaddr[0] as *const _ as u64, socketaddr.len() as u32, task.Umask());
         if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            return Err(HostErr("Bind", self.fd, -res as i32))
         }
 
+        *self.localAddr.lock() = NormalizeInetAddr(self.family, socketaddr);
+
         return Ok(res)
     }
 
@@ -825,21 +2669,26 @@ impl SockOperations for SocketOperations {
             backlog
         };
 
+        let limit = SHARESPACE.config.read().AcceptQueueHighWatermark;
         let socketBuf = self.socketBuf.lock().clone();
         let acceptQueue = match socketBuf {
             SocketBufType::TCPUringlServer(q) => {
-                q.lock().SetQueueLen(len as usize);
+                q.lock().SetQueueLen(len as usize, limit);
                 return Ok(0)
             },
             SocketBufType::TCPRDMAServer(q) => {
-                q.lock().SetQueueLen(len as usize);
+                q.lock().SetQueueLen(len as usize, limit);
                 return Ok(0)
             },
             SocketBufType::TCPInit => AcceptQueue::default(),
             _=> AcceptQueue::default(), // panic?
         };
 
-        acceptQueue.lock().SetQueueLen(len as usize);
+        acceptQueue.lock().SetQueueLen(len as usize, limit);
+        acceptQueue.lock().SetSockBufSize(self.rcvBuf.load(Ordering::Relaxed) as usize,
+                                          self.sndBuf.load(Ordering::Relaxed) as usize);
+
+        ImplicitBind(self.fd, self.family, self.stype)?;
 
         let res = if enableRDMA {
             Kernel::HostSpace::RDMAListen(self.fd, backlog, asyncAccept, acceptQueue.clone())
@@ -848,7 +2697,7 @@ impl SockOperations for SocketOperations {
         };
 
         if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            return Err(HostErr("Listen", self.fd, -res as i32))
         }
 
         *self.socketBuf.lock() = if enableRDMA {
@@ -864,38 +2713,102 @@ impl SockOperations for SocketOperations {
             SocketBufType::TCPNormalServer
         };
 
+        if self.NatEnabled() {
+            if let Some((_, port)) = HostSockAddrV4(self.fd, false) {
+                NAT.AddListener(port, self.Downgrade());
+            }
+        }
+
+        // the other sandboxes on the host connect to the listener over a host unix socket
+        if SHARESPACE.config.read().LoopbackFastPath && !enableRDMA
+            && self.family == AFType::AF_INET && self.stype == SockType::SOCK_STREAM
+            && self.loopbackFd.load(Ordering::Relaxed) < 0 {
+            let lfd = HostSpace::LoopbackListen(self.fd) as i32;
+            if lfd >= 0 {
+                AddFD(lfd, &self.hostops);
+                self.loopbackFd.store(lfd, Ordering::Relaxed);
+            }
+        }
+
         return Ok(res)
     }
 
     fn Shutdown(&self, task: &Task, how: i32) -> Result<i64> {
         let how = how as u64;
+        if how != LibcConst::SHUT_RD && how != LibcConst::SHUT_WR && how != LibcConst::SHUT_RDWR {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
 
-        if how == LibcConst::SHUT_WR || how == LibcConst::SHUT_RDWR {
-            if self.SocketBuf().HasWriteData() {
-                self.SocketBuf().SetPendingWriteShutdown();
-                let general = task.blocker.generalEntry.clone();
-                self.EventRegister(task, &general, EVENT_PENDING_SHUTDOWN);
-                defer!(self.EventUnregister(task, &general));
+        let shutRead = how != LibcConst::SHUT_WR;
+        let shutWrite = how != LibcConst::SHUT_RD;
 
-                while self.SocketBuf().HasWriteData() {
-                    task.blocker.BlockGeneralOnly();
-                }
+        // the host shuts down the unconnected udp socket with ENOTCONN too
+        if shutRead {
+            if let Some(buf) = self.DgramBuf() {
+                buf.ShutdownRead();
             }
         }
 
-        if how == LibcConst::SHUT_RD || how == LibcConst::SHUT_WR || how == LibcConst::SHUT_RDWR {
-            let res = Kernel::HostSpace::Shutdown(self.fd, how as i32);
-            if res < 0 {
-                return Err(Error::SysError(-res as i32))
+        // same as linux, SHUT_WR doesn't wait for the data in the write buf, the FIN is sent
+        // after it by the AsyncSend draining the buf. The read side keeps delivering the data
+        // till the peer FIN.
+        let mut hostHow = Some(how);
+        match self.SocketBufType() {
+            SocketBufType::Uring(buf) => {
+                if shutWrite {
+                    self.FlushCork();
+                    if buf.ShutdownWrite() {
+                        hostHow = if shutRead { Some(LibcConst::SHUT_RD) } else { None };
+                    }
+                }
+
+                if shutRead {
+                    buf.SetRClosed();
+                }
+
+                self.Notify(buf.Events());
             }
+            SocketBufType::Loopback(sock) => {
+                if sock.Shutdown(shutRead, shutWrite) {
+                    hostHow = None;
+                } else {
+                    hostHow = Some(LibcConst::SHUT_WR);
+                }
 
-            return Ok(res)
+                self.queue.Notify(sock.Events(&self.queue, EVENT_IN | EVENT_OUT));
+            }
+            // the rdma socket sends the write buf to the peer without the host socket
+            SocketBufType::RDMA(buf) if shutWrite => {
+                if buf.HasWriteData() {
+                    buf.SetPendingWriteShutdown();
+                    let general = task.blocker.generalEntry.clone();
+                    self.EventRegister(task, &general, EVENT_PENDING_SHUTDOWN);
+                    defer!(self.EventUnregister(task, &general));
+
+                    while buf.HasWriteData() {
+                        task.blocker.BlockGeneralOnly();
+                    }
+                }
+
+                buf.SetWClosed();
+            }
+            _ => (),
         }
 
-        return Err(Error::SysError(SysErr::EINVAL))
+        let how = match hostHow {
+            None => return Ok(0),
+            Some(how) => how,
+        };
+
+        let res = Kernel::HostSpace::Shutdown(self.fd, how as i32);
+        if res < 0 {
+            return Err(HostErr("Shutdown", self.fd, -res as i32))
+        }
+
+        return Ok(res)
     }
 
-    fn GetSockOpt(&self, _task: &Task, level: i32, name: i32, opt: &mut [u8]) -> Result<i64> {
+    fn GetSockOpt(&self, task: &Task, level: i32, name: i32, opt: &mut [u8]) -> Result<i64> {
         /*
         let optlen = match level as u64 {
             LibcConst::SOL_IPV6 => {
@@ -951,7 +2864,7 @@ impl SockOperations for SocketOperations {
                         Kernel::HostSpace::GetSockOpt(self.fd, level, name, &opt[0] as *const _ as u64, &bufferSize as *const _ as u64)
                     };
                     if res < 0 {
-                        return Err(Error::SysError(-res as i32))
+                        return Err(HostErr("GetSockOpt", self.fd, -res as i32))
                     }
                     // if optlen < sizeof(i32), the return of getsockopt will be of sizeof(i8)
                     return Ok(bufferSize as i64)
@@ -963,12 +2876,128 @@ impl SockOperations for SocketOperations {
         let opt = &opt[..optlen];
         let res = Kernel::HostSpace::GetSockOpt(self.fd, level, name, &opt[0] as *const _ as u64, &optlen as *const _ as u64);
         if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            return Err(HostErr("GetSockOpt", self.fd, -res as i32))
         }
 
         return Ok(optlen as i64)
         */
 
+        // without the guest nat rules, the host answers for the connection redirected on the host
+        if level == SOL_IP && name == SO_ORIGINAL_DST && self.NatEnabled() {
+            if let Some((addr, port)) = self.NatOrigDst() {
+                if opt.len() < SIZEOF_SOCKADDR_INET {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+
+                opt[..SIZEOF_SOCKADDR_INET].copy_from_slice(&NewSockAddrV4(addr, port));
+                return Ok(SIZEOF_SOCKADDR_INET as i64)
+            }
+        }
+
+        // SO_SNDLOWAT goes to the host socket, linux always reports 1 and fails the set with
+        // ENOPROTOOPT
+        if level == SOL_SOCKET && name == SO_RCVLOWAT && self.SockBufOptInGuest() {
+            if opt.len() < 4 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            let val = if let Some(buf) = self.StreamBuf() {
+                buf.RcvLowat()
+            } else {
+                self.rcvLowat.load(Ordering::Relaxed) as usize
+            };
+
+            unsafe {
+                *(&mut opt[0] as * mut _ as u64 as * mut i32) = val as i32;
+            }
+            return Ok(4)
+        }
+
+        // the guest buffer size, the host socket buffer is not used by the buffered socket
+        if level == SOL_SOCKET && IsSockBufSizeOpt(name) && self.SockBufOptInGuest() && opt.len() >= 4 {
+            unsafe {
+                *(&mut opt[0] as * mut _ as u64 as * mut i32) = self.SockBufSize(name) as i32;
+            }
+            return Ok(4)
+        }
+
+        if (level as u64) == LibcConst::SOL_TCP && (name as u64) == LibcConst::TCP_CORK && self.SockBufOptInGuest() && opt.len() >= 4 {
+            unsafe {
+                *(&mut opt[0] as * mut _ as u64 as * mut i32) = self.Corked() as i32;
+            }
+            return Ok(4)
+        }
+
+        if (level as u64) == LibcConst::SOL_TCP && (name as u64) == LibcConst::TCP_FASTOPEN_CONNECT && self.SockBufOptInGuest() && opt.len() >= 4 {
+            unsafe {
+                *(&mut opt[0] as * mut _ as u64 as * mut i32) = self.fastOpenConnect.load(Ordering::Relaxed) as i32;
+            }
+            return Ok(4)
+        }
+
+        // the multicast send options are answered from the guest copy
+        if let Some(n) = self.multicastOpts.lock().Get(level, name, opt)? {
+            return Ok(n as i64)
+        }
+
+        // the host socket is bound by the host name of the device, answer with the guest name
+        if level == SOL_SOCKET {
+            if let Some(n) = BoundDevice(name, self.bindDevice.load(Ordering::Relaxed), opt)? {
+                return Ok(n as i64)
+            }
+        }
+
+        if level == SOL_SOCKET && name == SO_PEERCRED {
+            let cred = match self.acceptPeer.lock().as_ref() {
+                None => None,
+                Some(info) => info.Cred(),
+            };
+
+            if let Some(cred) = cred {
+                let n = core::cmp::min(opt.len(), cred.len());
+                opt[..n].copy_from_slice(&cred[..n]);
+                return Ok(n as i64)
+            }
+        }
+
+        if (level as u64) == LibcConst::SOL_TCP && (name as u64) == LibcConst::TCP_INFO {
+            let tcpInfo = match self.acceptPeer.lock().as_mut() {
+                None => None,
+                Some(info) => info.TakeTcpInfo(),
+            };
+
+            if let Some(tcpInfo) = tcpInfo {
+                let n = core::cmp::min(opt.len(), tcpInfo.len());
+                opt[..n].copy_from_slice(&tcpInfo[..n]);
+                return Ok(n as i64)
+            }
+        }
+
+        // the SO_ERROR of the nonblocking connect is sampled by PollConnect
+        if level == SOL_SOCKET && name == SO_ERROR && opt.len() >= 4 {
+            self.PollConnect(task)?;
+            if let Some(Err(Error::SysError(err))) = self.TakeConnectResult(false) {
+                unsafe {
+                    *(&mut opt[0] as * mut _ as u64 as * mut i32) = err;
+                }
+                return Ok(4)
+            }
+        }
+
+        // the host SO_ERROR of the buffered socket is consumed by the uring ops
+        if (self.SocketBufEnabled() || self.DgramBuf().is_some()) && level == SOL_SOCKET && name == SO_ERROR && opt.len() >= 4 {
+            let err = match (self.DgramBuf(), self.StreamBuf()) {
+                (Some(buf), _) => buf.ConsumeErr(),
+                (None, Some(buf)) => buf.ConsumeErr(),
+                // the errors of the loopback connection are returned by the read and the write
+                (None, None) => 0,
+            };
+            unsafe {
+                *(&mut opt[0] as * mut _ as u64 as * mut i32) = err;
+            }
+            return Ok(4)
+        }
+
         let mut optLen = opt.len();
         let res = if optLen == 0 {
             Kernel::HostSpace::GetSockOpt(self.fd, level, name, ptr::null::<u8>() as u64, &mut optLen as *mut _ as u64)
@@ -977,7 +3006,7 @@ impl SockOperations for SocketOperations {
         };
 
         if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            return Err(HostErr("GetSockOpt", self.fd, -res as i32))
         }
 
         return Ok(optLen as i64)
@@ -1032,373 +3061,407 @@ impl SockOperations for SocketOperations {
                 }
             }
 
-        // TCP_INQ is bound to buffer implementation
-        if (level as u64) == LibcConst::SOL_TCP &&
-            (name as u64) == LibcConst::TCP_INQ {
-                let val = unsafe {
-                    *(&opt[0] as * const _ as u64 as * const i32)
-                };
-                if val == 1 {
-                    self.passInq.store(true, Ordering::Relaxed);
-                } else {
-                    self.passInq.store(false, Ordering::Relaxed);
+        if (level as u64) == LibcConst::SOL_SOCKET &&
+            (name as u64) == LibcConst::SO_LINGER {
+                if opt.len() < SocketSize::SIZEOF_LINGER {
+                    return Err(Error::SysError(SysErr::EINVAL));
                 }
-        }
-
-        let optLen = opt.len();
-        let res = if optLen == 0 {
-            Kernel::HostSpace::SetSockOpt(self.fd, level, name, ptr::null::<u8>() as u64, optLen as u32)
-        } else {
-            Kernel::HostSpace::SetSockOpt(self.fd, level, name, &opt[0] as *const _ as u64, optLen as u32)
-        };
-
-        if res < 0 {
-            return Err(Error::SysError(-res as i32))
-        }
-
-        return Ok(res)
-    }
 
-    fn GetSockName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
-        let len = socketaddr.len() as i32;
+                let linger = unsafe {
+                    *(&opt[0] as * const _ as u64 as * const Linger)
+                };
+                *self.linger.lock() = linger;
+            }
 
-        let res = Kernel::HostSpace::GetSockName(self.fd, &socketaddr[0] as *const _ as u64, &len as *const _ as u64);
-        if res < 0 {
-            return Err(Error::SysError(-res as i32))
-        }
+        // the receive timestamp of socket buffer is generated in the guest
+        if (level as u64) == LibcConst::SOL_SOCKET && opt.len() >= 4 {
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            };
 
-        return Ok(len as i64)
-    }
+            match name {
+                SO_TIMESTAMP | SO_TIMESTAMPNS => {
+                    if val != 0 {
+                        self.passTimestamp.store(name, Ordering::Relaxed);
+                    } else if self.passTimestamp.load(Ordering::Relaxed) == name {
+                        self.passTimestamp.store(0, Ordering::Relaxed);
+                    }
+                }
+                SO_TIMESTAMPING => {
+                    if val as u32 & !SOF_TIMESTAMPING_MASK != 0 {
+                        return Err(Error::SysError(SysErr::EINVAL));
+                    }
+                    self.timestampingFlags.store(val as u32, Ordering::Relaxed);
+                }
+                _ => ()
+            }
 
-    fn GetPeerName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
-        let len = socketaddr.len() as i32;
-        let res = Kernel::HostSpace::GetPeerName(self.fd, &socketaddr[0] as *const _ as u64, &len as *const _ as u64);
-        if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            if name == SO_TIMESTAMP || name == SO_TIMESTAMPNS || name == SO_TIMESTAMPING {
+                if let Some(buf) = self.StreamBuf() {
+                    buf.SetRxStampWanted(self.RxStampWanted());
+                }
+            }
         }
 
-        return Ok(len as i64)
-    }
-
-    fn RecvMsg(&self, task: &Task, dsts: &mut [IoVec], flags: i32, deadline: Option<Time>, senderRequested: bool, controlDataLen: usize)
-        -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)>  {
-
-        //let family = self.family;
-        //let stype = self.stype;
-
-        //error!("RecvMsg ... host socket  fd {} {}/{}/{}/{}", self.fd, flags & MsgType::MSG_DONTWAIT, self.SocketBufEnabled(), family, stype);
-        if self.SocketBufEnabled() {
-            let controlDataLen = 0;
-
-            let len = IoVec::NumBytes(dsts);
-            let mut iovs = dsts;
-
-            let mut count = 0;
-            let mut tmp;
-            let socketType = self.SocketBufType();
-
-            loop {
-                match self.ReadFromBuf(task, socketType.clone(), iovs) {
-                    Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
-                        if flags & MsgType::MSG_DONTWAIT != 0 {
-                            if count > 0 {
-                                let (retFlags, controlData) = self.prepareControlMessage(controlDataLen);
-                                return Ok((count as i64, retFlags, None, controlData))
-                            }
-
-                            return Err(Error::SysError(SysErr::EWOULDBLOCK))
-                        }
-
-                        break;
-                    }
-                    Err(e) => {
-                        if count > 0 {
-                            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen);
-                            return Ok((count as i64, retFlags, None, controlData))
-                        }
-                        return Err(e)
-                    },
-                    Ok(n) => {
-                        if n == 0 {
-                            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen);
-                            return Ok((count, retFlags, None, controlData))
-                        }
-
-                        count += n;
-                        if count == len as i64 {
-                            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen);
-                            return Ok((count as i64, retFlags, None, controlData))
-                        }
+        // same as linux, SO_RCVBUF/SO_SNDBUF are capped by net.core.rmem_max/wmem_max of the
+        // sandbox, the host socket caps them again with its own
+        let capped: [u8; 4];
+        let opt = if level == SOL_SOCKET && (name == SO_RCVBUF || name == SO_SNDBUF) && opt.len() >= 4 {
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            };
 
-                        tmp = Iovs(iovs).DropFirst(n as usize);
-                        iovs = &mut tmp;
-                    }
-                }
+            let max = if name == SO_RCVBUF { SYSCTL.RmemMax() } else { SYSCTL.WmemMax() };
+            if val > max {
+                capped = max.to_ne_bytes();
+                &capped[..]
+            } else {
+                opt
             }
+        } else {
+            opt
+        };
 
-            let general = task.blocker.generalEntry.clone();
-            self.EventRegister(task, &general, EVENT_READ);
-            defer!(self.EventUnregister(task, &general));
+        // the low watermark of the buffered socket is checked against the guest socket buffer
+        if level == SOL_SOCKET && name == SO_RCVLOWAT && self.SockBufOptInGuest() {
+            if opt.len() < 4 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
 
-            'main: loop {
-                loop {
-                    match self.ReadFromBuf(task, socketType.clone(), iovs) {
-                        Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
-                            if count > 0 {
-                                break 'main;
-                            }
-                            break;
-                        },
-                        Err(e) => {
-                            if count > 0 {
-                                break 'main;
-                            }
-                            return Err(e);
-                        }
-                        Ok(n) => {
-                            if n == 0 {
-                                break 'main;
-                            }
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            };
 
-                            count += n;
-                            if count == len as i64 {
-                                break 'main;
-                            }
+            // same as linux: 0 means 1 and a negative value means INT_MAX
+            let val = if val == 0 {
+                1
+            } else if val < 0 {
+                i32::MAX
+            } else {
+                val
+            };
 
-                            tmp = Iovs(iovs).DropFirst(n as usize);
-                            iovs = &mut tmp;
-                        }
-                    };
-                }
+            self.rcvLowat.store(val, Ordering::Relaxed);
+            if let Some(buf) = self.StreamBuf() {
+                self.InitSockBufOpts(&buf);
+                // the waiters have to check the readiness with the new watermark
+                self.queue.Notify(EVENT_IN);
+            }
 
-                match task.blocker.BlockWithMonoTimer(true, deadline) {
-                    Err(e) => {
-                        if count > 0 {
-                            break 'main;
-                        }
-                        match e {
-                            Error::SysError(SysErr::ETIMEDOUT) => {
-                                return Err(Error::SysError(SysErr::EAGAIN));
-                            }
-                            Error::ErrInterrupted => {
-                                return Err(Error::SysError(SysErr::ERESTARTSYS));
-                            }
-                            _ => {
-                                return Err(e);
-                            }
-                        }
-                    }
-                    _ => ()
-                }
+            return Ok(0)
+        }
+
+        // the buffers of the buffered socket are allocated when it is connected or accepted, the
+        // option is also set on the host socket for its own buffer
+        if level == SOL_SOCKET && IsSockBufSizeOpt(name) && self.SockBufOptInGuest() {
+            if opt.len() < 4 {
+                return Err(Error::SysError(SysErr::EINVAL))
             }
 
-            let senderAddr = if senderRequested {
-                let addr = self.remoteAddr.lock().as_ref().unwrap().clone();
-                let l = addr.Len();
-                Some((addr, l))
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            };
+
+            // 0 means the option is not set, a negative or 0 value gets the min buffer as linux
+            let val = core::cmp::max(val, 1);
+            if name == SO_RCVBUF || name == SO_RCVBUFFORCE {
+                self.rcvBuf.store(val, Ordering::Relaxed);
             } else {
-                None
+                self.sndBuf.store(val, Ordering::Relaxed);
+            }
+
+            if let Some(queue) = self.AcceptQueue() {
+                queue.lock().SetSockBufSize(self.rcvBuf.load(Ordering::Relaxed) as usize,
+                                            self.sndBuf.load(Ordering::Relaxed) as usize);
+            }
+
+            if let Some(buf) = self.StreamBuf() {
+                self.InitSockBufOpts(&buf);
+            }
+        }
+
+        // the guest aggregates the corked data in the socket buffer, the host socket is not corked
+        if (level as u64) == LibcConst::SOL_TCP && (name as u64) == LibcConst::TCP_CORK && self.SockBufOptInGuest() {
+            if opt.len() < 4 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
             };
 
-            let (retFlags, controlData) = self.prepareControlMessage(controlDataLen);
-            return Ok((count as i64, retFlags, senderAddr, controlData))
+            self.cork.store(val != 0, Ordering::SeqCst);
+            if val == 0 {
+                self.FlushCork();
+            }
+
+            return Ok(0)
         }
 
-        //todo: we don't support MSG_ERRQUEUE
-        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_PEEK | MsgType::MSG_TRUNC | MsgType::MSG_CTRUNC | MsgType::MSG_WAITALL) != 0 {
-            return Err(Error::SysError(SysErr::EINVAL))
+        // the host socket of TCP_FASTOPEN_CONNECT sends the SYN with the first write, which the
+        // uring send of the socket buffer fails with EINPROGRESS. The buffered socket defers the
+        // connect in the guest and sends the first data with MSG_FASTOPEN instead.
+        if (level as u64) == LibcConst::SOL_TCP && (name as u64) == LibcConst::TCP_FASTOPEN_CONNECT && self.SockBufOptInGuest() {
+            if opt.len() < 4 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            };
+
+            // same as linux, it is only set before the connect
+            if val < 0 || val > 1 || self.SocketBufEnabled() || *self.connectState.lock() != ConnectState::Init
+                || self.FastOpenPending() {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            self.fastOpenConnect.store(val != 0, Ordering::Relaxed);
+            return Ok(0)
         }
 
-        /* 
-        if IoVec::NumBytes(dsts) == 0 {
-            return Ok((0, 0, None, SCMControlMessages::default()))
+        if let Some(ret) = self.KtlsSetSockOpt(task, level, name, opt)? {
+            return Ok(ret)
         }
-        */
 
-        /*defer!(task.GetMut().iovs.clear());
-        task.V2PIovs(dsts, true, &mut task.GetMut().iovs)?;
-        let iovs = &mut task.GetMut().iovs;*/
+        // TCP_NODELAY is also set on the host socket for the data sent by the AsyncSend
+        if (level as u64) == LibcConst::SOL_TCP && (name as u64) == LibcConst::TCP_NODELAY && self.SockBufOptInGuest() && opt.len() >= 4 {
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            };
 
-        let size = IoVec::NumBytes(dsts);
-        let buf = DataBuff::New(size);
-        let iovs = buf.Iovs();
+            self.noDelay.store(val != 0, Ordering::Relaxed);
+            if let Some(buf) = self.StreamBuf() {
+                buf.SetNoDelay(val != 0);
+            }
 
-        let mut msgHdr = MsgHdr::default();
-        if IoVec::NumBytes(dsts) != 0 {
-            msgHdr.iov = &iovs[0] as *const _ as u64;
-        } else {
-            msgHdr.iov = ptr::null::<IoVec>() as u64;
+            // same as linux, enabling TCP_NODELAY pushes the pending data even when corked
+            if val != 0 {
+                self.FlushCork();
+            }
         }
-        
-        msgHdr.iovLen = iovs.len();
 
-        let mut addr : [u8; SIZEOF_SOCKADDR] = [0; SIZEOF_SOCKADDR];
-        if senderRequested {
-            msgHdr.msgName = &mut addr[0] as * mut _ as u64;
-            msgHdr.nameLen = SIZEOF_SOCKADDR as u32;
+        // TCP_INQ is bound to buffer implementation
+        if (level as u64) == LibcConst::SOL_TCP &&
+            (name as u64) == LibcConst::TCP_INQ {
+                let val = unsafe {
+                    *(&opt[0] as * const _ as u64 as * const i32)
+                };
+                if val == 1 {
+                    self.passInq.store(true, Ordering::Relaxed);
+                } else {
+                    self.passInq.store(false, Ordering::Relaxed);
+                }
         }
 
-        let mut controlVec: Vec<u8> = vec![0; controlDataLen];
-        msgHdr.msgControlLen = controlDataLen;
-        if msgHdr.msgControlLen != 0 {
-            msgHdr.msgControl = &mut controlVec[0] as *mut _ as u64;
-        } else {
-            msgHdr.msgControl = ptr::null::<u8>() as u64;
+        // the host reports the IP_PKTINFO/IP_TOS of the buffered socket with the options set on
+        // it below, the cached cmsgs are fetched again
+        if level == SOL_IP && (name as u64 == LibcConst::IP_PKTINFO || name as u64 == LibcConst::IP_RECVTOS)
+            && self.SockBufOptInGuest() && opt.len() >= 4 {
+            let val = unsafe {
+                *(&opt[0] as * const _ as u64 as * const i32)
+            };
+
+            if val != 0 {
+                self.passPktOptions.store(true, Ordering::Relaxed);
+            }
+            *self.pktOptions.lock() = None;
         }
 
-        let mut res = Kernel::HostSpace::IORecvMsg(self.fd, &mut msgHdr as *mut _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
-        while res == -SysErr::EWOULDBLOCK && flags & MsgType::MSG_DONTWAIT == 0 {
-            let general = task.blocker.generalEntry.clone();
+        // the classic BPF program is in the application memory, copy it to the kernel
+        // and pass the kernel copy to the host
+        let filter;
+        let fprog;
+        let opt = if level == SOL_SOCKET && (name == SO_ATTACH_FILTER || name == SO_ATTACH_REUSEPORT_CBPF) {
+            if opt.len() < SIZEOF_SOCK_FPROG {
+                return Err(Error::SysError(SysErr::EINVAL));
+            }
 
-            self.EventRegister(task, &general, EVENT_READ);
-            defer!(self.EventUnregister(task, &general));
-            match task.blocker.BlockWithMonoTimer(true, deadline) {
-                Err(Error::ErrInterrupted) => {
-                    return Err(Error::SysError(SysErr::ERESTARTSYS));
-                }
-                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
-                    return Err(Error::SysError(SysErr::EAGAIN));
-                }
-                Err(e) => {
-                    return Err(e);
-                }
-                _ => ()
+            let prog = unsafe {
+                *(&opt[0] as * const _ as u64 as * const SockFprog)
+            };
+
+            if prog.Len == 0 || prog.Len as usize > BPF_MAXINSNS {
+                return Err(Error::SysError(SysErr::EINVAL));
             }
 
-            res = Kernel::HostSpace::IORecvMsg(self.fd, &mut msgHdr as *mut _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
-        }
+            filter = task.CopyInVec::<SockFilter>(prog.Filter, prog.Len as usize)?;
+            fprog = SockFprog {
+                Len: prog.Len,
+                Filter: &filter[0] as * const _ as u64,
+                ..Default::default()
+            };
 
-        if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            unsafe {
+                core::slice::from_raw_parts(&fprog as * const _ as u64 as * const u8, SIZEOF_SOCK_FPROG)
+            }
+        } else {
+            opt
+        };
+
+        // multicast membership is joined by the host socket, the guest copies keep the guest
+        // interface index and the host gets the host one
+        let multicastOp = MulticastOp::Parse(level, name, opt)?;
+        if let Some(op) = &multicastOp {
+            self.multicast.lock().Check(op)?;
         }
+        let multicastOpt = MulticastOpt::Parse(level, name, opt)?;
+
+        let hostMulticastOpt;
+        let opt = match IfIndexOffset(level, name, opt.len()) {
+            None => opt,
+            Some(offset) => {
+                hostMulticastOpt = HostIfIndexOpt(opt, offset, &mut |index| HostIfIndex(self.fd, index))?;
+                &hostMulticastOpt[..]
+            }
+        };
 
-        let msgFlags = msgHdr.msgFlags & !MsgType::MSG_CTRUNC;
-        let senderAddr = if senderRequested
-            // for tcp connect, recvmsg get nameLen=0 msg
-            && msgHdr.nameLen >= 4 {
+        // the device of the guest link is bound by its name, SO_BINDTOIFINDEX is passed as
+        // SO_BINDTODEVICE too
+        let hostIfName;
+        let mut bindIndex = None;
+        let (name, opt) = match BindDeviceOpt::Parse(level, name, opt)? {
+            None => (name, opt),
+            Some(dev) => {
+                let (index, ifname) = BindDevice(task, &dev, self.bindDevice.load(Ordering::Relaxed))?;
+                bindIndex = Some(index);
+                hostIfName = ifname;
+                (SO_BINDTODEVICE, &hostIfName[..])
+            }
+        };
 
-            let addr = GetAddr(addr[0] as i16, &addr[0..msgHdr.nameLen as usize])?;
-            let l = addr.Len();
-            Some((addr, l))
+        let optLen = opt.len();
+        let res = if optLen == 0 {
+            Kernel::HostSpace::SetSockOpt(self.fd, level, name, ptr::null::<u8>() as u64, optLen as u32)
         } else {
-            None
+            Kernel::HostSpace::SetSockOpt(self.fd, level, name, &opt[0] as *const _ as u64, optLen as u32)
         };
 
-        controlVec.resize(msgHdr.msgControlLen, 0);
+        if res < 0 {
+            return Err(HostErr("SetSockOpt", self.fd, -res as i32))
+        }
 
-        task.CopyDataOutToIovs(&buf.buf[0..res as usize], dsts)?;
-        return Ok((res as i64, msgFlags, senderAddr, controlVec))
+        if let Some(op) = multicastOp {
+            self.multicast.lock().Apply(&op);
+        }
+
+        if let Some(o) = multicastOpt {
+            self.multicastOpts.lock().Apply(&o);
+        }
+
+        if let Some(index) = bindIndex {
+            self.bindDevice.store(index, Ordering::Relaxed);
+        }
+
+        if IsRecvErrOpt(level, name) {
+            self.SetRecvErr(opt);
+        }
+
+        return Ok(res)
     }
 
-    fn SendMsg(&self, task: &Task, srcs: &[IoVec], flags: i32, msgHdr: &mut MsgHdr, deadline: Option<Time>) -> Result<i64> {
-        if self.SocketBufEnabled() {
-            if msgHdr.msgName != 0 || msgHdr.msgControl != 0 {
-                panic!("Hostnet Socketbuf doesn't supprot MsgHdr");
-            }
+    fn GetSockName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        let localAddr = self.localAddr.lock().clone();
+        if let Some(addr) = localAddr {
+            let n = core::cmp::min(socketaddr.len(), addr.len());
+            socketaddr[..n].copy_from_slice(&addr[..n]);
+            return Ok(addr.len() as i64)
+        }
 
-            let len = Iovs(srcs).Count();
-            let mut count = 0;
-            let mut srcs = srcs;
-            let mut tmp;
-            let socketType = self.SocketBufType();
-            loop {
-                loop {
-                    match self.WriteToBuf(task, socketType.clone(), srcs) {
-                        Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
-                            if count > 0 {
-                                return Ok(count)
-                            }
+        let len = socketaddr.len() as i32;
 
-                            if flags & MsgType::MSG_DONTWAIT != 0 {
-                                return Err(Error::SysError(SysErr::EWOULDBLOCK))
-                            }
+        let res = Kernel::HostSpace::GetSockName(self.fd, &socketaddr[0] as *const _ as u64, &len as *const _ as u64);
+        if res < 0 {
+            return Err(HostErr("GetSockName", self.fd, -res as i32))
+        }
 
-                            break;
-                        }
-                        Err(e) => {
-                            if count > 0 {
-                                return Ok(count)
-                            }
+        // the local address of the bound or connected socket doesn't change until connect
+        if len as usize <= socketaddr.len() {
+            *self.localAddr.lock() = NormalizeInetAddr(self.family, &socketaddr[..len as usize]);
+        }
 
-                            return Err(e)
-                        },
-                        Ok(n) => {
-                            count += n;
-                            if count == len as i64 {
-                                return Ok(count)
-                            }
-                            tmp = Iovs(srcs).DropFirst(n as usize);
-                            srcs = &mut tmp;
-                        },
-                    }
-                }
+        return Ok(len as i64)
+    }
 
-                let general = task.blocker.generalEntry.clone();
-                self.EventRegister(task, &general, EVENT_WRITE);
-                defer!(self.EventUnregister(task, &general));
+    fn GetPeerName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        // the cached peer is the original destination of the connection rewritten by the nat rules
+        if let Some(addr) = self.CachedPeerAddr() {
+            let n = core::cmp::min(socketaddr.len(), addr.len());
+            socketaddr[..n].copy_from_slice(&addr[..n]);
+            return Ok(addr.len() as i64)
+        }
 
-                match task.blocker.BlockWithMonoTimer(true, deadline) {
-                    Err(Error::SysError(SysErr::ETIMEDOUT)) => {
-                        if count > 0 {
-                            return Ok(count)
-                        }
-                        return Err(Error::SysError(SysErr::EWOULDBLOCK));
-                    }
-                    Err(e) => {
-                        if count > 0 {
-                            return Ok(count)
-                        }
-                        return Err(e);
-                    }
-                    _ => ()
-                }
+        if self.NatEnabled() {
+            if let Some((addr, port)) = HostSockAddrV4(self.fd, false).and_then(|local| NAT.OrigDst(local)) {
+                let sockaddr = NewSockAddrV4(addr, port);
+                let n = core::cmp::min(socketaddr.len(), sockaddr.len());
+                socketaddr[..n].copy_from_slice(&sockaddr[..n]);
+                return Ok(sockaddr.len() as i64)
             }
+        }
 
+        let len = socketaddr.len() as i32;
+        let res = Kernel::HostSpace::GetPeerName(self.fd, &socketaddr[0] as *const _ as u64, &len as *const _ as u64);
+        if res < 0 {
+            return Err(HostErr("GetPeerName", self.fd, -res as i32))
         }
 
-        if flags & !(MsgType::MSG_DONTWAIT | MsgType::MSG_EOR | MsgType::MSG_FASTOPEN | MsgType::MSG_MORE | MsgType::MSG_NOSIGNAL) != 0 {
-            return Err(Error::SysError(SysErr::EINVAL))
+        return Ok(len as i64)
+    }
+
+    fn RecvMsg(&self, task: &Task, dsts: &mut [IoVec], flags: i32, deadline: Option<Time>, senderRequested: bool, controlDataLen: usize)
+        -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)>  {
+        let ret = self.RecvMsgIntern(task, dsts, flags, deadline, senderRequested, controlDataLen)?;
+        if flags & (MsgType::MSG_PEEK | MsgType::MSG_ERRQUEUE) == 0 {
+            SHARESPACE.sockStats.Recv(ret.0);
         }
-        
-        /*defer!(task.GetMut().iovs.clear());
-        task.V2PIovs(srcs, false, &mut task.GetMut().iovs)?;
-        let iovs = &task.GetMut().iovs;*/
 
-        let size = IoVec::NumBytes(srcs);
-        let mut buf = DataBuff::New(size);
-        let iovs = buf.Iovs();
+        return Ok(ret)
+    }
 
-        task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
+    fn SendMsg(&self, task: &Task, srcs: &[IoVec], flags: i32, msgHdr: &mut MsgHdr, deadline: Option<Time>) -> Result<i64> {
+        let n = self.SendMsgIntern(task, srcs, flags, msgHdr, deadline)?;
+        SHARESPACE.sockStats.Send(n);
+        return Ok(n)
+    }
 
-        if IoVec::NumBytes(srcs) != 0 {
-            msgHdr.iov = &iovs[0] as *const _ as u64;
-        } else {
-            msgHdr.iov = ptr::null::<IoVec>() as u64;
-        }
-        msgHdr.iovLen = iovs.len();
-        msgHdr.msgFlags = 0;
+    fn RecvErrQueue(&self, task: &Task, dsts: &mut [IoVec], flags: i32, senderRequested: bool, controlDataLen: usize) -> Result<RecvMsgResult> {
+        return self.HostRecvErrQueue(task, dsts, flags, senderRequested, controlDataLen)
+    }
 
-        let mut res = Kernel::HostSpace::IOSendMsg(self.fd, msgHdr as *const _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
-        while res == -SysErr::EWOULDBLOCK && flags & MsgType::MSG_DONTWAIT == 0 {
-            let general = task.blocker.generalEntry.clone();
+    fn RecvMMsg(&self, task: &Task, msgs: &mut [MMsgRecv], flags: i32, deadline: Option<Time>) -> Result<Vec<RecvMsgResult>> {
+        // the buffered sockets take the messages from the guest buffer
+        if self.DgramBuf().is_some() || self.SocketBufEnabled() || flags & MsgType::MSG_ERRQUEUE != 0 {
+            return RecvMMsgOneByOne(self, task, msgs, flags, deadline)
+        }
 
-            self.EventRegister(task, &general, EVENT_WRITE);
-            defer!(self.EventUnregister(task, &general));
-            match task.blocker.BlockWithMonoTimer(true, deadline) {
-                Err(e) => {
-                    return Err(e);
-                }
-                _ => ()
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+        defer!(self.inflight.fetch_sub(1, Ordering::SeqCst));
+        let rets = self.HostRecvMMsg(task, msgs, flags, deadline)?;
+        if flags & MsgType::MSG_PEEK == 0 {
+            for ret in &rets {
+                SHARESPACE.sockStats.Recv(ret.0);
             }
+        }
 
-            res = Kernel::HostSpace::IOSendMsg(self.fd, msgHdr as *const _ as u64, flags | MsgType::MSG_DONTWAIT, false) as i32;
+        return Ok(rets)
+    }
+
+    fn SendMMsg(&self, task: &Task, msgs: &mut [MMsgSend], flags: i32, deadline: Option<Time>) -> Result<Vec<i64>> {
+        // MSG_FASTOPEN connects the socket with the first message
+        let fastOpen = flags & MsgType::MSG_FASTOPEN != 0 && self.stype == SockType::SOCK_STREAM;
+        if self.DgramBuf().is_some() || self.SocketBufEnabled() || fastOpen {
+            return SendMMsgOneByOne(self, task, msgs, flags, deadline)
         }
 
-        if res < 0 {
-            return Err(Error::SysError(-res as i32))
+        self.inflight.fetch_add(1, Ordering::SeqCst);
+        defer!(self.inflight.fetch_sub(1, Ordering::SeqCst));
+        let rets = self.HostSendMMsg(task, msgs, flags, deadline)?;
+        for n in &rets {
+            SHARESPACE.sockStats.Send(*n);
         }
 
-        return Ok(res as i64)
+        return Ok(rets)
     }
 
     fn SetRecvTimeout(&self, ns: i64) {
@@ -1424,18 +3487,48 @@ pub struct SocketProvider {
 
 impl Provider for SocketProvider {
     fn Socket(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<Arc<File>>> {
+        // the host provides only the unix socket pairs, the other unix sockets are of the guest
+        if self.family == AFType::AF_UNIX {
+            return Ok(None)
+        }
+
         let stype = stype & SocketType::SOCK_TYPE_MASK;
 
+        // raw and packet sockets are created by the host with the sandbox capability,
+        // the guest task has to have CAP_NET_RAW as linux does
+        if self.family == AFType::AF_PACKET
+            || stype == SocketType::SOCK_RAW
+            || stype == SocketType::SOCK_PACKET {
+            if !SHARESPACE.config.read().EnableRawSocket {
+                return Err(Error::SysError(SysErr::EPERM))
+            }
+
+            if !task.Creds().HasCapability(Capability::CAP_NET_RAW) {
+                return Err(Error::SysError(SysErr::EPERM))
+            }
+        }
+
         let res = Kernel::HostSpace::Socket(self.family, stype | SocketFlags::SOCK_CLOEXEC, protocol);
         if res < 0 {
-            return Err(Error::SysError(-res as i32))
+            return Err(HostErr("Socket", -1, -res as i32))
         }
 
        let fd = res as i32;
 
-        let socketType = if (self.family == AFType::AF_INET || self.family == AFType::AF_INET6)
-            && stype == SockType::SOCK_STREAM {
+        let inet = self.family == AFType::AF_INET || self.family == AFType::AF_INET6;
+        // the udp socket which qvisor can't take goes by the uring or the host calls
+        let rdmaDgram = if inet && stype == SockType::SOCK_DGRAM && SHARESPACE.config.read().EnableRDMA {
+            RDMA::NewDgramBuf(fd).ok()
+        } else {
+            None
+        };
+
+        let socketType = if inet && stype == SockType::SOCK_STREAM {
             SocketBufType::TCPInit
+        } else if let Some(buf) = rdmaDgram {
+            SocketBufType::Dgram(buf)
+        } else if inet && stype == SockType::SOCK_DGRAM && SHARESPACE.config.read().UdpUringBuf {
+            SocketBufType::Dgram(Arc::new(DgramBuff::default()))
         } else {
             SocketBufType::NoTCP
         };
@@ -1446,31 +3539,57 @@ impl Provider for SocketProvider {
                                  stype & SocketType::SOCK_TYPE_MASK,
                                  stype & SocketFlags::SOCK_NONBLOCK != 0,
                                  socketType,
+                                 None,
                                  None)?;
         return Ok(Some(Arc::new(file)))
     }
 
-    fn Pair(&self, _task: &Task, _stype: i32, _protocol: i32) -> Result<Option<(Arc<File>, Arc<File>)>> {
-        /*if self.family == AFType::AF_UNIX {
-            let fds: [i32; 2] = [0; 2];
+    fn Pair(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<(Arc<File>, Arc<File>)>> {
+        // linux only has the unix socket pairs
+        if self.family != AFType::AF_UNIX {
+            return Err(Error::SysError(SysErr::EOPNOTSUPP))
+        }
+
+        let nonblock = stype & SocketFlags::SOCK_NONBLOCK != 0;
+        let stype = stype & SocketType::SOCK_TYPE_MASK;
+        let mut fds: [i32; 2] = [-1; 2];
 
-            let res = Kernel::HostSpace::SocketPair(self.family, stype | SocketFlags::SOCK_CLOEXEC, protocol, &fds[0] as *const _ as u64);
-            if res < 0 {
-                return Err(Error::SysError(-res as i32))
-            }
+        // the host fds are always nonblocking, the guest file gets SOCK_NONBLOCK. The hostfds
+        // are registered to the fd notifier with the inodes.
+        let res = Kernel::HostSpace::SocketPair(self.family, stype | SocketFlags::SOCK_CLOEXEC, protocol, &mut fds[0] as *mut _ as u64);
+        if res < 0 {
+            return Err(HostErr("SocketPair", -1, -res as i32))
+        }
 
-            let file0 = newSocketFile(task, self.family, fds[0], stype & SocketFlags::SOCK_NONBLOCK != 0)?;
-            let file1 = newSocketFile(task, self.family, fds[1], stype & SocketFlags::SOCK_NONBLOCK != 0)?;
+        let file0 = match newSocketFile(task, self.family, fds[0], stype, nonblock, SocketBufType::NoTCP, None, None) {
+            Err(e) => {
+                HostSpace::Close(fds[0]);
+                HostSpace::Close(fds[1]);
+                return Err(e)
+            }
+            Ok(f) => f,
+        };
 
-            return Ok(Some((Arc::new(file0), Arc::new(file1))));
-        }*/
+        // fds[0] is closed with file0
+        let file1 = match newSocketFile(task, self.family, fds[1], stype, nonblock, SocketBufType::NoTCP, None, None) {
+            Err(e) => {
+                HostSpace::Close(fds[1]);
+                return Err(e)
+            }
+            Ok(f) => f,
+        };
 
-        return Err(Error::SysError(SysErr::EOPNOTSUPP))
+        return Ok(Some((Arc::new(file0), Arc::new(file1))));
     }
 }
 
 pub fn Init() {
-    for family in [AFType::AF_INET, AFType::AF_INET6, AFType::AF_NETLINK].iter() {
+    for family in [AFType::AF_INET, AFType::AF_INET6, AFType::AF_NETLINK, AFType::AF_PACKET].iter() {
         FAMILIAES.write().RegisterProvider(*family, Box::new(SocketProvider { family: *family }))
     }
+
+    // it is registered before the guest unix provider so that it takes the socketpair calls
+    if SHARESPACE.config.read().HostUnixSocketPair {
+        FAMILIAES.write().RegisterProvider(AFType::AF_UNIX, Box::new(SocketProvider { family: AFType::AF_UNIX }))
+    }
 }
\ No newline at end of file
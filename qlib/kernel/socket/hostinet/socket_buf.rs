@@ -16,17 +16,51 @@ use super::super::super::super::linux_def::*;
 use super::super::super::super::common::*;
 use super::super::super::super::socket_buf::*;
 use super::super::super::task::Task;
+use super::super::super::fd::*;
+use super::super::super::Kernel::HostSpace;
+use super::super::super::kernel::waiter::*;
+use super::super::super::quring::buf_pool::*;
 
 impl SocketBuff {
     pub fn Readv(&self, task: &Task, iovs: &mut [IoVec]) -> Result<(bool, usize)> {
+        return self.readv(task, iovs, false)
+    }
+
+    // Peekv copies the data of the read buf without consuming it, it is the recv with MSG_PEEK
+    pub fn Peekv(&self, task: &Task, iovs: &mut [IoVec]) -> Result<usize> {
+        let (_, cnt) = self.readv(task, iovs, true)?;
+        return Ok(cnt)
+    }
+
+    fn readv(&self, task: &Task, iovs: &mut [IoVec], peek: bool) -> Result<(bool, usize)> {
         let mut trigger = false;
         let mut cnt = 0;
 
+        // the data in the receive pool bufs is ahead of the read buf, it is read first
+        let mut pooled = self.pooledRead.lock();
+        if pooled.size > 0 {
+            let srcIovs = pooled.Iovs();
+            let ret = task.mm.CopyIovsOutFromIovs(task, &srcIovs, iovs);
+            if let Ok(n) = &ret {
+                if !peek {
+                    trigger = self.ConsumePooled(&mut pooled, *n);
+                }
+            }
+
+            core::mem::drop(pooled);
+            RecvBufPool::ProvidePending();
+            return Ok((trigger, ret?))
+        }
+
         let mut buf = self.readBuf.lock();
         let srcIovs = buf.GetDataIovsVec();
         if srcIovs.len() > 0 {
             cnt = task.mm.CopyIovsOutFromIovs(task, &srcIovs, iovs)?;
-            trigger = buf.Consume(cnt);
+            if !peek {
+                trigger = buf.Consume(cnt);
+                buf.tune.Account(cnt);
+                self.Touch();
+            }
         }
 
         if cnt > 0 {
@@ -40,18 +74,24 @@ impl SocketBuff {
         }
     }
 
-    pub fn Writev(&self, task: &Task, iovs: &[IoVec]) -> Result<(usize, Option<(u64, usize)>)> {
+    // cork: TCP_CORK or MSG_MORE, the send of the data which turns the buf non empty is held
+    pub fn Writev(&self, task: &Task, iovs: &[IoVec], cork: bool) -> Result<(usize, Option<(u64, usize)>)> {
         if self.Error() != 0 {
             return Err(Error::SysError(self.Error()));
         }
 
+        // the write side is shut down
         if self.WClosed() {
-            error!("writev it is closed");
-            //return Ok((0, None))
             return Err(Error::SysError(SysErr::EPIPE))
         }
 
         let mut buf = self.writeBuf.lock();
+        // the space is being read from the host file by sendfile
+        if self.FileReadPending() {
+            return Err(Error::SysError(SysErr::EAGAIN));
+        }
+
+        buf.GrowPending();
         let dstIovs = buf.GetSpaceIovsVec();
         if dstIovs.len() == 0 {
             return Err(Error::SysError(SysErr::EAGAIN));
@@ -65,11 +105,90 @@ impl SocketBuff {
         }
 
         let trigger = buf.Produce(cnt);
-        if !trigger {
+        buf.tune.Account(cnt);
+        self.Touch();
+        if !trigger || (cork && self.HoldLocked(&buf)) {
             return Ok((cnt, None))
         } else {
             let (addr, len) = buf.GetDataBuf();
             return Ok((cnt, Some((addr, len))))
         }
     }
+
+    // WriteDirect sends the data to the host socket without going through the uring when the write
+    // buf is empty, i.e. there is no AsyncSend so the data order is kept. The data is queued in the
+    // write buf and sent from there with the buf unlocked, the writers coming meanwhile append to
+    // it and leave the AsyncSend of the part not taken by the host socket to this one. It returns
+    // None when the data has to go through Writev.
+    pub fn WriteDirect(&self, task: &Task, fd: i32, queue: &Queue, iovs: &[IoVec]) -> Result<Option<(usize, Option<(u64, usize)>)>> {
+        if self.Error() != 0 || self.WClosed() {
+            return Ok(None)
+        }
+
+        let size = IoVec::NumBytes(iovs);
+        if size == 0 || size > DIRECT_SEND_MAX {
+            return Ok(None)
+        }
+
+        let (cnt, srcIovs) = {
+            let mut buf = self.writeBuf.lock();
+            if buf.AvailableDataSize() > 0 || size > buf.AvailableSpace() || self.SendHeld() || self.FileReadPending() {
+                return Ok(None)
+            }
+
+            let dstIovs = buf.GetSpaceIovsVec();
+            let cnt = task.mm.CopyIovsOutToIovs(task, iovs, &dstIovs)?;
+            if cnt == 0 {
+                return Ok(None)
+            }
+
+            buf.Produce(cnt);
+            buf.tune.Account(cnt);
+            self.Touch();
+            (cnt, buf.GetDataIovsVec())
+        };
+
+        // the data stays at the head of the write buf until it is consumed here, there is no
+        // AsyncSend or resize of the write buf meanwhile
+        let (sent, err) = match IOWrite(fd, &srcIovs) {
+            Err(Error::SysError(SysErr::EAGAIN)) => (0, None),
+            // the data is dropped as the failed write
+            Err(e) => (cnt, Some(e)),
+            Ok(n) => (n as usize, None),
+        };
+
+        let (trigger, addr, len) = self.ConsumeAndGetAvailableWriteBuf(sent);
+        if trigger {
+            queue.Notify(EventMaskFromLinux(EVENT_OUT as u32));
+        }
+
+        if let Some(e) = err {
+            if len > 0 {
+                // the data of the other writers can't follow the failed one
+                let errno = match e {
+                    Error::SysError(errno) => errno,
+                    _ => SysErr::EIO,
+                };
+                self.SetErr(errno);
+                queue.Notify(EventMaskFromLinux((EVENT_ERR | EVENT_IN) as u32));
+            }
+
+            return Err(e)
+        }
+
+        if len == 0 {
+            // the SHUT_WR and the drain coming meanwhile wait for the write buf as the AsyncSend
+            if self.TakeFinPending() {
+                HostSpace::ShutdownAsync(fd, LibcConst::SHUT_WR as i32);
+            }
+
+            if self.PendingWriteShutdown() {
+                queue.Notify(EVENT_PENDING_SHUTDOWN);
+            }
+
+            return Ok(Some((cnt, None)))
+        }
+
+        return Ok(Some((cnt, Some((addr, len)))))
+    }
 }
\ No newline at end of file
@@ -0,0 +1,374 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+use super::super::super::super::control_msg::RDMAConnStatsInfo;
+use super::super::super::super::control_msg::SockStatsInfo;
+use super::super::super::super::linux::time::SECOND;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::mutex::*;
+use super::super::super::super::socket_buf::SocketBuff;
+use super::super::super::kernel::async_process::*;
+use super::super::super::kernel::timer::timer::*;
+use super::super::super::kernel::timer::MONOTONIC_CLOCK;
+use super::super::super::kernel::timer::MonotonicNow;
+use super::super::super::Kernel::HostSpace;
+use super::super::unix::transport::unix::SockType;
+use super::super::super::ASYNC_PROCESS;
+use super::super::super::SHARESPACE;
+
+// Socket statistics of the sandbox: the bytes and the packets moved by the app on the host
+// inet sockets, the tcp retransmits of the host sockets and the times the accept queue of a
+// listener is full. A packet is a socket call moving data, it is a datagram of the udp socket.
+// They are read by the control socket and /proc/net/sockstat.
+
+// tcpi_total_retrans of struct tcp_info
+pub const TCP_INFO_TOTAL_RETRANS_OFFSET: usize = 100;
+
+// the retransmits of the live tcp sockets are summed by qvisor at most once in the period
+pub const LIVE_RETRANS_PERIOD: i64 = SECOND;
+
+// SockCounters is in the share space as the accept queue is filled by the uring completions,
+// which may run on the host io thread
+#[derive(Default, Debug)]
+pub struct SockCounters {
+    pub bytesIn: AtomicU64,
+    pub bytesOut: AtomicU64,
+    pub packetsIn: AtomicU64,
+    pub packetsOut: AtomicU64,
+    // the retransmits of the closed tcp sockets, they are added by qvisor at the close of the
+    // host socket
+    pub closedRetrans: AtomicU64,
+    // the accept queue is full and the host accept is paused, the connections wait in the host
+    // backlog, which drops them when it is full too
+    pub acceptOverflows: AtomicU64,
+    // the RDMA connections of the host which fall back to the tcp path or are reset as the work
+    // requests fail, they are counted by qvisor
+    pub rdmaFallbacks: AtomicU64,
+    pub rdmaResets: AtomicU64,
+}
+
+impl SockCounters {
+    pub fn Recv(&self, n: i64) {
+        if n <= 0 {
+            return
+        }
+
+        self.bytesIn.fetch_add(n as u64, Ordering::Relaxed);
+        self.packetsIn.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn Send(&self, n: i64) {
+        if n <= 0 {
+            return
+        }
+
+        self.bytesOut.fetch_add(n as u64, Ordering::Relaxed);
+        self.packetsOut.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn AcceptOverflow(&self) {
+        self.acceptOverflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn RDMAFallback(&self) {
+        self.rdmaFallbacks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn RDMAReset(&self) {
+        self.rdmaResets.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// TcpInfoRetrans returns tcpi_total_retrans of the struct tcp_info got with len bytes
+pub fn TcpInfoRetrans(info: &[u8], len: usize) -> u64 {
+    if len < TCP_INFO_TOTAL_RETRANS_OFFSET + 4 || info.len() < TCP_INFO_TOTAL_RETRANS_OFFSET + 4 {
+        return 0
+    }
+
+    let mut retrans = [0u8; 4];
+    retrans.copy_from_slice(&info[TCP_INFO_TOTAL_RETRANS_OFFSET..TCP_INFO_TOTAL_RETRANS_OFFSET + 4]);
+    return u32::from_ne_bytes(retrans) as u64
+}
+
+// InetSockets counts the live inet sockets, the retransmits of the tcp ones are summed by qvisor
+pub struct InetSockets {
+    pub tcpInuse: AtomicU64,
+    pub udpInuse: AtomicU64,
+    // the retransmits of the live tcp sockets and the time they are got from qvisor
+    pub liveRetrans: AtomicU64,
+    pub liveRetransTime: AtomicI64,
+    // the socket bufs shared with qvisor by the RDMA sockets, which have the RDMA counters.
+    // The bool is true for the udp socket.
+    pub rdma: QMutex<Option<BTreeMap<i32, (bool, Arc<SocketBuff>)>>>,
+}
+
+pub static INET_SOCKETS: InetSockets = InetSockets::New();
+
+impl InetSockets {
+    pub const fn New() -> Self {
+        return Self {
+            tcpInuse: AtomicU64::new(0),
+            udpInuse: AtomicU64::new(0),
+            liveRetrans: AtomicU64::new(0),
+            liveRetransTime: AtomicI64::new(i64::MIN),
+            rdma: QMutex::new(None),
+        }
+    }
+
+    fn Inuse(&self, stype: i32) -> Option<&AtomicU64> {
+        match stype {
+            SockType::SOCK_STREAM => return Some(&self.tcpInuse),
+            SockType::SOCK_DGRAM => return Some(&self.udpInuse),
+            _ => return None,
+        }
+    }
+
+    pub fn AddRDMA(&self, fd: i32, udp: bool, buf: &Arc<SocketBuff>) {
+        self.rdma.lock().get_or_insert_with(BTreeMap::new).insert(fd, (udp, buf.clone()));
+        StartRDMAStatsLog();
+    }
+
+    // RemoveRDMA is only called by the RDMA sockets, the others don't take the lock of the map
+    pub fn RemoveRDMA(&self, fd: i32) {
+        if let Some(rdma) = self.rdma.lock().as_mut() {
+            rdma.remove(&fd);
+        }
+    }
+
+    pub fn RDMAStats(&self) -> Vec<RDMAConnStatsInfo> {
+        let mut stats = Vec::new();
+        if let Some(rdma) = self.rdma.lock().as_ref() {
+            for (fd, (udp, buf)) in rdma.iter() {
+                stats.push(buf.rdmaStats.Info(*fd, *udp, buf.RDMAFallback()));
+            }
+        }
+
+        return stats
+    }
+
+    pub fn Add(&self, stype: i32) {
+        if let Some(inuse) = self.Inuse(stype) {
+            inuse.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // Remove is called by the inet socket only, the retransmits of the tcp socket are added by
+    // qvisor when it closes the host fd
+    pub fn Remove(&self, stype: i32) {
+        if let Some(inuse) = self.Inuse(stype) {
+            inuse.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    // LiveRetrans returns the retransmits of the live tcp sockets. They are got from qvisor in
+    // one host call at most once in LIVE_RETRANS_PERIOD, the readers meanwhile get the last sum.
+    pub fn LiveRetrans(&self, now: i64) -> u64 {
+        if self.tcpInuse.load(Ordering::Relaxed) == 0 {
+            return 0
+        }
+
+        let last = self.liveRetransTime.load(Ordering::Relaxed);
+        if now.saturating_sub(last) >= LIVE_RETRANS_PERIOD &&
+            self.liveRetransTime.compare_exchange(last, now, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+            let ret = HostSpace::SockRetrans();
+            if ret >= 0 {
+                self.liveRetrans.store(ret as u64, Ordering::Relaxed);
+            }
+        }
+
+        return self.liveRetrans.load(Ordering::Relaxed)
+    }
+
+    pub fn Stats(&self) -> SockStatsInfo {
+        let counters = &SHARESPACE.sockStats;
+        let tcpInuse = self.tcpInuse.load(Ordering::Relaxed);
+        let retransmits = counters.closedRetrans.load(Ordering::Relaxed) + self.LiveRetrans(MonotonicNow());
+        let (fdNotifies, fdNotifiesCoalesced) = SHARESPACE.FdNotifyStat();
+
+        return SockStatsInfo {
+            tcpInuse: tcpInuse,
+            udpInuse: self.udpInuse.load(Ordering::Relaxed),
+            bytesIn: counters.bytesIn.load(Ordering::Relaxed),
+            bytesOut: counters.bytesOut.load(Ordering::Relaxed),
+            packetsIn: counters.packetsIn.load(Ordering::Relaxed),
+            packetsOut: counters.packetsOut.load(Ordering::Relaxed),
+            retransmits: retransmits,
+            acceptOverflows: counters.acceptOverflows.load(Ordering::Relaxed),
+            rdmaFallbacks: counters.rdmaFallbacks.load(Ordering::Relaxed),
+            rdmaResets: counters.rdmaResets.load(Ordering::Relaxed),
+            fdNotifies: fdNotifies,
+            fdNotifiesCoalesced: fdNotifiesCoalesced,
+        }
+    }
+}
+
+static RDMA_STATS_LOG: spin::Once<Timer> = spin::Once::new();
+
+// StartRDMAStatsLog starts the periodic summary of the RDMA counters with the first RDMA socket
+pub fn StartRDMAStatsLog() {
+    let period = SHARESPACE.config.read().RDMAStatsLogSec as i64 * SECOND;
+    if period == 0 {
+        return
+    }
+
+    RDMA_STATS_LOG.call_once(|| {
+        let listener = TimerListener::RDMAStatsListener(Arc::new(RDMAStatsListener {}));
+        Timer::Period(&MONOTONIC_CLOCK, listener, period)
+    });
+}
+
+pub struct RDMAStatsListener {}
+
+impl TimerListenerTrait for RDMAStatsListener {
+    fn Notify(&self, _exp: u64) {
+        ASYNC_PROCESS.Queue(AsyncWork::LogRDMAStats);
+    }
+
+    fn Destroy(&self) {}
+}
+
+// LogRDMAStats logs the sum of the counters of the live RDMA sockets and the ones which stall
+// or fail, the per connection counters are got by the control socket
+pub fn LogRDMAStats() {
+    let stats = INET_SOCKETS.RDMAStats();
+    if stats.len() == 0 {
+        return
+    }
+
+    let mut sum = RDMAConnStatsInfo::default();
+    let mut fallbacks = 0;
+    for s in &stats {
+        sum.writesPosted += s.writesPosted;
+        sum.bytesWritten += s.bytesWritten;
+        sum.writeCompletions += s.writeCompletions;
+        sum.recvCompletions += s.recvCompletions;
+        sum.freespaceStalls += s.freespaceStalls;
+        sum.retries += s.retries;
+        sum.qpErrors += s.qpErrors;
+        if s.fallback {
+            fallbacks += 1;
+        }
+    }
+
+    info!("RDMA stats: sockets {} fallbacks {} writes {} bytes {} write_completions {} \
+           recv_completions {} freespace_stalls {} retries {} qp_errors {}",
+          stats.len(), fallbacks, sum.writesPosted, sum.bytesWritten, sum.writeCompletions,
+          sum.recvCompletions, sum.freespaceStalls, sum.retries, sum.qpErrors);
+    for s in &stats {
+        if s.retries > 0 || s.qpErrors > 0 {
+            info!("RDMA stats of fd {}: {:?}", s.fd, s);
+        }
+    }
+}
+
+// SockStatText is /proc/net/sockstat, the lines of linux and the counters of the sandbox in the
+// same format
+pub fn SockStatText(stats: &SockStatsInfo) -> String {
+    return format!("sockets: used {}\n\
+                    TCP: inuse {} orphan 0 tw 0 alloc {} mem 0\n\
+                    UDP: inuse {} mem 0\n\
+                    UDPLITE: inuse 0\n\
+                    RAW: inuse 0\n\
+                    FRAG: inuse 0 memory 0\n\
+                    QUARK: bytes_in {} bytes_out {} packets_in {} packets_out {} retrans {} accept_overflows {} \
+                    rdma_fallbacks {} rdma_resets {} fd_notifies {} fd_notifies_coalesced {}\n",
+                   stats.tcpInuse + stats.udpInuse,
+                   stats.tcpInuse, stats.tcpInuse,
+                   stats.udpInuse,
+                   stats.bytesIn, stats.bytesOut, stats.packetsIn, stats.packetsOut,
+                   stats.retransmits, stats.acceptOverflows,
+                   stats.rdmaFallbacks, stats.rdmaResets,
+                   stats.fdNotifies, stats.fdNotifiesCoalesced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::super::test_util;
+
+    #[test]
+    fn test_sock_stat_text() {
+        let stats = SockStatsInfo {
+            tcpInuse: 2,
+            udpInuse: 1,
+            bytesIn: 10,
+            ..Default::default()
+        };
+
+        let text = SockStatText(&stats);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "sockets: used 3");
+        assert_eq!(lines[1], "TCP: inuse 2 orphan 0 tw 0 alloc 2 mem 0");
+        assert_eq!(lines[2], "UDP: inuse 1 mem 0");
+        assert!(lines[6].starts_with("QUARK: bytes_in 10 bytes_out 0"));
+        assert!(lines[6].ends_with("rdma_fallbacks 0 rdma_resets 0 fd_notifies 0 fd_notifies_coalesced 0"));
+    }
+
+    #[test]
+    fn test_inet_sockets() {
+        let sockets = InetSockets::New();
+        sockets.Add(SockType::SOCK_STREAM);
+        sockets.Add(SockType::SOCK_STREAM);
+        sockets.Add(SockType::SOCK_DGRAM);
+        sockets.Add(SockType::SOCK_RAW);
+        sockets.Remove(SockType::SOCK_STREAM);
+        sockets.Remove(SockType::SOCK_RAW);
+        assert_eq!(sockets.tcpInuse.load(Ordering::Relaxed), 1);
+        assert_eq!(sockets.udpInuse.load(Ordering::Relaxed), 1);
+
+        // the sum got within the period is returned without the host call
+        sockets.liveRetrans.store(7, Ordering::Relaxed);
+        sockets.liveRetransTime.store(100, Ordering::Relaxed);
+        assert_eq!(sockets.LiveRetrans(100 + LIVE_RETRANS_PERIOD - 1), 7);
+        sockets.Remove(SockType::SOCK_STREAM);
+        assert_eq!(sockets.LiveRetrans(100 + LIVE_RETRANS_PERIOD), 0);
+    }
+
+    #[test]
+    fn test_tcp_info_retrans() {
+        let mut info = [0u8; 232];
+        info[TCP_INFO_TOTAL_RETRANS_OFFSET..TCP_INFO_TOTAL_RETRANS_OFFSET + 4].copy_from_slice(&9u32.to_ne_bytes());
+        assert_eq!(TcpInfoRetrans(&info, info.len()), 9);
+        // the old kernel doesn't have the field
+        assert_eq!(TcpInfoRetrans(&info, TCP_INFO_TOTAL_RETRANS_OFFSET), 0);
+    }
+
+    // the counters are bumped by qvisor in the share space, the rdma feature builds its callers
+    #[test]
+    fn test_rdma_counters() {
+        let _l = test_util::Lock();
+        test_util::InitShareSpace();
+        let counters = &SHARESPACE.sockStats;
+        let fallbacks = counters.rdmaFallbacks.load(Ordering::Relaxed);
+        let resets = counters.rdmaResets.load(Ordering::Relaxed);
+
+        counters.RDMAFallback();
+        counters.RDMAReset();
+        counters.RDMAReset();
+
+        let stats = INET_SOCKETS.Stats();
+        assert_eq!(stats.rdmaFallbacks, fallbacks + 1);
+        assert_eq!(stats.rdmaResets, resets + 2);
+        assert!(SockStatText(&stats).contains(&format!("rdma_fallbacks {} rdma_resets {}",
+                                                       fallbacks + 1, resets + 2)));
+    }
+}
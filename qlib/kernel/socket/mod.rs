@@ -18,8 +18,11 @@ pub mod hostinet;
 pub mod control;
 pub mod buffer;
 pub mod epsocket;
+pub mod netlink;
 
 pub fn Init() {
+    // the guest NETLINK_ROUTE provider is registered before the host one to take the route sockets
+    self::netlink::Init();
     self::hostinet::Init();
     self::unix::Init();
 }
\ No newline at end of file
@@ -0,0 +1,196 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+pub mod route;
+
+use alloc::vec::Vec;
+use alloc::collections::vec_deque::VecDeque;
+use core::ptr;
+
+use super::super::super::linux_def::*;
+
+pub const NLMSG_HDRLEN: usize = LibcConst::NLMSG_HDRLEN as usize;
+
+// NetlinkMsgHdr is struct nlmsghdr, from uapi/linux/netlink.h.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NetlinkMsgHdr {
+    pub Length: u32,
+    pub Type: u16,
+    pub Flags: u16,
+    pub Seq: u32,
+    pub PortID: u32,
+}
+
+impl NetlinkMsgHdr {
+    pub fn FromBytes(buf: &[u8]) -> Self {
+        assert!(buf.len() >= NLMSG_HDRLEN);
+        // the messages of the app buffer are not aligned
+        return unsafe {
+            ptr::read_unaligned(&buf[0] as *const _ as *const Self)
+        }
+    }
+
+    pub fn ToBytes(&self, buf: &mut [u8]) {
+        assert!(buf.len() >= NLMSG_HDRLEN);
+        unsafe {
+            ptr::write_unaligned(&mut buf[0] as *mut _ as *mut Self, *self);
+        }
+    }
+}
+
+pub fn NlmsgAlign(len: usize) -> usize {
+    let align = LibcConst::NLMSG_ALIGNTO as usize;
+    return (len + align - 1) & !(align - 1)
+}
+
+// ParseMessages splits buf into the netlink messages, each with its header and the whole message
+// bytes. As linux netlink_rcv_skb, it stops at the first malformed message.
+pub fn ParseMessages(buf: &[u8]) -> Vec<(NetlinkMsgHdr, &[u8])> {
+    let mut msgs = Vec::new();
+    let mut offset = 0;
+    while offset + NLMSG_HDRLEN <= buf.len() {
+        let hdr = NetlinkMsgHdr::FromBytes(&buf[offset..]);
+        let len = hdr.Length as usize;
+        if len < NLMSG_HDRLEN || offset + len > buf.len() {
+            break;
+        }
+
+        msgs.push((hdr, &buf[offset..offset + len]));
+        offset += NlmsgAlign(len);
+    }
+
+    return msgs
+}
+
+// FindAttr returns the payload of the first rtattr of attrType, the attributes start at
+// offset in the message
+pub fn FindAttr(msg: &[u8], offset: usize, attrType: u16) -> Option<&[u8]> {
+    let mut offset = NlmsgAlign(offset);
+    while offset + 4 <= msg.len() {
+        let len = u16::from_ne_bytes([msg[offset], msg[offset+1]]) as usize;
+        let t = u16::from_ne_bytes([msg[offset+2], msg[offset+3]]);
+        if len < 4 || offset + len > msg.len() {
+            return None
+        }
+
+        if t == attrType {
+            return Some(&msg[offset + 4..offset + len])
+        }
+
+        offset += NlmsgAlign(len);
+    }
+
+    return None
+}
+
+// QueueDatagrams queues the reply datagrams of one request when the queued bytes stay within
+// limit, an empty queue takes them whatever their size. As linux netlink_overrun, it returns
+// false when they are dropped.
+pub fn QueueDatagrams(queue: &mut VecDeque<Vec<u8>>, datagrams: Vec<Vec<u8>>, limit: usize) -> bool {
+    let queued: usize = queue.iter().map(|d| d.len()).sum();
+    let size: usize = datagrams.iter().map(|d| d.len()).sum();
+    if queued > 0 && queued + size > limit {
+        return false
+    }
+
+    queue.extend(datagrams);
+    return true
+}
+
+pub fn Init() {
+    self::route::Init();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use super::*;
+
+    fn Msg(t: u16, payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0; NlmsgAlign(NLMSG_HDRLEN + payload.len())];
+        let hdr = NetlinkMsgHdr {
+            Length: (NLMSG_HDRLEN + payload.len()) as u32,
+            Type: t,
+            ..Default::default()
+        };
+        hdr.ToBytes(&mut buf);
+        buf[NLMSG_HDRLEN..NLMSG_HDRLEN + payload.len()].copy_from_slice(payload);
+        return buf
+    }
+
+    #[test]
+    fn TestParseMessages() {
+        let mut buf = Msg(0x12, &[1, 2, 3]);
+        buf.append(&mut Msg(0x16, &[4; 8]));
+        // truncated trailing header is dropped
+        buf.extend_from_slice(&[0xff; 8]);
+
+        let msgs = ParseMessages(&buf);
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].0.Type, 0x12);
+        assert_eq!(msgs[0].1.len(), NLMSG_HDRLEN + 3);
+        assert_eq!(msgs[1].0.Type, 0x16);
+        assert_eq!(msgs[1].1[NLMSG_HDRLEN..], [4; 8]);
+    }
+
+    #[test]
+    fn TestUnalignedHdr() {
+        let mut buf = vec![0; NLMSG_HDRLEN + 1];
+        let hdr = NetlinkMsgHdr {
+            Length: 20,
+            Type: 0x12,
+            Flags: 0x301,
+            Seq: 7,
+            PortID: 9,
+        };
+        hdr.ToBytes(&mut buf[1..]);
+
+        let got = NetlinkMsgHdr::FromBytes(&buf[1..]);
+        assert_eq!(got.Length, 20);
+        assert_eq!(got.Type, 0x12);
+        assert_eq!(got.Flags, 0x301);
+        assert_eq!(got.Seq, 7);
+        assert_eq!(got.PortID, 9);
+    }
+
+    #[test]
+    fn TestQueueDatagrams() {
+        let mut queue = VecDeque::new();
+        // the empty queue takes the replies over the limit
+        assert!(QueueDatagrams(&mut queue, vec![vec![0; 100], vec![0; 100]], 150));
+        assert_eq!(queue.len(), 2);
+
+        assert!(!QueueDatagrams(&mut queue, vec![vec![0; 1]], 150));
+        assert_eq!(queue.len(), 2);
+
+        queue.clear();
+        assert!(QueueDatagrams(&mut queue, vec![vec![0; 100]], 150));
+        assert!(QueueDatagrams(&mut queue, vec![vec![0; 50]], 150));
+        assert!(!QueueDatagrams(&mut queue, vec![vec![0; 1]], 150));
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn TestFindAttr() {
+        // ifinfomsg followed by IFLA_MTU and IFLA_IFNAME "lo"
+        let mut payload = vec![0; 16];
+        payload.extend_from_slice(&[8, 0, 4, 0, 0, 0x10, 0, 0]);
+        payload.extend_from_slice(&[7, 0, 3, 0, b'l', b'o', 0, 0]);
+        let msg = Msg(0x10, &payload);
+
+        assert_eq!(FindAttr(&msg, NLMSG_HDRLEN + 16, 3), Some(&b"lo\0"[..]));
+        assert_eq!(FindAttr(&msg, NLMSG_HDRLEN + 16, 1), None);
+    }
+}
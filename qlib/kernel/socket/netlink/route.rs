@@ -0,0 +1,645 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::boxed::Box;
+use alloc::collections::vec_deque::VecDeque;
+use core::any::Any;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+use crate::qlib::mutex::*;
+
+use super::super::socket::*;
+use super::super::super::fs::attr::*;
+use super::super::super::fs::file::*;
+use super::super::super::fs::flags::*;
+use super::super::super::fs::dentry::*;
+use super::super::super::fs::dirent::*;
+use super::super::super::fs::host::hostinodeop::*;
+use super::super::super::kernel::waiter::*;
+use super::super::super::kernel::time::*;
+use super::super::super::super::common::*;
+use super::super::super::super::linux_def::*;
+use super::super::super::super::linux::time::Timeval;
+use super::super::super::task::*;
+use super::super::super::tcpip::tcpip::*;
+use super::super::super::Kernel::HostSpace;
+use super::super::super::SHARESPACE;
+use super::*;
+
+// IFLA_IFNAME is the interface name attribute of RTM_NEWLINK, from uapi/linux/if_link.h
+pub const IFLA_IFNAME: u16 = 3;
+
+// the family headers after nlmsghdr: ifinfomsg, ifaddrmsg and rtmsg
+pub const IFINFOMSG_SIZE: usize = 16;
+
+// the dump replies are packed into datagrams of about a page as linux NLMSG_GOODSIZE
+pub const NETLINK_DUMP_DATAGRAM_SIZE: usize = 4096;
+
+pub const NETLINK_SOCKET_BUF_SIZE: i32 = 212992;
+
+// ReadRouteDump gets the host dump of msgType taken at the sandbox start
+pub fn ReadRouteDump(msgType: u16) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(16 * 1024);
+    buf.resize(16 * 1024, 0);
+    loop {
+        let ret = HostSpace::ReadRouteDump(msgType, &mut buf[0] as *mut _ as u64, buf.len());
+        if ret < 0 {
+            return Err(Error::SysError(-ret as i32))
+        }
+
+        let len = ret as usize;
+        if len <= buf.len() {
+            buf.truncate(len);
+            return Ok(buf)
+        }
+
+        // the dump is larger than the buffer, retry with its size
+        buf.resize(len, 0);
+    }
+}
+
+pub fn NewNetlinkRouteSocket(task: &Task, stype: i32) -> Result<File> {
+    // the host socket only backs the inode so that fstat reports a socket, the messages never
+    // reach it
+    let fd = HostSpace::Socket(AFType::AF_NETLINK, SocketType::SOCK_RAW | SocketFlags::SOCK_CLOEXEC, LibcConst::NETLINK_ROUTE as i32) as i32;
+    if fd < 0 {
+        return Err(Error::SysError(-fd))
+    }
+
+    let dirent = NewSocketDirent(task, SOCKET_DEVICE.clone(), fd)?;
+    let fileFlags = FileFlags {
+        Read: true,
+        Write: true,
+        ..Default::default()
+    };
+
+    return Ok(File::New(&dirent, &fileFlags, NetlinkRouteSocket::New(stype)))
+}
+
+// NetlinkRouteSocket is the NETLINK_ROUTE socket answered in the guest. The requests are handled
+// in SendMsg and the replies are queued as datagrams till RecvMsg.
+pub struct NetlinkRouteSocket {
+    pub stype: i32,
+    pub portId: AtomicU32,
+    pub replies: QMutex<VecDeque<Vec<u8>>>,
+    // the replies are dropped as the queue is full, the recv fails with ENOBUFS once
+    pub overrun: AtomicBool,
+    pub queue: Queue,
+    pub send: AtomicI64,
+    pub recv: AtomicI64,
+}
+
+impl NetlinkRouteSocket {
+    pub fn New(stype: i32) -> Self {
+        return Self {
+            stype: stype,
+            portId: AtomicU32::new(0),
+            replies: QMutex::new(VecDeque::new()),
+            overrun: AtomicBool::new(false),
+            queue: Queue::default(),
+            send: AtomicI64::new(0),
+            recv: AtomicI64::new(0),
+        }
+    }
+
+    // PortID returns the bound port id, the socket is bound to the tgid on the first use as
+    // linux netlink_autobind
+    pub fn PortID(&self, task: &Task) -> u32 {
+        let portId = self.portId.load(Ordering::Relaxed);
+        if portId != 0 {
+            return portId
+        }
+
+        let tgid = task.Thread().ThreadGroup().ID() as u32;
+        return match self.portId.compare_exchange(0, tgid, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => tgid,
+            Err(portId) => portId,
+        }
+    }
+
+    pub fn Addr(&self, portId: u32) -> SockAddrNetlink {
+        return SockAddrNetlink {
+            Family: AFType::AF_NETLINK as u16,
+            Padding: 0,
+            PortID: portId,
+            Groups: 0,
+        }
+    }
+
+    fn Reply(&self, req: &NetlinkMsgHdr, portId: u32, msg: &[u8], flags: u16) -> Vec<u8> {
+        let mut msg = msg.to_vec();
+        let mut hdr = NetlinkMsgHdr::FromBytes(&msg);
+        hdr.Flags = flags;
+        hdr.Seq = req.Seq;
+        hdr.PortID = portId;
+        hdr.ToBytes(&mut msg);
+        msg.resize(NlmsgAlign(msg.len()), 0);
+        return msg
+    }
+
+    // ErrorMsg is the NLMSG_ERROR reply, the ack when errno is 0. As linux netlink_ack, the error
+    // carries the whole request and the ack only its header.
+    fn ErrorMsg(&self, req: &NetlinkMsgHdr, reqMsg: &[u8], portId: u32, errno: i32) -> Vec<u8> {
+        let payload = if errno == 0 {
+            &reqMsg[..NLMSG_HDRLEN]
+        } else {
+            reqMsg
+        };
+
+        let len = NLMSG_HDRLEN + 4 + payload.len();
+        let mut msg = vec![0; NlmsgAlign(len)];
+        let hdr = NetlinkMsgHdr {
+            Length: len as u32,
+            Type: LibcConst::NLMSG_ERROR as u16,
+            Flags: 0,
+            Seq: req.Seq,
+            PortID: portId,
+        };
+        hdr.ToBytes(&mut msg);
+        msg[NLMSG_HDRLEN..NLMSG_HDRLEN + 4].copy_from_slice(&(-errno).to_ne_bytes());
+        msg[NLMSG_HDRLEN + 4..len].copy_from_slice(payload);
+        return msg
+    }
+
+    fn DoneMsg(&self, req: &NetlinkMsgHdr, portId: u32) -> Vec<u8> {
+        let len = NLMSG_HDRLEN + 4;
+        let mut msg = vec![0; len];
+        let hdr = NetlinkMsgHdr {
+            Length: len as u32,
+            Type: LibcConst::NLMSG_DONE as u16,
+            Flags: LibcConst::NLM_F_MULTI as u16,
+            Seq: req.Seq,
+            PortID: portId,
+        };
+        hdr.ToBytes(&mut msg);
+        return msg
+    }
+
+    // Dump packs the messages of the host dump with the requested family into datagrams,
+    // AF_UNSPEC gets all of them. The links are not filtered as linux rtnl_dump_ifinfo.
+    fn Dump(&self, req: &NetlinkMsgHdr, reqMsg: &[u8], portId: u32, datagrams: &mut Vec<Vec<u8>>) -> Result<()> {
+        let family = if reqMsg.len() > NLMSG_HDRLEN {
+            reqMsg[NLMSG_HDRLEN] as i32
+        } else {
+            AFType::AF_UNSPEC
+        };
+
+        let dump = ReadRouteDump(req.Type)?;
+        let mut datagram = Vec::new();
+        for (_, msg) in ParseMessages(&dump) {
+            if req.Type as u64 != LibcConst::RTM_GETLINK
+                && family != AFType::AF_UNSPEC
+                && msg.len() > NLMSG_HDRLEN
+                && msg[NLMSG_HDRLEN] as i32 != family {
+                continue;
+            }
+
+            let msg = self.Reply(req, portId, msg, LibcConst::NLM_F_MULTI as u16);
+            if datagram.len() > 0 && datagram.len() + msg.len() > NETLINK_DUMP_DATAGRAM_SIZE {
+                datagrams.push(datagram);
+                datagram = Vec::new();
+            }
+            datagram.extend_from_slice(&msg);
+        }
+
+        datagram.extend_from_slice(&self.DoneMsg(req, portId));
+        datagrams.push(datagram);
+        return Ok(())
+    }
+
+    // GetLink looks up the link by ifi_index or IFLA_IFNAME for the non dump RTM_GETLINK
+    fn GetLink(&self, req: &NetlinkMsgHdr, reqMsg: &[u8], portId: u32) -> Result<Vec<u8>> {
+        if reqMsg.len() < NLMSG_HDRLEN + IFINFOMSG_SIZE {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let index = Index(reqMsg);
+        let name = FindAttr(reqMsg, NLMSG_HDRLEN + IFINFOMSG_SIZE, IFLA_IFNAME).map(TrimName);
+        if index == 0 && name.is_none() {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let dump = ReadRouteDump(req.Type)?;
+        match FindLink(&dump, index, name) {
+            None => return Err(Error::SysError(SysErr::ENODEV)),
+            Some(msg) => return Ok(self.Reply(req, portId, msg, 0)),
+        }
+    }
+
+    fn HandleRequest(&self, req: &NetlinkMsgHdr, reqMsg: &[u8], portId: u32, datagrams: &mut Vec<Vec<u8>>) {
+        // as linux netlink_rcv_skb, the control messages and the non requests are skipped
+        if req.Flags as u64 & LibcConst::NLM_F_REQUEST == 0 || (req.Type as u64) < LibcConst::NLMSG_MIN_TYPE {
+            return
+        }
+
+        let res = match req.Type as u64 {
+            LibcConst::RTM_GETLINK | LibcConst::RTM_GETADDR | LibcConst::RTM_GETROUTE => {
+                if req.Flags as u64 & LibcConst::NLM_F_DUMP != 0 {
+                    self.Dump(req, reqMsg, portId, datagrams)
+                } else if req.Type as u64 == LibcConst::RTM_GETLINK {
+                    self.GetLink(req, reqMsg, portId).map(|msg| datagrams.push(msg))
+                } else {
+                    Err(Error::SysError(SysErr::EOPNOTSUPP))
+                }
+            }
+            _ => Err(Error::SysError(SysErr::EOPNOTSUPP)),
+        };
+
+        match res {
+            Err(Error::SysError(errno)) => {
+                datagrams.push(self.ErrorMsg(req, reqMsg, portId, errno));
+            }
+            Err(e) => {
+                error!("netlink route request {:?} fail with error {:?}", req, e);
+                datagrams.push(self.ErrorMsg(req, reqMsg, portId, SysErr::EIO));
+            }
+            Ok(()) => {
+                if req.Flags as u64 & LibcConst::NLM_F_ACK != 0 {
+                    datagrams.push(self.ErrorMsg(req, reqMsg, portId, 0));
+                }
+            }
+        }
+    }
+
+    fn TryRecv(&self, task: &Task, dsts: &mut [IoVec], flags: i32, senderRequested: bool) -> Result<Option<RecvMsgResult>> {
+        let mut replies = self.replies.lock();
+        let datagram = match replies.front() {
+            None => {
+                if self.overrun.swap(false, Ordering::SeqCst) {
+                    return Err(Error::SysError(SysErr::ENOBUFS))
+                }
+                return Ok(None)
+            }
+            Some(datagram) => datagram,
+        };
+
+        let n = task.CopyDataOutToIovs(datagram, dsts)?;
+        let len = datagram.len();
+        let mut msgFlags = 0;
+        if n < len {
+            msgFlags |= MsgType::MSG_TRUNC;
+        }
+
+        if flags & MsgType::MSG_PEEK == 0 {
+            replies.pop_front();
+        }
+
+        let ret = if flags & MsgType::MSG_TRUNC != 0 { len } else { n };
+        let sender = if senderRequested {
+            let addr = self.Addr(0);
+            let l = addr.Len();
+            Some((SockAddr::Netlink(addr), l))
+        } else {
+            None
+        };
+
+        return Ok(Some((ret as i64, msgFlags, sender, Vec::new())))
+    }
+
+    pub fn Send(&self, task: &Task, buf: &[u8]) -> Result<i64> {
+        let portId = self.PortID(task);
+        let mut datagrams = Vec::new();
+        for (hdr, msg) in ParseMessages(buf) {
+            self.HandleRequest(&hdr, msg, portId, &mut datagrams);
+        }
+
+        if datagrams.len() > 0 {
+            let queued = QueueDatagrams(&mut self.replies.lock(), datagrams, NETLINK_SOCKET_BUF_SIZE as usize);
+            if !queued {
+                self.overrun.store(true, Ordering::SeqCst);
+            }
+            self.queue.Notify(EVENT_IN);
+        }
+
+        return Ok(buf.len() as i64)
+    }
+}
+
+// FindLink returns the RTM_NEWLINK of the link dump with the index, or with the name if index is 0
+pub fn FindLink<'a>(dump: &'a [u8], index: i32, name: Option<&[u8]>) -> Option<&'a [u8]> {
+    for (_, msg) in ParseMessages(dump) {
+        if msg.len() < NLMSG_HDRLEN + IFINFOMSG_SIZE {
+            continue;
+        }
+
+        let matched = if index != 0 {
+            Index(msg) == index
+        } else {
+            FindAttr(msg, NLMSG_HDRLEN + IFINFOMSG_SIZE, IFLA_IFNAME).map(TrimName) == name
+        };
+
+        if matched {
+            return Some(msg)
+        }
+    }
+
+    return None
+}
+
+// LinkIndex returns the index of the guest link with the name
+pub fn LinkIndex(name: &[u8]) -> Result<i32> {
+    let dump = ReadRouteDump(LibcConst::RTM_GETLINK as u16)?;
+    match FindLink(&dump, 0, Some(name)) {
+        None => return Err(Error::SysError(SysErr::ENODEV)),
+        Some(msg) => return Ok(Index(msg)),
+    }
+}
+
+// LinkName returns the name of the guest link with the index
+pub fn LinkName(index: i32) -> Result<Vec<u8>> {
+    let dump = ReadRouteDump(LibcConst::RTM_GETLINK as u16)?;
+    let name = FindLink(&dump, index, None)
+        .and_then(|msg| FindAttr(msg, NLMSG_HDRLEN + IFINFOMSG_SIZE, IFLA_IFNAME))
+        .map(TrimName);
+    match name {
+        None => return Err(Error::SysError(SysErr::ENODEV)),
+        Some(name) => return Ok(name.to_vec()),
+    }
+}
+
+// Index returns ifi_index of the ifinfomsg
+fn Index(msg: &[u8]) -> i32 {
+    let offset = NLMSG_HDRLEN + 4;
+    return i32::from_ne_bytes([msg[offset], msg[offset+1], msg[offset+2], msg[offset+3]])
+}
+
+fn TrimName(name: &[u8]) -> &[u8] {
+    match name.iter().position(|c| *c == 0) {
+        None => name,
+        Some(end) => &name[..end],
+    }
+}
+
+impl Waitable for NetlinkRouteSocket {
+    fn Readiness(&self, _task: &Task, mask: EventMask) -> EventMask {
+        let mut ready = EVENT_OUT;
+        if self.replies.lock().len() > 0 {
+            ready |= EVENT_IN;
+        }
+
+        if self.overrun.load(Ordering::Relaxed) {
+            ready |= EVENT_IN | EVENT_ERR;
+        }
+
+        return mask & ready
+    }
+
+    fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
+        self.queue.EventRegister(task, e, mask)
+    }
+
+    fn EventUnregister(&self, task: &Task, e: &WaitEntry) {
+        self.queue.EventUnregister(task, e)
+    }
+}
+
+impl SpliceOperations for NetlinkRouteSocket {}
+
+impl FileOperations for NetlinkRouteSocket {
+    fn as_any(&self) -> &Any {
+        return self;
+    }
+
+    fn FopsType(&self) -> FileOpsType {
+        return FileOpsType::NetlinkSocketOperations
+    }
+
+    fn Seekable(&self) -> bool {
+        return false;
+    }
+
+    fn Seek(&self, _task: &Task, _f: &File, _whence: i32, _current: i64, _offset: i64) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ESPIPE))
+    }
+
+    fn ReadDir(&self, _task: &Task, _f: &File, _offset: i64, _serializer: &mut DentrySerializer) -> Result<i64> {
+        return Err(Error::SysError(SysErr::ENOTDIR))
+    }
+
+    fn ReadAt(&self, task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, blocking: bool) -> Result<i64> {
+        let flags = if blocking { 0 } else { MsgType::MSG_DONTWAIT };
+        let (n, _, _, _) = self.RecvMsg(task, dsts, flags, None, false, 0)?;
+        return Ok(n)
+    }
+
+    fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+        let mut buf = vec![0; IoVec::NumBytes(srcs)];
+        task.CopyDataInFromIovs(&mut buf, srcs)?;
+        return self.Send(task, &buf)
+    }
+
+    fn Append(&self, task: &Task, f: &File, srcs: &[IoVec]) -> Result<(i64, i64)> {
+        let n = self.WriteAt(task, f, srcs, 0, false)?;
+        return Ok((n, 0))
+    }
+
+    fn Fsync(&self, _task: &Task, _f: &File, _start: i64, _end: i64, _syncType: SyncType) -> Result<()> {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    fn Flush(&self, _task: &Task, _f: &File) -> Result<()> {
+        return Ok(())
+    }
+
+    fn UnstableAttr(&self, task: &Task, f: &File) -> Result<UnstableAttr> {
+        let inode = f.Dirent.Inode();
+        return inode.UnstableAttr(task);
+    }
+
+    fn Ioctl(&self, _task: &Task, _f: &File, _fd: i32, _request: u64, _val: u64) -> Result<()> {
+        return Err(Error::SysError(SysErr::ENOTTY))
+    }
+
+    fn IterateDir(&self, _task: &Task, _d: &Dirent, _dirCtx: &mut DirCtx, _offset: i32) -> (i32, Result<i64>) {
+        return (0, Err(Error::SysError(SysErr::ENOTDIR)))
+    }
+
+    fn Mappable(&self) -> Result<HostInodeOp> {
+        return Err(Error::SysError(SysErr::ENODEV))
+    }
+}
+
+impl SockOperations for NetlinkRouteSocket {
+    // the socket only talks to the kernel, connect is accepted as linux does for port 0
+    fn Connect(&self, task: &Task, socketaddr: &[u8], _blocking: bool) -> Result<i64> {
+        GetAddr(AFType::AF_NETLINK as i16, socketaddr)?;
+        self.PortID(task);
+        return Ok(0)
+    }
+
+    fn Bind(&self, task: &Task, socketaddr: &[u8]) -> Result<i64> {
+        let addr = match GetAddr(AFType::AF_NETLINK as i16, socketaddr)? {
+            SockAddr::Netlink(addr) => addr,
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        };
+
+        // the multicast groups are accepted but no notification is sent
+        if addr.PortID == 0 {
+            self.PortID(task);
+            return Ok(0)
+        }
+
+        match self.portId.compare_exchange(0, addr.PortID, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => return Ok(0),
+            Err(portId) if portId == addr.PortID => return Ok(0),
+            Err(_) => return Err(Error::SysError(SysErr::EINVAL)),
+        }
+    }
+
+    fn GetSockOpt(&self, _task: &Task, level: i32, name: i32, opt: &mut [u8]) -> Result<i64> {
+        if level as u64 != LibcConst::SOL_SOCKET {
+            return Err(Error::SysError(SysErr::ENOPROTOOPT))
+        }
+
+        let val = match name as u64 {
+            LibcConst::SO_TYPE => self.stype,
+            LibcConst::SO_DOMAIN => AFType::AF_NETLINK,
+            LibcConst::SO_PROTOCOL => LibcConst::NETLINK_ROUTE as i32,
+            LibcConst::SO_RCVBUF | LibcConst::SO_SNDBUF => NETLINK_SOCKET_BUF_SIZE,
+            _ => return Err(Error::SysError(SysErr::ENOPROTOOPT)),
+        };
+
+        if opt.len() < SocketSize::SIZEOF_INT32 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        opt[..SocketSize::SIZEOF_INT32].copy_from_slice(&val.to_ne_bytes());
+        return Ok(SocketSize::SIZEOF_INT32 as i64)
+    }
+
+    // the options other than the timeouts don't change the synthesized replies and are ignored
+    fn SetSockOpt(&self, _task: &Task, level: i32, name: i32, opt: &[u8]) -> Result<i64> {
+        if level as u64 == LibcConst::SOL_SOCKET
+            && (name as u64 == LibcConst::SO_RCVTIMEO || name as u64 == LibcConst::SO_SNDTIMEO) {
+            if opt.len() < SocketSize::SIZEOF_TIMEVAL {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            let timeVal = unsafe {
+                *(&opt[0] as *const _ as u64 as *const Timeval)
+            };
+
+            if name as u64 == LibcConst::SO_RCVTIMEO {
+                self.SetRecvTimeout(timeVal.ToDuration() as i64);
+            } else {
+                self.SetSendTimeout(timeVal.ToDuration() as i64);
+            }
+        }
+
+        return Ok(0)
+    }
+
+    fn GetSockName(&self, task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        let addr = self.Addr(self.PortID(task));
+        let l = addr.Len();
+        SockAddr::Netlink(addr).Marsh(socketaddr, l)?;
+        return Ok(l as i64)
+    }
+
+    fn GetPeerName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        let addr = self.Addr(0);
+        let l = addr.Len();
+        SockAddr::Netlink(addr).Marsh(socketaddr, l)?;
+        return Ok(l as i64)
+    }
+
+    fn RecvMsg(&self, task: &Task, dsts: &mut [IoVec], flags: i32, deadline: Option<Time>, senderRequested: bool, _controlDataLen: usize)
+               -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)> {
+        match self.TryRecv(task, dsts, flags, senderRequested)? {
+            Some(res) => return Ok(res),
+            None => (),
+        }
+
+        if flags & MsgType::MSG_DONTWAIT != 0 {
+            return Err(Error::SysError(SysErr::EWOULDBLOCK))
+        }
+
+        let general = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &general, EVENT_IN);
+        defer!(self.EventUnregister(task, &general));
+
+        loop {
+            // check the queue again after the registration to not miss the notification
+            match self.TryRecv(task, dsts, flags, senderRequested)? {
+                Some(res) => return Ok(res),
+                None => (),
+            }
+
+            match task.blocker.BlockWithMonoTimer(true, deadline) {
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                    return Err(Error::SysError(SysErr::EAGAIN));
+                }
+                Err(Error::ErrInterrupted) => {
+                    return Err(Error::SysError(SysErr::ERESTARTSYS));
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+                _ => ()
+            }
+        }
+    }
+
+    // the messages always go to the kernel, the other netlink ports are not reachable in the guest
+    fn SendMsg(&self, task: &Task, srcs: &[IoVec], _flags: i32, _msgHdr: &mut MsgHdr, _deadline: Option<Time>) -> Result<i64> {
+        let mut buf = vec![0; IoVec::NumBytes(srcs)];
+        task.CopyDataInFromIovs(&mut buf, srcs)?;
+        return self.Send(task, &buf)
+    }
+
+    fn SetRecvTimeout(&self, ns: i64) {
+        self.recv.store(ns, Ordering::Relaxed)
+    }
+
+    fn SetSendTimeout(&self, ns: i64) {
+        self.send.store(ns, Ordering::Relaxed)
+    }
+
+    fn RecvTimeout(&self) -> i64 {
+        return self.recv.load(Ordering::Relaxed)
+    }
+
+    fn SendTimeout(&self) -> i64 {
+        return self.send.load(Ordering::Relaxed)
+    }
+}
+
+pub struct NetlinkRouteProvider {}
+
+impl Provider for NetlinkRouteProvider {
+    // the other netlink protocols and the disabled config fall through to the host provider
+    fn Socket(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<Arc<File>>> {
+        if protocol as u64 != LibcConst::NETLINK_ROUTE || !SHARESPACE.config.read().NetlinkRouteInGuest {
+            return Ok(None)
+        }
+
+        if stype != SocketType::SOCK_RAW && stype != SocketType::SOCK_DGRAM {
+            return Err(Error::SysError(SysErr::ESOCKTNOSUPPORT))
+        }
+
+        return Ok(Some(Arc::new(NewNetlinkRouteSocket(task, stype)?)))
+    }
+
+    fn Pair(&self, _task: &Task, _stype: i32, _protocol: i32) -> Result<Option<(Arc<File>, Arc<File>)>> {
+        return Ok(None)
+    }
+}
+
+pub fn Init() {
+    FAMILIAES.write().RegisterProvider(AFType::AF_NETLINK, Box::new(NetlinkRouteProvider {}))
+}
@@ -43,11 +43,21 @@ pub trait Provider: Send + Sync {
     fn Pair(&self, task: &Task, stype: i32, protocol: i32) -> Result<Option<(Arc<File>, Arc<File>)>>;
 }
 
+// the socket providers are registered by the first socket call instead of boot,
+// the app which doesn't use socket doesn't pay for them
+static PROVIDERS_INIT: spin::Once<()> = spin::Once::new();
+
+pub fn InitProviders() {
+    PROVIDERS_INIT.call_once(|| super::Init());
+}
+
 pub fn NewSocket(task: &Task, family: i32, stype: i32, protocol: i32) -> Result<Arc<File>> {
+    InitProviders();
     return FAMILIAES.read().NewSocket(task, family, stype, protocol)
 }
 
 pub fn NewPair(task: &Task, family: i32, stype: i32, protocol: i32) -> Result<(Arc<File>, Arc<File>)> {
+    InitProviders();
     return FAMILIAES.read().NewPair(task, family, stype, protocol)
 }
 
@@ -28,6 +28,7 @@ use super::super::super::super::super::linux::socket::*;
 use super::super::super::super::super::linux_def::*;
 use super::super::super::super::task::*;
 use super::super::super::super::uid::*;
+use super::super::super::super::super::auth::id::*;
 //use super::super::super::control::*;
 use super::unix::*;
 use super::queue::*;
@@ -70,6 +71,27 @@ pub trait ConnectingEndpoint : PartialEndPoint {
     fn WaiterQueue(&self) -> Queue;
 }
 
+// PeerCred is the credentials reported by SO_PEERCRED. As linux does, it is taken from the task
+// which listened on the socket, connected it or created the socket pair.
+#[derive(Clone, Copy, Debug)]
+pub struct PeerCred {
+    pub pid: i32,
+    pub uid: KUID,
+    pub gid: KGID,
+}
+
+impl PeerCred {
+    pub fn New(task: &Task) -> Self {
+        let creds = task.Creds();
+        let creds = creds.lock();
+        return Self {
+            pid: task.Thread().ThreadGroup().ID(),
+            uid: creds.EffectiveKUID,
+            gid: creds.EffectiveKGID,
+        }
+    }
+}
+
 pub struct ConnectionedEndPointInternal {
     pub baseEndpoint: BaseEndpoint,
 
@@ -91,6 +113,13 @@ pub struct ConnectionedEndPointInternal {
     //
     // If nil, then no listen call has been made.
     pub acceptedChan: Option<BufChan<ConnectionedEndPoint>>,
+
+    // cred is the credentials of the task which called listen, the connecting sockets get it
+    // as their peer credentials
+    pub cred: Option<PeerCred>,
+
+    // peerCred is the credentials of the peer, None before the socket is connected
+    pub peerCred: Option<PeerCred>,
 }
 
 impl ConnectionedEndPointInternal {
@@ -157,6 +186,8 @@ impl ConnectionedEndPoint {
             stype: stype,
             backlog: 0,
             acceptedChan: None,
+            cred: None,
+            peerCred: None,
         };
 
         return Self(Arc::new((QMutex::new(internal), QMutex::new(()))))
@@ -181,6 +212,8 @@ impl ConnectionedEndPoint {
             stype: stype,
             backlog: 0,
             acceptedChan: None,
+            cred: None,
+            peerCred: None,
         };
 
         return Self(Arc::new((QMutex::new(internal), QMutex::new(()))))
@@ -228,11 +261,31 @@ impl ConnectionedEndPoint {
             stype: stype,
             backlog: 0,
             acceptedChan: None,
+            cred: None,
+            peerCred: None,
         };
 
         return Self(Arc::new((QMutex::new(internal), QMutex::new(()))))
     }
 
+    pub fn SetCred(&self, cred: PeerCred) {
+        self.lock().cred = Some(cred);
+    }
+
+    pub fn SetPeerCred(&self, cred: PeerCred) {
+        self.lock().peerCred = Some(cred);
+    }
+
+    // PeerCred returns the credentials of the peer, the listening socket has its own ones
+    pub fn PeerCred(&self) -> Option<PeerCred> {
+        let e = self.lock();
+        if e.Listening() {
+            return e.cred
+        }
+
+        return e.peerCred
+    }
+
     pub fn TryLock(&self) -> Option<QMutexGuard<()>> {
         return (self.0).1.try_lock()
     }
@@ -289,6 +342,7 @@ impl ConnectionedEndPoint {
         baseEndPoint.lock().path = self.lock().baseEndpoint.lock().path.to_string();
         let stype = self.lock().stype;
         let ne = ConnectionedEndPoint::NewWithBaseEndpoint(baseEndPoint, stype);
+        ne.SetPeerCred(PeerCred::New(task));
 
         let readq = ce.WaiterQueue();
         let writeq = ne.lock().baseEndpoint.lock().queue.clone();
@@ -473,7 +527,15 @@ impl Endpoint for ConnectionedEndPoint {
             self.lock().baseEndpoint.lock().connected = Some(ce);
         };
 
-        return server.BidirectionalConnect(task, Arc::new(self.clone()), returnConnect)
+        server.BidirectionalConnect(task, Arc::new(self.clone()), returnConnect)?;
+
+        // the peer of the connecting socket is the task which listened on the server
+        if let BoundEndpoint::Connected(ref s) = server {
+            let cred = s.lock().cred;
+            self.lock().peerCred = cred;
+        }
+
+        return Ok(())
     }
 
     // Listen starts listening on the connection.
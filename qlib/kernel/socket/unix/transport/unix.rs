@@ -248,6 +248,18 @@ impl BoundEndpoint {
         }
     }
 
+    // PeerCred returns the SO_PEERCRED credentials, the connectionless endpoints have none
+    pub fn PeerCred(&self) -> Option<PeerCred> {
+        match self {
+            BoundEndpoint::Connected(ref c) => {
+                return c.PeerCred()
+            }
+            BoundEndpoint::ConnectLess(_) => {
+                return None
+            }
+        }
+    }
+
     pub fn UnidirectionalConnect(&self) -> Result<UnixConnectedEndpoint> {
         match self {
             BoundEndpoint::Connected(ref c) => {
@@ -32,6 +32,7 @@ use super::super::super::fs::dirent::*;
 //use super::super::super::fs::attr::*;
 use super::super::super::fs::host::hostinodeop::*;
 use super::super::super::kernel::fd_table::*;
+use super::super::super::guestfdnotifier::*;
 use super::super::super::kernel::abstract_socket_namespace::*;
 use super::super::super::kernel::waiter::*;
 use super::super::super::kernel::time::*;
@@ -46,11 +47,16 @@ use super::super::super::super::path::*;
 use super::super::super::Kernel::HostSpace;
 //use super::super::super::fd::*;
 use super::super::super::tcpip::tcpip::*;
+use super::super::super::tcpip::sockaddr::SockAddrFamily;
+use super::super::super::SHARESPACE;
+use super::super::super::super::config::UnixAbstractMode;
 use super::transport::unix::*;
 use super::transport::connectioned::*;
 use super::transport::connectionless::*;
 use super::super::super::socket::control::*;
 use super::super::super::socket::epsocket::epsocket::*;
+use super::super::hostinet::socket::SocketOperations as HostSocketOperations;
+use super::super::hostinet::socket::SocketBufType;
 
 pub fn NewUnixSocket(task: &Task, ep: BoundEndpoint, stype: i32, hostfd: i32) -> Result<File> {
     //assert!(family == AFType::AF_UNIX, "NewUnixSocket family is not AF_UNIX");
@@ -61,7 +67,25 @@ pub fn NewUnixSocket(task: &Task, ep: BoundEndpoint, stype: i32, hostfd: i32) ->
         ..Default::default()
     };
 
-    return Ok(File::New(&dirent, &fileFlags, UnixSocketOperations::New(ep, stype, hostfd)))
+    let inode = dirent.Inode();
+    let iops = inode.lock().InodeOp.clone();
+    let hostops = iops.as_any().downcast_ref::<HostInodeOp>().unwrap().clone();
+    return Ok(File::New(&dirent, &fileFlags, UnixSocketOperations::New(ep, stype, hostfd, hostops)))
+}
+
+// HostProxy is the shadow host socket which serves the abstract names of the Host mode: the
+// connection to a name which is not bound in the sandbox goes through it, and the listener
+// bound to a name accepts the host connections with it besides the guest ones
+#[derive(Clone)]
+pub enum HostProxy {
+    Connected(HostSocketOperations),
+    Listening(HostSocketOperations),
+}
+
+enum Accepted {
+    Guest(ConnectionedEndPoint),
+    // the fd of the host connection installed by the host listener
+    Host(i64),
 }
 
 pub struct UnixSocketOperations {
@@ -71,10 +95,12 @@ pub struct UnixSocketOperations {
     pub recv: AtomicI64,
     pub name: QMutex<Option<Vec<u8>>>,
     pub hostfd: i32,
+    pub hostops: HostInodeOp,
+    pub host: QMutex<Option<HostProxy>>,
 }
 
 impl UnixSocketOperations {
-    pub fn New(ep: BoundEndpoint, stype: i32, hostfd: i32) -> Self {
+    pub fn New(ep: BoundEndpoint, stype: i32, hostfd: i32, hostops: HostInodeOp) -> Self {
         let ret = Self {
             ep: ep,
             stype: stype,
@@ -82,6 +108,8 @@ impl UnixSocketOperations {
             recv: AtomicI64::new(0),
             name: QMutex::new(None),
             hostfd: hostfd,
+            hostops: hostops,
+            host: QMutex::new(None),
         };
 
         return ret;
@@ -214,15 +242,26 @@ impl ConnectedPasscred  for UnixSocketOperations {
 
 impl Waitable for UnixSocketOperations {
     fn Readiness(&self, task: &Task, mask: EventMask) -> EventMask {
-        self.ep.Readiness(task, mask)
+        match self.HostProxy() {
+            None => self.ep.Readiness(task, mask),
+            Some(HostProxy::Connected(host)) => host.Readiness(task, mask),
+            Some(HostProxy::Listening(host)) => self.ep.Readiness(task, mask) | host.Readiness(task, mask),
+        }
     }
 
+    // the host socket shares the queue of the endpoint, it updates the host events of the fd too
     fn EventRegister(&self, task: &Task, e: &WaitEntry, mask: EventMask) {
-        self.ep.EventRegister(task, e, mask)
+        match self.HostProxy() {
+            None => self.ep.EventRegister(task, e, mask),
+            Some(HostProxy::Connected(host)) | Some(HostProxy::Listening(host)) => host.EventRegister(task, e, mask),
+        }
     }
 
     fn EventUnregister(&self, task: &Task,e: &WaitEntry) {
-        self.ep.EventUnregister(task, e)
+        match self.HostProxy() {
+            None => self.ep.EventUnregister(task, e),
+            Some(HostProxy::Connected(host)) | Some(HostProxy::Listening(host)) => host.EventUnregister(task, e),
+        }
     }
 }
 
@@ -248,6 +287,192 @@ pub fn ExtractPath(sockAddr: &[u8]) -> Result<Vec<u8>> {
     return Ok(p);
 }
 
+// IsAbstract returns whether the path is of the abstract namespace, which starts with a nul
+pub fn IsAbstract(path: &[u8]) -> bool {
+    return path.len() > 0 && path[0] == 0
+}
+
+// HostAbstractName returns the abstract name of the address when the names are of the host
+pub fn HostAbstractName(sockAddr: &[u8]) -> Option<Vec<u8>> {
+    if SHARESPACE.config.read().UnixAbstract != UnixAbstractMode::Host {
+        return None
+    }
+
+    match ExtractPath(sockAddr) {
+        Ok(path) if IsAbstract(&path) => return Some(path),
+        _ => return None,
+    }
+}
+
+// HostAbstractAddr returns the host address of the abstract name and its length, which is
+// exact as the name is all the bytes after the family
+pub fn HostAbstractAddr(name: &[u8]) -> (SockAddrUnixNative, u32) {
+    let mut addr = SockAddrUnixNative {
+        Family: AFType::AF_UNIX as u16,
+        Path: [0; UNIX_PATH_MAX],
+    };
+    addr.Path[..name.len()].copy_from_slice(name);
+    return (addr, (name.len() + 2) as u32)
+}
+
+impl UnixSocketOperations {
+    // BindAbstract binds the socket to the abstract name, the name is taken in the guest
+    // namespace first so the binds of the same name in the sandbox collide without the host
+    pub fn BindAbstract(&self, task: &Task, name: Vec<u8>) -> Result<()> {
+        // GetAddr has checked the path is a string
+        let addr = SockAddrUnix::New(core::str::from_utf8(&name).map_err(|_| Error::SysError(SysErr::EINVAL))?);
+
+        Bind(name.clone(), &self.ep)?;
+        let res = match self.BindHostAbstract(task, &name) {
+            Ok(()) => self.ep.Bind(&addr),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = res {
+            // the name is still held by the live endpoint, nobody else has replaced it
+            Unbind(&name);
+            return Err(e)
+        }
+
+        *(self.name.lock()) = Some(name);
+        return Ok(())
+    }
+
+    // BindHostAbstract binds the shadow host socket to the abstract name in the Host mode
+    fn BindHostAbstract(&self, task: &Task, name: &Vec<u8>) -> Result<()> {
+        if SHARESPACE.config.read().UnixAbstract != UnixAbstractMode::Host {
+            return Ok(())
+        }
+
+        let (addr, len) = HostAbstractAddr(name);
+        let ret = HostSpace::Bind(self.hostfd, &addr as *const _ as u64, len, task.Umask());
+        if ret < 0 {
+            return Err(Error::SysError(-ret as i32))
+        }
+
+        return Ok(())
+    }
+
+    pub fn HostProxy(&self) -> Option<HostProxy> {
+        return self.host.lock().clone()
+    }
+
+    // HostConnected returns the host socket when the socket is connected to a host abstract name
+    fn HostConnected(&self) -> Option<HostSocketOperations> {
+        match self.HostProxy() {
+            Some(HostProxy::Connected(host)) => return Some(host),
+            _ => return None,
+        }
+    }
+
+    // HostBound returns whether the socket is bound to an abstract name on the host too
+    fn HostBound(&self) -> bool {
+        if SHARESPACE.config.read().UnixAbstract != UnixAbstractMode::Host {
+            return false
+        }
+
+        match &*self.name.lock() {
+            Some(name) => return IsAbstract(name),
+            None => return false,
+        }
+    }
+
+    // NewHostProxy makes the host socket over the shadow host socket. Its events go to the
+    // queue of the endpoint, so the waiters registered before keep working.
+    fn NewHostProxy(&self) -> Result<HostSocketOperations> {
+        let queue = match &self.ep {
+            BoundEndpoint::Connected(ep) => ep.WaiterQueue(),
+            // the datagrams to the host names are not proxied
+            BoundEndpoint::ConnectLess(_) => return Err(Error::SysError(SysErr::ECONNREFUSED)),
+        };
+
+        MoveFD(self.hostfd, &queue);
+        let host = HostSocketOperations::New(AFType::AF_UNIX, self.hostfd, self.stype, queue,
+                                             self.hostops.clone(), SocketBufType::NoTCP, None)?;
+        UpdateFD(self.hostfd)?;
+        return Ok(host)
+    }
+
+    // ConnectHostAbstract connects the shadow host socket to the abstract name bound by a host
+    // process or another sandbox, the socket is served by the host socket from then on
+    fn ConnectHostAbstract(&self, task: &Task, socketaddr: &[u8], blocking: bool) -> Result<i64> {
+        let host = self.NewHostProxy()?;
+        let res = host.Connect(task, socketaddr, blocking);
+        match res {
+            Ok(_) | Err(Error::SysError(SysErr::EINPROGRESS)) => {
+                *self.host.lock() = Some(HostProxy::Connected(host));
+            }
+            _ => (),
+        }
+
+        return res
+    }
+
+    // AcceptEndpoint takes a connection of the guest endpoint, or one of the host listener of
+    // the abstract name when there is no guest one
+    fn AcceptEndpoint(&self, task: &Task, addr: &mut [u8], addrlen: &mut u32, flags: i32, blocking: bool) -> Result<Accepted> {
+        let host = match self.HostProxy() {
+            Some(HostProxy::Listening(host)) => host,
+            _ => {
+                let ep = match self.ep.Accept() {
+                    Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
+                        if !blocking {
+                            return Err(Error::SysError(SysErr::EWOULDBLOCK));
+                        }
+
+                        self.BlockingAccept(task)?
+                    }
+                    Err(e) => return Err(e),
+                    Ok(ep) => ep,
+                };
+
+                return Ok(Accepted::Guest(ep))
+            }
+        };
+
+        let entry = task.blocker.generalEntry.clone();
+        self.EventRegister(task, &entry, EVENT_IN);
+        defer!(self.EventUnregister(task, &entry));
+
+        loop {
+            match self.ep.Accept() {
+                Ok(ep) => return Ok(Accepted::Guest(ep)),
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => (),
+                Err(e) => return Err(e),
+            }
+
+            match host.Accept(task, addr, addrlen, flags, false) {
+                Err(Error::SysError(SysErr::EWOULDBLOCK)) => (),
+                res => return Ok(Accepted::Host(res?)),
+            }
+
+            if !blocking {
+                return Err(Error::SysError(SysErr::EWOULDBLOCK))
+            }
+
+            task.blocker.BlockGeneral()?;
+        }
+    }
+
+    // AutoBind binds the socket to an unused abstract name as linux does for the bind with only
+    // the family
+    pub fn AutoBind(&self, task: &Task) -> Result<()> {
+        // linux leaves the bound socket as it is
+        if self.ep.GetLocalAddress()?.Path.len() != 0 {
+            return Ok(())
+        }
+
+        for _ in 0..AUTOBIND_NAMES {
+            match self.BindAbstract(task, NextAutoBindName()) {
+                Err(Error::SysError(SysErr::EADDRINUSE)) => continue,
+                res => return res,
+            }
+        }
+
+        return Err(Error::SysError(SysErr::ENOSPC))
+    }
+}
+
 impl SpliceOperations for UnixSocketOperations {}
 
 impl FileOperations for UnixSocketOperations {
@@ -271,7 +496,11 @@ impl FileOperations for UnixSocketOperations {
         return Err(Error::SysError(SysErr::ENOTDIR))
     }
 
-    fn ReadAt(&self, _task: &Task, _f: &File, dsts: &mut [IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+    fn ReadAt(&self, task: &Task, f: &File, dsts: &mut [IoVec], offset: i64, blocking: bool) -> Result<i64> {
+        if let Some(host) = self.HostConnected() {
+            return host.ReadAt(task, f, dsts, offset, blocking)
+        }
+
         let count = IoVec::NumBytes(dsts);
 
         if count == 0 {
@@ -293,7 +522,11 @@ impl FileOperations for UnixSocketOperations {
         }
     }
 
-    fn WriteAt(&self, task: &Task, _f: &File, srcs: &[IoVec], _offset: i64, _blocking: bool) -> Result<i64> {
+    fn WriteAt(&self, task: &Task, f: &File, srcs: &[IoVec], offset: i64, blocking: bool) -> Result<i64> {
+        if let Some(host) = self.HostConnected() {
+            return host.WriteAt(task, f, srcs, offset, blocking)
+        }
+
         let ctrl = if self.ep.ConnectedPasscred() || self.ep.Passcred() {
             NewControlMessage(task, Some(self.ep.clone()), None)
         } else {
@@ -353,7 +586,7 @@ pub fn ExtractEndpoint(task: &Task, sockAddr: &[u8]) -> Result<BoundEndpoint> {
     //info!("unix socket path is {}", String::from_utf8(path.to_vec()).unwrap());
 
     // Is it abstract?
-    if path[0] == 0 {
+    if IsAbstract(&path) {
         let ep = match BoundEndpoint(&path) {
             None => return Err(Error::SysError(SysErr::ECONNREFUSED)),
             Some(ep) => ep,
@@ -396,8 +629,18 @@ pub fn ExtractEndpoint(task: &Task, sockAddr: &[u8]) -> Result<BoundEndpoint> {
 }
 
 impl SockOperations for UnixSocketOperations {
-    fn Connect(&self, task: &Task, socketaddr: &[u8], _blocking: bool) -> Result<i64> {
-        let ep = ExtractEndpoint(task, socketaddr)?;
+    fn Connect(&self, task: &Task, socketaddr: &[u8], blocking: bool) -> Result<i64> {
+        if let Some(host) = self.HostConnected() {
+            return host.Connect(task, socketaddr, blocking)
+        }
+
+        let ep = match ExtractEndpoint(task, socketaddr) {
+            // the name is not bound in the sandbox, it may be bound on the host
+            Err(Error::SysError(SysErr::ECONNREFUSED)) if HostAbstractName(socketaddr).is_some() => {
+                return self.ConnectHostAbstract(task, socketaddr, blocking)
+            }
+            res => res?,
+        };
 
         // Connect the server endpoint.
         match self.ep.Connect(task, &ep) {
@@ -405,7 +648,7 @@ impl SockOperations for UnixSocketOperations {
                 // Linux for abstract sockets returns ErrConnectionRefused
                 // instead of ErrWrongProtocolForSocket.
                 let path = ExtractPath(socketaddr)?;
-                if path[0] == 0 {
+                if IsAbstract(&path) {
                     return Err(Error::SysError(SysErr::ECONNREFUSED))
                 } else {
                     return Err(Error::SysError(SysErr::EPROTOTYPE))
@@ -420,16 +663,9 @@ impl SockOperations for UnixSocketOperations {
     // Accept implements the linux syscall accept(2) for sockets backed by
     // a transport.Endpoint.
     fn Accept(&self, task: &Task, addr: &mut [u8], addrlen: &mut u32, flags: i32, blocking: bool) -> Result<i64> {
-        let ep = match self.ep.Accept() {
-            Err(Error::SysError(SysErr::EWOULDBLOCK)) => {
-                if !blocking {
-                    return Err(Error::SysError(SysErr::EWOULDBLOCK));
-                }
-
-                self.BlockingAccept(task)?
-            }
-            Err(e) => return Err(e),
-            Ok(ep) => ep,
+        let ep = match self.AcceptEndpoint(task, addr, addrlen, flags, blocking)? {
+            Accepted::Guest(ep) => ep,
+            Accepted::Host(fd) => return Ok(fd),
         };
 
         let ep = BoundEndpoint::Connected(ep);
@@ -460,21 +696,31 @@ impl SockOperations for UnixSocketOperations {
     }
 
     fn Bind(&self, task: &Task, socketaddr: &[u8]) -> Result<i64> {
+        // the address with only the family asks for an autobind
+        if socketaddr.len() == 2 {
+            if SockAddrFamily(socketaddr)? != AFType::AF_UNIX as u16 {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            self.AutoBind(task)?;
+            return Ok(0)
+        }
+
         let p = ExtractPath(socketaddr)?;
 
         info!("Bind p is {:?}", &p);
         let bep = self.ep.clone();
 
-        let addr = SockAddrUnix::New(core::str::from_utf8(&p).expect("Bind to string fail"));
-        self.ep.Bind(&addr)?;
-
         let root = task.fsContext.RootDirectory();
 
         // Is it abstract?
-        if p[0] == 0 {
-            Bind(p.clone(), &bep)?;
-            *(self.name.lock()) = Some(p);
+        if IsAbstract(&p) {
+            // the endpoint is bound after the name is taken
+            self.BindAbstract(task, p)?;
         } else {
+            let addr = SockAddrUnix::New(core::str::from_utf8(&p).expect("Bind to string fail"));
+            self.ep.Bind(&addr)?;
+
             let p = String::from_utf8(p).unwrap();
             info!("bind address is {}", &p);
 
@@ -539,12 +785,30 @@ impl SockOperations for UnixSocketOperations {
         return Ok(0)
     }
 
-    fn Listen(&self, _task: &Task, backlog: i32) -> Result<i64> {
+    fn Listen(&self, task: &Task, backlog: i32) -> Result<i64> {
         self.ep.Listen(backlog)?;
+        if let BoundEndpoint::Connected(ref c) = self.ep {
+            c.SetCred(PeerCred::New(task));
+        }
+
+        // the abstract name bound on the host takes the host connections too
+        if self.HostBound() {
+            let host = match self.HostProxy() {
+                Some(HostProxy::Listening(host)) => host,
+                _ => self.NewHostProxy()?,
+            };
+
+            host.Listen(task, backlog)?;
+            *self.host.lock() = Some(HostProxy::Listening(host));
+        }
         return Ok(0);
     }
 
-    fn Shutdown(&self, _task: &Task, how: i32) -> Result<i64> {
+    fn Shutdown(&self, task: &Task, how: i32) -> Result<i64> {
+        if let Some(host) = self.HostConnected() {
+            return host.Shutdown(task, how)
+        }
+
         let f = ConvertShutdown(how)?;
 
         self.ep.Shutdown(f)?;
@@ -552,6 +816,13 @@ impl SockOperations for UnixSocketOperations {
     }
 
     fn GetSockOpt(&self, task: &Task, level: i32, name: i32, opt: &mut [u8]) -> Result<i64> {
+        // the peer of the socket connected to a host abstract name is the host one
+        if level == SOL_SOCKET && (name == SO_PEERCRED || name == SO_PEERSEC) {
+            if let Some(host) = self.HostConnected() {
+                return host.GetSockOpt(task, level, name, opt)
+            }
+        }
+
         let ret = GetSockOpt(task, self, &self.ep, AFType::AF_UNIX, self.ep.Type(), level, name, opt.len())?;
         let size = ret.Marsh(opt)?;
         return Ok(size as i64)
@@ -571,7 +842,11 @@ impl SockOperations for UnixSocketOperations {
         return Ok(l as i64)
     }
 
-    fn GetPeerName(&self, _task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+    fn GetPeerName(&self, task: &Task, socketaddr: &mut [u8]) -> Result<i64> {
+        if let Some(host) = self.HostConnected() {
+            return host.GetPeerName(task, socketaddr)
+        }
+
         let addr = self.ep.GetRemoteAddress()?;
 
         let l = addr.Len();
@@ -582,6 +857,10 @@ impl SockOperations for UnixSocketOperations {
 
     fn RecvMsg(&self, task: &Task, dsts: &mut [IoVec], flags: i32, deadline: Option<Time>, senderRequested: bool, controlDataLen: usize)
                -> Result<(i64, i32, Option<(SockAddr, usize)>, Vec<u8>)>  {
+        if let Some(host) = self.HostConnected() {
+            return host.RecvMsg(task, dsts, flags, deadline, senderRequested, controlDataLen)
+        }
+
         let trunc = flags & MsgType::MSG_TRUNC != 0;
         let peek = flags & MsgType::MSG_PEEK != 0;
         let dontWait = flags & MsgType::MSG_DONTWAIT != 0;
@@ -617,7 +896,12 @@ impl SockOperations for UnixSocketOperations {
         let mut outputctrls = SCMControlMessages::default();
         let mut ControlVec = self.encodeControlMsg(task, outputctrls, controlDataLen, &mut msgFlags, cloexec);
         let size = IoVec::NumBytes(dsts);
-        let buf = DataBuff::New(size);
+        // the message of the SOCK_DGRAM and SOCK_SEQPACKET socket can't be cut
+        let buf = if self.IsPacket() {
+            DataBuff::NewUser(size)?
+        } else {
+            DataBuff::NewUserIO(size)?
+        };
 
         let mut bs = BlockSeqToIoVecs(buf.BlockSeq());
         match self.ep.RecvMsg(&mut bs, wantCreds, numRights as u64, peek, Some(&mut unixAddr)) {
@@ -757,6 +1041,10 @@ impl SockOperations for UnixSocketOperations {
     }
 
     fn SendMsg(&self, task: &Task, srcs: &[IoVec], flags: i32, msgHdr: &mut MsgHdr, deadline: Option<Time>) -> Result<i64> {
+        if let Some(host) = self.HostConnected() {
+            return host.SendMsg(task, srcs, flags, msgHdr, deadline)
+        }
+
         let to: Vec<u8> = if msgHdr.msgName != 0 {
             if self.stype == SockType::SOCK_SEQPACKET {
                 Vec::new()
@@ -795,7 +1083,7 @@ impl SockOperations for UnixSocketOperations {
         let scmCtrlMsg = ctrlMsg.ToSCMUnix(task, &self.ep, &toEp)?;
 
         let size = IoVec::NumBytes(srcs);
-        let mut buf = DataBuff::New(size);
+        let mut buf = DataBuff::NewUser(size)?;
         task.CopyDataInFromIovs(&mut buf.buf, srcs)?;
         let n = match self.ep.SendMsg(&buf.Iovs(), &scmCtrlMsg, &toEp) {
             Err(Error::SysError(SysErr::EAGAIN)) => {
@@ -930,6 +1218,9 @@ impl Provider for UnixSocketProvider {
 
         // Create the endpoints and sockets.
         let (ep1, ep2) = ConnectionedEndPoint::NewPair(stype, fd1, fd2);
+        let cred = PeerCred::New(task);
+        ep1.SetPeerCred(cred);
+        ep2.SetPeerCred(cred);
         let ep1 = BoundEndpoint::Connected(ep1);
         let ep2 = BoundEndpoint::Connected(ep2);
         let s1 = NewUnixSocket(task, ep1, stype, fd1)?;
@@ -941,4 +1232,40 @@ impl Provider for UnixSocketProvider {
 
 pub fn Init() {
     FAMILIAES.write().RegisterProvider(AFType::AF_UNIX, Box::new(UnixSocketProvider { }))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::super::test_util;
+    use super::super::super::super::super::config::Config;
+
+    fn UnixAddr(path: &[u8]) -> Vec<u8> {
+        let mut addr = (AFType::AF_UNIX as u16).to_le_bytes().to_vec();
+        addr.extend_from_slice(path);
+        return addr
+    }
+
+    #[test]
+    fn test_host_abstract_name() {
+        let _l = test_util::Lock();
+        let mut config = Config::default();
+        test_util::SetConfig(config);
+        assert_eq!(HostAbstractName(&UnixAddr(b"\0bus")), None);
+
+        // only the abstract names go to the host
+        config.UnixAbstract = UnixAbstractMode::Host;
+        test_util::SetConfig(config);
+        assert_eq!(HostAbstractName(&UnixAddr(b"\0bus")), Some(b"\0bus".to_vec()));
+        assert_eq!(HostAbstractName(&UnixAddr(b"/run/bus")), None);
+
+        test_util::SetConfig(Config::default());
+    }
+
+    #[test]
+    fn test_host_abstract_addr() {
+        let (addr, len) = HostAbstractAddr(b"\0bus");
+        assert_eq!(len, 6);
+        assert_eq!(addr.Family, AFType::AF_UNIX as u16);
+        assert_eq!(&addr.Path[..5], b"\0bus\0");
+    }
+}
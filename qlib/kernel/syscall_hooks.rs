@@ -0,0 +1,65 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// the max number of the syscall hooks registered at the same time
+pub const MAX_SYSCALL_HOOKS: usize = 8;
+
+// EnteredHooks records the hooks whose Enter has run in one syscall, only they get the Exit
+// call. A hook registered during the syscall or behind the hook failing the Enter didn't enter.
+#[derive(Debug, Default)]
+pub struct EnteredHooks {
+    names: [&'static str; MAX_SYSCALL_HOOKS],
+    cnt: usize,
+}
+
+impl EnteredHooks {
+    pub fn Enter(&mut self, name: &'static str) {
+        assert!(self.cnt < MAX_SYSCALL_HOOKS, "EnteredHooks: too many hooks");
+        self.names[self.cnt] = name;
+        self.cnt += 1;
+    }
+
+    pub fn Entered(&self, name: &str) -> bool {
+        return self.names[..self.cnt].iter().any(|n| *n == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entered_hooks() {
+        let mut entered = EnteredHooks::default();
+        assert!(!entered.Entered("errctx"));
+
+        entered.Enter("errctx");
+        entered.Enter("compat");
+        assert!(entered.Entered("errctx"));
+        assert!(entered.Entered("compat"));
+
+        // the hook behind the failing one or registered later didn't enter
+        assert!(!entered.Entered("audit"));
+        assert!(!entered.Entered(""));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_entered_hooks_overflow() {
+        let mut entered = EnteredHooks::default();
+        for _ in 0..MAX_SYSCALL_HOOKS + 1 {
+            entered.Enter("hook");
+        }
+    }
+}
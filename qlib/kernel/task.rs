@@ -35,6 +35,7 @@ use super::super::task_mgr::*;
 use super::super::perf_tunning::*;
 use super::kernel::time::*;
 use super::super::usage::io::*;
+use super::super::mem::user_buf_pool::*;
 use super::fs::dirent::*;
 use super::kernel::uts_namespace::*;
 use super::kernel::ipc_namespace::*;
@@ -46,6 +47,7 @@ use super::threadmgr::task_sched::*;
 use super::threadmgr::thread::*;
 use super::kernel::waiter::*;
 use super::kernel::futex::*;
+use super::kernel::epoll::epoll_ring::*;
 use super::kernel::timer::*;
 use super::memmgr::mm::*;
 use super::perflog::*;
@@ -53,6 +55,7 @@ use super::perflog::*;
 use super::fs::file::*;
 use super::fs::mount::*;
 use super::kernel::fs_context::*;
+use super::kernel::deterministic::*;
 
 use super::asm::*;
 use super::super::singleton::*;
@@ -188,17 +191,27 @@ pub struct Task {
 
     pub mountNS: MountNs,
     pub blocker: Blocker,
+    // the ready entries of the epoll the task waits on, see EpollRing
+    pub epollRing: Arc<TaskEpollRing>,
 
     pub thread: Option<Thread>,
     pub haveSyscallReturn: bool,
     pub syscallRestartBlock: Option<Box<SyscallRestartBlock>>,
     pub futexMgr: FutexMgr,
     pub ioUsage: IO,
+    // the user buf charge of the thread group, cached as ioUsage
+    pub userBuf: Option<UserBufGroup>,
     pub sched: TaskSchedInfo,
     pub iovs: Vec<IoVec>,
 
     pub perfcounters: Option<Arc<Counters>>,
 
+    // where the last host errno entered the guest kernel, only recorded in debug build
+    pub errCtx: Option<ErrContext>,
+
+    // the random stream of the deterministic mode, it is seeded at the first use
+    pub randState: Option<u64>,
+
     pub guard: Guard,
     //check whether the stack overflow
 }
@@ -211,6 +224,25 @@ impl Default for Task {
     }
 }
 
+// HostErr converts the errno returned by the host operation op on fd to Error::SysError.
+// In debug build the call site, op and fd are recorded in the current task and reported
+// when the errno is returned to the application.
+#[track_caller]
+pub fn HostErr(op: &'static str, fd: i32, errno: i32) -> Error {
+    if cfg!(debug_assertions) {
+        let caller = core::panic::Location::caller();
+        Task::Current().errCtx = Some(ErrContext {
+            op: op,
+            fd: fd,
+            errno: errno,
+            file: caller.file(),
+            line: caller.line(),
+        });
+    }
+
+    return Error::SysError(errno)
+}
+
 impl Task {
     #[inline(always)]
     pub fn Check(&self) {
@@ -234,7 +266,9 @@ impl Task {
         self.syscallRestartBlock = None;
         self.futexMgr = dummyTask.futexMgr.clone();
         self.perfcounters = None;
+        self.errCtx = None;
         self.ioUsage = dummyTask.ioUsage.clone();
+        self.userBuf = None;
     }
 
     pub fn SaveFp(&self) {
@@ -296,14 +330,18 @@ impl Task {
 
             fdTbl: fdTbl,
             blocker: blocker,
+            epollRing: Arc::new(TaskEpollRing::New()),
             thread: None,
             haveSyscallReturn: false,
             syscallRestartBlock: None,
             futexMgr: futexMgr,
             ioUsage: IO::default(),
+            userBuf: None,
             sched: TaskSchedInfo::default(),
             iovs: Vec::new(),
             perfcounters: None,
+            errCtx: None,
+            randState: None,
             guard: Guard::default(),
         };
 
@@ -416,6 +454,23 @@ impl Task {
         return self.fsContext.Umask();
     }
 
+    // DeterministicRandom fills buf from the random stream of the thread in the deterministic mode
+    pub fn DeterministicRandom(&mut self, buf: &mut [u8]) {
+        let mut state = match self.randState {
+            Some(state) => state,
+            None => {
+                let tid = match &self.thread {
+                    None => 0,
+                    Some(t) => t.lock().id,
+                };
+                ThreadSeed(tid)
+            }
+        };
+
+        DeterministicFill(&mut state, buf);
+        self.randState = Some(state);
+    }
+
     pub fn Creds(&self) -> Credentials {
         return self.creds.clone();
     }
@@ -616,6 +671,21 @@ impl Task {
         }
     }
 
+    // ReportErrContext logs where the errno returned by the syscall nr came from the host.
+    // The context is dropped whatever the syscall result is so that it doesn't leak to the next syscall.
+    pub fn ReportErrContext(&mut self, nr: u64, errno: i32) {
+        let ctx = match self.errCtx.take() {
+            None => return,
+            Some(ctx) => ctx,
+        };
+
+        if ctx.errno != errno || errno == SysErr::EAGAIN || errno == SysErr::EINPROGRESS {
+            return
+        }
+
+        info!("Syscall[{}]: errno {} from host {} fd {} at {}:{}", nr, errno, ctx.op, ctx.fd, ctx.file, ctx.line);
+    }
+
     pub fn GetTaskId(&self) -> TaskId {
         return TaskId::New(self.taskId)
     }
@@ -666,14 +736,18 @@ impl Task {
 
                 fdTbl: FDTable::default(),
                 blocker: blocker,
+                epollRing: Arc::new(TaskEpollRing::New()),
                 thread: None,
                 haveSyscallReturn: false,
                 syscallRestartBlock: None,
                 futexMgr: futexMgr,
                 ioUsage: ioUsage,
+                userBuf: None,
                 sched: TaskSchedInfo::default(),
                 iovs: Vec::with_capacity(4),
                 perfcounters: perfcounters,
+                errCtx: None,
+                randState: None,
                 guard: Guard::default(),
             });
 
@@ -768,14 +842,18 @@ impl Task {
 
                 fdTbl: FDTable::default(),
                 blocker: Blocker::New(baseStackAddr),
+                epollRing: Arc::new(TaskEpollRing::New()),
                 thread: None,
                 haveSyscallReturn: false,
                 syscallRestartBlock: None,
                 futexMgr: FUTEX_MGR.clone(),
                 ioUsage: dummyTask.ioUsage.clone(),
+                userBuf: None,
                 sched: TaskSchedInfo::default(),
                 iovs: Vec::new(),
                 perfcounters: None,
+                errCtx: None,
+                randState: None,
                 guard: Guard::default(),
             });
 
@@ -801,6 +879,25 @@ impl Task {
         SetFs(self.context.fs);
     }
 
+    // SaveFS keeps the fs base changed by the application with wrfsbase when the task is
+    // switched out, the fs base can only be changed by arch_prctl without FSGSBASE
+    #[inline]
+    pub fn SaveFS(&mut self) {
+        if HasFsGsBase() {
+            self.context.fs = ReadFsBase();
+        }
+    }
+
+    #[inline]
+    pub fn SetShadowStack(&self) {
+        self.context.shadowStack.Restore();
+    }
+
+    #[inline]
+    pub fn SaveShadowStack(&mut self) {
+        self.context.shadowStack.Save();
+    }
+
     #[inline]
     pub fn GetContext(&self) -> u64 {
         return (&self.context as *const Context) as u64;
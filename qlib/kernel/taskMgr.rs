@@ -20,14 +20,17 @@ use super::task::*;
 use super::SHARESPACE;
 use super::super::task_mgr::*;
 use super::Kernel::HostSpace;
-use super::TSC;
 use super::super::linux_def::*;
 use super::super::vcpu_mgr::*;
 use super::threadmgr::task_sched::*;
-use super::KERNEL_STACK_ALLOCATOR;
+use super::kernel::async_process::*;
 use super::quring::uring_mgr::*;
 use super::Shutdown;
 use super::ASYNC_PROCESS;
+use super::kernel::timer::MonotonicNow;
+use super::kernel::timer::SpinTimer;
+use super::kernel::timer::{HighResTimerPoll, HighResTimerRelease};
+use super::{Tsc, TSC};
 
 static ACTIVE_TASK: AtomicU32 = AtomicU32::new(0);
 
@@ -68,34 +71,33 @@ fn switch_to(to: TaskId) {
         toCtx.SwitchPageTable();
     }
     toCtx.SetFS();
+    toCtx.SetShadowStack();
     unsafe {
         context_swap_to(0, toCtx.GetContext(), 1, 0);
     }
 }
 
-pub const IO_WAIT_CYCLES : i64 = 20_000_000; // 1ms
-pub const WAIT_CYCLES : i64 = 1_000_000; // 1ms
-
+// the spin budgets are measured with the host monotonic clock, the TSC rate of the host changes
+// with the cpu frequency when it is not invariant
 pub fn IOWait() {
-    let mut start = TSC.Rdtsc();
+    let mut timer = SpinTimer::New(SHARESPACE.IOSpinNs());
 
     while !Shutdown() {
         if PollAsyncMsg() > 10 {
-            start = TSC.Rdtsc();
+            timer.Reset();
         }
 
-        let currentTime = TSC.Rdtsc();
-        if currentTime - start >= IO_WAIT_CYCLES || Shutdown() {
+        if timer.Expired() || Shutdown() {
             // after change the state, check again in case new message coming
             if PollAsyncMsg() > 10 && !Shutdown() {
-                start = TSC.Rdtsc();
+                timer.Reset();
                 continue;
             }
 
             //debug!("IOWait sleep");
             HostSpace::IOWait();
             //debug!("IOWait wakeup");
-            start = TSC.Rdtsc();
+            timer.Reset();
         }
     }
 
@@ -142,8 +144,9 @@ pub fn WaitFn() {
 
                 let pendingFreeStack = CPULocal::PendingFreeStack();
                 if pendingFreeStack != 0 {
-                    //(*PAGE_ALLOCATOR).Free(pendingFreeStack, DEFAULT_STACK_PAGES).unwrap();
-                    KERNEL_STACK_ALLOCATOR.Free(pendingFreeStack).unwrap();
+                    // the stack is freed by the background work of the free class
+                    DEFERRED_STACKS.Push(pendingFreeStack);
+                    ASYNC_PROCESS.Queue(AsyncWork::FreeStacks);
                     CPULocal::SetPendingFreeStack(0);
                 }
 
@@ -183,7 +186,7 @@ pub fn ProcessOne() -> bool {
 
 pub fn Wait() {
     CPULocal::Myself().ToSearch(&SHARESPACE);
-    let start = TSC.Rdtsc();
+    let mut timer = SpinTimer::New(SHARESPACE.VcpuSpinNs());
 
     loop {
         let next = { SHARESPACE.scheduler.GetNext() };
@@ -193,6 +196,7 @@ pub fn Wait() {
             //let vcpuId = newTask.GetTask().queueId;
             //assert!(CPULocal::CpuId()==vcpuId, "cpu {}, target cpu {}", CPULocal::CpuId(), vcpuId);
 
+            HighResTimerRelease();
             CPULocal::Myself().SwitchToRunning();
             if current.data != newTask.data {
                 switch(current, newTask);
@@ -203,8 +207,7 @@ pub fn Wait() {
 
         //super::ALLOCATOR.Free();
 
-        let currentTime = TSC.Rdtsc();
-        if currentTime - start >= WAIT_CYCLES {
+        if timer.Expired() && !HighResTimerPoll(MonotonicNow()) {
             let current = TaskId::New(CPULocal::CurrentTask());
             let waitTask = TaskId::New(CPULocal::WaitTask());
             switch(current, waitTask);
@@ -293,19 +296,19 @@ impl Scheduler {
 
         let count = self.queue[vcpuId].lock().len();
         for _ in 0..count {
-            let task = {
+            // the level is charged only when the task is dispatched, the task which is not
+            // ready yet goes back to the queue without using the pass of its level
+            let (taskId, class) = {
                 let mut queue = self.queue[vcpuId].lock();
-                let task = queue.pop_front();
-                if task.is_none() {
-                    return None;
-                }
+                let picked = match queue.Pick() {
+                    None => return None,
+                    Some(picked) => picked,
+                };
 
                 let _cnt = self.DecReadyTaskCount();
-                task
+                picked
             };
 
-            let taskId = task.unwrap();
-
             assert!(vcpuId==taskId.GetTask().QueueId(),
             "vcpuId is {:x}, taskId.GetTask().QueueId() is {:x}, task {:x?}/{:x?}", vcpuId, taskId.GetTask().QueueId(), taskId, taskId.GetTask().guard);
             if taskId.GetTask().context.Ready() != 0 || taskId.data == Task::Current().taskId {
@@ -320,8 +323,9 @@ impl Scheduler {
                     }
                 }
 
+                self.queue[vcpuId].lock().Charge(class);
                 //error!("GetNextForCpu task is {:x?}", taskId);
-                return task
+                return Some(taskId)
             }
 
             self.ScheduleQ(taskId, vcpuId as u64);
@@ -352,6 +356,34 @@ pub fn Yield() {
     Wait();
 }
 
+// NeedResched returns whether the current task has run in the guest kernel longer than a time
+// slice while other tasks are waiting for a vcpu
+pub fn NeedResched() -> bool {
+    let timeSlice = SHARESPACE.TimeSlice();
+    if timeSlice == 0 || SHARESPACE.scheduler.GlobalReadyTaskCnt() == 0 {
+        return false
+    }
+
+    let start = CPULocal::Myself().KernelEnterTimestamp();
+    return start != 0 && Tsc::Scale(TSC.Rdtsc() - start) * 1000 > timeSlice
+}
+
+// CondResched is the preemption point of the long running kernel loops, the caller must not
+// hold any spin lock as the next task on the vcpu may spin on it. The wait task and the async
+// completions processed by Wait()/IOWait() run while the vcpu is not in the Running state,
+// they must never yield.
+pub fn CondResched() {
+    if CPULocal::CurrentTask() == CPULocal::WaitTask()
+        || CPULocal::CPUState() != VcpuState::Running {
+        return
+    }
+
+    if NeedResched() {
+        Yield();
+        CPULocal::Myself().SetKernelEnterTimestamp(TSC.Rdtsc());
+    }
+}
+
 pub fn NewTask(taskId: TaskId) {
     SHARESPACE.scheduler.NewTask(taskId);
 }
@@ -13,4 +13,5 @@
 // limitations under the License.
 
 pub mod tcpip;
+pub mod sockaddr;
 pub mod buffer;
\ No newline at end of file
@@ -0,0 +1,209 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::mem;
+use core::ptr;
+
+use super::super::super::common::*;
+use super::super::super::linux_def::*;
+use super::tcpip::*;
+
+// The sockaddr from the app or from the host is only a byte buffer. It may be shorter than the
+// struct of its family, not aligned for it, or come with a length larger than the buffer, e.g.
+// the msg_namelen of a truncated recvmsg. None of them is trusted here.
+
+// SOCK_ADDR_INET6_MIN_SIZE is SIN6_LEN_RFC2133, linux still accepts the sockaddr_in6 without
+// sin6_scope_id
+pub const SOCK_ADDR_INET6_MIN_SIZE: usize = 24;
+
+// SockAddrFamily reads the sa_family of the address
+pub fn SockAddrFamily(addr: &[u8]) -> Result<u16> {
+    if addr.len() < 2 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    return Ok(u16::from_ne_bytes([addr[0], addr[1]]))
+}
+
+// ReadSockAddr copies the struct from the start of addr, the fields after a shorter addr are
+// left zero
+pub fn ReadSockAddr<T: Copy + Default>(addr: &[u8]) -> T {
+    let mut ret = T::default();
+    let len = core::cmp::min(addr.len(), mem::size_of::<T>());
+    unsafe {
+        ptr::copy_nonoverlapping(addr.as_ptr(), &mut ret as *mut T as *mut u8, len);
+    }
+
+    return ret
+}
+
+// ParseSockAddr parses the address of the family in its sa_family
+pub fn ParseSockAddr(addr: &[u8]) -> Result<SockAddr> {
+    let family = SockAddrFamily(addr)?;
+    return GetAddr(family as i16, addr)
+}
+
+// HostSockAddr parses the address returned by the host with its length, e.g. the peer of
+// recvmsg, and returns it with the length for the app. The host returns the full length of a
+// truncated address, so nameLen is limited to the buffer. The data is already received when
+// the address is parsed, an address which is empty, e.g. of a connected tcp socket, or which
+// the guest can't parse is dropped instead of failing the call.
+pub fn HostSockAddr(addr: &[u8], nameLen: usize) -> Option<(SockAddr, usize)> {
+    let addr = &addr[..core::cmp::min(nameLen, addr.len())];
+    match SockAddrFamily(addr) {
+        Err(_) => return None,
+        Ok(family) if family as i32 == AFType::AF_UNSPEC => return None,
+        Ok(_) => (),
+    }
+
+    match ParseSockAddr(addr) {
+        Err(e) => {
+            debug!("HostSockAddr drops the address {:?}: {:?}", addr, e);
+            return None
+        }
+        Ok(a) => {
+            let len = a.Len();
+            return Some((a, len))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+
+    // XorShift is the pseudo random bytes of the fuzz tests, the seed is fixed so that a failure
+    // can be reproduced
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn Next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            return self.0
+        }
+
+        fn Bytes(&mut self, len: usize) -> Vec<u8> {
+            return (0..len).map(|_| self.Next() as u8).collect()
+        }
+    }
+
+    const FAMILIES: [i32; 5] = [AFType::AF_UNIX, AFType::AF_INET, AFType::AF_INET6,
+                                AFType::AF_NETLINK, AFType::AF_PACKET];
+
+    // MinLen is the shortest address of the family GetAddr accepts
+    fn MinLen(family: i32) -> usize {
+        match family {
+            AFType::AF_INET => SOCK_ADDR_INET_SIZE,
+            AFType::AF_INET6 => SOCK_ADDR_INET6_MIN_SIZE,
+            AFType::AF_NETLINK => SockAddrNetlink::SOCK_ADDR_NETLINK_SIZE,
+            AFType::AF_PACKET => SockAddrLink::SOCK_ADDR_LINK_SIZE,
+            _ => 2,
+        }
+    }
+
+    #[test]
+    fn test_sockaddr_fuzz() {
+        let mut rng = XorShift(0x9e3779b97f4a7c15);
+        for family in FAMILIES.iter() {
+            for len in 0..UNIX_PATH_MAX + 16 {
+                for _ in 0..32 {
+                    let mut addr = rng.Bytes(len);
+                    if len >= 2 {
+                        addr[..2].copy_from_slice(&(*family as u16).to_ne_bytes());
+                    }
+
+                    // the unaligned copy of the buffer
+                    let mut shifted = rng.Bytes(1);
+                    shifted.extend_from_slice(&addr);
+
+                    let res = ParseSockAddr(&addr);
+                    assert_eq!(res.is_ok(), ParseSockAddr(&shifted[1..]).is_ok());
+                    if len < 2 {
+                        assert!(res.is_err());
+                        continue
+                    }
+
+                    match res {
+                        Ok(a) => {
+                            assert!(len >= MinLen(*family), "family {} len {}", family, len);
+                            let buf = a.ToVec().unwrap();
+                            assert_eq!(SockAddrFamily(&buf).unwrap(), *family as u16);
+                        }
+                        Err(_) => {
+                            // only the unix path may be rejected for its content
+                            assert!(len < MinLen(*family) || *family == AFType::AF_UNIX,
+                                    "family {} len {}", family, len);
+                        }
+                    }
+
+                    // any name length of the host is limited to the buffer
+                    for nameLen in [0, 1, 2, len / 2, len, len + 1, 4096].iter() {
+                        HostSockAddr(&addr, *nameLen);
+                    }
+                }
+            }
+        }
+
+        for len in 0..64 {
+            for _ in 0..256 {
+                let addr = rng.Bytes(len);
+                ParseSockAddr(&addr).ok();
+                HostSockAddr(&addr, rng.Next() as usize % 128);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sockaddr_inet() {
+        let mut addr = [0u8; 28];
+        addr[..2].copy_from_slice(&(AFType::AF_INET6 as u16).to_ne_bytes());
+        addr[2..4].copy_from_slice(&htons(80).to_ne_bytes());
+        addr[8] = 0xfe;
+        match ParseSockAddr(&addr[..24]).unwrap() {
+            SockAddr::Inet6(a) => {
+                assert_eq!(ntohs(a.Port), 80);
+                assert_eq!(a.Addr[0], 0xfe);
+                assert_eq!(a.Scope_id, 0);
+            }
+            a => panic!("unexpected {:?}", a),
+        }
+        assert!(ParseSockAddr(&addr[..23]).is_err());
+
+        addr[..2].copy_from_slice(&(AFType::AF_INET as u16).to_ne_bytes());
+        assert!(ParseSockAddr(&addr[..15]).is_err());
+        assert!(ParseSockAddr(&addr[..16]).is_ok());
+
+        assert!(HostSockAddr(&addr, 0).is_none());
+        assert_eq!(HostSockAddr(&addr, 128).unwrap().1, SOCK_ADDR_INET_SIZE);
+        addr[..2].copy_from_slice(&(AFType::AF_UNSPEC as u16).to_ne_bytes());
+        assert!(HostSockAddr(&addr, 16).is_none());
+    }
+
+    #[test]
+    fn test_sockaddr_link() {
+        let mut addr = [0u8; SockAddrLink::SOCK_ADDR_LINK_SIZE];
+        addr[..2].copy_from_slice(&(AFType::AF_PACKET as u16).to_ne_bytes());
+        addr[4..8].copy_from_slice(&2i32.to_ne_bytes());
+        match ParseSockAddr(&addr).unwrap() {
+            SockAddr::Link(a) => assert_eq!(a.InterfaceIndex, 2),
+            a => panic!("unexpected {:?}", a),
+        }
+
+        let short = ParseSockAddr(&addr[..SockAddrLink::SOCK_ADDR_LINK_SIZE - 1]);
+        assert_eq!(short.err(), Some(Error::SysError(SysErr::EINVAL)));
+    }
+}
@@ -21,6 +21,7 @@ use core::slice;
 
 use super::super::super::common::*;
 use super::super::super::linux_def::*;
+use super::sockaddr::*;
 
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -181,16 +182,8 @@ impl FullAddr {
 // to the FullAddress format. It supports AF_UNIX, AF_INET and AF_INET6
 // addresses.
 pub fn GetAddr(sfamily: i16, addr: &[u8]) -> Result<SockAddr> {
-    // Make sure we have at least 2 bytes for the address family.
-    if addr.len() < 2 {
-        return Err(Error::SysError(SysErr::EINVAL))
-    }
-
-    let family = unsafe {
-        &*(&addr[0] as * const u8 as * const i16)
-    };
-
-    if *family != sfamily {
+    // the address has at least 2 bytes for the family
+    if SockAddrFamily(addr)? as i16 != sfamily {
         return Err(Error::SysError(SysErr::EINVAL))
     }
 
@@ -232,33 +225,29 @@ pub fn GetAddr(sfamily: i16, addr: &[u8]) -> Result<SockAddr> {
                 return Err(Error::SysError(SysErr::EFAULT))
             }
 
-            let a = unsafe {
-                & * ((&addr[0]) as * const _ as * const SockAddrInet)
-            };
-
-            return Ok(SockAddr::Inet(*a));
+            return Ok(SockAddr::Inet(ReadSockAddr(addr)));
         }
         AFType::AF_INET6 => {
-            if addr.len() < SOCK_ADDR_INET6_SIZE {
+            if addr.len() < SOCK_ADDR_INET6_MIN_SIZE {
                 return Err(Error::SysError(SysErr::EFAULT))
             }
 
-            let a = unsafe {
-                & * ((&addr[0]) as * const _ as * const SocketAddrInet6)
-            };
-
-            return Ok(SockAddr::Inet6(*a));
+            return Ok(SockAddr::Inet6(ReadSockAddr(addr)));
         }
         AFType::AF_NETLINK => {
             if addr.len() < SockAddrNetlink::SOCK_ADDR_NETLINK_SIZE {
                 return Err(Error::SysError(SysErr::EFAULT))
             }
 
-            let a = unsafe {
-                & * ((&addr[0]) as * const _ as * const SockAddrNetlink)
-            };
+            return Ok(SockAddr::Netlink(ReadSockAddr(addr)));
+        }
+        AFType::AF_PACKET => {
+            // linux packet_bind and packet_sendmsg return EINVAL for the short sockaddr_ll
+            if addr.len() < SockAddrLink::SOCK_ADDR_LINK_SIZE {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
 
-            return Ok(SockAddr::Netlink(*a));
+            return Ok(SockAddr::Link(ReadSockAddr(addr)));
         }
         _ => ()
     }
@@ -294,6 +283,7 @@ pub enum SockAddr {
     Inet6(SocketAddrInet6),
     Unix(SockAddrUnix),
     Netlink(SockAddrNetlink),
+    Link(SockAddrLink),
     None,
 }
 
@@ -304,6 +294,7 @@ impl SockAddr {
             SockAddr::Inet6(addr) => addr.Len(),
             SockAddr::Unix(addr) => addr.Len(),
             SockAddr::Netlink(addr) => addr.Len(),
+            SockAddr::Link(addr) => addr.Len(),
             SockAddr::None => 0,
         }
     }
@@ -359,6 +350,15 @@ impl SockAddr {
                 }
                 return Ok(())
             }
+            SockAddr::Link(addr) => {
+                let ptr = addr as *const _ as u64 as * const u8;
+                let slice = unsafe { slice::from_raw_parts(ptr, len) };
+
+                for i in 0..len {
+                    buf[i] = slice[i];
+                }
+                return Ok(())
+            }
             SockAddr::None => {
                 return Err(Error::SysError(SysErr::EINVAL))
             }
@@ -471,7 +471,7 @@ impl SockAddrUnix {
 
 // SockAddrNetlink is struct sockaddr_nl, from uapi/linux/netlink.h.
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 pub struct SockAddrNetlink {
     pub Family: u16,
     pub Padding: u16,
@@ -485,4 +485,25 @@ impl SockAddrNetlink {
     pub fn Len(&self) -> usize {
         return Self::SOCK_ADDR_NETLINK_SIZE;
     }
-}
\ No newline at end of file
+}
+
+// SockAddrLink is struct sockaddr_ll, from uapi/linux/if_packet.h.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SockAddrLink {
+    pub Family: u16,
+    pub Protocol: u16,
+    pub InterfaceIndex: i32,
+    pub HardwareType: u16,
+    pub PacketType: u8,
+    pub HardwareAddrLen: u8,
+    pub HardwareAddr: [u8; 8],
+}
+
+impl SockAddrLink {
+    pub const SOCK_ADDR_LINK_SIZE : usize = 20;
+
+    pub fn Len(&self) -> usize {
+        return Self::SOCK_ADDR_LINK_SIZE;
+    }
+}
@@ -0,0 +1,147 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Host test harness: the qlib kernel code is built into qvisor for "cargo test" (or with the
+// "host-test" feature) and runs without VM. SHARESPACE is backed by a process local ShareSpace,
+// HostSpace::Call/HCall and ClockGetTime are routed to the mocks registered here.
+//
+// The mocks are process global, a test which registers a mock has to hold the guard of Lock()
+// so that the tests running in parallel don't see each other's mock.
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::Ordering;
+use alloc::boxed::Box;
+
+use super::super::mutex::*;
+use super::super::qmsg::*;
+use super::super::config::*;
+use super::super::ShareSpace;
+use super::SHARESPACE;
+
+pub type MockHostCallFn = fn(msg: &mut Msg) -> u64;
+
+static SHARESPACE_INITED: AtomicBool = AtomicBool::new(false);
+static SHARESPACE_INIT_LOCK: QMutex<()> = QMutex::new(());
+static MOCK_LOCK: QMutex<()> = QMutex::new(());
+static MOCK_HOST_CALL: QMutex<Option<MockHostCallFn>> = QMutex::new(None);
+
+// mock clock in ns, a negative value means the host clock is used
+static MOCK_TIME: AtomicI64 = AtomicI64::new(-1);
+
+// InitShareSpace makes SHARESPACE usable by the test. It is done once per process and the
+// ShareSpace lives until the process exits.
+pub fn InitShareSpace() {
+    if SHARESPACE_INITED.load(Ordering::Acquire) {
+        return
+    }
+
+    let _l = SHARESPACE_INIT_LOCK.lock();
+    if SHARESPACE_INITED.load(Ordering::Acquire) {
+        return
+    }
+
+    let shareSpace = Box::leak(Box::new(ShareSpace::default()));
+    SHARESPACE.SetValue(shareSpace as * const _ as u64);
+    SHARESPACE_INITED.store(true, Ordering::Release);
+}
+
+// SetConfig replaces the config seen by the code under test through SHARESPACE.config
+pub fn SetConfig(config: Config) {
+    InitShareSpace();
+    *SHARESPACE.config.write() = config;
+    SHARESPACE.CacheConfig();
+}
+
+// Lock serializes the tests which use the mocks, the mocks are reset when the guard is taken.
+pub fn Lock() -> QMutexGuard<'static, ()> {
+    let guard = MOCK_LOCK.lock();
+    ClearMockHostCall();
+    MOCK_TIME.store(-1, Ordering::SeqCst);
+    return guard
+}
+
+pub fn SetMockHostCall(f: MockHostCallFn) {
+    *MOCK_HOST_CALL.lock() = Some(f);
+}
+
+pub fn ClearMockHostCall() {
+    *MOCK_HOST_CALL.lock() = None;
+}
+
+// MockHostCall returns None when there is no mock, the caller falls back to its own behavior
+pub fn MockHostCall(msg: &mut Msg) -> Option<u64> {
+    let f = *MOCK_HOST_CALL.lock();
+    return f.map(|f| f(msg))
+}
+
+pub fn SetMockTime(ns: i64) {
+    MOCK_TIME.store(ns, Ordering::SeqCst);
+}
+
+pub fn AdvanceMockTime(ns: i64) -> i64 {
+    return MOCK_TIME.fetch_add(ns, Ordering::SeqCst) + ns
+}
+
+pub fn MockTime() -> Option<i64> {
+    let ns = MOCK_TIME.load(Ordering::SeqCst);
+    if ns < 0 {
+        return None
+    }
+
+    return Some(ns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mockClose(msg: &mut Msg) -> u64 {
+        match msg {
+            Msg::Close(msg) => return msg.fd as u64,
+            _ => return -1i64 as u64,
+        }
+    }
+
+    #[test]
+    fn test_sharespace_config() {
+        let _l = Lock();
+        let mut config = Config::default();
+        config.EphemeralPortStart = 40000;
+        config.EphemeralPortEnd = 40010;
+        SetConfig(config);
+        assert_eq!(SHARESPACE.config.read().EphemeralPortStart, 40000);
+        SetConfig(Config::default());
+    }
+
+    #[test]
+    fn test_mock_host_call() {
+        let _l = Lock();
+        let mut msg = Msg::Close(Close { fd: 7 });
+        assert_eq!(MockHostCall(&mut msg), None);
+        SetMockHostCall(mockClose);
+        assert_eq!(MockHostCall(&mut msg), Some(7));
+        ClearMockHostCall();
+        assert_eq!(MockHostCall(&mut msg), None);
+    }
+
+    #[test]
+    fn test_mock_time() {
+        let _l = Lock();
+        assert_eq!(MockTime(), None);
+        SetMockTime(1000);
+        assert_eq!(AdvanceMockTime(500), 1500);
+        assert_eq!(MockTime(), Some(1500));
+    }
+}
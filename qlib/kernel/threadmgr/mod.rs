@@ -35,3 +35,4 @@ pub mod task_sched;
 pub mod task_usermem;
 pub mod task_exec;
 pub mod task_futex;
+pub mod task_shadow_stack;
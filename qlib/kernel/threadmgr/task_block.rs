@@ -21,6 +21,7 @@ use super::super::kernel::timer::timer::WaitEntryListener;
 use super::super::threadmgr::thread::*;
 use super::super::super::linux::time::*;
 use super::super::kernel::time::*;
+use super::super::kernel::deterministic::*;
 use super::super::super::common::*;
 use super::super::super::linux_def::*;
 use super::super::task::*;
@@ -157,6 +158,7 @@ impl Blocker {
 
         let clock = timer.Clock();
         let start = clock.Now().0;
+        let virtualStart = DeterministicNow(CLOCK_MONOTONIC);
 
         let deadline = if core::i64::MAX - timeout > start { // avoid overflow
             Time(start + timeout)
@@ -166,7 +168,14 @@ impl Blocker {
 
         let res = self.BlockWithTimer(timer, waitGeneral, Some(deadline));
         match res {
-            Err(Error::SysError(SysErr::ETIMEDOUT)) => return (0, Err(Error::SysError(SysErr::ETIMEDOUT))),
+            Err(Error::SysError(SysErr::ETIMEDOUT)) => {
+                // the virtual clock catches up with the timeout as the host clock did
+                if IsDeterministic() {
+                    DeterministicAdvanceTo(virtualStart.saturating_add(timeout));
+                }
+
+                return (0, Err(Error::SysError(SysErr::ETIMEDOUT)))
+            }
             _ => (),
         }
 
@@ -21,6 +21,7 @@ use alloc::vec::Vec;
 use super::super::*;
 use super::super::arch::x86_64::context::*;
 use super::super::kernel::ipc_namespace::*;
+use super::super::kernel::epoll::epoll_ring::*;
 use super::super::threadmgr::task_start::*;
 use super::super::threadmgr::thread::*;
 use super::super::SignalDef::*;
@@ -421,7 +422,16 @@ impl Task {
 
         info!("Clone opts is {:x?}", &opts);
 
-        let (pid, childTask) = self.CloneVM(&opts, userSp)?; //, cStack as * const u8);
+        let shadowStack = Self::Current().ShadowStackForClone(&opts)?;
+        let (pid, childTask) = match self.CloneVM(&opts, userSp) { //, cStack as * const u8);
+            Err(e) => {
+                if shadowStack.base != 0 && !opts.sharingOption.NewAddressSpace {
+                    self.mm.MUnmap(self, shadowStack.base, shadowStack.size).ok();
+                }
+                return Err(e)
+            }
+            Ok(ret) => ret,
+        };
         if opts.ParentSetTID {
             self.CopyOutObj(&pid, pTid)?;
         }
@@ -445,6 +455,8 @@ impl Task {
             cTask.context.fs = tls;
         }
 
+        cTask.context.shadowStack = shadowStack;
+
         taskMgr::NewTask(TaskId::New(cTask.taskId));
 
         return Ok(pid);
@@ -492,6 +504,7 @@ impl Task {
             };
 
             let ioUsage = nt.lock().ioUsage.clone();
+            let userBuf = tg.lock().userBuf.clone();
 
             ptr::write_volatile(taskPtr, Self {
                 context: Context::New(),
@@ -509,20 +522,25 @@ impl Task {
                 fdTbl: fdTbl,
                 blocker: blocker,
                 //Blocker::New(s_ptr as u64),
+                epollRing: Arc::new(TaskEpollRing::New()),
                 thread: Some(nt.clone()),
                 haveSyscallReturn: false,
                 syscallRestartBlock: None,
                 futexMgr: futexMgr,
                 ioUsage: ioUsage,
+                userBuf: Some(userBuf),
                 sched: sched,
                 iovs: Vec::with_capacity(4),
                 perfcounters: Some(THREAD_COUNTS.lock().NewCounters()),
+                errCtx: None,
                 guard: Guard::default(),
             });
         }
 
         let curr = Self::Current();
         let new = unsafe { &mut *taskPtr };
+        // the child inherits the niceness of the parent
+        new.context.SetSchedClass(SchedClass(nt.Niceness()));
 
         new.PerfGoto(PerfType::Blocked);
         new.PerfGoto(PerfType::User);
@@ -1175,6 +1175,8 @@ impl Task {
             panic!("Exit from wait thread!")
         }
 
+        self.FreeShadowStack();
+
         if !t.Signaled() {
             match self.tidInfo.clear_child_tid {
                 None => {
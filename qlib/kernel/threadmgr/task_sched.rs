@@ -29,6 +29,7 @@ use super::super::Tsc;
 use super::super::super::linux_def::*;
 use super::super::super::vcpu_mgr::*;
 use super::super::task::*;
+use super::super::super::task_mgr::*;
 use super::super::kernel::timer::timer::*;
 use super::super::kernel::time::*;
 use super::super::kernel::kernel::*;
@@ -319,9 +320,15 @@ impl Thread {
         return self.lock().niceness + 20
     }
 
-    // SetNiceness sets t's niceness to n.
+    // SetNiceness sets t's niceness to n, the task is queued in the level of it when it is
+    // scheduled next.
     pub fn SetNiceness(&self, n: i32) {
-        self.lock().niceness = n;
+        let mut t = self.lock();
+        t.niceness = n;
+        // the task of the zombie has released its stack
+        if t.exitState < TaskExitState::TaskExitZombie {
+            Task::GetTask(t.taskId).context.SetSchedClass(SchedClass(n));
+        }
     }
 
     // NumaPolicy returns t's current numa policy.
@@ -0,0 +1,248 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::ToString;
+
+use super::super::super::addr::*;
+use super::super::super::common::*;
+use super::super::super::limits::*;
+use super::super::super::linux_def::*;
+use super::super::arch::x86_64::shadow_stack::*;
+use super::super::asm::*;
+use super::super::memmgr::*;
+use super::super::task::*;
+use super::task_clone::*;
+
+// The CET shadow stack of the guest processes, it follows linux arch/x86/kernel/shstk.c.
+// The application enables it with arch_prctl(ARCH_SHSTK_ENABLE), e.g. glibc does it when the
+// executable is built with -fcf-protection. map_shadow_stack is not supported.
+impl Task {
+    // AllocShadowStack maps a shadow stack, the size is the stack rlimit when it is 0
+    pub fn AllocShadowStack(&self, size: u64) -> Result<(u64, u64)> {
+        let mut size = size;
+        if size == 0 {
+            size = self.Thread().ThreadGroup().Limits().Get(LimitType::Stack).Cur;
+        }
+
+        let size = Addr(core::cmp::min(size, SHSTK_MAX_SIZE)).RoundUp()?.0;
+
+        let mut opts = MMapOpts {
+            Length: size,
+            Addr: 0,
+            Offset: 0,
+            Fixed: false,
+            Unmap: false,
+            Map32Bit: false,
+            Perms: AccessType::ReadOnly(),
+            MaxPerms: AccessType::ReadOnly(),
+            Private: true,
+            VDSO: false,
+            GrowsDown: false,
+            ShadowStack: true,
+            Precommit: false,
+            MLockMode: MLockMode::default(),
+            Kernel: false,
+            Mapping: None,
+            Mappable: None,
+            Hint: "".to_string(),
+        };
+
+        let addr = self.mm.MMap(self, &mut opts)?;
+        return Ok((addr, size))
+    }
+
+    pub fn ShadowStackPrctl(&mut self, option: u64, features: u64) -> Result<i64> {
+        match option {
+            ARCH_SHSTK_LOCK => {
+                self.context.shadowStack.locked |= features;
+                return Ok(0)
+            }
+            // only allowed by ptrace
+            ARCH_SHSTK_UNLOCK => return Err(Error::SysError(SysErr::EINVAL)),
+            ARCH_SHSTK_STATUS => {
+                let status = self.context.shadowStack.features;
+                self.CopyOutObj(&status, features)?;
+                return Ok(0)
+            }
+            _ => (),
+        }
+
+        if features & self.context.shadowStack.locked != 0 {
+            return Err(Error::SysError(SysErr::EPERM))
+        }
+
+        // only one feature is changed at a time
+        if features.count_ones() > 1 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let enable = option == ARCH_SHSTK_ENABLE;
+        if features & ARCH_SHSTK_WRSS != 0 {
+            return self.ShadowStackWrss(enable);
+        }
+
+        if features & ARCH_SHSTK_SHSTK != 0 {
+            if enable {
+                return self.EnableShadowStack();
+            }
+
+            return self.DisableShadowStack();
+        }
+
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    fn EnableShadowStack(&mut self) -> Result<i64> {
+        if !HasShadowStack() {
+            return Err(Error::SysError(SysErr::EOPNOTSUPP))
+        }
+
+        if self.context.shadowStack.Enabled() {
+            return Ok(0)
+        }
+
+        let (base, size) = self.AllocShadowStack(0)?;
+        let shstk = &mut self.context.shadowStack;
+        shstk.base = base;
+        shstk.size = size;
+        shstk.ssp = base + size;
+        shstk.features |= ARCH_SHSTK_SHSTK;
+        shstk.Restore();
+        return Ok(0)
+    }
+
+    fn DisableShadowStack(&mut self) -> Result<i64> {
+        if !self.context.shadowStack.Enabled() {
+            return Ok(0)
+        }
+
+        self.FreeShadowStack();
+        let shstk = &mut self.context.shadowStack;
+        shstk.features &= !(ARCH_SHSTK_SHSTK | ARCH_SHSTK_WRSS);
+        shstk.ssp = 0;
+        shstk.Restore();
+        return Ok(0)
+    }
+
+    fn ShadowStackWrss(&mut self, enable: bool) -> Result<i64> {
+        let shstk = &mut self.context.shadowStack;
+        if !HasShadowStack() {
+            return Err(Error::SysError(SysErr::EOPNOTSUPP))
+        }
+
+        // WRSS is only allowed with the shadow stack
+        if !shstk.Enabled() {
+            return Err(Error::SysError(SysErr::EPERM))
+        }
+
+        if enable {
+            shstk.features |= ARCH_SHSTK_WRSS;
+        } else {
+            shstk.features &= !ARCH_SHSTK_WRSS;
+        }
+
+        WriteMsr(MSR_IA32_U_CET, shstk.Cet());
+        return Ok(0)
+    }
+
+    // FreeShadowStack unmaps the shadow stack of the exiting thread, the vfork child doesn't own
+    // the shadow stack of its parent
+    pub fn FreeShadowStack(&mut self) {
+        let shstk = self.context.shadowStack;
+        if !shstk.Enabled() || shstk.base == 0 {
+            return
+        }
+
+        if let Err(e) = self.mm.MUnmap(self, shstk.base, shstk.size) {
+            info!("FreeShadowStack unmap {:x}/{:x} fail {:?}", shstk.base, shstk.size, e);
+        }
+
+        self.context.shadowStack.base = 0;
+        self.context.shadowStack.size = 0;
+    }
+
+    // ResetShadowStack clears the shadow stack state at exec, the old one goes with the old mm
+    pub fn ResetShadowStack(&mut self) {
+        self.context.shadowStack = ShadowStack::default();
+        self.context.shadowStack.Restore();
+    }
+
+    // ShadowStackForClone returns the shadow stack state of the child. A new thread gets its own
+    // shadow stack, the fork child uses its copy of the parent's and the vfork child shares the
+    // parent's one.
+    pub fn ShadowStackForClone(&mut self, opts: &CloneOptions) -> Result<ShadowStack> {
+        self.SaveShadowStack();
+        let mut shstk = self.context.shadowStack;
+        if !shstk.Enabled() {
+            return Ok(shstk)
+        }
+
+        if opts.Vfork {
+            shstk.base = 0;
+            shstk.size = 0;
+            return Ok(shstk)
+        }
+
+        if opts.sharingOption.NewAddressSpace {
+            return Ok(shstk)
+        }
+
+        let (base, size) = self.AllocShadowStack(0)?;
+        shstk.base = base;
+        shstk.size = size;
+        shstk.ssp = base + size;
+        return Ok(shstk)
+    }
+
+    // PushShadowStackSigframe pushes the restore token and the signal restorer to the shadow
+    // stack, the handler returns to the restorer and rt_sigreturn pops the token
+    pub fn PushShadowStackSigframe(&mut self, restorer: u64) -> Result<()> {
+        if !HasShadowStack() || !self.context.shadowStack.Enabled() {
+            return Ok(())
+        }
+
+        let ssp = ReadMsr(MSR_IA32_PL3_SSP);
+        if ssp & 0x7 != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let tokenAddr = ssp - 8;
+        self.mm.ShadowStackWrite(self, tokenAddr, ssp | SHSTK_DATA_BIT)?;
+        let restorerAddr = tokenAddr - 8;
+        self.mm.ShadowStackWrite(self, restorerAddr, restorer)?;
+
+        WriteMsr(MSR_IA32_PL3_SSP, restorerAddr);
+        return Ok(())
+    }
+
+    pub fn PopShadowStackSigframe(&mut self) -> Result<()> {
+        if !HasShadowStack() || !self.context.shadowStack.Enabled() {
+            return Ok(())
+        }
+
+        let ssp = ReadMsr(MSR_IA32_PL3_SSP);
+        if ssp & 0x7 != 0 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let token: u64 = self.CopyInObj(ssp)?;
+        let target = match RestoreTokenTarget(ssp, token) {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(target) => target,
+        };
+
+        WriteMsr(MSR_IA32_PL3_SSP, target);
+        return Ok(())
+    }
+}
@@ -1022,6 +1022,8 @@ impl Task {
     }
 
     pub fn deliverSignalToHandler(&mut self, info: &SignalInfo, sigAct: &SigAct) -> Result<()> {
+        self.PushShadowStackSigframe(sigAct.restorer)?;
+
         let pt = self.GetPtRegs();
         let mut userStack = Stack::New(pt.rsp - 128); // red zone
 
@@ -1085,6 +1087,13 @@ impl Task {
     }
 
     pub fn SignalReturn(&mut self, _rt: bool) -> Result<i64> {
+        if let Err(e) = self.PopShadowStackSigframe() {
+            info!("SignalReturn bad shadow stack frame: {:?}", e);
+            self.Thread().forceSignal(Signal(Signal::SIGSEGV), false);
+            self.Thread().SendSignal(&SignalInfoPriv(Signal::SIGSEGV)).unwrap();
+            return Err(Error::SysCallRetCtrl(TaskRunState::RunApp))
+        }
+
         let pt = self.GetPtRegs();
 
         let mut userStack = Stack::New(pt.rsp);
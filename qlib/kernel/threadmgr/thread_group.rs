@@ -33,6 +33,7 @@ use super::super::threadmgr::task_exit::*;
 use super::super::super::linux;
 use super::super::super::usage::cpu::*;
 use super::super::super::usage::io::*;
+use super::super::super::mem::user_buf_pool::*;
 use super::super::kernel::signal_handler::*;
 use super::super::kernel::waiter::queue::*;
 use super::super::kernel::waiter::waitgroup::*;
@@ -246,6 +247,10 @@ pub struct ThreadGroupInternal {
     // The ioUsage pointer is immutable.
     pub ioUsage: IO,
 
+    // userBuf is the bytes of the user bufs charged by the tasks in the thread group.
+    // The userBuf pointer is immutable.
+    pub userBuf: UserBufGroup,
+
     // maxRSS is the historical maximum resident set size of the thread group, updated when:
     //
     // - A task in the thread group exits, since after all tasks have
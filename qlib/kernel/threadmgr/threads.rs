@@ -203,7 +203,7 @@ impl TaskSet {
             trapNotifyPending: false,
             allowedCPUMask: cfg.AllowedCPUMask.Copy(),
             cpu: 0,
-            niceness: 0,
+            niceness: cfg.Niceness,
             numaPolicy: 0,
             numaNodeMask: 0,
             netns: false,
@@ -232,6 +232,7 @@ impl TaskSet {
             let ioUsage = t.lock().ioUsage.clone();
             task.thread = Some(t.clone());
             task.ioUsage = ioUsage;
+            task.userBuf = Some(tg.lock().userBuf.clone());
         }
 
         {
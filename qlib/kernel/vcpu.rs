@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
 
 use super::asm::*;
@@ -24,6 +25,20 @@ use super::SHARESPACE;
 pub static VCPU_COUNT : Singleton<AtomicUsize> = Singleton::<AtomicUsize>::New();
 pub static CPU_LOCAL : Singleton<&'static [CPULocal]> = Singleton::<&'static [CPULocal]>::New();
 
+// the vcpu supports the FSGSBASE instructions, CR4.FSGSBASE is set by the vmm
+pub static FSGSBASE : AtomicBool = AtomicBool::new(false);
+
+// InitFsGsBase checks the FSGSBASE support with CPUID.(EAX=07H, ECX=0):EBX[0]
+pub fn InitFsGsBase() {
+    let (_, ebx, _, _) = AsmHostID(7, 0);
+    FSGSBASE.store(ebx & 1 != 0, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn HasFsGsBase() -> bool {
+    return FSGSBASE.load(Ordering::Relaxed)
+}
+
 pub fn SetVCPCount(cpuCnt: usize) {
     VCPU_COUNT.store(cpuCnt, Ordering::SeqCst)
 }
@@ -68,16 +83,34 @@ pub fn RegisterSysCall(addr: u64) {
     WriteMsr(MSR::MSR_LSTAR as u32, addr);
 }
 
+// SetFs only writes the fs base when it changes, e.g. the task is switched back to the same vcpu
+// after it blocks
 #[inline]
 pub fn SetFs(addr: u64) {
     //println!("SetFs from {:x} to {:x}", GetFs(), addr);
-    WriteMsr(MSR::MSR_FS_BASE as u32, addr);
+    if HasFsGsBase() {
+        // the application can change the fs base with wrfsbase, so compare with the register
+        if ReadFsBase() != addr {
+            WriteFsBase(addr);
+        }
+        return
+    }
+
+    let cpu = CPULocal::Myself();
+    if cpu.fsBase.load(Ordering::Relaxed) != addr {
+        WriteMsr(MSR::MSR_FS_BASE as u32, addr);
+        cpu.fsBase.store(addr, Ordering::Relaxed);
+    }
     //println!("the input value is {:x}, the get fs result is {:x}", addr, ReadMsr(MSR::MSR_FS_BASE as u32));
 }
 
 #[inline]
 pub fn GetFs() -> u64 {
     //unsafe{ llvm_asm!("movw $0, %fs " :: "r" (0) : "memory");}
+    if HasFsGsBase() {
+        return ReadFsBase();
+    }
+
     return ReadMsr(MSR::MSR_FS_BASE as u32);
 }
 
@@ -81,6 +81,22 @@ pub const SO_COOKIE                :i32 = 57;
 pub const SO_PEERGROUPS            :i32 = 59;
 pub const SO_ZEROCOPY              :i32 = 60;
 pub const SO_TXTIME                :i32 = 61;
+pub const SO_BINDTOIFINDEX         :i32 = 62;
+
+// The original destination of the connection rewritten by the nat rules, from
+// uapi/linux/netfilter_ipv4.h and uapi/linux/netfilter_ipv6/ip6_tables.h.
+pub const SO_ORIGINAL_DST          :i32 = 80;
+pub const IP6T_SO_ORIGINAL_DST     :i32 = 80;
+
+// SO_TIMESTAMPING flags, from uapi/linux/net_tstamp.h.
+pub const SOF_TIMESTAMPING_TX_HARDWARE  :u32 = 1 << 0;
+pub const SOF_TIMESTAMPING_TX_SOFTWARE  :u32 = 1 << 1;
+pub const SOF_TIMESTAMPING_RX_HARDWARE  :u32 = 1 << 2;
+pub const SOF_TIMESTAMPING_RX_SOFTWARE  :u32 = 1 << 3;
+pub const SOF_TIMESTAMPING_SOFTWARE     :u32 = 1 << 4;
+pub const SOF_TIMESTAMPING_RAW_HARDWARE :u32 = 1 << 6;
+pub const SOF_TIMESTAMPING_OPT_ID       :u32 = 1 << 7;
+pub const SOF_TIMESTAMPING_MASK         :u32 = (1 << 15) - 1;
 
 // shutdown(2) how commands, from <linux/net.h>.
 pub const SHUT_RD   :i32 = 0;
@@ -102,4 +118,58 @@ pub const SS_DISCONNECTING :i32 = 4; // In process of disconnecting.
 pub struct LingerOption {
     Enabled: bool,
     Timeout: i64,
-}
\ No newline at end of file
+}
+// IPMreq is struct ip_mreq, from uapi/linux/in.h.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct IPMreq {
+    pub MulticastAddr: [u8; 4],
+    pub InterfaceAddr: [u8; 4],
+}
+
+pub const SIZEOF_IP_MREQ: usize = 8;
+
+// IPMreqn is struct ip_mreqn, from uapi/linux/in.h.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IPMreqn {
+    pub MulticastAddr: [u8; 4],
+    pub InterfaceAddr: [u8; 4],
+    pub InterfaceIndex: i32,
+}
+
+pub const SIZEOF_IP_MREQN: usize = 12;
+
+// IPv6Mreq is struct ipv6_mreq, from uapi/linux/in6.h.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct IPv6Mreq {
+    pub MulticastAddr: [u8; 16],
+    pub InterfaceIndex: i32,
+}
+
+pub const SIZEOF_IPV6_MREQ: usize = 20;
+
+// SockFilter is struct sock_filter, one classic BPF instruction, from uapi/linux/filter.h.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SockFilter {
+    pub Code: u16,
+    pub JT: u8,
+    pub JF: u8,
+    pub K: u32,
+}
+
+// SockFprog is struct sock_fprog, from uapi/linux/filter.h.
+#[repr(C)]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SockFprog {
+    pub Len: u16,
+    pub pad: [u8; 6],
+    pub Filter: u64,
+}
+
+pub const SIZEOF_SOCK_FPROG: usize = 16;
+
+// BPF_MAXINSNS is the maximum number of instructions of a classic BPF program
+pub const BPF_MAXINSNS: usize = 4096;
@@ -210,11 +210,12 @@ pub const EVENT_PRI: EventMask = 0x02; // POLLPRI
 pub const EVENT_OUT: EventMask = 0x04; // POLLOUT
 pub const EVENT_ERR: EventMask = 0x08; // POLLERR
 pub const EVENT_HUP: EventMask = 0x10; // POLLHUP
+pub const EVENT_RDHUP: EventMask = 0x2000; // POLLRDHUP
 
 // Quark event, when application shutdown the connection, it is used for wait the uring to drain the writing buffer
 pub const EVENT_PENDING_SHUTDOWN: EventMask = 0x20;
 
-pub const ALL_EVENTS: EventMask = 0x1f;
+pub const ALL_EVENTS: EventMask = 0x1f | EVENT_RDHUP;
 pub const EVENT_READ: EventMask = EVENT_IN | EVENT_HUP | EVENT_ERR;
 pub const EVENT_WRITE: EventMask = EVENT_OUT | EVENT_HUP | EVENT_ERR;
 
@@ -379,6 +380,7 @@ pub struct FSMagic {}
 
 impl FSMagic {
     pub const ANON_INODE_FS_MAGIC: u64 = 0x09041934;
+    pub const CGROUP2_SUPER_MAGIC: u64 = 0x63677270;
     pub const DEVPTS_SUPER_MAGIC: u64 = 0x00001cd1;
     pub const EXT_SUPER_MAGIC: u64 = 0xef53;
     pub const OVERLAYFS_SUPER_MAGIC: u64 = 0x794c7630;
@@ -1133,6 +1135,7 @@ impl LibcConst {
     pub const IPV6_CHECKSUM: u64 = 0x7;
     pub const IPV6_DROP_MEMBERSHIP: u64 = 0x15;
     pub const IPV6_DSTOPTS: u64 = 0x3b;
+    pub const IPV6_HDRINCL: u64 = 0x24;
     pub const IPV6_HOPLIMIT: u64 = 0x34;
     pub const IPV6_HOPOPTS: u64 = 0x36;
     pub const IPV6_IPSEC_POLICY: u64 = 0x22;
@@ -1746,6 +1749,7 @@ impl LibcConst {
     pub const SOL_RAW: u64 = 0xff;
     pub const SOL_SOCKET: u64 = 0x1;
     pub const SOL_TCP: u64 = 0x6;
+    pub const SOL_TLS: u64 = 0x11a;
     pub const SOL_X25: u64 = 0x106;
 
     pub const SOMAXCONN: u64 = 0x80;
@@ -1822,6 +1826,7 @@ impl LibcConst {
     pub const TCP_CONGESTION: u64 = 0xd;
     pub const TCP_CORK: u64 = 0x3;
     pub const TCP_DEFER_ACCEPT: u64 = 0x9;
+    pub const TCP_FASTOPEN_CONNECT: u64 = 0x1e;
     pub const TCP_INFO: u64 = 0xb;
     pub const TCP_KEEPCNT: u64 = 0x6;
     pub const TCP_KEEPIDLE: u64 = 0x4;
@@ -1836,6 +1841,7 @@ impl LibcConst {
     pub const TCP_NODELAY: u64 = 0x1;
     pub const TCP_QUICKACK: u64 = 0xc;
     pub const TCP_SYNCNT: u64 = 0x7;
+    pub const TCP_ULP: u64 = 0x1f;
     pub const TCP_WINDOW_CLAMP: u64 = 0xa;
     pub const TCP_INQ:u64 = 0x24;
     pub const TIOCCBRK: u64 = 0x5428;
@@ -1919,6 +1925,7 @@ impl LibcConst {
     pub const TUNSETSNDBUF: u64 = 0x400454d4;
     pub const TUNSETTXFILTER: u64 = 0x400454d1;
     pub const TUNSETVNETHDRSZ: u64 = 0x400454d8;
+    pub const UMOUNT_NOFOLLOW: u64 = 0x8;
     pub const WALL: u64 = 0x40000000;
     pub const WCLONE: u64 = 0x80000000;
     pub const WCONTINUED: u64 = 0x8;
@@ -2255,10 +2262,19 @@ pub struct IoVec {
 }
 
 pub struct DataBuff {
-    pub buf: Vec<u8>
+    pub buf: Vec<u8>,
+    // the charge of the buf sized by the app to the user buf pool
+    pub charge: Option<UserBufCharge>,
 }
 
 use super::mem::seq::BlockSeq;
+use super::mem::user_buf_pool::*;
+use super::common::{Error, Result};
+use super::kernel::task::Task;
+use super::kernel::kernel::timer::MONOTONIC_CLOCK;
+
+// the interval to check the user buf pool again when it is used up
+pub const USER_BUF_WAIT_NS: i64 = 1_000_000;
 
 impl DataBuff {
     pub fn New(size: usize) -> Self {
@@ -2268,7 +2284,55 @@ impl DataBuff {
         }
 
         return Self {
-            buf: buf
+            buf: buf,
+            charge: None,
+        }
+    }
+
+    // NewUser allocates the buf of a size given by the app, it waits for the bufs in use to be
+    // freed when the user buf pool or the thread group is used up and fails with ENOMEM when the
+    // size is larger than the max buf or the group limit
+    pub fn NewUser(size: usize) -> Result<Self> {
+        let (charge, _) = Self::Charge(size, size)?;
+        let mut buf = Self::New(size);
+        buf.charge = Some(charge);
+        return Ok(buf)
+    }
+
+    // NewUserIO allocates the buf of a read or write which may be short, the size is limited
+    // to the max buf and to what is left to the thread group, at least a page
+    pub fn NewUserIO(size: usize) -> Result<Self> {
+        let pool = &super::kernel::SHARESPACE.userBufPool;
+        let size = core::cmp::min(size, core::cmp::min(pool.MaxBuf(), pool.GroupLimit()));
+        let min = core::cmp::min(size, MemoryDef::PAGE_SIZE as usize);
+        let (charge, size) = Self::Charge(min, size)?;
+        let mut buf = Self::New(size);
+        buf.charge = Some(charge);
+        return Ok(buf)
+    }
+
+    // Charge charges at least min and at most max bytes to the user buf pool and the thread group
+    // of the task, it waits until min bytes are available.
+    // ret: (the charge, the size of the buf)
+    fn Charge(min: usize, max: usize) -> Result<(UserBufCharge, usize)> {
+        let pool = &super::kernel::SHARESPACE.userBufPool;
+        let task = Task::Current();
+        loop {
+            let available = pool.Available(task.userBuf.as_ref());
+            let size = core::cmp::max(min, core::cmp::min(max, available));
+            match pool.TryCharge(task.userBuf.as_ref(), size)? {
+                Some(charge) => return Ok((charge, size)),
+                None => (),
+            }
+
+            // the freed bufs are not notified, check the pool again after a while
+            let deadline = MONOTONIC_CLOCK.Now().Add(USER_BUF_WAIT_NS);
+            match task.blocker.BlockWithMonoTimer(true, Some(deadline)) {
+                Err(Error::SysError(SysErr::ETIMEDOUT)) => (),
+                Err(Error::ErrInterrupted) => return Err(Error::SysError(SysErr::ERESTARTSYS)),
+                Err(e) => return Err(e),
+                Ok(()) => (),
+            }
         }
     }
 
@@ -2632,6 +2696,7 @@ impl CloneOp {
     pub const CLONE_IO: u64 = 0x80000000;
     pub const CLONE_NEWIPC: i32 = 0x8000000;
     pub const CLONE_NEWNET: i32 = 0x40000000;
+    pub const CLONE_NEWCGROUP: i32 = 0x2000000;
     pub const CLONE_NEWNS: i32 = 0x20000;
     pub const CLONE_NEWPID: i32 = 0x20000000;
     pub const CLONE_NEWUSER: i32 = 0x10000000;
@@ -14,6 +14,7 @@
 
 use alloc::vec::Vec;
 use alloc::string::String;
+use alloc::collections::btree_map::BTreeMap;
 
 use super::limits::*;
 use super::auth::cap_set::*;
@@ -38,6 +39,10 @@ pub struct Process {
     //host
     pub NumCpu: u32,
     pub HostName: String,
+    // the etc files generated by the host, e.g. /etc/resolv.conf
+    pub EtcFiles: Vec<String>,
+    // the sysctls of the spec, e.g. net.core.somaxconn
+    pub Sysctls: BTreeMap<String, String>,
 
     //Container
     pub limitSet: LimitSetInternal,
@@ -0,0 +1,264 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+use super::mutex::*;
+use super::bytestream::*;
+use super::linux_def::*;
+use super::common::*;
+
+// The loopback buf is the data path of the TCP connection between two sandboxes on one host. The
+// client creates the shared memory and passes it to the server with the connection, both guests
+// map it. It is the header page, the ring of the client to the server and the ring of the server
+// to the client.
+
+// the shared memory is mapped in the 2MB unit of the host file mapping
+pub const LOOPBACK_SHM_SIZE: u64 = MemoryDef::PMD_SIZE;
+pub const LOOPBACK_RING_PAGES: usize = 128;
+
+// the header: the head/tail of the two rings, then the write shutdown flag of the two rings
+const HDR_HEADTAIL: u64 = 0;
+const HDR_WCLOSED: u64 = 16;
+
+pub struct LoopbackBuf {
+    addr: u64,
+    pub readBuf: QMutex<RingBuf>,
+    pub writeBuf: QMutex<RingBuf>,
+    // the peer has shut down the write of the read ring
+    rclosed: &'static AtomicU32,
+    wclosed: &'static AtomicU32,
+}
+
+impl LoopbackBuf {
+    // New gets the ring pair in the shared memory at addr, the client writes the ring 0
+    pub fn New(addr: u64, client: bool) -> Self {
+        let (w, r) = if client { (0, 1) } else { (1, 0) };
+        return Self {
+            addr: addr,
+            readBuf: QMutex::new(Self::Ring(addr, r)),
+            writeBuf: QMutex::new(Self::Ring(addr, w)),
+            rclosed: Self::Flag(addr, r),
+            wclosed: Self::Flag(addr, w),
+        }
+    }
+
+    fn Ring(addr: u64, idx: u64) -> RingBuf {
+        let headtail = addr + HDR_HEADTAIL + idx * 8;
+        let buf = addr + MemoryDef::PAGE_SIZE + idx * LOOPBACK_RING_PAGES as u64 * MemoryDef::PAGE_SIZE;
+        return RingBuf::New(LOOPBACK_RING_PAGES, RingeBufAllocator::ShareAllocator(headtail, buf))
+    }
+
+    fn Flag(addr: u64, idx: u64) -> &'static AtomicU32 {
+        return unsafe { &*((addr + HDR_WCLOSED + idx * 4) as *const AtomicU32) }
+    }
+
+    pub fn Addr(&self) -> u64 {
+        return self.addr
+    }
+
+    pub fn RClosed(&self) -> bool {
+        return self.rclosed.load(Ordering::Acquire) != 0
+    }
+
+    pub fn WClosed(&self) -> bool {
+        return self.wclosed.load(Ordering::Acquire) != 0
+    }
+
+    pub fn ShutdownWrite(&self) {
+        self.wclosed.store(1, Ordering::Release);
+    }
+
+    pub fn AvailableDataSize(&self) -> usize {
+        return self.readBuf.lock().AvailableDataSize()
+    }
+
+    pub fn Events(&self) -> EventMask {
+        let mut event = 0;
+        if self.RClosed() || self.readBuf.lock().AvailableDataSize() > 0 {
+            event |= EVENT_IN;
+        }
+
+        if self.WClosed() || self.writeBuf.lock().AvailableSpace() > 0 {
+            event |= EVENT_OUT;
+        }
+
+        return event
+    }
+
+    // Read copies the data of the read ring with copy, which returns the count copied from the
+    // ring iovs. It returns whether the ring was full, i.e. the peer may wait for the space.
+    pub fn Read(&self, peek: bool, copy: impl FnOnce(&[IoVec]) -> Result<usize>) -> Result<(bool, usize)> {
+        let buf = self.readBuf.lock();
+        let mut iovs = SocketBufIovs::default();
+        buf.PrepareDataIovs(&mut iovs);
+        if iovs.cnt == 0 {
+            if self.RClosed() {
+                return Ok((false, 0))
+            }
+
+            return Err(Error::SysError(SysErr::EAGAIN))
+        }
+
+        let cnt = copy(&iovs.iovs[..iovs.cnt])?;
+        if peek || cnt == 0 {
+            return Ok((false, cnt))
+        }
+
+        return Ok((buf.Consume(cnt), cnt))
+    }
+
+    // Write copies the data to the write ring with copy, which returns the count copied to the
+    // ring iovs. It returns whether the ring was empty, i.e. the peer may wait for the data.
+    pub fn Write(&self, copy: impl FnOnce(&[IoVec]) -> Result<usize>) -> Result<(bool, usize)> {
+        if self.WClosed() {
+            return Err(Error::SysError(SysErr::EPIPE))
+        }
+
+        let buf = self.writeBuf.lock();
+        let mut iovs = SocketBufIovs::default();
+        buf.PrepareSpaceIovs(&mut iovs);
+        if iovs.cnt == 0 {
+            return Err(Error::SysError(SysErr::EAGAIN))
+        }
+
+        let cnt = copy(&iovs.iovs[..iovs.cnt])?;
+        if cnt == 0 {
+            return Ok((false, 0))
+        }
+
+        return Ok((buf.Produce(cnt), cnt))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use super::*;
+
+    struct Shm(u64);
+
+    impl Shm {
+        fn New() -> Self {
+            let pages = (LOOPBACK_SHM_SIZE / MemoryDef::PAGE_SIZE) as usize;
+            let addr = HeapAllocator::AlllocBuf(pages);
+            // the shared memory of the host is zeroed
+            unsafe {
+                core::ptr::write_bytes(addr as *mut u8, 0, LOOPBACK_SHM_SIZE as usize);
+            }
+            return Self(addr)
+        }
+    }
+
+    impl Drop for Shm {
+        fn drop(&mut self) {
+            HeapAllocator::FreeBuf(self.0, LOOPBACK_SHM_SIZE as usize);
+        }
+    }
+
+    fn CopyIn(data: &[u8], iovs: &[IoVec]) -> usize {
+        let mut cnt = 0;
+        for iov in iovs {
+            let n = core::cmp::min(iov.len, data.len() - cnt);
+            iov.ToSliceMut()[..n].copy_from_slice(&data[cnt..cnt + n]);
+            cnt += n;
+        }
+        return cnt
+    }
+
+    fn CopyOut(out: &mut Vec<u8>, max: usize, iovs: &[IoVec]) -> usize {
+        let mut cnt = 0;
+        for iov in iovs {
+            let n = core::cmp::min(iov.len, max - cnt);
+            out.extend_from_slice(&iov.ToSlice()[..n]);
+            cnt += n;
+        }
+        return cnt
+    }
+
+    #[test]
+    fn test_layout() {
+        let ring = LOOPBACK_RING_PAGES as u64 * MemoryDef::PAGE_SIZE;
+        assert!(MemoryDef::PAGE_SIZE + 2 * ring <= LOOPBACK_SHM_SIZE);
+        assert!(HDR_WCLOSED >= HDR_HEADTAIL + 16);
+    }
+
+    #[test]
+    fn test_ring_pair() {
+        let shm = Shm::New();
+        let client = LoopbackBuf::New(shm.0, true);
+        let server = LoopbackBuf::New(shm.0, false);
+        assert_eq!(client.Events(), EVENT_OUT);
+        assert_eq!(server.Events(), EVENT_OUT);
+
+        // the first write rings the peer
+        assert_eq!(client.Write(|iovs| Ok(CopyIn(b"hello", iovs))).unwrap(), (true, 5));
+        assert_eq!(client.Write(|iovs| Ok(CopyIn(b" world", iovs))).unwrap(), (false, 6));
+        assert_eq!(server.Events(), EVENT_IN | EVENT_OUT);
+
+        let mut out = Vec::new();
+        assert_eq!(server.Read(true, |iovs| Ok(CopyOut(&mut out, 5, iovs))).unwrap(), (false, 5));
+        assert_eq!(server.AvailableDataSize(), 11);
+        out.clear();
+        assert_eq!(server.Read(false, |iovs| Ok(CopyOut(&mut out, 64, iovs))).unwrap(), (false, 11));
+        assert_eq!(&out[..], b"hello world");
+        assert!(server.Read(false, |_| Ok(0)).is_err());
+
+        // the other direction doesn't see the data of this one
+        assert_eq!(client.AvailableDataSize(), 0);
+        assert_eq!(server.Write(|iovs| Ok(CopyIn(b"pong", iovs))).unwrap(), (true, 4));
+        assert_eq!(client.AvailableDataSize(), 4);
+    }
+
+    #[test]
+    fn test_full_and_wrap() {
+        let shm = Shm::New();
+        let client = LoopbackBuf::New(shm.0, true);
+        let server = LoopbackBuf::New(shm.0, false);
+        let size = LOOPBACK_RING_PAGES * MemoryDef::PAGE_SIZE as usize;
+        let data: Vec<u8> = (0..size + 100).map(|i| i as u8).collect();
+
+        assert_eq!(client.Write(|iovs| Ok(CopyIn(&data, iovs))).unwrap(), (true, size));
+        assert_eq!(client.Events(), 0);
+        assert_eq!(client.Write(|_| Ok(0)).unwrap_err(), Error::SysError(SysErr::EAGAIN));
+
+        // the read of the full ring rings the writer
+        let mut out = Vec::new();
+        assert_eq!(server.Read(false, |iovs| Ok(CopyOut(&mut out, 100, iovs))).unwrap(), (true, 100));
+        assert_eq!(client.Events(), EVENT_OUT);
+        assert_eq!(client.Write(|iovs| Ok(CopyIn(&data[size..], iovs))).unwrap(), (false, 100));
+
+        out.clear();
+        assert_eq!(server.Read(false, |iovs| Ok(CopyOut(&mut out, size, iovs))).unwrap(), (true, size));
+        assert_eq!(&out[..], &data[100..]);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let shm = Shm::New();
+        let client = LoopbackBuf::New(shm.0, true);
+        let server = LoopbackBuf::New(shm.0, false);
+        client.Write(|iovs| Ok(CopyIn(b"bye", iovs))).unwrap();
+        client.ShutdownWrite();
+        assert_eq!(client.Write(|_| Ok(0)).unwrap_err(), Error::SysError(SysErr::EPIPE));
+
+        // the data before the shutdown is read first, then the EOF
+        let mut out = Vec::new();
+        assert_eq!(server.Read(false, |iovs| Ok(CopyOut(&mut out, 64, iovs))).unwrap(), (false, 3));
+        assert_eq!(server.Read(false, |_| Ok(0)).unwrap(), (false, 0));
+        assert_eq!(server.Events() & EVENT_IN, EVENT_IN);
+        assert!(!server.WClosed());
+    }
+}
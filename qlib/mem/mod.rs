@@ -18,4 +18,5 @@ pub mod io;
 pub mod stackvec;
 pub mod areaset;
 pub mod pool;
-pub mod list_allocator;
\ No newline at end of file
+pub mod list_allocator;
+pub mod user_buf_pool;
\ No newline at end of file
@@ -0,0 +1,231 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+use super::super::common::*;
+use super::super::linux_def::*;
+
+// UserBufGroup is the bytes charged by the bufs of a thread group
+pub type UserBufGroup = Arc<AtomicUsize>;
+
+// The kernel bufs whose size comes from the app, e.g. the bounce buf of a read sized by the iov
+// total, are charged to the user buf pool before they are allocated from the kernel heap. A buf
+// is limited to maxBuf, the bufs of a thread group to groupLimit and all of them to the pool
+// size, so one process can't exhaust the heap shared by the sandbox kernel nor starve the others
+// of the pool. The pool is in the share space as the bufs of the async ops may be freed by the
+// host io threads.
+#[derive(Default, Debug)]
+pub struct UserBufPool {
+    // the bytes charged by the bufs in use
+    pub used: AtomicUsize,
+    // 0 before the pool is initialized, nothing is limited then
+    pub limit: AtomicUsize,
+    pub maxBuf: AtomicUsize,
+    pub groupLimit: AtomicUsize,
+}
+
+impl UserBufPool {
+    pub fn Init(&self, limit: usize, maxBuf: usize, groupLimit: usize) {
+        self.maxBuf.store(maxBuf, Ordering::SeqCst);
+        self.groupLimit.store(groupLimit, Ordering::SeqCst);
+        self.limit.store(limit, Ordering::SeqCst);
+    }
+
+    // MaxBuf returns the largest buf, the read and write which can be short are limited to it
+    pub fn MaxBuf(&self) -> usize {
+        match self.maxBuf.load(Ordering::Relaxed) {
+            0 => return usize::MAX,
+            max => return max,
+        }
+    }
+
+    pub fn GroupLimit(&self) -> usize {
+        match self.groupLimit.load(Ordering::Relaxed) {
+            0 => return usize::MAX,
+            limit => return limit,
+        }
+    }
+
+    // Available returns the bytes the group can charge now
+    pub fn Available(&self, group: Option<&UserBufGroup>) -> usize {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 {
+            return usize::MAX
+        }
+
+        let mut available = limit.saturating_sub(self.used.load(Ordering::Relaxed));
+        if let Some(group) = group {
+            let left = self.GroupLimit().saturating_sub(group.load(Ordering::Relaxed));
+            available = core::cmp::min(available, left);
+        }
+
+        return available
+    }
+
+    // TryCharge charges the buf of size to the pool and the group. It fails with ENOMEM when the
+    // size is larger than the max buf or the group limit as it never fits, None is returned when
+    // the pool or the group is used up by the bufs in use.
+    pub fn TryCharge(&self, group: Option<&UserBufGroup>, size: usize) -> Result<Option<UserBufCharge>> {
+        let limit = self.limit.load(Ordering::Relaxed);
+        if limit == 0 || size == 0 {
+            return Ok(Some(UserBufCharge { size: 0, group: None }))
+        }
+
+        if size > self.MaxBuf() || size > self.GroupLimit() {
+            return Err(Error::SysError(SysErr::ENOMEM))
+        }
+
+        if let Some(group) = group {
+            if !Self::Add(group, size, self.GroupLimit()) {
+                return Ok(None)
+            }
+        }
+
+        if !Self::Add(&self.used, size, limit) {
+            if let Some(group) = group {
+                group.fetch_sub(size, Ordering::AcqRel);
+            }
+
+            return Ok(None)
+        }
+
+        return Ok(Some(UserBufCharge {
+            size: size,
+            group: group.cloned(),
+        }))
+    }
+
+    // Add adds size to the counter when it stays in the limit
+    fn Add(counter: &AtomicUsize, size: usize, limit: usize) -> bool {
+        let mut used = counter.load(Ordering::Relaxed);
+        loop {
+            if used + size > limit {
+                return false
+            }
+
+            match counter.compare_exchange_weak(used, used + size, Ordering::AcqRel, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(curr) => used = curr,
+            }
+        }
+    }
+
+    pub fn Uncharge(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::AcqRel);
+    }
+
+    pub fn Used(&self) -> usize {
+        return self.used.load(Ordering::Relaxed)
+    }
+}
+
+// UserBufCharge gives the charge back to the pool of the share space and to the thread group
+// when the buf is freed
+#[derive(Debug)]
+pub struct UserBufCharge {
+    pub size: usize,
+    pub group: Option<UserBufGroup>,
+}
+
+impl Drop for UserBufCharge {
+    fn drop(&mut self) {
+        if self.size > 0 {
+            super::super::kernel::SHARESPACE.userBufPool.Uncharge(self.size);
+            if let Some(group) = &self.group {
+                group.fetch_sub(self.size, Ordering::AcqRel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Take charges size, the charge is given back with Give as the test has no share space
+    fn Take(pool: &UserBufPool, group: Option<&UserBufGroup>, size: usize) -> Result<bool> {
+        match pool.TryCharge(group, size)? {
+            None => return Ok(false),
+            Some(c) => {
+                core::mem::forget(c);
+                return Ok(true)
+            }
+        }
+    }
+
+    fn Give(pool: &UserBufPool, group: Option<&UserBufGroup>, size: usize) {
+        pool.Uncharge(size);
+        if let Some(group) = group {
+            group.fetch_sub(size, Ordering::AcqRel);
+        }
+    }
+
+    #[test]
+    fn test_user_buf_pool() {
+        let pool = UserBufPool::default();
+        // nothing is limited before the init
+        assert!(Take(&pool, None, 1 << 40).unwrap());
+        assert_eq!(pool.Used(), 0);
+
+        pool.Init(100, 60, 0);
+        assert!(Take(&pool, None, 61).is_err());
+        assert!(Take(&pool, None, 60).unwrap());
+        assert_eq!(pool.Available(None), 40);
+        assert!(!Take(&pool, None, 41).unwrap());
+        assert!(Take(&pool, None, 40).unwrap());
+        assert_eq!(pool.Used(), 100);
+
+        Give(&pool, None, 60);
+        assert!(Take(&pool, None, 60).unwrap());
+        Give(&pool, None, 60);
+        Give(&pool, None, 40);
+        assert_eq!(pool.Used(), 0);
+    }
+
+    #[test]
+    fn test_user_buf_group() {
+        let pool = UserBufPool::default();
+        pool.Init(100, 40, 50);
+        let g1 = UserBufGroup::default();
+        let g2 = UserBufGroup::default();
+
+        assert!(Take(&pool, Some(&g1), 40).unwrap());
+        assert_eq!(pool.Available(Some(&g1)), 10);
+        assert!(!Take(&pool, Some(&g1), 20).unwrap());
+        assert_eq!(pool.Used(), 40);
+        assert!(Take(&pool, Some(&g1), 10).unwrap());
+
+        // the other group still gets the rest of the pool
+        assert_eq!(pool.Available(Some(&g2)), 50);
+        assert!(Take(&pool, Some(&g2), 40).unwrap());
+        assert!(!Take(&pool, Some(&g2), 20).unwrap());
+        assert_eq!(g2.load(Ordering::Relaxed), 40);
+        assert_eq!(pool.Available(Some(&g2)), 10);
+
+        // the pool is used up, the charge of the group is rolled back
+        let g3 = UserBufGroup::default();
+        assert!(!Take(&pool, Some(&g3), 20).unwrap());
+        assert_eq!(g3.load(Ordering::Relaxed), 0);
+
+        Give(&pool, Some(&g1), 50);
+        assert_eq!(g1.load(Ordering::Relaxed), 0);
+        assert!(Take(&pool, Some(&g2), 10).unwrap());
+        assert!(!Take(&pool, Some(&g2), 1).unwrap());
+        Give(&pool, Some(&g2), 50);
+        assert_eq!(pool.Used(), 0);
+    }
+}
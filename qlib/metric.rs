@@ -32,6 +32,11 @@ pub fn NewU64Metric(name: &str, sync: bool, description: &str) -> Arc<U64Metric>
     return ALL_METRICS.lock().RegisterU64Metric(name.to_string(), sync, description.to_string())
 }
 
+// NewMetric registers the metric whose value is kept elsewhere, e.g. in the share space
+pub fn NewMetric(name: &str, sync: bool, description: &str, metric: Arc<Metric>) {
+    ALL_METRICS.lock().RegisterMetric(name.to_string(), sync, description.to_string(), metric)
+}
+
 pub trait Metric: Send + Sync {
     fn Value(&self) -> u64;
 }
@@ -80,19 +85,23 @@ impl MetricSet {
     }
 
     pub fn RegisterU64Metric(&mut self, name: String, sync: bool, description: String) -> Arc<U64Metric> {
+        let metric = Arc::new(U64Metric::New());
+        self.RegisterMetric(name, sync, description, metric.clone());
+        return metric;
+    }
+
+    pub fn RegisterMetric(&mut self, name: String, sync: bool, description: String, metric: Arc<Metric>) {
         if self.m.contains_key(&name) {
             panic!("Unable to create metric: {}", name);
         }
 
-        let metric = Arc::new(U64Metric::New());
         let data = MetricData {
             description: description,
             sync: sync,
-            metric: metric.clone(),
+            metric: metric,
         };
 
         self.m.insert(name, data);
-        return metric;
     }
 }
 
@@ -52,15 +52,20 @@ pub mod singleton;
 pub mod mutex;
 pub mod sort_arr;
 pub mod socket_buf;
+pub mod dgram_buf;
+pub mod loopback_buf;
 pub mod object_ref;
 
 pub mod ringbuf;
 pub mod rdma_share;
 pub mod vcpu_mgr;
+pub mod wakeup_moderator;
+pub mod clock_set;
 pub mod kernel;
 
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicI32;
+use core::sync::atomic::AtomicI64;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
 use self::mutex::*;
@@ -69,11 +74,15 @@ use alloc::vec::Vec;
 
 use super::asm::*;
 use self::task_mgr::*;
+use self::vcpu_mgr::*;
+use self::wakeup_moderator::*;
 use self::qmsg::*;
 use self::ringbuf::*;
 use self::config::*;
 use self::linux_def::*;
 use self::bytestream::*;
+use self::mem::user_buf_pool::*;
+use self::kernel::socket::hostinet::stats::SockCounters;
 use self::kernel::quring::uring_mgr::QUring;
 use self::kernel::kernel::timer::timekeeper::*;
 use self::kernel::guestfdnotifier::*;
@@ -121,7 +130,7 @@ pub const HYPERCALL_RELEASE_VCPU: u16 = 24;
 
 pub const DUMMY_TASKID: TaskId = TaskId::New(0xffff_ffff);
 
-pub const MAX_VCPU_COUNT: usize = 16;
+pub const MAX_VCPU_COUNT: usize = 256;
 
 #[allow(non_camel_case_types)]
 #[repr(u64)]
@@ -545,6 +554,14 @@ pub struct ShareSpace {
     pub hostProcessor: CachePadded<AtomicU64>,
     pub VcpuSearchingCnt: CachePadded<AtomicU64>,
 
+    // count of host fds whose async close has not been processed by the host
+    pub pendingCloseCnt: CachePadded<AtomicU64>,
+
+    // host fd notifications delivered to the guest wait queue and the ones coalesced
+    // because the event has been notified and not consumed yet
+    pub fdNotifyCnt: CachePadded<AtomicU64>,
+    pub fdNotifyCoalescedCnt: CachePadded<AtomicU64>,
+
     pub shutdown: CachePadded<AtomicBool>,
     pub ioUring: CachePadded<QUring>,
     pub timerkeeper: CachePadded<TimeKeeper>,
@@ -566,7 +583,39 @@ pub struct ShareSpace {
 
     pub values: Vec<[AtomicU64; 2]>,
     pub tlbShootdownLock: QMutex<()>,
-    pub tlbShootdownMask: AtomicU64,
+    pub tlbShootdownMask: AtomicVcpuMask,
+
+    // guest profiler sample interval in micro sec, 0 means the profiler is off
+    pub profileInterval: CachePadded<AtomicU64>,
+    pub profileLastSample: CachePadded<AtomicI64>,
+
+    // boot latency in micro sec: the host vm setup and the qkernel boot till the root process exec
+    pub vmInitTime: AtomicI64,
+    pub kernelBootTime: AtomicI64,
+
+    pub wakeupModerator: WakeupModerator,
+
+    // the host CLOCK_REALTIME sets to be handled by the kernel timer subsystem
+    pub clockSet: clock_set::ClockSetSeq,
+
+    // the kernel bufs sized by the app
+    pub userBufPool: UserBufPool,
+
+    pub sockStats: SockCounters,
+
+    // the spin budgets and the power save mode of the config, cached for the idle vcpus and the
+    // waits which read them without the config lock
+    pub ioSpinNs: AtomicI64,
+    pub vcpuSpinNs: AtomicI64,
+    pub powerSave: AtomicBool,
+    pub epollWakeupBatch: AtomicBool,
+    // the time slice of the app thread in ns, it is checked on every syscall
+    pub timeSlice: AtomicI64,
+    // the wakeup moderation of the config, read on every io completion
+    pub wakeupModerationRate: AtomicU64,
+    pub wakeupModerationInterval: AtomicI64,
+    // the host fd is closed by the uring, read on every host fd close
+    pub asyncClose: AtomicBool,
 }
 
 impl ShareSpace {
@@ -578,15 +627,62 @@ impl ShareSpace {
     }
 
     pub fn MaskTlbShootdown(&self, vcpuId: u64) {
-        self.tlbShootdownMask.fetch_or(1 << vcpuId, Ordering::Release);
+        self.tlbShootdownMask.Set(vcpuId as usize);
+    }
+
+    // CacheConfig caches the config values read out of the config lock, it is called by the host
+    // after the config is set
+    pub fn CacheConfig(&self) {
+        let config = self.config.read();
+        self.ioSpinNs.store(config.IOSpinNs(), Ordering::Relaxed);
+        self.vcpuSpinNs.store(config.VcpuSpinNs(), Ordering::Relaxed);
+        self.powerSave.store(config.PowerSave == PowerSaveMode::On, Ordering::Relaxed);
+        self.epollWakeupBatch.store(config.EpollWakeupBatch, Ordering::Relaxed);
+        self.timeSlice.store(config.TimeSlice as i64 * 1000, Ordering::Relaxed);
+        self.wakeupModerationRate.store(config.WakeupModerationRate, Ordering::Relaxed);
+        self.wakeupModerationInterval.store(config.WakeupModerationInterval as i64, Ordering::Relaxed);
+        self.asyncClose.store(config.AsyncClose, Ordering::Relaxed);
+    }
+
+    pub fn IOSpinNs(&self) -> i64 {
+        return self.ioSpinNs.load(Ordering::Relaxed)
+    }
+
+    pub fn VcpuSpinNs(&self) -> i64 {
+        return self.vcpuSpinNs.load(Ordering::Relaxed)
+    }
+
+    pub fn PowerSave(&self) -> bool {
+        return self.powerSave.load(Ordering::Relaxed)
+    }
+
+    pub fn EpollWakeupBatch(&self) -> bool {
+        return self.epollWakeupBatch.load(Ordering::Relaxed)
+    }
+
+    // TimeSlice returns the time slice of app thread in ns, 0 means no preemption
+    pub fn TimeSlice(&self) -> i64 {
+        return self.timeSlice.load(Ordering::Relaxed)
+    }
+
+    pub fn WakeupModerationRate(&self) -> u64 {
+        return self.wakeupModerationRate.load(Ordering::Relaxed)
+    }
+
+    pub fn WakeupModerationInterval(&self) -> i64 {
+        return self.wakeupModerationInterval.load(Ordering::Relaxed)
+    }
+
+    pub fn AsyncClose(&self) -> bool {
+        return self.asyncClose.load(Ordering::Relaxed)
     }
 
-    pub fn TlbShootdownMask(&self) -> u64 {
-        return self.tlbShootdownMask.load(Ordering::Acquire);
+    pub fn TlbShootdownMask(&self) -> VcpuMask {
+        return self.tlbShootdownMask.Load();
     }
 
     pub fn ClearTlbShootdownMask(&self) {
-        self.tlbShootdownMask.store(0, Ordering::Release);
+        self.tlbShootdownMask.ClearAll();
     }
 
     pub fn SetIOUringsAddr(&self, addr: u64) {
@@ -641,6 +737,16 @@ impl ShareSpace {
         return self.timerStore.Addr()
     }
 
+    // HostClockSet is called by the host when its CLOCK_REALTIME is set, e.g. NTP step
+    pub fn HostClockSet(&self) {
+        self.clockSet.Set();
+    }
+
+    // TakeClockSet returns true for the caller which has to handle the pending clock set
+    pub fn TakeClockSet(&self) -> bool {
+        return self.clockSet.Take()
+    }
+
     pub fn Addr(&self) -> u64 {
         return self as * const _ as u64;
     }
@@ -681,6 +787,25 @@ impl ShareSpace {
         return ret - 1;
     }
 
+    pub fn IncrPendingClose(&self) -> u64 {
+        let ret = self.pendingCloseCnt.fetch_add(1, Ordering::SeqCst);
+        return ret + 1;
+    }
+
+    pub fn DecrPendingClose(&self) -> u64 {
+        let ret = self.pendingCloseCnt.fetch_sub(1, Ordering::SeqCst);
+        return ret - 1;
+    }
+
+    pub fn PendingCloseCnt(&self) -> u64 {
+        return self.pendingCloseCnt.load(Ordering::Relaxed);
+    }
+
+    // return: (delivered, coalesced)
+    pub fn FdNotifyStat(&self) -> (u64, u64) {
+        return (self.fdNotifyCnt.load(Ordering::Relaxed), self.fdNotifyCoalescedCnt.load(Ordering::Relaxed))
+    }
+
     #[inline]
     pub fn NeedHostProcess(&self) -> bool {
         match self.hostProcessor.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst) {
@@ -19,6 +19,7 @@ use super::super::linux_def::*;
 use super::super::kernel::guestfdnotifier::*;
 use super::super::socket_buf::*;
 use super::super::config::*;
+use super::super::vcpu_mgr::VcpuMask;
 
 #[repr(align(128))]
 #[derive(Clone, Debug)]
@@ -30,6 +31,7 @@ pub enum Msg {
 
     //Syscall
     Fallocate(Fallocate),
+    ReopenFd(ReopenFd),
     RenameAt(RenameAt),
     Ftruncate(Ftruncate),
     Seek(Seek),
@@ -62,6 +64,7 @@ pub enum Msg {
     FAccessAt(FAccessAt),
 
     Socket(Socket),
+    SocketPair(SocketPair),
     GetPeerName(GetPeerName),
     GetSockName(GetSockName),
     GetSockOpt(GetSockOpt),
@@ -72,6 +75,7 @@ pub enum Msg {
 
     RDMAListen(RDMAListen),
     RDMANotify(RDMANotify),
+    RDMAUdpSocket(RDMAUdpSocket),
 
     SchedGetAffinity(SchedGetAffinity),
     GetRandom(GetRandom),
@@ -94,13 +98,22 @@ pub enum Msg {
     IOAppend(IOAppend),
     IOAccept(IOAccept),
     IOConnect(IOConnect),
+    LoopbackListen(LoopbackListen),
+    LoopbackAccept(LoopbackAccept),
+    LoopbackShm(LoopbackShm),
+    SockRetrans(SockRetrans),
+    GetAcceptPeerInfo(GetAcceptPeerInfo),
     IORecvMsg(IORecvMsg),
     IOSendMsg(IOSendMsg),
+    IORecvMMsg(IORecvMMsg),
+    IOSendMMsg(IOSendMMsg),
+    SendFile(SendFile),
     MMapFile(MMapFile),
     MUnmap(MUnmap),
     NonBlockingPoll(NonBlockingPoll),
     NewTmpfsFile(NewTmpfsFile),
     IoUringEnter(IoUringEnter),
+    IoUringRegisterBufRing(IoUringRegisterBufRing),
     Statm(Statm),
     NewSocket(NewSocket),
     HostEpollWaitProcess(HostEpollWaitProcess),
@@ -112,11 +125,18 @@ pub enum Msg {
     SetTscOffset(SetTscOffset),
     TlbShootdown(TlbShootdown),
     Sysinfo(Sysinfo),
+    ReadEtcFile(ReadEtcFile),
+    ReadRouteDump(ReadRouteDump),
+    ReadSandboxIdentity(ReadSandboxIdentity),
+    ResolveHost(ResolveHost),
+    LoadCompatProfiles(LoadCompatProfiles),
+    LoadNatRules(LoadNatRules),
+    LoadNetPolicy(LoadNetPolicy),
 }
 
 #[derive(Clone, Default, Debug)]
 pub struct TlbShootdown {
-    pub vcpuMask: u64,
+    pub vcpuMask: VcpuMask,
 }
 
 #[derive(Clone, Default, Debug)]
@@ -124,6 +144,64 @@ pub struct Sysinfo {
     pub addr: u64,
 }
 
+#[derive(Clone, Default, Debug)]
+pub struct ReadEtcFile {
+    pub idx: usize,
+    pub buf: u64,
+    pub len: usize,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct ReadRouteDump {
+    pub msgType: u16,
+    pub buf: u64,
+    pub len: usize,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct ReadSandboxIdentity {
+    pub buf: u64,
+    pub len: usize,
+}
+
+// ResolveHost looks up the addresses of the host name, the host serves it in its resolver
+// threads and wakes up the task when it is done
+#[derive(Clone, Default, Debug)]
+pub struct ResolveHost {
+    pub name: u64,
+    pub nameLen: usize,
+    pub family: i32,
+    pub buf: u64,
+    pub len: usize,
+}
+
+// ResolvedAddr is the address entry of ResolveHost written in buf, the ipv4 address takes the
+// first 4 bytes of addr
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ResolvedAddr {
+    pub family: u32,
+    pub addr: [u8; 16],
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct LoadCompatProfiles {
+    pub addr: u64,
+    pub len: usize,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct LoadNatRules {
+    pub addr: u64,
+    pub len: usize,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct LoadNetPolicy {
+    pub addr: u64,
+    pub len: usize,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct Rdtsc {}
 
@@ -159,6 +237,14 @@ pub struct IoUringEnter {
     pub flags: u32,
 }
 
+#[derive(Clone, Default, Debug, Copy)]
+pub struct IoUringRegisterBufRing {
+    pub idx: usize,
+    pub ringAddr: u64,
+    pub entries: u32,
+    pub bgid: u16,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct InitPara {
     pub KernelPageTableRoot: u64,
@@ -190,6 +276,13 @@ pub struct Fallocate {
     pub len: i64,
 }
 
+// ReopenFd opens a new host file description of the host fd with the access mode of flags
+#[derive(Clone, Default, Debug)]
+pub struct ReopenFd {
+    pub fd: i32,
+    pub flags: i32,
+}
+
 // get vss/rss from /proc/self/statm
 #[derive(Clone, Default, Debug)]
 pub struct StatmInfo {
@@ -422,6 +515,15 @@ pub struct Socket {
     pub protocol: i32,
 }
 
+#[derive(Clone, Default, Debug)]
+pub struct SocketPair {
+    pub domain: i32,
+    pub type_: i32,
+    pub protocol: i32,
+    // address of the [i32; 2] which gets the hostfds
+    pub socketVect: u64,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct GetSockName {
     pub sockfd: i32,
@@ -499,6 +601,8 @@ pub enum RDMANotifyType {
     Write,
     RDMARead,
     RDMAWrite,
+    // the udp socket has written datagrams to its write buf
+    UDWrite,
 }
 
 impl Default for RDMANotifyType {
@@ -513,6 +617,14 @@ pub struct RDMANotify {
     pub typ: RDMANotifyType,
 }
 
+// RDMAUdpSocket hands the udp socket to qvisor, its datagrams are passed by the byte streams
+// of socketBuf and go by the UD queue pair when the peer is reachable by RDMA
+#[derive(Clone, Default, Debug)]
+pub struct RDMAUdpSocket {
+    pub sockfd: i32,
+    pub socketBuf: Arc<SocketBuff>,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct IOShutdown {
     pub sockfd: i32,
@@ -657,6 +769,32 @@ pub struct IOAccept {
     pub addrlen: u64,
 }
 
+#[derive(Clone, Default, Debug)]
+pub struct LoopbackListen {
+    pub fd: i32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct LoopbackAccept {
+    pub fd: i32,
+    pub addr: u64,
+    pub addrlen: u64,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct LoopbackShm {
+    pub fd: i32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct SockRetrans {}
+
+#[derive(Clone, Default, Debug)]
+pub struct GetAcceptPeerInfo {
+    pub fd: i32,
+    pub info: u64,
+}
+
 pub struct RDMAAcceptStruct {
     pub addr: TcpSockAddr,
     pub addrlen: u32,
@@ -715,6 +853,32 @@ pub struct IOSendMsg {
     pub blocking: bool,
 }
 
+#[derive(Clone, Default, Debug)]
+pub struct IORecvMMsg {
+    pub fd: i32,
+    //address of the MMsgHdr array
+    pub msgvec: u64,
+    pub vlen: u32,
+    pub flags: i32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct IOSendMMsg {
+    pub fd: i32,
+    //address of the MMsgHdr array
+    pub msgvec: u64,
+    pub vlen: u32,
+    pub flags: i32,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct SendFile {
+    pub outfd: i32,
+    pub infd: i32,
+    pub offset: i64,
+    pub count: u64,
+}
+
 #[derive(Clone, Default, Debug)]
 pub struct NewSocket {
     pub fd: i32
@@ -787,6 +951,8 @@ pub enum HostOutputMsg {
     WaitFDAsync(WaitFDAsync),
     EventfdWriteAsync(EventfdWriteAsync),
     PostRDMAConnect(u64),
+    CloseAsync(CloseAsync),
+    ShutdownAsync(ShutdownAsync),
 }
 
 impl Default for HostOutputMsg {
@@ -807,3 +973,14 @@ pub struct EventfdWriteAsync {
     pub fd: i32,
 }
 
+#[derive(Clone, Default, Debug, Copy)]
+pub struct CloseAsync {
+    pub fd: i32,
+}
+
+#[derive(Clone, Default, Debug, Copy)]
+pub struct ShutdownAsync {
+    pub fd: i32,
+    pub how: i32,
+}
+
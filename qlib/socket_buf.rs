@@ -14,10 +14,15 @@
 
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicI32;
+use core::sync::atomic::AtomicI64;
 use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
 use alloc::collections::vec_deque::VecDeque;
 use alloc::sync::Arc;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::string::String;
 use core::ops::Deref;
 use core::fmt;
 
@@ -25,11 +30,96 @@ use super::mutex::*;
 use super::bytestream::*;
 use super::linux_def::*;
 use super::common::*;
+use super::control_msg::RDMAConnStatsInfo;
+
+// SendFlushPolicy decides how the data written by the application leaves the write buf
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendFlushPolicy {
+    // the data is aggregated in the write buf and sent by the AsyncSend
+    Batch,
+    // TCP_NODELAY: a small write to the empty write buf is sent to the host socket directly
+    // so that it doesn't wait for the uring round trip
+    Immediate,
+}
+
+// the max write size sent directly by SendFlushPolicy::Immediate
+pub const DIRECT_SEND_MAX: usize = 16 * 1024;
+
+// the data held by TCP_CORK/MSG_MORE is sent when it reaches the bytes
+pub const CORK_MAX_BYTES: usize = 64 * 1024;
+
+// CorkFullLocked returns whether the held data of the write buf w is to be sent, the limit is
+// capped at half of the write buf so that the writer doesn't block on the full buf
+fn CorkFullLocked(w: &ByteStream) -> bool {
+    return w.AvailableDataSize() >= core::cmp::min(CORK_MAX_BYTES, w.BufSize() / 2)
+}
+
+// the page count range of the read/write buf sized by SO_RCVBUF/SO_SNDBUF
+pub const SOCKET_BUF_MIN_PAGES: u64 = 2;
+pub const SOCKET_BUF_MAX_PAGES: u64 = 256;
+
+// the max page count of the ring buf grown by the autotuning
+pub const SOCKET_BUF_AUTOTUNE_MAX_PAGES: u64 = 1024;
+// the max chunks whose receive time is kept, the later chunks are merged into the last one
+pub const RX_STAMP_CHUNKS: usize = 64;
+
+// the bytes of the socket bufs of the sandbox grown by the autotuning, it is capped by
+// SocketBufAutoTuneMB
+pub static SOCKET_BUF_TUNED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// ReserveTunedBytes reserves the memory of the ring buf growth, it fails when the sandbox
+// reaches limit
+pub fn ReserveTunedBytes(bytes: usize, limit: usize) -> bool {
+    let mut cur = SOCKET_BUF_TUNED_BYTES.load(Ordering::Relaxed);
+    loop {
+        if cur + bytes > limit {
+            return false
+        }
+
+        match SOCKET_BUF_TUNED_BYTES.compare_exchange(cur, cur + bytes, Ordering::Relaxed, Ordering::Relaxed) {
+            Ok(_) => return true,
+            Err(v) => cur = v,
+        }
+    }
+}
+
+// SocketBufPages returns the page count of the ring buffer for the SO_RCVBUF/SO_SNDBUF value.
+// Same as linux the value is doubled for the bookkeeping overhead, and the ring buffer size is
+// a power of two. 0 means the option is not set.
+pub fn SocketBufPages(val: usize) -> u64 {
+    if val == 0 {
+        return MemoryDef::DEFAULT_BUF_PAGE_COUNT
+    }
+
+    let pages = (2 * val as u64 + MemoryDef::PAGE_SIZE - 1) / MemoryDef::PAGE_SIZE;
+    let pages = pages.next_power_of_two();
+    if pages < SOCKET_BUF_MIN_PAGES {
+        return SOCKET_BUF_MIN_PAGES
+    }
+
+    if pages > SOCKET_BUF_MAX_PAGES {
+        return SOCKET_BUF_MAX_PAGES
+    }
+
+    return pages
+}
 
 pub struct SocketBuff {
+    // the write side is shut down by SHUT_WR, the writes fail with EPIPE
     pub wClosed: AtomicBool,
+    // the read side is closed by the FIN of the peer or SHUT_RD, the reads get the data left
+    // and then EOF
     pub rClosed: AtomicBool,
+    // notify EVENT_PENDING_SHUTDOWN when the write buf is drained
     pub pendingWShutdown: AtomicBool,
+    // the host SHUT_WR waits for the data in the write buf, it is done by the AsyncSend
+    // draining it so that the FIN follows the data
+    pub finPending: AtomicBool,
+    // SO_LINGER with zero timeout: the unsent data is dropped on close and the connection is reset
+    pub writeAbort: AtomicBool,
+    // the RDMA connection has fallen back to the tcp path, the guest notifies the plain read
+    // and write of the host socket
+    pub rdmaFallback: AtomicBool,
     pub error: AtomicI32,
 
     // used by RDMA data socket, used to sync with rdma remote peer for the local read buff free space size
@@ -39,14 +129,178 @@ pub struct SocketBuff {
     // to the peer in the rdmawrite packet to save rdmawrite call
     pub consumeReadData: AtomicU64,
 
+    // the receive time of the data not read yet, it is the software receive timestamp reported
+    // by SO_TIMESTAMP/SO_TIMESTAMPNS/SO_TIMESTAMPING
+    pub rxStamps: QMutex<RxStamps>,
+    // a receive timestamp option is set, the receive time is taken only when it is true
+    pub rxStampWanted: AtomicBool,
+
+    // SO_RCVLOWAT: min bytes of data for the socket to be readable
+    pub rcvLowat: AtomicUsize,
+
+    // the data in the write buf is held by TCP_CORK/MSG_MORE, there is no AsyncSend for it
+    pub sendHeld: AtomicBool,
+    // the space of the write buf is being read from the host file by sendfile, the other
+    // writers get EAGAIN until it is done
+    pub fileRead: AtomicBool,
+
+    // TCP_NODELAY: the flush policy of the write buf, see SendFlushPolicy
+    pub noDelay: AtomicBool,
+
+    // the read/write buf grows with the autotuning, it is off when SO_RCVBUF/SO_SNDBUF is set
+    pub rcvAutoTune: AtomicBool,
+    pub sndAutoTune: AtomicBool,
+    // the bytes of the growth reserved in SOCKET_BUF_TUNED_BYTES
+    pub tunedBytes: AtomicUsize,
+    // the host rtt in ns of the connection and the time it is fetched
+    pub rtt: AtomicI64,
+    pub rttTime: AtomicI64,
+
+    // idle reclaim: the count of the data transfers and the count seen by the last idle scan
+    pub activity: AtomicU64,
+    pub idleSeen: AtomicU64,
+    // the uring read is being canceled to shrink the read buf
+    pub idleShrink: AtomicBool,
+    // the page count of the read buf before the idle shrink, 0 when it is not shrunk
+    pub idleReadPages: AtomicU64,
+
+    // the counters of the RDMA data path, updated by qvisor
+    pub rdmaStats: RDMAStats,
+
+    // the data received into the bufs of the uring receive pool, it is ahead of the data of the
+    // read buf
+    pub pooledRead: QMutex<PooledRead>,
+    // the read buf is shrunk while the uring reads take the receive pool bufs, its size is kept
+    // in idleReadPages as the receive window
+    pub poolShrunk: AtomicBool,
+    // kTLS RX: the reads go to the host socket after the data read ahead, the uring read stops
+    // at its next completion
+    pub hostRead: AtomicBool,
+    pub readStopped: AtomicBool,
     pub readBuf: QMutex<ByteStream>,
     pub writeBuf: QMutex<ByteStream>,
 }
 
+// RDMAStats is the counters of the RDMA data path of the connection. The write counters are
+// the write imm work requests of the tcp connection or the UD sends of the udp socket, the recv
+// ones are their completions on the receiver.
+#[derive(Default, Debug)]
+pub struct RDMAStats {
+    pub writesPosted: AtomicU64,
+    pub bytesWritten: AtomicU64,
+    pub writeCompletions: AtomicU64,
+    pub recvCompletions: AtomicU64,
+    // the data waits in the write buf as the read buf of the peer is full
+    pub freespaceStalls: AtomicU64,
+    // the qp setup retries and the work requests failed after the retries of the device
+    pub retries: AtomicU64,
+    pub qpErrors: AtomicU64,
+}
+
+impl RDMAStats {
+    pub fn Write(&self, requests: usize, bytes: usize) {
+        self.writesPosted.fetch_add(requests as u64, Ordering::Relaxed);
+        self.bytesWritten.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn Inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn Info(&self, fd: i32, udp: bool, fallback: bool) -> RDMAConnStatsInfo {
+        return RDMAConnStatsInfo {
+            fd: fd,
+            udp: udp,
+            fallback: fallback,
+            writesPosted: self.writesPosted.load(Ordering::Relaxed),
+            bytesWritten: self.bytesWritten.load(Ordering::Relaxed),
+            writeCompletions: self.writeCompletions.load(Ordering::Relaxed),
+            recvCompletions: self.recvCompletions.load(Ordering::Relaxed),
+            freespaceStalls: self.freespaceStalls.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+            qpErrors: self.qpErrors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// RxStamps is the receive time of the data in the read buf and the receive pool bufs, one chunk
+// per read of the host socket
+#[derive(Default, Debug)]
+pub struct RxStamps {
+    // the bytes received so far
+    pub received: u64,
+    // the receive time of the next chunk, 0 when it is unknown
+    pub next: i64,
+    // the end offset and the receive time of the chunks not read yet
+    pub chunks: VecDeque<(u64, i64)>,
+}
+
+// PooledChunk is the data of a receive pool buf, the buf is given back to the pool when the data
+// is consumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PooledChunk {
+    pub bid: u16,
+    pub addr: u64,
+    pub len: usize,
+}
+
+// PooledRead is the data the uring reads of the socket put in the receive pool bufs. The readers
+// copy it from the pool bufs, there is no copy to the read buf.
+#[derive(Default)]
+pub struct PooledRead {
+    pub chunks: VecDeque<PooledChunk>,
+    pub size: usize,
+    // the uring read is stopped as the data fills the receive window
+    pub stopped: bool,
+    // gives the buf back to the receive pool
+    pub release: Option<fn(u16)>,
+}
+
+impl Drop for PooledRead {
+    fn drop(&mut self) {
+        self.Consume(self.size);
+    }
+}
+
+impl PooledRead {
+    pub fn Iovs(&self) -> Vec<IoVec> {
+        return self.chunks.iter().map(|c| IoVec { start: c.addr, len: c.len }).collect()
+    }
+
+    // Consume consumes count bytes and releases the bufs of the chunks consumed completely
+    pub fn Consume(&mut self, count: usize) {
+        assert!(count <= self.size, "PooledRead::Consume {} of {}", count, self.size);
+        self.size -= count;
+
+        let mut left = count;
+        while left > 0 {
+            let chunk = self.chunks.front_mut().unwrap();
+            if chunk.len > left {
+                chunk.addr += left as u64;
+                chunk.len -= left;
+                return
+            }
+
+            left -= chunk.len;
+            let bid = chunk.bid;
+            self.chunks.pop_front();
+            if let Some(release) = self.release {
+                release(bid);
+            }
+        }
+    }
+}
+
 impl fmt::Debug for SocketBuff {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "wClosed {:?}, rClosed {:?}, pendingWShutdown {:?}, error {:?}",
-               self.wClosed, self.wClosed, self.pendingWShutdown, self.error)
+        write!(f, "wClosed {:?}, rClosed {:?}, pendingWShutdown {:?}, finPending {:?}, error {:?}",
+               self.wClosed, self.rClosed, self.pendingWShutdown, self.finPending, self.error)
+    }
+}
+
+impl Drop for SocketBuff {
+    fn drop(&mut self) {
+        SOCKET_BUF_TUNED_BYTES.fetch_sub(self.tunedBytes.load(Ordering::Relaxed), Ordering::Relaxed);
     }
 }
 
@@ -58,14 +312,42 @@ impl Default for SocketBuff {
 
 impl SocketBuff {
     pub fn Init(pageCount: u64) -> Self {
+        return Self::InitWithSize(pageCount, pageCount)
+    }
+
+    // InitWithSize allocates the read buf and the write buf with their own page count
+    pub fn InitWithSize(readPageCount: u64, writePageCount: u64) -> Self {
         return Self {
             wClosed: AtomicBool::new(false),
             rClosed: AtomicBool::new(false),
             pendingWShutdown: AtomicBool::new(false),
+            finPending: AtomicBool::new(false),
+            writeAbort: AtomicBool::new(false),
+            rdmaFallback: AtomicBool::new(false),
             error: AtomicI32::new(0),
             consumeReadData: AtomicU64::new(0),
-            readBuf: QMutex::new(ByteStream::Init(pageCount)),
-            writeBuf: QMutex::new(ByteStream::Init(pageCount)),
+            rxStamps: QMutex::new(RxStamps::default()),
+            rxStampWanted: AtomicBool::new(false),
+            rcvLowat: AtomicUsize::new(1),
+            sendHeld: AtomicBool::new(false),
+            fileRead: AtomicBool::new(false),
+            noDelay: AtomicBool::new(false),
+            rcvAutoTune: AtomicBool::new(true),
+            sndAutoTune: AtomicBool::new(true),
+            tunedBytes: AtomicUsize::new(0),
+            rtt: AtomicI64::new(0),
+            rttTime: AtomicI64::new(0),
+            activity: AtomicU64::new(0),
+            idleSeen: AtomicU64::new(u64::MAX),
+            idleShrink: AtomicBool::new(false),
+            idleReadPages: AtomicU64::new(0),
+            rdmaStats: RDMAStats::default(),
+            pooledRead: QMutex::new(PooledRead::default()),
+            poolShrunk: AtomicBool::new(false),
+            hostRead: AtomicBool::new(false),
+            readStopped: AtomicBool::new(false),
+            readBuf: QMutex::new(ByteStream::Init(readPageCount)),
+            writeBuf: QMutex::new(ByteStream::Init(writePageCount)),
         }
     }
 
@@ -77,6 +359,406 @@ impl SocketBuff {
         return self.consumeReadData.swap(0, Ordering::Relaxed)
     }
 
+    pub fn RxStampWanted(&self) -> bool {
+        return self.rxStampWanted.load(Ordering::Relaxed)
+    }
+
+    // SetRxStampWanted turns on or off the receive timestamps. The data buffered when they are
+    // turned on has no timestamp, the chunks start after it.
+    pub fn SetRxStampWanted(&self, val: bool) {
+        if !val {
+            self.rxStampWanted.store(false, Ordering::Relaxed);
+            return
+        }
+
+        let mut stamps = self.rxStamps.lock();
+        if self.RxStampWanted() {
+            return
+        }
+
+        stamps.received = self.ReadableSize() as u64;
+        stamps.next = 0;
+        stamps.chunks.clear();
+        self.rxStampWanted.store(true, Ordering::Relaxed);
+    }
+
+    // SetRxTimestamp sets the receive time of the data produced next
+    pub fn SetRxTimestamp(&self, ns: i64) {
+        if !self.RxStampWanted() {
+            return
+        }
+
+        self.rxStamps.lock().next = ns;
+    }
+
+    // stampRx queues the chunk of the data produced with the receive time set before it
+    fn stampRx(&self, size: usize) {
+        if !self.RxStampWanted() {
+            return
+        }
+
+        let mut stamps = self.rxStamps.lock();
+        stamps.received += size as u64;
+        let chunk = (stamps.received, core::mem::replace(&mut stamps.next, 0));
+        if stamps.chunks.len() < RX_STAMP_CHUNKS {
+            stamps.chunks.push_back(chunk);
+        } else {
+            *stamps.chunks.back_mut().unwrap() = chunk;
+        }
+    }
+
+    // RxTimestamp returns the receive time of the last byte read, 0 when it is unknown, as linux
+    // reports the timestamp of the skb of it. peeked is the data read by MSG_PEEK, which is not
+    // consumed. The chunks read completely are dropped.
+    pub fn RxTimestamp(&self, peeked: usize) -> i64 {
+        let mut stamps = self.rxStamps.lock();
+        let consumed = stamps.received.saturating_sub(self.ReadableSize() as u64);
+        let end = consumed + peeked as u64;
+        let ts = match stamps.chunks.iter().find(|c| c.0 >= end) {
+            Some(c) => c.1,
+            None => stamps.chunks.back().map(|c| c.1).unwrap_or(0),
+        };
+
+        while stamps.chunks.front().map(|c| c.0 <= consumed).unwrap_or(false) {
+            stamps.chunks.pop_front();
+        }
+
+        return ts
+    }
+
+    pub fn RcvLowat(&self) -> usize {
+        return self.rcvLowat.load(Ordering::Relaxed)
+    }
+
+    pub fn SetRcvLowat(&self, val: usize) {
+        self.rcvLowat.store(val, Ordering::Relaxed)
+    }
+
+    // ReadTarget is the min bytes a blocking read of len bytes waits for
+    pub fn ReadTarget(&self, len: usize) -> usize {
+        let lowat = self.RcvLowat();
+        return if lowat < len { lowat } else { len }
+    }
+
+    // HoldLocked holds the send of the corked data which turned the write buf w non empty. It is
+    // called with the write buf locked so that the writers coming after it see the held send and
+    // don't leave their data to the cork timer.
+    pub fn HoldLocked(&self, w: &ByteStream) -> bool {
+        if CorkFullLocked(w) {
+            return false
+        }
+
+        self.sendHeld.store(true, Ordering::SeqCst);
+        return true
+    }
+
+    pub fn SendHeld(&self) -> bool {
+        return self.sendHeld.load(Ordering::SeqCst)
+    }
+
+    // ReleaseSend returns true for the caller which has to start sending the held data
+    pub fn ReleaseSend(&self) -> bool {
+        return self.sendHeld.swap(false, Ordering::SeqCst)
+    }
+
+    // CorkFull returns whether the held data is large enough to be sent
+    pub fn CorkFull(&self) -> bool {
+        return CorkFullLocked(&self.writeBuf.lock())
+    }
+
+    pub fn SetNoDelay(&self, val: bool) {
+        self.noDelay.store(val, Ordering::Relaxed)
+    }
+
+    pub fn FlushPolicy(&self) -> SendFlushPolicy {
+        if self.noDelay.load(Ordering::Relaxed) {
+            return SendFlushPolicy::Immediate
+        }
+
+        return SendFlushPolicy::Batch
+    }
+
+    pub fn SetAutoTune(&self, rcv: bool, snd: bool) {
+        self.rcvAutoTune.store(rcv, Ordering::Relaxed);
+        self.sndAutoTune.store(snd, Ordering::Relaxed);
+    }
+
+    pub fn RcvAutoTune(&self) -> bool {
+        return self.rcvAutoTune.load(Ordering::Relaxed)
+    }
+
+    pub fn SndAutoTune(&self) -> bool {
+        return self.sndAutoTune.load(Ordering::Relaxed)
+    }
+
+    // reserveGrowth reserves the memory for the ring of bufSize bytes to grow to pages
+    fn reserveGrowth(&self, bufSize: usize, pages: u64, limit: usize) -> bool {
+        let bytes = (pages * MemoryDef::PAGE_SIZE) as usize - bufSize;
+        if !ReserveTunedBytes(bytes, limit) {
+            return false
+        }
+
+        self.tunedBytes.fetch_add(bytes, Ordering::Relaxed);
+        return true
+    }
+
+    // TuneReadBuf grows the read buf when the app consumes more than half of it in one rtt. It is
+    // called after the full read buf is consumed, there is no uring read on the read buf until
+    // the caller restarts it. limit: the max bytes of the growth of the sandbox.
+    pub fn TuneReadBuf(&self, now: i64, rtt: i64, limit: usize) -> bool {
+        let mut buf = self.readBuf.lock();
+        buf.tune.Sample(now, rtt);
+        let bufSize = self.readWindow(&buf);
+        let pages = match buf.tune.Target(bufSize, SOCKET_BUF_AUTOTUNE_MAX_PAGES) {
+            None => return false,
+            Some(pages) => pages,
+        };
+
+        if !self.reserveGrowth(bufSize, pages, limit) {
+            return false
+        }
+
+        // the read buf shrunk for the receive pool grows its window only
+        if self.poolShrunk.load(Ordering::Relaxed) {
+            self.idleReadPages.store(pages, Ordering::Relaxed);
+        } else {
+            buf.Resize(pages);
+        }
+
+        return true
+    }
+
+    // TuneWriteBuf is called when the write buf is full, the growth is pending until the write
+    // buf is empty as there is an AsyncSend on it
+    pub fn TuneWriteBuf(&self, now: i64, rtt: i64, limit: usize) {
+        let mut buf = self.writeBuf.lock();
+        buf.tune.Sample(now, rtt);
+        if buf.tune.pendingPages != 0 {
+            return
+        }
+
+        let pages = match buf.tune.Target(buf.BufSize(), SOCKET_BUF_AUTOTUNE_MAX_PAGES) {
+            None => return,
+            Some(pages) => pages,
+        };
+
+        if self.reserveGrowth(buf.BufSize(), pages, limit) {
+            buf.tune.pendingPages = pages;
+        }
+    }
+
+    pub fn Touch(&self) {
+        self.activity.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // IdleScan returns whether there is no data transfer since the last scan
+    pub fn IdleScan(&self) -> bool {
+        let cur = self.activity.load(Ordering::Relaxed);
+        return self.idleSeen.swap(cur, Ordering::Relaxed) == cur
+    }
+
+    // StartIdleShrink returns whether the caller cancels the uring read of the empty read buf so
+    // that it is shrunk by IdleShrinkRead
+    pub fn StartIdleShrink(&self) -> bool {
+        if self.Error() != 0 || self.RClosed() || self.idleReadPages.load(Ordering::Relaxed) != 0 {
+            return false
+        }
+
+        {
+            let r = self.readBuf.lock();
+            if r.AvailableDataSize() != 0 || r.BufSize() <= (SOCKET_BUF_MIN_PAGES * MemoryDef::PAGE_SIZE) as usize {
+                return false
+            }
+        }
+
+        return self.idleShrink.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    // CancelIdleShrink is called when the uring read completes before the cancel
+    pub fn CancelIdleShrink(&self) {
+        self.idleShrink.store(false, Ordering::SeqCst)
+    }
+
+    // SetHostRead moves the reads to the host socket, it returns false when they are moved
+    // already
+    pub fn SetHostRead(&self) -> bool {
+        return !self.hostRead.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn HostRead(&self) -> bool {
+        return self.hostRead.load(Ordering::SeqCst)
+    }
+
+    // SetReadStopped is called when the uring read of the host read socket completes, no data
+    // enters the read buf after it
+    pub fn SetReadStopped(&self) {
+        self.readStopped.store(true, Ordering::SeqCst)
+    }
+
+    pub fn ReadStopped(&self) -> bool {
+        return self.readStopped.load(Ordering::SeqCst)
+    }
+
+    // IdleShrinkRead shrinks the read buf after its uring read is canceled by the idle scan. It
+    // returns the free buf for the restart of the read, None when the read is not canceled by it.
+    pub fn IdleShrinkRead(&self) -> Option<(u64, usize)> {
+        if !self.idleShrink.swap(false, Ordering::SeqCst) {
+            return None
+        }
+
+        let mut r = self.readBuf.lock();
+        let pages = r.BufSize() as u64 / MemoryDef::PAGE_SIZE;
+        if r.AvailableDataSize() == 0 && pages > SOCKET_BUF_MIN_PAGES {
+            // the reservation of the autotuning is kept for the regrow
+            r.Resize(SOCKET_BUF_MIN_PAGES);
+            self.idleReadPages.store(pages, Ordering::Relaxed);
+        }
+
+        return Some(r.GetSpaceBuf())
+    }
+
+    // IdleRegrowRead restores the size of the shrunk read buf. It is called after the full read
+    // buf is consumed, there is no uring read on it until the caller restarts it.
+    pub fn IdleRegrowRead(&self) -> bool {
+        // the read buf shrunk for the receive pool grows back when the pool is used up
+        if self.poolShrunk.load(Ordering::Relaxed) {
+            return false
+        }
+
+        let pages = self.idleReadPages.swap(0, Ordering::Relaxed);
+        if pages == 0 {
+            return false
+        }
+
+        self.readBuf.lock().Resize(pages);
+        return true
+    }
+
+    // PoolShrinkRead shrinks the empty read buf of the socket whose uring reads take the receive
+    // pool bufs, its size is kept as the receive window. There is no uring read on it yet.
+    pub fn PoolShrinkRead(&self) {
+        let mut r = self.readBuf.lock();
+        let pages = r.BufSize() as u64 / MemoryDef::PAGE_SIZE;
+        if r.AvailableDataSize() != 0 || pages <= SOCKET_BUF_MIN_PAGES || self.idleReadPages.load(Ordering::Relaxed) != 0 {
+            return
+        }
+
+        r.Resize(SOCKET_BUF_MIN_PAGES);
+        self.idleReadPages.store(pages, Ordering::Relaxed);
+        self.poolShrunk.store(true, Ordering::Relaxed);
+    }
+
+    // PoolRegrowRead restores the size of the read buf when the receive pool is used up and the
+    // uring read falls back to the read buf, it is called at the completion of the read
+    pub fn PoolRegrowRead(&self) {
+        let mut r = self.readBuf.lock();
+        if !self.poolShrunk.swap(false, Ordering::Relaxed) {
+            return
+        }
+
+        let pages = self.idleReadPages.swap(0, Ordering::Relaxed);
+        if pages != 0 {
+            r.Resize(pages);
+        }
+    }
+
+    // readWindow is the size of the data the socket holds, the read buf shrunk for the receive
+    // pool holds its data in the pool bufs
+    fn readWindow(&self, r: &ByteStream) -> usize {
+        if self.poolShrunk.load(Ordering::Relaxed) {
+            return (self.idleReadPages.load(Ordering::Relaxed) * MemoryDef::PAGE_SIZE) as usize
+        }
+
+        return r.BufSize()
+    }
+
+    // pooledWindow returns the space of the receive window for the pooled data, there is none
+    // when the read buf has data as the pooled data can't pass it
+    fn pooledWindow(&self, pooled: &PooledRead) -> usize {
+        let r = self.readBuf.lock();
+        if r.AvailableDataSize() != 0 {
+            return 0
+        }
+
+        return self.readWindow(&r).saturating_sub(pooled.size)
+    }
+
+    // PooledRecvLen returns the len of the next uring read into a receive pool buf, 0 when it
+    // goes to the read buf
+    pub fn PooledRecvLen(&self, max: usize) -> usize {
+        let pooled = self.pooledRead.lock();
+        return core::cmp::min(self.pooledWindow(&pooled), max)
+    }
+
+    // ProducePooled queues the data the uring read put in a receive pool buf, the read buf is
+    // empty. It returns whether the readers are notified and whether the uring read goes on, the
+    // read stopped on the full receive window is restarted by the reader.
+    pub fn ProducePooled(&self, chunk: PooledChunk, release: fn(u16)) -> (bool, bool) {
+        self.stampRx(chunk.len);
+        let mut pooled = self.pooledRead.lock();
+        pooled.release = Some(release);
+        let trigger = pooled.size == 0;
+        pooled.size += chunk.len;
+        pooled.chunks.push_back(chunk);
+
+        let more = self.pooledWindow(&pooled) > 0;
+        pooled.stopped = !more;
+        return (trigger, more)
+    }
+
+    // ConsumePooled consumes the pooled data read by the app, it returns whether the reader
+    // restarts the uring read stopped on the full receive window, which is half free
+    pub fn ConsumePooled(&self, pooled: &mut PooledRead, count: usize) -> bool {
+        pooled.Consume(count);
+        self.readBuf.lock().tune.Account(count);
+        self.Touch();
+
+        if pooled.stopped && self.pooledWindow(pooled) >= self.RcvBufSize() / 2 {
+            pooled.stopped = false;
+            return true
+        }
+
+        return false
+    }
+
+    // ReadableSize is the data of the receive pool bufs and the read buf
+    pub fn ReadableSize(&self) -> usize {
+        let pooled = self.pooledRead.lock();
+        return pooled.size + self.readBuf.lock().AvailableDataSize()
+    }
+
+    // IdleShrinkWrite shrinks the empty write buf, there is no AsyncSend on it. It grows back to
+    // its size with the next write as the pending growth of the autotuning.
+    pub fn IdleShrinkWrite(&self) {
+        if self.SendHeld() {
+            return
+        }
+
+        let mut w = self.writeBuf.lock();
+        let pages = w.BufSize() as u64 / MemoryDef::PAGE_SIZE;
+        if w.AvailableDataSize() != 0 || w.tune.pendingPages != 0 || pages <= SOCKET_BUF_MIN_PAGES {
+            return
+        }
+
+        w.Resize(SOCKET_BUF_MIN_PAGES);
+        w.tune.pendingPages = pages;
+    }
+
+    // RcvBufSize is the size of the read buf, the idle shrink is not visible to the app
+    pub fn RcvBufSize(&self) -> usize {
+        let pages = self.idleReadPages.load(Ordering::Relaxed);
+        if pages != 0 {
+            return (pages * MemoryDef::PAGE_SIZE) as usize
+        }
+
+        return self.readBuf.lock().BufSize()
+    }
+
+    pub fn SndBufSize(&self) -> usize {
+        let w = self.writeBuf.lock();
+        return core::cmp::max(w.BufSize(), (w.tune.pendingPages * MemoryDef::PAGE_SIZE) as usize)
+    }
+
     pub fn ReadBuf(&self) -> (u64, usize) {
         return self.readBuf.lock().GetRawBuf();
     }
@@ -93,12 +775,32 @@ impl SocketBuff {
         self.pendingWShutdown.store(true, Ordering::SeqCst)
     }
 
+    pub fn ClearPendingWriteShutdown(&self) {
+        self.pendingWShutdown.store(false, Ordering::SeqCst)
+    }
+
+    pub fn WriteAbort(&self) -> bool {
+        self.writeAbort.load(Ordering::SeqCst)
+    }
+
+    pub fn SetWriteAbort(&self) {
+        self.writeAbort.store(true, Ordering::SeqCst)
+    }
+
+    pub fn RDMAFallback(&self) -> bool {
+        self.rdmaFallback.load(Ordering::SeqCst)
+    }
+
+    pub fn SetRDMAFallback(&self) {
+        self.rdmaFallback.store(true, Ordering::SeqCst)
+    }
+
     pub fn HasWriteData(&self) -> bool {
         return self.writeBuf.lock().AvailableDataSize() > 0;
     }
 
     pub fn HasReadData(&self) -> bool {
-        return self.readBuf.lock().AvailableDataSize() > 0;
+        return self.ReadableSize() > 0;
     }
 
     pub fn WriteBufAvailableDataSize(&self) -> usize {
@@ -107,16 +809,33 @@ impl SocketBuff {
 
     pub fn Events(&self) -> EventMask {
         let mut event = EventMask::default();
-        if self.readBuf.lock().AvailableDataSize() > 0 {
+        // the low watermark larger than the buffer would never be reached
+        let (data, bufSize) = {
+            let pooled = self.pooledRead.lock();
+            let r = self.readBuf.lock();
+            (pooled.size + r.AvailableDataSize(), self.readWindow(&r))
+        };
+        if data > 0 && data >= core::cmp::min(self.RcvLowat(), bufSize) {
             event |= EVENT_IN;
-        } else if self.RClosed() || self.WClosed() {
+        } else if self.RClosed() {
+            // EOF is readable
             event |= EVENT_IN
         }
 
-        if self.writeBuf.lock().AvailableSpace() > 0 {
+        // same as linux, the socket hangs up only when both sides are shut down
+        if self.RClosed() {
+            event |= EVENT_RDHUP;
+            if self.WClosed() {
+                event |= EVENT_HUP;
+            }
+        }
+
+        // the shut down write side is writable so that the writers get EPIPE
+        if self.WClosed() || self.writeBuf.lock().AvailableSpace() > 0 {
             event |= EVENT_OUT;
         }
 
+        // the error is latched until it is consumed by SO_ERROR
         if self.Error() != 0 {
             event |= EVENT_ERR;
         }
@@ -124,6 +843,13 @@ impl SocketBuff {
         return event
     }
 
+    pub fn DebugString(&self) -> String {
+        return format!("rbuf {}/{} wbuf {}/{} rclosed {} wclosed {} err {} flush {:?}",
+                       self.readBuf.lock().AvailableDataSize(), self.readBuf.lock().BufSize(),
+                       self.writeBuf.lock().AvailableDataSize(), self.writeBuf.lock().BufSize(),
+                       self.RClosed(), self.WClosed(), self.Error(), self.FlushPolicy())
+    }
+
     pub fn WClosed(&self) -> bool {
         self.wClosed.load(Ordering::SeqCst)
     }
@@ -140,6 +866,40 @@ impl SocketBuff {
         self.rClosed.store(true, Ordering::SeqCst)
     }
 
+    // ReadClosedEvents is the events of the read side closed by the peer FIN or SHUT_RD
+    pub fn ReadClosedEvents(&self) -> EventMask {
+        let mut mask = EVENT_IN | EVENT_RDHUP;
+        if self.WClosed() {
+            mask |= EVENT_HUP;
+        }
+
+        return mask
+    }
+
+    // ShutdownWrite shuts down the write side for SHUT_WR. It returns true when the write buf
+    // has data, the host SHUT_WR is then left to the AsyncSend draining it by TakeFinPending.
+    pub fn ShutdownWrite(&self) -> bool {
+        let w = self.writeBuf.lock();
+        self.SetWClosed();
+        if w.AvailableDataSize() == 0 {
+            return false
+        }
+
+        // it is set with the write buf locked, the AsyncSend consuming the last data sees it
+        self.finPending.store(true, Ordering::SeqCst);
+        return true
+    }
+
+    // TakeFinPending returns whether the caller does the pending host SHUT_WR, it is called by
+    // the AsyncSend after the write buf is drained
+    pub fn TakeFinPending(&self) -> bool {
+        if !self.finPending.load(Ordering::SeqCst) || self.writeBuf.lock().AvailableDataSize() > 0 {
+            return false
+        }
+
+        return self.finPending.swap(false, Ordering::SeqCst)
+    }
+
     pub fn Error(&self) -> i32 {
         self.error.load(Ordering::SeqCst)
     }
@@ -148,6 +908,18 @@ impl SocketBuff {
         self.error.store(err, Ordering::SeqCst)
     }
 
+    // ConsumeErr returns and clears the pending error for SO_ERROR. The connection
+    // is not usable after the error: read returns EOF and write returns EPIPE.
+    pub fn ConsumeErr(&self) -> i32 {
+        let err = self.error.swap(0, Ordering::SeqCst);
+        if err != 0 {
+            self.SetRClosed();
+            self.SetWClosed();
+        }
+
+        return err
+    }
+
     // get iovs(max 2 iovs) for free read buf space
     // ret: 0: no more space, 1: 1 iov, 2: 2 iovs
     pub fn GetFreeReadIovs(&self) -> (u64, usize) {
@@ -159,10 +931,12 @@ impl SocketBuff {
     }
 
     pub fn ProduceReadBuf(&self, size: usize) -> bool {
+        self.stampRx(size);
         return self.readBuf.lock().Produce(size);
     }
 
     pub fn ProduceAndGetFreeReadBuf(&self, size: usize) -> (bool, u64, usize) {
+        self.stampRx(size);
         let mut r = self.readBuf.lock();
         let trigger = r.Produce(size);
         let (addr, size) = r.GetSpaceBuf();
@@ -187,9 +961,163 @@ impl SocketBuff {
     pub fn GetAvailableWriteBuf(&self) -> (u64, usize) {
         return self.writeBuf.lock().GetDataBuf();
     }
+
+    // ReserveFileRead reserves the space of the write buf for the read of len bytes of the host
+    // file, the read is done with the write buf unlocked. With link the space of the empty write
+    // buf is produced for the send linked to the read.
+    // ret: (addr, the size to read, whether the send is linked)
+    pub fn ReserveFileRead(&self, len: usize, link: bool) -> Result<(u64, usize, bool)> {
+        if self.Error() != 0 {
+            return Err(Error::SysError(self.Error()));
+        }
+
+        if self.WClosed() {
+            return Err(Error::SysError(SysErr::EPIPE))
+        }
+
+        let mut buf = self.writeBuf.lock();
+        if self.fileRead.load(Ordering::SeqCst) {
+            return Err(Error::SysError(SysErr::EAGAIN));
+        }
+
+        buf.GrowPending();
+        let (addr, size) = buf.GetSpaceBuf();
+        if size == 0 {
+            return Err(Error::SysError(SysErr::EAGAIN));
+        }
+
+        let size = core::cmp::min(size, len);
+        // the empty write buf has no AsyncSend, the linked send is the only one
+        let linked = link && buf.AvailableDataSize() == 0 && !self.SendHeld();
+        if linked {
+            buf.Produce(size);
+        }
+
+        self.fileRead.store(true, Ordering::SeqCst);
+        return Ok((addr, size, linked))
+    }
+
+    // CommitFileRead queues the cnt bytes read to the space reserved by ReserveFileRead. The
+    // linked send is canceled by the short read, the data read is then sent as the written data.
+    // It returns the data buf when the AsyncSend has to be started, the corked data is held.
+    pub fn CommitFileRead(&self, size: usize, cnt: usize, linked: bool, cork: bool) -> Option<(u64, usize)> {
+        let mut buf = self.writeBuf.lock();
+        let trigger = if linked {
+            buf.Truncate(size - cnt);
+            cnt < size
+        } else {
+            buf.Produce(cnt)
+        };
+
+        self.fileRead.store(false, Ordering::SeqCst);
+        if cnt == 0 {
+            return None
+        }
+
+        buf.tune.Account(cnt);
+        self.Touch();
+        if !trigger || (cork && self.HoldLocked(&buf)) {
+            return None
+        }
+
+        return Some(buf.GetDataBuf())
+    }
+
+    // FileReadPending returns whether the space of the write buf is reserved by sendfile
+    pub fn FileReadPending(&self) -> bool {
+        return self.fileRead.load(Ordering::SeqCst)
+    }
 }
 
 pub const TCP_ADDR_LEN : usize = 128;
+// the buffer of the TCP_INFO snapshot, it is larger than the struct tcp_info of the host
+pub const TCP_INFO_MAX_LEN : usize = 256;
+
+// AcceptPeerInfo is the peer info of an accepted connection, it is fetched by one host call
+// for the getpeername, SO_PEERCRED and TCP_INFO which most servers issue right after accept
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct AcceptPeerInfo {
+    // the host getpeername, addrLen is 0 when it is used or not available
+    pub addr: TcpSockAddr,
+    pub addrLen: u32,
+
+    // SO_PEERCRED of the unix socket
+    pub hasCred: bool,
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+
+    // the TCP_INFO snapshot of the tcp socket, tcpInfoLen is 0 when it is used or not available
+    pub tcpInfoLen: u32,
+    pub tcpInfo: [u8; TCP_INFO_MAX_LEN],
+}
+
+impl Default for AcceptPeerInfo {
+    fn default() -> Self {
+        return Self {
+            addr: TcpSockAddr::default(),
+            addrLen: 0,
+            hasCred: false,
+            pid: 0,
+            uid: 0,
+            gid: 0,
+            tcpInfoLen: 0,
+            tcpInfo: [0; TCP_INFO_MAX_LEN],
+        }
+    }
+}
+
+impl AcceptPeerInfo {
+    // TakePeerAddr returns the peer address normalized to the sockaddr of the socket family,
+    // it is None when the host gives another family, e.g. the inet peer of the loopback
+    // connection for a unix socket. The address answers only the first getpeername as the
+    // connection may be closed later.
+    pub fn TakePeerAddr(&mut self, family: i32) -> Option<Vec<u8>> {
+        let len = core::cmp::min(self.addrLen as usize, self.addr.data.len());
+        self.addrLen = 0;
+        if len < 2 {
+            return None
+        }
+
+        let data = &self.addr.data;
+        if u16::from_ne_bytes([data[0], data[1]]) as i32 != family {
+            return None
+        }
+
+        let len = match family {
+            AFType::AF_INET => core::cmp::min(len, SocketSize::SIZEOF_SOCKADDR_INET4),
+            AFType::AF_INET6 => core::cmp::min(len, SocketSize::SIZEOF_SOCKADDR_INET6),
+            _ => len,
+        };
+
+        return Some(data[..len].to_vec())
+    }
+
+    // TakeTcpInfo returns the TCP_INFO snapshot, it is only good for the first TCP_INFO
+    pub fn TakeTcpInfo(&mut self) -> Option<Vec<u8>> {
+        let len = core::cmp::min(self.tcpInfoLen as usize, self.tcpInfo.len());
+        self.tcpInfoLen = 0;
+        if len == 0 {
+            return None
+        }
+
+        return Some(self.tcpInfo[..len].to_vec())
+    }
+
+    // Cred returns the SO_PEERCRED ucred, the peer credential doesn't change
+    pub fn Cred(&self) -> Option<[u8; 12]> {
+        if !self.hasCred {
+            return None
+        }
+
+        let mut cred = [0; 12];
+        cred[0..4].copy_from_slice(&self.pid.to_ne_bytes());
+        cred[4..8].copy_from_slice(&self.uid.to_ne_bytes());
+        cred[8..12].copy_from_slice(&self.gid.to_ne_bytes());
+        return Some(cred)
+    }
+}
 
 #[derive(Default, Debug)]
 pub struct AcceptItem {
@@ -197,6 +1125,59 @@ pub struct AcceptItem {
     pub addr: TcpSockAddr,
     pub len: u32,
     pub sockBuf: Arc<SocketBuff>,
+
+    // for error tagged item, the host accept fails with a pending network error,
+    // there is no fd and the error is returned by the guest accept in queue order
+    pub error: i32,
+
+    // the peer info fetched when the connection is queued, None when it is not fetched
+    pub peerInfo: Option<Box<AcceptPeerInfo>>,
+
+    // the connection of another sandbox on the host over the shared memory
+    pub loopback: bool,
+}
+
+impl AcceptItem {
+    pub fn NewErr(error: i32) -> Self {
+        return Self {
+            fd: -1,
+            error: error,
+            ..Default::default()
+        }
+    }
+
+    // the error the guest accept should return for the item, 0 for a healthy connection
+    pub fn Err(&self) -> i32 {
+        if self.error != 0 {
+            return self.error
+        }
+
+        // the connection is reset while sitting in the accept queue,
+        // don't hand a dead fd to the application
+        if self.fd >= 0 && self.sockBuf.Error() != 0 {
+            return SysErr::ECONNABORTED
+        }
+
+        return 0
+    }
+}
+
+// the accept errors caused by a pending network error of the new connection.
+// linux passes them to the application, the listening socket is still usable.
+pub fn IsTransientAcceptErr(errno: i32) -> bool {
+    match errno {
+        SysErr::ECONNABORTED |
+        SysErr::EPROTO |
+        SysErr::ENETDOWN |
+        SysErr::ENOPROTOOPT |
+        SysErr::EHOSTDOWN |
+        SysErr::ENONET |
+        SysErr::EHOSTUNREACH |
+        SysErr::EOPNOTSUPP |
+        SysErr::ENETUNREACH |
+        SysErr::EPERM => return true,
+        _ => return false,
+    }
 }
 
 #[derive(Default, Clone,  Debug)]
@@ -216,6 +1197,17 @@ pub struct AcceptQueueIntern {
     pub queueLen: usize,
     pub error: i32,
     pub total: u64,
+
+    // every queued connection holds a host fd. The host accept is paused when the queue
+    // reaches the high watermark and resumed when the application drains it to the low
+    // watermark, the connections in between wait in the host listen backlog.
+    pub highWatermark: usize,
+    pub lowWatermark: usize,
+    pub paused: bool,
+
+    // SO_RCVBUF/SO_SNDBUF of the listening socket, they size the buffers of the accepted sockets
+    pub rcvBuf: usize,
+    pub sndBuf: usize,
 }
 
 impl AcceptQueueIntern {
@@ -227,34 +1219,89 @@ impl AcceptQueueIntern {
         return self.error
     }
 
-    pub fn SetQueueLen(&mut self, len: usize) {
+    // limit: the max host fds held by the queue, 0 means the backlog
+    pub fn SetQueueLen(&mut self, len: usize, limit: usize) {
         self.queueLen = len;
+        self.highWatermark = if limit == 0 || limit > len {
+            len
+        } else {
+            limit
+        };
+        self.lowWatermark = self.highWatermark / 2;
     }
 
     pub fn HasSpace(&self) -> bool {
-        return self.queue.len() < self.queueLen
+        return !self.paused && self.queue.len() < self.highWatermark
+    }
+
+    pub fn SetSockBufSize(&mut self, rcvBuf: usize, sndBuf: usize) {
+        self.rcvBuf = rcvBuf;
+        self.sndBuf = sndBuf;
+    }
+
+    // NewSocketBuff allocates the buffer of an accepted socket
+    pub fn NewSocketBuff(&self) -> SocketBuff {
+        return SocketBuff::InitWithSize(SocketBufPages(self.rcvBuf), SocketBufPages(self.sndBuf))
+    }
+
+    // the host accept stops when there is no space
+    fn CheckSpace(&mut self) -> bool {
+        if self.queue.len() < self.highWatermark {
+            return true
+        }
+
+        self.paused = true;
+        return false
     }
 
     //return: (trigger, hasSpace)
-    pub fn EnqSocket(&mut self, fd: i32, addr: TcpSockAddr, len: u32, sockBuf: Arc<SocketBuff>) -> (bool, bool) {
+    pub fn EnqSocket(&mut self, fd: i32, addr: TcpSockAddr, len: u32, sockBuf: Arc<SocketBuff>, peerInfo: Option<Box<AcceptPeerInfo>>) -> (bool, bool) {
         let item = AcceptItem {
             fd: fd,
             addr: addr,
             len: len,
             sockBuf: sockBuf,
+            error: 0,
+            peerInfo: peerInfo,
+            loopback: false,
         };
 
         self.queue.push_back(item);
         self.total += 1;
         let trigger = self.queue.len() == 1;
-        return (trigger, self.queue.len() < self.queueLen);
+        return (trigger, self.CheckSpace());
     }
 
+    // EnqRedirected queues the connection which is redirected from another listener by the
+    // nat rules, it doesn't hold the host accept of this listener. The connection is returned
+    // when the queue is at the high watermark.
+    pub fn EnqRedirected(&mut self, item: AcceptItem) -> Option<AcceptItem> {
+        if self.queue.len() >= self.highWatermark {
+            return Some(item)
+        }
+
+        self.queue.push_back(item);
+        self.total += 1;
+        return None
+    }
 
+    //return: (trigger, hasSpace)
+    pub fn EnqError(&mut self, error: i32) -> (bool, bool) {
+        self.queue.push_back(AcceptItem::NewErr(error));
+        let trigger = self.queue.len() == 1;
+        return (trigger, self.CheckSpace());
+    }
+
+    //return: (trigger, item), trigger means the paused host accept has to be resumed
     pub fn DeqSocket(&mut self) -> (bool, Result<AcceptItem>) {
-        let trigger = self.queue.len() == self.queueLen;
+        let item = self.queue.pop_front();
 
-        match self.queue.pop_front() {
+        let trigger = self.paused && self.queue.len() <= self.lowWatermark;
+        if trigger {
+            self.paused = false;
+        }
+
+        match item {
             None => {
                 if self.error != 0 {
                     return (false, Err(Error::SysError(self.error)))
@@ -281,3 +1328,362 @@ impl AcceptQueueIntern {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket_buff_events() {
+        let buf = SocketBuff::Init(2);
+        assert_eq!(buf.Events(), EVENT_OUT);
+
+        buf.SetRClosed();
+        assert_eq!(buf.Events(), EVENT_IN | EVENT_RDHUP | EVENT_OUT);
+
+        buf.SetWClosed();
+        assert_eq!(buf.Events(), EVENT_IN | EVENT_RDHUP | EVENT_HUP | EVENT_OUT);
+        assert_eq!(buf.ReadClosedEvents(), EVENT_IN | EVENT_RDHUP | EVENT_HUP);
+    }
+
+    #[test]
+    fn test_socket_buff_half_close() {
+        // SHUT_WR with the empty write buf shuts down the host socket at once
+        let buf = SocketBuff::Init(2);
+        assert!(!buf.ShutdownWrite());
+        assert!(!buf.TakeFinPending());
+        // the read side still waits for the data, the writers get EPIPE
+        assert_eq!(buf.Events(), EVENT_OUT);
+
+        // the FIN follows the data in the write buf
+        let buf = SocketBuff::Init(2);
+        buf.writeBuf.lock().Produce(100);
+        assert!(buf.ShutdownWrite());
+        assert!(!buf.TakeFinPending());
+        buf.writeBuf.lock().Consume(100);
+        assert!(buf.TakeFinPending());
+        assert!(!buf.TakeFinPending());
+
+        // the data received before the peer FIN is read before EOF, no hangup before SHUT_WR
+        let buf = SocketBuff::Init(2);
+        buf.ProduceReadBuf(10);
+        buf.SetRClosed();
+        assert_eq!(buf.Events(), EVENT_IN | EVENT_RDHUP | EVENT_OUT);
+        assert_eq!(buf.ReadClosedEvents(), EVENT_IN | EVENT_RDHUP);
+    }
+
+    #[test]
+    fn test_socket_buff_lowat() {
+        let buf = SocketBuff::Init(2);
+        buf.SetRcvLowat(100);
+        assert_eq!(buf.ReadTarget(10), 10);
+        assert_eq!(buf.ReadTarget(1000), 100);
+
+        buf.ProduceReadBuf(50);
+        assert!(buf.Events() & EVENT_IN == 0);
+        buf.ProduceReadBuf(50);
+        assert!(buf.Events() & EVENT_IN != 0);
+    }
+
+    #[test]
+    fn test_socket_buff_rx_timestamp() {
+        let buf = SocketBuff::Init(2);
+        // the receive time is not taken without a timestamp option
+        buf.SetRxTimestamp(100);
+        buf.ProduceReadBuf(10);
+        assert_eq!(buf.rxStamps.lock().chunks.len(), 0);
+        buf.readBuf.lock().Consume(10);
+
+        buf.SetRxStampWanted(true);
+        buf.SetRxTimestamp(100);
+        buf.ProduceReadBuf(10);
+        buf.SetRxTimestamp(200);
+        buf.ProduceReadBuf(10);
+
+        // the peek reports the chunk of the last byte peeked
+        assert_eq!(buf.RxTimestamp(5), 100);
+        assert_eq!(buf.RxTimestamp(15), 200);
+
+        // the read reports the chunk of the last byte read and drops the chunks read
+        buf.readBuf.lock().Consume(10);
+        assert_eq!(buf.RxTimestamp(0), 100);
+        assert_eq!(buf.rxStamps.lock().chunks.len(), 1);
+        buf.readBuf.lock().Consume(5);
+        assert_eq!(buf.RxTimestamp(0), 200);
+        buf.readBuf.lock().Consume(5);
+        assert_eq!(buf.RxTimestamp(0), 200);
+        assert_eq!(buf.rxStamps.lock().chunks.len(), 0);
+
+        // the data produced without the receive time has no timestamp
+        buf.ProduceReadBuf(10);
+        buf.readBuf.lock().Consume(10);
+        assert_eq!(buf.RxTimestamp(0), 0);
+    }
+
+    #[test]
+    fn test_socket_buff_consume_err() {
+        let buf = SocketBuff::Init(2);
+        buf.SetErr(SysErr::ECONNRESET);
+        assert!(buf.Events() & EVENT_ERR != 0);
+        // the error is latched until SO_ERROR
+        assert!(buf.Events() & EVENT_ERR != 0);
+
+        assert_eq!(buf.ConsumeErr(), SysErr::ECONNRESET);
+        assert_eq!(buf.ConsumeErr(), 0);
+        assert!(buf.Events() & EVENT_ERR == 0);
+        assert!(buf.RClosed() && buf.WClosed());
+    }
+
+    #[test]
+    fn test_socket_buf_pages() {
+        assert_eq!(SocketBufPages(0), MemoryDef::DEFAULT_BUF_PAGE_COUNT);
+        assert_eq!(SocketBufPages(1), SOCKET_BUF_MIN_PAGES);
+        // 2 * 100KB is 50 pages
+        assert_eq!(SocketBufPages(100 * 1024), 64);
+        assert_eq!(SocketBufPages(i32::MAX as usize), SOCKET_BUF_MAX_PAGES);
+
+        let mut queue = AcceptQueueIntern::default();
+        queue.SetSockBufSize(4096, 0);
+        let buf = queue.NewSocketBuff();
+        assert_eq!(buf.readBuf.lock().BufSize(), 2 * MemoryDef::PAGE_SIZE as usize);
+        assert_eq!(buf.writeBuf.lock().BufSize(), (MemoryDef::DEFAULT_BUF_PAGE_COUNT * MemoryDef::PAGE_SIZE) as usize);
+    }
+
+    #[test]
+    fn test_accept_queue_watermark() {
+        let mut queue = AcceptQueueIntern::default();
+        queue.SetQueueLen(8, 4);
+        assert_eq!((queue.highWatermark, queue.lowWatermark), (4, 2));
+
+        let sockBuf = Arc::new(SocketBuff::Init(2));
+        for i in 0..3 {
+            assert_eq!(queue.EnqSocket(i, TcpSockAddr::default(), 0, sockBuf.clone(), None).1, true);
+        }
+        assert_eq!(queue.EnqSocket(3, TcpSockAddr::default(), 0, sockBuf.clone(), None).1, false);
+        assert!(!queue.HasSpace());
+
+        // resumed only after the queue is drained to the low watermark
+        assert_eq!(queue.DeqSocket().0, false);
+        assert_eq!(queue.DeqSocket().0, true);
+        assert!(queue.HasSpace());
+    }
+
+    #[test]
+    fn test_accept_queue_redirected() {
+        let mut queue = AcceptQueueIntern::default();
+        queue.SetQueueLen(8, 2);
+
+        let item = |fd| AcceptItem { fd: fd, sockBuf: Arc::new(SocketBuff::Init(2)), ..Default::default() };
+        assert!(queue.EnqRedirected(item(1)).is_none());
+        assert!(queue.EnqRedirected(item(2)).is_none());
+
+        // the redirected connection is refused at the high watermark and the host accept of
+        // the listener is not paused by it
+        assert_eq!(queue.EnqRedirected(item(3)).map(|i| i.fd), Some(3));
+        assert_eq!(queue.queue.len(), 2);
+        assert!(!queue.paused);
+
+        assert_eq!(queue.DeqSocket().1.map(|i| i.fd), Ok(1));
+        assert!(queue.EnqRedirected(item(3)).is_none());
+    }
+
+    #[test]
+    fn test_buf_autotune() {
+        let mut tune = BufAutoTune::default();
+        tune.Sample(1000, 100);
+        // 4 pages in 1000ns is 2 pages in the rtt of 500ns
+        tune.Account(4 * MemoryDef::PAGE_SIZE as usize);
+        assert_eq!(tune.Sample(2000, 500), 2 * MemoryDef::PAGE_SIZE as usize);
+        assert_eq!(tune.Target(4 * MemoryDef::PAGE_SIZE as usize, 16), None);
+        assert_eq!(tune.Target(2 * MemoryDef::PAGE_SIZE as usize, 16), Some(4));
+        assert_eq!(tune.Target(2 * MemoryDef::PAGE_SIZE as usize, 2), None);
+
+        let mut stream = ByteStream::Init(1);
+        let data = [7u8; 100];
+        stream.write(&data).unwrap();
+        stream.Resize(2);
+        assert_eq!(stream.BufSize(), 2 * MemoryDef::PAGE_SIZE as usize);
+        let mut out = [0u8; 200];
+        assert_eq!(stream.read(&mut out).unwrap().1, 100);
+        assert_eq!(&out[..100], &data[..]);
+
+        let buf = SocketBuff::Init(2);
+        buf.TuneWriteBuf(1000, 100, 0);
+        assert!(!ReserveTunedBytes(1, 0));
+        assert_eq!(buf.writeBuf.lock().tune.pendingPages, 0);
+    }
+
+    #[test]
+    fn test_idle_shrink() {
+        let page = MemoryDef::PAGE_SIZE as usize;
+        let buf = SocketBuff::Init(8);
+        // the first scan only records the activity
+        assert!(!buf.IdleScan());
+        assert!(buf.IdleScan());
+        buf.Touch();
+        assert!(!buf.IdleScan());
+
+        assert!(buf.StartIdleShrink());
+        assert!(!buf.StartIdleShrink());
+        assert!(buf.IdleShrinkRead().is_some());
+        assert!(buf.IdleShrinkRead().is_none());
+        assert_eq!(buf.readBuf.lock().BufSize(), 2 * page);
+        assert_eq!(buf.RcvBufSize(), 8 * page);
+        assert!(buf.IdleRegrowRead());
+        assert!(!buf.IdleRegrowRead());
+        assert_eq!(buf.readBuf.lock().BufSize(), 8 * page);
+
+        buf.IdleShrinkWrite();
+        assert_eq!(buf.writeBuf.lock().BufSize(), 2 * page);
+        assert_eq!(buf.SndBufSize(), 8 * page);
+        buf.writeBuf.lock().GrowPending();
+        assert_eq!(buf.writeBuf.lock().BufSize(), 8 * page);
+    }
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    fn release(bid: u16) {
+        RELEASED.fetch_add(bid as usize, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_host_read() {
+        let buf = SocketBuff::Init(2);
+        assert!(!buf.HostRead());
+        assert!(buf.SetHostRead());
+        assert!(!buf.SetHostRead());
+        assert!(buf.HostRead());
+
+        assert!(!buf.ReadStopped());
+        buf.SetReadStopped();
+        assert!(buf.ReadStopped());
+    }
+
+    #[test]
+    fn test_pooled_read() {
+        let page = MemoryDef::PAGE_SIZE as usize;
+        let buf = SocketBuff::Init(8);
+        buf.PoolShrinkRead();
+        assert_eq!(buf.readBuf.lock().BufSize(), 2 * page);
+        assert_eq!(buf.RcvBufSize(), 8 * page);
+        // the pooled read buf doesn't regrow with the restart of the read
+        assert!(!buf.IdleRegrowRead());
+        assert_eq!(buf.PooledRecvLen(4 * page), 4 * page);
+
+        assert_eq!(buf.ProducePooled(PooledChunk { bid: 1, addr: 0x1000, len: 4 * page }, release), (true, true));
+        assert_eq!(buf.ProducePooled(PooledChunk { bid: 2, addr: 0x9000, len: 4 * page }, release), (false, false));
+        assert_eq!(buf.ReadableSize(), 8 * page);
+        assert_eq!(buf.PooledRecvLen(4 * page), 0);
+        assert_eq!(buf.Events() & EVENT_IN, EVENT_IN);
+
+        let mut pooled = buf.pooledRead.lock();
+        assert_eq!(pooled.Iovs()[1].start, 0x9000);
+        // the read restarts when half of the window is free
+        assert!(!buf.ConsumePooled(&mut pooled, 2 * page));
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 0);
+        assert_eq!(pooled.Iovs()[0].start, 0x1000 + 2 * page as u64);
+        assert!(buf.ConsumePooled(&mut pooled, 2 * page + 1));
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 1);
+        assert!(!buf.ConsumePooled(&mut pooled, 1));
+        core::mem::drop(pooled);
+
+        // the data left is released with the buf, the read buf grows back for the fallback
+        buf.PoolRegrowRead();
+        assert_eq!(buf.readBuf.lock().BufSize(), 8 * page);
+        core::mem::drop(buf);
+        assert_eq!(RELEASED.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_accept_peer_info() {
+        let mut info = AcceptPeerInfo::default();
+        info.addr.data[0..2].copy_from_slice(&(AFType::AF_INET as u16).to_ne_bytes());
+        info.addrLen = 128;
+        // the unix socket doesn't take the inet peer
+        assert_eq!(info.clone().TakePeerAddr(AFType::AF_UNIX), None);
+        assert_eq!(info.TakePeerAddr(AFType::AF_INET).unwrap().len(), SocketSize::SIZEOF_SOCKADDR_INET4);
+        assert_eq!(info.TakePeerAddr(AFType::AF_INET), None);
+
+        info.tcpInfoLen = 4;
+        assert_eq!(info.TakeTcpInfo().unwrap().len(), 4);
+        assert_eq!(info.TakeTcpInfo(), None);
+        assert_eq!(info.Cred(), None);
+    }
+
+    #[test]
+    fn test_file_read_linked() {
+        let buf = SocketBuff::Init(2);
+        let (addr, size, linked) = buf.ReserveFileRead(100, true).unwrap();
+        assert!(linked);
+        assert_eq!(size, 100);
+        // the space is produced for the linked send, the other writers wait for the read
+        assert_eq!(buf.GetAvailableWriteBuf(), (addr, 100));
+        assert!(buf.FileReadPending());
+        assert_eq!(buf.ReserveFileRead(100, true), Err(Error::SysError(SysErr::EAGAIN)));
+
+        // the full read is sent by the linked send
+        assert_eq!(buf.CommitFileRead(size, 100, linked, false), None);
+        assert!(!buf.FileReadPending());
+        assert_eq!(buf.WriteBufAvailableDataSize(), 100);
+        assert!(!buf.ConsumeWriteBuf(100));
+
+        // the short read cancels the linked send, the data read is sent by the AsyncSend
+        let (addr, size, linked) = buf.ReserveFileRead(100, true).unwrap();
+        assert_eq!(buf.CommitFileRead(size, 60, linked, false), Some((addr, 60)));
+        assert_eq!(buf.WriteBufAvailableDataSize(), 60);
+    }
+
+    #[test]
+    fn test_file_read_unlinked() {
+        let buf = SocketBuff::Init(2);
+        buf.writeBuf.lock().write(&[1; 10]).unwrap();
+        // the data in the write buf has its AsyncSend, the read isn't linked
+        let (_, size, linked) = buf.ReserveFileRead(100, true).unwrap();
+        assert!(!linked);
+        assert_eq!(buf.WriteBufAvailableDataSize(), 10);
+        assert_eq!(buf.CommitFileRead(size, 50, linked, false), None);
+        assert_eq!(buf.WriteBufAvailableDataSize(), 60);
+        assert!(!buf.ConsumeWriteBuf(60));
+
+        // the read of the empty write buf starts the AsyncSend, the eof queues nothing
+        let (addr, size, linked) = buf.ReserveFileRead(100, false).unwrap();
+        assert!(!linked);
+        assert_eq!(buf.CommitFileRead(size, 0, linked, false), None);
+        assert_eq!(buf.WriteBufAvailableDataSize(), 0);
+        let (_, size, linked) = buf.ReserveFileRead(100, false).unwrap();
+        assert_eq!(buf.CommitFileRead(size, 100, linked, false), Some((addr, 100)));
+
+        buf.SetWClosed();
+        assert_eq!(buf.ReserveFileRead(100, false), Err(Error::SysError(SysErr::EPIPE)));
+    }
+
+    #[test]
+    fn test_cork_hold() {
+        let buf = SocketBuff::Init(2);
+        let (_, size, linked) = buf.ReserveFileRead(100, false).unwrap();
+        // the corked data of the empty write buf is held, the writers coming after see it
+        assert_eq!(buf.CommitFileRead(size, 100, linked, true), None);
+        assert!(buf.SendHeld());
+        assert!(!buf.CorkFull());
+
+        // only one of the flushers sends the held data
+        assert!(buf.ReleaseSend());
+        assert!(!buf.ReleaseSend());
+        assert!(!buf.ConsumeWriteBuf(100));
+    }
+
+    #[test]
+    fn test_cork_full() {
+        // the held data is capped at half of the write buf
+        let buf = SocketBuff::Init(2);
+        let half = buf.writeBuf.lock().BufSize() / 2;
+        buf.writeBuf.lock().write(&alloc::vec![1; half - 1]).unwrap();
+        assert!(buf.HoldLocked(&buf.writeBuf.lock()));
+        assert!(buf.ReleaseSend());
+
+        buf.writeBuf.lock().write(&[1]).unwrap();
+        assert!(buf.CorkFull());
+        assert!(!buf.HoldLocked(&buf.writeBuf.lock()));
+        assert!(!buf.SendHeld());
+    }
+}
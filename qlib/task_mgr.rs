@@ -24,6 +24,7 @@ use cache_padded::CachePadded;
 use alloc::boxed::Box;
 
 use super::kernel::arch::x86_64::arch_x86::*;
+use super::kernel::arch::x86_64::shadow_stack::*;
 
 use super::vcpu_mgr::*;
 
@@ -83,7 +84,10 @@ pub struct Context {
     pub sigFPState: Vec<Box<X86fpstate>>,
     // job queue id
     pub queueId: AtomicUsize,
-    pub links: Links
+    // the level of the ready queue, given by the niceness of the thread
+    pub schedClass: AtomicUsize,
+    pub links: Links,
+    pub shadowStack: ShadowStack,
 }
 
 impl Context {
@@ -104,8 +108,9 @@ impl Context {
             X86fpstate: Default::default(),
             sigFPState: Default::default(),
             queueId: AtomicUsize::new(0),
+            schedClass: AtomicUsize::new(SCHED_CLASS_NORMAL),
             links: Links::default(),
-
+            shadowStack: ShadowStack::default(),
         }
     }
 
@@ -117,6 +122,14 @@ impl Context {
         return self.ready.store(val, Ordering::SeqCst)
     }
 
+    pub fn SchedClass(&self) -> usize {
+        return self.schedClass.load(Ordering::Relaxed)
+    }
+
+    pub fn SetSchedClass(&self, class: usize) {
+        self.schedClass.store(class, Ordering::Relaxed)
+    }
+
     pub fn CopySigFPState(&self) -> Vec<Box<X86fpstate>> {
         let mut sigfs = Vec::with_capacity(self.sigFPState.len());
 
@@ -138,7 +151,7 @@ pub struct Scheduler {
     pub readyTaskCnt: AtomicUsize,
     pub haltVcpuCnt: AtomicUsize,
 
-    pub vcpuWaitMask: AtomicU64,
+    pub vcpuWaitMask: AtomicVcpuMask,
     pub VcpuArr : Vec<CPULocal>,
 }
 
@@ -221,7 +234,7 @@ impl Scheduler {
 
     pub fn AllTasks(&self) -> Vec<TaskId> {
         let mut ret = Vec::new();
-        for i in 0..8 {
+        for i in 0..self.queue.len() {
             for t in self.queue[i].lock().iter() {
                 ret.push(*t)
             }
@@ -232,12 +245,10 @@ impl Scheduler {
 
     pub fn WakeOne(&self) -> i64 {
         loop {
-            let mask = self.vcpuWaitMask.load(Ordering::Acquire);
-
-            let vcpuId = mask.trailing_zeros() as usize;
-            if vcpuId >= 64 {
-                return -1;
-            }
+            let vcpuId = match self.vcpuWaitMask.First() {
+                None => return -1,
+                Some(vcpuId) => vcpuId,
+            };
 
             if self.WakeIdleCPU(vcpuId) {
                 return vcpuId as i64
@@ -252,10 +263,7 @@ impl Scheduler {
     }
 
     pub fn WakeIdleCPU(&self, vcpuId: usize) -> bool {
-        let vcpuMask = (1<<vcpuId) as u64;
-        let prev = self.vcpuWaitMask.fetch_and(!vcpuMask, Ordering::Acquire);
-
-        let wake = (prev & vcpuMask) != 0;
+        let wake = self.vcpuWaitMask.Clear(vcpuId);
         if wake {
             self.VcpuArr[vcpuId].Wakeup();
         }
@@ -272,12 +280,142 @@ impl Scheduler {
     }
 }
 
-pub struct TaskQueue(pub QMutex<VecDeque<TaskId>>);
+// the levels of the ready queues, one for each niceness from -20 to 19
+// the non empty levels of the ready queue are a u64 bitmap, there are at most 64 levels
+pub const SCHED_CLASS_CNT: usize = 40;
+pub const SCHED_CLASS_NORMAL: usize = 20;
+
+// the weights of the niceness as in linux, the levels get the vcpu in proportion to them
+pub const SCHED_CLASS_WEIGHTS: [u64; SCHED_CLASS_CNT] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+// the pass of a level goes up by SCHED_STRIDE / weight each time it is served
+pub const SCHED_STRIDE: u64 = 1 << 20;
+
+// SchedClass returns the level of the ready queue of the niceness
+pub fn SchedClass(niceness: i32) -> usize {
+    if niceness < -20 {
+        return 0
+    } else if niceness > 19 {
+        return SCHED_CLASS_CNT - 1
+    }
+
+    return (niceness + 20) as usize
+}
+
+// ReadyQueue is the ready tasks of a vcpu in the levels of their niceness. The levels are served
+// by stride scheduling: the non empty level of the lowest pass is picked, so that each level gets
+// the vcpu in proportion to its weight. The stealing vcpu takes the tasks in the same order as
+// the owner.
+#[derive(Debug)]
+pub struct ReadyQueue {
+    pub levels: Vec<VecDeque<TaskId>>,
+    pub pass: [u64; SCHED_CLASS_CNT],
+    // the pass of the last served level, the level turning non empty starts from it so that it
+    // doesn't get the vcpu back for the time it had nothing to run
+    pub vtime: u64,
+    // the bit of a level is set when it is not empty
+    pub nonEmpty: u64,
+    pub count: usize,
+}
+
+impl Default for ReadyQueue {
+    fn default() -> Self {
+        let levels = (0..SCHED_CLASS_CNT).map(|c| {
+            if c == SCHED_CLASS_NORMAL {
+                VecDeque::with_capacity(128)
+            } else {
+                VecDeque::new()
+            }
+        }).collect();
+
+        return Self {
+            levels: levels,
+            pass: [0; SCHED_CLASS_CNT],
+            vtime: 0,
+            nonEmpty: 0,
+            count: 0,
+        }
+    }
+}
+
+impl ReadyQueue {
+    pub fn len(&self) -> usize {
+        return self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0
+    }
+
+    pub fn push_back(&mut self, task: TaskId) {
+        let class = task.Context().SchedClass();
+        self.PushClass(task, class);
+    }
+
+    pub fn PushClass(&mut self, task: TaskId, class: usize) {
+        if self.levels[class].is_empty() && self.pass[class] < self.vtime {
+            self.pass[class] = self.vtime;
+        }
+
+        self.levels[class].push_back(task);
+        self.nonEmpty |= 1 << class;
+        self.count += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<TaskId> {
+        let (task, class) = self.Pick()?;
+        self.Charge(class);
+        return Some(task)
+    }
+
+    // Pick pops the first task of the non empty level of the lowest pass without charging the
+    // level, the level is charged with Charge when the task gets the vcpu
+    pub fn Pick(&mut self) -> Option<(TaskId, usize)> {
+        let mut class = None;
+        let mut bits = self.nonEmpty;
+        while bits != 0 {
+            let c = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            match class {
+                Some(b) if self.pass[b] <= self.pass[c] => (),
+                _ => class = Some(c),
+            }
+        }
+
+        let class = class?;
+        let task = self.levels[class].pop_front()?;
+        if self.levels[class].is_empty() {
+            self.nonEmpty &= !(1 << class);
+        }
+        self.count -= 1;
+        return Some((task, class))
+    }
+
+    pub fn Charge(&mut self, class: usize) {
+        self.vtime = self.pass[class];
+        self.pass[class] += SCHED_STRIDE / SCHED_CLASS_WEIGHTS[class];
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TaskId> {
+        return self.levels.iter().flat_map(|q| q.iter())
+    }
+}
+
+pub struct TaskQueue(pub QMutex<ReadyQueue>);
 
 impl Deref for TaskQueue {
-    type Target = QMutex<VecDeque<TaskId>>;
+    type Target = QMutex<ReadyQueue>;
 
-    fn deref(&self) -> &QMutex<VecDeque<TaskId>> {
+    fn deref(&self) -> &QMutex<ReadyQueue> {
         &self.0
     }
 }
@@ -290,7 +428,7 @@ impl Default for TaskQueue {
 
 impl TaskQueue {
     pub fn New() -> Self {
-        return TaskQueue(QMutex::new(VecDeque::with_capacity(128)));
+        return TaskQueue(QMutex::new(ReadyQueue::default()));
     }
 
     pub fn Dequeue(&self) -> Option<TaskId> {
@@ -309,3 +447,151 @@ impl TaskQueue {
         return self.lock().len() as u64;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // newTask returns the task of a context of the niceness, the context is leaked as the
+    // contexts of the kernel tasks
+    fn newTask(niceness: i32) -> TaskId {
+        let ctx = Box::leak(Box::new(Context::New()));
+        ctx.SetSchedClass(SchedClass(niceness));
+        return TaskId::New(ctx as * const _ as u64)
+    }
+
+    #[test]
+    fn test_sched_class() {
+        assert_eq!(SchedClass(0), SCHED_CLASS_NORMAL);
+        assert_eq!(SchedClass(-20), 0);
+        assert_eq!(SchedClass(-30), 0);
+        assert_eq!(SchedClass(19), SCHED_CLASS_CNT - 1);
+        assert_eq!(SchedClass(40), SCHED_CLASS_CNT - 1);
+    }
+
+    #[test]
+    fn test_scheduler_queue() {
+        let sched = Scheduler::New(2);
+        let normal = newTask(0);
+        let high = newTask(-5);
+
+        // no vcpu is waiting, so nothing is woken
+        sched.ScheduleQ(normal, 0);
+        sched.ScheduleQ(high, 0);
+        assert_eq!(sched.ReadyTaskCnt(0), 2);
+        assert_eq!(sched.ReadyTaskCnt(1), 0);
+        assert_eq!(sched.GlobalReadyTaskCnt(), 2);
+        assert_eq!(sched.AllTasks().len(), 2);
+
+        // the level of the task is the niceness of its context
+        assert_eq!(sched.queue[0].Dequeue().unwrap().data, high.data);
+        assert_eq!(sched.queue[0].Dequeue().unwrap().data, normal.data);
+        assert!(sched.queue[0].Dequeue().is_none());
+        assert_eq!(sched.DecReadyTaskCount(), 1);
+
+        sched.queue[1].Enqueue(normal);
+        assert_eq!(sched.ReadyTaskCnt(1), 1);
+        assert_eq!(sched.WakeOne(), -1);
+    }
+
+    #[test]
+    fn test_ready_queue_levels() {
+        let mut q = ReadyQueue::default();
+        for i in 1..=12 {
+            q.PushClass(TaskId::New(i), SchedClass(0));
+        }
+        q.PushClass(TaskId::New(100), SchedClass(19));
+        q.PushClass(TaskId::New(200), SchedClass(-20));
+        assert_eq!(q.len(), 14);
+
+        assert_eq!(q.pop_front().unwrap().data, 200);
+        // the nice 19 level is served once and then waits for its pass
+        let order: Vec<u64> = (0..13).map(|_| q.pop_front().unwrap().data).collect();
+        assert_eq!(order, vec![1, 100, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        assert!(q.pop_front().is_none());
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn test_ready_queue_pick() {
+        let mut q = ReadyQueue::default();
+        q.PushClass(TaskId::New(1), SchedClass(0));
+        q.PushClass(TaskId::New(2), SchedClass(0));
+        q.PushClass(TaskId::New(100), SchedClass(19));
+        assert_eq!(q.nonEmpty.count_ones(), 2);
+
+        // the task picked and put back without the charge doesn't use the pass of its level
+        let (t, class) = q.Pick().unwrap();
+        assert_eq!(t.data, 1);
+        assert_eq!(q.pass[class], 0);
+        q.PushClass(t, class);
+        assert_eq!(q.len(), 3);
+
+        let (t, class) = q.Pick().unwrap();
+        assert_eq!(t.data, 2);
+        q.Charge(class);
+        assert!(q.pass[class] > 0);
+
+        // the nice 19 level has the lowest pass now
+        assert_eq!(q.pop_front().unwrap().data, 100);
+        assert_eq!(q.nonEmpty, 1 << SchedClass(0));
+        assert_eq!(q.pop_front().unwrap().data, 1);
+        assert_eq!(q.nonEmpty, 0);
+        assert!(q.is_empty());
+    }
+
+    // picks returns how many times each task is picked when the tasks are always ready
+    fn picks(nices: &[i32], rounds: usize) -> Vec<usize> {
+        let mut q = ReadyQueue::default();
+        for (i, n) in nices.iter().enumerate() {
+            q.PushClass(TaskId::New(i as u64), SchedClass(*n));
+        }
+
+        let mut cnt = vec![0; nices.len()];
+        for _ in 0..rounds {
+            let t = q.pop_front().unwrap();
+            let i = t.data as usize;
+            cnt[i] += 1;
+            q.PushClass(t, SchedClass(nices[i]));
+        }
+
+        return cnt
+    }
+
+    #[test]
+    fn test_ready_queue_weights() {
+        // the weight of nice 0 is about 3 times the one of nice 5
+        let cnt = picks(&[0, 5], 4000);
+        assert!(cnt[0] > cnt[1] * 29 / 10 && cnt[0] < cnt[1] * 32 / 10, "{:?}", cnt);
+
+        // nice 1 and nice 19 are different levels
+        let cnt = picks(&[0, 1, 19], 10000);
+        assert!(cnt[1] > cnt[2] * 40, "{:?}", cnt);
+        assert!(cnt[2] > 0, "{:?}", cnt);
+
+        // the negative niceness doesn't starve the others
+        let cnt = picks(&[-20, 19], 100000);
+        assert!(cnt[1] > 0, "{:?}", cnt);
+    }
+
+    #[test]
+    fn test_ready_queue_idle_level() {
+        let mut q = ReadyQueue::default();
+        for i in 0..100 {
+            q.PushClass(TaskId::New(i), SchedClass(0));
+            q.pop_front().unwrap();
+        }
+
+        // the level which was idle doesn't get the vcpu back for the idle time
+        q.PushClass(TaskId::New(1), SchedClass(0));
+        q.PushClass(TaskId::New(1000), SchedClass(5));
+        let mut order = Vec::new();
+        for _ in 0..8 {
+            let t = q.pop_front().unwrap();
+            order.push(t.data);
+            let class = if t.data == 1 { SchedClass(0) } else { SchedClass(5) };
+            q.PushClass(t, class);
+        }
+        assert_eq!(order, vec![1000, 1, 1, 1, 1000, 1, 1, 1]);
+    }
+}
@@ -29,7 +29,10 @@ use self::util::{Fd, Mmap};
 use self::porting::*;
 use super::common::*;
 
+use core::sync::atomic::AtomicI64;
 use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
 
 #[derive(Default)]
 pub struct Submission {
@@ -49,6 +52,20 @@ pub struct Completion {
 pub struct IoUring {
     pub fd: Fd,
     pub pendingCnt: AtomicU64,
+    // the host io thread of the uring is blocked and has to be woken up for new submission
+    pub ioThreadWaiting: AtomicBool,
+    // the uring is submitted and reaped by its own host io thread instead of the kernel io thread
+    pub ownIOThread: AtomicBool,
+    // the TSC when the host io thread last checked the completions, a vcpu reaping them for the
+    // lagging io thread moves it forward too
+    pub hostReapTsc: AtomicI64,
+    // completion-to-wakeup latency: the TSC when the completions are first seen in the uring, 0
+    // when none is seen, and the reaps of the seen completions with their total and max latency
+    // in TSC cycles. They are updated by the vcpus and the host io thread.
+    pub cqSeenTsc: AtomicI64,
+    pub wakeups: AtomicU64,
+    pub wakeupCycles: AtomicU64,
+    pub wakeupMaxCycles: AtomicU64,
     pub lock: QMutex<()>,
     pub params: Parameters,
     pub memory: MemoryMap,
@@ -60,6 +77,41 @@ impl IoUring {
     pub fn HasCompleteEntry(&self) -> bool {
         return self.completion().lock().len() > 0;
     }
+
+    pub fn HostReaped(&self, tsc: i64) {
+        self.hostReapTsc.store(tsc, Ordering::Relaxed);
+    }
+
+    // CompletionSeen records when the completions are first seen, the uring being drained is
+    // skipped
+    pub fn CompletionSeen(&self, tsc: i64) {
+        if self.cqSeenTsc.load(Ordering::Relaxed) != 0 {
+            return
+        }
+
+        let pending = match self.cq.try_lock() {
+            None => false,
+            Some(cq) => cq.len() > 0,
+        };
+
+        if pending {
+            self.cqSeenTsc.compare_exchange(0, tsc, Ordering::Relaxed, Ordering::Relaxed).ok();
+        }
+    }
+
+    // CompletionReaped counts the wakeup of the completions reaped at tsc, the latency is the one
+    // of the oldest completion seen
+    pub fn CompletionReaped(&self, tsc: i64) {
+        let seen = self.cqSeenTsc.swap(0, Ordering::Relaxed);
+        if seen == 0 || tsc < seen {
+            return
+        }
+
+        let cycles = (tsc - seen) as u64;
+        self.wakeups.fetch_add(1, Ordering::Relaxed);
+        self.wakeupCycles.fetch_add(cycles, Ordering::Relaxed);
+        self.wakeupMaxCycles.fetch_max(cycles, Ordering::Relaxed);
+    }
 }
 
 #[allow(dead_code)]
@@ -272,3 +324,28 @@ pub fn io_uring_register(
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_wakeup() {
+        let uring = IoUring::default();
+        // the reap of the completions not seen before is not counted
+        uring.CompletionReaped(100);
+        assert_eq!(uring.wakeups.load(Ordering::Relaxed), 0);
+
+        uring.cqSeenTsc.store(100, Ordering::Relaxed);
+        uring.CompletionReaped(400);
+        uring.cqSeenTsc.store(500, Ordering::Relaxed);
+        uring.CompletionReaped(600);
+        assert_eq!(uring.cqSeenTsc.load(Ordering::Relaxed), 0);
+        assert_eq!(uring.wakeups.load(Ordering::Relaxed), 2);
+        assert_eq!(uring.wakeupCycles.load(Ordering::Relaxed), 400);
+        assert_eq!(uring.wakeupMaxCycles.load(Ordering::Relaxed), 300);
+
+        // the fd of the uring is not closed on drop
+        core::mem::forget(uring);
+    }
+}
@@ -1110,7 +1110,6 @@ opcode!(
     }
 );
 
-#[cfg(feature = "unstable")]
 opcode!(
     pub struct ProvideBuffers {
         addr: { *mut u8 },
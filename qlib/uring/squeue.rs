@@ -192,4 +192,16 @@ impl Entry {
         self.0.user_data = user_data;
         self
     }
+
+    /// Set the fd of the operation which doesn't take a file, e.g. AsyncCancel, so that it is
+    /// sharded to the uring of the requests of the fd
+    pub fn shard_fd(mut self, fd: i32) -> Entry {
+        self.0.fd = fd;
+        self
+    }
+
+    /// The fd of the operation, it is used to shard the entries among the urings
+    pub fn get_fd(&self) -> i32 {
+        self.0.fd
+    }
 }
@@ -300,6 +300,30 @@ pub struct io_uring_files_update {
     pub fds: __u64,
 }
 
+// the provided buffer ring of linux 5.19, the ring memory is shared with the kernel which takes
+// the bufs from its head, the tail is in the resv of the first entry
+pub const IORING_REGISTER_PBUF_RING: u32 = 22;
+pub const IORING_UNREGISTER_PBUF_RING: u32 = 23;
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_buf {
+    pub addr: __u64,
+    pub len: __u32,
+    pub bid: __u16,
+    pub resv: __u16,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct io_uring_buf_reg {
+    pub ring_addr: __u64,
+    pub ring_entries: __u32,
+    pub bgid: __u16,
+    pub flags: __u16,
+    pub resv: [__u64; 3usize],
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone)]
 pub struct io_uring_probe_op {
@@ -17,6 +17,7 @@ use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicI64;
 
 use super::ShareSpace;
+use super::MAX_VCPU_COUNT;
 use super::mem::list_allocator::*;
 
 #[derive(Clone, Debug, PartialEq, Copy)]
@@ -49,6 +50,12 @@ pub struct CPULocal {
     // it is the time to enter guest ring3. If it is in ring0, the vale will be zero
     pub enterAppTimestamp: AtomicI64,
     pub interruptMask: AtomicU64,
+    // it is the time the current task entered the guest kernel or was switched in, the long
+    // kernel loops give up the vcpu when it is older than a time slice
+    pub kernelEnterTimestamp: AtomicI64,
+
+    // the fs base of the vcpu written by wrmsr, the msr write is skipped when it doesn't change
+    pub fsBase: AtomicU64,
 }
 
 impl CPULocal {
@@ -93,6 +100,14 @@ impl CPULocal {
         return self.enterAppTimestamp.load(Ordering::Relaxed)
     }
 
+    pub fn SetKernelEnterTimestamp(&self, val: i64) {
+        self.kernelEnterTimestamp.store(val,  Ordering::Relaxed)
+    }
+
+    pub fn KernelEnterTimestamp(&self) -> i64 {
+        return self.kernelEnterTimestamp.load(Ordering::Relaxed)
+    }
+
     pub fn ResetInterruptMask(&self) -> u64 {
         return self.interruptMask.swap(0, Ordering::SeqCst)
     }
@@ -103,6 +118,7 @@ impl CPULocal {
 
     pub const TLB_SHOOTDOWN_MASK : u64 = 1<<0;
     pub const THREAD_TIMEOUT : u64 = 1<<1;
+    pub const PROFILE_SAMPLE : u64 = 1<<2;
 
     pub fn InterruptTlbShootdown(&self) {
         self.SetInterruptMask(Self::TLB_SHOOTDOWN_MASK);
@@ -112,6 +128,10 @@ impl CPULocal {
         self.SetInterruptMask(Self::THREAD_TIMEOUT);
     }
 
+    pub fn InterruptProfileSample(&self) {
+        self.SetInterruptMask(Self::PROFILE_SAMPLE);
+    }
+
     pub fn InterruptByTlbShootdown(mask: u64) -> bool {
         return mask & Self::TLB_SHOOTDOWN_MASK != 0;
     }
@@ -119,4 +139,142 @@ impl CPULocal {
     pub fn InterruptByThreadTimeout(mask: u64) -> bool {
         return mask & Self::THREAD_TIMEOUT != 0;
     }
-}
\ No newline at end of file
+
+    pub fn InterruptByProfileSample(mask: u64) -> bool {
+        return mask & Self::PROFILE_SAMPLE != 0;
+    }
+}
+pub const VCPU_MASK_WORDS: usize = (MAX_VCPU_COUNT + 63) / 64;
+
+// VcpuMask is a bitmap of vcpu ids, a u64 mask can't cover more than 64 vcpus
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct VcpuMask {
+    pub words: [u64; VCPU_MASK_WORDS],
+}
+
+impl VcpuMask {
+    pub fn Set(&mut self, vcpuId: usize) {
+        self.words[vcpuId / 64] |= 1 << (vcpuId % 64);
+    }
+
+    pub fn Clear(&mut self, vcpuId: usize) {
+        self.words[vcpuId / 64] &= !(1 << (vcpuId % 64));
+    }
+
+    pub fn IsSet(&self, vcpuId: usize) -> bool {
+        return self.words[vcpuId / 64] & (1 << (vcpuId % 64)) != 0;
+    }
+
+    pub fn IsEmpty(&self) -> bool {
+        return self.words.iter().all(|w| *w == 0);
+    }
+
+    pub fn Count(&self) -> usize {
+        return self.words.iter().map(|w| w.count_ones() as usize).sum();
+    }
+
+    // AndNot returns the vcpus in self but not in other
+    pub fn AndNot(&self, other: &VcpuMask) -> VcpuMask {
+        let mut ret = *self;
+        for i in 0..VCPU_MASK_WORDS {
+            ret.words[i] &= !other.words[i];
+        }
+
+        return ret
+    }
+
+    // First returns the lowest vcpu id in the mask
+    pub fn First(&self) -> Option<usize> {
+        for i in 0..VCPU_MASK_WORDS {
+            if self.words[i] != 0 {
+                return Some(i * 64 + self.words[i].trailing_zeros() as usize)
+            }
+        }
+
+        return None
+    }
+}
+
+// AtomicVcpuMask is the VcpuMask shared by the vcpus, each word is updated atomically
+#[derive(Debug, Default)]
+pub struct AtomicVcpuMask {
+    pub words: [AtomicU64; VCPU_MASK_WORDS],
+}
+
+impl AtomicVcpuMask {
+    // Set returns whether the vcpu was in the mask
+    pub fn Set(&self, vcpuId: usize) -> bool {
+        let bit = 1 << (vcpuId % 64);
+        let prev = self.words[vcpuId / 64].fetch_or(bit, Ordering::SeqCst);
+        return prev & bit != 0
+    }
+
+    // Clear returns whether the vcpu was in the mask
+    pub fn Clear(&self, vcpuId: usize) -> bool {
+        let bit = 1 << (vcpuId % 64);
+        let prev = self.words[vcpuId / 64].fetch_and(!bit, Ordering::SeqCst);
+        return prev & bit != 0
+    }
+
+    pub fn IsSet(&self, vcpuId: usize) -> bool {
+        return self.words[vcpuId / 64].load(Ordering::Acquire) & (1 << (vcpuId % 64)) != 0;
+    }
+
+    pub fn ClearAll(&self) {
+        for w in &self.words {
+            w.store(0, Ordering::Release);
+        }
+    }
+
+    pub fn Load(&self) -> VcpuMask {
+        let mut ret = VcpuMask::default();
+        for i in 0..VCPU_MASK_WORDS {
+            ret.words[i] = self.words[i].load(Ordering::Acquire);
+        }
+
+        return ret
+    }
+
+    pub fn First(&self) -> Option<usize> {
+        return self.Load().First();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vcpu_mask() {
+        let mut mask = VcpuMask::default();
+        assert!(mask.IsEmpty());
+        assert_eq!(mask.First(), None);
+        mask.Set(130);
+        mask.Set(3);
+        assert!(mask.IsSet(130) && mask.IsSet(3) && !mask.IsSet(66));
+        assert_eq!(mask.Count(), 2);
+        assert_eq!(mask.First(), Some(3));
+
+        let mut other = VcpuMask::default();
+        other.Set(3);
+        let left = mask.AndNot(&other);
+        assert_eq!(left.First(), Some(130));
+        mask.Clear(3);
+        assert_eq!(mask, left);
+    }
+
+    #[test]
+    fn test_atomic_vcpu_mask() {
+        let mask = AtomicVcpuMask::default();
+        assert!(!mask.Set(100));
+        assert!(mask.Set(100));
+        assert!(mask.IsSet(100));
+        assert_eq!(mask.First(), Some(100));
+        assert!(mask.Clear(100));
+        assert!(!mask.Clear(100));
+        mask.Set(64);
+        mask.ClearAll();
+        assert!(mask.Load().IsEmpty());
+    }
+}
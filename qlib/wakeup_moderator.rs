@@ -0,0 +1,174 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use alloc::string::String;
+
+use super::vcpu_mgr::*;
+
+// WakeupModerator batches the vcpu kicks caused by the host io completions.
+// When the completion rate is above the threshold, the kicks are deferred and the io thread
+// flushes them every moderation interval. Otherwise the vcpu is kicked immediately.
+// All the time is in us.
+#[derive(Debug, Default)]
+pub struct WakeupModerator {
+    pendingMask: AtomicVcpuMask,
+    moderating: AtomicBool,
+    windowStart: AtomicI64,
+    windowCount: AtomicU64,
+    lastFlush: AtomicI64,
+
+    pub lastRate: AtomicU64,
+    pub immediateKicks: AtomicU64,
+    pub deferredKicks: AtomicU64,
+    // the kicks merged into a pending kick of the same vcpu
+    pub coalescedKicks: AtomicU64,
+    pub flushes: AtomicU64,
+}
+
+impl WakeupModerator {
+    // completion rate sampling window
+    pub const WINDOW: i64 = 1000;
+
+    // OnCompletions accounts cnt completions processed at now. At the end of the window the
+    // moderation is turned on when the completion rate (per second) reaches rate, 0 disables it.
+    pub fn OnCompletions(&self, cnt: usize, now: i64, rate: u64) {
+        self.windowCount.fetch_add(cnt as u64, Ordering::Relaxed);
+        let start = self.windowStart.load(Ordering::Relaxed);
+        let elapsed = now - start;
+        if elapsed < Self::WINDOW {
+            return
+        }
+
+        // several io threads could reach the end of the window, only one closes it
+        if self.windowStart.compare_exchange(start, now, Ordering::AcqRel, Ordering::Relaxed).is_err() {
+            return
+        }
+
+        let count = self.windowCount.swap(0, Ordering::Relaxed);
+        let curr = count * 1_000_000 / elapsed as u64;
+        self.lastRate.store(curr, Ordering::Relaxed);
+        self.moderating.store(rate > 0 && curr >= rate, Ordering::Release);
+    }
+
+    pub fn Moderating(&self) -> bool {
+        return self.moderating.load(Ordering::Acquire)
+    }
+
+    // Defer returns true when the kick of the vcpu is deferred to the next Flush
+    pub fn Defer(&self, vcpuId: usize) -> bool {
+        if !self.Moderating() {
+            self.immediateKicks.fetch_add(1, Ordering::Relaxed);
+            return false
+        }
+
+        if self.pendingMask.Set(vcpuId) {
+            self.coalescedKicks.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.deferredKicks.fetch_add(1, Ordering::Relaxed);
+        }
+
+        return true
+    }
+
+    // Flush returns the vcpus to kick. The pending kicks are due when interval has passed since
+    // the last flush, force flushes them anyway, e.g. before the io thread blocks.
+    pub fn Flush(&self, now: i64, interval: i64, force: bool) -> VcpuMask {
+        let mut ret = VcpuMask::default();
+        let mut pending = self.pendingMask.Load();
+        if pending.IsEmpty() {
+            return ret
+        }
+
+        if !force && now - self.lastFlush.load(Ordering::Relaxed) < interval {
+            return ret
+        }
+
+        self.lastFlush.store(now, Ordering::Relaxed);
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        while let Some(vcpuId) = pending.First() {
+            pending.Clear(vcpuId);
+            // another io thread could have flushed it
+            if self.pendingMask.Clear(vcpuId) {
+                ret.Set(vcpuId);
+            }
+        }
+
+        return ret
+    }
+
+    pub fn Report(&self) -> String {
+        return format!("moderating {} rate {}/s immediate {} deferred {} coalesced {} flushes {}\n",
+                       self.Moderating(),
+                       self.lastRate.load(Ordering::Relaxed),
+                       self.immediateKicks.load(Ordering::Relaxed),
+                       self.deferredKicks.load(Ordering::Relaxed),
+                       self.coalescedKicks.load(Ordering::Relaxed),
+                       self.flushes.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_low_rate_kicks_immediately() {
+        let m = WakeupModerator::default();
+        m.OnCompletions(10, 1000, 100_000);
+        m.OnCompletions(10, 2000, 100_000);
+        assert!(!m.Moderating());
+        assert!(!m.Defer(1));
+        assert!(m.Flush(2000, 50, true).IsEmpty());
+        assert_eq!(m.immediateKicks.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_high_rate_batches() {
+        let m = WakeupModerator::default();
+        m.OnCompletions(0, 1000, 100_000);
+        // 200 completions in 1ms is 200k/s
+        m.OnCompletions(200, 2000, 100_000);
+        assert!(m.Moderating());
+
+        assert!(m.Defer(1));
+        assert!(m.Defer(1));
+        assert!(m.Defer(3));
+        assert_eq!(m.coalescedKicks.load(Ordering::Relaxed), 1);
+
+        let mask = m.Flush(2010, 50, false);
+        assert!(mask.IsSet(1) && mask.IsSet(3));
+        assert_eq!(mask.Count(), 2);
+
+        assert!(m.Defer(2));
+        // the interval has not passed since the last flush
+        assert!(m.Flush(2020, 50, false).IsEmpty());
+        assert!(m.Flush(2060, 50, false).IsSet(2));
+
+        // rate drops
+        m.OnCompletions(1, 3100, 100_000);
+        assert!(!m.Moderating());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let m = WakeupModerator::default();
+        m.OnCompletions(0, 1000, 0);
+        m.OnCompletions(1_000_000, 2000, 0);
+        assert!(!m.Moderating());
+    }
+}
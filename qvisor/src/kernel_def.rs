@@ -1,6 +1,7 @@
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering;
 use std::fmt;
+use std::cell::Cell;
 use libc::*;
 
 use super::qlib::*;
@@ -23,6 +24,7 @@ use super::FD_NOTIFIER;
 use super::QUARK_CONFIG;
 use super::URING_MGR;
 use super::VMS;
+use super::SHARE_SPACE;
 use super::vmspace::*;
 use super::ThreadId;
 
@@ -100,6 +102,10 @@ impl<'a> ShareSpace {
 impl ShareSpace {
     pub fn Init(&mut self, vcpuCount: usize, controlSock: i32) {
         *self.config.write() = *QUARK_CONFIG.lock();
+        {
+            let config = self.config.read();
+            self.userBufPool.Init(config.UserBufPoolSize(), config.UserBufMaxSize(), config.UserBufGroupSize());
+        }
         let mut values = Vec::with_capacity(vcpuCount);
         for _i in 0..vcpuCount {
             values.push([AtomicU64::new(0), AtomicU64::new(0)])
@@ -121,22 +127,23 @@ impl ShareSpace {
         super::vmspace::VMSpace::BlockFd(controlSock);
     }
 
-    pub fn TlbShootdown(&self, vcpuMask: u64) -> i64 {
+    // TlbShootdown returns the count of the vcpus interrupted
+    pub fn TlbShootdown(&self, vcpuMask: VcpuMask) -> i64 {
         let _l = self.tlbShootdownLock.lock();
 
         self.ClearTlbShootdownMask();
-        let mask = VMS.lock().TlbShootdown(vcpuMask);
+        let mask = VMS.lock().TlbShootdown(&vcpuMask);
 
         for _ in 0..10_000 { // total 10_000 micro sec
-            if mask & !self.TlbShootdownMask() == 0 {
-                return mask as _;
+            if mask.AndNot(&self.TlbShootdownMask()).IsEmpty() {
+                return mask.Count() as _;
             }
             Self::Yield();
-            //mask = VMS.lock().TlbShootdown(mask & !self.TlbShootdownMask());
+            //mask = VMS.lock().TlbShootdown(&mask.AndNot(&self.TlbShootdownMask()));
         }
 
-        error!("TlbShootdown waiting for {:b} timeout", mask & !self.TlbShootdownMask());
-        return mask as _;
+        error!("TlbShootdown waiting for {:x?} timeout", mask.AndNot(&self.TlbShootdownMask()).words);
+        return mask.Count() as _;
     }
 
     pub fn Yield() {
@@ -146,6 +153,11 @@ impl ShareSpace {
     }
 
     pub fn CheckVcpuTimeout(&self) {
+        let timeSlice = self.TimeSlice();
+        if timeSlice == 0 {
+            return
+        }
+
         let now = TSC.Rdtsc();
         for i in 1..self.scheduler.VcpuArr.len() {
             let enterAppTimestamp = self.scheduler.VcpuArr[i].EnterAppTimestamp();
@@ -154,7 +166,7 @@ impl ShareSpace {
             }
 
             //error!("CheckVcpuTimeout {}/{}/{}/{}", i, enterAppTimestamp, now, Tsc::Scale(now - enterAppTimestamp));
-            if Tsc::Scale(now - enterAppTimestamp) * 1000 > CLOCK_TICK {
+            if Tsc::Scale(now - enterAppTimestamp) * 1000 > timeSlice {
                 self.scheduler.VcpuArr[i].ResetEnterAppTimestamp();
                 self.scheduler.VcpuArr[i].InterruptThreadTimeout();
 
@@ -163,6 +175,81 @@ impl ShareSpace {
             }
         }
     }
+
+    // NextVcpuTimeout returns the ms till the earliest time slice expiration of the app vcpus,
+    // it is the io thread wait time so that a busy loop app thread is preempted in time.
+    pub fn NextVcpuTimeout(&self) -> i32 {
+        let timeSlice = self.TimeSlice();
+        if timeSlice == 0 {
+            return -1
+        }
+
+        let now = TSC.Rdtsc();
+        let mut remain = timeSlice;
+        for i in 1..self.scheduler.VcpuArr.len() {
+            let enterAppTimestamp = self.scheduler.VcpuArr[i].EnterAppTimestamp();
+            if enterAppTimestamp == 0 {
+                continue;
+            }
+
+            let left = timeSlice - Tsc::Scale(now - enterAppTimestamp) * 1000;
+            if left < remain {
+                remain = left;
+            }
+        }
+
+        // round up so that the time slice has expired when the io thread wakes up
+        let ms = (remain + MILLISECOND - 1) / MILLISECOND;
+        return if ms < 1 { 1 } else { ms as i32 }
+    }
+
+    // ProcessIOCompletion runs the host io completion processing f, the vcpu kicks caused by the
+    // completions go through the wakeup moderation
+    pub fn ProcessIOCompletion<F: FnOnce() -> usize>(&self, f: F) -> usize {
+        IN_IO_COMPLETION.with(|c| c.set(true));
+        let cnt = f();
+        IN_IO_COMPLETION.with(|c| c.set(false));
+
+        let rate = self.WakeupModerationRate();
+        self.wakeupModerator.OnCompletions(cnt, Tsc::Scale(TSC.Rdtsc()), rate);
+        return cnt
+    }
+
+    // FlushWakeup kicks the vcpus whose kick is deferred by the wakeup moderation. force is set
+    // before the io thread blocks so that no kick is left behind.
+    pub fn FlushWakeup(&self, force: bool) {
+        let interval = self.WakeupModerationInterval();
+        let mut mask = self.wakeupModerator.Flush(Tsc::Scale(TSC.Rdtsc()), interval, force);
+        while let Some(vcpuId) = mask.First() {
+            mask.Clear(vcpuId);
+            self.scheduler.VcpuArr[vcpuId].Kick();
+        }
+    }
+
+    // CheckProfileSample interrupts the running vcpus when the guest profiler is on
+    pub fn CheckProfileSample(&self) {
+        let interval = self.profileInterval.load(Ordering::Relaxed);
+        if interval == 0 {
+            return
+        }
+
+        let now = TSC.Rdtsc();
+        let last = self.profileLastSample.load(Ordering::Relaxed);
+        if (Tsc::Scale(now - last) as u64) < interval {
+            return
+        }
+        self.profileLastSample.store(now, Ordering::Relaxed);
+
+        for i in 1..self.scheduler.VcpuArr.len() {
+            // the waiting vcpu is halted in the host, there is nothing to sample
+            if self.scheduler.VcpuArr[i].State() != VcpuState::Running {
+                continue;
+            }
+
+            self.scheduler.VcpuArr[i].InterruptProfileSample();
+            VMS.lock().vcpus[i].Signal(Signal::SIGCHLD);
+        }
+    }
 }
 
 
@@ -218,12 +305,23 @@ pub fn StartSubContainerProcess(_elfEntry: u64, _userStackAddr: u64, _kernelStac
 
 pub unsafe fn CopyPageUnsafe(_to: u64, _from: u64){}
 
+thread_local!(static IN_IO_COMPLETION: Cell<bool> = Cell::new(false));
+
 impl CPULocal {
     pub fn CpuId() -> usize {
         return ThreadId() as _;
     }
 
     pub fn Wakeup(&self) {
+        // the kick caused by the host io completion could be batched
+        if IN_IO_COMPLETION.with(|c| c.get()) && SHARE_SPACE.wakeupModerator.Defer(self.vcpuId) {
+            return
+        }
+
+        self.Kick();
+    }
+
+    pub fn Kick(&self) {
         let val : u64 = 8;
         let ret = unsafe {
             libc::write(self.eventfd, &val as * const _ as *const libc::c_void, 8)
@@ -239,6 +337,11 @@ impl PageMgrInternal {
 }
 
 pub fn ClockGetTime(clockId: i32) -> i64 {
+    #[cfg(any(test, feature = "host-test"))]
+    if let Some(ns) = super::qlib::kernel::test_util::MockTime() {
+        return ns
+    }
+
     let ts = Timespec::default();
     let res = unsafe {
         clock_gettime(clockId as clockid_t, &ts as *const _ as u64 as *mut timespec) as i64
@@ -268,11 +371,29 @@ impl HostSpace {
         return VMSpace::Close(fd);
     }
 
+    // the uring completion processed by the io thread shuts down the host socket directly
+    pub fn ShutdownAsync(fd: i32, how: i32) {
+        let ret = VMSpace::Shutdown(fd, how);
+        if ret < 0 {
+            error!("ShutdownAsync fail err is {}, fd is {}", ret, fd);
+        }
+    }
+
     pub fn Call(msg: &mut Msg, _mustAsync: bool) -> u64 {
+        #[cfg(any(test, feature = "host-test"))]
+        if let Some(ret) = super::qlib::kernel::test_util::MockHostCall(msg) {
+            return ret
+        }
+
         panic!("HostSpace::Call msg {:x?}", msg);
     }
 
     pub fn HCall(msg: &mut Msg, _lock: bool) -> u64 {
+        #[cfg(any(test, feature = "host-test"))]
+        if let Some(ret) = super::qlib::kernel::test_util::MockHostCall(msg) {
+            return ret
+        }
+
         panic!("HostSpace::HCall msg {:x?}", msg);
     }
 }
@@ -40,6 +40,9 @@ use super::qlib::vcpu_mgr::*;
 use super::qlib::buddyallocator::ZeroPage;
 use super::amd64_def::*;
 use super::URING_MGR;
+use super::vmspace::hibernate::*;
+use super::vmspace::journal::*;
+use super::vmspace::resolver::*;
 use super::runc::runtime::vm::*;
 
 #[repr(C)]
@@ -261,6 +264,15 @@ impl KVMVcpu {
         vcpu_sregs.cr3 = VMS.lock().pageTables.GetRoot();
         //vcpu_sregs.cr4 = CR4_PAE | CR4_OSFXSR | CR4_OSXMMEXCPT;
         vcpu_sregs.cr4 = CR4_PAE | CR4_PGE | CR4_OSFXSR | CR4_OSXMMEXCPT | CR4_FSGSBASE;// | CR4_UMIP ;// CR4_PSE | | CR4_SMEP | CR4_SMAP;
+        // CR4.CET requires CR0.WP, the guest only enables the user shadow stack with IA32_U_CET
+        if QUARK_CONFIG.lock().ShadowStack {
+            vcpu_sregs.cr4 |= CR4_CET;
+        }
+
+        // the user rdtsc faults and is emulated from the virtual clock in the deterministic mode
+        if QUARK_CONFIG.lock().Deterministic {
+            vcpu_sregs.cr4 |= CR4_TSD;
+        }
 
         vcpu_sregs.efer = EFER_LME | EFER_LMA | EFER_SCE | EFER_NX;
 
@@ -461,6 +473,7 @@ impl KVMVcpu {
                             super::print::LOG.lock().Clear();
                             PerfPrint();
 
+                            JOURNAL.Record(JournalKind::Exit, &format!("code={} {}", exitCode, Journal::Snapshot()));
                             SetExitStatus(exitCode);
 
                             //wake up Kernel io thread
@@ -478,6 +491,7 @@ impl KVMVcpu {
                             };
 
                             eprintln!("Application error: {}", msg.str);
+                            JOURNAL.Record(JournalKind::Panic, &msg.str);
                             ::std::process::exit(1);
                         }
 
@@ -513,6 +527,7 @@ impl KVMVcpu {
                             let data2 = vcpu_regs.rcx;
                             error!("OOM!!! cpu [{}], size is {:x}, alignment is {:x}", self.id, data1, data2);
                             eprintln!("OOM!!! cpu [{}], size is {:x}, alignment is {:x}", self.id, data1, data2);
+                            JOURNAL.Record(JournalKind::Oom, &format!("cpu={} size={:x} alignment={:x} {}", self.id, data1, data2, Journal::Snapshot()));
                             ::std::process::exit(1);
                         }
 
@@ -629,6 +644,11 @@ impl KVMVcpu {
                             let ret = SHARE_SPACE.scheduler.WaitVcpu(&SHARE_SPACE, self.id, true);
                             match ret {
                                 Ok(taskId) => {
+                                    // the sandbox exits when the guest memory can't be restored
+                                    if HIBERNATOR.OnVcpuRun().is_err() {
+                                        return Ok(())
+                                    }
+
                                     unsafe {
                                         *(retAddr as * mut u64) = taskId as u64;
                                     }
@@ -764,6 +784,11 @@ impl KVMVcpu {
             let ret = sharespace.scheduler.WaitVcpu(sharespace, self.id, true);
             match ret {
                 Ok(taskId) => {
+                    // the sandbox exits when the guest memory can't be restored
+                    if HIBERNATOR.OnVcpuRun().is_err() {
+                        return -1
+                    }
+
                     return taskId as i64
                 },
                 Err(Error::Exit) => return -1,
@@ -789,6 +814,13 @@ impl KVMVcpu {
                     };
                     let currTaskId = qmsg.taskId;
 
+                    // the host name lookup waits for the dns servers, it is done by the resolver
+                    // threads which wake up the task
+                    if let Msg::ResolveHost(_) = qmsg.msg {
+                        RESOLVER.Submit(addr);
+                        continue;
+                    }
+
                     {
                         let _l = if qmsg.globalLock {
                             Some(super::GLOCK.lock())
@@ -823,15 +855,11 @@ impl Scheduler {
     }
 
     pub fn VcpWaitMaskSet(&self, vcpuId: usize) -> bool {
-        let mask = 1<<vcpuId;
-        let prev = self.vcpuWaitMask.fetch_or(mask, Ordering::SeqCst);
-        return (prev & mask) != 0
+        return self.vcpuWaitMask.Set(vcpuId)
     }
 
     pub fn VcpWaitMaskClear(&self, vcpuId: usize) -> bool {
-        let mask = 1<<vcpuId;
-        let prev = self.vcpuWaitMask.fetch_and(!(1<<vcpuId), Ordering::SeqCst);
-        return (prev & mask) != 0;
+        return self.vcpuWaitMask.Clear(vcpuId)
     }
 
 
@@ -900,14 +928,14 @@ impl CPULocal {
         let mut count = 0;
 
         loop {
-            let cnt = IOURING.IOUrings()[0].HostSubmit().unwrap();
+            let cnt = IOURING.IOUrings()[0].HostSubmit(0).unwrap();
             if cnt == 0 {
                 break;
             }
             count += cnt;
         }
 
-        count += IOURING.DrainCompletionQueue();
+        count += IOURING.DrainSharedCompletionQueue();
         count += KVMVcpu::GuestMsgProcess(sharespace);
         count += FD_NOTIFIER.HostEpollWait() as usize;
 
@@ -59,6 +59,11 @@ extern crate fs2;
 extern crate regex;
 extern crate simplelog;
 extern crate tabwriter;
+extern crate ed25519_dalek;
+extern crate sha2;
+extern crate base64;
+extern crate lz4_flex;
+extern crate rustc_demangle;
 
 #[macro_use]
 pub mod asm;
@@ -128,6 +133,8 @@ lazy_static! {
         Arc::new(Mutex::new(ShareSpace::New()));
     pub static ref VMS: Mutex<VMSpace> = Mutex::new(VMSpace::Init());
     pub static ref ROOT_CONTAINER_ID: Mutex<String> = Mutex::new(String::new());
+    // the net policy annotation of the sandbox, validated in VirtualMachine::Init
+    pub static ref NET_POLICY: Mutex<String> = Mutex::new(String::new());
     pub static ref PAGE_ALLOCATOR: MemAllocator = MemAllocator::New();
     pub static ref FD_NOTIFIER: HostFdNotifier = HostFdNotifier::New();
     pub static ref IO_MGR: vmspace::HostFileMap::IOMgr =
@@ -137,6 +144,18 @@ lazy_static! {
     pub static ref QUARK_CONFIG: Mutex<Config> = {
         let mut config = Config::default();
         config.Load();
+        match config.Validate() {
+            Ok(notes) => {
+                for note in notes {
+                    info!("config: {}", note);
+                }
+            }
+            Err(e) => {
+                error!("invalid config {}: {:?}", Config::CONFIG_FILE, e);
+                eprintln!("invalid config {}: {:?}", Config::CONFIG_FILE, e);
+                ::std::process::exit(-1);
+            }
+        }
         Mutex::new(config)
     };
     pub static ref URING_MGR: Arc<Mutex<UringMgr>> = {
@@ -173,7 +192,8 @@ fn main() {
         info!("commandline args is {}", str);
     }
 
-    let shimMode = QUARK_CONFIG.lock().ShimMode;
+    // "config check" validates the config file itself, don't load it as the runtime config
+    let shimMode = &cmd != "config" && QUARK_CONFIG.lock().ShimMode;
     if shimMode == true && &cmd != "boot"  {
         error!("*********shim mode***************");
         containerd_shim::run::<Service>("io.containerd.empty.v1", None)
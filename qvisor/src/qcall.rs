@@ -16,11 +16,12 @@ use super::qlib::{ShareSpace};
 use super::qlib::common::*;
 use super::qlib::qmsg::*;
 use super::qlib::range::*;
+use super::qlib::linux_def::SysErr;
 use super::qlib::kernel::*;
 use super::*;
 use super::kvm_vcpu::KVMVcpu;
 
-pub fn AQHostCall(msg: HostOutputMsg, _shareSpace: &ShareSpace) {
+pub fn AQHostCall(msg: HostOutputMsg, shareSpace: &ShareSpace) {
     let _l = super::GLOCK.lock();
     match msg {
         HostOutputMsg::Default => {
@@ -46,10 +47,28 @@ pub fn AQHostCall(msg: HostOutputMsg, _shareSpace: &ShareSpace) {
                 panic!("Eventfd write fail with error {}", ret)
             }
         }
-        HostOutputMsg::PostRDMAConnect(_addr) => {
-            //let msgRef = PostRDMAConnect::ToRef(addr);
-            //super::VMSpace::PostRDMAConnect(msgRef);
-            panic!("PostRDMAConnect qcall not implemented")
+        #[cfg(feature = "rdma")]
+        HostOutputMsg::PostRDMAConnect(addr) => {
+            let msgRef = PostRDMAConnect::ToRef(addr);
+            super::VMSpace::PostRDMAConnect(msgRef);
+        }
+        // the connection goes on by the tcp path
+        #[cfg(not(feature = "rdma"))]
+        HostOutputMsg::PostRDMAConnect(addr) => {
+            PostRDMAConnect::ToRef(addr).Finish(0);
+        }
+        HostOutputMsg::CloseAsync(msg) => {
+            let ret = super::VMSpace::Close(msg.fd);
+            if ret < 0 {
+                error!("CloseAsync fail err is {}, fd is {}", ret, msg.fd);
+            }
+            shareSpace.DecrPendingClose();
+        }
+        HostOutputMsg::ShutdownAsync(msg) => {
+            let ret = super::VMSpace::Shutdown(msg.fd, msg.how);
+            if ret < 0 {
+                error!("ShutdownAsync fail err is {}, fd is {}", ret, msg.fd);
+            }
         }
     }
 }
@@ -82,6 +101,9 @@ impl KVMVcpu {
             Msg::Fallocate(msg) => {
                 ret = super::VMSpace::Fallocate(msg.fd, msg.mode, msg.offset, msg.len) as u64;
             },
+            Msg::ReopenFd(msg) => {
+                ret = super::VMSpace::ReopenFd(msg.fd, msg.flags) as u64;
+            },
             Msg::RenameAt(msg) => {
                 ret = super::VMSpace::RenameAt(msg.olddirfd, msg.oldpath, msg.newdirfd, msg.newpath) as u64;
             },
@@ -170,6 +192,9 @@ impl KVMVcpu {
             Msg::Socket(msg) => {
                 ret = super::VMSpace::Socket(msg.domain, msg.type_, msg.protocol) as u64;
             },
+            Msg::SocketPair(msg) => {
+                ret = super::VMSpace::SocketPair(msg.domain, msg.type_, msg.protocol, msg.socketVect) as u64;
+            },
             Msg::GetPeerName(msg) => {
                 ret = super::VMSpace::GetPeerName(msg.sockfd, msg.addr, msg.addrlen) as u64;
             },
@@ -185,13 +210,22 @@ impl KVMVcpu {
             Msg::IOBind(msg) => {
                 ret = super::VMSpace::Bind(msg.sockfd, msg.addr, msg.addrlen, msg.umask) as u64;
             },
-            Msg::RDMAListen(_msg) => {
-                //ret = super::VMSpace::RDMAListen(msg.sockfd, msg.backlog, msg.block, msg.acceptQueue.clone()) as u64;
-                panic!("RDMAListen qcall not implemented")
+            #[cfg(feature = "rdma")]
+            Msg::RDMAListen(msg) => {
+                ret = super::VMSpace::RDMAListen(msg.sockfd, msg.backlog, msg.block, msg.acceptQueue.clone()) as u64;
+            },
+            #[cfg(feature = "rdma")]
+            Msg::RDMANotify(msg) => {
+                ret = super::VMSpace::RDMANotify(msg.sockfd, msg.typ) as u64;
             },
-            Msg::RDMANotify(_msg) => {
-                //ret = super::VMSpace::RDMANotify(msg.sockfd, msg.typ) as u64;
-                panic!("RDMANotify qcall not implemented")
+            #[cfg(feature = "rdma")]
+            Msg::RDMAUdpSocket(msg) => {
+                ret = super::VMSpace::RDMAUdpSocket(msg.sockfd, msg.socketBuf.clone()) as u64;
+            },
+            // qvisor is built without the rdma feature, EnableRDMA is turned off by Config::Validate
+            #[cfg(not(feature = "rdma"))]
+            Msg::RDMAListen(_) | Msg::RDMANotify(_) | Msg::RDMAUdpSocket(_) => {
+                ret = -SysErr::ENODEV as i64 as u64;
             },
             Msg::IOListen(msg) => {
                 ret = super::VMSpace::Listen(msg.sockfd, msg.backlog, msg.block) as u64;
@@ -260,12 +294,36 @@ impl KVMVcpu {
             Msg::IOConnect(msg) => {
                 ret = super::VMSpace::IOConnect(msg.fd, msg.addr, msg.addrlen) as u64;
             },
+            Msg::LoopbackListen(msg) => {
+                ret = super::VMSpace::LoopbackListen(msg.fd) as u64;
+            },
+            Msg::LoopbackAccept(msg) => {
+                ret = super::VMSpace::LoopbackAccept(msg.fd, msg.addr, msg.addrlen) as u64;
+            },
+            Msg::LoopbackShm(msg) => {
+                ret = super::VMSpace::LoopbackShm(msg.fd) as u64;
+            },
+            Msg::SockRetrans(_) => {
+                ret = super::VMSpace::SockRetrans() as u64;
+            },
+            Msg::GetAcceptPeerInfo(msg) => {
+                ret = super::VMSpace::GetAcceptPeerInfo(msg.fd, msg.info) as u64;
+            },
             Msg::IORecvMsg(msg) => {
                 ret = super::VMSpace::IORecvMsg(msg.fd, msg.msghdr, msg.flags) as u64;
             },
             Msg::IOSendMsg(msg) => {
                 ret = super::VMSpace::IOSendMsg(msg.fd, msg.msghdr, msg.flags) as u64;
             },
+            Msg::IORecvMMsg(msg) => {
+                ret = super::VMSpace::IORecvMMsg(msg.fd, msg.msgvec, msg.vlen, msg.flags) as u64;
+            },
+            Msg::IOSendMMsg(msg) => {
+                ret = super::VMSpace::IOSendMMsg(msg.fd, msg.msgvec, msg.vlen, msg.flags) as u64;
+            },
+            Msg::SendFile(msg) => {
+                ret = super::VMSpace::SendFile(msg.outfd, msg.infd, msg.offset, msg.count) as u64;
+            },
             Msg::MMapFile(msg) => {
                 ret = match super::PMA_KEEPER.MapFile(msg.len, msg.prot, msg.fd, msg.offset) {
                     Err(Error::SysError(e)) => -e as u64,
@@ -292,6 +350,13 @@ impl KVMVcpu {
                     _ => panic!("UringMgr Enter fail")
                 }
             },
+            Msg::IoUringRegisterBufRing(msg) => {
+                ret = match URING_MGR.lock().RegisterBufRing(msg.idx, msg.ringAddr, msg.entries, msg.bgid) {
+                    Ok(()) => 0,
+                    Err(Error::SysError(v)) => -v as i64 as u64,
+                    _ => panic!("UringMgr RegisterBufRing fail")
+                }
+            },
             Msg::Statm(msg) => {
                 ret = super::VMSpace::Statm(msg.buf) as u64;
             },
@@ -316,6 +381,27 @@ impl KVMVcpu {
             Msg::Sysinfo(msg) => {
                 ret = super::VMSpace::Sysinfo(msg.addr) as u64;
             },
+            Msg::ReadEtcFile(msg) => {
+                ret = super::VMSpace::ReadEtcFile(msg.idx, msg.buf, msg.len) as u64;
+            },
+            Msg::ReadRouteDump(msg) => {
+                ret = super::VMSpace::ReadRouteDump(msg.msgType, msg.buf, msg.len) as u64;
+            },
+            Msg::ReadSandboxIdentity(msg) => {
+                ret = super::VMSpace::ReadSandboxIdentity(msg.buf, msg.len) as u64;
+            },
+            Msg::ResolveHost(msg) => {
+                ret = super::vmspace::resolver::RESOLVER.Resolve(msg) as u64;
+            },
+            Msg::LoadCompatProfiles(msg) => {
+                ret = super::VMSpace::LoadCompatProfiles(msg.addr, msg.len) as u64;
+            },
+            Msg::LoadNatRules(msg) => {
+                ret = super::VMSpace::LoadNatRules(msg.addr, msg.len) as u64;
+            },
+            Msg::LoadNetPolicy(msg) => {
+                ret = super::VMSpace::LoadNetPolicy(msg.addr, msg.len) as u64;
+            },
             Msg::Rdtsc(_msg) => {
                 ret = TSC.Rdtsc() as u64;
             },
@@ -89,6 +89,12 @@ impl Config {
         return true;
     }
 
+    pub fn LoadFile(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::IOError(format!("read {} fail: {:?}", path, e)))?;
+        let config = serde_json::from_str(&contents).map_err(|e| Error::SerdeJson(format!("{} wrong format: {:?}", path, e)))?;
+        return Ok(config)
+    }
+
     pub fn Print(&self) {
         let c = serde_json::to_string(self).unwrap();
         error!("config is {}", c);
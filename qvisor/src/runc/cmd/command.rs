@@ -32,6 +32,14 @@ use super::ps::*;
 use super::kill::*;
 use super::delete::*;
 use super::state::*;
+use super::configcmd::*;
+use super::debug::*;
+use super::profile::*;
+use super::shm::*;
+use super::inject::*;
+use super::journal::*;
+use super::pool::*;
+use super::sockstat::*;
 
 fn id_validator(val: String) -> core::result::Result<(), String> {
     if val.contains("..") || val.contains('/') {
@@ -203,6 +211,30 @@ pub fn Parse() -> Result<Arguments> {
         .subcommand(
             StateCmd::SubCommand(&common)
         )
+        .subcommand(
+            ConfigCmd::SubCommand(&common)
+        )
+        .subcommand(
+            DebugCmd::SubCommand(&common)
+        )
+        .subcommand(
+            ProfileCmd::SubCommand(&common)
+        )
+        .subcommand(
+            JournalCmd::SubCommand(&common)
+        )
+        .subcommand(
+            PoolCmd::SubCommand(&common)
+        )
+        .subcommand(
+            SockStatCmd::SubCommand(&common)
+        )
+        .subcommand(
+            ShmCmd::SubCommand(&common)
+        )
+        .subcommand(
+            InjectCmd::SubCommand(&common)
+        )
         .get_matches_from(get_args());
 
     let level = match matches.occurrences_of("v") {
@@ -315,6 +347,54 @@ pub fn Parse() -> Result<Arguments> {
                 cmd: Command::StateCmd(StateCmd::Init(&cmd_matches)?)
             }
         }
+        ("config", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::ConfigCmd(ConfigCmd::Init(&cmd_matches)?)
+            }
+        }
+        ("debug", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::DebugCmd(DebugCmd::Init(&cmd_matches)?)
+            }
+        }
+        ("profile", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::ProfileCmd(ProfileCmd::Init(&cmd_matches)?)
+            }
+        }
+        ("shm", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::ShmCmd(ShmCmd::Init(&cmd_matches)?)
+            }
+        }
+        ("inject", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::InjectCmd(InjectCmd::Init(&cmd_matches)?)
+            }
+        }
+        ("journal", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::JournalCmd(JournalCmd::Init(&cmd_matches)?)
+            }
+        }
+        ("pool", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::PoolCmd(PoolCmd::Init(&cmd_matches)?)
+            }
+        }
+        ("sockstat", Some(cmd_matches)) => {
+            Arguments {
+                config: gConfig,
+                cmd: Command::SockStatCmd(SockStatCmd::Init(&cmd_matches)?)
+            }
+        }
         // We should never reach here because clap already enforces this
          _ => panic!("command not recognized"),
     };
@@ -343,7 +423,15 @@ pub enum Command {
     PsCmd(PsCmd),
     KillCmd(KillCmd),
     DeleteCmd(DeleteCmd),
-    StateCmd(StateCmd)
+    StateCmd(StateCmd),
+    ConfigCmd(ConfigCmd),
+    DebugCmd(DebugCmd),
+    ProfileCmd(ProfileCmd),
+    ShmCmd(ShmCmd),
+    InjectCmd(InjectCmd),
+    JournalCmd(JournalCmd),
+    PoolCmd(PoolCmd),
+    SockStatCmd(SockStatCmd),
 }
 
 pub fn Run(args: &mut Arguments) -> Result<()> {
@@ -361,6 +449,14 @@ pub fn Run(args: &mut Arguments) -> Result<()> {
         Command::PsCmd(cmd) => return cmd.Run(&mut args.config),
         Command::KillCmd(cmd) => return cmd.Run(&mut args.config),
         Command::DeleteCmd(cmd) => return cmd.Run(&mut args.config),
-        Command::StateCmd(cmd) => return cmd.Run(&mut args.config)
+        Command::StateCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::ConfigCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::DebugCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::ProfileCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::ShmCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::InjectCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::JournalCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::PoolCmd(cmd) => return cmd.Run(&mut args.config),
+        Command::SockStatCmd(cmd) => return cmd.Run(&mut args.config),
     }
 }
@@ -0,0 +1,76 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, AppSettings, SubCommand, ArgMatches, Arg};
+
+use super::super::super::qlib::common::*;
+use super::super::super::qlib::config::*;
+use super::config::GlobalConfig;
+use super::command::CommonArgs;
+
+#[derive(Debug)]
+pub struct ConfigCmd {
+    pub file: String,
+}
+
+impl ConfigCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        let check = match cmd_matches.subcommand_matches("check") {
+            None => return Err(Error::Common("config: subcommand is required, e.g. config check".to_string())),
+            Some(check) => check,
+        };
+
+        return Ok(Self {
+            file: check.value_of("file").unwrap().to_string(),
+        })
+    }
+
+    pub fn SubCommand<'a, 'b>(_common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("config")
+            .setting(AppSettings::ColoredHelp)
+            .setting(AppSettings::SubcommandRequired)
+            .about("Manage the quark config file")
+            .subcommand(
+                SubCommand::with_name("check")
+                    .setting(AppSettings::ColoredHelp)
+                    .about("Validate the config file and print the derived defaults")
+                    .arg(
+                        Arg::with_name("file")
+                            .default_value(Config::CONFIG_FILE)
+                            .long("file")
+                            .short("f")
+                            .takes_value(true)
+                            .help("path of the config file"),
+                    )
+            );
+    }
+
+    pub fn Run(&self, _gCfg: &GlobalConfig) -> Result<()> {
+        let mut config = Config::LoadFile(&self.file)?;
+        match config.Validate() {
+            Ok(notes) => {
+                for note in notes {
+                    println!("{}: {}", &self.file, note);
+                }
+                println!("{}: ok", &self.file);
+                return Ok(())
+            }
+            Err(Error::Common(e)) => {
+                println!("{}: {}", &self.file, e);
+                return Err(Error::Common(e))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
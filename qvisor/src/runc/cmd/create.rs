@@ -20,6 +20,8 @@ use super::super::super::qlib::path::*;
 use super::super::cmd::config::*;
 use super::super::oci::*;
 use super::super::container::container::*;
+use super::super::specutils::specutils::*;
+use super::pool::*;
 use super::command::*;
 
 #[derive(Debug)]
@@ -57,7 +59,15 @@ impl CreateCmd {
 
     pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
         let specfile = Join(&self.bundleDir, "config.json");
-        let spec = Spec::load(&specfile).unwrap();
+        let mut spec = Spec::load(&specfile).unwrap();
+
+        // the container takes a warm sandbox of the pool when the pool manager runs
+        if ShouldCreateSandbox(&spec) {
+            if let Some(sid) = ClaimWarmSandbox(&gCfg.RootDir, &self.id, &spec) {
+                info!("create container {} in the warm sandbox {}", &self.id, &sid);
+                JoinSandbox(&mut spec, &sid);
+            }
+        }
 
         Container::Create(
             &self.id,
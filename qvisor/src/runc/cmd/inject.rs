@@ -0,0 +1,160 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, AppSettings, SubCommand, ArgMatches, Arg};
+use alloc::string::String;
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+
+use super::super::super::qlib::common::*;
+use super::super::cmd::config::*;
+use super::super::container::container::*;
+use super::command::*;
+
+#[derive(Debug)]
+pub struct InjectCmd {
+    pub id: String,
+    pub path: String,
+    pub source: String,
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl InjectCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        let path = cmd_matches.value_of("path").unwrap().to_string();
+        if !path.starts_with('/') {
+            return Err(Error::Common(format!("inject: {} is not an absolute path", path)))
+        }
+
+        let mode = u16::from_str_radix(cmd_matches.value_of("mode").unwrap(), 8)
+            .map_err(|e| Error::Common(format!("inject: invalid mode {:?}", e)))?;
+        let uid = cmd_matches.value_of("uid").unwrap().parse::<u32>()
+            .map_err(|e| Error::Common(format!("inject: invalid uid {:?}", e)))?;
+        let gid = cmd_matches.value_of("gid").unwrap().parse::<u32>()
+            .map_err(|e| Error::Common(format!("inject: invalid gid {:?}", e)))?;
+
+        return Ok(Self {
+            id: cmd_matches.value_of("id").unwrap().to_string(),
+            path: path,
+            source: cmd_matches.value_of("source").unwrap().to_string(),
+            mode: mode & 0o7777,
+            uid: uid,
+            gid: gid,
+        })
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("inject")
+            .setting(AppSettings::ColoredHelp)
+            .arg(&common.id_arg)
+            .arg(
+                Arg::with_name("path")
+                    .required(true)
+                    .takes_value(true)
+                    .help("absolute path of the file in the sandbox"),
+            )
+            .arg(
+                Arg::with_name("source")
+                    .help("host file of the content")
+                    .required(true)
+                    .takes_value(true)
+                    .long("source")
+                    .short("s"),
+            )
+            .arg(
+                Arg::with_name("mode")
+                    .help("octal file mode")
+                    .default_value("0644")
+                    .takes_value(true)
+                    .long("mode"),
+            )
+            .arg(
+                Arg::with_name("uid")
+                    .help("owner uid in the sandbox")
+                    .default_value("0")
+                    .takes_value(true)
+                    .long("uid"),
+            )
+            .arg(
+                Arg::with_name("gid")
+                    .help("owner gid in the sandbox")
+                    .default_value("0")
+                    .takes_value(true)
+                    .long("gid"),
+            )
+            .about("Create or replace a file in the sandbox atomically, e.g. to rotate the secrets");
+    }
+
+    pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
+        info!("Container:: inject ....");
+        let container = Container::Load(&gCfg.RootDir, &self.id)?;
+
+        // the file is read in the sandbox with pread, the source must be a regular file
+        let source = File::open(&self.source)
+            .map_err(|e| Error::Common(format!("inject: open {} fail {:?}", self.source, e)))?;
+        let meta = source.metadata()
+            .map_err(|e| Error::Common(format!("inject: stat {} fail {:?}", self.source, e)))?;
+        if !meta.is_file() {
+            return Err(Error::Common(format!("inject: {} is not a regular file", self.source)))
+        }
+
+        container.InjectFile(&self.path, source.as_raw_fd(), self.mode, self.uid, self.gid)?;
+        return Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<InjectCmd> {
+        let common = CommonArgs::New();
+        let mut argv = vec!["inject"];
+        argv.extend_from_slice(args);
+        let matches = InjectCmd::SubCommand(&common).get_matches_from_safe(argv)
+            .map_err(|e| Error::Common(format!("{:?}", e)))?;
+        return InjectCmd::Init(&matches)
+    }
+
+    #[test]
+    fn test_inject_cmd_defaults() {
+        let cmd = parse(&["c1", "/etc/secret", "--source", "/tmp/secret"]).unwrap();
+        assert_eq!(cmd.id, "c1");
+        assert_eq!(cmd.path, "/etc/secret");
+        assert_eq!(cmd.source, "/tmp/secret");
+        assert_eq!(cmd.mode, 0o644);
+        assert_eq!(cmd.uid, 0);
+        assert_eq!(cmd.gid, 0);
+    }
+
+    #[test]
+    fn test_inject_cmd_options() {
+        let cmd = parse(&["c1", "/run/token", "-s", "/tmp/token", "--mode", "104600",
+                          "--uid", "1000", "--gid", "100"]).unwrap();
+        // only the permission bits are kept of the mode
+        assert_eq!(cmd.mode, 0o4600);
+        assert_eq!(cmd.uid, 1000);
+        assert_eq!(cmd.gid, 100);
+    }
+
+    #[test]
+    fn test_inject_cmd_invalid() {
+        assert!(parse(&["c1", "etc/secret", "-s", "/tmp/secret"]).is_err());
+        assert!(parse(&["c1", "/etc/secret", "-s", "/tmp/secret", "--mode", "0999"]).is_err());
+        assert!(parse(&["c1", "/etc/secret", "-s", "/tmp/secret", "--uid", "-1"]).is_err());
+        assert!(parse(&["c1", "/etc/secret"]).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, AppSettings, SubCommand, ArgMatches};
+use alloc::string::String;
+use chrono::{DateTime, Local};
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+
+use super::super::super::qlib::common::*;
+use super::super::super::vmspace::journal::*;
+use super::super::cmd::config::*;
+use super::command::*;
+
+#[derive(Debug)]
+pub struct JournalCmd {
+    pub id: String,
+}
+
+impl JournalCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        return Ok(Self {
+            id: cmd_matches.value_of("id").unwrap().to_string(),
+        })
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("journal")
+            .setting(AppSettings::ColoredHelp)
+            .arg(&common.id_arg)
+            .about("Print the event journal of a sandbox, the sandbox doesn't have to exist any more");
+    }
+
+    pub fn Run(&self, _gCfg: &GlobalConfig) -> Result<()> {
+        let path = JournalPath(&self.id);
+        let records = ReadJournal(&path)
+            .map_err(|e| Error::IOError(format!("journal: read {} fail with error {:?}", &path, e)))?;
+        for r in records {
+            let datetime: DateTime<Local> = (UNIX_EPOCH + Duration::from_nanos(r.time)).into();
+            println!("{:<8} {} {:<9} {}", r.seq, datetime.format("%Y-%m-%dT%H:%M:%S%.6f"), r.kind.Name(), r.payload);
+        }
+
+        return Ok(())
+    }
+}
@@ -27,4 +27,12 @@ pub mod resume;
 pub mod ps;
 pub mod kill;
 pub mod delete;
-pub mod state;
\ No newline at end of file
+pub mod state;
+pub mod configcmd;
+pub mod debug;
+pub mod profile;
+pub mod shm;
+pub mod inject;
+pub mod journal;
+pub mod pool;
+pub mod sockstat;
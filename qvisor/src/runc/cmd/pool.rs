@@ -0,0 +1,492 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, AppSettings, SubCommand, ArgMatches, Arg};
+use alloc::string::String;
+use fs2::FileExt;
+use sha2::Digest;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::{thread, time};
+
+use super::super::super::qlib::common::*;
+use super::super::super::qlib::path::*;
+use super::super::cmd::config::*;
+use super::super::oci::*;
+use super::super::container::container::*;
+use super::command::*;
+
+// The sandbox pool keeps size sandboxes booted from the template bundle. While the pool manager
+// runs, the create of a root container takes a warm sandbox and creates the container in it, so
+// the app doesn't wait for the sandbox boot. A sandbox serves one container, it is destroyed
+// after the container is deleted and the manager boots a new one.
+//
+// The pool state is in the directory beside the runtime root, as the runtime root only has the
+// containers: warm/<sandbox> is a sandbox ready to take with the spec hash of the template in it,
+// a create moves it to claimed/<sandbox> and writes the container id into it.
+//
+// A create takes a warm sandbox only when its spec hash is the one of the template, i.e. the
+// sandbox wide settings are the same, or when it opts in with POOL_CLAIM_ANNOTATION.
+
+const POOL_PID_FILE: &str = "pid";
+const POOL_METRICS_FILE: &str = "metrics.json";
+const POOL_MISSES_FILE: &str = "misses";
+const POOL_WARM_DIR: &str = "warm";
+const POOL_CLAIMED_DIR: &str = "claimed";
+
+// the create with "true" takes a warm sandbox whatever its spec is
+pub const POOL_CLAIM_ANNOTATION: &str = "dev.quark.pool.claim";
+
+// the claimed sandbox whose container has no metadata is released after the grace, the create
+// may still be running before it
+const CLAIM_GRACE: time::Duration = time::Duration::from_secs(30);
+
+pub fn PoolDir(rootDir: &str) -> String {
+    return format!("{}-pool", rootDir.trim_end_matches('/'))
+}
+
+// PoolRunning returns whether the pool manager of the pool dir is alive
+pub fn PoolRunning(dir: &str) -> bool {
+    let pid = match fs::read_to_string(Join(dir, POOL_PID_FILE)) {
+        Err(_) => return false,
+        Ok(s) => match s.trim().parse::<i32>() {
+            Err(_) => return false,
+            Ok(pid) => pid,
+        }
+    };
+
+    return unsafe { libc::kill(pid, 0) } == 0
+}
+
+// SandboxSpecHash returns the hash of the sandbox wide settings of the spec: the hostname, the
+// mounts and the linux section. The process, the root and the annotations are the container's.
+pub fn SandboxSpecHash(spec: &Spec) -> Result<String> {
+    // the maps of the json value are ordered, the hash doesn't depend on the HashMap order
+    let value = serde_json::to_value(&(&spec.hostname, &spec.mounts, &spec.linux))
+        .map_err(|e| Error::Common(format!("pool: serialize spec fail with error {:?}", e)))?;
+    let data = serde_json::to_string(&value)
+        .map_err(|e| Error::Common(format!("pool: serialize spec fail with error {:?}", e)))?;
+    let digest = Sha256::digest(data.as_bytes());
+    return Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// ClaimWarmSandbox takes a warm sandbox of the pool matching the spec for the container id, it
+// returns None when the pool manager doesn't run or the pool has none
+pub fn ClaimWarmSandbox(rootDir: &str, id: &str, spec: &Spec) -> Option<String> {
+    let dir = PoolDir(rootDir);
+    if !PoolRunning(&dir) {
+        return None
+    }
+
+    let any = spec.annotations.get(POOL_CLAIM_ANNOTATION).map(|v| v == "true").unwrap_or(false);
+    let hash = if any {
+        String::new()
+    } else {
+        match SandboxSpecHash(spec) {
+            Err(e) => {
+                error!("{:?}", e);
+                return None
+            }
+            Ok(h) => h,
+        }
+    };
+
+    if let Ok(entries) = fs::read_dir(Join(&dir, POOL_WARM_DIR)) {
+        for entry in entries.flatten() {
+            let sid = entry.file_name().to_string_lossy().to_string();
+            if !any && fs::read_to_string(entry.path()).unwrap_or_default() != hash {
+                continue;
+            }
+
+            let claimed = Join(&Join(&dir, POOL_CLAIMED_DIR), &sid);
+            // the rename is atomic, the other creates fail with ENOENT
+            if fs::rename(entry.path(), &claimed).is_err() {
+                continue;
+            }
+
+            if let Err(e) = fs::write(&claimed, id) {
+                error!("pool: fail to record container {} of sandbox {}: {:?}", id, &sid, e);
+            }
+
+            return Some(sid)
+        }
+    }
+
+    if let Err(e) = CountMiss(&dir) {
+        error!("pool: fail to count the miss: {:?}", e);
+    }
+
+    return None
+}
+
+// CountMiss increments the miss counter in the misses file, the file is locked by the creates
+fn CountMiss(dir: &str) -> std::io::Result<()> {
+    let mut f = OpenOptions::new().create(true).read(true).write(true).open(Join(dir, POOL_MISSES_FILE))?;
+    f.lock_exclusive()?;
+    let misses = ReadCount(&mut f) + 1;
+    f.set_len(0)?;
+    f.seek(SeekFrom::Start(0))?;
+    f.write_all(format!("{}", misses).as_bytes())?;
+    return f.unlock()
+}
+
+fn ReadCount(f: &mut fs::File) -> u64 {
+    let mut s = String::new();
+    f.read_to_string(&mut s).ok();
+    return s.trim().parse::<u64>().unwrap_or(0)
+}
+
+// Misses returns the count of the creates which found no warm sandbox
+pub fn Misses(dir: &str) -> u64 {
+    let mut f = match fs::File::open(Join(dir, POOL_MISSES_FILE)) {
+        Err(_) => return 0,
+        Ok(f) => f,
+    };
+
+    if f.lock_shared().is_err() {
+        return 0
+    }
+
+    let misses = ReadCount(&mut f);
+    f.unlock().ok();
+    return misses
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PoolMetrics {
+    pub size: usize,
+    pub warm: usize,
+    pub claimed: usize,
+    // the creates which took a warm sandbox and the ones which found the pool empty
+    pub hits: u64,
+    pub misses: u64,
+    pub boots: u64,
+    pub bootFailures: u64,
+    // the claimed sandboxes destroyed after their container is gone
+    pub recycled: u64,
+    pub lastBootMs: u64,
+    pub avgBootMs: u64,
+}
+
+pub struct SandboxPool<'a> {
+    pub conf: &'a GlobalConfig,
+    pub dir: String,
+    pub bundleDir: String,
+    // the sandbox spec hash of the template
+    pub specHash: String,
+    pub size: usize,
+    pub pivot: bool,
+    pub seq: u64,
+    // the claimed sandboxes counted as the hits
+    pub claimed: HashSet<String>,
+    pub metrics: PoolMetrics,
+}
+
+impl <'a> SandboxPool <'a> {
+    pub fn New(conf: &'a GlobalConfig, bundleDir: &str, size: usize, pivot: bool) -> Result<Self> {
+        let dir = PoolDir(&conf.RootDir);
+        if PoolRunning(&dir) {
+            return Err(Error::Common(format!("pool: the pool manager of {} is running", &dir)))
+        }
+
+        for d in &[POOL_WARM_DIR, POOL_CLAIMED_DIR] {
+            fs::create_dir_all(Join(&dir, d))
+                .map_err(|e| Error::IOError(format!("pool: create {} fail with error {:?}", d, e)))?;
+        }
+
+        let specfile = Join(bundleDir, "config.json");
+        let spec = Spec::load(&specfile)
+            .map_err(|e| Error::Common(format!("pool: load template {} fail with error {:?}", &specfile, e)))?;
+        let specHash = SandboxSpecHash(&spec)?;
+
+        Container::WriteStr(&Join(&dir, POOL_PID_FILE), &format!("{}", std::process::id()))?;
+
+        // the sandboxes left by the previous manager are checked by the first refresh
+        return Ok(Self {
+            conf: conf,
+            dir: dir,
+            bundleDir: bundleDir.to_string(),
+            specHash: specHash,
+            size: size,
+            pivot: pivot,
+            seq: 0,
+            claimed: HashSet::new(),
+            metrics: PoolMetrics {
+                size: size,
+                ..Default::default()
+            },
+        })
+    }
+
+    pub fn Refresh(&mut self) {
+        self.ReapClaimed();
+        let mut warm = self.CheckWarm();
+        while warm < self.size {
+            match self.Boot() {
+                Err(e) => {
+                    error!("pool: boot sandbox fail: {:?}", e);
+                    self.metrics.bootFailures += 1;
+                    break;
+                }
+                Ok(()) => warm += 1,
+            }
+        }
+
+        self.metrics.warm = warm;
+        self.metrics.claimed = self.claimed.len();
+        self.metrics.misses = Misses(&self.dir);
+        match serde_json::to_string_pretty(&self.metrics) {
+            Err(e) => error!("pool: serialize metrics fail: {:?}", e),
+            Ok(s) => {
+                if let Err(e) = Container::WriteStr(&Join(&self.dir, POOL_METRICS_FILE), &s) {
+                    error!("pool: write metrics fail: {:?}", e);
+                }
+            }
+        }
+    }
+
+    // ReapClaimed destroys the claimed sandboxes whose container is deleted
+    pub fn ReapClaimed(&mut self) {
+        let entries = match fs::read_dir(Join(&self.dir, POOL_CLAIMED_DIR)) {
+            Err(_) => return,
+            Ok(entries) => entries,
+        };
+
+        for entry in entries.flatten() {
+            let sid = entry.file_name().to_string_lossy().to_string();
+            if self.claimed.insert(sid.clone()) {
+                self.metrics.hits += 1;
+            }
+
+            let id = fs::read_to_string(entry.path()).unwrap_or_default();
+            let meta = Join(&Join(&self.conf.RootDir, id.trim()), METADATA_FILENAME);
+            if id.trim().len() > 0 && Path::new(&meta).exists() {
+                continue;
+            }
+
+            let age = entry.metadata().ok()
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.elapsed().ok())
+                .unwrap_or(CLAIM_GRACE);
+            if age < CLAIM_GRACE {
+                continue;
+            }
+
+            info!("pool: recycle sandbox {} of container {}", &sid, id.trim());
+            self.Destroy(&sid);
+            fs::remove_file(entry.path()).ok();
+            self.claimed.remove(&sid);
+            self.metrics.recycled += 1;
+        }
+    }
+
+    // CheckWarm drops the warm sandboxes which are not running or of another template, e.g. the
+    // ones left by the previous manager, and returns the count of the rest
+    pub fn CheckWarm(&mut self) -> usize {
+        let entries = match fs::read_dir(Join(&self.dir, POOL_WARM_DIR)) {
+            Err(_) => return 0,
+            Ok(entries) => entries,
+        };
+
+        let mut count = 0;
+        for entry in entries.flatten() {
+            let sid = entry.file_name().to_string_lossy().to_string();
+            let running = match Container::Load(&self.conf.RootDir, &sid) {
+                Err(_) => false,
+                Ok(c) => c.Sandbox.as_ref().map(|s| s.IsRunning()).unwrap_or(false),
+            };
+            let template = fs::read_to_string(entry.path()).unwrap_or_default() == self.specHash;
+
+            if running && template {
+                count += 1;
+                continue;
+            }
+
+            // a create may take it after the check, the rename fails then
+            if fs::remove_file(entry.path()).is_ok() {
+                error!("pool: warm sandbox {} is not running or of another template", &sid);
+                self.Destroy(&sid);
+            }
+        }
+
+        return count
+    }
+
+    // Boot creates and starts a sandbox of the template
+    pub fn Boot(&mut self) -> Result<()> {
+        let id = format!("pool-{}-{}", std::process::id(), self.seq);
+        self.seq += 1;
+
+        let specfile = Join(&self.bundleDir, "config.json");
+        let spec = Spec::load(&specfile)
+            .map_err(|e| Error::Common(format!("pool: load template {} fail with error {:?}", &specfile, e)))?;
+        // the template may be changed, the warm sandboxes of the old one are dropped by CheckWarm
+        self.specHash = SandboxSpecHash(&spec)?;
+
+        let start = time::Instant::now();
+        let mut c = Container::Create(&id, RunAction::Create, spec, self.conf, &self.bundleDir, "", "", "", true, self.pivot)?;
+        if let Err(e) = c.Start(self.conf) {
+            c.Destroy().ok();
+            return Err(e)
+        }
+
+        let ms = start.elapsed().as_millis() as u64;
+        self.metrics.boots += 1;
+        self.metrics.lastBootMs = ms;
+        self.metrics.avgBootMs = (self.metrics.avgBootMs * (self.metrics.boots - 1) + ms) / self.metrics.boots;
+
+        Container::WriteStr(&Join(&Join(&self.dir, POOL_WARM_DIR), &id), &self.specHash)?;
+        info!("pool: sandbox {} is warm after {} ms", &id, ms);
+        return Ok(())
+    }
+
+    pub fn Destroy(&self, sid: &str) {
+        match Container::Load(&self.conf.RootDir, sid) {
+            Err(e) => error!("pool: load sandbox {} fail: {:?}", sid, e),
+            Ok(mut c) => {
+                if let Err(e) = c.Destroy() {
+                    error!("pool: destroy sandbox {} fail: {:?}", sid, e);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PoolCmd {
+    pub bundleDir: String,
+    pub size: usize,
+    pub interval: u64,
+    pub pivot: bool,
+    pub metrics: bool,
+}
+
+impl PoolCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        let size = cmd_matches.value_of("size").unwrap().parse::<usize>()
+            .map_err(|e| Error::Common(format!("pool: invalid size {:?}", e)))?;
+        let interval = cmd_matches.value_of("interval").unwrap().parse::<u64>()
+            .map_err(|e| Error::Common(format!("pool: invalid interval {:?}", e)))?;
+
+        return Ok(Self {
+            bundleDir: cmd_matches.value_of("bundle").unwrap().to_string(),
+            size: size,
+            interval: interval,
+            pivot: !cmd_matches.is_present("no-pivot"),
+            metrics: cmd_matches.is_present("metrics"),
+        })
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("pool")
+            .setting(AppSettings::ColoredHelp)
+            .arg(&common.bundle_arg)
+            .arg(&common.no_pivot_arg)
+            .arg(
+                Arg::with_name("size")
+                    .help("number of the warm sandboxes")
+                    .default_value("4")
+                    .takes_value(true)
+                    .long("size")
+                    .short("s"),
+            )
+            .arg(
+                Arg::with_name("interval")
+                    .help("refill interval in ms")
+                    .default_value("500")
+                    .takes_value(true)
+                    .long("interval"),
+            )
+            .arg(
+                Arg::with_name("metrics")
+                    .help("print the metrics of the running pool and exit")
+                    .long("metrics"),
+            )
+            .about("Keep warm sandboxes of the template bundle for the created containers");
+    }
+
+    pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
+        if self.metrics {
+            let path = Join(&PoolDir(&gCfg.RootDir), POOL_METRICS_FILE);
+            let data = fs::read_to_string(&path)
+                .map_err(|e| Error::IOError(format!("pool: read {} fail with error {:?}", &path, e)))?;
+            println!("{}", data);
+            return Ok(())
+        }
+
+        let mut pool = SandboxPool::New(gCfg, &self.bundleDir, self.size, self.pivot)?;
+        loop {
+            pool.Refresh();
+            thread::sleep(time::Duration::from_millis(self.interval));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_warm_sandbox() {
+        let root = format!("/tmp/quark-pool-test-{}", std::process::id());
+        let dir = PoolDir(&root);
+        fs::create_dir_all(Join(&dir, POOL_WARM_DIR)).unwrap();
+        fs::create_dir_all(Join(&dir, POOL_CLAIMED_DIR)).unwrap();
+        defer!(fs::remove_dir_all(&dir).ok());
+
+        let template = Spec::default();
+        let hash = SandboxSpecHash(&template).unwrap();
+        fs::write(Join(&Join(&dir, POOL_WARM_DIR), "a"), &hash).unwrap();
+        fs::write(Join(&Join(&dir, POOL_WARM_DIR), "b"), &hash).unwrap();
+        assert_eq!(ClaimWarmSandbox(&root, "c1", &template), None);
+
+        fs::write(Join(&dir, POOL_PID_FILE), format!("{}", std::process::id())).unwrap();
+        // the sandbox wide settings differ from the template
+        let mut other = Spec::default();
+        other.hostname = "other".to_string();
+        assert_eq!(ClaimWarmSandbox(&root, "c1", &other), None);
+        assert_eq!(Misses(&dir), 1);
+
+        // the container settings are not in the hash
+        let mut same = Spec::default();
+        same.process.args = vec!["sh".to_string()];
+        let sid = ClaimWarmSandbox(&root, "c1", &same).unwrap();
+        assert_eq!(fs::read_to_string(Join(&Join(&dir, POOL_CLAIMED_DIR), &sid)).unwrap(), "c1");
+
+        // the opt-in takes the sandbox whatever the spec is
+        other.annotations.insert(POOL_CLAIM_ANNOTATION.to_string(), "true".to_string());
+        assert!(ClaimWarmSandbox(&root, "c2", &other).is_some());
+        assert_eq!(ClaimWarmSandbox(&root, "c3", &other), None);
+        assert_eq!(Misses(&dir), 2);
+    }
+
+    #[test]
+    fn test_misses_bounded() {
+        let dir = format!("/tmp/quark-pool-misses-test-{}", std::process::id());
+        fs::create_dir_all(&dir).unwrap();
+        defer!(fs::remove_dir_all(&dir).ok());
+
+        assert_eq!(Misses(&dir), 0);
+        for _ in 0..1000 {
+            CountMiss(&dir).unwrap();
+        }
+
+        assert_eq!(Misses(&dir), 1000);
+        assert_eq!(fs::metadata(Join(&dir, POOL_MISSES_FILE)).unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, AppSettings, SubCommand, ArgMatches, Arg};
+use alloc::string::String;
+use alloc::vec::Vec;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::{thread, time};
+use memmap::Mmap;
+use xmas_elf::ElfFile;
+use xmas_elf::sections::SectionData;
+use xmas_elf::symbol_table::Entry;
+use xmas_elf::symbol_table::Type;
+
+use super::super::super::qlib::common::*;
+use super::super::super::qlib::control_msg::*;
+use super::super::cmd::config::*;
+use super::super::container::container::*;
+use super::super::runtime::vm::*;
+use super::command::*;
+
+#[derive(Debug)]
+pub struct ProfileCmd {
+    pub id: String,
+    pub hz: u64,
+    pub duration: u64,
+    pub output: String,
+    pub kernel: String,
+}
+
+impl ProfileCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        let hz = cmd_matches.value_of("hz").unwrap().parse::<u64>()
+            .map_err(|e| Error::Common(format!("profile: invalid hz {:?}", e)))?;
+        let duration = cmd_matches.value_of("duration").unwrap().parse::<u64>()
+            .map_err(|e| Error::Common(format!("profile: invalid duration {:?}", e)))?;
+
+        return Ok(Self {
+            id: cmd_matches.value_of("id").unwrap().to_string(),
+            hz: hz,
+            duration: duration,
+            output: cmd_matches.value_of("output").unwrap_or("").to_string(),
+            kernel: cmd_matches.value_of("kernel").unwrap_or(VirtualMachine::KERNEL_IMAGE).to_string(),
+        })
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("profile")
+            .setting(AppSettings::ColoredHelp)
+            .arg(&common.id_arg)
+            .arg(
+                Arg::with_name("hz")
+                    .help("sample frequency per vcpu")
+                    .default_value("99")
+                    .takes_value(true)
+                    .long("hz"),
+            )
+            .arg(
+                Arg::with_name("duration")
+                    .help("sample duration in seconds")
+                    .default_value("10")
+                    .takes_value(true)
+                    .long("duration")
+                    .short("d"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .help("write the folded stacks to the file instead of stdout")
+                    .takes_value(true)
+                    .long("output")
+                    .short("o"),
+            )
+            .arg(
+                Arg::with_name("kernel")
+                    .help("qkernel image used to symbolize the kernel frames")
+                    .takes_value(true)
+                    .long("kernel"),
+            )
+            .about("Sample the guest vcpus and output the folded stacks for flamegraph");
+    }
+
+    pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
+        info!("Container:: profile ....");
+        let container = Container::Load(&gCfg.RootDir, &self.id)?;
+
+        container.ProfileStart(self.hz)?;
+        thread::sleep(time::Duration::from_secs(self.duration));
+        let result = container.ProfileStop()?;
+
+        let symbols = match Symbolizer::Load(&self.kernel) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("profile: can't load symbols from {}: {:?}", self.kernel, e);
+                Symbolizer::default()
+            }
+        };
+
+        let folded = Fold(&result, &symbols);
+        if result.dropped > 0 {
+            eprintln!("profile: {} samples dropped", result.dropped);
+        }
+
+        if self.output.len() == 0 {
+            print!("{}", folded);
+            return Ok(())
+        }
+
+        let mut file = File::create(&self.output)
+            .map_err(|e| Error::IOError(format!("profile: create {} fail {:?}", self.output, e)))?;
+        file.write_all(folded.as_bytes())
+            .map_err(|e| Error::IOError(format!("profile: write {} fail {:?}", self.output, e)))?;
+        return Ok(())
+    }
+}
+
+// Fold outputs the stacks in the folded format of flamegraph.pl: "root;...;leaf count"
+pub fn Fold(result: &ProfileResult, symbols: &Symbolizer) -> String {
+    let mut lines: BTreeMap<String, u64> = BTreeMap::new();
+    for stack in &result.stacks {
+        let mut names = Vec::with_capacity(stack.frames.len() + 1);
+        if stack.user {
+            // the user binaries are not loaded on the host, the user frames are the addresses
+            names.push("[user]".to_string());
+            for frame in stack.frames.iter().rev() {
+                names.push(format!("{:#x}", frame));
+            }
+        } else {
+            names.push("[kernel]".to_string());
+            for frame in stack.frames.iter().rev() {
+                names.push(symbols.Lookup(*frame));
+            }
+        }
+
+        *lines.entry(names.join(";")).or_insert(0) += stack.count;
+    }
+
+    let mut output = String::new();
+    for (line, count) in lines {
+        output += &format!("{} {}\n", line, count);
+    }
+
+    return output
+}
+
+// Symbolizer resolves the kernel address with the function symbols of the qkernel image.
+// qkernel is loaded at its ELF virtual address so that no relocation is needed.
+#[derive(Debug, Default)]
+pub struct Symbolizer {
+    // (start, size, name) sorted by start
+    pub syms: Vec<(u64, u64, String)>,
+}
+
+impl Symbolizer {
+    pub fn Load(fileName: &str) -> Result<Self> {
+        let f = File::open(fileName).map_err(|e| Error::IOError(format!("io::error is {:?}", e)))?;
+        let mmap = unsafe { Mmap::map(&f).map_err(|e| Error::IOError(format!("io::error is {:?}", e)))? };
+        let elfFile = ElfFile::new(&mmap).map_err(Error::ELFLoadError)?;
+
+        let mut syms = Vec::new();
+        for section in elfFile.section_iter() {
+            let entries = match section.get_data(&elfFile) {
+                Ok(SectionData::SymbolTable64(entries)) => entries,
+                _ => continue,
+            };
+
+            for entry in entries {
+                match entry.get_type() {
+                    Ok(Type::Func) => (),
+                    _ => continue,
+                }
+
+                if entry.value() == 0 {
+                    continue;
+                }
+
+                let name = match entry.get_name(&elfFile) {
+                    Ok(name) => Demangle(name),
+                    Err(_) => continue,
+                };
+
+                syms.push((entry.value(), entry.size(), name));
+            }
+        }
+
+        syms.sort_by(|a, b| a.0.cmp(&b.0));
+        return Ok(Self {
+            syms: syms
+        })
+    }
+
+    pub fn Lookup(&self, addr: u64) -> String {
+        let idx = match self.syms.binary_search_by(|s| s.0.cmp(&addr)) {
+            Ok(idx) => idx,
+            Err(0) => return format!("{:#x}", addr),
+            Err(idx) => idx - 1,
+        };
+
+        let (start, size, name) = &self.syms[idx];
+        if *size != 0 && addr >= start + size {
+            return format!("{:#x}", addr);
+        }
+
+        return name.clone()
+    }
+}
+
+// Demangle decodes the rust symbol without the hash, the other names are returned unchanged
+pub fn Demangle(name: &str) -> String {
+    return format!("{:#}", rustc_demangle::demangle(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demangle() {
+        assert_eq!(Demangle("_ZN4core3fmt5write17h0123456789abcdefE"), "core::fmt::write");
+        assert_eq!(Demangle("_ZN60_$LT$alloc..vec..Vec$LT$T$GT$$u20$as$u20$core..ops..Drop$GT$4drop17h0123456789abcdefE"),
+                   "<alloc::vec::Vec<T> as core::ops::Drop>::drop");
+        assert_eq!(Demangle("memcpy"), "memcpy");
+    }
+
+    #[test]
+    fn test_fold() {
+        let symbols = Symbolizer {
+            syms: vec![(0x1000, 0x100, "a".to_string()), (0x2000, 0x100, "b".to_string())],
+        };
+        let result = ProfileResult {
+            stacks: vec![
+                ProfileStack { user: false, frames: vec![0x2010, 0x1010], count: 2 },
+                ProfileStack { user: false, frames: vec![0x2020, 0x1020], count: 1 },
+                ProfileStack { user: true, frames: vec![0x400000], count: 5 },
+            ],
+            dropped: 0,
+        };
+        assert_eq!(Fold(&result, &symbols), "[kernel];a;b 3\n[user];0x400000 5\n");
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, AppSettings, SubCommand, ArgMatches, Arg};
+use alloc::string::String;
+
+use super::super::super::qlib::common::*;
+use super::super::super::vmspace::shared_mem;
+use super::super::cmd::config::*;
+use super::super::container::container::*;
+use super::command::*;
+
+#[derive(Debug)]
+pub enum ShmAction {
+    Create(u64),
+    Import,
+    Delete,
+}
+
+#[derive(Debug)]
+pub struct ShmCmd {
+    pub action: ShmAction,
+    pub id: String,
+    pub name: String,
+    pub token: String,
+}
+
+impl ShmCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        match cmd_matches.subcommand() {
+            ("create", Some(m)) => {
+                let size = m.value_of("size").unwrap().parse::<u64>()
+                    .map_err(|e| Error::Common(format!("shm: invalid size {:?}", e)))?;
+                return Ok(Self {
+                    action: ShmAction::Create(size),
+                    id: m.value_of("id").unwrap().to_string(),
+                    name: m.value_of("name").unwrap().to_string(),
+                    token: String::new(),
+                })
+            }
+            ("import", Some(m)) => {
+                return Ok(Self {
+                    action: ShmAction::Import,
+                    id: m.value_of("id").unwrap().to_string(),
+                    name: m.value_of("name").unwrap().to_string(),
+                    token: m.value_of("token").unwrap().to_string(),
+                })
+            }
+            ("delete", Some(m)) => {
+                return Ok(Self {
+                    action: ShmAction::Delete,
+                    id: String::new(),
+                    name: String::new(),
+                    token: m.value_of("token").unwrap().to_string(),
+                })
+            }
+            _ => return Err(Error::Common(String::from("shm: create, import or delete is required"))),
+        }
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        let name = Arg::with_name("name")
+            .required(true)
+            .takes_value(true)
+            .help("the segment appears as /dev/quark-shm/<name> in the sandbox");
+        let token = Arg::with_name("token")
+            .required(true)
+            .takes_value(true)
+            .help("token printed by shm create");
+
+        return SubCommand::with_name("shm")
+            .setting(AppSettings::ColoredHelp)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("create")
+                    .arg(&common.id_arg)
+                    .arg(&name)
+                    .arg(
+                        Arg::with_name("size")
+                            .help("segment size in bytes")
+                            .required(true)
+                            .takes_value(true)
+                            .long("size")
+                            .short("s"),
+                    )
+                    .about("Create a shared memory segment in the sandbox and print its token"),
+            )
+            .subcommand(
+                SubCommand::with_name("import")
+                    .arg(&common.id_arg)
+                    .arg(&name)
+                    .arg(&token)
+                    .about("Attach the shared memory segment of the token to the sandbox"),
+            )
+            .subcommand(
+                SubCommand::with_name("delete")
+                    .arg(&token)
+                    .about("Delete the shared memory segment, the existing mappings stay valid"),
+            )
+            .about("Share memory segments between the sandboxes on the host");
+    }
+
+    pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
+        info!("Container:: shm ....");
+        match &self.action {
+            ShmAction::Create(size) => {
+                if !shared_mem::ValidName(&self.name) {
+                    return Err(Error::Common(format!("shm: invalid name {}", self.name)))
+                }
+
+                let container = Container::Load(&gCfg.RootDir, &self.id)?;
+                let token = shared_mem::CreateSegment(*size)?;
+                // nobody else has the token of the segment which fails to attach
+                if let Err(e) = container.ShmImport(&self.name, &token) {
+                    shared_mem::DeleteSegment(&token).ok();
+                    return Err(e)
+                }
+
+                println!("{}", token);
+            }
+            ShmAction::Import => {
+                let container = Container::Load(&gCfg.RootDir, &self.id)?;
+                container.ShmImport(&self.name, &self.token)?;
+            }
+            ShmAction::Delete => {
+                shared_mem::DeleteSegment(&self.token)?;
+            }
+        }
+
+        return Ok(())
+    }
+}
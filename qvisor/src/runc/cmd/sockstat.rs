@@ -0,0 +1,63 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{App, AppSettings, Arg, SubCommand, ArgMatches};
+use alloc::string::String;
+
+use super::super::super::qlib::common::*;
+use super::super::cmd::config::*;
+use super::super::container::container::*;
+use super::command::*;
+
+#[derive(Debug)]
+pub struct SockStatCmd {
+    pub id: String,
+    pub rdma: bool,
+}
+
+impl SockStatCmd {
+    pub fn Init(cmd_matches: &ArgMatches) -> Result<Self> {
+        return Ok(Self {
+            id: cmd_matches.value_of("id").unwrap().to_string(),
+            rdma: cmd_matches.is_present("rdma"),
+        })
+    }
+
+    pub fn SubCommand<'a, 'b>(common: &CommonArgs<'a, 'b>) -> App<'a, 'b> {
+        return SubCommand::with_name("sockstat")
+            .setting(AppSettings::ColoredHelp)
+            .arg(&common.id_arg)
+            .arg(
+                Arg::with_name("rdma")
+                    .long("rdma")
+                    .help("output the counters of the RDMA sockets"),
+            )
+            .about("Output the socket statistics of a sandbox in json");
+    }
+
+    pub fn Run(&self, gCfg: &GlobalConfig) -> Result<()> {
+        info!("Container:: sockstat ....");
+        let container = Container::Load(&gCfg.RootDir, &self.id)?;
+        let data = if self.rdma {
+            serde_json::to_string_pretty(&container.RDMAStats()?)
+        } else {
+            serde_json::to_string_pretty(&container.SockStats()?)
+        };
+
+        let data = data
+            .map_err(|e| Error::Common(format!("sockstat: serialize fail with error {:?}", e)))?;
+        println!("{}", data);
+        return Ok(())
+    }
+}
@@ -51,7 +51,7 @@ use super::super::shim::container_io::*;
 
 // metadataFilename is the name of the metadata file relative to the
 // container root directory that holds sandbox metadata.
-const METADATA_FILENAME : &str = "meta.json";
+pub const METADATA_FILENAME : &str = "meta.json";
 
 // metadataLockFilename is the name of a lock file in the container
 // root directory that is used to prevent concurrent modifications to
@@ -781,6 +781,41 @@ impl Container {
         return self.Sandbox.as_ref().unwrap().Processes(&self.ID);
     }
 
+    pub fn Debug(&self) -> Result<String> {
+        self.RequireStatus("debug", &[Status::Running, Status::Paused])?;
+        return self.Sandbox.as_ref().unwrap().Debug();
+    }
+
+    pub fn ProfileStart(&self, hz: u64) -> Result<()> {
+        self.RequireStatus("profile", &[Status::Running])?;
+        return self.Sandbox.as_ref().unwrap().ProfileStart(hz);
+    }
+
+    pub fn ProfileStop(&self) -> Result<ProfileResult> {
+        self.RequireStatus("profile", &[Status::Running])?;
+        return self.Sandbox.as_ref().unwrap().ProfileStop();
+    }
+
+    pub fn SockStats(&self) -> Result<SockStatsInfo> {
+        self.RequireStatus("get socket stats of", &[Status::Running, Status::Paused])?;
+        return self.Sandbox.as_ref().unwrap().SockStats();
+    }
+
+    pub fn RDMAStats(&self) -> Result<Vec<RDMAConnStatsInfo>> {
+        self.RequireStatus("get RDMA stats of", &[Status::Running, Status::Paused])?;
+        return self.Sandbox.as_ref().unwrap().RDMAStats();
+    }
+
+    pub fn ShmImport(&self, name: &str, token: &str) -> Result<()> {
+        self.RequireStatus("import shm in", &[Status::Running])?;
+        return self.Sandbox.as_ref().unwrap().ShmImport(name, token);
+    }
+
+    pub fn InjectFile(&self, path: &str, fd: i32, mode: u16, uid: u32, gid: u32) -> Result<()> {
+        self.RequireStatus("inject file in", &[Status::Running])?;
+        return self.Sandbox.as_ref().unwrap().InjectFile(path, fd, mode, uid, gid);
+    }
+
     // Start starts running the containerized process inside the sandbox.
     pub fn StartRootContainer(&mut self) -> Result<()> {
         info!("Start container {}", &self.ID);
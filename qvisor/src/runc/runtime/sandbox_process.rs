@@ -46,6 +46,9 @@ use super::super::cmd::config::*;
 use super::super::specutils::specutils::*;
 use super::super::super::ucall::usocket::*;
 use super::super::super::ucall::ucall::*;
+use super::super::super::qlib::kernel::fs::etc::*;
+use super::super::super::vmspace::etc_files::*;
+use super::super::super::QUARK_CONFIG;
 use super::util::*;
 use super::loader::*;
 use super::vm::*;
@@ -297,6 +300,18 @@ impl SandboxProcess {
                 //mount_cgroups(m, rootfs, flags, &data, &linux.mount_label, cpath)?;
                 // won't mount cgroup
                 continue;
+            } else if m.typ == "bind" && QUARK_CONFIG.lock().EtcFilesInGuest && EtcFileIdx(&m.destination).is_some() {
+                // the file is generated in the guest, fall back to the bind mount when the source isn't usable
+                match ETC_FILES_MGR.lock().AddSource(&m.destination, &m.source) {
+                    Ok(()) => {
+                        TouchMountPoint(&self.Rootfs, &m.destination);
+                        continue;
+                    }
+                    Err(e) => {
+                        info!("etc file {} uses bind mount, source {} fail with {:?}", &m.destination, &m.source, e);
+                        MountFrom(m, &self.Rootfs, flags, &data, &linux.mount_label)?;
+                    }
+                }
             } else if m.destination == "/dev" {
                 // dev can't be read only yet because we have to mount devices
                 MountFrom(
@@ -529,6 +544,18 @@ impl SandboxProcess {
     }
 }
 
+// TouchMountPoint creates the file in the rootfs which the guest mounts over
+fn TouchMountPoint(rootfs: &str, destination: &str) {
+    let dest = format!{"{}{}", rootfs, destination};
+    if let Err(e) = create_dir_all(Path::new(&dest).parent().unwrap()) {
+        debug!("ignoring create dir fail of {:?}: {}", &dest, e)
+    }
+
+    if let Err(e) = OpenOptions::new().create(true).write(true).open(&dest) {
+        debug!("ignoring touch fail of {:?}: {}", &dest, e)
+    }
+}
+
 fn MountFrom(m: &Mount, rootfs: &str, flags: MsFlags, data: &str, label: &str) -> Result<()> {
     let d;
     if !label.is_empty() && m.typ != "proc" && m.typ != "sysfs" {
@@ -29,6 +29,7 @@ use super::super::super::qlib::linux_def::*;
 use super::super::super::qlib::ShareSpace;
 use super::super::super::SHARE_SPACE_STRUCT;
 use super::super::super::SHARE_SPACE;
+use super::super::super::NET_POLICY;
 use super::super::super::qlib::addr;
 use super::super::super::qlib::perf_tunning::*;
 use super::super::super::qlib::task_mgr::*;
@@ -46,9 +47,15 @@ use super::super::super::qlib::pagetable::AlignedAllocator;
 use super::super::super::print::LOG;
 use super::super::super::syncmgr;
 use super::super::super::runc::runtime::loader::*;
+use super::super::super::runc::specutils::specutils;
 use super::super::super::kvm_vcpu::*;
 use super::super::super::elf_loader::*;
 use super::super::super::vmspace::*;
+use super::super::super::vmspace::kernel_io_thread::*;
+use super::super::super::vmspace::conn_pool::*;
+use super::super::super::vmspace::journal::*;
+use super::super::super::vmspace::cpufreq::*;
+use super::super::super::vmspace::io_backend::*;
 use super::super::super::{VMS, ROOT_CONTAINER_ID, PMA_KEEPER, QUARK_CONFIG, URING_MGR, KERNEL_IO_THREAD, THREAD_ID, ThreadId};
 
 lazy_static! {
@@ -151,7 +158,26 @@ impl VirtualMachine {
 
         let sharespace = SHARE_SPACE.Ptr();
         let logfd = super::super::super::print::LOG.lock().Logfd();
-        URING_MGR.lock().Init(sharespace.config.read().DedicateUring);
+        URING_MGR.lock().Init(sharespace.config.read().DedicateUring, sharespace.config.read().IOThreadCount);
+
+        // the options built on the uring opcodes missing in the host kernel are turned off
+        let ops = URING_MGR.lock().ProbeOps();
+        sharespace.ioUring.SetSupportedOps(ops);
+        {
+            let mut config = QUARK_CONFIG.lock();
+            match config.ApplyUringOps(ops) {
+                Ok(notes) => {
+                    for note in notes {
+                        info!("config: {}", note);
+                    }
+                }
+                Err(e) => panic!("the host uring doesn't support the config: {:?}", e),
+            }
+            *sharespace.config.write() = *config;
+        }
+        // the vcpus check the lag on every syscall return without the config lock
+        sharespace.ioUring.SetReapLag(sharespace.config.read().IOReapLagNs());
+        sharespace.CacheConfig();
         URING_MGR.lock().Addfd(logfd).unwrap();
 
         for i in 0..cpuCount {
@@ -186,27 +212,65 @@ impl VirtualMachine {
         super::super::super::print::SetSyncPrint(syncPrint);
     }
 
+    // ResolveShadowStack turns off the ShadowStack config when kvm doesn't pass the CET shadow
+    // stack, CPUID.(EAX=07H, ECX=0):ECX[7], to the guest. The host kernel needs the kvm CET support.
+    pub fn ResolveShadowStack(kvmCpuid: &[kvm_cpuid_entry2]) {
+        let mut config = QUARK_CONFIG.lock();
+        if !config.ShadowStack {
+            return
+        }
+
+        let supported = kvmCpuid.iter().any(|entry| {
+            entry.function == 7 && entry.index == 0 && entry.ecx & (1 << 7) != 0
+        });
+
+        if !supported {
+            info!("shadow stack: kvm doesn't support CET shadow stack, it is disabled");
+            config.ShadowStack = false;
+        }
+    }
+
     pub fn Init(args: Args /*args: &Args, kvmfd: i32*/) -> Result<Self> {
         PerfGoto(PerfType::Other);
+        let initStart = std::time::Instant::now();
 
         *ROOT_CONTAINER_ID.lock() = args.ID.clone();
         if QUARK_CONFIG.lock().PerSandboxLog {
             LOG.lock().Reset(&args.ID[0..12]);
         }
 
+        ResolvePowerSave();
+        SelectIoBackend();
+
+        if let Some((start, end)) = specutils::EphemeralPortRange(&args.Spec)? {
+            let mut config = QUARK_CONFIG.lock();
+            config.EphemeralPortStart = start;
+            config.EphemeralPortEnd = end;
+        }
+
+        if let Some(policy) = specutils::NetPolicy(&args.Spec)? {
+            *NET_POLICY.lock() = policy;
+        }
+
         let kvmfd = args.KvmFd;
 
         let cnt = QUARK_CONFIG.lock().DedicateUring;
 
-        /*if QUARK_CONFIG.lock().EnableRDMA {
-            // use default rdma device
-            let rdmaDeviceName = "";
-            let lbPort = QUARK_CONFIG.lock().RDMAPort;
-            super::super::super::vmspace::HostFileMap::rdma::RDMA.Init(rdmaDeviceName, lbPort);
-        }*/
+        let kvm = unsafe { Kvm::from_raw_fd(kvmfd) };
 
         let reserveCpuCount = QUARK_CONFIG.lock().ReserveCpuCount;
-        let cpuCount = VMSpace::VCPUCount() - cnt - reserveCpuCount;
+        let mut cpuCount = VMSpace::VCPUCount() - cnt - reserveCpuCount;
+        // large host could have more cpus than the vcpus kvm supports for one vm
+        if cpuCount > kvm.get_max_vcpus() {
+            info!("vcpu count {} is limited to kvm max vcpus {}", cpuCount, kvm.get_max_vcpus());
+            cpuCount = kvm.get_max_vcpus();
+        }
+
+        // the vcpu 0 serves the io, only the vcpu 1 runs the tasks in the deterministic mode
+        if QUARK_CONFIG.lock().Deterministic && cpuCount > 2 {
+            info!("vcpu count {} is limited to 2 in the deterministic mode", cpuCount);
+            cpuCount = 2;
+        }
         VMS.lock().vcpuCount = cpuCount; //VMSpace::VCPUCount();
         VMS.lock().RandomVcpuMapping();
         let kernelMemRegionSize = QUARK_CONFIG.lock().KernelMemSize;
@@ -215,9 +279,8 @@ impl VirtualMachine {
         let umask = Self::Umask();
         info!("reset umask from {:o} to {}, kernelMemRegionSize is {:x}", umask, 0, kernelMemRegionSize);
 
-        let kvm = unsafe { Kvm::from_raw_fd(kvmfd) };
-
         let kvm_cpuid = kvm.get_supported_cpuid(kvm_bindings::KVM_MAX_CPUID_ENTRIES).unwrap();
+        Self::ResolveShadowStack(kvm_cpuid.as_slice());
 
         let vm_fd = kvm.create_vm().map_err(|e| Error::IOError(format!("io::error is {:?}", e)))?;
 
@@ -300,6 +363,11 @@ impl VirtualMachine {
             elf: elf,
         };
 
+        SHARE_SPACE.vmInitTime.store(initStart.elapsed().as_micros() as i64, Ordering::SeqCst);
+
+        JOURNAL.Open(&ROOT_CONTAINER_ID.lock());
+        JOURNAL.Record(JournalKind::Start, &format!("cpus={} init={}us", cpuCount, initStart.elapsed().as_micros()));
+
         PerfGofrom(PerfType::Other);
         Ok(vm)
     }
@@ -335,9 +403,41 @@ impl VirtualMachine {
             }).unwrap());
         }
 
+        // the io threads exit with the vm, they are not joined
+        let vcpuCount = self.vcpus.len();
+        for (idx, eventfd) in URING_MGR.lock().IOThreadEventfds() {
+            thread::Builder::new().name(format!("io{}", idx)).spawn(move || {
+                THREAD_ID.with ( |f| {
+                    *f.borrow_mut() = (vcpuCount + idx) as i32;
+                });
+                info!("io thread#{} start", idx);
+                KIOThread::RunUringThread(idx, eventfd);
+                info!("io thread#{} finish", idx);
+            }).unwrap();
+        }
+
+        // the clock watcher sees the vm exit within a period of its timer
+        let clock = thread::Builder::new().name("clock".to_string()).spawn(move || {
+            KIOThread::RunClockWatcher(&SHARE_SPACE);
+        }).unwrap();
+
+        if ConnPool::Enabled() {
+            thread::Builder::new().name("connpool".to_string()).spawn(move || {
+                CONN_POOL.Run();
+            }).unwrap();
+        }
+
+        if JOURNAL.Enabled() {
+            thread::Builder::new().name("journal".to_string()).spawn(move || {
+                JOURNAL.Run();
+            }).unwrap();
+        }
+
         for t in threads {
             t.join().expect("the working threads has panicked");
         }
+
+        clock.join().expect("the clock thread has panicked");
         Ok(GetExitStatus())
     }
 
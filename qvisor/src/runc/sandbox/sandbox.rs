@@ -289,6 +289,121 @@ impl Sandbox {
         }
     }
 
+    pub fn Debug(&self) -> Result<String> {
+        info!("Getting debug report of sandbox {}", self.ID);
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::Debug;
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::DebugResp(report) => Ok(report),
+            resp => {
+                panic!("Debug get unknow resp {:?}", resp);
+            }
+        }
+    }
+
+    pub fn ProfileStart(&self, hz: u64) -> Result<()> {
+        info!("Starting profiler of sandbox {} at {} hz", self.ID, hz);
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::ProfileStart(hz);
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::ProfileStartResp => Ok(()),
+            resp => {
+                panic!("ProfileStart get unknow resp {:?}", resp);
+            }
+        }
+    }
+
+    pub fn ProfileStop(&self) -> Result<ProfileResult> {
+        info!("Stopping profiler of sandbox {}", self.ID);
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::ProfileStop;
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::ProfileStopResp(result) => Ok(result),
+            resp => {
+                panic!("ProfileStop get unknow resp {:?}", resp);
+            }
+        }
+    }
+
+    pub fn SockStats(&self) -> Result<SockStatsInfo> {
+        info!("Getting socket stats of sandbox {}", self.ID);
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::SockStats;
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::SockStatsResp(stats) => Ok(stats),
+            resp => {
+                panic!("SockStats get unknow resp {:?}", resp);
+            }
+        }
+    }
+
+    pub fn RDMAStats(&self) -> Result<Vec<RDMAConnStatsInfo>> {
+        info!("Getting RDMA stats of sandbox {}", self.ID);
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::RDMAStats;
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::RDMAStatsResp(stats) => Ok(stats),
+            resp => {
+                panic!("RDMAStats get unknow resp {:?}", resp);
+            }
+        }
+    }
+
+    pub fn ShmImport(&self, name: &str, token: &str) -> Result<()> {
+        info!("Importing shm segment {} as {} in sandbox {}", token, name, self.ID);
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::ShmImport(ShmArgs {
+            name: name.to_string(),
+            token: token.to_string(),
+        });
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::ShmAttachResp(_) => Ok(()),
+            resp => {
+                panic!("ShmImport get unknow resp {:?}", resp);
+            }
+        }
+    }
+
+    // InjectFile replaces the file path in the sandbox with the content of the host file fd
+    pub fn InjectFile(&self, path: &str, fd: i32, mode: u16, uid: u32, gid: u32) -> Result<()> {
+        info!("Injecting file {} in sandbox {}", path, self.ID);
+        let client = self.SandboxConnect()?;
+
+        let req = UCallReq::InjectFile(InjectFileArgs {
+            path: path.to_string(),
+            mode: mode,
+            uid: uid,
+            gid: gid,
+            fds: vec![fd],
+        });
+
+        let resp = client.Call(&req)?;
+        match resp {
+            UCallResp::InjectFileResp => Ok(()),
+            resp => {
+                panic!("InjectFile get unknow resp {:?}", resp);
+            }
+        }
+    }
+
     pub fn StartRootContainer(&self) -> Result<()> {
         let client = self.SandboxConnect()?;
 
@@ -9,6 +9,8 @@ use super::super::super::qlib::common::*;
 use super::super::super::qlib::linux_def::*;
 use super::super::super::qlib::path::*;
 use super::super::super::qlib::auth::cap_set::*;
+use super::super::super::qlib::kernel::socket::hostinet;
+use super::super::super::qlib::kernel::socket::hostinet::policy::{NET_POLICY_ANNOTATION, NET_POLICY_MAX};
 use super::super::oci::*;
 use super::fs::*;
 
@@ -212,6 +214,52 @@ pub fn SandboxID(spec: &Spec) -> Option<String> {
     }
 }
 
+// JoinSandbox sets the annotations to create the container in the existing sandbox, e.g. the
+// warm sandbox of the pool
+pub fn JoinSandbox(spec: &mut Spec, sandboxId: &str) {
+    spec.annotations.insert(CONTAINERD_CONTAINER_TYPE_ANNOTATION.to_string(), CONTAINERD_CONTAINER_TYPE_CONTAINER.to_string());
+    spec.annotations.insert(CONTAINERD_SANDBOX_IDANNOTATION.to_string(), sandboxId.to_string());
+}
+
+// EphemeralPortRangeAnnotation is the OCI annotation to set the sandbox ephemeral
+// port range of the hostinet sockets, in the format of "start-end", e.g. "40000-40999".
+const EPHEMERAL_PORT_RANGE_ANNOTATION :&str = "dev.quark.net.ephemeral-port-range";
+
+// EphemeralPortRange returns the sandbox ephemeral port range set in the spec.
+pub fn EphemeralPortRange(spec: &Spec) -> Result<Option<(u16, u16)>> {
+    let range = match spec.annotations.get(EPHEMERAL_PORT_RANGE_ANNOTATION) {
+        None => return Ok(None),
+        Some(r) => r,
+    };
+
+    let err = || Error::Common(format!("invalid annotation {}: {:?}", EPHEMERAL_PORT_RANGE_ANNOTATION, range));
+    let mut iter = range.trim().splitn(2, '-');
+    let start = iter.next().ok_or_else(err)?.trim().parse::<u16>().map_err(|_| err())?;
+    let end = iter.next().ok_or_else(err)?.trim().parse::<u16>().map_err(|_| err())?;
+    if start == 0 || end < start {
+        return Err(err())
+    }
+
+    return Ok(Some((start, end)))
+}
+
+// NetPolicy returns the sandbox net policy set in the spec, the rules are checked here so that
+// the sandbox fails to start instead of running without the policy.
+pub fn NetPolicy(spec: &Spec) -> Result<Option<String>> {
+    let policy = match spec.annotations.get(NET_POLICY_ANNOTATION) {
+        None => return Ok(None),
+        Some(p) => p,
+    };
+
+    if policy.len() > NET_POLICY_MAX {
+        return Err(Error::Common(format!("invalid annotation {}: larger than {}", NET_POLICY_ANNOTATION, NET_POLICY_MAX)))
+    }
+
+    hostinet::policy::NetPolicy::Parse(policy)
+        .map_err(|e| Error::Common(format!("invalid annotation {}: {:?}", NET_POLICY_ANNOTATION, e)))?;
+    return Ok(Some(policy.clone()))
+}
+
 pub fn MkdirAll(dst: &str) -> Result<()> {
     return fs::create_dir_all(dst).map_err(|e| Error::IOError(format!("Mkdir({:?}) failed: {:?}", dst, e)));
 }
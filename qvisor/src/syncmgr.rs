@@ -19,12 +19,13 @@ use libc::*;
 use super::vmspace::syscall::*;
 use super::qlib::SysCallID;
 use super::qlib::MAX_VCPU_COUNT;
+use super::qlib::vcpu_mgr::{VcpuMask, VCPU_MASK_WORDS};
 use super::*;
 
 pub struct SyncMgr {
     pub sharespaceReady: AtomicU32,
     pub vcpuWait: [u32; MAX_VCPU_COUNT],
-    pub waitMask: u32,
+    pub waitMask: VcpuMask,
     // if there is vcpu thread wait at host, vcpuWait = 1
 }
 
@@ -33,7 +34,7 @@ impl SyncMgr {
         return Self {
             sharespaceReady: AtomicU32::new(0),
             vcpuWait: [0; MAX_VCPU_COUNT],
-            waitMask: 0,
+            waitMask: VcpuMask { words: [0; VCPU_MASK_WORDS] },
         }
     }
 
@@ -65,7 +66,7 @@ impl SyncMgr {
             let mut syncMgr = super::SYNC_MGR.lock();
             let addr = &syncMgr.vcpuWait[vcpuId] as *const _ as u64;
             syncMgr.vcpuWait[vcpuId] = 1;
-            syncMgr.waitMask |= 1<<vcpuId;
+            syncMgr.waitMask.Set(vcpuId);
             addr
         };
 
@@ -74,7 +75,7 @@ impl SyncMgr {
 
         let mut syncMgr = super::SYNC_MGR.lock();
         syncMgr.vcpuWait[vcpuId] = 0;
-        syncMgr.waitMask &= !(1 << vcpuId);
+        syncMgr.waitMask.Clear(vcpuId);
         return ret
     }
 
@@ -86,20 +87,20 @@ impl SyncMgr {
         let vcpuId = if vcpuId > 0 {
             vcpuId as usize
         } else {
-            if syncMgr.waitMask == 0 { // async host message trigger
-                return 0;
-            }
-
             // async host message trigger, find first waiting vcpu
-            let lastone = (syncMgr.waitMask & !1).trailing_zeros();
-            lastone as usize
+            let mut mask = syncMgr.waitMask;
+            mask.Clear(0);
+            match mask.First() {
+                None => return 0,
+                Some(vcpuId) => vcpuId,
+            }
         };
 
-        if vcpuId >= 32 {
+        if vcpuId >= MAX_VCPU_COUNT {
             return 0
         }
 
-        if syncMgr.waitMask & (1 << vcpuId) != 0 { // if target vcpu is waiting
+        if syncMgr.waitMask.IsSet(vcpuId) { // if target vcpu is waiting
             //there is waiting thread, need to call futex wake
             let addr = &syncMgr.vcpuWait[vcpuId] as * const _ as u64;
             syncMgr.vcpuWait[vcpuId] = 0;
@@ -23,6 +23,8 @@ pub fn ControlSocketAddr(id: &str) -> String {
 }
 
 pub const UCALL_BUF_LEN : usize = 4096;
+// the max resp size, the debug report of a large sandbox is over UCALL_BUF_LEN
+pub const UCALL_RESP_MAX_LEN : usize = 16 << 20;
 type Cid = String;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -39,6 +41,13 @@ pub enum UCallReq {
     CreateSubContainer(CreateArgs),
     StartSubContainer(StartArgs),
     WaitAll,
+    Debug,
+    ProfileStart(u64),
+    ProfileStop,
+    ShmImport(ShmArgs),
+    InjectFile(InjectFileArgs),
+    SockStats,
+    RDMAStats,
 }
 
 impl FileDescriptors for UCallReq {
@@ -61,6 +70,7 @@ impl FileDescriptors for UCallReq {
                     return None
                 }
             }
+            UCallReq::InjectFile(args) => return Some(&args.fds),
             _ => return None,
         }
     }
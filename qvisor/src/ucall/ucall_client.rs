@@ -42,11 +42,14 @@ impl UCallClient {
         self.sock.WriteAll(&reqArr)?;
 
         let (len, _fds) = self.sock.ReadLen()?;
-        let mut buf : [u8; UCALL_BUF_LEN] = [0; UCALL_BUF_LEN];
+        if len > UCALL_RESP_MAX_LEN {
+            return Err(Error::Common(format!("UCallClient resp len {} is over {}", len, UCALL_RESP_MAX_LEN)))
+        }
 
-        assert!(len < UCALL_BUF_LEN, "UCallClient::Call resp is too long");
-        self.sock.ReadAll(&mut buf[0..len])?;
-        let resp : UCallResp = serde_json::from_slice(&buf[0..len]).map_err(|e|Error::Common(format!("UCallClient deser error is {:?}", e)))?;
+        let mut buf = vec![0; len];
+
+        self.sock.ReadAll(&mut buf[..])?;
+        let resp : UCallResp = serde_json::from_slice(&buf[..]).map_err(|e|Error::Common(format!("UCallClient deser error is {:?}", e)))?;
         match resp {
             UCallResp::UCallRespErr(s) => return Err(Error::Common(s)),
             _ => (),
@@ -70,7 +73,10 @@ impl UCallClient {
         let (len, _fds) = self.sock.ReadLen()?;
         let mut buf : [u8; UCALL_BUF_LEN] = [0; UCALL_BUF_LEN];
 
-        assert!(len < UCALL_BUF_LEN, "UCallClient::Call resp is too long");
+        if len >= UCALL_BUF_LEN {
+            return Err(Error::Common(format!("UCallClient stream resp len {} is too long", len)))
+        }
+
         self.sock.ReadAll(&mut buf[0..len])?;
         let resp : UCallResp = serde_json::from_slice(&buf[0..len]).map_err(|e|Error::Common(format!("UCallClient deser error is {:?}", e)))?;
         match resp {
@@ -79,4 +85,47 @@ impl UCallClient {
         }
         return Ok(resp);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (UCallClient, USocket) {
+        let mut fds = [0i32; 2];
+        let ret = unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) };
+        assert_eq!(ret, 0);
+        return (UCallClient { sock: USocket { socket: fds[0] } }, USocket { socket: fds[1] })
+    }
+
+    fn close(c: UCallClient, s: USocket) {
+        unsafe {
+            libc::close(c.sock.socket);
+            libc::close(s.socket);
+        }
+    }
+
+    #[test]
+    fn test_call_resp_too_long() {
+        let (c, s) = pair();
+        s.WriteLen(UCALL_RESP_MAX_LEN + 1, &[]).unwrap();
+        assert!(c.Call(&UCallReq::Pause).is_err());
+        close(c, s);
+    }
+
+    #[test]
+    fn test_stream_resp_too_long() {
+        let (c, s) = pair();
+        s.WriteLen(UCALL_BUF_LEN, &[]).unwrap();
+        assert!(c.StreamGetRet().is_err());
+        close(c, s);
+    }
+
+    #[test]
+    fn test_call_resp() {
+        let (c, s) = pair();
+        s.SendResp(&UCallResp::PauseResp).unwrap();
+        assert!(c.Call(&UCallReq::Pause).is_ok());
+        close(c, s);
+    }
+}
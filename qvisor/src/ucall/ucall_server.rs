@@ -16,11 +16,12 @@ use super::super::qlib::common::*;
 use super::super::qlib::linux_def::*;
 use super::super::qlib::control_msg::*;
 use super::super::qlib::loader;
-use super::super::{IO_MGR};
+use super::super::{IO_MGR, URING_MGR};
 use super::ucall::*;
 use super::usocket::*;
 use super::super::runc::container::container::*;
 use super::super::vmspace::*;
+use super::super::vmspace::shared_mem;
 
 pub fn ReadControlMsg(fd: i32) -> Result<ControlMsg> {
     let usock = USocket {
@@ -37,8 +38,15 @@ pub fn ReadControlMsg(fd: i32) -> Result<ControlMsg> {
         }
     };
 
-    let msg = ProcessReqHandler(&mut req, &fds);
-    return msg
+    match ProcessReqHandler(&mut req, &fds) {
+        Err(e) => {
+            let err = UCallResp::UCallRespErr(format!("{:?}", e));
+            usock.SendResp(&err)?;
+            usock.Drop();
+            return Err(e)
+        }
+        Ok(msg) => return Ok(msg),
+    }
 }
 
 pub fn RootContainerStartHandler(start: &RootContainerStart) -> Result<ControlMsg> {
@@ -92,6 +100,62 @@ pub fn PsHandler(cid: &str) -> Result<ControlMsg> {
     return Ok(msg)
 }
 
+pub fn DebugHandler() -> Result<ControlMsg> {
+    let msg = ControlMsg::New(Payload::Debug);
+    return Ok(msg)
+}
+
+pub fn ProfileStartHandler(hz: u64) -> Result<ControlMsg> {
+    let msg = ControlMsg::New(Payload::ProfileStart(hz));
+    return Ok(msg)
+}
+
+pub fn ProfileStopHandler() -> Result<ControlMsg> {
+    let msg = ControlMsg::New(Payload::ProfileStop);
+    return Ok(msg)
+}
+
+pub fn SockStatsHandler() -> Result<ControlMsg> {
+    let msg = ControlMsg::New(Payload::SockStats);
+    return Ok(msg)
+}
+
+pub fn RDMAStatsHandler() -> Result<ControlMsg> {
+    let msg = ControlMsg::New(Payload::RDMAStats);
+    return Ok(msg)
+}
+
+fn ShmAttachMsg(name: &str, token: String, osfd: i32) -> ControlMsg {
+    let hostfd = IO_MGR.AddFile(osfd);
+    URING_MGR.lock().Addfd(osfd).unwrap();
+
+    return ControlMsg::New(Payload::ShmAttach(ShmAttach {
+        name: name.to_string(),
+        token: token,
+        hostfd: hostfd,
+    }))
+}
+
+pub fn ShmImportHandler(args: &ShmArgs) -> Result<ControlMsg> {
+    if !shared_mem::ValidName(&args.name) {
+        return Err(Error::Common(format!("shm: invalid name {}", args.name)))
+    }
+
+    let osfd = shared_mem::OpenSegment(&args.token)?;
+    return Ok(ShmAttachMsg(&args.name, args.token.clone(), osfd))
+}
+
+pub fn InjectFileHandler(args: &InjectFileArgs, fds: &[i32]) -> Result<ControlMsg> {
+    if fds.len() != 1 {
+        return Err(Error::Common(format!("inject: expect 1 fd, get {}", fds.len())))
+    }
+
+    let hostfd = IO_MGR.AddFile(fds[0]);
+    let mut args = args.clone();
+    args.fds = vec![hostfd];
+    return Ok(ControlMsg::New(Payload::InjectFile(args)))
+}
+
 pub fn WaitHandler(cid: &str) -> Result<ControlMsg> {
     let msg = ControlMsg::New(Payload::WaitContainer(cid.to_string()));
     return Ok(msg)
@@ -169,6 +233,13 @@ pub fn ProcessReqHandler(req: &mut UCallReq, fds: &[i32]) -> Result<ControlMsg>
         UCallReq::CreateSubContainer(args) => CreateSubContainerHandler(args, fds)?,
         UCallReq::StartSubContainer(args) => StartSubContainerHandler(args, fds)?,
         UCallReq::WaitAll => WaitAll()?,
+        UCallReq::Debug => DebugHandler()?,
+        UCallReq::ProfileStart(hz) => ProfileStartHandler(*hz)?,
+        UCallReq::ProfileStop => ProfileStopHandler()?,
+        UCallReq::ShmImport(args) => ShmImportHandler(args)?,
+        UCallReq::InjectFile(args) => InjectFileHandler(args, fds)?,
+        UCallReq::SockStats => SockStatsHandler()?,
+        UCallReq::RDMAStats => RDMAStatsHandler()?,
     };
 
     return Ok(msg)
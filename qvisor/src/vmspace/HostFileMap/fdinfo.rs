@@ -18,7 +18,16 @@ use core::ops::Deref;
 use libc::*;
 
 use super::socket_info::*;
-//use super::rdma_socket::*;
+#[cfg(feature = "rdma")]
+use super::rdma_socket::*;
+#[cfg(feature = "rdma")]
+use super::rdma_udp::*;
+#[cfg(feature = "rdma")]
+use super::super::super::qlib::socket_buf::*;
+#[cfg(feature = "rdma")]
+use super::super::super::qlib::qmsg::qcall::*;
+use super::super::super::qlib::kernel::socket::hostinet::stats::*;
+use super::super::super::SHARE_SPACE;
 use super::super::*;
 use super::super::qlib::common::*;
 use super::super::super::util::*;
@@ -218,6 +227,22 @@ impl FdInfo {
         return SysRet(ret as i64);
     }
 
+    pub fn RecvMMsg(sockfd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let ret = unsafe{
+            recvmmsg(sockfd, msgvec as *mut mmsghdr, vlen as c_uint, flags as c_int, core::ptr::null_mut())
+        };
+
+        return SysRet(ret as i64);
+    }
+
+    pub fn SendMMsg(sockfd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let ret = unsafe{
+            sendmmsg(sockfd, msgvec as *mut mmsghdr, vlen as c_uint, flags as c_int)
+        };
+
+        return SysRet(ret as i64);
+    }
+
     pub fn GetSockName(sockfd: i32, addr: u64, addrlen: u64) -> i64 {
         let ret = unsafe{
             getsockname(sockfd, addr as *mut sockaddr, addrlen as *mut socklen_t)
@@ -304,13 +329,57 @@ impl FdInfo {
         return self.lock().fd;
     }
 
+    // ReplaceFd makes the osfd refer to the file of newfd and closes newfd, so the hostfd mapping
+    // stays. The epoll and uring registrations are of the old file, they are moved to the new one.
+    // The events notified for the old file are not valid for the new one.
+    pub fn ReplaceFd(&self, newfd: i32) -> i64 {
+        let fdInfo = self.lock();
+        let osfd = fdInfo.fd;
+        let mask = {
+            let mut wi = fdInfo.waitInfo.lock();
+            wi.notified = 0;
+            wi.mask
+        };
+        if mask != 0 {
+            FD_NOTIFIER.EpollCtlDel(osfd).ok();
+        }
+
+        let ret = unsafe {
+            dup3(newfd, osfd, O_CLOEXEC)
+        };
+
+        let err = errno::errno().0;
+        unsafe {
+            close(newfd);
+        }
+
+        if ret < 0 {
+            // the old file is still there
+            error!("ReplaceFd: dup3 to osfd {} fail, errno is {}", osfd, err);
+            if mask != 0 {
+                FD_NOTIFIER.EpollCtlAdd(osfd, mask).ok();
+            }
+            return -err as i64
+        }
+
+        URING_MGR.lock().Addfd(osfd).unwrap();
+        if mask != 0 {
+            if let Err(e) = FD_NOTIFIER.EpollCtlAdd(osfd, mask) {
+                error!("ReplaceFd: epoll add osfd {} fail with {:?}", osfd, e);
+            }
+        }
+
+        return 0
+    }
+
     pub fn NewSocket(fd: i32) -> Self {
         return Self(Arc::new(Mutex::new(FdInfoIntern::NewSocket(fd))))
     }
 
-    /*pub fn NewRDMAContext(fd: i32) -> Self {
+    #[cfg(feature = "rdma")]
+    pub fn NewRDMAContext(fd: i32) -> Self {
         return Self(Arc::new(Mutex::new(FdInfoIntern::NewRDMAContext(fd))))
-    }*/
+    }
 
     pub fn IOBufWrite(&self, addr: u64, len: usize, offset: isize) -> i64 {
         let fd = self.lock().fd;
@@ -378,6 +447,16 @@ impl FdInfo {
         return Self::SendMsg(fd, msghdr, flags);
     }
 
+    pub fn IORecvMMsg(&self, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fd = self.lock().fd;
+        return Self::RecvMMsg(fd, msgvec, vlen, flags);
+    }
+
+    pub fn IOSendMMsg(&self, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fd = self.lock().fd;
+        return Self::SendMMsg(fd, msgvec, vlen, flags);
+    }
+
     pub fn IOGetSockName(&self, addr: u64, addrlen: u64) -> i64 {
         let sockfd = self.lock().fd;
         return Self::GetSockName(sockfd, addr, addrlen);
@@ -408,7 +487,17 @@ impl FdInfo {
         return Self::Listen(sockfd, backlog, block);
     }
 
-    /*pub fn RDMAListen(&self, backlog: i32, block: bool, acceptQueue: AcceptQueue) -> i64 {
+    pub fn IOShutdown(&self, how: i32) -> i64 {
+        let sockfd = self.lock().fd;
+        return Self::Shutdown(sockfd, how);
+    }
+
+    ///////////////////////////socket operation//////////////////////////////
+}
+
+#[cfg(feature = "rdma")]
+impl FdInfo {
+    pub fn RDMAListen(&self, backlog: i32, block: bool, acceptQueue: AcceptQueue) -> i64 {
         let sockfd = self.lock().fd;
         let ret = Self::Listen(sockfd, backlog, block);
         if ret < 0 {
@@ -453,6 +542,64 @@ impl FdInfo {
         }
     }
 
+    pub fn ProcessRDMAError(&self, retry: bool) {
+        match self.SockInfo() {
+            SockInfo::RDMADataSocket(sock) => {
+                if retry {
+                    RDMAStats::Inc(&sock.socketBuf.rdmaStats.retries);
+                }
+                sock.RDMAFail(SysErr::ECONNRESET, &self.WaitInfo())
+            }
+            SockInfo::RDMAUdpSocket(sock) => {
+                if retry {
+                    RDMAStats::Inc(&sock.socketBuf.rdmaStats.retries);
+                }
+                sock.RDMAFail(SysErr::ECONNRESET, &self.WaitInfo())
+            }
+            _ => {
+                error!("ProcessRDMAError get unexpected socket {:?}", self.SockInfo())
+            }
+        }
+    }
+
+    pub fn ProcessUDSendFinish(&self, slot: u32) {
+        match self.SockInfo() {
+            SockInfo::RDMAUdpSocket(sock) => {
+                sock.ProcessUDSendFinish(slot, self.WaitInfo())
+            }
+            _ => {
+                error!("ProcessUDSendFinish get unexpected socket {:?}", self.SockInfo())
+            }
+        }
+    }
+
+    pub fn ProcessUDRecv(&self, slot: u32, len: u32) {
+        match self.SockInfo() {
+            SockInfo::RDMAUdpSocket(sock) => {
+                sock.ProcessUDRecv(slot, len, self.WaitInfo())
+            }
+            _ => {
+                error!("ProcessUDRecv get unexpected socket {:?}", self.SockInfo())
+            }
+        }
+    }
+
+    pub fn RDMAUdpSocket(&self, socketBuf: Arc<SocketBuff>) -> i64 {
+        let sockfd = self.Fd();
+        match self.SockInfo() {
+            SockInfo::Socket => {
+                let sock = RDMAUdpSock::New(sockfd, socketBuf);
+                *self.lock().sockInfo.lock() = SockInfo::RDMAUdpSocket(sock);
+                self.lock().AddWait(EVENT_READ | EVENT_WRITE).expect("RDMAUdpSocket EpollCtlAdd fail");
+                return 0;
+            }
+            _ => {
+                error!("RDMAUdpSocket fail with wrong state {:?}", self.SockInfo());
+                return -SysErr::EINVAL as i64;
+            }
+        }
+    }
+
     pub fn RDMANotify(&self, typ: RDMANotifyType) -> i64 {
         match self.SockInfo() {
             SockInfo::RDMAServerSocket(RDMAServerSock) => {
@@ -469,11 +616,11 @@ impl FdInfo {
                         //self.lock().AddWait(EVENT_WRITE).unwrap();
                     }
                     RDMANotifyType::RDMARead => {
-                        sock.RDMARead();
+                        sock.RDMARead(self.WaitInfo());
                         //self.lock().AddWait(EVENT_WRITE).unwrap();
                     }
                     RDMANotifyType::RDMAWrite => {
-                        sock.RDMAWrite();
+                        sock.RDMAWrite(self.WaitInfo());
                         //self.lock().AddWait(EVENT_WRITE).unwrap();
                     }
                     _ => {
@@ -481,6 +628,16 @@ impl FdInfo {
                     }
                 }
             }
+            SockInfo::RDMAUdpSocket(sock) => {
+                match typ {
+                    RDMANotifyType::UDWrite => {
+                        sock.WriteData(self.WaitInfo());
+                    }
+                    _ => {
+                        panic!("RDMANotify wrong state {:?}", typ);
+                    }
+                }
+            }
             _ => {
                 error!("RDMAListen RDMANotify fail with wrong state {:?}", self.SockInfo());
             }
@@ -516,14 +673,7 @@ impl FdInfo {
         if !RDMA_ENABLE {
             msg.Finish(0)
         }
-    }*/
-
-    pub fn IOShutdown(&self, how: i32) -> i64 {
-        let sockfd = self.lock().fd;
-        return Self::Shutdown(sockfd, how);
     }
-
-    ///////////////////////////socket operation//////////////////////////////
 }
 
 #[derive(Debug)]
@@ -558,7 +708,8 @@ impl FdInfoIntern {
         return res;
     }
 
-    /*pub fn NewRDMAContext(fd: i32) -> Self {
+    #[cfg(feature = "rdma")]
+    pub fn NewRDMAContext(fd: i32) -> Self {
         let flags = unsafe {
             fcntl(fd, F_GETFL)
         };
@@ -571,7 +722,7 @@ impl FdInfoIntern {
         };
 
         return res;
-    }*/
+    }
 
     pub fn NewSocket(fd: i32) -> Self {
         //info!("New fd {}, hostfd{}: epollable is {}", fd, hostfd, epollable);
@@ -609,6 +760,20 @@ impl FdInfoIntern {
     }
 
     pub fn Close(&self) -> i32 {
+        // the handshake pool drops the socket in the handshake
+        #[cfg(feature = "rdma")]
+        let rdmaSock = match self.sockInfo.lock().clone() {
+            SockInfo::RDMADataSocket(sock) => Some(sock),
+            _ => None,
+        };
+        #[cfg(feature = "rdma")]
+        let _handshake = rdmaSock.as_ref().and_then(|sock| sock.MarkClosed());
+
+        // the retransmits of the closed tcp socket are kept in the sockstat of the sandbox
+        if self.fd >= 0 && matches!(*self.sockInfo.lock(), SockInfo::Socket) {
+            SHARE_SPACE.sockStats.closedRetrans.fetch_add(self.TcpRetrans(), core::sync::atomic::Ordering::Relaxed);
+        }
+
         let _ioMgr = IO_MGR.fdTbl.lock(); //global lock
         if self.fd >= 0 {
             unsafe {
@@ -622,6 +787,21 @@ impl FdInfoIntern {
         return 0;
     }
 
+    // TcpRetrans returns tcpi_total_retrans of the socket, 0 for the socket other than tcp
+    pub fn TcpRetrans(&self) -> u64 {
+        let mut info = [0u8; TCP_INFO_TOTAL_RETRANS_OFFSET + 4];
+        let mut len = info.len() as socklen_t;
+        let ret = unsafe {
+            getsockopt(self.fd, SOL_TCP, TCP_INFO, &mut info[0] as *mut _ as *mut c_void, &mut len as *mut socklen_t)
+        };
+
+        if ret < 0 {
+            return 0
+        }
+
+        return TcpInfoRetrans(&info, len as usize)
+    }
+
     pub fn GetFlags(&mut self) -> i32 {
         return self.Flags().0
     }
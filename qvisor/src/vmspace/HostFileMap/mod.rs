@@ -14,9 +14,15 @@
 
 pub mod fdinfo;
 pub mod file_range_mgr;
-//pub mod rdma_socket;
+#[cfg(feature = "rdma")]
+pub mod rdma_socket;
+#[cfg(feature = "rdma")]
+pub mod rdma_udp;
+#[cfg(feature = "rdma")]
+pub mod rdma_channel;
 pub mod socket_info;
-//pub mod rdma;
+#[cfg(feature = "rdma")]
+pub mod rdma;
 
 use spin::Mutex;
 use std::collections::BTreeMap;
@@ -98,10 +104,11 @@ impl IOMgr {
         return fd;
     }
 
-    /*pub fn AddRDMAContext(&self, fd: i32) -> i32{
+    #[cfg(feature = "rdma")]
+    pub fn AddRDMAContext(&self, fd: i32) -> i32{
         self.fdTbl.lock().AddRDMAContext(fd).expect("hostfdMap: guest fd alloc fail");
         return fd;
-    }*/
+    }
 
     //ret: true: exist, false: not exist
     pub fn RemoveFd(&self, fd: i32) -> Option<FdInfo> {
@@ -117,6 +124,14 @@ impl IOMgr {
         return None;
     }
 
+    // Sockets returns the normal host sockets, the RDMA ones are not included
+    pub fn Sockets(&self) -> Vec<FdInfo> {
+        return self.fdTbl.lock().map.values()
+            .filter(|info| matches!(info.SockInfo(), socket_info::SockInfo::Socket))
+            .cloned()
+            .collect()
+    }
+
     pub fn GetByHost(&self, fd: i32) -> Option<FdInfo> {
         match self.fdTbl.lock().Get(fd) {
             None => {
@@ -156,7 +171,8 @@ impl IOMgr {
         }
     }
 
-    /*pub fn ProcessRDMAWriteImmFinish(&self, fd: i32) {
+    #[cfg(feature = "rdma")]
+    pub fn ProcessRDMAWriteImmFinish(&self, fd: i32) {
         let fdInfo = self.GetByHost(fd);
         match fdInfo {
             None => {
@@ -168,6 +184,7 @@ impl IOMgr {
         }
     }
 
+    #[cfg(feature = "rdma")]
     pub fn ProcessRDMARecvWriteImm(&self, fd: i32, recvCount: u64, writeCount: u64) {
         let fdInfo = self.GetByHost(fd);
         match fdInfo {
@@ -178,7 +195,48 @@ impl IOMgr {
                 fdInfo.ProcessRDMARecvWriteImm(recvCount, writeCount);
             }
         }
-    }*/
+    }
+
+    // RDMABusy returns whether a qp setup or an RDMA write is in flight
+    #[cfg(feature = "rdma")]
+    pub fn RDMABusy(&self) -> bool {
+        if rdma_socket::RDMA_HANDSHAKE.Busy() {
+            return true
+        }
+
+        let fdInfos: Vec<FdInfo> = self.fdTbl.lock().map.values().cloned().collect();
+        for fdInfo in fdInfos {
+            if let socket_info::SockInfo::RDMADataSocket(sock) = fdInfo.SockInfo() {
+                if sock.Sending() {
+                    return true
+                }
+            }
+        }
+
+        return false
+    }
+
+    // the fd may be closed before the flushed work requests are polled
+    #[cfg(feature = "rdma")]
+    pub fn ProcessRDMAError(&self, fd: i32, retry: bool) {
+        if let Some(fdInfo) = self.GetByHost(fd) {
+            fdInfo.ProcessRDMAError(retry);
+        }
+    }
+
+    #[cfg(feature = "rdma")]
+    pub fn ProcessUDSendFinish(&self, fd: i32, slot: u32) {
+        if let Some(fdInfo) = self.GetByHost(fd) {
+            fdInfo.ProcessUDSendFinish(slot);
+        }
+    }
+
+    #[cfg(feature = "rdma")]
+    pub fn ProcessUDRecv(&self, fd: i32, slot: u32, len: u32) {
+        if let Some(fdInfo) = self.GetByHost(fd) {
+            fdInfo.ProcessUDRecv(slot, len);
+        }
+    }
 }
 
 //guest fdset for one process
@@ -214,12 +272,13 @@ impl FdTbl {
         return Ok(fdInfo)
     }
 
-    /*pub fn AddRDMAContext(&mut self, osfd: i32) -> Result<FdInfo> {
+    #[cfg(feature = "rdma")]
+    pub fn AddRDMAContext(&mut self, osfd: i32) -> Result<FdInfo> {
         let fdInfo = FdInfo::NewRDMAContext(osfd);
 
         self.map.insert(osfd, fdInfo.clone());
         return Ok(fdInfo)
-    }*/
+    }
 
     pub fn Get(&self, fd: i32) -> Option<FdInfo> {
         match self.map.get(&fd) {
@@ -3,17 +3,26 @@ use core::sync::atomic;
 use core::sync::atomic::AtomicU64;
 use rdmaffi;
 use spin::Mutex;
+use spin::RwLock;
 use std::convert::TryInto;
+use std::fs;
+use std::mem;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::ptr;
 
 use super::super::super::qlib::common::*;
+use super::super::super::qlib::config::Config;
 use super::super::super::qlib::linux_def::*;
 use super::super::super::IO_MGR;
+use super::super::super::QUARK_CONFIG;
+use super::rdma_channel::*;
 
 use lazy_static::lazy_static;
 
 lazy_static! {
-    pub static ref RDMA: RDMAContext = RDMAContext::default();
+    pub static ref RDMA_DEVICES: RDMADevices = RDMADevices::default();
     static ref RDMAUID: AtomicU64 = AtomicU64::new(1);
 }
 
@@ -43,6 +52,28 @@ impl Gid {
     fn interface_id(&self) -> u64 {
         u64::from_be_bytes(self.raw[8..].try_into().unwrap())
     }
+
+    pub fn IsZero(&self) -> bool {
+        return self.raw == [0; 16];
+    }
+
+    // FromIp returns the RoCE gid of the ip address, the ipv4 one is mapped to ipv6
+    pub fn FromIp(addr: &IpAddr) -> Self {
+        let v6 = match addr {
+            IpAddr::V4(a) => a.to_ipv6_mapped(),
+            IpAddr::V6(a) => *a,
+        };
+
+        return Self { raw: v6.octets() };
+    }
+
+    pub fn FromRaw(raw: [u8; 16]) -> Self {
+        return Self { raw: raw };
+    }
+
+    pub fn Raw(&self) -> [u8; 16] {
+        return self.raw;
+    }
 }
 
 impl From<rdmaffi::ibv_gid> for Gid {
@@ -88,6 +119,30 @@ impl Default for IBContext {
 }
 
 impl IBContext {
+    // DeviceNames returns the names of the RDMA devices of the host
+    pub fn DeviceNames() -> Vec<String> {
+        let mut deviceNumber = 0;
+        let device_list = unsafe { rdmaffi::ibv_get_device_list(&mut deviceNumber as *mut _) };
+        if device_list.is_null() {
+            panic!("ibv_get_device_list failed: {}", errno::errno().0);
+        }
+
+        let devices = unsafe {
+            use std::slice;
+            slice::from_raw_parts_mut(device_list, deviceNumber as usize)
+        };
+
+        let mut names = Vec::new();
+        for device in devices.iter() {
+            let name = unsafe { rdmaffi::ibv_get_device_name(*device) };
+            let name = unsafe { std::ffi::CStr::from_ptr(name) };
+            names.push(name.to_str().unwrap().to_string());
+        }
+
+        unsafe { rdmaffi::ibv_free_device_list(device_list) };
+        return names;
+    }
+
     pub fn New(deviceName: &str) -> Self {
         // look for device
         let mut deviceNumber = 0;
@@ -140,6 +195,31 @@ impl IBContext {
         return Self(context);
     }
 
+    // OdpCaps returns whether the device pages the memory regions on demand for the sends, the
+    // recvs and the writes of the RC and UD queue pairs, and whether it has the implicit memory
+    // region of the whole address space
+    pub fn OdpCaps(&self) -> (bool, bool) {
+        let mut attr: rdmaffi::ibv_device_attr_ex = unsafe { mem::zeroed() };
+        let rc = unsafe { rdmaffi::ibv_query_device_ex(self.0, ptr::null(), &mut attr) };
+        if rc != 0 {
+            return (false, false);
+        }
+
+        let general = attr.odp_caps.general_caps;
+        let rcCaps = attr.odp_caps.per_transport_caps.rc_odp_caps;
+        let udCaps = attr.odp_caps.per_transport_caps.ud_odp_caps;
+        let send = rdmaffi::ibv_odp_transport_cap_bits::IBV_ODP_SUPPORT_SEND.0;
+        let recv = rdmaffi::ibv_odp_transport_cap_bits::IBV_ODP_SUPPORT_RECV.0;
+        let write = rdmaffi::ibv_odp_transport_cap_bits::IBV_ODP_SUPPORT_WRITE.0;
+
+        let odp = general & rdmaffi::ibv_odp_general_caps::IBV_ODP_SUPPORT.0 as u64 != 0
+            && rcCaps & (send | recv | write) == send | recv | write
+            && udCaps & (send | recv) == send | recv;
+        let implicit =
+            odp && general & rdmaffi::ibv_odp_general_caps::IBV_ODP_SUPPORT_IMPLICIT.0 as u64 != 0;
+        return (odp, implicit);
+    }
+
     pub fn QueryPort(&self, ibPort: u8) -> PortAttr {
         let mut port_attr = rdmaffi::ibv_port_attr {
             state: rdmaffi::ibv_port_state::IBV_PORT_NOP,
@@ -209,15 +289,133 @@ impl IBContext {
         return CompleteQueue(cq);
     }
 
-    pub fn QueryGid(&self, ibPort: u8) -> Gid {
+    pub fn QueryGid(&self, ibPort: u8, index: u8) -> Result<Gid> {
         let mut gid = Gid::default();
-        let ok = unsafe { rdmaffi::ibv_query_gid(self.0, ibPort, 0, gid.as_mut()) };
+        let ok = unsafe { rdmaffi::ibv_query_gid(self.0, ibPort, index as i32, gid.as_mut()) };
 
         if ok != 0 {
-            panic!("ibv_query_gid failed: {}\n", errno::errno().0);
+            return Err(Error::SysError(errno::errno().0));
         }
 
-        return gid;
+        return Ok(gid);
+    }
+
+    // QueryGids returns the valid entries of the gid table of the port
+    pub fn QueryGids(&self, deviceName: &str, ibPort: u8, tableLen: i32) -> Vec<RDMAGid> {
+        let mut gids = Vec::new();
+        for index in 0..core::cmp::min(tableLen, 256) as u8 {
+            match self.QueryGid(ibPort, index) {
+                Ok(gid) if !gid.IsZero() => gids.push(RDMAGid {
+                    index: index,
+                    gid: gid,
+                    roceV2: GidIsRoCEv2(deviceName, ibPort, index),
+                }),
+                _ => (),
+            }
+        }
+
+        return gids;
+    }
+}
+
+// RDMAGid is an entry of the gid table of the port
+#[derive(Clone, Copy, Debug)]
+pub struct RDMAGid {
+    pub index: u8,
+    pub gid: Gid,
+    // the RoCEv2 gid is the ip address of the netdev, it is routable by the ip network
+    pub roceV2: bool,
+}
+
+pub const IB_SYSFS: &str = "/sys/class/infiniband";
+
+pub fn GidIsRoCEv2(deviceName: &str, ibPort: u8, index: u8) -> bool {
+    let path = format!("{}/{}/ports/{}/gid_attrs/types/{}", IB_SYSFS, deviceName, ibPort, index);
+    match fs::read_to_string(&path) {
+        Ok(t) => return t.trim() == "RoCE v2",
+        Err(_) => return false,
+    }
+}
+
+// ActivePorts returns the ports of the device whose state is active
+pub fn ActivePorts(deviceName: &str) -> Vec<u8> {
+    let mut ports = Vec::new();
+    let dir = match fs::read_dir(format!("{}/{}/ports", IB_SYSFS, deviceName)) {
+        Ok(d) => d,
+        Err(_) => return ports,
+    };
+
+    for entry in dir.flatten() {
+        let port = match entry.file_name().to_str().and_then(|n| n.parse::<u8>().ok()) {
+            None => continue,
+            Some(p) => p,
+        };
+
+        // e.g. "4: ACTIVE"
+        match fs::read_to_string(entry.path().join("state")) {
+            Ok(s) if s.contains("ACTIVE") => ports.push(port),
+            _ => (),
+        }
+    }
+
+    ports.sort();
+    return ports;
+}
+
+// DefaultGidIndex returns the configured gid index, it is the first RoCEv2 gid or the index 0
+// when the config is -1
+pub fn DefaultGidIndex(gids: &[RDMAGid], gidIndex: i32) -> u8 {
+    if gidIndex >= 0 {
+        return gidIndex as u8;
+    }
+
+    match gids.iter().find(|g| g.roceV2) {
+        Some(g) => return g.index,
+        None => return 0,
+    }
+}
+
+// FindGid returns the gid entry of the local ip address, the RoCEv2 one is preferred
+pub fn FindGid(gids: &[RDMAGid], addr: &IpAddr) -> Option<RDMAGid> {
+    let target = Gid::FromIp(addr);
+    let mut found = None;
+    for g in gids {
+        if g.gid != target {
+            continue;
+        }
+
+        if g.roceV2 {
+            return Some(*g);
+        }
+
+        if found.is_none() {
+            found = Some(*g);
+        }
+    }
+
+    return found;
+}
+
+// LocalAddr returns the local address of the connected socket, which is the source address of
+// the route to the peer picked by the host kernel
+pub fn LocalAddr(fd: i32) -> Option<IpAddr> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let ret = unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) };
+    if ret < 0 {
+        return None;
+    }
+
+    match addr.ss_family as i32 {
+        libc::AF_INET => {
+            let a = unsafe { *(&addr as *const _ as *const libc::sockaddr_in) };
+            return Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr))));
+        }
+        libc::AF_INET6 => {
+            let a = unsafe { *(&addr as *const _ as *const libc::sockaddr_in6) };
+            return Some(IpAddr::V6(Ipv6Addr::from(a.sin6_addr.s6_addr)));
+        }
+        _ => return None,
     }
 }
 
@@ -273,6 +471,21 @@ impl Default for ProtectionDomain {
     }
 }
 
+impl ProtectionDomain {
+    // RegImplicitOdp registers the implicit ODP memory region of the whole address space, it has
+    // only the local access
+    pub fn RegImplicitOdp(&self) -> Result<MemoryRegion> {
+        let access = rdmaffi::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
+            | rdmaffi::ibv_access_flags::IBV_ACCESS_ON_DEMAND;
+        let mr = unsafe { rdmaffi::ibv_reg_mr(self.0, ptr::null_mut(), usize::MAX, access.0 as i32) };
+        if mr.is_null() {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(MemoryRegion(mr));
+    }
+}
+
 pub struct CompleteChannel(pub *mut rdmaffi::ibv_comp_channel);
 impl Drop for CompleteChannel {
     fn drop(&mut self) {}
@@ -305,15 +518,42 @@ pub struct RDMAContextIntern {
     completeChannel: CompleteChannel, /* io completion channel */
     completeQueue: CompleteQueue,     /* CQ handle */
     ccfd: i32,                        // complete channel fd
+    deviceName: String,
     ibPort: u8,
+    gids: Vec<RDMAGid>,
+    // the gid used when the local address of the connection isn't in the gid table
+    gidIndex: u8,
     gid: Gid,
+    // the memory regions are registered with on-demand paging
+    odp: bool,
+    // the implicit ODP memory region of the whole address space for the local access, null
+    // when the device doesn't have it
+    localMR: MemoryRegion,
 }
 
 impl RDMAContextIntern {
-    pub fn New(deviceName: &str, ibPort: u8) -> Self {
+    pub fn New(deviceName: &str, ibPort: u8, gidIndex: i32, odp: bool) -> Self {
         let ibContext = IBContext::New(deviceName);
         let portAttr = ibContext.QueryPort(ibPort);
         let protectDomain = ibContext.AllocProtectionDomain();
+        let (odp, implicit) = if odp {
+            ibContext.OdpCaps()
+        } else {
+            (false, false)
+        };
+
+        let localMR = if implicit {
+            match protectDomain.RegImplicitOdp() {
+                Ok(mr) => mr,
+                Err(e) => {
+                    error!("implicit ODP memory region of {} fails with {:?}", deviceName, e);
+                    MemoryRegion::default()
+                }
+            }
+        } else {
+            MemoryRegion::default()
+        };
+        info!("RDMA device {} ODP: {}, implicit ODP: {}", deviceName, odp, !localMR.0.is_null());
         let completeChannel = ibContext.CreateCompleteChannel();
         let ccfd = unsafe { (*completeChannel.0).fd };
 
@@ -322,7 +562,12 @@ impl RDMAContextIntern {
         IO_MGR.AddWait(ccfd, EVENT_READ);
 
         let completeQueue = ibContext.CreateCompleteQueue(&completeChannel);
-        let gid = ibContext.QueryGid(ibPort);
+        let gids = ibContext.QueryGids(deviceName, ibPort, portAttr.gid_tbl_len);
+        let gidIndex = DefaultGidIndex(&gids, gidIndex);
+        let gid = match ibContext.QueryGid(ibPort, gidIndex) {
+            Ok(gid) => gid,
+            Err(e) => panic!("ibv_query_gid of {} port {} index {} failed: {:?}", deviceName, ibPort, gidIndex, e),
+        };
 
         // unblock complete channel fd
         super::super::VMSpace::UnblockFd(ccfd);
@@ -334,8 +579,13 @@ impl RDMAContextIntern {
             completeChannel: completeChannel,
             ccfd: ccfd,
             completeQueue: completeQueue,
+            deviceName: deviceName.to_string(),
             ibPort: ibPort,
+            gids: gids,
+            gidIndex: gidIndex,
             gid: gid,
+            odp: odp,
+            localMR: localMR,
         };
     }
 }
@@ -354,16 +604,170 @@ impl Deref for RDMAContext {
     }
 }
 
+// RDMADeviceConfig is the RDMA device list of the config file, it is read by the host only as
+// the config in the share space can't keep the strings
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct RDMADeviceConfig {
+    // the names of the devices to use, all the devices of the host when it is empty
+    pub RDMADevices: Vec<String>,
+}
+
+impl RDMADeviceConfig {
+    pub fn Load() -> Self {
+        let contents = match fs::read_to_string(Config::CONFIG_FILE) {
+            Ok(c) => c,
+            _ => return Self::default(),
+        };
+
+        return serde_json::from_str(&contents).unwrap_or_default();
+    }
+}
+
+// RDMAEndpoint is the local port and the gid which a connection uses
+#[derive(Clone, Copy)]
+pub struct RDMAEndpoint {
+    pub context: &'static RDMAContext,
+    pub gidIndex: u8,
+    pub gid: Gid,
+}
+
+// RDMADevices is the contexts of the ports used by the sandbox, one for each port of the
+// configured devices. A connection uses the port whose gid table has the local address of its
+// route, so the RDMA traffic goes by the NIC that the tcp connection uses.
+#[derive(Default)]
+pub struct RDMADevices {
+    // the contexts live as long as the sandbox
+    pub contexts: RwLock<Vec<&'static RDMAContext>>,
+    // the ports are opened by the first RDMA socket, see Open
+    pub opened: atomic::AtomicBool,
+}
+
+impl RDMADevices {
+    // Open opens the ports of the config with the first RDMA socket, the sandbox which doesn't
+    // use RDMA doesn't pay for the device contexts and their queues at boot
+    pub fn Open(&self) {
+        if self.opened.load(atomic::Ordering::Acquire) {
+            return;
+        }
+
+        let mut contexts = self.contexts.write();
+        if self.opened.load(atomic::Ordering::Acquire) {
+            return;
+        }
+
+        // the configured devices, all the devices of the host when none is configured
+        let devices = RDMADeviceConfig::Load().RDMADevices;
+        let (ibPort, gidIndex, odp) = {
+            let config = QUARK_CONFIG.lock();
+            (config.RDMAPort, config.RDMAGidIndex, config.RDMAOdp)
+        };
+
+        Self::OpenPorts(&mut contexts, &devices, ibPort, gidIndex, odp);
+        self.opened.store(true, atomic::Ordering::Release);
+    }
+
+    // OpenPorts opens the ports of the devices, all the devices of the host when it is empty and
+    // all the active ports of a device when ibPort is 0. The sockets fall back to tcp when no
+    // port is opened.
+    fn OpenPorts(contexts: &mut Vec<&'static RDMAContext>, devices: &[String], ibPort: u8, gidIndex: i32, odp: bool) {
+        let names = if devices.len() == 0 {
+            IBContext::DeviceNames()
+        } else {
+            devices.to_vec()
+        };
+
+        for name in &names {
+            let ports = if ibPort != 0 {
+                vec![ibPort]
+            } else {
+                ActivePorts(name)
+            };
+
+            for port in ports {
+                let context = RDMAContext::New(name, port, gidIndex, odp);
+                info!("RDMA uses device {} port {} gid index {}", name, port, context.GidIndex());
+                contexts.push(Box::leak(Box::new(context)));
+            }
+        }
+
+        if contexts.len() == 0 {
+            error!("no active RDMA port is found in the devices {:?}", names);
+        }
+    }
+
+    // Select returns the port and the gid for the connected socket fd. The first port with its
+    // default gid is used when the local address isn't a gid, e.g. the infiniband ports. It is
+    // None when no port is opened.
+    pub fn Select(&self, fd: i32) -> Option<RDMAEndpoint> {
+        self.Open();
+        let contexts = self.contexts.read();
+        if contexts.len() == 0 {
+            return None;
+        }
+
+        if let Some(addr) = LocalAddr(fd) {
+            for context in contexts.iter() {
+                if let Some(g) = context.FindGid(&addr) {
+                    return Some(RDMAEndpoint {
+                        context: *context,
+                        gidIndex: g.index,
+                        gid: g.gid,
+                    });
+                }
+            }
+        }
+
+        let context = contexts[0];
+        return Some(RDMAEndpoint {
+            context: context,
+            gidIndex: context.GidIndex(),
+            gid: context.Gid(),
+        });
+    }
+
+    pub fn PollCompletionQueueAndProcess(&self) -> usize {
+        let mut count = 0;
+        for context in self.contexts.read().iter() {
+            count += context.PollCompletionQueueAndProcess();
+        }
+
+        return count;
+    }
+
+    pub fn HandleCQEvent(&self) -> Result<()> {
+        for context in self.contexts.read().iter() {
+            context.HandleCQEvent()?;
+        }
+
+        return Ok(());
+    }
+}
+
 pub const MAX_SEND_WR: u32 = 100;
 pub const MAX_RECV_WR: u32 = 8192;
 pub const MAX_SEND_SGE: u32 = 1;
 pub const MAX_RECV_SGE: u32 = 1;
 
 impl RDMAContext {
-    pub fn Init(&self, deviceName: &str, ibPort: u8) {
-        if RDMA_ENABLE {
-            *self.0.lock() = RDMAContextIntern::New(deviceName, ibPort);
-        }
+    pub fn New(deviceName: &str, ibPort: u8, gidIndex: i32, odp: bool) -> Self {
+        return Self(Mutex::new(RDMAContextIntern::New(deviceName, ibPort, gidIndex, odp)));
+    }
+
+    pub fn DeviceName(&self) -> String {
+        return self.lock().deviceName.clone();
+    }
+
+    pub fn Port(&self) -> u8 {
+        return self.lock().ibPort;
+    }
+
+    pub fn GidIndex(&self) -> u8 {
+        return self.lock().gidIndex;
+    }
+
+    pub fn FindGid(&self, addr: &IpAddr) -> Option<RDMAGid> {
+        return FindGid(&self.lock().gids, addr);
     }
 
     pub fn Lid(&self) -> u16 {
@@ -376,7 +780,20 @@ impl RDMAContext {
         return context.gid;
     }
 
-    pub fn CreateQueuePair(&self) -> Result<QueuePair> {
+    // CreateQueuePair creates the RC qp with the inline data of maxInline bytes. The device
+    // which can't support it gets the qp without the inline data.
+    // ret: (qp, the max inline data size of the qp)
+    pub fn CreateQueuePair(&self, maxInline: u32) -> Result<(QueuePair, u32)> {
+        match self.CreateRCQueuePair(maxInline) {
+            Err(e) if maxInline > 0 => {
+                error!("CreateQueuePair with inline data {} fails with {:?}, retry without it", maxInline, e);
+                return self.CreateRCQueuePair(0);
+            }
+            res => return res,
+        }
+    }
+
+    fn CreateRCQueuePair(&self, maxInline: u32) -> Result<(QueuePair, u32)> {
         let context = self.lock();
         //create queue pair
         let mut qp_init_attr = rdmaffi::ibv_qp_init_attr {
@@ -390,7 +807,7 @@ impl RDMAContext {
                 max_recv_wr: 8192, //MAX_RECV_WR,
                 max_send_sge: MAX_SEND_SGE,
                 max_recv_sge: MAX_RECV_SGE,
-                max_inline_data: 0,
+                max_inline_data: maxInline,
             },
             qp_type: rdmaffi::ibv_qp_type::IBV_QPT_RC,
             sq_sig_all: 0,
@@ -402,15 +819,85 @@ impl RDMAContext {
             return Err(Error::SysError(errno::errno().0));
         }
 
+        // the device may give more than asked, the sends don't go beyond the threshold
+        let inline = core::cmp::min(qp_init_attr.cap.max_inline_data, maxInline);
+        return Ok((QueuePair(Mutex::new(qp)), inline));
+    }
+
+    // CreateUDQueuePair creates the unreliable datagram qp of a udp socket
+    pub fn CreateUDQueuePair(&self, maxSend: u32, maxRecv: u32) -> Result<QueuePair> {
+        let context = self.lock();
+        let mut qp_init_attr = rdmaffi::ibv_qp_init_attr {
+            qp_context: 0 as *mut _,
+            send_cq: context.completeQueue.0 as *const _ as *mut _,
+            recv_cq: context.completeQueue.0 as *const _ as *mut _,
+            srq: ptr::null::<rdmaffi::ibv_srq>() as *mut _,
+            cap: rdmaffi::ibv_qp_cap {
+                max_send_wr: maxSend,
+                max_recv_wr: maxRecv,
+                max_send_sge: MAX_SEND_SGE,
+                max_recv_sge: MAX_RECV_SGE,
+                max_inline_data: 0,
+            },
+            qp_type: rdmaffi::ibv_qp_type::IBV_QPT_UD,
+            sq_sig_all: 1,
+        };
+
+        let qp =
+            unsafe { rdmaffi::ibv_create_qp(context.protectDomain.0, &mut qp_init_attr as *mut _) };
+        if qp.is_null() {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
         return Ok(QueuePair(Mutex::new(qp)));
     }
 
+    // CreateAddressHandle creates the address handle of the UD peer
+    pub fn CreateAddressHandle(&self, dlid: u16, dgid: Gid, sgidIndex: u8) -> Result<AddressHandle> {
+        let context = self.lock();
+        let mut attr = rdmaffi::ibv_ah_attr {
+            grh: rdmaffi::ibv_global_route {
+                dgid: rdmaffi::ibv_gid::from(dgid),
+                flow_label: 0,
+                sgid_index: sgidIndex,
+                hop_limit: 64,
+                traffic_class: 0,
+            },
+            dlid: dlid,
+            sl: 0,
+            src_path_bits: 0,
+            static_rate: 0,
+            is_global: 1,
+            port_num: context.ibPort,
+        };
+
+        let ah = unsafe { rdmaffi::ibv_create_ah(context.protectDomain.0, &mut attr) };
+        if ah.is_null() {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(AddressHandle(ah));
+    }
+
+    // ActiveMtu returns the bytes of the active mtu of the port, a UD message is limited to it
+    pub fn ActiveMtu(&self) -> usize {
+        let mtu = self.lock().portAttr.0.active_mtu as u32;
+        // IBV_MTU_256 is 1 .. IBV_MTU_4096 is 5
+        return 128 << mtu;
+    }
+
     pub fn CreateMemoryRegion(&self, addr: u64, size: usize) -> Result<MemoryRegion> {
         let context = self.lock();
         let access = rdmaffi::ibv_access_flags::IBV_ACCESS_LOCAL_WRITE
             | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_WRITE
-            | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ
-            | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC;
+            | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_READ;
+        // the pages are faulted in by the device when they are accessed instead of being pinned,
+        // the ODP caps don't include the atomics
+        let access = if context.odp {
+            access | rdmaffi::ibv_access_flags::IBV_ACCESS_ON_DEMAND
+        } else {
+            access | rdmaffi::ibv_access_flags::IBV_ACCESS_REMOTE_ATOMIC
+        };
 
         let mr = unsafe {
             rdmaffi::ibv_reg_mr(
@@ -428,6 +915,19 @@ impl RDMAContext {
         return Ok(MemoryRegion(mr));
     }
 
+    // CreateLocalMemoryRegion returns the memory region of the buffer which is only accessed by
+    // the local device, e.g. the source of the writes. It is the implicit ODP one when the device
+    // has it, the buffer is neither registered nor pinned. The implicit one has no remote access
+    // as its rkey would expose all the memory of qvisor to the peers.
+    pub fn CreateLocalMemoryRegion(&self, addr: u64, size: usize) -> Result<MemoryRegion> {
+        let localMR = self.lock().localMR.0;
+        if !localMR.is_null() {
+            return Ok(MemoryRegion(localMR));
+        }
+
+        return self.CreateMemoryRegion(addr, size);
+    }
+
     pub fn CompleteQueue(&self) -> *mut rdmaffi::ibv_cq {
         return self.lock().completeQueue.0;
     }
@@ -642,13 +1142,30 @@ impl RDMAContext {
         //         );
         //     }
         // }
+        // the recv requests of the channel are dispatched to the streams by the channel
+        if fd == RDMA_CHANNEL_FD {
+            RDMA_CHANNELS.ProcessWC(wrid.Slot(), wc);
+            return;
+        }
+
+        // the opcode of the failed work request is not valid, the RDMA of the connection is
+        // torn down, it gets the flushed ones after that
         if wc.status != rdmaffi::ibv_wc_status::IBV_WC_SUCCESS {
             error!(
                 "ProcessWC::1, work reqeust failed with status: {}, id: {}",
                 wc.status, wc.wr_id
             );
+            // the peer doesn't ack after the retries of the device
+            let retry = wc.status == rdmaffi::ibv_wc_status::IBV_WC_RETRY_EXC_ERR
+                || wc.status == rdmaffi::ibv_wc_status::IBV_WC_RNR_RETRY_EXC_ERR;
+            IO_MGR.ProcessRDMAError(fd, retry);
+            return;
         }
-        if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_RDMA_WRITE {
+        if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_SEND {
+            IO_MGR.ProcessUDSendFinish(fd, wrid.Slot());
+        } else if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_RECV {
+            IO_MGR.ProcessUDRecv(fd, wrid.Slot(), wc.byte_len);
+        } else if wc.opcode == rdmaffi::ibv_wc_opcode::IBV_WC_RDMA_WRITE {
             // debug!(
             //     "ProcessWC::2, writeIMM status: {}, id: {}",
             //     wc.status, wc.wr_id
@@ -671,6 +1188,15 @@ impl RDMAContext {
     }
 }
 
+// WriteImmSeg is one write with immediate of the batch of WriteImmBatch
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteImmSeg {
+    pub laddr: u64,
+    pub len: u32,
+    pub raddr: u64,
+    pub imm: u32,
+}
+
 pub struct ImmData(pub u32);
 
 impl ImmData {
@@ -701,6 +1227,16 @@ impl WorkRequestId {
         return Self(((fd as u64) << 32) | (NewUID() as u32 as u64));
     }
 
+    // NewSlot is the work request of the UD qp, slot is the buffer of the datagram, or the recv
+    // request of the RDMA channel whose id is the slot
+    pub fn NewSlot(fd: i32, slot: u32) -> Self {
+        return Self(((fd as u64) << 32) | slot as u64);
+    }
+
+    pub fn Slot(&self) -> u32 {
+        return self.0 as u32;
+    }
+
     pub fn Fd(&self) -> i32 {
         ((self.0 >> 32) & 0xffff_ffff) as i32
     }
@@ -728,7 +1264,12 @@ unsafe impl Send for QueuePair {}
 unsafe impl Sync for QueuePair {}
 
 impl Drop for QueuePair {
-    fn drop(&mut self) {}
+    fn drop(&mut self) {
+        let qp = *self.0.lock();
+        if !qp.is_null() {
+            unsafe { rdmaffi::ibv_destroy_qp(qp) };
+        }
+    }
 }
 
 impl QueuePair {
@@ -750,46 +1291,87 @@ impl QueuePair {
         rkey: u32,
         imm: u32,
     ) -> Result<()> {
-        let opcode = rdmaffi::ibv_wr_opcode::IBV_WR_RDMA_WRITE_WITH_IMM;
-        let mut sge = rdmaffi::ibv_sge {
-            addr: laddr,
-            length: len,
-            lkey: lkey,
+        let seg = WriteImmSeg {
+            laddr: laddr,
+            len: len,
+            raddr: raddr,
+            imm: imm,
         };
 
-        let mut sw = rdmaffi::ibv_send_wr {
-            wr_id: wrId,
-            next: ptr::null_mut(),
-            sg_list: &mut sge,
-            num_sge: 1,
-            opcode: opcode,
-            send_flags: rdmaffi::ibv_send_flags::IBV_SEND_SIGNALED.0,
-            imm_data_invalidated_rkey_union: rdmaffi::imm_data_invalidated_rkey_union_t {
-                imm_data: imm,
-            }, //TODO: need double check
-            qp_type: rdmaffi::qp_type_t {
-                xrc: rdmaffi::xrc_t { remote_srqn: 0 },
-            },
-            wr: rdmaffi::wr_t {
-                rdma: rdmaffi::rdma_t {
-                    //TODO: this is not needed when opcode is IBV_WR_SEND
-                    remote_addr: raddr,
-                    rkey: rkey,
+        return self.WriteImmBatch(wrId, &[seg], lkey, rkey, 0);
+    }
+
+    // WriteImmBatch posts the writes of the segments by one doorbell. Only the last one is
+    // signaled, its completion covers the batch. The segment not larger than maxInline is
+    // copied to the work request so the device doesn't have to read it by DMA.
+    pub fn WriteImmBatch(
+        &self,
+        wrId: u64,
+        segs: &[WriteImmSeg],
+        lkey: u32,
+        rkey: u32,
+        maxInline: u32,
+    ) -> Result<()> {
+        let opcode = rdmaffi::ibv_wr_opcode::IBV_WR_RDMA_WRITE_WITH_IMM;
+        let mut sges: Vec<rdmaffi::ibv_sge> = segs
+            .iter()
+            .map(|s| rdmaffi::ibv_sge {
+                addr: s.laddr,
+                length: s.len,
+                lkey: lkey,
+            })
+            .collect();
+
+        let mut wrs: Vec<rdmaffi::ibv_send_wr> = Vec::with_capacity(segs.len());
+        for (i, s) in segs.iter().enumerate() {
+            let mut flags = 0;
+            if i == segs.len() - 1 {
+                flags |= rdmaffi::ibv_send_flags::IBV_SEND_SIGNALED.0;
+            }
+
+            if s.len > 0 && s.len <= maxInline {
+                flags |= rdmaffi::ibv_send_flags::IBV_SEND_INLINE.0;
+            }
+
+            wrs.push(rdmaffi::ibv_send_wr {
+                wr_id: wrId,
+                next: ptr::null_mut(),
+                sg_list: &mut sges[i],
+                num_sge: 1,
+                opcode: opcode,
+                send_flags: flags,
+                imm_data_invalidated_rkey_union: rdmaffi::imm_data_invalidated_rkey_union_t {
+                    imm_data: s.imm,
+                }, //TODO: need double check
+                qp_type: rdmaffi::qp_type_t {
+                    xrc: rdmaffi::xrc_t { remote_srqn: 0 },
                 },
-            },
-            bind_mw_tso_union: rdmaffi::bind_mw_tso_union_t {
-                //TODO: need a better init solution
-                tso: rdmaffi::tso_t {
-                    hdr: ptr::null_mut(),
-                    hdr_sz: 0,
-                    mss: 0,
+                wr: rdmaffi::wr_t {
+                    rdma: rdmaffi::rdma_t {
+                        remote_addr: s.raddr,
+                        rkey: rkey,
+                    },
                 },
-            },
-        };
+                bind_mw_tso_union: rdmaffi::bind_mw_tso_union_t {
+                    //TODO: need a better init solution
+                    tso: rdmaffi::tso_t {
+                        hdr: ptr::null_mut(),
+                        hdr_sz: 0,
+                        mss: 0,
+                    },
+                },
+            });
+        }
+
+        // the vec doesn't grow any more, the links stay valid
+        for i in 1..wrs.len() {
+            let next = &mut wrs[i] as *mut _;
+            wrs[i - 1].next = next;
+        }
 
         let mut bad_wr: *mut rdmaffi::ibv_send_wr = ptr::null_mut();
 
-        let rc = unsafe { rdmaffi::ibv_post_send(self.Data(), &mut sw, &mut bad_wr) };
+        let rc = unsafe { rdmaffi::ibv_post_send(self.Data(), &mut wrs[0], &mut bad_wr) };
 
         if rc != 0 {
             return Err(Error::SysError(errno::errno().0));
@@ -827,9 +1409,10 @@ impl QueuePair {
         remote_qpn: u32,
         dlid: u16,
         dgid: Gid,
+        sgidIndex: u8,
     ) -> Result<()> {
         self.ToInit(context)?;
-        self.ToRtr(context, remote_qpn, dlid, dgid)?;
+        self.ToRtr(context, remote_qpn, dlid, dgid, sgidIndex)?;
         self.ToRts()?;
         return Ok(());
     }
@@ -923,6 +1506,7 @@ impl QueuePair {
         remote_qpn: u32,
         dlid: u16,
         dgid: Gid,
+        sgidIndex: u8,
     ) -> Result<()> {
         let mut attr = rdmaffi::ibv_qp_attr {
             qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
@@ -998,18 +1582,15 @@ impl QueuePair {
         attr.ah_attr.sl = 0;
         attr.ah_attr.src_path_bits = 0;
         attr.ah_attr.port_num = context.lock().ibPort;
-        let gid_idx = 0;
 
-        // todo: configure with Qingqu
-        //if gid_idx >= 0 {
+        // the grh is needed by RoCE, the RoCEv2 packets may cross the ip routers
         {
             attr.ah_attr.is_global = 1;
-            attr.ah_attr.port_num = 1;
             // memcpy (&attr.ah_attr.grh.dgid, dgid, 16);
             attr.ah_attr.grh.dgid = rdmaffi::ibv_gid::from(dgid);
             attr.ah_attr.grh.flow_label = 0;
-            attr.ah_attr.grh.hop_limit = 1;
-            attr.ah_attr.grh.sgid_index = gid_idx;
+            attr.ah_attr.grh.hop_limit = 64;
+            attr.ah_attr.grh.sgid_index = sgidIndex;
             attr.ah_attr.grh.traffic_class = 0;
         }
 
@@ -1113,7 +1694,204 @@ impl QueuePair {
     }
 }
 
+// QpAttr returns the qp attributes to be set by ibv_modify_qp
+pub fn QpAttr() -> rdmaffi::ibv_qp_attr {
+    return rdmaffi::ibv_qp_attr {
+        qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
+        cur_qp_state: rdmaffi::ibv_qp_state::IBV_QPS_INIT,
+        path_mtu: rdmaffi::ibv_mtu::IBV_MTU_1024,
+        path_mig_state: rdmaffi::ibv_mig_state::IBV_MIG_ARMED,
+        qkey: 0,
+        rq_psn: 0,
+        sq_psn: 0,
+        dest_qp_num: 0,
+        qp_access_flags: 0,
+        cap: rdmaffi::ibv_qp_cap {
+            max_send_wr: 0,
+            max_recv_wr: 0,
+            max_send_sge: 0,
+            max_recv_sge: 0,
+            max_inline_data: 0,
+        },
+        ah_attr: rdmaffi::ibv_ah_attr {
+            grh: rdmaffi::ibv_global_route {
+                dgid: *Gid::default().as_mut(), //TODO: need recheck
+                flow_label: 0,
+                sgid_index: 0,
+                hop_limit: 0,
+                traffic_class: 0,
+            },
+            dlid: 0,
+            sl: 0,
+            src_path_bits: 0,
+            static_rate: 0,
+            is_global: 0,
+            port_num: 0,
+        },
+        alt_ah_attr: rdmaffi::ibv_ah_attr {
+            grh: rdmaffi::ibv_global_route {
+                dgid: *Gid::default().as_mut(), //TODO: need recheck
+                flow_label: 0,
+                sgid_index: 0,
+                hop_limit: 0,
+                traffic_class: 0,
+            },
+            dlid: 0,
+            sl: 0,
+            src_path_bits: 0,
+            static_rate: 0,
+            is_global: 0,
+            port_num: 0,
+        },
+        pkey_index: 0,
+        alt_pkey_index: 0,
+        en_sqd_async_notify: 0,
+        sq_draining: 0,
+        max_rd_atomic: 0,
+        max_dest_rd_atomic: 0,
+        min_rnr_timer: 0,
+        port_num: 0,
+        timeout: 0,
+        retry_cnt: 0,
+        rnr_retry: 0,
+        alt_port_num: 0,
+        alt_timeout: 0,
+        rate_limit: 0,
+    };
+}
+
+impl QueuePair {
+    // SetupUD moves the UD qp to RTS, the peers are given by the address handles of the sends
+    pub fn SetupUD(&self, context: &RDMAContext, qkey: u32) -> Result<()> {
+        let mut attr = QpAttr();
+        attr.qp_state = rdmaffi::ibv_qp_state::IBV_QPS_INIT;
+        attr.pkey_index = 0;
+        attr.port_num = context.Port();
+        attr.qkey = qkey;
+        let flags = rdmaffi::ibv_qp_attr_mask::IBV_QP_STATE
+            | rdmaffi::ibv_qp_attr_mask::IBV_QP_PKEY_INDEX
+            | rdmaffi::ibv_qp_attr_mask::IBV_QP_PORT
+            | rdmaffi::ibv_qp_attr_mask::IBV_QP_QKEY;
+        self.Modify(&mut attr, flags)?;
+
+        let mut attr = QpAttr();
+        attr.qp_state = rdmaffi::ibv_qp_state::IBV_QPS_RTR;
+        self.Modify(&mut attr, rdmaffi::ibv_qp_attr_mask::IBV_QP_STATE)?;
+
+        let mut attr = QpAttr();
+        attr.qp_state = rdmaffi::ibv_qp_state::IBV_QPS_RTS;
+        attr.sq_psn = 0;
+        let flags = rdmaffi::ibv_qp_attr_mask::IBV_QP_STATE | rdmaffi::ibv_qp_attr_mask::IBV_QP_SQ_PSN;
+        return self.Modify(&mut attr, flags);
+    }
+
+    fn Modify(&self, attr: &mut rdmaffi::ibv_qp_attr, flags: rdmaffi::ibv_qp_attr_mask) -> Result<()> {
+        let rc = unsafe { rdmaffi::ibv_modify_qp(self.Data(), attr, flags.0 as i32) };
+        if rc != 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(());
+    }
+
+    // PostSendUD sends the datagram in the registered buf to the UD qp of the peer
+    pub fn PostSendUD(
+        &self,
+        wrId: u64,
+        laddr: u64,
+        len: u32,
+        lkey: u32,
+        ah: &AddressHandle,
+        remoteQpn: u32,
+        remoteQkey: u32,
+    ) -> Result<()> {
+        let mut sge = rdmaffi::ibv_sge {
+            addr: laddr,
+            length: len,
+            lkey: lkey,
+        };
+
+        let mut sw = rdmaffi::ibv_send_wr {
+            wr_id: wrId,
+            next: ptr::null_mut(),
+            sg_list: &mut sge,
+            num_sge: 1,
+            opcode: rdmaffi::ibv_wr_opcode::IBV_WR_SEND,
+            send_flags: rdmaffi::ibv_send_flags::IBV_SEND_SIGNALED.0,
+            imm_data_invalidated_rkey_union: rdmaffi::imm_data_invalidated_rkey_union_t {
+                imm_data: 0,
+            },
+            qp_type: rdmaffi::qp_type_t {
+                xrc: rdmaffi::xrc_t { remote_srqn: 0 },
+            },
+            wr: rdmaffi::wr_t {
+                ud: rdmaffi::ud_t {
+                    ah: ah.0,
+                    remote_qpn: remoteQpn,
+                    remote_qkey: remoteQkey,
+                },
+            },
+            bind_mw_tso_union: rdmaffi::bind_mw_tso_union_t {
+                tso: rdmaffi::tso_t {
+                    hdr: ptr::null_mut(),
+                    hdr_sz: 0,
+                    mss: 0,
+                },
+            },
+        };
+
+        let mut bad_wr: *mut rdmaffi::ibv_send_wr = ptr::null_mut();
+        let rc = unsafe { rdmaffi::ibv_post_send(self.Data(), &mut sw, &mut bad_wr) };
+        if rc != 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(());
+    }
+
+    // PostRecvBuf posts the buf of len bytes for the UD receive, it starts with the 40 bytes
+    // of the grh
+    pub fn PostRecvBuf(&self, wrId: u64, addr: u64, len: u32, lkey: u32) -> Result<()> {
+        let mut sge = rdmaffi::ibv_sge {
+            addr: addr,
+            length: len,
+            lkey: lkey,
+        };
+        let mut rw = rdmaffi::ibv_recv_wr {
+            wr_id: wrId,
+            next: ptr::null_mut(),
+            sg_list: &mut sge,
+            num_sge: 1,
+        };
+        let mut bad_wr: *mut rdmaffi::ibv_recv_wr = ptr::null_mut();
+        let rc = unsafe { rdmaffi::ibv_post_recv(self.Data(), &mut rw, &mut bad_wr) };
+        if rc != 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(());
+    }
+}
+
+// AddressHandle is the path to a UD peer, it is shared by the sends to the peers of the same
+// gid through the cache of rdma_udp
+pub struct AddressHandle(pub *mut rdmaffi::ibv_ah);
+
+unsafe impl Send for AddressHandle {}
+unsafe impl Sync for AddressHandle {}
+
+impl Drop for AddressHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe { rdmaffi::ibv_destroy_ah(self.0) };
+        }
+    }
+}
+
+// MemoryRegion isn't deregistered when it is dropped, the implicit ODP one of the context is
+// shared by the buffers
 pub struct MemoryRegion(pub *mut rdmaffi::ibv_mr);
+
 impl Drop for MemoryRegion {
     fn drop(&mut self) {}
 }
@@ -1136,3 +1914,61 @@ impl MemoryRegion {
 
 unsafe impl Send for MemoryRegion {}
 unsafe impl Sync for MemoryRegion {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn RoCEGid(index: u8, addr: &str, roceV2: bool) -> RDMAGid {
+        return RDMAGid {
+            index: index,
+            gid: Gid::FromIp(&addr.parse().unwrap()),
+            roceV2: roceV2,
+        };
+    }
+
+    #[test]
+    fn test_gid_from_ip() {
+        let gid = Gid::FromIp(&"192.168.1.2".parse().unwrap());
+        assert_eq!(gid.Raw(), [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 192, 168, 1, 2]);
+        assert_eq!(gid.subnet_prefix(), 0);
+        assert_eq!(gid.interface_id(), 0x0000_ffff_c0a8_0102);
+
+        let addr: Ipv6Addr = "fe80::1".parse().unwrap();
+        let gid = Gid::FromIp(&IpAddr::V6(addr));
+        assert_eq!(gid.Raw(), addr.octets());
+        assert!(!gid.IsZero());
+        assert!(Gid::default().IsZero());
+    }
+
+    #[test]
+    fn test_default_gid_index() {
+        let gids = [
+            RoCEGid(0, "fe80::1", false),
+            RoCEGid(1, "fe80::1", true),
+            RoCEGid(2, "10.0.0.1", false),
+            RoCEGid(3, "10.0.0.1", true),
+        ];
+        assert_eq!(DefaultGidIndex(&gids, -1), 1);
+        assert_eq!(DefaultGidIndex(&gids, 2), 2);
+        assert_eq!(DefaultGidIndex(&gids[2..3], -1), 0);
+        assert_eq!(DefaultGidIndex(&[], -1), 0);
+    }
+
+    #[test]
+    fn test_find_gid() {
+        let gids = [
+            RoCEGid(0, "fe80::1", false),
+            RoCEGid(2, "10.0.0.1", false),
+            RoCEGid(3, "10.0.0.1", true),
+            RoCEGid(4, "10.0.0.2", false),
+        ];
+        // the RoCEv2 gid is preferred
+        assert_eq!(FindGid(&gids, &"10.0.0.1".parse().unwrap()).unwrap().index, 3);
+        assert_eq!(FindGid(&gids, &"10.0.0.2".parse().unwrap()).unwrap().index, 4);
+        assert_eq!(FindGid(&gids, &"fe80::1".parse().unwrap()).unwrap().index, 0);
+        assert!(FindGid(&gids, &"10.0.0.3".parse().unwrap()).is_none());
+        // the ipv4 mapped ipv6 address is the same gid
+        assert_eq!(FindGid(&gids, &"::ffff:10.0.0.1".parse().unwrap()).unwrap().index, 3);
+    }
+}
@@ -0,0 +1,329 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::super::super::qlib::mutex::*;
+use alloc::sync::Arc;
+use core::mem;
+use core::ops::Deref;
+use libc::*;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+use super::super::super::qlib::common::*;
+use super::super::super::qlib::linux_def::*;
+use super::super::super::IO_MGR;
+use super::rdma::*;
+
+lazy_static! {
+    pub static ref RDMA_CHANNELS: RDMAChannelMgr = RDMAChannelMgr::default();
+}
+
+// the fd of the work requests of the channel qp, the id of the channel is in the low 32 bits
+pub const RDMA_CHANNEL_FD: i32 = -2;
+// the read count in the immediate data is in the credits of 64 bytes
+pub const RDMA_CREDIT_SHIFT: usize = 6;
+pub const RDMA_MAX_CREDITS: usize = 0xffff;
+pub const RDMA_MAX_STREAMS: usize = 0xffff;
+
+// MuxImmData is the immediate data of the write on the channel qp: the stream id of the receiver
+// in the high 16 bits and the read count of the sender in credits in the low 16 bits
+pub struct MuxImmData(pub u32);
+
+impl MuxImmData {
+    // New returns the immediate data with the credits of the read count, the rest of the read
+    // count which doesn't fit is returned to be sent by the next write.
+    // ret: (imm, the read count left)
+    pub fn New(streamId: u16, readCount: usize) -> (Self, usize) {
+        let credits = core::cmp::min(readCount >> RDMA_CREDIT_SHIFT, RDMA_MAX_CREDITS);
+        let left = readCount - (credits << RDMA_CREDIT_SHIFT);
+        return (Self(((streamId as u32) << 16) | credits as u32), left);
+    }
+
+    pub fn StreamId(&self) -> u16 {
+        return (self.0 >> 16) as u16;
+    }
+
+    pub fn ReadCount(&self) -> usize {
+        return ((self.0 & 0xffff) as usize) << RDMA_CREDIT_SHIFT;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelState {
+    Init,
+    // connected to the channel qp of the peer host
+    Ready(u32),
+    Error,
+}
+
+pub struct RDMAChannelIntern {
+    pub id: u32,
+    pub peer: IpAddr,
+    pub endpoint: RDMAEndpoint,
+    pub qp: QMutex<QueuePair>,
+    pub maxInline: u32,
+    pub state: QMutex<ChannelState>,
+    // the local stream id to the host fd of the socket
+    pub streams: QMutex<BTreeMap<u16, i32>>,
+    pub nextStream: QMutex<u16>,
+}
+
+// RDMAChannel is the RC qp shared by the RDMA connections to the same peer host on the same port.
+// A connection is a stream of the channel: its writes go by the channel qp with the stream id of
+// the peer in the immediate data, the receives of all the streams are posted by the channel and
+// dispatched by the stream id. The read and write bufs of the connections are still registered
+// by the connections.
+#[derive(Clone)]
+pub struct RDMAChannel(Arc<RDMAChannelIntern>);
+
+impl Deref for RDMAChannel {
+    type Target = Arc<RDMAChannelIntern>;
+
+    fn deref(&self) -> &Arc<RDMAChannelIntern> {
+        &self.0
+    }
+}
+
+impl RDMAChannel {
+    pub fn AddStream(&self, fd: i32) -> Result<u16> {
+        let mut streams = self.streams.lock();
+        if streams.len() >= RDMA_MAX_STREAMS {
+            return Err(Error::SysError(SysErr::ENOSPC));
+        }
+
+        let mut next = self.nextStream.lock();
+        loop {
+            let id = *next;
+            *next = next.wrapping_add(1);
+            if !streams.contains_key(&id) {
+                streams.insert(id, fd);
+                return Ok(id);
+            }
+        }
+    }
+
+    pub fn RemoveStream(&self, id: u16) {
+        self.streams.lock().remove(&id);
+    }
+
+    pub fn QpNum(&self) -> u32 {
+        return self.qp.lock().qpNum();
+    }
+
+    // Connect connects the channel qp to the one of the peer host given by the handshake of a
+    // connection, the channel connected before is used by the later connections. The channel
+    // of the peer which is not the connected one is a new one after its failure, so the stale
+    // channel is failed and the connection falls back to tcp.
+    pub fn Connect(&self, remoteQpn: u32, lid: u16, gid: Gid) -> Result<()> {
+        let mut state = self.state.lock();
+        match *state {
+            ChannelState::Init => (),
+            ChannelState::Ready(qpn) if qpn == remoteQpn => return Ok(()),
+            ChannelState::Ready(_) => {
+                drop(state);
+                self.Fail(false);
+                return Err(Error::SysError(SysErr::ECONNRESET));
+            }
+            ChannelState::Error => return Err(Error::SysError(SysErr::ECONNRESET)),
+        }
+
+        let context = self.endpoint.context;
+        self.qp
+            .lock()
+            .Setup(context, remoteQpn, lid, gid, self.endpoint.gidIndex)?;
+        // the recv requests are shared by all the streams
+        for _i in 0..MAX_RECV_WR {
+            self.PostRecv()?;
+        }
+
+        *state = ChannelState::Ready(remoteQpn);
+        return Ok(());
+    }
+
+    // the write with immediate data has no receive buffer, the sge is empty
+    fn PostRecv(&self) -> Result<()> {
+        let wr = WorkRequestId::NewSlot(RDMA_CHANNEL_FD, self.id);
+        return self.qp.lock().PostRecv(wr.0, 0, 0);
+    }
+
+    pub fn WriteImmBatch(&self, wrId: u64, segs: &[WriteImmSeg], lkey: u32, rkey: u32) -> Result<()> {
+        return self
+            .qp
+            .lock()
+            .WriteImmBatch(wrId, segs, lkey, rkey, self.maxInline);
+    }
+
+    pub fn ProcessRecv(&self, imm: u32, len: u32) {
+        let imm = MuxImmData(imm);
+        let fd = self.streams.lock().get(&imm.StreamId()).copied();
+        match fd {
+            // the stream is closed, the data in flight is dropped
+            None => (),
+            Some(fd) => IO_MGR.ProcessRDMARecvWriteImm(fd, len as _, imm.ReadCount() as _),
+        }
+
+        if let Err(e) = self.PostRecv() {
+            error!("PostRecv of RDMA channel {} fails with {:?}", self.id, e);
+            self.Fail(false);
+        }
+    }
+
+    // Fail resets the streams of the channel, the later connections to the peer get a new one
+    pub fn Fail(&self, retry: bool) {
+        {
+            let mut state = self.state.lock();
+            if *state == ChannelState::Error {
+                return;
+            }
+            *state = ChannelState::Error;
+        }
+
+        error!("RDMA channel {} to {:?} fails", self.id, self.peer);
+        RDMA_CHANNELS.Remove(self);
+        let streams = mem::take(&mut *self.streams.lock());
+        for (_, fd) in streams {
+            IO_MGR.ProcessRDMAError(fd, retry);
+        }
+
+        *self.qp.lock() = QueuePair::default();
+    }
+}
+
+// RDMAStream is the stream of a connection on the channel, it is removed when the connection
+// is closed
+pub struct RDMAStream {
+    pub channel: RDMAChannel,
+    pub id: u16,
+}
+
+impl RDMAStream {
+    pub fn New(channel: &RDMAChannel, fd: i32) -> Result<Self> {
+        let id = channel.AddStream(fd)?;
+        return Ok(Self {
+            channel: channel.clone(),
+            id: id,
+        });
+    }
+}
+
+impl Drop for RDMAStream {
+    fn drop(&mut self) {
+        self.channel.RemoveStream(self.id);
+    }
+}
+
+#[derive(Default)]
+pub struct RDMAChannelMgr {
+    // (the context, the peer host) to the channel
+    pub channels: QMutex<HashMap<(usize, IpAddr), RDMAChannel>>,
+    pub ids: QMutex<BTreeMap<u32, RDMAChannel>>,
+    pub nextId: QMutex<u32>,
+}
+
+impl RDMAChannelMgr {
+    // Get returns the channel to the peer host of the connection on the port of the endpoint
+    pub fn Get(&self, endpoint: &RDMAEndpoint, fd: i32, maxInline: u32) -> Result<RDMAChannel> {
+        let peer = match PeerAddr(fd) {
+            None => return Err(Error::SysError(SysErr::ENOTCONN)),
+            Some(p) => p,
+        };
+
+        let key = (endpoint.context as *const _ as usize, peer);
+        let mut channels = self.channels.lock();
+        if let Some(c) = channels.get(&key) {
+            return Ok(c.clone());
+        }
+
+        let (qp, maxInline) = endpoint.context.CreateQueuePair(maxInline)?;
+        let id = {
+            let mut next = self.nextId.lock();
+            *next += 1;
+            *next
+        };
+
+        let channel = RDMAChannel(Arc::new(RDMAChannelIntern {
+            id: id,
+            peer: peer,
+            endpoint: *endpoint,
+            qp: QMutex::new(qp),
+            maxInline: maxInline,
+            state: QMutex::new(ChannelState::Init),
+            streams: QMutex::new(BTreeMap::new()),
+            nextStream: QMutex::new(0),
+        }));
+
+        info!("RDMA channel {} to {:?} on {}", id, peer, endpoint.context.DeviceName());
+        channels.insert(key, channel.clone());
+        self.ids.lock().insert(id, channel.clone());
+        return Ok(channel);
+    }
+
+    pub fn Remove(&self, channel: &RDMAChannel) {
+        let key = (channel.endpoint.context as *const _ as usize, channel.peer);
+        let mut channels = self.channels.lock();
+        let same = match channels.get(&key) {
+            Some(c) => Arc::ptr_eq(&c.0, &channel.0),
+            None => false,
+        };
+
+        if same {
+            channels.remove(&key);
+        }
+
+        self.ids.lock().remove(&channel.id);
+    }
+
+    // ProcessWC processes the completion of the recv request of the channel, the writes are
+    // completed to the connections which post them
+    pub fn ProcessWC(&self, id: u32, wc: &rdmaffi::ibv_wc) {
+        let channel = match self.ids.lock().get(&id) {
+            None => return,
+            Some(c) => c.clone(),
+        };
+
+        if wc.status != rdmaffi::ibv_wc_status::IBV_WC_SUCCESS {
+            // the flushed ones of the failed channel
+            channel.Fail(false);
+            return;
+        }
+
+        let imm = unsafe { wc.imm_data_invalidated_rkey_union.imm_data };
+        channel.ProcessRecv(imm, wc.byte_len);
+    }
+}
+
+// PeerAddr returns the address of the peer host of the connected socket
+pub fn PeerAddr(fd: i32) -> Option<IpAddr> {
+    let mut addr: sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+    let ret = unsafe { getpeername(fd, &mut addr as *mut _ as *mut sockaddr, &mut len) };
+    if ret < 0 {
+        return None;
+    }
+
+    match addr.ss_family as i32 {
+        AF_INET => {
+            let a = unsafe { *(&addr as *const _ as *const sockaddr_in) };
+            return Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr))));
+        }
+        AF_INET6 => {
+            let a = unsafe { *(&addr as *const _ as *const sockaddr_in6) };
+            return Some(IpAddr::V6(Ipv6Addr::from(a.sin6_addr.s6_addr)));
+        }
+        _ => return None,
+    }
+}
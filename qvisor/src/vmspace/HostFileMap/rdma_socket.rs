@@ -2,10 +2,15 @@ use super::super::super::qlib::mutex::*;
 use alloc::sync::Arc;
 use core::mem;
 use core::ops::Deref;
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
 use libc::*;
+use std::collections::VecDeque;
+use std::sync::Condvar;
+use std::time::Duration;
+use std::time::Instant;
 
 use super::super::super::qlib::common::*;
 use super::super::super::qlib::kernel::guestfdnotifier::*;
@@ -13,11 +18,243 @@ use super::super::super::qlib::linux_def::*;
 use super::super::super::qlib::qmsg::qcall::*;
 use super::super::super::qlib::socket_buf::*;
 use super::super::super::IO_MGR;
+use super::super::super::SHARE_SPACE;
 use super::super::super::URING_MGR;
+use super::super::super::QUARK_CONFIG;
 use super::rdma::*;
+use super::rdma_channel::*;
 use super::socket_info::*;
 use super::super::super::qlib::kernel::TSC;
 
+lazy_static! {
+    pub static ref RDMA_HANDSHAKE: RDMAHandshakePool = RDMAHandshakePool::New();
+}
+
+pub const RDMA_HANDSHAKE_THREADS: usize = 2;
+// the qp setup is retried before the connection falls back to the tcp path
+pub const RDMA_SETUP_RETRIES: usize = 3;
+// the handshake timeouts are checked by the pool threads at the tick
+pub const RDMA_HANDSHAKE_TICK: Duration = Duration::from_millis(100);
+// the keepalive probes without answer before the peer host is dead
+pub const RDMA_KEEPALIVE_PROBES: u32 = 3;
+
+// RDMAHandshakePool sets up the queue pairs of the new RDMA connections. The qp setup and the
+// PostRecv of MAX_RECV_WR requests are slow, so they run on the pool threads instead of the io
+// notification path, which goes on serving the established connections. The handshake resumes
+// from the pool thread when the setup is done. The pool threads also fall back the handshakes
+// which don't finish in RDMAHandshakeTimeout to the tcp path.
+pub struct RDMAHandshakePool {
+    pub queue: std::sync::Mutex<VecDeque<(RDMADataSock, FdWaitInfo)>>,
+    pub cond: Condvar,
+    pub threads: std::sync::Once,
+    // the sockets in the handshake
+    pub watching: std::sync::Mutex<Vec<(RDMADataSock, FdWaitInfo)>>,
+    // the qp setups queued or running
+    pub setups: AtomicUsize,
+}
+
+impl RDMAHandshakePool {
+    pub fn New() -> Self {
+        return Self {
+            queue: std::sync::Mutex::new(VecDeque::new()),
+            cond: Condvar::new(),
+            threads: std::sync::Once::new(),
+            watching: std::sync::Mutex::new(Vec::new()),
+            setups: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn Busy(&self) -> bool {
+        return self.setups.load(Ordering::SeqCst) > 0
+    }
+
+    fn Start(&'static self) {
+        self.threads.call_once(|| {
+            for i in 0..RDMA_HANDSHAKE_THREADS {
+                std::thread::Builder::new()
+                    .name(format!("rdma_handshake{}", i))
+                    .spawn(move || RDMA_HANDSHAKE.Process())
+                    .unwrap();
+            }
+        });
+    }
+
+    // Submit queues the socket which has got the RDMA metadata of the peer
+    pub fn Submit(&'static self, sock: RDMADataSock, waitinfo: FdWaitInfo) {
+        self.Start();
+        self.setups.fetch_add(1, Ordering::SeqCst);
+        self.queue.lock().unwrap().push_back((sock, waitinfo));
+        self.cond.notify_one();
+    }
+
+    // Watch checks the timeout of the socket until its handshake is over or the socket is closed,
+    // there is nothing to check when the timeout is disabled
+    pub fn Watch(&'static self, sock: RDMADataSock, waitinfo: FdWaitInfo) {
+        if QUARK_CONFIG.lock().RDMAHandshakeTimeout == 0 {
+            return;
+        }
+
+        self.Start();
+        self.watching.lock().unwrap().push((sock, waitinfo));
+    }
+
+    fn Process(&self) {
+        loop {
+            let item = {
+                let mut queue = self.queue.lock().unwrap();
+                if queue.is_empty() {
+                    queue = self.cond.wait_timeout(queue, RDMA_HANDSHAKE_TICK).unwrap().0;
+                }
+
+                queue.pop_front()
+            };
+
+            if let Some((sock, waitinfo)) = item {
+                let res = sock.SetupRDMA();
+                sock.FinishSetup(res, waitinfo);
+                self.setups.fetch_sub(1, Ordering::SeqCst);
+            }
+
+            self.CheckTimeouts();
+        }
+    }
+
+    fn CheckTimeouts(&self) {
+        let socks = mem::take(&mut *self.watching.lock().unwrap());
+        let mut pending = Vec::new();
+        for (sock, waitinfo) in socks {
+            if !sock.HandshakeTimeout(&waitinfo) {
+                pending.push((sock, waitinfo));
+            }
+        }
+
+        self.watching.lock().unwrap().extend(pending);
+    }
+}
+
+// HandshakeDeadline returns the deadline of the next handshake step, None when the timeout is
+// disabled
+pub fn HandshakeDeadline() -> Option<Instant> {
+    let timeout = QUARK_CONFIG.lock().RDMAHandshakeTimeout;
+    if timeout == 0 {
+        return None;
+    }
+
+    return Some(Instant::now() + Duration::from_millis(timeout));
+}
+
+// the messages of the handshake over the tcp socket before the data goes by RDMA
+pub const HANDSHAKE_MAGIC: u32 = 0x51444d41;
+pub const HANDSHAKE_INFO: u32 = 1;
+pub const HANDSHAKE_ACK: u32 = 2;
+// the connection uses the tcp path, the data follows the fallbacks of both sides
+pub const HANDSHAKE_FALLBACK: u32 = 3;
+
+#[derive(Clone, Default)]
+#[repr(C)]
+pub struct HandshakeMsg {
+    pub magic: u32,
+    pub kind: u32,
+    pub info: RDMAInfo,
+}
+
+impl HandshakeMsg {
+    pub fn Size() -> usize {
+        return mem::size_of::<Self>();
+    }
+}
+
+#[derive(Default)]
+pub struct Handshake {
+    // the bytes of the messages not sent yet
+    pub send: Vec<u8>,
+    // the part of the message being received
+    pub recv: Vec<u8>,
+    pub deadline: Option<Instant>,
+    pub peerAcked: bool,
+    pub fallbackSent: bool,
+    pub peerFallback: bool,
+}
+
+impl Handshake {
+    pub fn Queue(&mut self, kind: u32, info: &RDMAInfo) {
+        let msg = HandshakeMsg {
+            magic: HANDSHAKE_MAGIC,
+            kind: kind,
+            info: info.clone(),
+        };
+
+        let bytes = unsafe {
+            std::slice::from_raw_parts(&msg as *const _ as *const u8, HandshakeMsg::Size())
+        };
+        self.send.extend_from_slice(bytes);
+    }
+
+    // Flush sends the queued bytes, the ones left are sent on the next write event
+    pub fn Flush(&mut self, fd: i32) -> Result<()> {
+        while self.send.len() > 0 {
+            let ret = unsafe { write(fd, self.send.as_ptr() as _, self.send.len() as _) };
+            if ret < 0 {
+                let errno = errno::errno().0;
+                if errno == SysErr::EINTR {
+                    continue;
+                }
+
+                if errno == SysErr::EAGAIN {
+                    return Ok(());
+                }
+
+                return Err(Error::SysError(errno));
+            }
+
+            self.send.drain(..ret as usize);
+        }
+
+        return Ok(());
+    }
+
+    // Recv reads the next message, None when it is not complete yet
+    pub fn Recv(&mut self, fd: i32) -> Result<Option<HandshakeMsg>> {
+        let size = HandshakeMsg::Size();
+        while self.recv.len() < size {
+            let have = self.recv.len();
+            self.recv.resize(size, 0);
+            let ret = unsafe { read(fd, self.recv[have..].as_mut_ptr() as _, (size - have) as _) };
+            if ret <= 0 {
+                self.recv.truncate(have);
+                if ret == 0 {
+                    return Err(Error::SysError(SysErr::ECONNRESET));
+                }
+
+                let errno = errno::errno().0;
+                if errno == SysErr::EINTR {
+                    continue;
+                }
+
+                if errno == SysErr::EAGAIN {
+                    return Ok(None);
+                }
+
+                return Err(Error::SysError(errno));
+            }
+
+            self.recv.truncate(have + ret as usize);
+        }
+
+        let mut msg = HandshakeMsg::default();
+        unsafe {
+            std::ptr::copy_nonoverlapping(self.recv.as_ptr(), &mut msg as *mut _ as *mut u8, size);
+        }
+        self.recv.clear();
+
+        if msg.magic != HANDSHAKE_MAGIC {
+            return Err(Error::SysError(SysErr::EPROTO));
+        }
+
+        return Ok(Some(msg));
+    }
+}
+
 pub struct RDMAServerSockIntern {
     pub fd: i32,
     pub acceptQueue: AcceptQueue,
@@ -74,6 +311,16 @@ impl RDMAServerSock {
                     return;
                 }
 
+                if IsTransientAcceptErr(errno) {
+                    let (trigger, tmp) = acceptQueue.lock().EnqError(errno);
+                    hasSpace = tmp;
+
+                    if trigger {
+                        waitinfo.Notify(EVENT_IN);
+                    }
+                    continue;
+                }
+
                 waitinfo.Notify(EVENT_ERR | EVENT_IN);
                 acceptQueue.lock().SetErr(errno);
                 return;
@@ -143,6 +390,15 @@ pub struct RDMADataSockIntern {
     pub writeMemoryRegion: MemoryRegion,
     pub rdmaType: RDMAType,
     pub writeCount: AtomicUsize, //when run the writeimm, save the write bytes count here
+    pub handshake: QMutex<Handshake>,
+    // the port and the gid selected by the route of the tcp connection, None without RDMA
+    pub endpoint: Option<RDMAEndpoint>,
+    // the write not larger than it is sent inline, 0 when the qp has no inline data
+    pub maxInline: u32,
+    // the stream of the shared channel qp with RDMAMultiplex, the connection has no qp of its own
+    pub stream: Option<RDMAStream>,
+    // the host fd is closed, the handshake pool drops the socket and doesn't touch the fd
+    pub closed: AtomicBool,
 }
 
 #[derive(Clone, Default)]
@@ -157,6 +413,8 @@ pub struct RDMAInfo {
     freespace: u32, //read buffer free space size
     gid: Gid,       /* gid */
     sending: bool,  // the writeimmediately is ongoing
+    streamId: u16,  // the stream of the channel qp
+    mux: bool,      // qp_num is the channel qp shared with the other connections
 }
 
 impl RDMAInfo {
@@ -171,9 +429,14 @@ pub enum SocketState {
     Init,
     Connect,
     WaitingForRemoteMeta,
+    SettingUp,
     WaitingForRemoteReady,
     Ready,
     Error,
+    // the fallback is sent, waiting for the one of the peer
+    FallingBack,
+    // the handshake has fallen back, the data goes through the tcp socket
+    Tcp,
 }
 
 pub enum RDMAType {
@@ -195,169 +458,402 @@ impl Deref for RDMADataSock {
 
 impl RDMADataSock {
     pub fn New(fd: i32, socketBuf: Arc<SocketBuff>, rdmaType: RDMAType) -> Self {
-        if RDMA_ENABLE {
-            let (addr, len) = socketBuf.ReadBuf();
-            let readMR = RDMA
-                .CreateMemoryRegion(addr, len)
-                .expect("RDMADataSock CreateMemoryRegion fail");
-            let qp = RDMA.CreateQueuePair().expect("RDMADataSock create QP fail");
-
-            let localRDMAInfo = RDMAInfo {
-                raddr: addr,
-                rlen: len as _,
-                rkey: readMR.RKey(),
-                qp_num: qp.qpNum(),
-                lid: RDMA.Lid(),
-                offset: 0,
-                freespace: len as u32,
-                gid: RDMA.Gid(),
-                sending: false,
-            };
+        // the connection which can't get the RDMA resources falls back to the tcp path in the
+        // handshake
+        let res = if RDMA_ENABLE {
+            match Self::NewRDMA(fd, &socketBuf) {
+                Ok(res) => Some(res),
+                Err(e) => {
+                    error!("RDMA resources of fd {} can't be created with {:?}, fall back to tcp", fd, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
 
-            let (waddr, wlen) = socketBuf.WriteBuf();
-            let writeMR = RDMA
-                .CreateMemoryRegion(waddr, wlen)
-                .expect("RDMADataSock CreateMemoryRegion fail");
-
-            return Self(Arc::new(RDMADataSockIntern {
-                fd: fd,
-                socketBuf: socketBuf,
-                readLock: QMutex::new(()),
-                writeLock: QMutex::new(()),
-                qp: QMutex::new(qp),
-                peerInfo: QMutex::new(RDMAInfo::default()),
-                socketState: AtomicU64::new(0),
-                localRDMAInfo: localRDMAInfo,
-                remoteRDMAInfo: QMutex::new(RDMAInfo::default()),
-                readMemoryRegion: readMR,
-                writeMemoryRegion: writeMR,
-                rdmaType: rdmaType,
-                writeCount: AtomicUsize::new(0),
-            }));
+        let (endpoint, (qp, maxInline, stream), readMR, writeMR, localRDMAInfo) = match res {
+            Some((endpoint, qp, readMR, writeMR, localRDMAInfo)) => {
+                (Some(endpoint), qp, readMR, writeMR, localRDMAInfo)
+            }
+            None => (
+                None,
+                (QueuePair::default(), 0, None),
+                MemoryRegion::default(),
+                MemoryRegion::default(),
+                RDMAInfo::default(),
+            ),
+        };
+
+        return Self(Arc::new(RDMADataSockIntern {
+            fd: fd,
+            socketBuf: socketBuf,
+            readLock: QMutex::new(()),
+            writeLock: QMutex::new(()),
+            qp: QMutex::new(qp),
+            peerInfo: QMutex::new(RDMAInfo::default()),
+            socketState: AtomicU64::new(0),
+            localRDMAInfo: localRDMAInfo,
+            remoteRDMAInfo: QMutex::new(RDMAInfo::default()),
+            readMemoryRegion: readMR,
+            writeMemoryRegion: writeMR,
+            rdmaType: rdmaType,
+            writeCount: AtomicUsize::new(0),
+            handshake: QMutex::new(Handshake::default()),
+            endpoint: endpoint,
+            maxInline: maxInline,
+            stream: stream,
+            closed: AtomicBool::new(false),
+        }));
+    }
+
+    fn NewRDMA(
+        fd: i32,
+        socketBuf: &SocketBuff,
+    ) -> Result<(
+        RDMAEndpoint,
+        (QueuePair, u32, Option<RDMAStream>),
+        MemoryRegion,
+        MemoryRegion,
+        RDMAInfo,
+    )> {
+        let endpoint = match RDMA_DEVICES.Select(fd) {
+            None => return Err(Error::SysError(SysErr::ENODEV)),
+            Some(e) => e,
+        };
+
+        let context = endpoint.context;
+        let (addr, len) = socketBuf.ReadBuf();
+        let readMR = context.CreateMemoryRegion(addr, len)?;
+        let (waddr, wlen) = socketBuf.WriteBuf();
+        // the write buf is only read by the local device
+        let writeMR = context.CreateLocalMemoryRegion(waddr, wlen)?;
+
+        let (inline, multiplex) = {
+            let config = QUARK_CONFIG.lock();
+            (config.RDMAInlineThreshold, config.RDMAMultiplex)
+        };
+
+        let (qp, maxInline, stream) = if multiplex {
+            let channel = RDMA_CHANNELS.Get(&endpoint, fd, inline)?;
+            let stream = RDMAStream::New(&channel, fd)?;
+            (QueuePair::default(), channel.maxInline, Some(stream))
         } else {
-            let readMR = MemoryRegion::default();
-            let writeMR = MemoryRegion::default();
-            let qp = QueuePair::default();
-
-            let localRDMAInfo = RDMAInfo::default();
-
-            return Self(Arc::new(RDMADataSockIntern {
-                fd: fd,
-                socketBuf: socketBuf,
-                readLock: QMutex::new(()),
-                writeLock: QMutex::new(()),
-                qp: QMutex::new(qp),
-                peerInfo: QMutex::new(RDMAInfo::default()),
-                socketState: AtomicU64::new(0),
-                localRDMAInfo: localRDMAInfo,
-                remoteRDMAInfo: QMutex::new(RDMAInfo::default()),
-                readMemoryRegion: readMR,
-                writeMemoryRegion: writeMR,
-                rdmaType: rdmaType,
-                writeCount: AtomicUsize::new(0),
-            }));
-        }
-    }
-
-    pub fn SendLocalRDMAInfo(&self) -> Result<()> {
-        let ret = unsafe {
-            write(
-                self.fd,
-                &self.localRDMAInfo as *const _ as u64 as _,
-                RDMAInfo::Size(),
-            )
+            let (qp, maxInline) = context.CreateQueuePair(inline)?;
+            (qp, maxInline, None)
         };
 
-        if ret < 0 {
-            let errno = errno::errno().0;
-            // debug!("SendLocalRDMAInfo, err: {}", errno);
-            self.socketBuf.SetErr(errno);
-            return Err(Error::SysError(errno));
+        let (qpNum, streamId) = match &stream {
+            None => (qp.qpNum(), 0),
+            Some(s) => (s.channel.QpNum(), s.id),
+        };
+
+        let localRDMAInfo = RDMAInfo {
+            raddr: addr,
+            rlen: len as _,
+            rkey: readMR.RKey(),
+            qp_num: qpNum,
+            lid: context.Lid(),
+            offset: 0,
+            freespace: len as u32,
+            gid: endpoint.gid,
+            sending: false,
+            streamId: streamId,
+            mux: multiplex,
+        };
+
+        return Ok((endpoint, (qp, maxInline, stream), readMR, writeMR, localRDMAInfo));
+    }
+
+    // Handshake drives the handshake with the events of the host socket, the pool threads and
+    // the timer. The host socket is nonblocking, the messages are sent and received in pieces.
+    pub fn Handshake(&self, waitinfo: FdWaitInfo) {
+        let mut hs = self.handshake.lock();
+        if let Err(e) = self.HandshakeLocked(&mut hs, &waitinfo) {
+            self.HandshakeFail(&mut hs, e);
+        }
+    }
+
+    fn HandshakeLocked(&self, hs: &mut Handshake, waitinfo: &FdWaitInfo) -> Result<()> {
+        if let SocketState::Init = self.SocketState() {
+            RDMA_HANDSHAKE.Watch(self.clone(), waitinfo.clone());
+            if self.endpoint.is_none() {
+                // the RDMA resources can't be created, the peer is asked to use the tcp path
+                self.StartFallback(hs)?;
+            } else {
+                hs.Queue(HANDSHAKE_INFO, &self.localRDMAInfo);
+                hs.deadline = HandshakeDeadline();
+                self.SetSocketState(SocketState::WaitingForRemoteMeta);
+            }
+        }
+
+        hs.Flush(self.fd)?;
+
+        // the bytes after the fallback of the peer are the data of the tcp path
+        while !hs.peerFallback {
+            let msg = match hs.Recv(self.fd)? {
+                None => break,
+                Some(msg) => msg,
+            };
+
+            match (msg.kind, self.SocketState()) {
+                (HANDSHAKE_FALLBACK, _) => {
+                    hs.peerFallback = true;
+                    if !hs.fallbackSent {
+                        self.StartFallback(hs)?;
+                    }
+                }
+                // the messages sent by the peer before it gets the fallback are dropped
+                (_, SocketState::FallingBack) => (),
+                (HANDSHAKE_INFO, SocketState::WaitingForRemoteMeta) => {
+                    *self.remoteRDMAInfo.lock() = msg.info;
+                    self.SetSocketState(SocketState::SettingUp);
+                    RDMA_HANDSHAKE.Submit(self.clone(), waitinfo.clone());
+                }
+                // the ack of the peer may come when the local qp is being set up
+                (HANDSHAKE_ACK, SocketState::SettingUp) | (HANDSHAKE_ACK, SocketState::WaitingForRemoteReady) => {
+                    hs.peerAcked = true;
+                }
+                (kind, state) => {
+                    error!("RDMA handshake of fd {} gets message {} in state {:?}", self.fd, kind, state);
+                    return Err(Error::SysError(SysErr::EPROTO));
+                }
+            }
+        }
+
+        // the socket is ready when the messages of this side are all sent
+        if hs.send.len() > 0 {
+            return Ok(());
+        }
+
+        if hs.peerFallback {
+            self.FallBack();
+            self.SetReady(SocketState::Tcp, waitinfo.clone());
+            // the peer may have sent the data after its fallback
+            self.ReadData(waitinfo.clone());
+        } else if hs.peerAcked {
+            if let SocketState::WaitingForRemoteReady = self.SocketState() {
+                self.SetReady(SocketState::Ready, waitinfo.clone());
+            }
         }
 
-        assert!(
-            ret == RDMAInfo::Size() as isize,
-            "SendLocalRDMAInfo fail ret is {}, expect {}",
-            ret,
-            RDMAInfo::Size()
-        );
         return Ok(());
     }
 
-    pub fn RecvRemoteRDMAInfo(&self) -> Result<()> {
-        let mut data = RDMAInfo::default();
-        let ret = unsafe { read(self.fd, &mut data as *mut _ as u64 as _, RDMAInfo::Size()) };
+    // StartFallback asks the peer to use the tcp path, both sides switch to it when they have
+    // got the fallback of the other
+    fn StartFallback(&self, hs: &mut Handshake) -> Result<()> {
+        hs.fallbackSent = true;
+        hs.Queue(HANDSHAKE_FALLBACK, &RDMAInfo::default());
+        hs.deadline = HandshakeDeadline();
+        self.SetSocketState(SocketState::FallingBack);
+        return hs.Flush(self.fd);
+    }
+
+    // FallBack tears down the RDMA state of the connection before it is handed to the guest,
+    // the socket buf is marked so that the guest notifies the plain read and write
+    fn FallBack(&self) {
+        *self.qp.lock() = QueuePair::default();
+        self.socketBuf.SetRDMAFallback();
+        SHARE_SPACE.sockStats.RDMAFallback();
+    }
 
-        if ret < 0 {
-            let errno = errno::errno().0;
-            // debug!("RecvRemoteRDMAInfo, err: {}", errno);
-            //self.socketBuf.SetErr(errno);
-            return Err(Error::SysError(errno));
+    // RDMAFail resets the ready connection when a work request can't be posted or completes
+    // with an error. The data in flight may be lost, it can't go on by the tcp path in order.
+    pub fn RDMAFail(&self, errno: i32, waitinfo: &FdWaitInfo) {
+        let _handshake = self.handshake.lock();
+        match self.SocketState() {
+            SocketState::Ready => (),
+            _ => return,
         }
 
-        //self.socketBuf.SetErr(0); //TODO: find a better place
+        error!("RDMA of fd {} fails with errno {}, reset the connection", self.fd, errno);
+        RDMAStats::Inc(&self.socketBuf.rdmaStats.qpErrors);
+        self.Reset(errno, waitinfo);
+        // the peer gets the reset by the tcp socket
+        unsafe {
+            shutdown(self.fd, SHUT_RDWR);
+        }
+    }
 
-        assert!(
-            ret == RDMAInfo::Size() as isize,
-            "SendLocalRDMAInfo fail ret is {}, expect {}",
-            ret,
-            RDMAInfo::Size()
-        );
+    // PeerFail resets the ready connection whose tcp socket fails, e.g. the keepalive gets no
+    // answer when the peer host is gone. The writer waiting for the space of the peer posts no
+    // work request which could fail, it is woken up with the error here.
+    pub fn PeerFail(&self, errno: i32, waitinfo: &FdWaitInfo) {
+        let _handshake = self.handshake.lock();
+        match self.SocketState() {
+            SocketState::Ready => (),
+            _ => return,
+        }
 
-        *self.remoteRDMAInfo.lock() = data;
+        error!("tcp socket of the RDMA fd {} fails with errno {}, reset the connection", self.fd, errno);
+        self.Reset(errno, waitinfo);
+    }
 
-        return Ok(());
+    // need to be called when the self.handshake is locked
+    fn Reset(&self, errno: i32, waitinfo: &FdWaitInfo) {
+        self.SetSocketState(SocketState::Error);
+        *self.qp.lock() = QueuePair::default();
+        SHARE_SPACE.sockStats.RDMAReset();
+        self.socketBuf.SetErr(errno);
+        waitinfo.Notify(EVENT_ERR | EVENT_IN | EVENT_OUT);
     }
 
-    pub const ACK_DATA: u64 = 0x1234567890;
-    pub fn SendAck(&self) -> Result<()> {
-        let data: u64 = Self::ACK_DATA;
-        let ret = unsafe { write(self.fd, &data as *const _ as u64 as _, 8) };
-        if ret < 0 {
-            let errno = errno::errno().0;
-            
-            self.socketBuf.SetErr(errno);
-            return Err(Error::SysError(errno));
+    // SetKeepalive turns on the keepalive of the tcp socket of the ready connection, the socket
+    // fails in about timeout seconds after the peer host stops answering
+    fn SetKeepalive(&self, timeout: u32) -> Result<()> {
+        let intvl = core::cmp::max(timeout / (RDMA_KEEPALIVE_PROBES + 1), 1) as i32;
+        let opts = [
+            (SOL_SOCKET, SO_KEEPALIVE, 1),
+            (IPPROTO_TCP, TCP_KEEPIDLE, intvl),
+            (IPPROTO_TCP, TCP_KEEPINTVL, intvl),
+            (IPPROTO_TCP, TCP_KEEPCNT, RDMA_KEEPALIVE_PROBES as i32),
+            // the probes are not retransmitted longer than it
+            (IPPROTO_TCP, TCP_USER_TIMEOUT, (timeout * 1000) as i32),
+        ];
+
+        for &(level, name, val) in opts.iter() {
+            let ret = unsafe {
+                setsockopt(
+                    self.fd,
+                    level,
+                    name,
+                    &val as *const i32 as *const c_void,
+                    mem::size_of::<i32>() as socklen_t,
+                )
+            };
+            if ret < 0 {
+                return Err(Error::SysError(errno::errno().0));
+            }
         }
 
-        assert!(ret == 8, "SendAck fail ret is {}, expect {}", ret, 8);
         return Ok(());
     }
 
-    pub fn RecvAck(&self) -> Result<()> {
-        let mut data = 0;
-        let ret = unsafe { read(self.fd, &mut data as *mut _ as u64 as _, 8) };
+    fn HandshakeFail(&self, hs: &mut Handshake, e: Error) {
+        let errno = match e {
+            Error::SysError(errno) => errno,
+            _ => SysErr::EPROTO,
+        };
 
-        if ret < 0 {
-            let errno = errno::errno().0;
-            // debug!("RecvAck::1, err: {}", errno);
-            if errno == SysErr::EAGAIN {
-                return Err(Error::SysError(errno));
+        error!("RDMA handshake of fd {} fails with errno {}", self.fd, errno);
+        hs.send.clear();
+        self.SetSocketState(SocketState::Error);
+        self.socketBuf.SetErr(errno);
+        match &self.rdmaType {
+            RDMAType::Client(ref addr) => {
+                let msg = PostRDMAConnect::ToRef(*addr);
+                msg.Finish(-errno as i64);
+            }
+            // the connection is not in the accept queue yet, nobody else holds it. The handshake
+            // lock is held, so it is marked closed before the fd is closed.
+            RDMAType::Server(_) => {
+                self.closed.store(true, Ordering::SeqCst);
+                super::super::VMSpace::Close(self.fd);
             }
-            // debug!("RecvAck::2, err: {}", errno);
-            self.socketBuf.SetErr(errno);
-            return Err(Error::SysError(errno));
+            RDMAType::None => (),
         }
+    }
 
-        assert!(
-            ret == 8 as isize,
-            "RecvAck fail ret is {}, expect {}",
-            ret,
-            8
-        );
-        assert!(
-            data == Self::ACK_DATA,
-            "RecvAck fail data is {:x}, expect {:x}",
-            ret,
-            Self::ACK_DATA
-        );
+    // FinishSetup acks the peer after the qp is set up by the handshake pool, the connection
+    // falls back to the tcp path when the qp can't be set up
+    pub fn FinishSetup(&self, res: Result<()>, waitinfo: FdWaitInfo) {
+        let mut hs = self.handshake.lock();
+        if self.Closed() {
+            return;
+        }
 
-        return Ok(());
+        // the handshake has timed out or failed during the setup
+        match self.SocketState() {
+            SocketState::SettingUp => (),
+            _ => return,
+        }
+
+        let res = match res {
+            Err(e) => {
+                error!("RDMA setup of fd {} fails with {:?}, fall back to tcp", self.fd, e);
+                self.StartFallback(&mut hs)
+            }
+            Ok(()) => {
+                hs.Queue(HANDSHAKE_ACK, &RDMAInfo::default());
+                self.SetSocketState(SocketState::WaitingForRemoteReady);
+                Ok(())
+            }
+        };
+
+        let res = match res {
+            Err(e) => Err(e),
+            Ok(()) => self.HandshakeLocked(&mut hs, &waitinfo),
+        };
+
+        if let Err(e) = res {
+            self.HandshakeFail(&mut hs, e);
+        }
+    }
+
+    // HandshakeTimeout is checked by the handshake pool, it returns true when the handshake is
+    // over. The side which has sent the ack doesn't fall back as the peer may be ready with
+    // the RDMA path, the connection fails instead.
+    pub fn HandshakeTimeout(&self, waitinfo: &FdWaitInfo) -> bool {
+        let mut hs = self.handshake.lock();
+        if self.Closed() {
+            return true;
+        }
+
+        let state = self.SocketState();
+        match state {
+            SocketState::Ready | SocketState::Tcp | SocketState::Error => return true,
+            _ => (),
+        }
+
+        match hs.deadline {
+            Some(deadline) if Instant::now() >= deadline => (),
+            _ => return false,
+        }
+
+        let res = match state {
+            SocketState::WaitingForRemoteReady | SocketState::FallingBack => {
+                Err(Error::SysError(SysErr::ETIMEDOUT))
+            }
+            _ => {
+                error!("RDMA handshake of fd {} times out in state {:?}, fall back to tcp", self.fd, state);
+                self.StartFallback(&mut hs)
+            }
+        };
+
+        let res = match res {
+            Err(e) => Err(e),
+            Ok(()) => self.HandshakeLocked(&mut hs, waitinfo),
+        };
+
+        if let Err(e) = res {
+            self.HandshakeFail(&mut hs, e);
+            return true;
+        }
+
+        return false;
+    }
+
+    pub fn Closed(&self) -> bool {
+        return self.closed.load(Ordering::SeqCst);
+    }
+
+    // MarkClosed is called before the host fd is closed, the returned guard holds the handshake
+    // until the fd is closed so the pool threads don't use the fd number reused by another file.
+    // It is None when the socket is closed by the failed handshake, which holds the lock.
+    pub fn MarkClosed(&self) -> Option<QMutexGuard<Handshake>> {
+        if self.Closed() {
+            return None;
+        }
+
+        let hs = self.handshake.lock();
+        self.closed.store(true, Ordering::SeqCst);
+        return Some(hs);
     }
 
     pub fn SocketState(&self) -> SocketState {
         let state = self.socketState.load(Ordering::Relaxed);
-        assert!(state <= SocketState::Ready as u64);
+        assert!(state <= SocketState::Tcp as u64);
         let state: SocketState = unsafe { mem::transmute(state) };
         return state;
     }
@@ -368,81 +864,172 @@ impl RDMADataSock {
 
     /************************************ rdma integration ****************************/
     // after get remote peer's RDMA metadata and need to setup RDMA
-    pub fn SetupRDMA(&self) {
-        let remoteInfo = self.remoteRDMAInfo.lock();
+    pub fn SetupRDMA(&self) -> Result<()> {
+        let remoteInfo = self.remoteRDMAInfo.lock().clone();
+        let endpoint = self.endpoint.expect("RDMADataSock without the RDMA endpoint");
+        // both sides have to multiplex, otherwise the connection falls back to tcp
+        if remoteInfo.mux != self.stream.is_some() {
+            return Err(Error::SysError(SysErr::EPROTO));
+        }
+
+        // the recv requests are posted by the channel when it is connected first
+        if let Some(stream) = &self.stream {
+            return stream
+                .channel
+                .Connect(remoteInfo.qp_num, remoteInfo.lid, remoteInfo.gid);
+        }
+
         let start = TSC.Rdtsc();
-        self.qp
-            .lock()
-            .Setup(&RDMA, remoteInfo.qp_num, remoteInfo.lid, remoteInfo.gid)
-            .expect("SetupRDMA fail...");
+        let mut retries = 0;
+        loop {
+            match self.qp.lock().Setup(endpoint.context, remoteInfo.qp_num, remoteInfo.lid,
+                                       remoteInfo.gid, endpoint.gidIndex) {
+                Ok(()) => break,
+                Err(e) => {
+                    retries += 1;
+                    RDMAStats::Inc(&self.socketBuf.rdmaStats.retries);
+                    if retries >= RDMA_SETUP_RETRIES {
+                        return Err(e);
+                    }
+                }
+            }
+        }
         let d1 = TSC.Rdtsc() - start;
         let start1 = TSC.Rdtsc();
         for _i in 0..MAX_RECV_WR {
             let wr = WorkRequestId::New(self.fd);
             self.qp
                 .lock()
-                .PostRecv(wr.0, self.localRDMAInfo.raddr, self.localRDMAInfo.rkey)
-                .expect("SetupRDMA PostRecv fail");
+                .PostRecv(wr.0, self.localRDMAInfo.raddr, self.localRDMAInfo.rkey)?;
         }
         let d2 = TSC.Rdtsc() - start1;
         let d3 = TSC.Rdtsc() - start;
-        error!("Setup time: set up qp {}, create recv request: {}, total: {}", d1, d2, d3);
+        debug!("Setup time: set up qp {}, create recv request: {}, total: {}", d1, d2, d3);
+        return Ok(());
     }
 
+    // RDMAWriteImm writes the segments (addr, len) of the write buf to the remote read buf from
+    // its offset by one batch, the read count goes with the first one
     pub fn RDMAWriteImm(
         &self,
-        localAddr: u64,
-        remoteAddr: u64,
-        writeCount: usize,
+        segs: &[(u64, usize)],
         readCount: usize,
         remoteInfo: &QMutexGuard<RDMAInfo>,
     ) -> Result<()> {
         let wrid = WorkRequestId::New(self.fd);
-        let immData = ImmData::New(readCount);
-        let rkey = remoteInfo.rkey;
-
-        self.qp.lock().WriteImm(
-            wrid.0,
-            localAddr,
-            writeCount as u32,
-            self.writeMemoryRegion.LKey(),
-            remoteAddr,
-            rkey,
-            immData.0,
-        )?;
+        // the read count of the stream is sent in credits, the rest goes with the next write
+        let (imm, rest, left) = match &self.stream {
+            None => (ImmData::New(readCount).0, ImmData::New(0).0, 0),
+            Some(_) => {
+                let (imm, left) = MuxImmData::New(remoteInfo.streamId, readCount);
+                let (rest, _) = MuxImmData::New(remoteInfo.streamId, 0);
+                (imm.0, rest.0, left)
+            }
+        };
+
+        let mut offset = remoteInfo.offset;
+        let mut writeCount = 0;
+        let mut wrs = Vec::with_capacity(segs.len());
+        for (i, &(addr, len)) in segs.iter().enumerate() {
+            wrs.push(WriteImmSeg {
+                laddr: addr,
+                len: len as u32,
+                raddr: remoteInfo.raddr + offset as u64,
+                imm: if i == 0 { imm } else { rest },
+            });
+            offset = (offset + len as u32) % remoteInfo.rlen;
+            writeCount += len;
+        }
+
+        let lkey = self.writeMemoryRegion.LKey();
+        match &self.stream {
+            None => self.qp.lock().WriteImmBatch(
+                wrid.0,
+                &wrs,
+                lkey,
+                remoteInfo.rkey,
+                self.maxInline,
+            )?,
+            Some(stream) => stream
+                .channel
+                .WriteImmBatch(wrid.0, &wrs, lkey, remoteInfo.rkey)?,
+        }
+
+        if left > 0 {
+            self.socketBuf.AddConsumeReadData(left as u64);
+        }
+        self.socketBuf.rdmaStats.Write(wrs.len(), writeCount);
         self.writeCount.store(writeCount, QOrdering::RELEASE);
         return Ok(());
     }
 
+    // Sending returns whether the write of the socket is posted and not completed
+    pub fn Sending(&self) -> bool {
+        return self.remoteRDMAInfo.lock().sending;
+    }
+
     // need to be called when the self.writeLock is locked
-    pub fn RDMASend(&self) {
+    pub fn RDMASend(&self, waitinfo: &FdWaitInfo) {
         let remoteInfo = self.remoteRDMAInfo.lock();
         if remoteInfo.sending == true {
             return; // the sending is ongoing
         }
 
-        self.RDMASendLocked(remoteInfo);
+        self.RDMASendLocked(remoteInfo, waitinfo);
     }
 
-    pub fn RDMASendLocked(&self, mut remoteInfo: QMutexGuard<RDMAInfo>) {
+    pub fn RDMASendLocked(&self, mut remoteInfo: QMutexGuard<RDMAInfo>, waitinfo: &FdWaitInfo) {
+        // the socket is closed with SO_LINGER and the data left is dropped, the host close
+        // resets the connection
+        if self.socketBuf.WriteAbort() {
+            return;
+        }
+
         let readCount = self.socketBuf.GetAndClearConsumeReadData();
         let buf = self.socketBuf.writeBuf.lock();
-        let (addr, mut len) = buf.GetDataBuf();
+        let (addr, dataLen) = buf.GetDataBuf();
+        // the read count less than a credit of the stream waits for the next write
+        let readCount = match &self.stream {
+            Some(_) if dataLen == 0 && readCount < 1 << RDMA_CREDIT_SHIFT => {
+                if readCount > 0 {
+                    self.socketBuf.AddConsumeReadData(readCount);
+                }
+                0
+            }
+            _ => readCount,
+        };
         // debug!("RDMASendLocked::1, readCount: {}, addr: {:x}, len: {}, remote.freespace: {}", readCount, addr, len, remoteInfo.freespace);
-        if readCount > 0 || len > 0 {
-            if len > remoteInfo.freespace as usize {
-                len = remoteInfo.freespace as usize;
-            }
-
-            if len != 0 || readCount > 0 {
-                self.RDMAWriteImm(
-                    addr,
-                    remoteInfo.raddr + remoteInfo.offset as u64,
-                    len,
-                    readCount as usize,
-                    &remoteInfo,
-                )
-                .expect("RDMAWriteImm fail...");
+        if readCount > 0 || dataLen > 0 {
+            let mut space = remoteInfo.freespace as usize;
+            if dataLen > 0 && space == 0 {
+                RDMAStats::Inc(&self.socketBuf.rdmaStats.freespaceStalls);
+            }
+
+            let mut segs = Vec::with_capacity(2);
+            let first = core::cmp::min(dataLen, space);
+            if first != 0 || readCount > 0 {
+                segs.push((addr, first));
+            }
+            space -= first;
+
+            // the data wrapped to the start of the ring goes by the same doorbell
+            let wrapped = buf.AvailableDataSize() - dataLen;
+            if dataLen > 0 && first == dataLen && wrapped > 0 && space > 0 {
+                segs.push((buf.buf.buf, core::cmp::min(wrapped, space)));
+            }
+
+            let len: usize = segs.iter().map(|&(_, l)| l).sum();
+            if segs.len() > 0 {
+                let res = self.RDMAWriteImm(&segs, readCount as usize, &remoteInfo);
+
+                if let Err(e) = res {
+                    drop(buf);
+                    drop(remoteInfo);
+                    error!("RDMAWriteImm of fd {} fails with {:?}", self.fd, e);
+                    self.RDMAFail(SysErr::ECONNRESET, waitinfo);
+                    return;
+                }
+
                 remoteInfo.freespace -= len as u32;
                 remoteInfo.offset = (remoteInfo.offset + len as u32) % remoteInfo.rlen;
                 remoteInfo.sending = true;
@@ -454,6 +1041,11 @@ impl RDMADataSock {
     // triggered by the RDMAWriteImmediately finish
     pub fn ProcessRDMAWriteImmFinish(&self, waitinfo: FdWaitInfo) {
         let _writelock = self.writeLock.lock();
+        if let SocketState::Error = self.SocketState() {
+            return;
+        }
+
+        RDMAStats::Inc(&self.socketBuf.rdmaStats.writeCompletions);
         let mut remoteInfo = self.remoteRDMAInfo.lock();
         remoteInfo.sending = false;
 
@@ -469,7 +1061,7 @@ impl RDMADataSock {
         }
 
         if addr != 0 {
-            self.RDMASendLocked(remoteInfo)
+            self.RDMASendLocked(remoteInfo, &waitinfo)
         }
     }
 
@@ -480,12 +1072,24 @@ impl RDMADataSock {
         writeConsumeCount: u64,
         waitinfo: FdWaitInfo,
     ) {
-        let wr = WorkRequestId::New(self.fd);
+        if let SocketState::Error = self.SocketState() {
+            return;
+        }
 
-        let _res = self
-            .qp
-            .lock()
-            .PostRecv(wr.0, self.localRDMAInfo.raddr, self.localRDMAInfo.rkey);
+        RDMAStats::Inc(&self.socketBuf.rdmaStats.recvCompletions);
+        // the channel reposts the recv request of the stream
+        if self.stream.is_none() {
+            let wr = WorkRequestId::New(self.fd);
+            let res = self
+                .qp
+                .lock()
+                .PostRecv(wr.0, self.localRDMAInfo.raddr, self.localRDMAInfo.rkey);
+            if let Err(e) = res {
+                error!("PostRecv of fd {} fails with {:?}", self.fd, e);
+                self.RDMAFail(SysErr::ECONNRESET, &waitinfo);
+                return;
+            }
+        }
 
         // debug!("ProcessRDMARecvWriteImm::1, recvCount: {}, writeConsumeCount: {}", recvCount, writeConsumeCount);
 
@@ -506,14 +1110,25 @@ impl RDMADataSock {
             // debug!("ProcessRDMARecvWriteImm::3, trigger {}, remoteInfo.sending: {}", trigger, remoteInfo.sending);
 
             if trigger && !remoteInfo.sending {
-                self.RDMASendLocked(remoteInfo);
+                self.RDMASendLocked(remoteInfo, &waitinfo);
             }
         }
     }
 
     /*********************************** end of rdma integration ****************************/
 
-    pub fn SetReady(&self, _waitinfo: FdWaitInfo) {
+    // SetReady hands the connection to the guest, state is the data path of the handshake
+    pub fn SetReady(&self, state: SocketState, _waitinfo: FdWaitInfo) {
+        if let SocketState::Ready = state {
+            let timeout = QUARK_CONFIG.lock().RDMAKeepaliveSec;
+            if timeout != 0 {
+                if let Err(e) = self.SetKeepalive(timeout) {
+                    error!("keepalive of the RDMA fd {} fails with {:?}", self.fd, e);
+                }
+            }
+        }
+
+        self.SetSocketState(state);
         match &self.rdmaType {
             RDMAType::Client(ref addr) => {
                 //let addr = msg as *const _ as u64;
@@ -537,8 +1152,6 @@ impl RDMADataSock {
                 panic!("RDMADataSock setready fail ...");
             }
         }
-
-        self.SetSocketState(SocketState::Ready);
     }
 
     pub fn Read(&self, waitinfo: FdWaitInfo) {
@@ -546,60 +1159,53 @@ impl RDMADataSock {
             self.ReadData(waitinfo);
         } else {
             match self.SocketState() {
-                SocketState::WaitingForRemoteMeta => {
-                    let _readlock = self.readLock.lock();
-                    match self.RecvRemoteRDMAInfo() {
-                        Ok(()) => {},
-                        _ => return,
-                    }
-                    self.SetupRDMA();
-                    self.SendAck().unwrap(); // assume the socket is ready for send
-                    self.SetSocketState(SocketState::WaitingForRemoteReady);
-
-                    match self.RecvAck() {
-                        Ok(()) => {
-                            let waitinfo = match &self.rdmaType {
-                                RDMAType::Client(_) => waitinfo,
-                                RDMAType::Server(ref serverSock) => serverSock.waitInfo.clone(),
-                                _ => {
-                                    panic!("Not right RDMAType");
-                                }
-                            };
-                            self.SetReady(waitinfo);
-                        }
-                        _ => (),
-                    }
-                }
-                SocketState::WaitingForRemoteReady => {
-                    let _readlock = self.readLock.lock();
-                    match self.RecvAck() {
-                        Ok(()) => {},
-                        _ => return,
+                SocketState::Ready => {
+                    // the tcp socket of the ready connection carries no data, it fails when the
+                    // peer host is gone
+                    self.ReadData(waitinfo.clone());
+                    let errno = self.socketBuf.Error();
+                    if errno != 0 {
+                        self.PeerFail(errno, &waitinfo);
                     }
-                    self.SetReady(waitinfo);
                 }
-                SocketState::Ready => {
+                SocketState::Tcp => {
                     self.ReadData(waitinfo);
                 }
-                _ => {
-                    panic!(
-                        "RDMA socket read state error with state {:?}",
-                        self.SocketState()
-                    )
-                }
+                SocketState::Error => (),
+                _ => self.Handshake(waitinfo),
             }
         }
     }
 
     //notify rdmadatasocket to sync read buff freespace with peer
-    pub fn RDMARead(&self) {
+    pub fn RDMARead(&self, waitinfo: FdWaitInfo) {
+        // the connection fallen back reads the host socket to the freed space
+        match self.SocketState() {
+            SocketState::Tcp => {
+                self.socketBuf.GetAndClearConsumeReadData();
+                self.ReadData(waitinfo);
+                return;
+            }
+            SocketState::Error => return,
+            _ => (),
+        }
+
         let _writelock = self.writeLock.lock();
-        self.RDMASend();
+        self.RDMASend(&waitinfo);
     }
 
-    pub fn RDMAWrite(&self) {
+    pub fn RDMAWrite(&self, waitinfo: FdWaitInfo) {
+        match self.SocketState() {
+            SocketState::Tcp => {
+                self.WriteData(waitinfo);
+                return;
+            }
+            SocketState::Error => return,
+            _ => (),
+        }
+
         let _writelock = self.writeLock.lock();
-        self.RDMASend();
+        self.RDMASend(&waitinfo);
     }
 
     pub fn ReadData(&self, waitinfo: FdWaitInfo) {
@@ -666,27 +1272,13 @@ impl RDMADataSock {
         if !RDMA_ENABLE {
             self.WriteData(waitinfo);
         } else {
-            let _writelock = self.writeLock.lock();
             match self.SocketState() {
-                SocketState::Init => {
-                    self.SendLocalRDMAInfo().unwrap();
-                    self.SetSocketState(SocketState::WaitingForRemoteMeta);
-                }
-                SocketState::WaitingForRemoteMeta => {
-                    //TODO: server side received 4(W) first and 5 (R|W) afterwards. Need more investigation to see why it's different.
-                }
-                SocketState::WaitingForRemoteReady => {
-                    //TODO: server side received 4(W) first and 5 (R|W) afterwards. Need more investigation to see why it's different.
-                }
-                SocketState::Ready => {
-                    self.WriteDataLocked(waitinfo);
-                }
-                _ => {
-                    panic!(
-                        "RDMA socket Write state error with state {:?}",
-                        self.SocketState()
-                    )
+                SocketState::Ready | SocketState::Tcp => {
+                    self.WriteData(waitinfo);
                 }
+                SocketState::Error => (),
+                // the messages left of the handshake are sent when the socket is writable
+                _ => self.Handshake(waitinfo),
             }
         }
     }
@@ -706,6 +1298,11 @@ impl RDMADataSock {
         }
 
         loop {
+            // the socket is closed with SO_LINGER and the data left is dropped
+            if socketBuf.WriteAbort() {
+                return;
+            }
+
             let len = unsafe { write(fd, addr as _, count as _) };
 
             // closed
@@ -773,3 +1370,159 @@ impl RDMADataSock {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::super::qlib::kernel::waiter::*;
+
+    fn SocketPair() -> (i32, i32) {
+        let mut fds = [0i32; 2];
+        let ret = unsafe { socketpair(AF_UNIX, SOCK_STREAM | SOCK_NONBLOCK, 0, &mut fds[0]) };
+        assert_eq!(ret, 0);
+        return (fds[0], fds[1]);
+    }
+
+    fn Write(fd: i32, buf: &[u8]) {
+        let ret = unsafe { write(fd, buf.as_ptr() as _, buf.len() as _) };
+        assert_eq!(ret, buf.len() as isize);
+    }
+
+    fn Close(fd: i32) {
+        unsafe {
+            close(fd);
+        }
+    }
+
+    fn Message(kind: u32) -> Vec<u8> {
+        let mut hs = Handshake::default();
+        hs.Queue(kind, &RDMAInfo::default());
+        return hs.send;
+    }
+
+    #[test]
+    fn test_handshake_recv_partial() {
+        let (w, r) = SocketPair();
+        let msg = Message(HANDSHAKE_ACK);
+        let mut hs = Handshake::default();
+
+        assert!(hs.Recv(r).unwrap().is_none());
+        Write(w, &msg[..10]);
+        assert!(hs.Recv(r).unwrap().is_none());
+        assert_eq!(hs.recv.len(), 10);
+        Write(w, &msg[10..11]);
+        assert!(hs.Recv(r).unwrap().is_none());
+        assert_eq!(hs.recv.len(), 11);
+
+        // the rest of the message and the first bytes of the next one
+        let next = Message(HANDSHAKE_FALLBACK);
+        let mut rest = msg[11..].to_vec();
+        rest.extend_from_slice(&next[..4]);
+        Write(w, &rest);
+        assert_eq!(hs.Recv(r).unwrap().unwrap().kind, HANDSHAKE_ACK);
+        assert!(hs.recv.is_empty());
+        assert!(hs.Recv(r).unwrap().is_none());
+        assert_eq!(hs.recv.len(), 4);
+        Write(w, &next[4..]);
+        assert_eq!(hs.Recv(r).unwrap().unwrap().kind, HANDSHAKE_FALLBACK);
+
+        Close(w);
+        assert_eq!(hs.Recv(r).err(), Some(Error::SysError(SysErr::ECONNRESET)));
+        Close(r);
+    }
+
+    // a connected tcp pair over the loopback
+    fn TcpPair() -> (i32, i32) {
+        use std::os::unix::io::IntoRawFd;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.set_nonblocking(true).unwrap();
+        server.set_nonblocking(true).unwrap();
+        return (client.into_raw_fd(), server.into_raw_fd());
+    }
+
+    fn ReadErrno(fd: i32) -> i32 {
+        let mut buf = [0u8; 64];
+        let ret = unsafe { read(fd, buf.as_mut_ptr() as _, buf.len() as _) };
+        assert!(ret < 0);
+        return errno::errno().0;
+    }
+
+    #[test]
+    fn test_linger_timeout_reset() {
+        let (client, server) = TcpPair();
+        let socketBuf = Arc::new(SocketBuff::Init(2));
+        let sock = RDMADataSock::New(client, socketBuf.clone(), RDMAType::Client(0));
+        let waitinfo = FdWaitInfo::New(Queue::default(), 0);
+
+        // the linger of the close expires with the data left in the write buf
+        socketBuf.writeBuf.lock().Produce(100);
+        socketBuf.SetWriteAbort();
+
+        // neither the tcp path nor the RDMA write-imm sends the data left
+        sock.WriteData(waitinfo.clone());
+        assert_eq!(ReadErrno(server), SysErr::EAGAIN);
+        {
+            let mut remoteInfo = sock.remoteRDMAInfo.lock();
+            remoteInfo.rlen = 4096;
+            remoteInfo.freespace = 4096;
+        }
+        sock.RDMASend(&waitinfo);
+        assert!(!sock.Sending());
+        assert_eq!(sock.remoteRDMAInfo.lock().freespace, 4096);
+
+        // the zero linger of the host close resets the connection
+        let linger = libc::linger { l_onoff: 1, l_linger: 0 };
+        let ret = unsafe {
+            setsockopt(client, SOL_SOCKET, SO_LINGER, &linger as *const _ as _, mem::size_of::<libc::linger>() as _)
+        };
+        assert_eq!(ret, 0);
+        Close(client);
+        assert_eq!(ReadErrno(server), SysErr::ECONNRESET);
+        Close(server);
+    }
+
+    #[test]
+    fn test_handshake_recv_bad_magic() {
+        let (w, r) = SocketPair();
+        let mut msg = Message(HANDSHAKE_INFO);
+        msg[0] ^= 0xff;
+        Write(w, &msg);
+        let mut hs = Handshake::default();
+        assert_eq!(hs.Recv(r).err(), Some(Error::SysError(SysErr::EPROTO)));
+        Close(w);
+        Close(r);
+    }
+
+    #[test]
+    fn test_handshake_flush_partial() {
+        let (w, r) = SocketPair();
+        let mut hs = Handshake::default();
+        // more than the socket buffer, the write stops with EAGAIN
+        let total = 4 << 20;
+        hs.send = (0..total).map(|i| i as u8).collect();
+        hs.Flush(w).unwrap();
+        assert!(hs.send.len() > 0 && hs.send.len() < total);
+
+        let mut received = Vec::new();
+        let mut buf = vec![0u8; 64 << 10];
+        while received.len() < total {
+            let ret = unsafe { read(r, buf.as_mut_ptr() as _, buf.len() as _) };
+            if ret > 0 {
+                received.extend_from_slice(&buf[..ret as usize]);
+            }
+            hs.Flush(w).unwrap();
+        }
+
+        assert!(hs.send.is_empty());
+        assert!(received.iter().enumerate().all(|(i, b)| *b == i as u8));
+
+        // the peer is gone, the bytes left can't be sent
+        Close(r);
+        hs.send = vec![0; 16];
+        assert_eq!(hs.Flush(w).err(), Some(Error::SysError(SysErr::EPIPE)));
+        Close(w);
+    }
+}
@@ -0,0 +1,833 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::super::super::qlib::mutex::*;
+use alloc::sync::Arc;
+use alloc::sync::Weak;
+use core::mem;
+use core::ops::Deref;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+use libc::*;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::UdpSocket;
+use std::time::Duration;
+use std::time::Instant;
+
+use super::super::super::qlib::common::*;
+use super::super::super::qlib::dgram_buf::*;
+use super::super::super::qlib::kernel::guestfdnotifier::*;
+use super::super::super::qlib::linux_def::*;
+use super::super::super::qlib::socket_buf::*;
+use super::super::super::QUARK_CONFIG;
+use super::super::super::SHARE_SPACE;
+use super::rdma::*;
+
+lazy_static! {
+    pub static ref RDMA_UDP: RDMAUdpMgr = RDMAUdpMgr::New();
+}
+
+// the qkey of the UD queue pairs of the sandboxes
+pub const UD_QKEY: u32 = 0x5155_4450;
+pub const UD_SEND_SLOTS: usize = 64;
+pub const UD_RECV_SLOTS: usize = 256;
+// the UD receive starts with the global route header
+pub const UD_GRH_SIZE: usize = 40;
+// the slot holds the largest UD message of the 4096 mtu
+pub const UD_SLOT_SIZE: usize = 4096 + UD_GRH_SIZE;
+
+pub const UD_RESOLVE_MAGIC: u32 = 0x5155_4452;
+pub const UD_RESOLVE_REQ: u32 = 1;
+pub const UD_RESOLVE_REP: u32 = 2;
+// there is no RDMA udp socket on the port of the peer
+pub const UD_RESOLVE_NAK: u32 = 3;
+
+// the resolved peer is asked again after the ttl, the socket of the port may be gone
+pub const UD_PEER_TTL: Duration = Duration::from_secs(30);
+// the peer which doesn't answer or has no RDMA socket goes by the host socket until the ttl
+pub const UD_UNREACHABLE_TTL: Duration = Duration::from_secs(10);
+pub const UD_RESOLVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// UDHdr is put before the payload of the UD send, the receiver gets the udp source address of
+// the datagram from it
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct UDHdr {
+    pub addr: [u8; 16],
+    pub port: u16,
+    // AF_INET or AF_INET6
+    pub family: u16,
+}
+
+pub const UD_HDR_SIZE: usize = mem::size_of::<UDHdr>();
+
+impl UDHdr {
+    pub fn New(addr: &SocketAddr) -> Self {
+        let (raw, family) = match addr.ip() {
+            IpAddr::V4(a) => (a.to_ipv6_mapped().octets(), AF_INET as u16),
+            IpAddr::V6(a) => (a.octets(), AF_INET6 as u16),
+        };
+
+        return Self {
+            addr: raw,
+            port: addr.port(),
+            family: family,
+        };
+    }
+
+    pub fn SockAddr(&self) -> SocketAddr {
+        let v6 = Ipv6Addr::from(self.addr);
+        let ip = match (self.family as i32, v6.to_ipv4()) {
+            (AF_INET, Some(v4)) => IpAddr::V4(v4),
+            _ => IpAddr::V6(v6),
+        };
+
+        return SocketAddr::new(ip, self.port);
+    }
+}
+
+// UDResolveMsg is exchanged by the resolvers of the hosts on RDMAUdpResolvePort. The request
+// asks for the UD queue pair of the udp port of the peer, the reply gives it.
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug)]
+pub struct UDResolveMsg {
+    pub magic: u32,
+    pub kind: u32,
+    pub port: u16,
+    pub lid: u16,
+    pub qpn: u32,
+    pub qkey: u32,
+    pub gid: [u8; 16],
+}
+
+impl UDResolveMsg {
+    pub fn AsBytes(&self) -> &[u8] {
+        let ptr = self as *const _ as *const u8;
+        return unsafe { std::slice::from_raw_parts(ptr, mem::size_of::<Self>()) };
+    }
+
+    pub fn FromBytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < mem::size_of::<Self>() {
+            return None;
+        }
+
+        let msg = unsafe { *(buf.as_ptr() as *const Self) };
+        if msg.magic != UD_RESOLVE_MAGIC {
+            return None;
+        }
+
+        return Some(msg);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct UDPeerInfo {
+    pub qpn: u32,
+    pub qkey: u32,
+    pub lid: u16,
+    pub gid: Gid,
+}
+
+pub enum UDPeer {
+    Pending(Instant),
+    Unreachable(Instant),
+    Resolved(UDPeerInfo, Instant),
+}
+
+// RDMAUdpMgr resolves the udp addresses of the peers to their UD queue pairs. The peers are
+// asked by the resolver socket, the answers are cached with the address handles to them.
+pub struct RDMAUdpMgr {
+    pub peers: QMutex<HashMap<SocketAddr, UDPeer>>,
+    // the address handles of the (context, gid) of the peers
+    pub ahs: QMutex<HashMap<(usize, [u8; 16]), Arc<AddressHandle>>>,
+    // the RDMA udp sockets which answer the requests of the peers
+    pub socks: QMutex<Vec<Weak<RDMAUdpSockIntern>>>,
+    pub resolver: QMutex<Option<UdpSocket>>,
+    pub started: std::sync::Once,
+}
+
+impl RDMAUdpMgr {
+    pub fn New() -> Self {
+        return Self {
+            peers: QMutex::new(HashMap::new()),
+            ahs: QMutex::new(HashMap::new()),
+            socks: QMutex::new(Vec::new()),
+            resolver: QMutex::new(None),
+            started: std::sync::Once::new(),
+        };
+    }
+
+    fn Start(&'static self) {
+        self.started.call_once(|| {
+            let port = QUARK_CONFIG.lock().RDMAUdpResolvePort;
+            let sock = match UdpSocket::bind(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port)) {
+                Ok(s) => s,
+                Err(e) => {
+                    // all the datagrams go by the host sockets
+                    error!("RDMA udp resolver can't bind port {} with {:?}", port, e);
+                    return;
+                }
+            };
+
+            sock.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+            *self.resolver.lock() = Some(sock.try_clone().unwrap());
+            std::thread::Builder::new()
+                .name("rdma_udp_resolver".to_string())
+                .spawn(move || RDMA_UDP.Process(sock))
+                .unwrap();
+        });
+    }
+
+    pub fn Register(&'static self, sock: &RDMAUdpSock) {
+        self.Start();
+        let mut socks = self.socks.lock();
+        socks.retain(|s| s.strong_count() > 0);
+        socks.push(Arc::downgrade(&sock.0));
+    }
+
+    fn Lookup(&self, port: u16) -> Option<RDMAUdpSock> {
+        for s in self.socks.lock().iter() {
+            let sock = match s.upgrade() {
+                None => continue,
+                Some(s) => RDMAUdpSock(s),
+            };
+
+            if sock.fallback.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            if let Some(addr) = SockName(sock.fd) {
+                if addr.port() == port {
+                    return Some(sock);
+                }
+            }
+        }
+
+        return None;
+    }
+
+    // Peer returns the UD queue pair of the peer address, the unknown peer is resolved in the
+    // background and goes by the host socket until then
+    pub fn Peer(&self, addr: &SocketAddr) -> Option<UDPeerInfo> {
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        match peers.get(addr) {
+            Some(UDPeer::Resolved(info, expire)) if *expire > now => return Some(*info),
+            Some(UDPeer::Pending(_)) => return None,
+            Some(UDPeer::Unreachable(expire)) if *expire > now => return None,
+            _ => (),
+        }
+
+        peers.insert(*addr, UDPeer::Pending(now + UD_RESOLVE_TIMEOUT));
+        drop(peers);
+
+        let port = QUARK_CONFIG.lock().RDMAUdpResolvePort;
+        let req = UDResolveMsg {
+            magic: UD_RESOLVE_MAGIC,
+            kind: UD_RESOLVE_REQ,
+            port: addr.port(),
+            ..Default::default()
+        };
+
+        if let Some(resolver) = &*self.resolver.lock() {
+            resolver
+                .send_to(req.AsBytes(), SocketAddr::new(Mapped(addr.ip()), port))
+                .ok();
+        }
+
+        return None;
+    }
+
+    // AddressHandle returns the cached address handle of the peer in the context of the socket
+    pub fn AddressHandle(&self, endpoint: &RDMAEndpoint, peer: &UDPeerInfo) -> Result<Arc<AddressHandle>> {
+        let key = (endpoint.context as *const _ as usize, peer.gid.Raw());
+        let mut ahs = self.ahs.lock();
+        if let Some(ah) = ahs.get(&key) {
+            return Ok(ah.clone());
+        }
+
+        let ah = Arc::new(endpoint.context.CreateAddressHandle(peer.lid, peer.gid, endpoint.gidIndex)?);
+        ahs.insert(key, ah.clone());
+        return Ok(ah);
+    }
+
+    // Unreachable makes the peer go by the host socket, its UD send has failed
+    pub fn Unreachable(&self, addr: &SocketAddr) {
+        self.peers
+            .lock()
+            .insert(*addr, UDPeer::Unreachable(Instant::now() + UD_UNREACHABLE_TTL));
+    }
+
+    fn Process(&self, sock: UdpSocket) {
+        let mut buf = [0u8; 128];
+        loop {
+            match sock.recv_from(&mut buf) {
+                Ok((n, from)) => {
+                    if let Some(msg) = UDResolveMsg::FromBytes(&buf[..n]) {
+                        self.ProcessMsg(&sock, &msg, from);
+                    }
+                }
+                Err(_) => (),
+            }
+
+            self.CheckTimeouts();
+        }
+    }
+
+    fn ProcessMsg(&self, sock: &UdpSocket, msg: &UDResolveMsg, from: SocketAddr) {
+        match msg.kind {
+            UD_RESOLVE_REQ => {
+                let mut rep = UDResolveMsg {
+                    magic: UD_RESOLVE_MAGIC,
+                    kind: UD_RESOLVE_NAK,
+                    port: msg.port,
+                    ..Default::default()
+                };
+
+                if let Some(s) = self.Lookup(msg.port) {
+                    if let Some(endpoint) = &s.endpoint {
+                        rep.kind = UD_RESOLVE_REP;
+                        rep.qpn = s.qp.lock().qpNum();
+                        rep.qkey = UD_QKEY;
+                        rep.lid = endpoint.context.Lid();
+                        rep.gid = endpoint.gid.Raw();
+                    }
+                }
+
+                sock.send_to(rep.AsBytes(), from).ok();
+            }
+            UD_RESOLVE_REP | UD_RESOLVE_NAK => {
+                let addr = SocketAddr::new(Unmapped(from.ip()), msg.port);
+                let mut peers = self.peers.lock();
+                // the answer of the request which has timed out is dropped
+                match peers.get(&addr) {
+                    Some(UDPeer::Pending(_)) => (),
+                    _ => return,
+                }
+
+                let now = Instant::now();
+                let peer = if msg.kind == UD_RESOLVE_REP {
+                    let info = UDPeerInfo {
+                        qpn: msg.qpn,
+                        qkey: msg.qkey,
+                        lid: msg.lid,
+                        gid: Gid::FromRaw(msg.gid),
+                    };
+                    UDPeer::Resolved(info, now + UD_PEER_TTL)
+                } else {
+                    UDPeer::Unreachable(now + UD_UNREACHABLE_TTL)
+                };
+
+                peers.insert(addr, peer);
+            }
+            _ => (),
+        }
+    }
+
+    fn CheckTimeouts(&self) {
+        let now = Instant::now();
+        let mut peers = self.peers.lock();
+        peers.retain(|_, p| match p {
+            UDPeer::Unreachable(expire) if *expire <= now => false,
+            UDPeer::Resolved(_, expire) if *expire <= now => false,
+            _ => true,
+        });
+
+        // the peer which doesn't answer isn't asked again until the ttl
+        for p in peers.values_mut() {
+            if let UDPeer::Pending(expire) = p {
+                if *expire <= now {
+                    *p = UDPeer::Unreachable(now + UD_UNREACHABLE_TTL);
+                }
+            }
+        }
+    }
+}
+
+// the resolver socket is ipv6, the ipv4 peers are mapped
+fn Mapped(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(a) => return IpAddr::V6(a.to_ipv6_mapped()),
+        IpAddr::V6(_) => return ip,
+    }
+}
+
+fn Unmapped(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(a) => match a.to_ipv4() {
+            Some(v4) if a.segments()[5] == 0xffff => return IpAddr::V4(v4),
+            _ => return ip,
+        },
+        IpAddr::V4(_) => return ip,
+    }
+}
+
+// SockAddrFromBytes parses the linux sockaddr of the guest
+pub fn SockAddrFromBytes(addr: &[u8]) -> Option<SocketAddr> {
+    if addr.len() < 2 {
+        return None;
+    }
+
+    let family = u16::from_ne_bytes([addr[0], addr[1]]) as i32;
+    match family {
+        AF_INET if addr.len() >= mem::size_of::<sockaddr_in>() => {
+            let a = unsafe { *(addr.as_ptr() as *const sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr));
+            return Some(SocketAddr::new(IpAddr::V4(ip), u16::from_be(a.sin_port)));
+        }
+        AF_INET6 if addr.len() >= mem::size_of::<sockaddr_in6>() => {
+            let a = unsafe { *(addr.as_ptr() as *const sockaddr_in6) };
+            let ip = Ipv6Addr::from(a.sin6_addr.s6_addr);
+            return Some(SocketAddr::new(IpAddr::V6(ip), u16::from_be(a.sin6_port)));
+        }
+        _ => return None,
+    }
+}
+
+// SockAddrBytes returns the linux sockaddr of the address for the guest, the family follows
+// the one of the socket
+pub fn SockAddrBytes(addr: &SocketAddr, family: i32) -> Vec<u8> {
+    match (addr.ip(), family) {
+        (IpAddr::V4(ip), AF_INET) => {
+            let mut a: sockaddr_in = unsafe { mem::zeroed() };
+            a.sin_family = AF_INET as _;
+            a.sin_port = addr.port().to_be();
+            a.sin_addr.s_addr = u32::from(ip).to_be();
+            let ptr = &a as *const _ as *const u8;
+            return unsafe { std::slice::from_raw_parts(ptr, mem::size_of::<sockaddr_in>()) }.to_vec();
+        }
+        (ip, _) => {
+            let mut a: sockaddr_in6 = unsafe { mem::zeroed() };
+            a.sin6_family = AF_INET6 as _;
+            a.sin6_port = addr.port().to_be();
+            a.sin6_addr.s6_addr = match Mapped(ip) {
+                IpAddr::V6(v6) => v6.octets(),
+                IpAddr::V4(v4) => v4.to_ipv6_mapped().octets(),
+            };
+            let ptr = &a as *const _ as *const u8;
+            return unsafe { std::slice::from_raw_parts(ptr, mem::size_of::<sockaddr_in6>()) }.to_vec();
+        }
+    }
+}
+
+fn ErrnoOf(e: &Error) -> i32 {
+    match e {
+        Error::SysError(errno) => return *errno,
+        _ => return SysErr::EIO,
+    }
+}
+
+fn SockName(fd: i32) -> Option<SocketAddr> {
+    let mut addr: sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+    let ret = unsafe { getsockname(fd, &mut addr as *mut _ as *mut sockaddr, &mut len) };
+    if ret < 0 {
+        return None;
+    }
+
+    let ptr = &addr as *const _ as *const u8;
+    return SockAddrFromBytes(unsafe { std::slice::from_raw_parts(ptr, len as usize) });
+}
+
+fn PeerName(fd: i32) -> Option<SocketAddr> {
+    let mut addr: sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+    let ret = unsafe { getpeername(fd, &mut addr as *mut _ as *mut sockaddr, &mut len) };
+    if ret < 0 {
+        return None;
+    }
+
+    let ptr = &addr as *const _ as *const u8;
+    return SockAddrFromBytes(unsafe { std::slice::from_raw_parts(ptr, len as usize) });
+}
+
+// UDSlab is the registered buffer of the send or recv slots of the UD queue pair
+pub struct UDSlab {
+    pub buf: Vec<u8>,
+    pub mr: MemoryRegion,
+}
+
+impl UDSlab {
+    pub fn New(context: &RDMAContext, slots: usize) -> Result<Self> {
+        let buf = vec![0u8; slots * UD_SLOT_SIZE];
+        // the datagrams are copied in and out of the slots, the peers don't access them
+        let mr = context.CreateLocalMemoryRegion(buf.as_ptr() as u64, buf.len())?;
+        return Ok(Self { buf: buf, mr: mr });
+    }
+
+    pub fn Addr(&self, slot: u32) -> u64 {
+        return self.buf.as_ptr() as u64 + slot as u64 * UD_SLOT_SIZE as u64;
+    }
+
+    pub fn Slot(&self, slot: u32) -> &mut [u8] {
+        let ptr = self.Addr(slot) as *mut u8;
+        return unsafe { std::slice::from_raw_parts_mut(ptr, UD_SLOT_SIZE) };
+    }
+}
+
+pub struct RDMAUdpSockIntern {
+    pub fd: i32,
+    pub socketBuf: Arc<SocketBuff>,
+    pub endpoint: Option<RDMAEndpoint>,
+    pub qp: QMutex<QueuePair>,
+    pub sendSlab: Option<UDSlab>,
+    pub recvSlab: Option<UDSlab>,
+    pub freeSlots: QMutex<Vec<u32>>,
+    // the datagram which can't be sent for now, it is sent before the ones of the write buf
+    pub pending: QMutex<Option<Datagram>>,
+    pub writeLock: QMutex<()>,
+    // the UD queue pair has failed, all the datagrams go by the host socket
+    pub fallback: AtomicBool,
+    pub family: i32,
+}
+
+// RDMAUdpSock is the udp socket whose datagrams bypass the host network stack by the UD queue
+// pair. The datagrams of the guest are records in the write buf of the socket buf, the ones to
+// the peers which have a RDMA udp socket go by the UD sends and the others by the host socket.
+// The datagrams received by both are written to the read buf.
+#[derive(Clone)]
+pub struct RDMAUdpSock(Arc<RDMAUdpSockIntern>);
+
+impl Deref for RDMAUdpSock {
+    type Target = Arc<RDMAUdpSockIntern>;
+
+    fn deref(&self) -> &Arc<RDMAUdpSockIntern> {
+        &self.0
+    }
+}
+
+impl RDMAUdpSock {
+    pub fn New(fd: i32, socketBuf: Arc<SocketBuff>) -> Self {
+        let res = if RDMA_ENABLE {
+            match Self::NewRDMA(fd) {
+                Ok(res) => Some(res),
+                Err(e) => {
+                    error!("RDMA resources of udp fd {} can't be created with {:?}, fall back to host", fd, e);
+                    SHARE_SPACE.sockStats.RDMAFallback();
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let family = match SockName(fd) {
+            Some(SocketAddr::V4(_)) => AF_INET,
+            _ => AF_INET6,
+        };
+
+        let (endpoint, qp, sendSlab, recvSlab) = match res {
+            Some((endpoint, qp, sendSlab, recvSlab)) => {
+                (Some(endpoint), qp, Some(sendSlab), Some(recvSlab))
+            }
+            None => (None, QueuePair::default(), None, None),
+        };
+
+        let sock = Self(Arc::new(RDMAUdpSockIntern {
+            fd: fd,
+            socketBuf: socketBuf,
+            fallback: AtomicBool::new(endpoint.is_none()),
+            endpoint: endpoint,
+            qp: QMutex::new(qp),
+            sendSlab: sendSlab,
+            recvSlab: recvSlab,
+            freeSlots: QMutex::new((0..UD_SEND_SLOTS as u32).collect()),
+            pending: QMutex::new(None),
+            writeLock: QMutex::new(()),
+            family: family,
+        }));
+
+        if !sock.fallback.load(Ordering::Relaxed) {
+            match sock.PostRecvs() {
+                Ok(()) => RDMA_UDP.Register(&sock),
+                Err(e) => {
+                    error!("RDMA udp fd {} can't post the recvs with {:?}, fall back to host", fd, e);
+                    sock.FallBack();
+                }
+            }
+        }
+
+        return sock;
+    }
+
+    fn NewRDMA(fd: i32) -> Result<(RDMAEndpoint, QueuePair, UDSlab, UDSlab)> {
+        let endpoint = match RDMA_DEVICES.Select(fd) {
+            None => return Err(Error::SysError(SysErr::ENODEV)),
+            Some(e) => e,
+        };
+
+        let context = endpoint.context;
+        let qp = context.CreateUDQueuePair(UD_SEND_SLOTS as u32, UD_RECV_SLOTS as u32)?;
+        qp.SetupUD(context, UD_QKEY)?;
+        let sendSlab = UDSlab::New(context, UD_SEND_SLOTS)?;
+        let recvSlab = UDSlab::New(context, UD_RECV_SLOTS)?;
+        return Ok((endpoint, qp, sendSlab, recvSlab));
+    }
+
+    fn PostRecvs(&self) -> Result<()> {
+        for slot in 0..UD_RECV_SLOTS as u32 {
+            self.PostRecv(slot)?;
+        }
+
+        return Ok(());
+    }
+
+    fn PostRecv(&self, slot: u32) -> Result<()> {
+        let slab = self.recvSlab.as_ref().unwrap();
+        return self.qp.lock().PostRecvBuf(
+            WorkRequestId::NewSlot(self.fd, slot).0,
+            slab.Addr(slot),
+            UD_SLOT_SIZE as u32,
+            slab.mr.LKey(),
+        );
+    }
+
+    // MaxPayload is the largest datagram which fits in one UD message of the port
+    fn MaxPayload(&self) -> usize {
+        match &self.endpoint {
+            None => return 0,
+            Some(e) => return e.context.ActiveMtu() - UD_HDR_SIZE,
+        }
+    }
+
+    fn FallBack(&self) {
+        self.fallback.store(true, Ordering::Relaxed);
+        *self.qp.lock() = QueuePair::default();
+        SHARE_SPACE.sockStats.RDMAFallback();
+    }
+
+    // RDMAFail falls the socket back to the host socket. Unlike the tcp one the socket goes on,
+    // the datagrams in flight are lost as the udp ones may be. The peers find the UD queue pair
+    // gone when their cached entries expire.
+    pub fn RDMAFail(&self, errno: i32, waitinfo: &FdWaitInfo) {
+        if self.fallback.load(Ordering::Relaxed) {
+            return;
+        }
+
+        error!("RDMA of udp fd {} fails with errno {}, fall back to host", self.fd, errno);
+        RDMAStats::Inc(&self.socketBuf.rdmaStats.qpErrors);
+        self.FallBack();
+        self.WriteData(waitinfo.clone());
+    }
+
+    pub fn ProcessUDSendFinish(&self, slot: u32, waitinfo: FdWaitInfo) {
+        RDMAStats::Inc(&self.socketBuf.rdmaStats.writeCompletions);
+        self.freeSlots.lock().push(slot);
+        self.WriteData(waitinfo);
+    }
+
+    pub fn ProcessUDRecv(&self, slot: u32, len: u32, waitinfo: FdWaitInfo) {
+        if self.fallback.load(Ordering::Relaxed) {
+            return;
+        }
+
+        RDMAStats::Inc(&self.socketBuf.rdmaStats.recvCompletions);
+        let slab = self.recvSlab.as_ref().unwrap();
+        let buf = slab.Slot(slot);
+        let len = len as usize;
+        if len >= UD_GRH_SIZE + UD_HDR_SIZE {
+            let hdr = unsafe { *(buf[UD_GRH_SIZE..].as_ptr() as *const UDHdr) };
+            let addr = SockAddrBytes(&hdr.SockAddr(), self.family);
+            let data = &buf[UD_GRH_SIZE + UD_HDR_SIZE..len];
+            // the datagram is dropped when the read buf is full, as the host socket does
+            if let Ok(true) = PushDgram(&mut self.socketBuf.readBuf.lock(), &addr, data) {
+                waitinfo.Notify(EVENT_IN);
+            }
+        }
+
+        if let Err(e) = self.PostRecv(slot) {
+            self.RDMAFail(ErrnoOf(&e), &waitinfo);
+        }
+    }
+
+    // ReadData moves the datagrams of the host socket to the read buf
+    pub fn ReadData(&self, waitinfo: FdWaitInfo) {
+        let mut buf = vec![0u8; DGRAM_MAX_SIZE];
+        let mut notify = false;
+        loop {
+            let mut addr: sockaddr_storage = unsafe { mem::zeroed() };
+            let mut len = mem::size_of::<sockaddr_storage>() as socklen_t;
+            let ret = unsafe {
+                recvfrom(
+                    self.fd,
+                    buf.as_mut_ptr() as *mut c_void,
+                    buf.len(),
+                    MSG_DONTWAIT,
+                    &mut addr as *mut _ as *mut sockaddr,
+                    &mut len,
+                )
+            };
+
+            if ret < 0 {
+                let errno = errno::errno().0;
+                if errno != SysErr::EAGAIN {
+                    self.socketBuf.SetErr(errno);
+                    waitinfo.Notify(EVENT_ERR | EVENT_IN);
+                }
+                break;
+            }
+
+            let ptr = &addr as *const _ as *const u8;
+            let addr = unsafe { std::slice::from_raw_parts(ptr, len as usize) };
+            if let Ok(true) = PushDgram(&mut self.socketBuf.readBuf.lock(), addr, &buf[..ret as usize]) {
+                notify = true;
+            }
+        }
+
+        if notify {
+            waitinfo.Notify(EVENT_IN);
+        }
+    }
+
+    // WriteData sends the datagrams of the write buf until it is empty or the sends block
+    pub fn WriteData(&self, waitinfo: FdWaitInfo) {
+        let _writeLock = self.writeLock.lock();
+        let mut sent = false;
+        loop {
+            let pending = self.pending.lock().take();
+            let dgram = match pending {
+                Some(d) => d,
+                None => match PopDgram(&mut self.socketBuf.writeBuf.lock()) {
+                    None => break,
+                    Some(d) => d,
+                },
+            };
+
+            match self.Send(&dgram, &waitinfo) {
+                Ok(()) => sent = true,
+                Err(Error::SysError(SysErr::EAGAIN)) => {
+                    // it goes on with the EVENT_WRITE of the host socket or the send completion
+                    *self.pending.lock() = Some(dgram);
+                    break;
+                }
+                Err(e) => {
+                    self.socketBuf.SetErr(ErrnoOf(&e));
+                    waitinfo.Notify(EVENT_ERR | EVENT_IN);
+                }
+            }
+        }
+
+        if sent {
+            waitinfo.Notify(EVENT_OUT);
+        }
+    }
+
+    fn Send(&self, dgram: &Datagram, waitinfo: &FdWaitInfo) -> Result<()> {
+        let addr = if dgram.addr.len() > 0 {
+            SockAddrFromBytes(&dgram.addr)
+        } else {
+            PeerName(self.fd)
+        };
+
+        if let Some(addr) = addr {
+            if !self.fallback.load(Ordering::Relaxed) && dgram.data.len() <= self.MaxPayload() {
+                if let Some(peer) = RDMA_UDP.Peer(&addr) {
+                    return self.SendUD(dgram, &addr, &peer, waitinfo);
+                }
+            }
+        }
+
+        return self.SendHost(dgram);
+    }
+
+    fn SendUD(&self, dgram: &Datagram, addr: &SocketAddr, peer: &UDPeerInfo, waitinfo: &FdWaitInfo) -> Result<()> {
+        let endpoint = self.endpoint.as_ref().unwrap();
+        let ah = match RDMA_UDP.AddressHandle(endpoint, peer) {
+            Ok(ah) => ah,
+            Err(e) => {
+                error!("RDMA udp fd {} can't reach {:?} with {:?}", self.fd, addr, e);
+                RDMA_UDP.Unreachable(addr);
+                return self.SendHost(dgram);
+            }
+        };
+
+        let slot = match self.freeSlots.lock().pop() {
+            None => return Err(Error::SysError(SysErr::EAGAIN)),
+            Some(s) => s,
+        };
+
+        // the source address of the datagram is the one of the socket on the gid of the port
+        let port = SockName(self.fd).map(|a| a.port()).unwrap_or(0);
+        let gid = Ipv6Addr::from(endpoint.gid.Raw());
+        let src = match gid.to_ipv4() {
+            Some(v4) if gid.segments()[5] == 0xffff => SocketAddr::new(IpAddr::V4(v4), port),
+            _ => SocketAddr::new(IpAddr::V6(gid), port),
+        };
+
+        let slab = self.sendSlab.as_ref().unwrap();
+        let buf = slab.Slot(slot);
+        let hdr = UDHdr::New(&src);
+        let hdrBytes = unsafe { std::slice::from_raw_parts(&hdr as *const _ as *const u8, UD_HDR_SIZE) };
+        buf[..UD_HDR_SIZE].copy_from_slice(hdrBytes);
+        buf[UD_HDR_SIZE..UD_HDR_SIZE + dgram.data.len()].copy_from_slice(&dgram.data);
+
+        let res = self.qp.lock().PostSendUD(
+            WorkRequestId::NewSlot(self.fd, slot).0,
+            slab.Addr(slot),
+            (UD_HDR_SIZE + dgram.data.len()) as u32,
+            slab.mr.LKey(),
+            &ah,
+            peer.qpn,
+            peer.qkey,
+        );
+
+        if let Err(e) = res {
+            self.freeSlots.lock().push(slot);
+            self.RDMAFail(ErrnoOf(&e), waitinfo);
+            return self.SendHost(dgram);
+        }
+
+        self.socketBuf.rdmaStats.Write(1, dgram.data.len());
+        return Ok(());
+    }
+
+    fn SendHost(&self, dgram: &Datagram) -> Result<()> {
+        let (addr, len) = if dgram.addr.len() > 0 {
+            (dgram.addr.as_ptr() as *const sockaddr, dgram.addr.len() as socklen_t)
+        } else {
+            (0 as *const sockaddr, 0)
+        };
+
+        let ret = unsafe {
+            sendto(
+                self.fd,
+                dgram.data.as_ptr() as *const c_void,
+                dgram.data.len(),
+                MSG_DONTWAIT,
+                addr,
+                len,
+            )
+        };
+
+        if ret < 0 {
+            return Err(Error::SysError(errno::errno().0));
+        }
+
+        return Ok(());
+    }
+
+    pub fn Notify(&self, eventmask: EventMask, waitinfo: FdWaitInfo) {
+        if eventmask & EVENT_WRITE != 0 {
+            self.WriteData(waitinfo.clone());
+        }
+
+        if eventmask & EVENT_READ != 0 {
+            self.ReadData(waitinfo);
+        }
+    }
+}
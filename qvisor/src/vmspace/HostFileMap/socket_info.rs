@@ -1,6 +1,9 @@
 use core::fmt;
 
-//use super::rdma_socket::*;
+#[cfg(feature = "rdma")]
+use super::rdma_socket::*;
+#[cfg(feature = "rdma")]
+use super::rdma_udp::*;
 use super::super::super::qlib::linux_def::*;
 use super::super::super::qlib::kernel::guestfdnotifier::*;
 
@@ -8,9 +11,14 @@ use super::super::super::qlib::kernel::guestfdnotifier::*;
 pub enum SockInfo {
     File, // it is not socket
     Socket, // normal socket
-    //RDMAServerSocket(RDMAServerSock), //
-    //RDMADataSocket(RDMADataSock), //
-    //RDMAContext,
+    #[cfg(feature = "rdma")]
+    RDMAServerSocket(RDMAServerSock), //
+    #[cfg(feature = "rdma")]
+    RDMADataSocket(RDMADataSock), //
+    #[cfg(feature = "rdma")]
+    RDMAUdpSocket(RDMAUdpSock), //
+    #[cfg(feature = "rdma")]
+    RDMAContext,
 }
 
 impl fmt::Debug for SockInfo {
@@ -18,9 +26,14 @@ impl fmt::Debug for SockInfo {
         match self {
             Self::File => write!(f, "SockInfo::File"),
             Self::Socket => write!(f, "SockInfo::Socket"),
-            //Self::RDMAServerSocket(_) => write!(f, "SockInfo::RDMAServerSocket"),
-            //Self::RDMADataSocket(_) => write!(f, "SockInfo::RDMADataSocket"),
-            //Self::RDMAContext => write!(f, "SockInfo::RDMAContext"),
+            #[cfg(feature = "rdma")]
+            Self::RDMAServerSocket(_) => write!(f, "SockInfo::RDMAServerSocket"),
+            #[cfg(feature = "rdma")]
+            Self::RDMADataSocket(_) => write!(f, "SockInfo::RDMADataSocket"),
+            #[cfg(feature = "rdma")]
+            Self::RDMAUdpSocket(_) => write!(f, "SockInfo::RDMAUdpSocket"),
+            #[cfg(feature = "rdma")]
+            Self::RDMAContext => write!(f, "SockInfo::RDMAContext"),
         }
     }
 }
@@ -34,16 +47,23 @@ impl SockInfo {
             Self::Socket => {
                 waitinfo.Notify(eventmask);
             }
-            /*Self::RDMAServerSocket(ref sock) => {
+            #[cfg(feature = "rdma")]
+            Self::RDMAServerSocket(ref sock) => {
                 sock.Notify(eventmask, waitinfo)
             }
+            #[cfg(feature = "rdma")]
             Self::RDMADataSocket(ref sock) => {
                 sock.Notify(eventmask, waitinfo)
             }
+            #[cfg(feature = "rdma")]
+            Self::RDMAUdpSocket(ref sock) => {
+                sock.Notify(eventmask, waitinfo)
+            }
+            #[cfg(feature = "rdma")]
             Self::RDMAContext => {
                 //RDMA.PollCompletion().expect("RDMA.PollCompletion fail");
                 //error!("RDMAContextEpoll");
-            }*/
+            }
         }
     }
 }
@@ -0,0 +1,358 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::btree_set::BTreeSet;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::vec::Vec;
+use spin::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use libc::*;
+
+use super::super::runc::runtime::vm::IsRunning;
+use super::super::QUARK_CONFIG;
+use super::HostFileMap::fdinfo::*;
+
+pub static CONN_POOL: ConnPool = ConnPool::New();
+
+// max destinations the pool keeps connections for
+pub const CONN_POOL_MAX_DESTS: usize = 64;
+// the pool thread refills the pools every CONN_POOL_TICK ms
+pub const CONN_POOL_TICK: u64 = 100;
+// timeout in ms of the pre connect
+pub const CONN_POOL_CONNECT_TIMEOUT: i32 = 1000;
+
+// ConnPool keeps pre-established host TCP connections to the destinations the guest connected
+// to recently, the connect of the guest gets one of them instead of doing the handshake. A
+// destination is warm for ConnPoolIdleTimeout seconds after the last guest connect to it.
+// Only the sockets the guest hasn't bound or set any option on are served from the pool, so the
+// guest can't tell the pooled connection from its own one, except for the local port.
+pub struct ConnPool {
+    dests: Mutex<Option<BTreeMap<Vec<u8>, PoolDest>>>,
+    // the hostfds on which the guest called bind or setsockopt
+    pinned: Mutex<Option<BTreeSet<i32>>>,
+}
+
+pub struct PoolDest {
+    pub lastUse: Instant,
+    // osfd and connect time of the idle connections
+    pub idle: VecDeque<(i32, Instant)>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Drop for PoolDest {
+    fn drop(&mut self) {
+        for (fd, _) in &self.idle {
+            unsafe {
+                close(*fd);
+            }
+        }
+    }
+}
+
+impl ConnPool {
+    pub const fn New() -> Self {
+        return Self {
+            dests: Mutex::new(None),
+            pinned: Mutex::new(None),
+        }
+    }
+
+    pub fn Enabled() -> bool {
+        return QUARK_CONFIG.lock().ConnPoolSize > 0
+    }
+
+    // DestKey returns the pool key of the inet sockaddr: family, port and address
+    pub fn DestKey(addr: u64, addrlen: u32) -> Option<Vec<u8>> {
+        if addrlen < 2 {
+            return None
+        }
+
+        let buf = unsafe {
+            core::slice::from_raw_parts(addr as *const u8, addrlen as usize)
+        };
+
+        let family = buf[0] as i32 | (buf[1] as i32) << 8;
+        let len = if family == AF_INET {
+            8
+        } else if family == AF_INET6 {
+            24
+        } else {
+            return None
+        };
+
+        if buf.len() < len {
+            return None
+        }
+
+        return Some(buf[..len].to_vec())
+    }
+
+    pub fn Pin(&self, hostfd: i32) {
+        if !Self::Enabled() {
+            return
+        }
+
+        self.pinned.lock().get_or_insert_with(BTreeSet::new).insert(hostfd);
+    }
+
+    // Forget is called when the hostfd is closed
+    pub fn Forget(&self, hostfd: i32) {
+        if let Some(pinned) = self.pinned.lock().as_mut() {
+            pinned.remove(&hostfd);
+        }
+    }
+
+    fn GetSockOptInt(osfd: i32, level: i32, name: i32) -> Option<i32> {
+        let mut val: i32 = 0;
+        let mut len = 4 as socklen_t;
+        let ret = unsafe {
+            getsockopt(osfd, level, name, &mut val as *mut _ as *mut c_void, &mut len)
+        };
+
+        if ret < 0 {
+            return None
+        }
+
+        return Some(val)
+    }
+
+    pub fn IsTcp(osfd: i32, family: i32) -> bool {
+        return Self::GetSockOptInt(osfd, SOL_SOCKET, SO_DOMAIN) == Some(family) &&
+            Self::GetSockOptInt(osfd, SOL_SOCKET, SO_TYPE) == Some(SOCK_STREAM) &&
+            Self::GetSockOptInt(osfd, SOL_SOCKET, SO_PROTOCOL) == Some(IPPROTO_TCP)
+    }
+
+    // Eligible returns whether the socket is a fresh tcp socket of the family
+    fn Eligible(osfd: i32, family: i32) -> bool {
+        if !Self::IsTcp(osfd, family) {
+            return false
+        }
+
+        // the unbound socket has port 0, sin_port and sin6_port are at the same offset
+        let mut addr = [0u8; 28];
+        let mut len = addr.len() as socklen_t;
+        let ret = unsafe {
+            getsockname(osfd, &mut addr[0] as *mut _ as *mut sockaddr, &mut len)
+        };
+
+        return ret == 0 && addr[2] == 0 && addr[3] == 0
+    }
+
+    // Alive returns whether the peer hasn't closed or reset the idle connection. The data
+    // sent by the server first, e.g. a greeting, is left for the guest.
+    fn Alive(osfd: i32) -> bool {
+        let mut pfd = pollfd {
+            fd: osfd,
+            events: POLLIN | POLLRDHUP,
+            revents: 0,
+        };
+
+        let ret = unsafe {
+            poll(&mut pfd, 1, 0)
+        };
+
+        return ret == 0 || (ret == 1 && pfd.revents & (POLLRDHUP | POLLHUP | POLLERR) == 0)
+    }
+
+    // Take returns an idle connection to the destination and marks the destination as warm
+    fn Take(&self, key: &[u8]) -> Option<i32> {
+        let ttl = Duration::from_secs(QUARK_CONFIG.lock().ConnPoolIdleTimeout);
+        let mut dests = self.dests.lock();
+        let dests = dests.get_or_insert_with(BTreeMap::new);
+        if !dests.contains_key(key) {
+            if dests.len() >= CONN_POOL_MAX_DESTS {
+                return None
+            }
+
+            dests.insert(key.to_vec(), PoolDest {
+                lastUse: Instant::now(),
+                idle: VecDeque::new(),
+                hits: 0,
+                misses: 0,
+            });
+        }
+
+        let dest = dests.get_mut(key).unwrap();
+        dest.lastUse = Instant::now();
+        while let Some((fd, created)) = dest.idle.pop_front() {
+            if created.elapsed() < ttl && Self::Alive(fd) {
+                dest.hits += 1;
+                return Some(fd)
+            }
+
+            unsafe {
+                close(fd);
+            }
+        }
+
+        dest.misses += 1;
+        return None
+    }
+
+    // Connect hands an idle connection to the guest socket, it returns None when the socket is
+    // connected by the host as usual
+    pub fn Connect(&self, hostfd: i32, fdInfo: &FdInfo, addr: u64, addrlen: u32) -> Option<i64> {
+        if !Self::Enabled() {
+            return None
+        }
+
+        let key = Self::DestKey(addr, addrlen)?;
+        if let Some(pinned) = self.pinned.lock().as_ref() {
+            if pinned.contains(&hostfd) {
+                return None
+            }
+        }
+
+        let family = key[0] as i32 | (key[1] as i32) << 8;
+        if !Self::Eligible(fdInfo.Fd(), family) {
+            return None
+        }
+
+        let pooled = self.Take(&key)?;
+
+        // the pooled connection takes over the osfd, on failure the old socket is still there and
+        // is connected as usual
+        if fdInfo.ReplaceFd(pooled) < 0 {
+            return None
+        }
+
+        return Some(0)
+    }
+
+    // NewConn connects a nonblocking socket to the destination
+    fn NewConn(addr: &[u8]) -> Option<i32> {
+        let family = addr[0] as i32 | (addr[1] as i32) << 8;
+        let fd = unsafe {
+            socket(family, SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC, IPPROTO_TCP)
+        };
+
+        if fd < 0 {
+            return None
+        }
+
+        let mut sa = [0u8; 28];
+        sa[..addr.len()].copy_from_slice(addr);
+        let len = if family == AF_INET { 16 } else { 28 };
+        let ret = unsafe {
+            connect(fd, &sa[0] as *const _ as *const sockaddr, len)
+        };
+
+        let connected = if ret == 0 {
+            true
+        } else if errno::errno().0 == EINPROGRESS {
+            let mut pfd = pollfd {
+                fd: fd,
+                events: POLLOUT,
+                revents: 0,
+            };
+
+            let ret = unsafe {
+                poll(&mut pfd, 1, CONN_POOL_CONNECT_TIMEOUT)
+            };
+
+            ret == 1 && Self::GetSockOptInt(fd, SOL_SOCKET, SO_ERROR) == Some(0)
+        } else {
+            false
+        };
+
+        if !connected {
+            unsafe {
+                close(fd);
+            }
+            return None
+        }
+
+        return Some(fd)
+    }
+
+    // Refill drops the expired connections and destinations and returns the destinations which
+    // need more connections
+    fn Refill(&self, size: usize, ttl: Duration) -> Vec<(Vec<u8>, usize)> {
+        let mut ret = Vec::new();
+        let mut dests = self.dests.lock();
+        let dests = match dests.as_mut() {
+            None => return ret,
+            Some(d) => d,
+        };
+
+        dests.retain(|key, d| {
+            if d.lastUse.elapsed() < ttl {
+                return true
+            }
+
+            info!("ConnPool: destination {:x?} expires, hits {} misses {}", key, d.hits, d.misses);
+            false
+        });
+        for (key, dest) in dests.iter_mut() {
+            dest.idle.retain(|(fd, created)| {
+                if created.elapsed() < ttl && Self::Alive(*fd) {
+                    return true
+                }
+
+                unsafe {
+                    close(*fd);
+                }
+                false
+            });
+
+            if dest.idle.len() < size {
+                ret.push((key.clone(), size - dest.idle.len()));
+            }
+        }
+
+        return ret
+    }
+
+    fn Put(&self, key: &[u8], fd: i32) {
+        let mut dests = self.dests.lock();
+        match dests.as_mut().and_then(|d| d.get_mut(key)) {
+            Some(dest) => dest.idle.push_back((fd, Instant::now())),
+            // the destination expired while connecting
+            None => unsafe {
+                close(fd);
+            },
+        }
+    }
+
+    // Run is the pool thread, it keeps ConnPoolSize idle connections to each warm destination
+    pub fn Run(&self) {
+        if !Self::Enabled() {
+            return
+        }
+
+        while IsRunning() {
+            thread::sleep(Duration::from_millis(CONN_POOL_TICK));
+
+            let (size, ttl) = {
+                let config = QUARK_CONFIG.lock();
+                (config.ConnPoolSize as usize, Duration::from_secs(config.ConnPoolIdleTimeout))
+            };
+
+            for (key, cnt) in self.Refill(size, ttl) {
+                for _ in 0..cnt {
+                    match Self::NewConn(&key) {
+                        None => break,
+                        Some(fd) => self.Put(&key, fd),
+                    }
+                }
+            }
+        }
+
+        *self.dests.lock() = None;
+    }
+}
@@ -0,0 +1,80 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs;
+
+use super::super::qlib::config::*;
+use super::super::QUARK_CONFIG;
+
+const CPU_DIR: &str = "/sys/devices/system/cpu";
+
+// HostPowerSave returns whether all the host cpus with cpufreq run the powersave governor
+pub fn HostPowerSave() -> bool {
+    let entries = match fs::read_dir(CPU_DIR) {
+        Err(_) => return false,
+        Ok(entries) => entries,
+    };
+
+    let mut governors = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        // cpu0, cpu1, ... but not cpufreq and cpuidle
+        if name.len() <= 3 || !name.starts_with("cpu") || !name[3..].chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        if let Ok(g) = fs::read_to_string(entry.path().join("cpufreq/scaling_governor")) {
+            governors.push(g.trim().to_string());
+        }
+    }
+
+    return governors.len() > 0 && governors.iter().all(|g| g == "powersave")
+}
+
+// HostInvariantTsc returns whether the host TSC runs at a constant rate in all the cpu states
+pub fn HostInvariantTsc() -> bool {
+    let cpuinfo = match fs::read_to_string("/proc/cpuinfo") {
+        Err(_) => return false,
+        Ok(s) => s,
+    };
+
+    for line in cpuinfo.lines() {
+        if line.starts_with("flags") {
+            let flags: Vec<&str> = line.split_whitespace().collect();
+            return flags.contains(&"constant_tsc") && flags.contains(&"nonstop_tsc")
+        }
+    }
+
+    return false
+}
+
+// ResolvePowerSave turns the Auto power save mode to On or Off by the host governor, it is
+// called before the config is copied to the share space
+pub fn ResolvePowerSave() {
+    let mut config = QUARK_CONFIG.lock();
+    if config.PowerSave == PowerSaveMode::Auto {
+        config.PowerSave = if HostPowerSave() {
+            PowerSaveMode::On
+        } else {
+            PowerSaveMode::Off
+        };
+    }
+
+    if !HostInvariantTsc() {
+        info!("cpufreq: the host TSC is not invariant");
+    }
+
+    info!("cpufreq: power save mode {:?}, io spin {}ns, vcpu spin {}ns",
+          config.PowerSave, config.IOSpinNs(), config.VcpuSpinNs());
+}
@@ -0,0 +1,1519 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::Weak;
+use std::thread;
+use std::time::Duration;
+
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::mutex::QMutex;
+use super::super::qlib::uring::sys::sys::*;
+use super::super::qlib::uring::util::{Fd, Mmap};
+use super::super::qlib::uring::*;
+use super::super::*;
+use super::io_backend::*;
+
+// max threads per host cpu running the requests on the blocking fds, i.e. the regular files.
+// The pool grows when there is no idle worker so that the requests don't wait for the blocked
+// ones, the first worker stays and the others exit after WORKER_IDLE_MS without the request.
+const WORKERS_PER_CPU: usize = 4;
+const WORKER_IDLE_MS: u64 = 10_000;
+// epoll data of the wake eventfd, the others are the host fds
+const WAKE_KEY: u64 = u64::MAX;
+// max time in ms the completions stay in the backlog when the guest cq is full
+const BACKLOG_RETRY_MS: i32 = 1;
+// interval in ms to interrupt again the canceled ops still running
+const INTERRUPT_RETRY_MS: i32 = 10;
+
+// layout of the emulated ring memory: the sq and cq headers, the cqes and then the sq array
+const SQ_HDR_OFF: u32 = 0;
+const CQ_HDR_OFF: u32 = 64;
+const CQES_OFF: u32 = 128;
+
+const SQE_SIZE: usize = core::mem::size_of::<io_uring_sqe>();
+const CQE_SIZE: usize = core::mem::size_of::<io_uring_cqe>();
+
+// EmuRing is the host side of an emulated ring, the addresses point into the ring memory shared
+// with the guest. The sq is consumed in Enter and the cq is filled by Post.
+struct EmuRing {
+    sqHead: u64,
+    sqTail: u64,
+    sqMask: u32,
+    sqes: u64,
+    cqHead: u64,
+    cqTail: u64,
+    cqMask: u32,
+    cqEntries: u32,
+    cqes: u64,
+    eventfd: i32,
+    // the completions which don't fit in the cq, they are posted when the guest frees the slots
+    backlog: VecDeque<io_uring_cqe>,
+}
+
+impl EmuRing {
+    fn CqLen(&self) -> u32 {
+        let head = unsafe { (*(self.cqHead as * const AtomicU32)).load(Ordering::Acquire) };
+        let tail = unsafe { (*(self.cqTail as * const AtomicU32)).load(Ordering::Relaxed) };
+        return tail.wrapping_sub(head)
+    }
+
+    // Flush moves the backlog to the cq as long as there is free slot, it returns the posted count
+    fn Flush(&mut self) -> usize {
+        let mut cnt = 0;
+        let mut tail = unsafe { (*(self.cqTail as * const AtomicU32)).load(Ordering::Relaxed) };
+        while self.backlog.len() > 0 && self.CqLen() < self.cqEntries {
+            let cqe = self.backlog.pop_front().unwrap();
+            unsafe {
+                let slot = (self.cqes + ((tail & self.cqMask) as usize * CQE_SIZE) as u64) as * mut io_uring_cqe;
+                slot.write_volatile(cqe);
+            }
+
+            tail = tail.wrapping_add(1);
+            unsafe {
+                (*(self.cqTail as * const AtomicU32)).store(tail, Ordering::Release);
+            }
+            cnt += 1;
+        }
+
+        return cnt
+    }
+}
+
+struct RingState {
+    emu: Mutex<EmuRing>,
+    // serializes the sq consumers
+    submit: Mutex<()>,
+    // signaled when new completions are posted, for the Enter waiting for completions
+    cond: Condvar,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OpState {
+    // waiting for the fd readiness in the epoll
+    Parked,
+    // waiting for the deadline, TIMEOUT and LINK_TIMEOUT
+    Timer,
+    // waiting for the worker in the pool
+    Queued,
+    // issued inline or running in the worker, the worker is interrupted to cancel it
+    Running,
+}
+
+// Link is an sqe of the IO_LINK chain with the LINK_TIMEOUT following it
+#[derive(Clone, Copy)]
+struct Link {
+    sqe: io_uring_sqe,
+    lt: Option<io_uring_sqe>,
+}
+
+// Chain is the rest of the IO_LINK chain, it is submitted when the op before it completes
+type Chain = VecDeque<Link>;
+
+fn Linked(sqe: &io_uring_sqe) -> bool {
+    return sqe.flags as u32 & ((1 << IOSQE_IO_LINK_BIT) | (1 << IOSQE_IO_HARDLINK_BIT)) != 0
+}
+
+fn HardLinked(sqe: &io_uring_sqe) -> bool {
+    return sqe.flags as u32 & (1 << IOSQE_IO_HARDLINK_BIT) != 0
+}
+
+struct PendingOp {
+    ring: usize,
+    sqe: io_uring_sqe,
+    // host fd after the fixed file lookup
+    fd: i32,
+    state: OpState,
+    // epoll events the parked op waits for
+    events: u32,
+    deadline: i64,
+    // the LINK_TIMEOUT of the op, or the op guarded by the LINK_TIMEOUT
+    link: Option<u64>,
+    // the worker running the op and whether it is interrupted by the cancel
+    worker: Option<libc::pthread_t>,
+    canceled: bool,
+    // the chain goes on after the failure of the op
+    hardlink: bool,
+    chain: Chain,
+}
+
+impl PendingOp {
+    fn New(ring: usize, sqe: io_uring_sqe, fd: i32) -> Self {
+        return Self {
+            ring: ring,
+            sqe: sqe,
+            fd: fd,
+            state: OpState::Running,
+            events: 0,
+            deadline: 0,
+            link: None,
+            worker: None,
+            canceled: false,
+            hardlink: false,
+            chain: Chain::new(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct EpollState {
+    nextId: u64,
+    ops: HashMap<u64, PendingOp>,
+    // (ring, user_data) to the op id, for TIMEOUT_REMOVE and ASYNC_CANCEL
+    userData: HashMap<(usize, u64), u64>,
+    // parked op ids of each host fd
+    waiters: HashMap<i32, Vec<u64>>,
+    // (deadline, op id)
+    timers: BTreeSet<(i64, u64)>,
+    // the chains whose previous ops complete, they are submitted before the state lock is released
+    next: VecDeque<(usize, Chain)>,
+    // the interrupted ops still running, the signal is sent again in case it comes before the
+    // worker blocks
+    interrupted: BTreeSet<u64>,
+}
+
+// Completion is a cqe to post after the state lock is released
+type Completion = (usize, u64, i32);
+
+impl EpollState {
+    fn NewOp(&mut self, op: PendingOp) -> u64 {
+        self.nextId += 1;
+        let id = self.nextId;
+        self.userData.insert((op.ring, op.sqe.user_data), id);
+        self.ops.insert(id, op);
+        return id
+    }
+
+    // RemoveOp drops the op from the waiters, the timers and the user_data index
+    fn RemoveOp(&mut self, id: u64) -> Option<PendingOp> {
+        let op = self.ops.remove(&id)?;
+        self.interrupted.remove(&id);
+        if self.userData.get(&(op.ring, op.sqe.user_data)) == Some(&id) {
+            self.userData.remove(&(op.ring, op.sqe.user_data));
+        }
+
+        match op.state {
+            OpState::Parked => {
+                let mut empty = false;
+                if let Some(ids) = self.waiters.get_mut(&op.fd) {
+                    ids.retain(|x| *x != id);
+                    empty = ids.len() == 0;
+                }
+
+                if empty {
+                    self.waiters.remove(&op.fd);
+                }
+            }
+            OpState::Timer => {
+                self.timers.remove(&(op.deadline, id));
+            }
+            // the worker skips the job of the removed op
+            OpState::Queued | OpState::Running => (),
+        }
+
+        return Some(op)
+    }
+
+    // Continue moves on the chain after the op completes with res: the chain is submitted after
+    // the success and completes with ECANCELED after the failure, unless the op is IO_HARDLINK
+    fn Continue(&mut self, ring: usize, chain: Chain, hardlink: bool, res: i32, completions: &mut Vec<Completion>) {
+        if chain.len() == 0 {
+            return
+        }
+
+        if res >= 0 || hardlink {
+            self.next.push_back((ring, chain));
+            return
+        }
+
+        for link in chain {
+            completions.push((ring, link.sqe.user_data, -SysErr::ECANCELED));
+            if let Some(lt) = link.lt {
+                completions.push((ring, lt.user_data, -SysErr::ECANCELED));
+            }
+        }
+    }
+
+    // Finish removes the completed op, the LINK_TIMEOUT guarding it completes with ECANCELED.
+    // The op guarded by the finished LINK_TIMEOUT goes on without it.
+    fn Finish(&mut self, id: u64, res: i32, completions: &mut Vec<Completion>) {
+        let op = match self.RemoveOp(id) {
+            None => return,
+            Some(op) => op,
+        };
+
+        completions.push((op.ring, op.sqe.user_data, res));
+        if let Some(link) = op.link {
+            if op.sqe.opcode as u32 == IORING_OP_LINK_TIMEOUT {
+                if let Some(target) = self.ops.get_mut(&link) {
+                    target.link = None;
+                }
+            } else if let Some(ltOp) = self.RemoveOp(link) {
+                completions.push((ltOp.ring, ltOp.sqe.user_data, -SysErr::ECANCELED));
+            }
+        }
+
+        self.Continue(op.ring, op.chain, op.hardlink, res, completions);
+    }
+
+    // Interrupt cancels the op running in the worker by a signal, the blocking call of the
+    // worker gets EINTR and the op completes with ECANCELED. The calls which don't take the
+    // signal, e.g. the regular file io, run to the end and complete with their results.
+    fn Interrupt(&mut self, id: u64) {
+        let op = self.ops.get_mut(&id).unwrap();
+        op.canceled = true;
+        if let Some(worker) = op.worker {
+            unsafe {
+                libc::pthread_kill(worker, CancelSignal());
+            }
+            self.interrupted.insert(id);
+        }
+    }
+
+    // Reinterrupt sends the signal again to the workers of the interrupted ops
+    fn Reinterrupt(&self) {
+        for id in self.interrupted.iter() {
+            if let Some(worker) = self.ops[id].worker {
+                unsafe {
+                    libc::pthread_kill(worker, CancelSignal());
+                }
+            }
+        }
+    }
+
+    // Cancel cancels the op of user data ud. It returns EALREADY like the host uring when the
+    // op is running and it is interrupted.
+    fn Cancel(&mut self, ring: usize, ud: u64, timeoutOnly: bool, completions: &mut Vec<Completion>) -> i32 {
+        let id = match self.userData.get(&(ring, ud)) {
+            None => return -SysErr::ENOENT,
+            Some(id) => *id,
+        };
+
+        let op = &self.ops[&id];
+        if timeoutOnly && op.sqe.opcode as u32 != IORING_OP_TIMEOUT {
+            return -SysErr::ENOENT
+        }
+
+        if op.state == OpState::Running {
+            self.Interrupt(id);
+            return -SysErr::EALREADY
+        }
+
+        self.Finish(id, -SysErr::ECANCELED, completions);
+        return 0
+    }
+
+    // Expire completes the timer op id: TIMEOUT with ETIME, LINK_TIMEOUT cancels its op first
+    fn Expire(&mut self, id: u64, completions: &mut Vec<Completion>) {
+        let op = match self.RemoveOp(id) {
+            None => return,
+            Some(op) => op,
+        };
+
+        if let Some(target) = op.link {
+            let running = match self.ops.get_mut(&target) {
+                None => false,
+                Some(t) => {
+                    t.link = None;
+                    t.state == OpState::Running
+                }
+            };
+
+            if running {
+                self.Interrupt(target);
+            } else {
+                self.Finish(target, -SysErr::ECANCELED, completions);
+            }
+
+            completions.push((op.ring, op.sqe.user_data, -SysErr::ETIME));
+            return
+        }
+
+        completions.push((op.ring, op.sqe.user_data, -SysErr::ETIME));
+        self.Continue(op.ring, op.chain, op.hardlink, -SysErr::ETIME, completions);
+    }
+}
+
+// CancelSignal interrupts the worker running the canceled op, it is not one of the signals
+// forwarded to the guest
+fn CancelSignal() -> i32 {
+    return libc::SIGRTMIN()
+}
+
+extern "C" fn CancelHandler(_signal: i32) {}
+
+// InstallCancelHandler sets the handler of CancelSignal without SA_RESTART, so that the
+// interrupted call returns EINTR instead of being restarted
+fn InstallCancelHandler() {
+    unsafe {
+        let mut act: libc::sigaction = core::mem::zeroed();
+        act.sa_sigaction = CancelHandler as usize;
+        act.sa_flags = 0;
+        libc::sigemptyset(&mut act.sa_mask);
+        if libc::sigaction(CancelSignal(), &act, core::ptr::null_mut()) < 0 {
+            panic!("EpollBackend set cancel signal handler fail, errno is {}", errno::errno().0);
+        }
+    }
+}
+
+struct Job {
+    id: u64,
+    sqe: io_uring_sqe,
+    fd: i32,
+}
+
+#[derive(Default)]
+struct JobQueue {
+    jobs: VecDeque<Job>,
+    // the worker threads and the idle ones waiting for the jobs
+    workers: usize,
+    idle: usize,
+    spawned: usize,
+}
+
+struct EpollShared {
+    epfd: i32,
+    wakefd: i32,
+    rings: Mutex<Vec<Arc<RingState>>>,
+    // the fixed file table, the guest registers its host fds at their own index
+    files: Mutex<Vec<i32>>,
+    state: Mutex<EpollState>,
+    jobs: Mutex<JobQueue>,
+    jobCond: Condvar,
+    maxWorkers: usize,
+    backlogged: AtomicBool,
+    // the shared state itself, for the workers spawned on demand
+    me: Mutex<Weak<EpollShared>>,
+}
+
+// EpollBackend emulates io_uring for the hosts without it. The requests on the nonblocking host
+// fds (sockets, pipes, eventfds) are issued inline and parked in an epoll on EAGAIN, the ones on
+// the blocking fds (regular files) and fsync/statx/splice run in a thread pool. The timeouts are
+// kept by the epoll thread. The IO_LINK chains are submitted one op after another.
+pub struct EpollBackend {
+    shared: Arc<EpollShared>,
+}
+
+fn MonoNow() -> i64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+
+    return ts.tv_sec as i64 * 1_000_000_000 + ts.tv_nsec as i64
+}
+
+fn SysRes(ret: i64) -> i32 {
+    if ret < 0 {
+        return -errno::errno().0
+    }
+
+    return ret as i32
+}
+
+// OffsetPtr turns the uring offset to the pointer of the syscalls, -1 means the current position
+fn OffsetPtr(off: &mut i64) -> * mut i64 {
+    if *off == -1 {
+        return 0 as * mut i64
+    }
+
+    return off as * mut i64
+}
+
+impl EpollBackend {
+    pub fn New() -> Self {
+        return Self {
+            shared: EpollShared::New(WORKERS_PER_CPU * num_cpus::get())
+        }
+    }
+}
+
+impl EpollShared {
+    fn New(maxWorkers: usize) -> Arc<Self> {
+        InstallCancelHandler();
+
+        let epfd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if epfd < 0 {
+            panic!("EpollBackend create epollfd fail, errno is {}", errno::errno().0);
+        }
+
+        let wakefd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if wakefd < 0 {
+            panic!("EpollBackend create eventfd fail, errno is {}", errno::errno().0);
+        }
+
+        let mut ev = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: WAKE_KEY,
+        };
+
+        let ret = unsafe { libc::epoll_ctl(epfd, libc::EPOLL_CTL_ADD, wakefd, &mut ev) };
+        if ret < 0 {
+            panic!("EpollBackend add eventfd fail, errno is {}", errno::errno().0);
+        }
+
+        let shared = Arc::new(EpollShared {
+            epfd: epfd,
+            wakefd: wakefd,
+            rings: Mutex::new(Vec::new()),
+            files: Mutex::new(Vec::new()),
+            state: Mutex::new(EpollState::default()),
+            jobs: Mutex::new(JobQueue::default()),
+            jobCond: Condvar::new(),
+            maxWorkers: core::cmp::max(maxWorkers, 1),
+            backlogged: AtomicBool::new(false),
+            me: Mutex::new(Weak::new()),
+        });
+        *shared.me.lock().unwrap() = Arc::downgrade(&shared);
+
+        let poller = shared.clone();
+        thread::Builder::new()
+            .name("io_epoll".to_string())
+            .spawn(move || poller.Poll())
+            .expect("EpollBackend spawn epoll thread fail");
+
+        if !shared.SpawnWorker(&mut shared.jobs.lock().unwrap()) {
+            panic!("EpollBackend spawn worker thread fail");
+        }
+
+        return shared
+    }
+
+    fn Ring(&self, idx: usize) -> Result<Arc<RingState>> {
+        match self.rings.lock().unwrap().get(idx) {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(r) => return Ok(r.clone()),
+        }
+    }
+
+    fn AddRing(&self, idx: usize, emu: EmuRing) -> Result<()> {
+        let state = Arc::new(RingState {
+            emu: Mutex::new(emu),
+            submit: Mutex::new(()),
+            cond: Condvar::new(),
+        });
+
+        let mut rings = self.rings.lock().unwrap();
+        if idx < rings.len() {
+            rings[idx] = state;
+        } else if idx == rings.len() {
+            rings.push(state);
+        } else {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        return Ok(())
+    }
+
+    fn SpawnWorker(&self, queue: &mut JobQueue) -> bool {
+        let worker = match self.me.lock().unwrap().upgrade() {
+            None => return false,
+            Some(w) => w,
+        };
+
+        let ret = thread::Builder::new()
+            .name(format!("io_worker{}", queue.spawned))
+            .spawn(move || worker.Work());
+        if let Err(e) = ret {
+            error!("EpollBackend spawn worker thread fail {:?}", e);
+            return false
+        }
+
+        queue.workers += 1;
+        queue.spawned += 1;
+        return true
+    }
+
+    // Queue hands the job to an idle worker, or to a new one so that it doesn't wait for the jobs
+    // blocked in the busy workers
+    fn Queue(&self, job: Job) {
+        let mut queue = self.jobs.lock().unwrap();
+        queue.jobs.push_back(job);
+        if queue.jobs.len() > queue.idle && queue.workers < self.maxWorkers {
+            self.SpawnWorker(&mut queue);
+        }
+
+        self.jobCond.notify_one();
+    }
+
+    // NextJob waits for the job, the worker exits when it is idle for WORKER_IDLE_MS and it is
+    // not the last one
+    fn NextJob(&self) -> Option<Job> {
+        let mut queue = self.jobs.lock().unwrap();
+        loop {
+            if let Some(job) = queue.jobs.pop_front() {
+                return Some(job)
+            }
+
+            queue.idle += 1;
+            let (q, res) = self.jobCond.wait_timeout(queue, Duration::from_millis(WORKER_IDLE_MS)).unwrap();
+            queue = q;
+            queue.idle -= 1;
+            if res.timed_out() && queue.jobs.len() == 0 && queue.workers > 1 {
+                queue.workers -= 1;
+                return None
+            }
+        }
+    }
+
+    fn Post(&self, completions: Vec<Completion>) {
+        for (idx, userData, res) in completions {
+            let ring = match self.Ring(idx) {
+                Err(_) => continue,
+                Ok(r) => r,
+            };
+
+            let mut emu = ring.emu.lock().unwrap();
+            emu.backlog.push_back(io_uring_cqe {
+                user_data: userData,
+                res: res,
+                flags: 0,
+            });
+
+            if emu.Flush() > 0 {
+                Self::Notify(emu.eventfd);
+                ring.cond.notify_all();
+            }
+
+            if emu.backlog.len() > 0 {
+                self.backlogged.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    fn Notify(eventfd: i32) {
+        if eventfd < 0 {
+            return
+        }
+
+        let val: u64 = 1;
+        unsafe {
+            libc::write(eventfd, &val as * const _ as * const libc::c_void, 8);
+        }
+    }
+
+    fn Wake(&self) {
+        Self::Notify(self.wakefd);
+    }
+
+    fn FixedFd(&self, fd: i32) -> i32 {
+        match self.files.lock().unwrap().get(fd as usize) {
+            None => return -1,
+            Some(f) => return *f,
+        }
+    }
+
+    fn OpFd(&self, sqe: &io_uring_sqe) -> i32 {
+        if sqe.flags as u32 & (1 << IOSQE_FIXED_FILE_BIT) != 0 {
+            return self.FixedFd(sqe.fd)
+        }
+
+        return sqe.fd
+    }
+
+    // Inline tells whether the request is issued by the caller, otherwise it goes to the pool
+    fn Inline(sqe: &io_uring_sqe, fd: i32) -> bool {
+        match sqe.opcode as u32 {
+            IORING_OP_NOP | IORING_OP_EPOLL_CTL | IORING_OP_POLL_ADD | IORING_OP_SEND |
+            IORING_OP_RECV | IORING_OP_SENDMSG | IORING_OP_RECVMSG => return true,
+            IORING_OP_ACCEPT | IORING_OP_READ | IORING_OP_WRITE |
+            IORING_OP_READV | IORING_OP_WRITEV => {
+                let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+                return flags >= 0 && flags & libc::O_NONBLOCK != 0
+            }
+            _ => return false,
+        }
+    }
+
+    // PollEvents returns the epoll events the request waits for when it gets EAGAIN
+    fn PollEvents(sqe: &io_uring_sqe) -> u32 {
+        match sqe.opcode as u32 {
+            IORING_OP_POLL_ADD => return unsafe { sqe.__bindgen_anon_3.poll32_events },
+            IORING_OP_SEND | IORING_OP_SENDMSG | IORING_OP_WRITE |
+            IORING_OP_WRITEV => return libc::EPOLLOUT as u32,
+            _ => return libc::EPOLLIN as u32,
+        }
+    }
+
+    // Issue runs the request, the inline ones don't block and return -EAGAIN when not ready.
+    // The fixed fd_in of splice is resolved in Submit.
+    fn Issue(sqe: &io_uring_sqe, fd: i32) -> i32 {
+        let addr = unsafe { sqe.__bindgen_anon_2.addr };
+        let off = unsafe { sqe.__bindgen_anon_1.off };
+        let len = sqe.len as usize;
+
+        let ret = unsafe {
+            match sqe.opcode as u32 {
+                IORING_OP_NOP => 0,
+                IORING_OP_POLL_ADD => {
+                    let events = sqe.__bindgen_anon_3.poll32_events;
+                    let mut pfd = libc::pollfd {
+                        fd: fd,
+                        events: events as i16,
+                        revents: 0,
+                    };
+
+                    let ret = libc::poll(&mut pfd, 1, 0);
+                    if ret == 0 {
+                        return -SysErr::EAGAIN
+                    }
+
+                    if ret > 0 {
+                        return pfd.revents as u16 as i32
+                    }
+                    ret as i64
+                }
+                IORING_OP_READ => {
+                    if off as i64 == -1 {
+                        libc::read(fd, addr as * mut libc::c_void, len) as i64
+                    } else {
+                        libc::pread(fd, addr as * mut libc::c_void, len, off as i64) as i64
+                    }
+                }
+                IORING_OP_WRITE => {
+                    if off as i64 == -1 {
+                        libc::write(fd, addr as * const libc::c_void, len) as i64
+                    } else {
+                        libc::pwrite(fd, addr as * const libc::c_void, len, off as i64) as i64
+                    }
+                }
+                IORING_OP_READV => {
+                    if off as i64 == -1 {
+                        libc::readv(fd, addr as * const libc::iovec, len as i32) as i64
+                    } else {
+                        libc::preadv(fd, addr as * const libc::iovec, len as i32, off as i64) as i64
+                    }
+                }
+                IORING_OP_WRITEV => {
+                    if off as i64 == -1 {
+                        libc::writev(fd, addr as * const libc::iovec, len as i32) as i64
+                    } else {
+                        libc::pwritev(fd, addr as * const libc::iovec, len as i32, off as i64) as i64
+                    }
+                }
+                IORING_OP_RECV => {
+                    let flags = sqe.__bindgen_anon_3.msg_flags as i32 | libc::MSG_DONTWAIT;
+                    libc::recv(fd, addr as * mut libc::c_void, len, flags) as i64
+                }
+                IORING_OP_SEND => {
+                    let flags = sqe.__bindgen_anon_3.msg_flags as i32 | libc::MSG_DONTWAIT;
+                    libc::send(fd, addr as * const libc::c_void, len, flags) as i64
+                }
+                IORING_OP_RECVMSG => {
+                    let flags = sqe.__bindgen_anon_3.msg_flags as i32 | libc::MSG_DONTWAIT;
+                    libc::recvmsg(fd, addr as * mut libc::msghdr, flags) as i64
+                }
+                IORING_OP_SENDMSG => {
+                    let flags = sqe.__bindgen_anon_3.msg_flags as i32 | libc::MSG_DONTWAIT;
+                    libc::sendmsg(fd, addr as * const libc::msghdr, flags) as i64
+                }
+                IORING_OP_ACCEPT => {
+                    libc::accept4(fd,
+                                  addr as * mut libc::sockaddr,
+                                  off as * mut libc::socklen_t,
+                                  sqe.__bindgen_anon_3.accept_flags as i32) as i64
+                }
+                IORING_OP_FSYNC => {
+                    if sqe.__bindgen_anon_3.fsync_flags & IORING_FSYNC_DATASYNC != 0 {
+                        libc::fdatasync(fd) as i64
+                    } else {
+                        libc::fsync(fd) as i64
+                    }
+                }
+                IORING_OP_STATX => {
+                    libc::syscall(libc::SYS_statx,
+                                  fd,
+                                  addr,
+                                  sqe.__bindgen_anon_3.statx_flags,
+                                  sqe.len,
+                                  off) as i64
+                }
+                IORING_OP_EPOLL_CTL => {
+                    // fd is the epollfd and off the target fd
+                    libc::epoll_ctl(fd, sqe.len as i32, off as i32, addr as * mut libc::epoll_event) as i64
+                }
+                IORING_OP_SPLICE => {
+                    let spliceFlags = sqe.__bindgen_anon_3.splice_flags;
+                    let fdIn = sqe.__bindgen_anon_4.__bindgen_anon_1.splice_fd_in;
+                    let mut offIn = sqe.__bindgen_anon_2.splice_off_in as i64;
+                    let mut offOut = off as i64;
+                    libc::splice(fdIn,
+                                 OffsetPtr(&mut offIn),
+                                 fd,
+                                 OffsetPtr(&mut offOut),
+                                 len,
+                                 spliceFlags) as i64
+                }
+                _ => return -SysErr::EINVAL,
+            }
+        };
+
+        return SysRes(ret)
+    }
+
+    // Park waits for the fd readiness of the op in the epoll
+    fn Park(&self, state: &mut EpollState, id: u64, completions: &mut Vec<Completion>) {
+        let (fd, events) = {
+            let op = state.ops.get_mut(&id).unwrap();
+            op.state = OpState::Parked;
+            op.events = Self::PollEvents(&op.sqe);
+            (op.fd, op.events)
+        };
+
+        let ids = state.waiters.entry(fd).or_insert(Vec::new());
+        ids.push(id);
+
+        let mut mask = events;
+        for waiter in state.waiters[&fd].iter() {
+            mask |= state.ops[waiter].events;
+        }
+
+        let mut ev = libc::epoll_event {
+            events: mask | libc::EPOLLONESHOT as u32,
+            u64: fd as u64,
+        };
+
+        let mut ret = unsafe { libc::epoll_ctl(self.epfd, libc::EPOLL_CTL_MOD, fd, &mut ev) };
+        if ret < 0 && errno::errno().0 == SysErr::ENOENT {
+            ret = unsafe { libc::epoll_ctl(self.epfd, libc::EPOLL_CTL_ADD, fd, &mut ev) };
+        }
+
+        if ret < 0 {
+            let errno = errno::errno().0;
+            state.Finish(id, -errno, completions);
+        }
+    }
+
+    // Start issues the inline request or hands it to the pool
+    fn Start(&self, state: &mut EpollState, id: u64, completions: &mut Vec<Completion>) {
+        let (sqe, fd) = {
+            let op = &state.ops[&id];
+            (op.sqe, op.fd)
+        };
+
+        if !Self::Inline(&sqe, fd) {
+            state.ops.get_mut(&id).unwrap().state = OpState::Queued;
+            self.Queue(Job {
+                id: id,
+                sqe: sqe,
+                fd: fd,
+            });
+            return
+        }
+
+        let res = Self::Issue(&sqe, fd);
+        if res == -SysErr::EAGAIN {
+            self.Park(state, id, completions);
+        } else {
+            state.Finish(id, res, completions);
+        }
+    }
+
+    fn AddTimer(&self, state: &mut EpollState, id: u64) {
+        let op = state.ops.get_mut(&id).unwrap();
+        let ts = unsafe { *(op.sqe.__bindgen_anon_2.addr as * const libc::timespec) };
+        let ns = ts.tv_sec as i64 * 1_000_000_000 + ts.tv_nsec as i64;
+        op.deadline = if unsafe { op.sqe.__bindgen_anon_3.timeout_flags } & IORING_TIMEOUT_ABS != 0 {
+            ns
+        } else {
+            MonoNow() + ns
+        };
+        op.state = OpState::Timer;
+
+        let earliest = match state.timers.iter().next() {
+            None => true,
+            Some((deadline, _)) => op.deadline < *deadline,
+        };
+
+        state.timers.insert((op.deadline, id));
+        if earliest {
+            self.Wake();
+        }
+    }
+
+    // Submit runs the IO_LINK chain, the rest of it is submitted when the first op completes
+    fn Submit(&self, ring: usize, chain: Chain) {
+        let mut completions = Vec::new();
+        {
+            let mut state = self.state.lock().unwrap();
+            self.SubmitLocked(&mut state, ring, chain, &mut completions);
+            self.RunChains(&mut state, &mut completions);
+        }
+
+        self.Post(completions);
+    }
+
+    // RunChains submits the chains whose previous ops complete
+    fn RunChains(&self, state: &mut EpollState, completions: &mut Vec<Completion>) {
+        while let Some((ring, chain)) = state.next.pop_front() {
+            self.SubmitLocked(state, ring, chain, completions);
+        }
+    }
+
+    // SubmitLocked runs the first sqe of the chain and the LINK_TIMEOUT linked to it
+    fn SubmitLocked(&self, state: &mut EpollState, ring: usize, mut chain: Chain, completions: &mut Vec<Completion>) {
+        let Link { sqe, lt } = match chain.pop_front() {
+            None => return,
+            Some(link) => link,
+        };
+
+        let hardlink = HardLinked(lt.as_ref().unwrap_or(&sqe));
+        let target = unsafe { sqe.__bindgen_anon_2.addr };
+        let res = match sqe.opcode as u32 {
+            IORING_OP_TIMEOUT => {
+                // the completion count (off) is not supported, the guest only uses 0
+                let mut op = PendingOp::New(ring, sqe, -1);
+                op.hardlink = hardlink;
+                op.chain = chain;
+                let id = state.NewOp(op);
+                self.AddTimer(state, id);
+                return
+            }
+            IORING_OP_NOP => 0,
+            IORING_OP_TIMEOUT_REMOVE => state.Cancel(ring, target, true, completions),
+            IORING_OP_ASYNC_CANCEL => {
+                let res = state.Cancel(ring, target, false, completions);
+                if res == -SysErr::EALREADY {
+                    // the epoll thread interrupts the op again until it completes
+                    self.Wake();
+                }
+                res
+            }
+            IORING_OP_LINK_TIMEOUT => -SysErr::EINVAL,
+            _ => {
+                let mut sqe = sqe;
+                let mut fd = self.OpFd(&sqe);
+                if sqe.opcode as u32 == IORING_OP_SPLICE {
+                    unsafe {
+                        let spliceFlags = sqe.__bindgen_anon_3.splice_flags;
+                        if spliceFlags & SPLICE_F_FD_IN_FIXED != 0 {
+                            let fdIn = self.FixedFd(sqe.__bindgen_anon_4.__bindgen_anon_1.splice_fd_in);
+                            sqe.__bindgen_anon_4.__bindgen_anon_1.splice_fd_in = fdIn;
+                            sqe.__bindgen_anon_3.splice_flags = spliceFlags & !SPLICE_F_FD_IN_FIXED;
+                            if fdIn < 0 {
+                                fd = -1;
+                            }
+                        }
+                    }
+                }
+
+                if fd < 0 {
+                    completions.push((ring, sqe.user_data, -SysErr::EBADF));
+                    if let Some(lt) = lt {
+                        completions.push((ring, lt.user_data, -SysErr::ECANCELED));
+                    }
+                    state.Continue(ring, chain, hardlink, -SysErr::EBADF, completions);
+                    return
+                }
+
+                let mut op = PendingOp::New(ring, sqe, fd);
+                op.hardlink = hardlink;
+                op.chain = chain;
+                let id = state.NewOp(op);
+
+                if let Some(lt) = lt {
+                    let mut ltOp = PendingOp::New(ring, lt, -1);
+                    ltOp.link = Some(id);
+                    let ltId = state.NewOp(ltOp);
+                    state.ops.get_mut(&id).unwrap().link = Some(ltId);
+                    self.AddTimer(state, ltId);
+                }
+
+                self.Start(state, id, completions);
+                return
+            }
+        };
+
+        completions.push((ring, sqe.user_data, res));
+        if let Some(lt) = lt {
+            completions.push((ring, lt.user_data, -SysErr::ECANCELED));
+        }
+        state.Continue(ring, chain, hardlink, res, completions);
+    }
+
+    fn Enter(&self, idx: usize, toSubmit: u32, minComplete: u32, flags: u32) -> Result<usize> {
+        let ring = self.Ring(idx)?;
+        let mut submitted = 0;
+        {
+            let _submit = ring.submit.lock().unwrap();
+            let (sqHead, sqTail, sqMask, sqes) = {
+                let emu = ring.emu.lock().unwrap();
+                (emu.sqHead as * const AtomicU32, emu.sqTail as * const AtomicU32, emu.sqMask, emu.sqes)
+            };
+
+            let readSqe = |head: u32| -> io_uring_sqe {
+                // the sq array is identity mapped at the ring setup
+                unsafe {
+                    ((sqes + ((head & sqMask) as usize * SQE_SIZE) as u64) as * const io_uring_sqe).read_volatile()
+                }
+            };
+
+            let mut head = unsafe { (*sqHead).load(Ordering::Relaxed) };
+            let tail = unsafe { (*sqTail).load(Ordering::Acquire) };
+            while submitted < toSubmit && head != tail {
+                // the chain ends at the sqe without IO_LINK, or at the end of the submission
+                let mut chain = Chain::new();
+                loop {
+                    let sqe = readSqe(head);
+                    head = head.wrapping_add(1);
+                    submitted += 1;
+
+                    let mut lt = None;
+                    if Linked(&sqe) && head != tail {
+                        let next = readSqe(head);
+                        if next.opcode as u32 == IORING_OP_LINK_TIMEOUT {
+                            head = head.wrapping_add(1);
+                            submitted += 1;
+                            lt = Some(next);
+                        }
+                    }
+
+                    let linked = Linked(lt.as_ref().unwrap_or(&sqe));
+                    chain.push_back(Link {
+                        sqe: sqe,
+                        lt: lt,
+                    });
+
+                    if !linked || submitted >= toSubmit || head == tail {
+                        break;
+                    }
+                }
+
+                // the slots are free for the guest once the sqes are copied
+                unsafe {
+                    (*sqHead).store(head, Ordering::Release);
+                }
+
+                self.Submit(idx, chain);
+            }
+        }
+
+        if flags & IORING_ENTER_GETEVENTS != 0 && minComplete > 0 {
+            let mut emu = ring.emu.lock().unwrap();
+            while emu.CqLen() < minComplete {
+                emu = ring.cond.wait(emu).unwrap();
+            }
+        }
+
+        return Ok(submitted as usize)
+    }
+
+    // Work runs the requests of the blocking fds
+    fn Work(&self) {
+        while let Some(job) = self.NextJob() {
+            {
+                let mut state = self.state.lock().unwrap();
+                match state.ops.get_mut(&job.id) {
+                    // canceled in the queue
+                    None => continue,
+                    Some(op) => {
+                        op.state = OpState::Running;
+                        op.worker = Some(unsafe { libc::pthread_self() });
+                    }
+                }
+            }
+
+            let mut completions = Vec::new();
+            loop {
+                let mut res = Self::Issue(&job.sqe, job.fd);
+                let mut state = self.state.lock().unwrap();
+                if res == -SysErr::EINTR {
+                    let canceled = match state.ops.get(&job.id) {
+                        None => true,
+                        Some(op) => op.canceled,
+                    };
+
+                    if !canceled {
+                        continue;
+                    }
+
+                    res = -SysErr::ECANCELED;
+                }
+
+                state.Finish(job.id, res, &mut completions);
+                self.RunChains(&mut state, &mut completions);
+                break;
+            }
+
+            self.Post(completions);
+        }
+    }
+
+    // Poll retries the parked requests when their fds are ready and fires the timers
+    fn Poll(&self) {
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 64];
+        loop {
+            let (mut timeout, interrupted) = {
+                let state = self.state.lock().unwrap();
+                let timeout = match state.timers.iter().next() {
+                    None => -1,
+                    Some((deadline, _)) => {
+                        let ns = *deadline - MonoNow();
+                        if ns <= 0 {
+                            0
+                        } else {
+                            ((ns + 999_999) / 1_000_000) as i32
+                        }
+                    }
+                };
+                (timeout, state.interrupted.len() > 0)
+            };
+
+            if interrupted && (timeout < 0 || timeout > INTERRUPT_RETRY_MS) {
+                timeout = INTERRUPT_RETRY_MS;
+            }
+
+            if self.backlogged.load(Ordering::Acquire) && (timeout < 0 || timeout > BACKLOG_RETRY_MS) {
+                timeout = BACKLOG_RETRY_MS;
+            }
+
+            let nfds = unsafe {
+                libc::epoll_wait(self.epfd, &mut events[0], events.len() as i32, timeout)
+            };
+
+            let mut completions = Vec::new();
+            {
+                let mut state = self.state.lock().unwrap();
+                for i in 0..core::cmp::max(nfds, 0) as usize {
+                    let key = events[i].u64;
+                    if key == WAKE_KEY {
+                        let mut data: u64 = 0;
+                        unsafe {
+                            libc::read(self.wakefd, &mut data as * mut _ as * mut libc::c_void, 8);
+                        }
+                        continue;
+                    }
+
+                    let ids = match state.waiters.remove(&(key as i32)) {
+                        None => continue,
+                        Some(ids) => ids,
+                    };
+
+                    for id in ids {
+                        let (sqe, fd) = {
+                            let op = state.ops.get_mut(&id).unwrap();
+                            op.state = OpState::Running;
+                            (op.sqe, op.fd)
+                        };
+
+                        let res = Self::Issue(&sqe, fd);
+                        if res == -SysErr::EAGAIN {
+                            self.Park(&mut state, id, &mut completions);
+                        } else {
+                            state.Finish(id, res, &mut completions);
+                        }
+                    }
+                }
+
+                let now = MonoNow();
+                loop {
+                    let id = match state.timers.iter().next() {
+                        Some((deadline, id)) if *deadline <= now => *id,
+                        _ => break,
+                    };
+
+                    state.Expire(id, &mut completions);
+                }
+
+                self.RunChains(&mut state, &mut completions);
+                state.Reinterrupt();
+            }
+
+            self.Post(completions);
+
+            if self.backlogged.swap(false, Ordering::AcqRel) {
+                for ring in self.rings.lock().unwrap().iter() {
+                    let mut emu = ring.emu.lock().unwrap();
+                    if emu.Flush() > 0 {
+                        Self::Notify(emu.eventfd);
+                        ring.cond.notify_all();
+                    }
+
+                    if emu.backlog.len() > 0 {
+                        self.backlogged.store(true, Ordering::Release);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl IoBackend for EpollBackend {
+    fn Name(&self) -> &'static str {
+        return "epoll"
+    }
+
+    fn NewRing(&self, idx: usize, entries: u32, cqEntries: u32, sqpollCpu: Option<u32>) -> Result<IoUring> {
+        if sqpollCpu.is_some() || !entries.is_power_of_two() || !cqEntries.is_power_of_two() {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let mut p = io_uring_params::default();
+        p.sq_entries = entries;
+        p.cq_entries = cqEntries;
+        p.flags = IORING_SETUP_CQSIZE;
+        p.features = IORING_FEAT_SINGLE_MMAP | IORING_FEAT_NODROP;
+        p.cq_off = io_cqring_offsets {
+            head: CQ_HDR_OFF,
+            tail: CQ_HDR_OFF + 4,
+            ring_mask: CQ_HDR_OFF + 8,
+            ring_entries: CQ_HDR_OFF + 12,
+            overflow: CQ_HDR_OFF + 16,
+            flags: CQ_HDR_OFF + 20,
+            cqes: CQES_OFF,
+            ..Default::default()
+        };
+        p.sq_off = io_sqring_offsets {
+            head: SQ_HDR_OFF,
+            tail: SQ_HDR_OFF + 4,
+            ring_mask: SQ_HDR_OFF + 8,
+            ring_entries: SQ_HDR_OFF + 12,
+            flags: SQ_HDR_OFF + 16,
+            dropped: SQ_HDR_OFF + 20,
+            array: CQES_OFF + cqEntries * CQE_SIZE as u32,
+            ..Default::default()
+        };
+
+        let prot = (MmapProt::PROT_WRITE | MmapProt::PROT_READ) as i32;
+        let ringLen = (p.sq_off.array as u64 + entries as u64 * 4 + MemoryDef::PAGE_SIZE - 1) & !(MemoryDef::PAGE_SIZE - 1);
+        let sqeLen = (entries as u64 * SQE_SIZE as u64 + MemoryDef::PAGE_SIZE - 1) & !(MemoryDef::PAGE_SIZE - 1);
+        let ringMmap = Mmap {
+            addr: PMA_KEEPER.MapAnon(ringLen, prot)?,
+            len: ringLen as usize,
+        };
+        let sqeMmap = Mmap {
+            addr: PMA_KEEPER.MapAnon(sqeLen, prot)?,
+            len: sqeLen as usize,
+        };
+
+        let base = ringMmap.addr;
+        unsafe {
+            *((base + p.sq_off.ring_mask as u64) as * mut u32) = entries - 1;
+            *((base + p.sq_off.ring_entries as u64) as * mut u32) = entries;
+            *((base + p.cq_off.ring_mask as u64) as * mut u32) = cqEntries - 1;
+            *((base + p.cq_off.ring_entries as u64) as * mut u32) = cqEntries;
+        }
+
+        let sq = unsafe { SubmissionQueue::new(&ringMmap, &sqeMmap, &p) };
+        let cq = unsafe { CompletionQueue::new(&ringMmap, &p) };
+
+        let emu = EmuRing {
+            sqHead: base + p.sq_off.head as u64,
+            sqTail: base + p.sq_off.tail as u64,
+            sqMask: entries - 1,
+            sqes: sqeMmap.addr,
+            cqHead: base + p.cq_off.head as u64,
+            cqTail: base + p.cq_off.tail as u64,
+            cqMask: cqEntries - 1,
+            cqEntries: cqEntries,
+            cqes: base + p.cq_off.cqes as u64,
+            eventfd: -1,
+            backlog: VecDeque::new(),
+        };
+
+        self.shared.AddRing(idx, emu)?;
+
+        return Ok(IoUring {
+            // there is no host uring fd behind the emulated ring
+            fd: Fd(-1),
+            lock: QMutex::new(()),
+            pendingCnt: AtomicU64::new(0),
+            ioThreadWaiting: AtomicBool::new(false),
+            ownIOThread: AtomicBool::new(false),
+            hostReapTsc: AtomicI64::new(0),
+            cqSeenTsc: AtomicI64::new(0),
+            wakeups: AtomicU64::new(0),
+            wakeupCycles: AtomicU64::new(0),
+            wakeupMaxCycles: AtomicU64::new(0),
+            sq: QMutex::new(sq),
+            cq: QMutex::new(cq),
+            params: Parameters(p),
+            memory: MemoryMap {
+                sq_mmap: ringMmap,
+                cq_mmap: None,
+                sqe_mmap: sqeMmap,
+            },
+        })
+    }
+
+    fn Enter(&self, idx: usize, _ring: &IoUring, toSubmit: u32, minComplete: u32, flags: u32) -> Result<usize> {
+        return self.shared.Enter(idx, toSubmit, minComplete, flags)
+    }
+
+    fn Register(&self, idx: usize, _ring: &IoUring, opcode: u32, arg: u64, nrArgs: u32) -> Result<()> {
+        match opcode {
+            IORING_REGISTER_FILES => {
+                let fds = unsafe { core::slice::from_raw_parts(arg as * const i32, nrArgs as usize) };
+                *self.shared.files.lock().unwrap() = fds.to_vec();
+            }
+            IORING_UNREGISTER_FILES => {
+                self.shared.files.lock().unwrap().clear();
+            }
+            IORING_REGISTER_FILES_UPDATE => {
+                let fu = unsafe { *(arg as * const io_uring_files_update) };
+                let fds = unsafe { core::slice::from_raw_parts(fu.fds as * const i32, nrArgs as usize) };
+                let mut files = self.shared.files.lock().unwrap();
+                let end = fu.offset as usize + fds.len();
+                if end > files.len() {
+                    return Err(Error::SysError(SysErr::EINVAL))
+                }
+                files[fu.offset as usize..end].copy_from_slice(fds);
+            }
+            IORING_REGISTER_EVENTFD | IORING_REGISTER_EVENTFD_ASYNC => {
+                let eventfd = unsafe { *(arg as * const i32) };
+                self.shared.Ring(idx)?.emu.lock().unwrap().eventfd = eventfd;
+            }
+            IORING_UNREGISTER_EVENTFD => {
+                self.shared.Ring(idx)?.emu.lock().unwrap().eventfd = -1;
+            }
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        }
+
+        return Ok(())
+    }
+
+    fn ProbeOps(&self, _ring: &IoUring) -> u64 {
+        let ops = [
+            IORING_OP_NOP, IORING_OP_READV, IORING_OP_WRITEV, IORING_OP_FSYNC,
+            IORING_OP_POLL_ADD, IORING_OP_SENDMSG, IORING_OP_RECVMSG, IORING_OP_TIMEOUT,
+            IORING_OP_TIMEOUT_REMOVE, IORING_OP_ACCEPT, IORING_OP_ASYNC_CANCEL,
+            IORING_OP_LINK_TIMEOUT, IORING_OP_STATX, IORING_OP_READ, IORING_OP_WRITE,
+            IORING_OP_SEND, IORING_OP_RECV, IORING_OP_EPOLL_CTL, IORING_OP_SPLICE,
+        ];
+
+        let mut bitmap = 0;
+        for op in ops.iter() {
+            bitmap |= 1 << *op;
+        }
+
+        info!("epoll io backend opcodes {:x}", bitmap);
+        return bitmap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::os::unix::io::AsRawFd;
+    use std::time::Instant;
+
+    // NewTestRing adds the ring idx with the cq in the heap, the tests submit the chains directly
+    fn NewTestRing(shared: &EpollShared, idx: usize) -> Arc<RingState> {
+        let cqEntries = 16;
+        let mem = vec![0u64; (CQES_OFF as usize + cqEntries * CQE_SIZE) / 8].leak();
+        let base = mem.as_ptr() as u64;
+        let emu = EmuRing {
+            sqHead: base + SQ_HDR_OFF as u64,
+            sqTail: base + SQ_HDR_OFF as u64 + 4,
+            sqMask: 0,
+            sqes: 0,
+            cqHead: base + CQ_HDR_OFF as u64,
+            cqTail: base + CQ_HDR_OFF as u64 + 4,
+            cqMask: cqEntries as u32 - 1,
+            cqEntries: cqEntries as u32,
+            cqes: base + CQES_OFF as u64,
+            eventfd: -1,
+            backlog: VecDeque::new(),
+        };
+
+        shared.AddRing(idx, emu).unwrap();
+        return shared.Ring(idx).unwrap()
+    }
+
+    fn Sqe(opcode: u32, fd: i32, userData: u64, flags: u32) -> Link {
+        let mut sqe = io_uring_sqe::default();
+        sqe.opcode = opcode as u8;
+        sqe.fd = fd;
+        sqe.user_data = userData;
+        sqe.flags = flags as u8;
+        return Link {
+            sqe: sqe,
+            lt: None,
+        }
+    }
+
+    fn Read(fd: i32, buf: &mut [u8], off: i64, userData: u64, flags: u32) -> Link {
+        let mut link = Sqe(IORING_OP_READ, fd, userData, flags);
+        link.sqe.__bindgen_anon_2.addr = buf.as_mut_ptr() as u64;
+        link.sqe.__bindgen_anon_1.off = off as u64;
+        link.sqe.len = buf.len() as u32;
+        return link
+    }
+
+    fn Cancel(target: u64, userData: u64) -> Link {
+        let mut link = Sqe(IORING_OP_ASYNC_CANCEL, -1, userData, 0);
+        link.sqe.__bindgen_anon_2.addr = target;
+        return link
+    }
+
+    // Reap waits for cnt completions and returns them as (user_data, res)
+    fn Reap(ring: &RingState, cnt: u32) -> Vec<(u64, i32)> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut emu = ring.emu.lock().unwrap();
+        while emu.CqLen() < cnt {
+            assert!(Instant::now() < deadline, "wait for {} completions timeout", cnt);
+            emu = ring.cond.wait_timeout(emu, Duration::from_millis(100)).unwrap().0;
+        }
+
+        let mut cqes = Vec::new();
+        let head = unsafe { &*(emu.cqHead as * const AtomicU32) };
+        for _i in 0..cnt {
+            let h = head.load(Ordering::Relaxed);
+            let cqe = unsafe {
+                ((emu.cqes + ((h & emu.cqMask) as usize * CQE_SIZE) as u64) as * const io_uring_cqe).read_volatile()
+            };
+            cqes.push((cqe.user_data, cqe.res));
+            head.store(h.wrapping_add(1), Ordering::Release);
+        }
+
+        return cqes
+    }
+
+    // WaitRunning waits for the worker to take the op of user data ud
+    fn WaitRunning(shared: &EpollShared, ud: u64) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            {
+                let state = shared.state.lock().unwrap();
+                let id = state.userData[&(0, ud)];
+                if state.ops[&id].state == OpState::Running {
+                    return
+                }
+            }
+
+            assert!(Instant::now() < deadline, "wait for the worker timeout");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn Pipe() -> (i32, i32) {
+        let mut fds = [0i32; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        return (fds[0], fds[1])
+    }
+
+    #[test]
+    fn test_link_chain() {
+        let shared = EpollShared::New(4);
+        let ring = NewTestRing(&shared, 0);
+        let link = 1 << IOSQE_IO_LINK_BIT;
+        let hardlink = 1 << IOSQE_IO_HARDLINK_BIT;
+
+        shared.Submit(0, vec![Sqe(IORING_OP_NOP, -1, 1, link), Sqe(IORING_OP_NOP, -1, 2, 0)].into());
+        assert_eq!(Reap(&ring, 2), vec![(1, 0), (2, 0)]);
+
+        // the failure cancels the rest of the chain
+        shared.Submit(0, vec![
+            Sqe(IORING_OP_READ, -1, 3, link),
+            Sqe(IORING_OP_NOP, -1, 4, link),
+            Sqe(IORING_OP_NOP, -1, 5, 0),
+        ].into());
+        assert_eq!(Reap(&ring, 3), vec![(3, -SysErr::EBADF), (4, -SysErr::ECANCELED), (5, -SysErr::ECANCELED)]);
+
+        shared.Submit(0, vec![Sqe(IORING_OP_READ, -1, 6, hardlink), Sqe(IORING_OP_NOP, -1, 7, 0)].into());
+        assert_eq!(Reap(&ring, 2), vec![(6, -SysErr::EBADF), (7, 0)]);
+
+        // the read of the regular file in the pool starts after the write completes
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"hello").unwrap();
+        let fd = file.as_raw_fd();
+        let data = *b"world";
+        let mut write = Sqe(IORING_OP_WRITE, fd, 8, link);
+        write.sqe.__bindgen_anon_2.addr = data.as_ptr() as u64;
+        write.sqe.__bindgen_anon_1.off = 5;
+        write.sqe.len = data.len() as u32;
+        let mut buf = [0u8; 10];
+        shared.Submit(0, vec![write, Read(fd, &mut buf, 0, 9, 0)].into());
+        assert_eq!(Reap(&ring, 2), vec![(8, 5), (9, 10)]);
+        assert_eq!(&buf, b"helloworld");
+    }
+
+    #[test]
+    fn test_cancel_worker() {
+        // one worker: the first read blocks it and the second one stays in the queue
+        let shared = EpollShared::New(1);
+        let ring = NewTestRing(&shared, 0);
+        let (r1, w1) = Pipe();
+        let (r2, w2) = Pipe();
+        let mut buf1 = [0u8; 8];
+        let mut buf2 = [0u8; 8];
+
+        shared.Submit(0, vec![Read(r1, &mut buf1, -1, 1, 0)].into());
+        WaitRunning(&shared, 1);
+        shared.Submit(0, vec![Read(r2, &mut buf2, -1, 2, 0)].into());
+
+        shared.Submit(0, vec![Cancel(2, 3)].into());
+        assert_eq!(Reap(&ring, 2), vec![(2, -SysErr::ECANCELED), (3, 0)]);
+
+        // the running read is interrupted
+        shared.Submit(0, vec![Cancel(1, 4)].into());
+        let mut cqes = Reap(&ring, 2);
+        cqes.sort();
+        assert_eq!(cqes, vec![(1, -SysErr::ECANCELED), (4, -SysErr::EALREADY)]);
+
+        // the worker goes on with the next job
+        shared.Submit(0, vec![Read(r2, &mut buf2, -1, 5, 0)].into());
+        assert_eq!(unsafe { libc::write(w2, b"data".as_ptr() as _, 4) }, 4);
+        assert_eq!(Reap(&ring, 1), vec![(5, 4)]);
+
+        for fd in [r1, w1, r2, w2] {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+
+    #[test]
+    fn test_worker_grow() {
+        let shared = EpollShared::New(4);
+        let ring = NewTestRing(&shared, 0);
+        let (r1, w1) = Pipe();
+        let (r2, w2) = Pipe();
+        let mut buf1 = [0u8; 8];
+        let mut buf2 = [0u8; 8];
+
+        // the blocked reads don't hold up the fsync
+        shared.Submit(0, vec![Read(r1, &mut buf1, -1, 1, 0)].into());
+        shared.Submit(0, vec![Read(r2, &mut buf2, -1, 2, 0)].into());
+        let file = tempfile::tempfile().unwrap();
+        shared.Submit(0, vec![Sqe(IORING_OP_FSYNC, file.as_raw_fd(), 3, 0)].into());
+        assert_eq!(Reap(&ring, 1), vec![(3, 0)]);
+        assert!(shared.jobs.lock().unwrap().workers >= 3);
+
+        assert_eq!(unsafe { libc::write(w1, b"a".as_ptr() as _, 1) }, 1);
+        assert_eq!(unsafe { libc::write(w2, b"b".as_ptr() as _, 1) }, 1);
+        let mut cqes = Reap(&ring, 2);
+        cqes.sort();
+        assert_eq!(cqes, vec![(1, 1), (2, 1)]);
+
+        for fd in [r1, w1, r2, w2] {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
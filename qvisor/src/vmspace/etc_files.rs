@@ -0,0 +1,189 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use spin::Mutex;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use libc::*;
+
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::kernel::fs::etc::*;
+
+lazy_static! {
+    pub static ref ETC_FILES_MGR: Mutex<EtcFilesMgr> = Mutex::new(EtcFilesMgr::default());
+}
+
+// EtcSource is the host file given for the bind mount of an etc file. The parent directory is
+// kept open so that the file is still reachable after the pivot root and a new file renamed
+// over it (e.g. kubelet rewrites resolv.conf) is seen.
+pub struct EtcSource {
+    pub dirfd: i32,
+    pub name: CString,
+}
+
+impl Drop for EtcSource {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.dirfd);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct EtcFilesMgr {
+    pub hostname: String,
+    pub sources: BTreeMap<usize, EtcSource>,
+}
+
+impl EtcFilesMgr {
+    // AddSource takes the bind mount source of an etc file instead of mounting it
+    pub fn AddSource(&mut self, destination: &str, source: &str) -> Result<()> {
+        let idx = match EtcFileIdx(destination) {
+            None => return Err(Error::SysError(SysErr::EINVAL)),
+            Some(idx) => idx,
+        };
+
+        let path = Path::new(source).canonicalize().map_err(|e| Error::IOError(format!("io error is {:?}", e)))?;
+        if !path.is_file() {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        let dir = CString::new(path.parent().unwrap().to_str().unwrap()).unwrap();
+        let name = CString::new(path.file_name().unwrap().to_str().unwrap()).unwrap();
+        let dirfd = unsafe {
+            open(dir.as_ptr(), O_PATH | O_DIRECTORY | O_CLOEXEC)
+        };
+
+        if dirfd < 0 {
+            return Err(Error::SysError(errno::errno().0))
+        }
+
+        info!("etc file {} is generated from {:?}", destination, &path);
+        self.sources.insert(idx, EtcSource {
+            dirfd: dirfd,
+            name: name,
+        });
+        return Ok(())
+    }
+
+    pub fn SetHostname(&mut self, hostname: &str) {
+        self.hostname = hostname.to_string();
+    }
+
+    // Paths returns the etc files which have content
+    pub fn Paths(&self) -> Vec<String> {
+        return (0..ETC_FILES.len())
+            .filter(|idx| self.sources.contains_key(idx) || self.Default(*idx).is_some())
+            .map(|idx| ETC_FILES[idx].to_string())
+            .collect()
+    }
+
+    // Default is the content when there is no source, from the hostname of the spec
+    fn Default(&self, idx: usize) -> Option<String> {
+        if self.hostname.len() == 0 {
+            return None
+        }
+
+        match ETC_FILES[idx] {
+            "/etc/hostname" => return Some(format!("{}\n", self.hostname)),
+            "/etc/hosts" => return Some(format!("127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n127.0.1.1\t{}\n", self.hostname)),
+            _ => return None,
+        }
+    }
+
+    fn ReadSource(src: &EtcSource) -> Result<Vec<u8>> {
+        let fd = unsafe {
+            openat(src.dirfd, src.name.as_ptr(), O_RDONLY | O_CLOEXEC)
+        };
+
+        if fd < 0 {
+            return Err(Error::SysError(errno::errno().0))
+        }
+
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        let mut data = Vec::new();
+        file.by_ref().take(ETC_FILE_MAX as u64 + 1).read_to_end(&mut data).map_err(|e| Error::IOError(format!("io error is {:?}", e)))?;
+        if data.len() > ETC_FILE_MAX {
+            error!("etc file {:?} is larger than {} bytes", src.name, ETC_FILE_MAX);
+            return Err(Error::SysError(SysErr::EFBIG))
+        }
+
+        return Ok(data)
+    }
+
+    pub fn Generate(&self, idx: usize) -> Result<Vec<u8>> {
+        if let Some(src) = self.sources.get(&idx) {
+            return Self::ReadSource(src)
+        }
+
+        match self.Default(idx) {
+            None => return Err(Error::SysError(SysErr::ENOENT)),
+            Some(s) => return Ok(s.into_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_default() {
+        let mut mgr = EtcFilesMgr::default();
+        assert_eq!(mgr.Paths().len(), 0);
+        assert_eq!(mgr.Generate(0), Err(Error::SysError(SysErr::ENOENT)));
+
+        mgr.SetHostname("pod");
+        assert_eq!(mgr.Paths(), vec!["/etc/hostname".to_string(), "/etc/hosts".to_string()]);
+        assert_eq!(mgr.Generate(0).unwrap(), b"pod\n".to_vec());
+        let hosts = String::from_utf8(mgr.Generate(1).unwrap()).unwrap();
+        assert!(hosts.contains("127.0.1.1\tpod\n"));
+        assert_eq!(mgr.Generate(2), Err(Error::SysError(SysErr::ENOENT)));
+    }
+
+    #[test]
+    fn test_source() {
+        let dir = std::env::temp_dir().join(format!("quark-etc-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("resolv.conf");
+        fs::write(&path, "nameserver 10.0.0.10\n").unwrap();
+
+        let mut mgr = EtcFilesMgr::default();
+        mgr.AddSource("/etc/resolv.conf", path.to_str().unwrap()).unwrap();
+        assert!(mgr.AddSource("/etc/passwd", path.to_str().unwrap()).is_err());
+        assert_eq!(mgr.Paths(), vec!["/etc/resolv.conf".to_string()]);
+        assert_eq!(mgr.Generate(2).unwrap(), b"nameserver 10.0.0.10\n".to_vec());
+
+        // the file renamed over the source is seen by the next read
+        let tmp = dir.join("resolv.conf.tmp");
+        fs::write(&tmp, "nameserver 10.0.0.11\n").unwrap();
+        fs::rename(&tmp, &path).unwrap();
+        assert_eq!(mgr.Generate(2).unwrap(), b"nameserver 10.0.0.11\n".to_vec());
+
+        // the file larger than ETC_FILE_MAX fails instead of being truncated
+        fs::write(&path, vec![b'#'; ETC_FILE_MAX + 1]).unwrap();
+        assert_eq!(mgr.Generate(2), Err(Error::SysError(SysErr::EFBIG)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
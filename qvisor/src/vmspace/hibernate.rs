@@ -0,0 +1,633 @@
+// Copyright (c) 2021 Quark Container Authors / 2018 The gVisor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use libc::*;
+
+use super::super::qlib::ShareSpace;
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::vcpu_mgr::*;
+use super::super::qlib::kernel::IOURING;
+use super::super::qlib::kernel::PAGE_MGR;
+use super::super::qlib::kernel::kernel::timer::TIME_KEEPER;
+use super::super::runc::runtime::vm::*;
+use super::super::{ROOT_CONTAINER_ID, KERNEL_IO_THREAD, SHARE_SPACE};
+#[cfg(feature = "rdma")]
+use super::super::IO_MGR;
+use super::journal::*;
+
+pub const HIBERNATE_DIR: &str = "/var/lib/quark/hibernate";
+// max time in ms the io thread sleeps between 2 idle checks
+pub const HIBERNATE_CHECK_INTERVAL: i32 = 1000;
+// the exit status of the sandbox whose guest memory can't be restored
+pub const HIBERNATE_RESTORE_FAIL: i32 = 128 + SIGBUS;
+
+const IMAGE_MAGIC: u64 = 0x5145_4e52_4542_4948; // "HIBERNEQ"
+const IMAGE_VERSION: u64 = 2;
+// the contiguous non zero pages are compressed together up to 1MB
+const IMAGE_RUN_PAGES: usize = 256;
+
+lazy_static! {
+    pub static ref HIBERNATOR: Hibernator = Hibernator::New();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HibernateState {
+    Running,
+    // the image is written by the hibernate thread, the guest memory is still there
+    Writing,
+    Hibernated,
+    // the image is read back by a vcpu or the resume thread
+    Restoring,
+    // the image can't be restored, the sandbox exits
+    Failed,
+}
+
+pub struct HibernateImage {
+    pub path: String,
+    pub pages: usize,
+    pub stored: usize,
+}
+
+pub struct HibernateStatus {
+    pub state: HibernateState,
+    pub image: Option<HibernateImage>,
+}
+
+// Hibernator writes the guest application memory (the pages of the guest PagePool) to an image
+// on disk and frees it on the host when the sandbox has been idle for HibernateIdleTimeout seconds.
+// The memory is restored before any vcpu runs the guest again, e.g. for the next incoming
+// connection of a listening socket. The listening sockets are host sockets which stay alive and
+// keep the connections in the host accept backlog, the connection is an event of the host epoll
+// or the completion of the uring accept, both of which wake up the io thread to resume.
+//
+// The image only stores the non zero pages compressed by lz4, the zero pages are restored by
+// the host as the freed anonymous memory reads as zero. The image is written and, when the io
+// thread wakes up first, read by a separate thread so the io thread goes on serving the host
+// events. A vcpu which runs the guest while the image is written cancels the hibernation.
+pub struct Hibernator {
+    // the times a waiting vcpu goes back to the guest, the guest is idle when it doesn't change
+    runCnt: AtomicU64,
+    lastRunCnt: AtomicU64,
+    // in us
+    idleStart: AtomicI64,
+    // the state is not Running
+    hibernated: AtomicBool,
+    status: Mutex<HibernateStatus>,
+    cond: Condvar,
+}
+
+impl Hibernator {
+    pub fn New() -> Self {
+        return Self {
+            runCnt: AtomicU64::new(0),
+            lastRunCnt: AtomicU64::new(0),
+            idleStart: AtomicI64::new(0),
+            hibernated: AtomicBool::new(false),
+            status: Mutex::new(HibernateStatus {
+                state: HibernateState::Running,
+                image: None,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    pub fn Hibernated(&self) -> bool {
+        return self.hibernated.load(Ordering::SeqCst)
+    }
+
+    pub fn State(&self) -> HibernateState {
+        return self.status.lock().unwrap().state
+    }
+
+    fn SetState(&self, status: &mut HibernateStatus, state: HibernateState) {
+        status.state = state;
+        self.hibernated.store(state != HibernateState::Running, Ordering::SeqCst);
+    }
+
+    // OnVcpuRun is called before a waiting vcpu runs the guest task, it restores the guest memory
+    // when the sandbox is hibernated.
+    pub fn OnVcpuRun(&self) -> Result<()> {
+        self.runCnt.fetch_add(1, Ordering::SeqCst);
+        return self.ResumeIfHibernated();
+    }
+
+    // ResumeIfHibernated restores the guest memory on the calling thread or waits for the resume
+    // thread to restore it. The error is returned when the image can't be restored.
+    pub fn ResumeIfHibernated(&self) -> Result<()> {
+        if !self.Hibernated() {
+            return Ok(())
+        }
+
+        let image = match self.StartResume()? {
+            None => return Ok(()),
+            Some(image) => image,
+        };
+
+        let res = Self::Restore(&image);
+        self.EndResume(image, res.clone());
+        return res
+    }
+
+    // ResumeAsync is called by the io thread when it wakes up, the image is restored by the
+    // resume thread while the io thread processes the host event, e.g. an incoming connection.
+    // The vcpus woken up by the event wait for the restore in OnVcpuRun.
+    pub fn ResumeAsync(&'static self) {
+        if !self.Hibernated() {
+            return
+        }
+
+        let image = match self.StartResume() {
+            Ok(Some(image)) => image,
+            _ => return,
+        };
+
+        thread::Builder::new().name("hibernate_resume".to_string()).spawn(move || {
+            let res = Self::Restore(&image);
+            self.EndResume(image, res);
+        }).unwrap();
+    }
+
+    // StartResume moves the hibernated state to Restoring and returns the image to restore.
+    // The image being written is dropped and nothing is to restore then. It waits for the
+    // restore of another thread.
+    fn StartResume(&self) -> Result<Option<HibernateImage>> {
+        let mut status = self.status.lock().unwrap();
+        loop {
+            match status.state {
+                HibernateState::Running => return Ok(None),
+                HibernateState::Writing => {
+                    // the hibernate thread drops the image when it sees the state
+                    self.SetState(&mut status, HibernateState::Running);
+                    self.idleStart.store(Self::NowUs(), Ordering::Relaxed);
+                    return Ok(None)
+                }
+                HibernateState::Hibernated => {
+                    let image = status.image.take();
+                    match image {
+                        None => self.SetState(&mut status, HibernateState::Running),
+                        Some(_) => self.SetState(&mut status, HibernateState::Restoring),
+                    }
+
+                    return Ok(image)
+                }
+                HibernateState::Restoring => {
+                    status = self.cond.wait(status).unwrap();
+                }
+                HibernateState::Failed => {
+                    return Err(Error::Common(String::from("hibernate: the guest memory is not restored")))
+                }
+            }
+        }
+    }
+
+    fn EndResume(&self, image: HibernateImage, res: Result<()>) {
+        let mut status = self.status.lock().unwrap();
+        match res {
+            Ok(()) => {
+                self.idleStart.store(Self::NowUs(), Ordering::Relaxed);
+                self.SetState(&mut status, HibernateState::Running);
+            }
+            Err(e) => {
+                // the image is kept for the inspection
+                error!("hibernate: restore image {} fail with error {:?}", &image.path, e);
+                JOURNAL.Record(JournalKind::Resume, &format!("pages={} error={:?}", image.pages, e));
+                self.SetState(&mut status, HibernateState::Failed);
+                status.image = Some(image);
+                Self::Stop();
+            }
+        }
+
+        self.cond.notify_all();
+    }
+
+    // Restore reads the image back to the guest memory
+    fn Restore(image: &HibernateImage) -> Result<()> {
+        if let Err(e) = Self::ReadImage(&image.path) {
+            return Err(Error::IOError(format!("{:?}", e)))
+        }
+
+        fs::remove_file(&image.path).ok();
+        info!("hibernate: {} pages are restored from {}", image.pages, &image.path);
+        JOURNAL.Record(JournalKind::Resume, &format!("pages={}", image.pages));
+
+        // the vdso parameter page could be updated by the host while the memory is freed
+        TIME_KEEPER.Update();
+        return Ok(())
+    }
+
+    // Stop makes the sandbox exit as the guest can't run without its memory
+    fn Stop() {
+        SetExitStatus(HIBERNATE_RESTORE_FAIL);
+        KERNEL_IO_THREAD.Wakeup(&SHARE_SPACE);
+        VirtualMachine::WakeAll(&SHARE_SPACE);
+    }
+
+    // GuestIdle returns whether no task is running or runnable and the uring and the RDMA have
+    // no work which completes without an event from outside of the sandbox
+    fn GuestIdle(sharespace: &ShareSpace) -> bool {
+        if sharespace.scheduler.GlobalReadyTaskCnt() > 0 || IOURING.SyncInflight() > 0 {
+            return false
+        }
+
+        // vcpu 0 is the io thread
+        for i in 1..sharespace.scheduler.vcpuCnt {
+            if sharespace.scheduler.VcpuArr[i].State() != VcpuState::Waiting {
+                return false
+            }
+        }
+
+        if IOURING.AsyncBusy() {
+            return false
+        }
+
+        #[cfg(feature = "rdma")]
+        if IO_MGR.RDMABusy() {
+            return false
+        }
+
+        return true
+    }
+
+    // CheckIdle is called by the io thread before it sleeps. It returns true when the io thread
+    // has to wake up in HIBERNATE_CHECK_INTERVAL to check again.
+    pub fn CheckIdle(&'static self, sharespace: &ShareSpace) -> bool {
+        let timeout = sharespace.config.read().HibernateIdleTimeout as i64;
+        if timeout == 0 || self.Hibernated() {
+            return false
+        }
+
+        let now = Self::NowUs();
+        let runCnt = self.runCnt.load(Ordering::SeqCst);
+        if !Self::GuestIdle(sharespace) || runCnt != self.lastRunCnt.swap(runCnt, Ordering::SeqCst)
+            || self.idleStart.load(Ordering::Relaxed) == 0 {
+            self.idleStart.store(now, Ordering::Relaxed);
+            return true
+        }
+
+        if now - self.idleStart.load(Ordering::Relaxed) >= timeout * 1_000_000 {
+            self.Hibernate(sharespace);
+        }
+
+        return !self.Hibernated()
+    }
+
+    // Hibernate starts the hibernate thread which writes the image
+    fn Hibernate(&'static self, sharespace: &ShareSpace) {
+        {
+            let mut status = self.status.lock().unwrap();
+
+            // the vcpu which passed OnVcpuRun before this is seen by the runCnt check
+            self.SetState(&mut status, HibernateState::Writing);
+            if !Self::GuestIdle(sharespace) || self.runCnt.load(Ordering::SeqCst) != self.lastRunCnt.load(Ordering::SeqCst) {
+                self.SetState(&mut status, HibernateState::Running);
+                return
+            }
+        }
+
+        let pages: Vec<u64> = PAGE_MGR.lock().allocator.lock().refs.keys().cloned().collect();
+        let path = format!("{}/{}.img", HIBERNATE_DIR, ROOT_CONTAINER_ID.lock());
+        thread::Builder::new().name("hibernate".to_string()).spawn(move || {
+            let res = Self::WriteImage(&path, &pages);
+            self.EndWrite(path, &pages, res);
+        }).unwrap();
+    }
+
+    // EndWrite frees the pages of the written image unless a vcpu has run meanwhile
+    fn EndWrite(&self, path: String, pages: &[u64], res: io::Result<usize>) {
+        let mut status = self.status.lock().unwrap();
+        let stored = match res {
+            Ok(stored) if status.state == HibernateState::Writing => stored,
+            res => {
+                if let Err(e) = res {
+                    error!("hibernate: write image {} fail with error {:?}", &path, e);
+                }
+
+                fs::remove_file(&path).ok();
+                if status.state == HibernateState::Writing {
+                    self.SetState(&mut status, HibernateState::Running);
+                    self.idleStart.store(Self::NowUs(), Ordering::Relaxed);
+                }
+
+                return
+            }
+        };
+
+        // the vcpus wait for the lock in OnVcpuRun while the pages are freed
+        Self::FreePages(pages);
+
+        info!("hibernate: {} pages, {} non zero pages are stored in {}", pages.len(), stored, &path);
+        JOURNAL.Record(JournalKind::Hibernate, &format!("pages={} stored={}", pages.len(), stored));
+        status.image = Some(HibernateImage {
+            path: path,
+            pages: pages.len(),
+            stored: stored,
+        });
+        self.SetState(&mut status, HibernateState::Hibernated);
+    }
+
+    // NowUs returns the host monotonic time in us
+    fn NowUs() -> i64 {
+        let mut ts = timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe {
+            clock_gettime(CLOCK_MONOTONIC, &mut ts);
+        }
+
+        return ts.tv_sec as i64 * 1_000_000 + ts.tv_nsec as i64 / 1000
+    }
+
+    fn IsZeroPage(addr: u64) -> bool {
+        let page = unsafe {
+            std::slice::from_raw_parts(addr as *const u64, (MemoryDef::PAGE_SIZE / 8) as usize)
+        };
+
+        return page.iter().all(|w| *w == 0)
+    }
+
+    // Runs returns the runs of the contiguous non zero pages as (start, page count)
+    fn Runs(pages: &[u64]) -> Vec<(u64, usize)> {
+        let mut runs: Vec<(u64, usize)> = Vec::new();
+        for &addr in pages {
+            if Self::IsZeroPage(addr) {
+                continue
+            }
+
+            match runs.last_mut() {
+                Some((start, count)) if *count < IMAGE_RUN_PAGES
+                    && *start + *count as u64 * MemoryDef::PAGE_SIZE == addr => *count += 1,
+                _ => runs.push((addr, 1)),
+            }
+        }
+
+        return runs
+    }
+
+    // WriteImage writes the non zero pages and returns their count.
+    // image: magic, version, count of runs, then count of (addr, page count, compressed size,
+    // the lz4 compressed pages)
+    fn WriteImage(path: &str, pages: &[u64]) -> io::Result<usize> {
+        let runs = Self::Runs(pages);
+
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(&IMAGE_MAGIC.to_le_bytes())?;
+        w.write_all(&IMAGE_VERSION.to_le_bytes())?;
+        w.write_all(&(runs.len() as u64).to_le_bytes())?;
+        let mut stored = 0;
+        for &(addr, count) in &runs {
+            let data = unsafe {
+                std::slice::from_raw_parts(addr as *const u8, count * MemoryDef::PAGE_SIZE as usize)
+            };
+
+            let compressed = lz4_flex::compress(data);
+            w.write_all(&addr.to_le_bytes())?;
+            w.write_all(&(count as u64).to_le_bytes())?;
+            w.write_all(&(compressed.len() as u64).to_le_bytes())?;
+            w.write_all(&compressed)?;
+            stored += count;
+        }
+
+        w.flush()?;
+        w.get_ref().sync_all()?;
+        return Ok(stored)
+    }
+
+    fn ReadU64(r: &mut impl Read) -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        r.read_exact(&mut buf)?;
+        return Ok(u64::from_le_bytes(buf))
+    }
+
+    fn Invalid(path: &str) -> io::Error {
+        return io::Error::new(io::ErrorKind::InvalidData, format!("hibernate image {} is invalid", path))
+    }
+
+    // ReadImage restores the pages of the image and returns their count
+    fn ReadImage(path: &str) -> io::Result<usize> {
+        let mut r = BufReader::new(File::open(path)?);
+        if Self::ReadU64(&mut r)? != IMAGE_MAGIC || Self::ReadU64(&mut r)? != IMAGE_VERSION {
+            return Err(Self::Invalid(path))
+        }
+
+        let runs = Self::ReadU64(&mut r)?;
+        let mut restored = 0;
+        let mut compressed = Vec::new();
+        for _ in 0..runs {
+            let addr = Self::ReadU64(&mut r)?;
+            let count = Self::ReadU64(&mut r)? as usize;
+            let size = Self::ReadU64(&mut r)? as usize;
+            if count == 0 || count > IMAGE_RUN_PAGES || size > lz4_flex::block::get_maximum_output_size(count * MemoryDef::PAGE_SIZE as usize) {
+                return Err(Self::Invalid(path))
+            }
+
+            compressed.resize(size, 0);
+            r.read_exact(&mut compressed)?;
+            let len = count * MemoryDef::PAGE_SIZE as usize;
+            let data = match lz4_flex::decompress(&compressed, len) {
+                Ok(data) if data.len() == len => data,
+                _ => return Err(Self::Invalid(path)),
+            };
+
+            let page = unsafe {
+                std::slice::from_raw_parts_mut(addr as *mut u8, len)
+            };
+            page.copy_from_slice(&data);
+            restored += count;
+        }
+
+        return Ok(restored)
+    }
+
+    // FreePages releases the host memory of the pages, the contiguous pages are freed together
+    fn FreePages(pages: &[u64]) {
+        let mut i = 0;
+        while i < pages.len() {
+            let start = pages[i];
+            let mut end = start + MemoryDef::PAGE_SIZE;
+            i += 1;
+            while i < pages.len() && pages[i] == end {
+                end += MemoryDef::PAGE_SIZE;
+                i += 1;
+            }
+
+            let ret = unsafe {
+                madvise(start as *mut c_void, (end - start) as usize, MADV_DONTNEED)
+            };
+
+            if ret < 0 {
+                error!("hibernate: free {:x}-{:x} fail with errno {}", start, end, errno::errno().0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const TEST_PAGES: usize = 8;
+
+    // Pages maps the anonymous memory of the test pages
+    fn Pages() -> Vec<u64> {
+        let len = TEST_PAGES * MemoryDef::PAGE_SIZE as usize;
+        let addr = unsafe {
+            mmap(core::ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        };
+        assert!(addr != MAP_FAILED);
+
+        return (0..TEST_PAGES).map(|i| addr as u64 + i as u64 * MemoryDef::PAGE_SIZE).collect()
+    }
+
+    fn Page(addr: u64) -> &'static mut [u8] {
+        return unsafe {
+            std::slice::from_raw_parts_mut(addr as *mut u8, MemoryDef::PAGE_SIZE as usize)
+        }
+    }
+
+    #[test]
+    fn test_image_restore() {
+        let pages = Pages();
+        // the pages 2 and 5 stay zero, 0 and 1 are in one run
+        for &i in &[0usize, 1, 3, 4, 6, 7] {
+            for (j, b) in Page(pages[i]).iter_mut().enumerate() {
+                *b = (i * 7 + j / 64) as u8;
+            }
+        }
+        let expect: Vec<Vec<u8>> = pages.iter().map(|&p| Page(p).to_vec()).collect();
+        assert_eq!(Hibernator::Runs(&pages), vec![(pages[0], 2), (pages[3], 2), (pages[6], 2)]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sub").join("test.img");
+        let path = path.to_str().unwrap();
+        assert_eq!(Hibernator::WriteImage(path, &pages).unwrap(), 6);
+        // the repeated bytes are compressed
+        assert!(fs::metadata(path).unwrap().len() < 2 * MemoryDef::PAGE_SIZE);
+
+        Hibernator::FreePages(&pages);
+        assert!(pages.iter().all(|&p| Hibernator::IsZeroPage(p)));
+
+        assert_eq!(Hibernator::ReadImage(path).unwrap(), 6);
+        for (i, &p) in pages.iter().enumerate() {
+            assert_eq!(Page(p), &expect[i][..]);
+        }
+    }
+
+    #[test]
+    fn test_image_invalid() {
+        let pages = Pages();
+        Page(pages[0])[0] = 1;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.img");
+        let path = path.to_str().unwrap();
+        Hibernator::WriteImage(path, &pages).unwrap();
+
+        // a truncated image fails instead of restoring a part of the pages
+        let data = fs::read(path).unwrap();
+        fs::write(path, &data[..data.len() - 1]).unwrap();
+        assert!(Hibernator::ReadImage(path).is_err());
+
+        let mut bad = data.clone();
+        bad[8] = 9;
+        fs::write(path, &bad).unwrap();
+        assert_eq!(Hibernator::ReadImage(path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        assert!(Hibernator::ReadImage(dir.path().join("none.img").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_cancel_writing() {
+        let h = Hibernator::New();
+        let pages = Pages();
+        Page(pages[0])[0] = 1;
+        {
+            let mut status = h.status.lock().unwrap();
+            h.SetState(&mut status, HibernateState::Writing);
+        }
+
+        // the vcpu runs before the image is written, the pages are kept
+        h.OnVcpuRun().unwrap();
+        assert_eq!(h.State(), HibernateState::Running);
+        assert!(!h.Hibernated());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.img").to_str().unwrap().to_string();
+        let res = Hibernator::WriteImage(&path, &pages);
+        h.EndWrite(path.clone(), &pages, res);
+        assert_eq!(h.State(), HibernateState::Running);
+        assert_eq!(Page(pages[0])[0], 1);
+        assert!(!Path::new(&path).exists());
+    }
+
+    #[test]
+    fn test_wait_restore() {
+        let h = Arc::new(Hibernator::New());
+        {
+            let mut status = h.status.lock().unwrap();
+            h.SetState(&mut status, HibernateState::Hibernated);
+            status.image = Some(HibernateImage {
+                path: String::from("test.img"),
+                pages: 1,
+                stored: 1,
+            });
+        }
+
+        let image = h.StartResume().unwrap().unwrap();
+        assert_eq!(h.State(), HibernateState::Restoring);
+
+        // the other vcpu waits for the restore
+        let done = Arc::new(AtomicBool::new(false));
+        let (h1, done1) = (h.clone(), done.clone());
+        let waiter = thread::spawn(move || {
+            let res = h1.StartResume();
+            done1.store(true, Ordering::SeqCst);
+            res
+        });
+        thread::sleep(std::time::Duration::from_millis(10));
+        assert!(!done.load(Ordering::SeqCst));
+
+        let mut status = h.status.lock().unwrap();
+        h.SetState(&mut status, HibernateState::Running);
+        status.image = Some(image);
+        drop(status);
+        h.cond.notify_all();
+        assert!(waiter.join().unwrap().unwrap().is_none());
+
+        // the vcpus of the sandbox which can't be restored fail
+        let mut status = h.status.lock().unwrap();
+        h.SetState(&mut status, HibernateState::Failed);
+        drop(status);
+        assert!(h.OnVcpuRun().is_err());
+        assert!(h.ResumeIfHibernated().is_err());
+    }
+}
@@ -14,6 +14,8 @@
 
 use core::mem;
 use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicI64;
+use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering;
 
 use super::super::qlib::uring::util::*;
@@ -25,6 +27,7 @@ use super::super::qlib::mutex::QMutex;
 use super::super::util::*;
 use super::super::*;
 use super::syscall::*;
+use super::io_backend::*;
 
 impl Mmap {
     pub fn new(fd: i32, offset: u64, len: usize) -> Result<Mmap> {
@@ -103,6 +106,13 @@ impl IoUring {
             fd: Fd(fd),
             lock: QMutex::new(()),
             pendingCnt: AtomicU64::new(0),
+            ioThreadWaiting: AtomicBool::new(false),
+            ownIOThread: AtomicBool::new(false),
+            hostReapTsc: AtomicI64::new(0),
+            cqSeenTsc: AtomicI64::new(0),
+            wakeups: AtomicU64::new(0),
+            wakeupCycles: AtomicU64::new(0),
+            wakeupMaxCycles: AtomicU64::new(0),
             sq: QMutex::new(sq),
             cq: QMutex::new(cq),
             params: Parameters(p),
@@ -116,8 +126,9 @@ impl IoUring {
         self.submitter().submit()
     }
 
+    // HostSubmit submits the requests the guest pushed to the uring idx through the io backend
     #[inline]
-    pub fn HostSubmit(&self) -> Result<usize> {
+    pub fn HostSubmit(&self, idx: usize) -> Result<usize> {
         let uringCnt = QUARK_CONFIG.lock().DedicateUring;
         if uringCnt != 0 {
             return Ok(0)
@@ -137,9 +148,7 @@ impl IoUring {
             return Ok(0);
         }
 
-        let ret = unsafe {
-            self.submitter().enter(count as u32, 0, 0)
-        };
+        let ret = Backend().Enter(idx, self, count as u32, 0, 0);
         //error!("HostSubmit_xxx 2");
         return ret;
     }
@@ -0,0 +1,177 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::boxed::Box;
+use spin::Once;
+
+use super::super::qlib::common::*;
+use super::super::qlib::config::*;
+use super::super::qlib::uring::sys::sys::*;
+use super::super::qlib::uring::*;
+use super::super::*;
+use super::epoll_backend::*;
+use super::host_uring::*;
+
+// IoBackend serves the guest urings on the host. The guest always talks to the shared rings,
+// the backend either hands them to the host kernel io_uring or emulates io_uring over them.
+pub trait IoBackend: Send + Sync {
+    fn Name(&self) -> &'static str;
+
+    // NewRing creates the ring idx with sqpoll thread on sqpollCpu if it is set
+    fn NewRing(&self, idx: usize, entries: u32, cqEntries: u32, sqpollCpu: Option<u32>) -> Result<IoUring>;
+
+    // Enter is io_uring_enter of the ring idx, it returns the count of the submitted entries
+    fn Enter(&self, idx: usize, ring: &IoUring, toSubmit: u32, minComplete: u32, flags: u32) -> Result<usize>;
+
+    // Register is io_uring_register of the ring idx
+    fn Register(&self, idx: usize, ring: &IoUring, opcode: u32, arg: u64, nrArgs: u32) -> Result<()>;
+
+    // ProbeOps returns the bitmap of the supported IORING_OP_*
+    fn ProbeOps(&self, ring: &IoUring) -> u64;
+}
+
+pub static IO_BACKEND: Once<Box<dyn IoBackend>> = Once::new();
+
+#[inline]
+pub fn Backend() -> &'static dyn IoBackend {
+    return IO_BACKEND.get().expect("io backend is not selected").as_ref();
+}
+
+// HostUringAvailable checks whether the host allows io_uring_setup, it is missing before linux
+// 5.1 and could be blocked by the seccomp profile, the LSM or kernel.io_uring_disabled
+pub fn HostUringAvailable() -> bool {
+    let mut p = io_uring_params::default();
+    let fd = IOUringSetup(4, &mut p as * mut _ as u64);
+    if fd < 0 {
+        info!("io backend: io_uring_setup fail {}", fd);
+        return false
+    }
+
+    unsafe {
+        libc::close(fd as i32);
+    }
+
+    return true
+}
+
+// SelectIoBackend picks the io backend by the IoBackend config and adjusts the config to it,
+// it is called before the config is copied to the share space
+pub fn SelectIoBackend() {
+    let mut config = QUARK_CONFIG.lock();
+    let epoll = match config.IoBackend {
+        IoBackendMode::Uring => false,
+        IoBackendMode::Epoll => true,
+        IoBackendMode::Auto => !HostUringAvailable(),
+    };
+
+    match config.ApplyIoBackend(epoll) {
+        Ok(notes) => {
+            for note in notes {
+                info!("config: {}", note);
+            }
+        }
+        Err(e) => panic!("the io backend doesn't support the config: {:?}", e),
+    }
+
+    IO_BACKEND.call_once(|| -> Box<dyn IoBackend> {
+        if epoll {
+            Box::new(EpollBackend::New())
+        } else {
+            Box::new(UringBackend::default())
+        }
+    });
+
+    info!("io backend: {} ({:?})", Backend().Name(), config.IoBackend);
+}
+
+// UringBackend runs the guest urings on the host kernel io_uring
+#[derive(Default)]
+pub struct UringBackend {}
+
+impl IoBackend for UringBackend {
+    fn Name(&self) -> &'static str {
+        return "io_uring"
+    }
+
+    fn NewRing(&self, _idx: usize, entries: u32, cqEntries: u32, sqpollCpu: Option<u32>) -> Result<IoUring> {
+        let mut builder = Builder::default();
+        if let Some(cpu) = sqpollCpu {
+            builder.setup_sqpoll(10)
+                .setup_sqpoll_cpu(cpu);
+        }
+
+        return builder
+            .setup_cqsize(cqEntries)
+            .build(entries);
+    }
+
+    fn Enter(&self, _idx: usize, ring: &IoUring, toSubmit: u32, minComplete: u32, flags: u32) -> Result<usize> {
+        let ret = IOUringEnter(ring.fd.0, toSubmit, minComplete, flags);
+        if ret < 0 {
+            return Err(Error::SysError(-ret as i32))
+        }
+
+        return Ok(ret as usize)
+    }
+
+    fn Register(&self, _idx: usize, ring: &IoUring, opcode: u32, arg: u64, nrArgs: u32) -> Result<()> {
+        let ret = IOUringRegister(ring.fd.0, opcode, arg, nrArgs);
+        if ret < 0 {
+            error!("IOUringRegister get fail {}", ret);
+            return Err(Error::SysError(-ret as i32))
+        }
+
+        return Ok(())
+    }
+
+    // The probe comes with linux 5.6, the older kernels get the opcodes of their version.
+    fn ProbeOps(&self, ring: &IoUring) -> u64 {
+        const PROBE_OPS_CNT : usize = 64;
+
+        #[repr(C)]
+        struct ProbeBuf {
+            probe: io_uring_probe,
+            ops: [io_uring_probe_op; PROBE_OPS_CNT],
+        }
+
+        let mut buf = ProbeBuf {
+            probe: io_uring_probe::default(),
+            ops: [io_uring_probe_op::default(); PROBE_OPS_CNT],
+        };
+
+        let ret = IOUringRegister(ring.fd.0, IORING_REGISTER_PROBE, &mut buf as * mut _ as u64, PROBE_OPS_CNT as u32);
+        if ret < 0 {
+            // linux 5.5 brings IORING_FEAT_NODROP together with accept, connect and async cancel
+            let last = if ring.params().is_feature_nodrop() {
+                IORING_OP_CONNECT
+            } else {
+                IORING_OP_TIMEOUT
+            };
+
+            info!("uring probe fail {}, the supported opcodes are up to {}", ret, last);
+            return (1 << (last + 1)) - 1;
+        }
+
+        let mut ops = 0;
+        let cnt = core::cmp::min(buf.probe.ops_len as usize, PROBE_OPS_CNT);
+        for op in &buf.ops[..cnt] {
+            if op.flags & IO_URING_OP_SUPPORTED as u16 != 0 && (op.op as usize) < PROBE_OPS_CNT {
+                ops |= 1 << op.op;
+            }
+        }
+
+        info!("uring supported opcodes {:x}", ops);
+        return ops;
+    }
+}
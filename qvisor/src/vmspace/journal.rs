@@ -0,0 +1,390 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use spin::Mutex;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use std::thread;
+use libc::*;
+
+use super::super::runc::runtime::vm::IsRunning;
+use super::super::QUARK_CONFIG;
+
+pub const JOURNAL_DIR: &str = "/var/log/quark/journal";
+
+const JOURNAL_MAGIC: u64 = 0x4c4e_5255_4f4a_4b51; // "QKJOURNL"
+const JOURNAL_VERSION: u32 = 1;
+
+// the journal file is an array of fixed size slots, slot 0 is the header and the records are
+// written to the other slots round robin by the seq number
+pub const SLOT_SIZE: usize = 256;
+// seq u64 | time u64 | kind u32 | len u32 | crc u32 | payload
+const RECORD_HEAD: usize = 28;
+pub const PAYLOAD_MAX: usize = SLOT_SIZE - RECORD_HEAD;
+const MIN_SLOTS: u64 = 16;
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JournalKind {
+    Start = 1,
+    Exit,
+    Oom,
+    Panic,
+    Hibernate,
+    Resume,
+    Snapshot,
+}
+
+impl JournalKind {
+    pub fn FromU32(v: u32) -> Option<Self> {
+        let kind = match v {
+            1 => Self::Start,
+            2 => Self::Exit,
+            3 => Self::Oom,
+            4 => Self::Panic,
+            5 => Self::Hibernate,
+            6 => Self::Resume,
+            7 => Self::Snapshot,
+            _ => return None,
+        };
+
+        return Some(kind)
+    }
+
+    pub fn Name(&self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Exit => "exit",
+            Self::Oom => "oom",
+            Self::Panic => "panic",
+            Self::Hibernate => "hibernate",
+            Self::Resume => "resume",
+            Self::Snapshot => "snapshot",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalRecord {
+    pub seq: u64,
+    // realtime in ns
+    pub time: u64,
+    pub kind: JournalKind,
+    pub payload: String,
+}
+
+// Crc32 is the IEEE crc32 of the record, a slot torn by the crash fails the check
+fn Crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    return !crc
+}
+
+impl JournalRecord {
+    pub fn Encode(&self) -> [u8; SLOT_SIZE] {
+        let mut payload = self.payload.as_bytes();
+        if payload.len() > PAYLOAD_MAX {
+            let mut end = PAYLOAD_MAX;
+            while !self.payload.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload = &payload[..end];
+        }
+
+        let mut slot = [0u8; SLOT_SIZE];
+        slot[0..8].copy_from_slice(&self.seq.to_le_bytes());
+        slot[8..16].copy_from_slice(&self.time.to_le_bytes());
+        slot[16..20].copy_from_slice(&(self.kind as u32).to_le_bytes());
+        slot[20..24].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        slot[RECORD_HEAD..RECORD_HEAD + payload.len()].copy_from_slice(payload);
+        let crc = Crc32(&slot[RECORD_HEAD..RECORD_HEAD + payload.len()]) ^ Crc32(&slot[0..24]);
+        slot[24..28].copy_from_slice(&crc.to_le_bytes());
+        return slot
+    }
+
+    // Decode returns None for the empty, torn or unknown slot
+    pub fn Decode(slot: &[u8]) -> Option<Self> {
+        let u64At = |i: usize| u64::from_le_bytes([slot[i], slot[i+1], slot[i+2], slot[i+3], slot[i+4], slot[i+5], slot[i+6], slot[i+7]]);
+        let u32At = |i: usize| u32::from_le_bytes([slot[i], slot[i+1], slot[i+2], slot[i+3]]);
+
+        let seq = u64At(0);
+        let len = u32At(20) as usize;
+        if seq == 0 || len > PAYLOAD_MAX {
+            return None
+        }
+
+        let payload = &slot[RECORD_HEAD..RECORD_HEAD + len];
+        if Crc32(payload) ^ Crc32(&slot[0..24]) != u32At(24) {
+            return None
+        }
+
+        return Some(Self {
+            seq: seq,
+            time: u64At(8),
+            kind: JournalKind::FromU32(u32At(16))?,
+            payload: String::from_utf8_lossy(payload).to_string(),
+        })
+    }
+}
+
+struct JournalFile {
+    file: File,
+    // number of the record slots
+    slots: u64,
+    nextSeq: u64,
+}
+
+impl JournalFile {
+    fn Header(slots: u64) -> [u8; SLOT_SIZE] {
+        let mut header = [0u8; SLOT_SIZE];
+        header[0..8].copy_from_slice(&JOURNAL_MAGIC.to_le_bytes());
+        header[8..12].copy_from_slice(&JOURNAL_VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(SLOT_SIZE as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&slots.to_le_bytes());
+        return header
+    }
+
+    // Open continues the journal of the same size, e.g. of the sandbox restarted with the same
+    // id, the other files are reset
+    fn Open(path: &str, slots: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+        let header = Self::Header(slots);
+        let size = (slots + 1) * SLOT_SIZE as u64;
+
+        let mut nextSeq = 1;
+        let mut cur = [0u8; SLOT_SIZE];
+        let valid = file.metadata()?.len() == size
+            && file.read_exact_at(&mut cur, 0).is_ok()
+            && cur[..] == header[..];
+        if valid {
+            for r in ReadRecords(&file, slots)? {
+                nextSeq = core::cmp::max(nextSeq, r.seq + 1);
+            }
+        } else {
+            file.set_len(0)?;
+            file.set_len(size)?;
+            file.write_all_at(&header, 0)?;
+            file.sync_all()?;
+        }
+
+        return Ok(Self {
+            file: file,
+            slots: slots,
+            nextSeq: nextSeq,
+        })
+    }
+
+    fn Append(&mut self, kind: JournalKind, payload: &str, sync: bool) -> io::Result<()> {
+        let record = JournalRecord {
+            seq: self.nextSeq,
+            time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64,
+            kind: kind,
+            payload: payload.to_string(),
+        };
+
+        self.nextSeq += 1;
+        let offset = (1 + record.seq % self.slots) * SLOT_SIZE as u64;
+        self.file.write_all_at(&record.Encode(), offset)?;
+        if sync {
+            self.file.sync_data()?;
+        }
+
+        return Ok(())
+    }
+}
+
+fn ReadRecords(file: &File, slots: u64) -> io::Result<Vec<JournalRecord>> {
+    let mut records = Vec::new();
+    let mut slot = [0u8; SLOT_SIZE];
+    for i in 0..slots {
+        file.read_exact_at(&mut slot, (i + 1) * SLOT_SIZE as u64)?;
+        if let Some(r) = JournalRecord::Decode(&slot) {
+            records.push(r);
+        }
+    }
+
+    records.sort_by_key(|r| r.seq);
+    return Ok(records)
+}
+
+// ReadJournal returns the records of the journal file from the oldest one
+pub fn ReadJournal(path: &str) -> io::Result<Vec<JournalRecord>> {
+    let file = File::open(path)?;
+    let mut header = [0u8; SLOT_SIZE];
+    file.read_exact_at(&mut header, 0)?;
+    let slots = u64::from_le_bytes([header[16], header[17], header[18], header[19], header[20], header[21], header[22], header[23]]);
+    if header[..] != JournalFile::Header(slots)[..] {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("journal {} is invalid", path)))
+    }
+
+    return ReadRecords(&file, slots)
+}
+
+pub fn JournalPath(id: &str) -> String {
+    return format!("{}/{}.journal", JOURNAL_DIR, id)
+}
+
+pub static JOURNAL: Journal = Journal::New();
+
+// Journal records the lifecycle events, the fatal errors and the periodic resource snapshots of
+// the sandbox in a file on the host, which is kept after the sandbox is gone for the post
+// incident analysis. The journal is a ring of JournalSize KB, the oldest records are overwritten.
+// The events are synced to the disk before qvisor goes on, e.g. exits on OOM.
+pub struct Journal {
+    file: Mutex<Option<JournalFile>>,
+}
+
+impl Journal {
+    pub const fn New() -> Self {
+        return Self {
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn Enabled(&self) -> bool {
+        return self.file.lock().is_some()
+    }
+
+    pub fn Open(&self, id: &str) {
+        let size = QUARK_CONFIG.lock().JournalSize * 1024;
+        if size == 0 {
+            return
+        }
+
+        let slots = core::cmp::max(size / SLOT_SIZE as u64 - 1, MIN_SLOTS);
+        let path = JournalPath(id);
+        let ret = fs::create_dir_all(JOURNAL_DIR).and_then(|_| JournalFile::Open(&path, slots));
+        match ret {
+            Err(e) => error!("journal: open {} fail with error {:?}", &path, e),
+            Ok(f) => *self.file.lock() = Some(f),
+        }
+    }
+
+    pub fn Record(&self, kind: JournalKind, payload: &str) {
+        self.Append(kind, payload, true);
+    }
+
+    fn Append(&self, kind: JournalKind, payload: &str, sync: bool) {
+        if let Some(f) = self.file.lock().as_mut() {
+            if let Err(e) = f.Append(kind, payload, sync) {
+                error!("journal: write {} record fail with error {:?}", kind.Name(), e);
+            }
+        }
+    }
+
+    // Snapshot is the resource usage of the qvisor process
+    pub fn Snapshot() -> String {
+        let mut usage: rusage = unsafe { core::mem::zeroed() };
+        unsafe {
+            getrusage(RUSAGE_SELF, &mut usage);
+        }
+
+        // the second field of statm is the resident pages
+        let rss = fs::read_to_string("/proc/self/statm").ok()
+            .and_then(|s| s.split_whitespace().nth(1).and_then(|v| v.parse::<u64>().ok()))
+            .unwrap_or(0) * 4;
+        let fds = fs::read_dir("/proc/self/fd").map(|d| d.count()).unwrap_or(0);
+        let ms = |tv: timeval| tv.tv_sec as i64 * 1000 + tv.tv_usec as i64 / 1000;
+
+        return format!("rss={}KB maxrss={}KB utime={}ms stime={}ms fds={} majflt={} nvcsw={} nivcsw={}",
+                       rss, usage.ru_maxrss, ms(usage.ru_utime), ms(usage.ru_stime), fds,
+                       usage.ru_majflt, usage.ru_nvcsw, usage.ru_nivcsw)
+    }
+
+    // Run records the snapshots every JournalSnapshotInterval seconds until the vm exits
+    pub fn Run(&self) {
+        let interval = QUARK_CONFIG.lock().JournalSnapshotInterval;
+        if interval == 0 {
+            return
+        }
+
+        let mut elapsed = 0;
+        while IsRunning() {
+            thread::sleep(Duration::from_secs(1));
+            elapsed += 1;
+            if elapsed >= interval {
+                elapsed = 0;
+                // the snapshots are not synced, the loss of the last ones on crash is fine
+                self.Append(JournalKind::Snapshot, &Self::Snapshot(), false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Record(seq: u64, payload: &str) -> JournalRecord {
+        return JournalRecord {
+            seq: seq,
+            time: 1,
+            kind: JournalKind::Snapshot,
+            payload: payload.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let r = Record(1, "rss=1KB");
+        assert_eq!(JournalRecord::Decode(&r.Encode()), Some(r));
+
+        let long = "x".repeat(SLOT_SIZE);
+        let r = JournalRecord::Decode(&Record(2, &long).Encode()).unwrap();
+        assert_eq!(r.payload.len(), PAYLOAD_MAX);
+
+        // the torn slot is skipped
+        let mut slot = Record(3, "oom").Encode();
+        slot[RECORD_HEAD] = b'x';
+        assert_eq!(JournalRecord::Decode(&slot), None);
+        assert_eq!(JournalRecord::Decode(&[0u8; SLOT_SIZE]), None);
+    }
+
+    #[test]
+    fn test_ring() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.journal").to_str().unwrap().to_string();
+        {
+            let mut f = JournalFile::Open(&path, MIN_SLOTS).unwrap();
+            for i in 0..MIN_SLOTS + 3 {
+                f.Append(JournalKind::Snapshot, &format!("{}", i), false).unwrap();
+            }
+        }
+
+        // the oldest records are overwritten, the reopened journal goes on after the last one
+        let records = ReadJournal(&path).unwrap();
+        assert_eq!(records.len(), MIN_SLOTS as usize);
+        assert_eq!(records[0].seq, 4);
+        assert_eq!(records[0].payload, "3");
+
+        let mut f = JournalFile::Open(&path, MIN_SLOTS).unwrap();
+        f.Append(JournalKind::Exit, "code=0", true).unwrap();
+        let records = ReadJournal(&path).unwrap();
+        assert_eq!(records.last().unwrap().seq, MIN_SLOTS + 4);
+        assert_eq!(records.last().unwrap().kind, JournalKind::Exit);
+    }
+}
@@ -13,23 +13,39 @@
 // limitations under the License.
 
 use libc::*;
+use core::sync::atomic::Ordering;
+use std::time::Instant;
 
 use super::super::qlib::ShareSpace;
 use super::super::qlib::common::*;
 use super::super::qlib::linux_def::*;
 use super::super::qlib::kernel::IOURING;
-use super::super::qlib::kernel::TSC;
 use super::super::qlib::kernel::ASYNC_PROCESS;
+use super::super::qlib::kernel::TSC;
 use super::super::runc::runtime::vm::*;
 use super::super::kvm_vcpu::*;
 use super::super::*;
-//use super::HostFileMap::rdma::*;
+use super::hibernate::*;
+#[cfg(feature = "rdma")]
+use super::HostFileMap::rdma::*;
 
 pub struct KIOThread {
     pub eventfd: i32,
 }
 
-pub const IO_WAIT_CYCLES : i64 = 100_000_000; // 1ms
+// the read of the timerfd fails with ECANCELED when the clock is set
+const TFD_TIMER_CANCEL_ON_SET: i32 = 1 << 1;
+
+// the clock watcher timer expires every period so that the watcher sees the vm exit
+const CLOCK_WATCH_PERIOD_SEC: time_t = 1;
+
+// ClockWatchSpec returns the absolute timer of the clock watcher, it expires a period after now
+pub fn ClockWatchSpec(now: &timespec) -> itimerspec {
+    return itimerspec {
+        it_interval: timespec { tv_sec: 0, tv_nsec: 0 },
+        it_value: timespec { tv_sec: now.tv_sec + CLOCK_WATCH_PERIOD_SEC, tv_nsec: now.tv_nsec },
+    }
+}
 
 impl KIOThread {
     pub fn New() -> Self {
@@ -44,36 +60,65 @@ impl KIOThread {
         }
     }
 
+    // SetPriority applies IOThreadNice to the calling io thread, so that the completions are
+    // still reaped in time when the vcpu threads keep the host cpus busy
+    pub fn SetPriority(sharespace: &ShareSpace) {
+        let nice = sharespace.config.read().IOThreadNice;
+        if nice == 0 {
+            return
+        }
+
+        let ret = unsafe {
+            setpriority(PRIO_PROCESS, gettid() as id_t, nice)
+        };
+
+        if ret < 0 {
+            error!("KIOThread::SetPriority nice {} fail, errno is {}", nice, errno::errno().0);
+        }
+    }
+
     pub fn ProcessOnce(sharespace: &ShareSpace) -> usize {
         let mut count = 0;
 
-        /*if QUARK_CONFIG.lock().EnableRDMA {
-            count += RDMA.PollCompletionQueueAndProcess();
-        }*/
+        #[cfg(feature = "rdma")]
+        if QUARK_CONFIG.lock().EnableRDMA {
+            count += RDMA_DEVICES.PollCompletionQueueAndProcess();
+        }
         
-        count += IOURING.IOUrings()[0].HostSubmit().unwrap();
-        count += IOURING.DrainCompletionQueue();
-        count += IOURING.IOUrings()[0].HostSubmit().unwrap();
+        count += IOURING.IOUrings()[0].HostSubmit(0).unwrap();
+        let now = TSC.Rdtsc();
+        for idx in 0..IOURING.UringCount() {
+            if IOURING.SharedUring(idx) {
+                IOURING.IOUrings()[idx].HostReaped(now);
+            }
+        }
+        count += sharespace.ProcessIOCompletion(|| IOURING.DrainSharedCompletionQueue());
+        count += IOURING.IOUrings()[0].HostSubmit(0).unwrap();
         count += KVMVcpu::GuestMsgProcess(sharespace);
-        count += IOURING.IOUrings()[0].HostSubmit().unwrap();
-        count += FD_NOTIFIER.HostEpollWait() as usize;
-        count += IOURING.IOUrings()[0].HostSubmit().unwrap();
+        count += IOURING.IOUrings()[0].HostSubmit(0).unwrap();
+        count += sharespace.ProcessIOCompletion(|| FD_NOTIFIER.HostEpollWait() as usize);
+        count += IOURING.IOUrings()[0].HostSubmit(0).unwrap();
 
         sharespace.CheckVcpuTimeout();
+        sharespace.CheckProfileSample();
+        sharespace.FlushWakeup(false);
 
         return count;
     }
 
+    // Process polls until nothing comes for the io spin budget, it is measured with the
+    // monotonic clock as the TSC rate changes with the cpu frequency on some hosts
     pub fn Process(sharespace: &ShareSpace) {
-        let mut start = TSC.Rdtsc();
+        let budget = sharespace.IOSpinNs() as u128;
+        let mut start = Instant::now();
 
         while IsRunning() {
             let count = Self::ProcessOnce(sharespace);
             if count > 0 {
-                start = TSC.Rdtsc()
+                start = Instant::now()
             }
 
-            if TSC.Rdtsc() - start >= IO_WAIT_CYCLES {
+            if start.elapsed().as_nanos() >= budget {
                 break;
             }
         }
@@ -118,6 +163,7 @@ impl KIOThread {
 
         let mut events = [epoll_event { events: 0, u64: 0 }; 2];
 
+        Self::SetPriority(sharespace);
         let mut data : u64 = 0;
         loop {
             sharespace.IncrHostProcessor();
@@ -125,6 +171,7 @@ impl KIOThread {
                 return Err(Error::Exit)
             }
 
+            HIBERNATOR.ResumeAsync();
             Self::Process(sharespace);
 
             let ret = unsafe {
@@ -141,22 +188,115 @@ impl KIOThread {
             }
 
             // when there is ready task, wake up for preemptive schedule
-            let waitTime = if sharespace.scheduler.GlobalReadyTaskCnt() > 0 {
-                10
+            let mut waitTime = if sharespace.scheduler.GlobalReadyTaskCnt() > 0 {
+                sharespace.NextVcpuTimeout()
             } else {
                 -1
             };
 
-            ASYNC_PROCESS.Process();
-            /*if QUARK_CONFIG.lock().EnableRDMA {
-                RDMA.HandleCQEvent()?;
-            }*/
+            if HIBERNATOR.CheckIdle(sharespace) && (waitTime < 0 || waitTime > HIBERNATE_CHECK_INTERVAL) {
+                waitTime = HIBERNATE_CHECK_INTERVAL;
+            }
+
+            ASYNC_PROCESS.ClockTick();
+            sharespace.FlushWakeup(true);
+            #[cfg(feature = "rdma")]
+            if QUARK_CONFIG.lock().EnableRDMA {
+                RDMA_DEVICES.HandleCQEvent()?;
+            }
             let _nfds = unsafe {
                 epoll_wait(epfd, &mut events[0], 2, waitTime)
             };
         }
     }
 
+    // RunUringThread serves the uring idx (>0) when IOThreadCount > 1: submits the guest requests
+    // and processes the completions of the uring. It blocks on its eventfd when idle, which is
+    // signaled by the guest submission (UringWake) or the uring completion.
+    pub fn RunUringThread(idx: usize, eventfd: i32) {
+        let sharespace = &SHARE_SPACE;
+        let uring = &IOURING.IOUrings()[idx];
+        let mut data : u64 = 0;
+        let budget = sharespace.IOSpinNs() as u128;
+
+        // the kernel io thread leaves the uring to this thread
+        uring.ownIOThread.store(true, Ordering::Release);
+        Self::SetPriority(sharespace);
+        while IsRunning() {
+            let mut start = Instant::now();
+            while IsRunning() {
+                let mut count = uring.HostSubmit(idx).unwrap();
+                uring.HostReaped(TSC.Rdtsc());
+                count += sharespace.ProcessIOCompletion(|| IOURING.DrainCompletionQueueOne(idx));
+                sharespace.FlushWakeup(false);
+                if count > 0 {
+                    start = Instant::now()
+                }
+
+                if start.elapsed().as_nanos() >= budget {
+                    break;
+                }
+            }
+
+            sharespace.FlushWakeup(true);
+            uring.ioThreadWaiting.store(true, Ordering::SeqCst);
+            if uring.pendingCnt.load(Ordering::SeqCst) == 0 && uring.cq.lock().len() == 0 {
+                let ret = unsafe {
+                    libc::read(eventfd, &mut data as * mut _ as *mut libc::c_void, 8)
+                };
+
+                if ret < 0 && errno::errno().0 != SysErr::EINTR {
+                    panic!("KIOThread::RunUringThread {} read eventfd fail, errno is {}", idx, errno::errno().0);
+                }
+            }
+            uring.ioThreadWaiting.store(false, Ordering::SeqCst);
+        }
+    }
+
+    // RunClockWatcher waits for the host CLOCK_REALTIME to be set (settimeofday, NTP step) and
+    // notifies the kernel through the share space, the kernel handles it in the async process.
+    pub fn RunClockWatcher(sharespace: &ShareSpace) {
+        let fd = unsafe {
+            timerfd_create(CLOCK_REALTIME, TFD_CLOEXEC)
+        };
+
+        if fd < 0 {
+            error!("KIOThread::RunClockWatcher create timerfd fail, errno is {}", errno::errno().0);
+            return
+        }
+
+        let mut data : u64 = 0;
+        while IsRunning() {
+            let mut now = timespec { tv_sec: 0, tv_nsec: 0 };
+            unsafe {
+                clock_gettime(CLOCK_REALTIME, &mut now);
+            }
+
+            let spec = ClockWatchSpec(&now);
+            let ret = unsafe {
+                timerfd_settime(fd, TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET, &spec, core::ptr::null_mut())
+            };
+
+            if ret < 0 {
+                error!("KIOThread::RunClockWatcher set timerfd fail, errno is {}", errno::errno().0);
+                break;
+            }
+
+            let ret = unsafe {
+                libc::read(fd, &mut data as * mut _ as *mut libc::c_void, 8)
+            };
+
+            if ret < 0 && errno::errno().0 == SysErr::ECANCELED {
+                sharespace.HostClockSet();
+                KERNEL_IO_THREAD.Wakeup(sharespace);
+            }
+        }
+
+        unsafe {
+            close(fd);
+        }
+    }
+
     pub fn Wakeup(&self, _sharespace: &ShareSpace) {
         let val : u64 = 1;
         let ret = unsafe {
@@ -169,3 +309,39 @@ impl KIOThread {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_watch_spec() {
+        let now = timespec { tv_sec: 1_700_000_000, tv_nsec: 500 };
+        let spec = ClockWatchSpec(&now);
+        assert_eq!(spec.it_value.tv_sec, now.tv_sec + CLOCK_WATCH_PERIOD_SEC);
+        assert_eq!(spec.it_value.tv_nsec, 500);
+        assert_eq!(spec.it_interval.tv_sec, 0);
+        assert_eq!(spec.it_interval.tv_nsec, 0);
+    }
+
+    #[test]
+    fn test_clock_watch_expires() {
+        let fd = unsafe { timerfd_create(CLOCK_REALTIME, TFD_CLOEXEC) };
+        assert!(fd >= 0);
+
+        let mut now = timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { clock_gettime(CLOCK_REALTIME, &mut now) };
+        let spec = ClockWatchSpec(&now);
+        let ret = unsafe {
+            timerfd_settime(fd, TFD_TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET, &spec, core::ptr::null_mut())
+        };
+        assert_eq!(ret, 0);
+
+        // the read returns after the period instead of blocking till the vm exits
+        let mut data : u64 = 0;
+        let ret = unsafe { libc::read(fd, &mut data as * mut _ as *mut libc::c_void, 8) };
+        assert_eq!(ret, 8);
+        assert_eq!(data, 1);
+        unsafe { close(fd) };
+    }
+}
@@ -0,0 +1,935 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The loopback fast path connects the TCP sockets of the sandboxes on one host with a ring pair
+// in the shared memory instead of the host TCP stack. Each TCP listener of the guest gets a unix
+// listener, which is published in LOOPBACK_DIR as "<a.b.c.d>:<port>" for each host address the
+// listener is bound to. When the guest connects to a published address, the host socket is
+// replaced with a unix connection to the listener. The client is bound to an abstract name with
+// the inet addresses of both ends, so that the server gets them with the accept and both guests
+// see the inet addresses of the connection in getsockname/getpeername. The client creates the
+// shared memory of the ring pair and sends it first on the connection, then the connection only
+// carries the doorbells of the rings.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::vec::Vec;
+use rand::RngCore;
+use spin::Mutex;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use libc::*;
+
+use super::super::qlib::linux_def::*;
+use super::super::qlib::loopback_buf::LOOPBACK_SHM_SIZE;
+use super::super::IO_MGR;
+use super::super::QUARK_CONFIG;
+use super::super::URING_MGR;
+use super::conn_pool::ConnPool;
+use super::HostFileMap::fdinfo::*;
+
+pub static LOOPBACK: Loopback = Loopback::New();
+
+pub const LOOPBACK_DIR: &str = "/var/run/quark/loopback";
+// the abstract name of the client is "<prefix>/<pid>/<nonce>/<hex of the ports and ips>"
+pub const LOOPBACK_CLIENT_PREFIX: &str = "quark-loopback";
+// the local port of the unbound client is picked from the range
+pub const LOOPBACK_PORT_START: u32 = 32768;
+pub const LOOPBACK_PORT_END: u32 = 61000;
+// the MSS of the host loopback device, it is what TCP_MAXSEG and TCP_INFO report
+pub const LOOPBACK_MSS: i32 = 65483;
+pub const TCP_INFO_LEN: usize = 104;
+
+pub type SockAddrIn = [u8; 16];
+
+pub enum ShmState {
+    // the shared memory is to be received from the client
+    Recv,
+    // hostfd of the shared memory, it is not taken by the guest yet
+    Ready(i32),
+    Taken,
+}
+
+pub struct LoopbackConn {
+    pub local: SockAddrIn,
+    pub peer: SockAddrIn,
+    pub shm: ShmState,
+    // the TCP and IP options set by the guest
+    pub opts: BTreeMap<(i32, i32), Vec<u8>>,
+}
+
+pub struct Loopback {
+    // hostfd of the unix listener -> the paths it is published as
+    listeners: Mutex<Option<BTreeMap<i32, Vec<CString>>>>,
+    // hostfd of the unix connection -> the connection
+    conns: Mutex<Option<BTreeMap<i32, LoopbackConn>>>,
+}
+
+impl Loopback {
+    pub const fn New() -> Self {
+        return Self {
+            listeners: Mutex::new(None),
+            conns: Mutex::new(None),
+        }
+    }
+
+    pub fn Enabled() -> bool {
+        return QUARK_CONFIG.lock().LoopbackFastPath
+    }
+
+    fn NewAddr(ip: &[u8], port: &[u8]) -> SockAddrIn {
+        let mut addr = [0u8; 16];
+        addr[0] = AF_INET as u8;
+        addr[1] = (AF_INET >> 8) as u8;
+        addr[2..4].copy_from_slice(port);
+        addr[4..8].copy_from_slice(ip);
+        return addr
+    }
+
+    fn SockName(osfd: i32) -> Option<SockAddrIn> {
+        let mut addr = [0u8; 16];
+        let mut len = addr.len() as socklen_t;
+        let ret = unsafe {
+            getsockname(osfd, &mut addr[0] as *mut _ as *mut sockaddr, &mut len)
+        };
+
+        if ret < 0 || addr[0] as i32 | (addr[1] as i32) << 8 != AF_INET {
+            return None
+        }
+
+        return Some(addr)
+    }
+
+    fn PathOf(addr: &SockAddrIn) -> CString {
+        let port = (addr[2] as u16) << 8 | addr[3] as u16;
+        let path = format!("{}/{}.{}.{}.{}:{}", LOOPBACK_DIR, addr[4], addr[5], addr[6], addr[7], port);
+        return CString::new(path).unwrap()
+    }
+
+    // UnixAddr returns the unix address of the name, which is the path with the terminating nul
+    // or the abstract name with the leading nul
+    fn UnixAddr(name: &[u8]) -> Option<(sockaddr_un, socklen_t)> {
+        let mut addr: sockaddr_un = unsafe { core::mem::zeroed() };
+        if name.len() > addr.sun_path.len() {
+            return None
+        }
+
+        addr.sun_family = AF_UNIX as sa_family_t;
+        for i in 0..name.len() {
+            addr.sun_path[i] = name[i] as c_char;
+        }
+
+        let len = core::mem::size_of::<sa_family_t>() + name.len();
+        return Some((addr, len as socklen_t))
+    }
+
+    // ClientName returns the abstract name of the client with the inet addresses of both ends
+    fn ClientName(src: &SockAddrIn, dst: &SockAddrIn) -> Vec<u8> {
+        let mut name = format!("\0{}/{}/{:016x}/", LOOPBACK_CLIENT_PREFIX, std::process::id(), rand::thread_rng().next_u64());
+        for b in src[2..8].iter().chain(dst[2..8].iter()) {
+            name += &format!("{:02x}", b);
+        }
+
+        return name.into_bytes()
+    }
+
+    // ParseClientName returns the inet addresses of the client and of the address it connects to
+    fn ParseClientName(name: &[u8]) -> Option<(SockAddrIn, SockAddrIn)> {
+        let name = core::str::from_utf8(name.strip_prefix(b"\0")?).ok()?;
+        let mut parts = name.split('/');
+        if parts.next()? != LOOPBACK_CLIENT_PREFIX {
+            return None
+        }
+
+        let hex = parts.nth(2)?;
+        if hex.len() != 24 || parts.next().is_some() {
+            return None
+        }
+
+        let mut bytes = [0u8; 12];
+        for i in 0..bytes.len() {
+            bytes[i] = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+        }
+
+        return Some((Self::NewAddr(&bytes[2..6], &bytes[0..2]), Self::NewAddr(&bytes[8..12], &bytes[6..8])))
+    }
+
+    // UnixConnect connects a nonblocking unix socket bound to the abstract name to the path, it
+    // returns the errno on failure
+    fn UnixConnect(path: &CString, name: &[u8]) -> core::result::Result<i32, i32> {
+        let (addr, len) = match Self::UnixAddr(path.as_bytes_with_nul()) {
+            None => return Err(ENAMETOOLONG),
+            Some(a) => a,
+        };
+
+        let (local, localLen) = match Self::UnixAddr(name) {
+            None => return Err(ENAMETOOLONG),
+            Some(a) => a,
+        };
+
+        let fd = unsafe {
+            socket(AF_UNIX, SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC, 0)
+        };
+
+        if fd < 0 {
+            return Err(errno::errno().0)
+        }
+
+        let ret = unsafe {
+            if bind(fd, &local as *const _ as *const sockaddr, localLen) < 0 {
+                -1
+            } else {
+                connect(fd, &addr as *const _ as *const sockaddr, len)
+            }
+        };
+
+        if ret < 0 {
+            let err = errno::errno().0;
+            unsafe {
+                close(fd);
+            }
+            return Err(err)
+        }
+
+        return Ok(fd)
+    }
+
+    // ListenAddrs returns the host addresses of the listening socket, the wildcard address is
+    // all the non loopback IPv4 addresses. The loopback addresses are only of the sandbox.
+    fn ListenAddrs(osfd: i32) -> Vec<SockAddrIn> {
+        let mut ret = Vec::new();
+        let addr = match Self::SockName(osfd) {
+            None => return ret,
+            Some(a) => a,
+        };
+
+        if addr[4] == 127 {
+            return ret
+        }
+
+        if addr[4..8] != [0, 0, 0, 0] {
+            ret.push(addr);
+            return ret
+        }
+
+        let mut ifaddrs: *mut ifaddrs = core::ptr::null_mut();
+        if unsafe { getifaddrs(&mut ifaddrs) } < 0 {
+            return ret
+        }
+
+        let mut ifa = ifaddrs;
+        while !ifa.is_null() {
+            let sa = unsafe { (*ifa).ifa_addr };
+            if !sa.is_null() && unsafe { (*sa).sa_family } as i32 == AF_INET {
+                let ip = unsafe {
+                    core::slice::from_raw_parts((sa as *const u8).offset(4), 4)
+                };
+                if ip[0] != 127 {
+                    ret.push(Self::NewAddr(ip, &addr[2..4]));
+                }
+            }
+            ifa = unsafe { (*ifa).ifa_next };
+        }
+
+        unsafe {
+            freeifaddrs(ifaddrs);
+        }
+
+        return ret
+    }
+
+    // Listen creates the unix listener of the guest TCP listener and publishes it, it returns
+    // the hostfd of the unix listener
+    pub fn Listen(&self, fdInfo: &FdInfo) -> i64 {
+        if !Self::Enabled() {
+            return -SysErr::EOPNOTSUPP as i64
+        }
+
+        let addrs = Self::ListenAddrs(fdInfo.Fd());
+        if addrs.len() == 0 {
+            return -SysErr::EADDRNOTAVAIL as i64
+        }
+
+        if let Err(e) = fs::DirBuilder::new().recursive(true).mode(0o755).create(LOOPBACK_DIR) {
+            error!("loopback: create {} fail {:?}", LOOPBACK_DIR, e);
+            return -SysErr::EOPNOTSUPP as i64
+        }
+
+        let fd = unsafe {
+            socket(AF_UNIX, SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC, 0)
+        };
+
+        if fd < 0 {
+            return -errno::errno().0 as i64
+        }
+
+        let sockPath = CString::new(format!("{}/{}-{}.sock", LOOPBACK_DIR, std::process::id(), fd)).unwrap();
+        let (addr, len) = Self::UnixAddr(sockPath.as_bytes_with_nul()).unwrap();
+        let ret = unsafe {
+            unlink(sockPath.as_ptr());
+            if bind(fd, &addr as *const _ as *const sockaddr, len) < 0 || listen(fd, SOMAXCONN) < 0 {
+                -1
+            } else {
+                0
+            }
+        };
+
+        if ret < 0 {
+            let err = errno::errno().0;
+            unsafe {
+                close(fd);
+            }
+            return -err as i64
+        }
+
+        let mut paths = Vec::new();
+        for addr in &addrs {
+            let path = Self::PathOf(addr);
+            if unsafe { symlink(sockPath.as_ptr(), path.as_ptr()) } < 0 {
+                // the path is left by a sandbox which is gone, or the address is of another
+                // sandbox in the same network namespace
+                if errno::errno().0 != EEXIST || !Self::Stale(&path) {
+                    continue
+                }
+
+                let ret = unsafe {
+                    unlink(path.as_ptr());
+                    symlink(sockPath.as_ptr(), path.as_ptr())
+                };
+
+                if ret < 0 {
+                    continue
+                }
+            }
+
+            paths.push(path);
+        }
+
+        paths.push(sockPath);
+        if paths.len() == 1 {
+            Self::Unlink(&paths);
+            unsafe {
+                close(fd);
+            }
+            return -SysErr::EADDRINUSE as i64
+        }
+
+        let hostfd = IO_MGR.AddSocket(fd);
+        URING_MGR.lock().Addfd(fd).unwrap();
+        self.listeners.lock().get_or_insert_with(BTreeMap::new).insert(hostfd, paths);
+        return hostfd as i64
+    }
+
+    // Stale returns whether the published path is of a qvisor process which is gone
+    fn Stale(path: &CString) -> bool {
+        let target = match fs::read_link(path.to_str().unwrap()) {
+            Err(_) => return false,
+            Ok(t) => t,
+        };
+
+        let pid = target.file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.split('-').next())
+            .and_then(|p| p.parse::<i32>().ok());
+
+        return match pid {
+            None => true,
+            Some(pid) => unsafe { kill(pid, 0) } < 0 && errno::errno().0 == ESRCH,
+        }
+    }
+
+    fn Unlink(paths: &[CString]) {
+        for path in paths {
+            unsafe {
+                unlink(path.as_ptr());
+            }
+        }
+    }
+
+    // SrcAddr returns the local address of the client socket, the unbound socket gets the
+    // address the host routes the destination from and a random port
+    fn SrcAddr(osfd: i32, dst: &SockAddrIn) -> Option<SockAddrIn> {
+        let mut src = Self::SockName(osfd)?;
+        if src[4..8] == [0, 0, 0, 0] {
+            let fd = unsafe {
+                socket(AF_INET, SOCK_DGRAM | SOCK_CLOEXEC, 0)
+            };
+
+            if fd < 0 {
+                return None
+            }
+
+            let route = unsafe {
+                if connect(fd, &dst[0] as *const _ as *const sockaddr, dst.len() as socklen_t) == 0 {
+                    Self::SockName(fd)
+                } else {
+                    None
+                }
+            };
+
+            unsafe {
+                close(fd);
+            }
+
+            src[4..8].copy_from_slice(&route?[4..8]);
+        }
+
+        if src[2] == 0 && src[3] == 0 {
+            let port = LOOPBACK_PORT_START + rand::thread_rng().next_u32() % (LOOPBACK_PORT_END - LOOPBACK_PORT_START);
+            src[2] = (port >> 8) as u8;
+            src[3] = port as u8;
+        }
+
+        return Some(src)
+    }
+
+    // CreateShm creates the shared memory of the ring pair, it is sealed so that the peer can
+    // trust its size
+    fn CreateShm() -> core::result::Result<i32, i32> {
+        let name = CString::new("quark-loopback").unwrap();
+        let fd = unsafe {
+            memfd_create(name.as_ptr(), MFD_CLOEXEC | MFD_ALLOW_SEALING)
+        };
+
+        if fd < 0 {
+            return Err(errno::errno().0)
+        }
+
+        let ret = unsafe {
+            if ftruncate(fd, LOOPBACK_SHM_SIZE as off_t) < 0 {
+                -1
+            } else {
+                fcntl(fd, F_ADD_SEALS, F_SEAL_SHRINK | F_SEAL_GROW | F_SEAL_SEAL)
+            }
+        };
+
+        if ret < 0 {
+            let err = errno::errno().0;
+            unsafe {
+                close(fd);
+            }
+            return Err(err)
+        }
+
+        return Ok(fd)
+    }
+
+    // CheckShm returns whether the shared memory received from the peer can be mapped: it has the
+    // size of the ring pair, which the peer can't change
+    fn CheckShm(fd: i32) -> bool {
+        let mut st: stat = unsafe { core::mem::zeroed() };
+        let seals = unsafe {
+            if fstat(fd, &mut st) < 0 {
+                return false
+            }
+            fcntl(fd, F_GET_SEALS)
+        };
+
+        let sealed = F_SEAL_SHRINK | F_SEAL_SEAL;
+        return seals >= 0 && seals & sealed == sealed && st.st_size == LOOPBACK_SHM_SIZE as off_t
+    }
+
+    // SendFd sends 1 byte with the fd on the unix connection
+    fn SendFd(fd: i32, sent: i32) -> core::result::Result<(), i32> {
+        let mut data = [0u8; 1];
+        let mut iov = iovec {
+            iov_base: &mut data[0] as *mut _ as *mut c_void,
+            iov_len: data.len(),
+        };
+
+        let mut control = [0u64; 4];
+        let mut hdr: msghdr = unsafe { core::mem::zeroed() };
+        hdr.msg_iov = &mut iov;
+        hdr.msg_iovlen = 1;
+        hdr.msg_control = &mut control[0] as *mut _ as *mut c_void;
+        hdr.msg_controllen = unsafe { CMSG_SPACE(4) } as _;
+
+        let ret = unsafe {
+            let cmsg = CMSG_FIRSTHDR(&hdr);
+            (*cmsg).cmsg_level = SOL_SOCKET;
+            (*cmsg).cmsg_type = SCM_RIGHTS;
+            (*cmsg).cmsg_len = CMSG_LEN(4) as _;
+            *(CMSG_DATA(cmsg) as *mut i32) = sent;
+            sendmsg(fd, &hdr, MSG_NOSIGNAL)
+        };
+
+        if ret != 1 {
+            return Err(if ret < 0 { errno::errno().0 } else { EAGAIN })
+        }
+
+        return Ok(())
+    }
+
+    // RecvFd receives the byte with the fd sent by SendFd without blocking. It fails with EAGAIN
+    // when it is not there yet, ECONNRESET when the peer is gone and EPROTO when there is no fd.
+    fn RecvFd(fd: i32) -> core::result::Result<i32, i32> {
+        let mut data = [0u8; 1];
+        let mut iov = iovec {
+            iov_base: &mut data[0] as *mut _ as *mut c_void,
+            iov_len: data.len(),
+        };
+
+        let mut control = [0u64; 4];
+        let mut hdr: msghdr = unsafe { core::mem::zeroed() };
+        hdr.msg_iov = &mut iov;
+        hdr.msg_iovlen = 1;
+        hdr.msg_control = &mut control[0] as *mut _ as *mut c_void;
+        hdr.msg_controllen = core::mem::size_of_val(&control) as _;
+
+        let ret = unsafe {
+            recvmsg(fd, &mut hdr, MSG_DONTWAIT | MSG_CMSG_CLOEXEC)
+        };
+
+        if ret < 0 {
+            return Err(errno::errno().0)
+        } else if ret == 0 {
+            return Err(ECONNRESET)
+        }
+
+        let mut received = -1;
+        unsafe {
+            let mut cmsg = CMSG_FIRSTHDR(&hdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == SOL_SOCKET && (*cmsg).cmsg_type == SCM_RIGHTS {
+                    let cnt = ((*cmsg).cmsg_len as usize - CMSG_LEN(0) as usize) / 4;
+                    for i in 0..cnt {
+                        let fd = *(CMSG_DATA(cmsg) as *const i32).add(i);
+                        if received < 0 {
+                            received = fd;
+                        } else {
+                            close(fd);
+                        }
+                    }
+                }
+                cmsg = CMSG_NXTHDR(&hdr, cmsg);
+            }
+        }
+
+        if received < 0 {
+            return Err(EPROTO)
+        }
+
+        return Ok(received)
+    }
+
+    // Connect replaces the guest socket with a unix connection when the destination is
+    // published by a sandbox, it returns None when the socket is connected by the host as usual
+    pub fn Connect(&self, hostfd: i32, fdInfo: &FdInfo, addr: u64, addrlen: u32) -> Option<i64> {
+        if !Self::Enabled() || addrlen < 16 {
+            return None
+        }
+
+        let buf = unsafe {
+            core::slice::from_raw_parts(addr as *const u8, 16)
+        };
+
+        if buf[0] as i32 | (buf[1] as i32) << 8 != AF_INET || buf[4] == 127 {
+            return None
+        }
+
+        let dst = Self::NewAddr(&buf[4..8], &buf[2..4]);
+        let osfd = fdInfo.Fd();
+        if !ConnPool::IsTcp(osfd, AF_INET) {
+            return None
+        }
+
+        let path = Self::PathOf(&dst);
+        if unsafe { access(path.as_ptr(), F_OK) } < 0 {
+            return None
+        }
+
+        let src = Self::SrcAddr(osfd, &dst)?;
+        let fd = match Self::UnixConnect(&path, &Self::ClientName(&src, &dst)) {
+            Err(_) => return None,
+            Ok(fd) => fd,
+        };
+
+        let shm = match Self::CreateShm() {
+            Err(e) => {
+                error!("loopback: create the shared memory fail, errno is {}", e);
+                unsafe {
+                    close(fd);
+                }
+                return None
+            }
+            Ok(shm) => shm,
+        };
+
+        // the unix connection is connected to the listener, the shared memory is sent to the
+        // socket buffer of it
+        if let Err(e) = Self::SendFd(fd, shm) {
+            error!("loopback: send the shared memory fail, errno is {}", e);
+            unsafe {
+                close(fd);
+                close(shm);
+            }
+            return None
+        }
+
+        if fdInfo.ReplaceFd(fd) < 0 {
+            unsafe {
+                close(shm);
+            }
+            return None
+        }
+
+        let conn = LoopbackConn {
+            local: src,
+            peer: dst,
+            shm: ShmState::Ready(IO_MGR.AddFile(shm)),
+            opts: BTreeMap::new(),
+        };
+
+        self.conns.lock().get_or_insert_with(BTreeMap::new).insert(hostfd, conn);
+        return Some(0)
+    }
+
+    // Accept accepts a connection of the unix listener, addr gets the inet address of the client
+    pub fn Accept(&self, fdInfo: &FdInfo, addr: u64, addrlen: u64) -> i64 {
+        let mut name: sockaddr_un = unsafe { core::mem::zeroed() };
+        let mut len = core::mem::size_of::<sockaddr_un>() as socklen_t;
+        let fd = unsafe {
+            accept4(fdInfo.Fd(), &mut name as *mut _ as *mut sockaddr, &mut len, SOCK_NONBLOCK | SOCK_CLOEXEC)
+        };
+
+        if fd < 0 {
+            return -errno::errno().0 as i64
+        }
+
+        let n = (len as usize).saturating_sub(core::mem::size_of::<sa_family_t>());
+        let bytes = unsafe {
+            core::slice::from_raw_parts(&name.sun_path[0] as *const _ as *const u8, n)
+        };
+
+        let (src, dst) = match Self::ParseClientName(bytes) {
+            None => {
+                unsafe {
+                    close(fd);
+                }
+                return -SysErr::ECONNABORTED as i64
+            }
+            Some(a) => a,
+        };
+
+        let hostfd = IO_MGR.AddSocket(fd);
+        URING_MGR.lock().Addfd(fd).unwrap();
+        let conn = LoopbackConn {
+            local: dst,
+            peer: src,
+            shm: ShmState::Recv,
+            opts: BTreeMap::new(),
+        };
+
+        self.conns.lock().get_or_insert_with(BTreeMap::new).insert(hostfd, conn);
+        Self::CopyAddr(&src, addr, addrlen);
+        return hostfd as i64
+    }
+
+    // Shm returns the hostfd of the shared memory of the connection to the guest, which maps it
+    // and closes it. The server receives it without blocking, EAGAIN when the client hasn't sent
+    // it yet. It is ENOENT when the socket is not a loopback connection.
+    pub fn Shm(&self, hostfd: i32) -> i64 {
+        let mut conns = self.conns.lock();
+        let conn = match conns.as_mut().and_then(|c| c.get_mut(&hostfd)) {
+            None => return -SysErr::ENOENT as i64,
+            Some(c) => c,
+        };
+
+        match conn.shm {
+            ShmState::Ready(shm) => {
+                conn.shm = ShmState::Taken;
+                return shm as i64
+            }
+            ShmState::Taken => return -SysErr::EINVAL as i64,
+            ShmState::Recv => (),
+        }
+
+        let osfd = match IO_MGR.GetFdByHost(hostfd) {
+            None => return -SysErr::EBADF as i64,
+            Some(fd) => fd,
+        };
+
+        let shm = match Self::RecvFd(osfd) {
+            Err(e) => return -e as i64,
+            Ok(shm) => shm,
+        };
+
+        conn.shm = ShmState::Taken;
+        if !Self::CheckShm(shm) {
+            error!("loopback: the shared memory of hostfd {} is invalid", hostfd);
+            unsafe {
+                close(shm);
+            }
+            return -SysErr::EPROTO as i64
+        }
+
+        return IO_MGR.AddFile(shm) as i64
+    }
+
+    fn CopyAddr(sa: &SockAddrIn, addr: u64, addrlen: u64) {
+        Self::CopyOpt(sa, addr, addrlen);
+        let len = unsafe { &mut *(addrlen as *mut socklen_t) };
+        *len = sa.len() as socklen_t;
+    }
+
+    // CopyOpt copies the value to the buffer of the length, the length is set to the size copied
+    fn CopyOpt(val: &[u8], addr: u64, addrlen: u64) {
+        let len = unsafe { &mut *(addrlen as *mut socklen_t) };
+        let n = core::cmp::min(*len as usize, val.len());
+        let buf = unsafe {
+            core::slice::from_raw_parts_mut(addr as *mut u8, n)
+        };
+        buf.copy_from_slice(&val[..n]);
+        *len = n as socklen_t;
+    }
+
+    fn Addrs(&self, hostfd: i32) -> Option<(SockAddrIn, SockAddrIn)> {
+        return self.conns.lock().as_ref().and_then(|c| c.get(&hostfd)).map(|c| (c.local, c.peer))
+    }
+
+    pub fn GetSockName(&self, hostfd: i32, addr: u64, addrlen: u64) -> Option<i64> {
+        let (local, _) = self.Addrs(hostfd)?;
+        Self::CopyAddr(&local, addr, addrlen);
+        return Some(0)
+    }
+
+    pub fn GetPeerName(&self, hostfd: i32, addr: u64, addrlen: u64) -> Option<i64> {
+        let (_, peer) = self.Addrs(hostfd)?;
+        Self::CopyAddr(&peer, addr, addrlen);
+        return Some(0)
+    }
+
+    // DefaultOpt returns the TCP or IP option of the connection which is not set by the guest,
+    // it is the one of a connection over the host loopback device. It is None for the options
+    // which are not supported.
+    fn DefaultOpt(level: i32, optname: i32) -> Option<Vec<u8>> {
+        let val: i32 = match (level, optname) {
+            (IPPROTO_TCP, TCP_INFO) => {
+                let mut info = vec![0u8; TCP_INFO_LEN];
+                info[0] = 1; // TCP_ESTABLISHED
+                // tcpi_snd_mss and tcpi_rcv_mss
+                info[16..20].copy_from_slice(&LOOPBACK_MSS.to_ne_bytes());
+                info[20..24].copy_from_slice(&LOOPBACK_MSS.to_ne_bytes());
+                return Some(info)
+            }
+            (IPPROTO_TCP, TCP_CONGESTION) => {
+                let mut name = vec![0u8; 16];
+                name[..5].copy_from_slice(b"cubic");
+                return Some(name)
+            }
+            (IPPROTO_TCP, TCP_NODELAY) | (IPPROTO_TCP, TCP_CORK) |
+            (IPPROTO_TCP, TCP_USER_TIMEOUT) | (IPPROTO_TCP, TCP_DEFER_ACCEPT) => 0,
+            (IPPROTO_TCP, TCP_QUICKACK) => 1,
+            (IPPROTO_TCP, TCP_MAXSEG) => LOOPBACK_MSS,
+            (IPPROTO_TCP, TCP_KEEPIDLE) => 7200,
+            (IPPROTO_TCP, TCP_KEEPINTVL) => 75,
+            (IPPROTO_TCP, TCP_KEEPCNT) => 9,
+            (IPPROTO_TCP, TCP_SYNCNT) => 6,
+            (IPPROTO_IP, IP_TOS) => 0,
+            (IPPROTO_IP, IP_TTL) => 64,
+            _ => return None,
+        };
+
+        return Some(val.to_ne_bytes().to_vec())
+    }
+
+    // SetSockOpt keeps the TCP and IP options of the connection for the getsockopt, they have
+    // no effect on the ring pair. kTLS is not supported as the records would be sent unencrypted.
+    pub fn SetSockOpt(&self, hostfd: i32, level: i32, optname: i32, optval: u64, optlen: u32) -> Option<i64> {
+        if level == SOL_SOCKET {
+            return None
+        }
+
+        let mut conns = self.conns.lock();
+        let conn = conns.as_mut().and_then(|c| c.get_mut(&hostfd))?;
+        if (level, optname) == (IPPROTO_TCP, TCP_INFO) || Self::DefaultOpt(level, optname).is_none() {
+            return Some(-SysErr::ENOPROTOOPT as i64)
+        }
+
+        let len = core::cmp::min(optlen as usize, TCP_INFO_LEN);
+        let val = unsafe {
+            core::slice::from_raw_parts(optval as *const u8, len)
+        };
+        conn.opts.insert((level, optname), val.to_vec());
+        return Some(0)
+    }
+
+    // GetSockOpt returns the options of the unix connection as a TCP socket, the TCP and IP
+    // options are the ones set by the guest or the defaults
+    pub fn GetSockOpt(&self, hostfd: i32, level: i32, optname: i32, optval: u64, optlen: u64) -> Option<i64> {
+        let conns = self.conns.lock();
+        let conn = conns.as_ref().and_then(|c| c.get(&hostfd))?;
+        let val = match (level, optname) {
+            (SOL_SOCKET, SO_DOMAIN) => AF_INET.to_ne_bytes().to_vec(),
+            (SOL_SOCKET, SO_PROTOCOL) => IPPROTO_TCP.to_ne_bytes().to_vec(),
+            (SOL_SOCKET, _) => return None,
+            _ => match conn.opts.get(&(level, optname)).cloned().or_else(|| Self::DefaultOpt(level, optname)) {
+                None => return Some(-SysErr::ENOPROTOOPT as i64),
+                Some(v) => v,
+            }
+        };
+
+        Self::CopyOpt(&val, optval, optlen);
+        return Some(0)
+    }
+
+    // Forget is called when the hostfd is closed
+    pub fn Forget(&self, hostfd: i32) {
+        let conn = self.conns.lock().as_mut().and_then(|c| c.remove(&hostfd));
+        if let Some(LoopbackConn { shm: ShmState::Ready(shm), .. }) = conn {
+            super::super::VMSpace::Close(shm);
+        }
+
+        let paths = match self.listeners.lock().as_mut() {
+            None => return,
+            Some(l) => l.remove(&hostfd),
+        };
+
+        if let Some(paths) = paths {
+            Self::Unlink(&paths);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::qlib::loopback_buf::LoopbackBuf;
+
+    fn Addr(ip: [u8; 4], port: u16) -> SockAddrIn {
+        return Loopback::NewAddr(&ip, &port.to_be_bytes())
+    }
+
+    fn SocketPair() -> (i32, i32) {
+        let mut fds = [0i32; 2];
+        let ret = unsafe {
+            socketpair(AF_UNIX, SOCK_STREAM | SOCK_NONBLOCK | SOCK_CLOEXEC, 0, &mut fds[0])
+        };
+        assert_eq!(ret, 0);
+        return (fds[0], fds[1])
+    }
+
+    fn Map(fd: i32) -> u64 {
+        let addr = unsafe {
+            mmap(core::ptr::null_mut(), LOOPBACK_SHM_SIZE as usize, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0)
+        };
+        assert!(addr != MAP_FAILED);
+        return addr as u64
+    }
+
+    #[test]
+    fn test_path_of() {
+        let path = Loopback::PathOf(&Addr([10, 0, 0, 7], 8080));
+        assert_eq!(path.to_str().unwrap(), format!("{}/10.0.0.7:8080", LOOPBACK_DIR));
+    }
+
+    #[test]
+    fn test_client_name() {
+        let src = Addr([10, 0, 0, 7], 40000);
+        let dst = Addr([192, 168, 1, 2], 443);
+        let name = Loopback::ClientName(&src, &dst);
+        assert_eq!(name[0], 0);
+        assert!(Loopback::UnixAddr(&name).is_some());
+        assert_eq!(Loopback::ParseClientName(&name), Some((src, dst)));
+
+        assert_eq!(Loopback::ParseClientName(b"\0quark-loopback/1/2/00"), None);
+        assert_eq!(Loopback::ParseClientName(b"\0other/1/2/000000000000000000000000"), None);
+        assert_eq!(Loopback::ParseClientName(b"/quark-loopback/1/2/000000000000000000000000"), None);
+    }
+
+    #[test]
+    fn test_default_opt() {
+        let val = Loopback::DefaultOpt(IPPROTO_TCP, TCP_MAXSEG).unwrap();
+        assert_eq!(val, LOOPBACK_MSS.to_ne_bytes().to_vec());
+        let info = Loopback::DefaultOpt(IPPROTO_TCP, TCP_INFO).unwrap();
+        assert_eq!(info.len(), TCP_INFO_LEN);
+        assert_eq!(info[0], 1);
+        assert!(Loopback::DefaultOpt(IPPROTO_TCP, TCP_FASTOPEN).is_none());
+        assert!(Loopback::DefaultOpt(IPPROTO_IPV6, IPV6_V6ONLY).is_none());
+    }
+
+    #[test]
+    fn test_recv_fd() {
+        let (a, b) = SocketPair();
+        assert_eq!(Loopback::RecvFd(b), Err(EAGAIN));
+
+        // a byte without the fd, e.g. a doorbell
+        let ret = unsafe { write(a, &0u8 as *const _ as *const c_void, 1) };
+        assert_eq!(ret, 1);
+        assert_eq!(Loopback::RecvFd(b), Err(EPROTO));
+
+        unsafe {
+            close(a);
+        }
+        assert_eq!(Loopback::RecvFd(b), Err(ECONNRESET));
+        unsafe {
+            close(b);
+        }
+    }
+
+    #[test]
+    fn test_shm_ring_pair() {
+        let (a, b) = SocketPair();
+        let shm = Loopback::CreateShm().unwrap();
+        assert!(Loopback::CheckShm(shm));
+        Loopback::SendFd(a, shm).unwrap();
+        let received = Loopback::RecvFd(b).unwrap();
+        assert!(Loopback::CheckShm(received));
+
+        // the size is sealed
+        assert!(unsafe { ftruncate(received, 4096) } < 0);
+
+        let clientAddr = Map(shm);
+        let serverAddr = Map(received);
+        let client = LoopbackBuf::New(clientAddr, true);
+        let server = LoopbackBuf::New(serverAddr, false);
+        let (trigger, n) = client.Write(|iovs| {
+            iovs[0].ToSliceMut()[..4].copy_from_slice(b"ping");
+            Ok(4)
+        }).unwrap();
+        assert!(trigger);
+        assert_eq!(n, 4);
+
+        let mut out = Vec::new();
+        server.Read(false, |iovs| {
+            out.extend_from_slice(iovs[0].ToSlice());
+            Ok(iovs[0].len)
+        }).unwrap();
+        assert_eq!(&out[..], b"ping");
+        assert_eq!(client.Events(), EVENT_OUT);
+
+        drop(client);
+        drop(server);
+        unsafe {
+            munmap(clientAddr as *mut c_void, LOOPBACK_SHM_SIZE as usize);
+            munmap(serverAddr as *mut c_void, LOOPBACK_SHM_SIZE as usize);
+            close(shm);
+            close(received);
+            close(a);
+            close(b);
+        }
+    }
+
+    #[test]
+    fn test_check_shm_unsealed() {
+        let name = CString::new("test").unwrap();
+        let fd = unsafe { memfd_create(name.as_ptr(), MFD_CLOEXEC) };
+        assert!(fd >= 0);
+        assert_eq!(unsafe { ftruncate(fd, LOOPBACK_SHM_SIZE as off_t) }, 0);
+        assert!(!Loopback::CheckShm(fd));
+        unsafe {
+            close(fd);
+        }
+    }
+}
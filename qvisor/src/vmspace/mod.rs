@@ -23,6 +23,18 @@ pub mod limits;
 pub mod uringMgr;
 pub mod host_uring;
 pub mod kernel_io_thread;
+pub mod hibernate;
+pub mod conn_pool;
+pub mod loopback;
+pub mod shared_mem;
+pub mod etc_files;
+pub mod route_dump;
+pub mod sandbox_identity;
+pub mod resolver;
+pub mod journal;
+pub mod cpufreq;
+pub mod io_backend;
+pub mod epoll_backend;
 
 use std::str;
 use std::slice;
@@ -41,6 +53,7 @@ use super::runc::runtime::loader::*;
 use super::runc::specutils::specutils::*;
 use super::runc::container::mounts::*;
 use super::qlib::*;
+use super::qlib::vcpu_mgr::VcpuMask;
 use super::qlib::task_mgr::*;
 use super::qlib::common::{Error, Result};
 use super::qlib::linux_def::*;
@@ -49,10 +62,14 @@ use super::qlib::addr::{Addr};
 use super::qlib::control_msg::*;
 use super::qlib::qmsg::*;
 use super::qlib::cstring::*;
-//use super::qlib::socket_buf::*;
+#[cfg(feature = "rdma")]
+use super::qlib::socket_buf::*;
+use super::qlib::socket_buf::AcceptPeerInfo;
 use super::qlib::perf_tunning::*;
 use super::qlib::kernel::guestfdnotifier::*;
 use super::qlib::kernel::SignalProcess;
+use super::qlib::kernel::compat::COMPAT_FILE;
+use super::qlib::kernel::socket::hostinet::nat::NAT_RULES_FILE;
 use super::namespace::MountNs;
 use super::ucall::usocket::*;
 use super::*;
@@ -122,13 +139,13 @@ impl VMSpace {
         return IO_MGR.GetFdByHost(hostfd);
     }
 
-    pub fn TlbShootdown(&self, vcpuMask: u64) -> u64 {
-        let mut mask = 0;
+    pub fn TlbShootdown(&self, vcpuMask: &VcpuMask) -> VcpuMask {
+        let mut mask = VcpuMask::default();
 
-        for i in 0..64 {
-            if (1<<i) & vcpuMask != 0 {
+        for i in 0..self.vcpus.len() {
+            if vcpuMask.IsSet(i) {
                 if self.vcpus[i].Signal(Signal::SIGCHLD) {
-                    mask |= 1 << i;
+                    mask.Set(i);
                     SHARE_SPACE.scheduler.VcpuArr[i].InterruptTlbShootdown();
                 }
             }
@@ -197,14 +214,29 @@ impl VMSpace {
             cpuCount = 2; // at least 2 vcpu (one for host io and the other for process vcpu)
         }
 
-        if cpuCount > MAX_VCPU_COUNT {
-            cpuCount = MAX_VCPU_COUNT;
+        // the vcpu masks are sized by MAX_VCPU_COUNT, VcpuCount is validated against it
+        let maxCount = QUARK_CONFIG.lock().VcpuCount;
+        if cpuCount > maxCount {
+            cpuCount = maxCount;
         }
 
         return cpuCount
     }
 
     pub fn LoadProcessKernel(&mut self, processAddr: u64, buffLen: usize) -> i64 {
+        // the identity key and the bundle are read before the pivot root
+        let (identity, ttl) = {
+            let config = QUARK_CONFIG.lock();
+            (config.SandboxIdentity, config.SandboxIdentityTtl)
+        };
+        if identity {
+            let args = self.args.as_ref().unwrap();
+            match sandbox_identity::SANDBOX_IDENTITY.lock().Generate(&args.ID, &args.BundleDir, &args.Spec.annotations, ttl) {
+                Err(e) => error!("sandbox identity generate fail with error {:?}", e),
+                Ok(()) => (),
+            }
+        }
+
         let mut process = loader::Process::default();
         process.ID = self.args.as_ref().unwrap().ID.to_string();
         let spec = &mut self.args.as_mut().unwrap().Spec;
@@ -229,6 +261,24 @@ impl VMSpace {
         process.Caps = Capabilities(false, &spec.process.capabilities);
 
         process.HostName = spec.hostname.to_string();
+        if QUARK_CONFIG.lock().EtcFilesInGuest {
+            let mut etcFiles = etc_files::ETC_FILES_MGR.lock();
+            etcFiles.SetHostname(&spec.hostname);
+            process.EtcFiles = etcFiles.Paths();
+        }
+
+        if QUARK_CONFIG.lock().NetlinkRouteInGuest {
+            match route_dump::ROUTE_DUMP.lock().Snapshot() {
+                Err(e) => error!("route dump snapshot fail with error {:?}", e),
+                Ok(()) => (),
+            }
+        }
+
+        if let Some(linux) = &spec.linux {
+            for (key, value) in &linux.sysctl {
+                process.Sysctls.insert(key.to_string(), value.to_string());
+            }
+        }
 
         process.NumCpu = self.vcpuCount as u32;
         process.ExecId = Some("".to_string());
@@ -314,6 +364,28 @@ impl VMSpace {
         return hostfd as i64
     }
 
+    // ReopenFd opens the file of the host fd again by /proc/self/fd, the new file description has
+    // the access mode of flags instead of the one of the host fd
+    pub fn ReopenFd(fd: i32, flags: i32) -> i64 {
+        let fd = match Self::GetOsfd(fd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let path = CString::New(&format!("/proc/self/fd/{}", fd));
+        let newfd = unsafe {
+            open(path.Ptr() as *const c_char, (flags & O_ACCMODE) | O_CLOEXEC | O_NOCTTY)
+        };
+
+        if newfd < 0 {
+            return Self::GetRet(newfd as i64)
+        }
+
+        URING_MGR.lock().Addfd(newfd).unwrap();
+        let hostfd = IO_MGR.AddFile(newfd);
+        return hostfd as i64
+    }
+
     pub fn Fallocate(fd: i32, mode: i32, offset: i64, len: i64) -> i64 {
         let fd = match Self::GetOsfd(fd) {
             Some(fd) => fd,
@@ -532,6 +604,8 @@ impl VMSpace {
     }
 
     pub fn Close(fd: i32) -> i64 {
+        conn_pool::CONN_POOL.Forget(fd);
+        loopback::LOOPBACK.Forget(fd);
         let info = IO_MGR.RemoveFd(fd);
 
         URING_MGR.lock().Removefd(fd).unwrap();
@@ -647,6 +721,83 @@ impl VMSpace {
         return fdInfo.IOAccept(addr, addrlen)
     }
 
+    pub fn LoopbackListen(fd: i32) -> i64 {
+        let fdInfo = match Self::GetFdInfo(fd) {
+            Some(info) => info,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        return loopback::LOOPBACK.Listen(&fdInfo)
+    }
+
+    pub fn LoopbackAccept(fd: i32, addr: u64, addrlen: u64) -> i64 {
+        let fdInfo = match Self::GetFdInfo(fd) {
+            Some(info) => info,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        return loopback::LOOPBACK.Accept(&fdInfo, addr, addrlen)
+    }
+
+    pub fn LoopbackShm(fd: i32) -> i64 {
+        return loopback::LOOPBACK.Shm(fd)
+    }
+
+    // SockRetrans sums the retransmits of the live tcp sockets for the sockstat of the sandbox
+    pub fn SockRetrans() -> i64 {
+        let mut retrans = 0;
+        for info in IO_MGR.Sockets() {
+            retrans += info.lock().TcpRetrans();
+        }
+
+        return retrans as i64
+    }
+
+    // GetAcceptPeerInfo gets the peer info of the accepted connection in one host call, the
+    // info which is not available is left empty and the guest asks the host again for it
+    pub fn GetAcceptPeerInfo(fd: i32, info: u64) -> i64 {
+        let info = unsafe {
+            &mut *(info as *mut AcceptPeerInfo)
+        };
+
+        let mut len = info.addr.data.len() as socklen_t;
+        if Self::GetPeerName(fd, info.addr.Addr(), &mut len as *mut _ as u64) >= 0 {
+            info.addrLen = len;
+        }
+
+        let mut domain: i32 = 0;
+        let mut len = 4 as socklen_t;
+        let ret = Self::GetSockOpt(fd, SOL_SOCKET, SO_DOMAIN, &mut domain as *mut _ as u64, &mut len as *mut _ as u64);
+        if ret < 0 {
+            return ret
+        }
+
+        let mut stype: i32 = 0;
+        let mut len = 4 as socklen_t;
+        let ret = Self::GetSockOpt(fd, SOL_SOCKET, SO_TYPE, &mut stype as *mut _ as u64, &mut len as *mut _ as u64);
+        if ret < 0 {
+            return ret
+        }
+
+        if domain == AF_UNIX {
+            let mut cred = ucred { pid: 0, uid: 0, gid: 0 };
+            let mut len = core::mem::size_of::<ucred>() as socklen_t;
+            if Self::GetSockOpt(fd, SOL_SOCKET, SO_PEERCRED, &mut cred as *mut _ as u64, &mut len as *mut _ as u64) >= 0 {
+                info.hasCred = true;
+                info.pid = cred.pid;
+                info.uid = cred.uid;
+                info.gid = cred.gid;
+            }
+        } else if stype == SOCK_STREAM {
+            let mut len = info.tcpInfo.len() as socklen_t;
+            if Self::GetSockOpt(fd, IPPROTO_TCP, TCP_INFO, &mut info.tcpInfo[0] as *mut _ as u64, &mut len as *mut _ as u64) >= 0 {
+                info.tcpInfoLen = len;
+            }
+        }
+
+        return 0
+    }
+
     pub fn NewSocket(fd: i32) -> i64 {
         IO_MGR.AddSocket(fd);
         URING_MGR.lock().Addfd(fd).unwrap();
@@ -659,6 +810,14 @@ impl VMSpace {
             None => return -SysErr::EBADF as i64,
         };
 
+        if let Some(ret) = loopback::LOOPBACK.Connect(fd, &fdInfo, addr, addrlen) {
+            return ret
+        }
+
+        if let Some(ret) = conn_pool::CONN_POOL.Connect(fd, &fdInfo, addr, addrlen) {
+            return ret
+        }
+
         return fdInfo.IOConnect(addr, addrlen)
     }
 
@@ -668,7 +827,49 @@ impl VMSpace {
             None => return -SysErr::EBADF as i64,
         };
 
-        return fdInfo.IORecvMsg(msghdr, flags)
+        let ret = fdInfo.IORecvMsg(msghdr, flags);
+        if ret >= 0 {
+            Self::AddRights(unsafe { &*(msghdr as *const libc::msghdr) });
+        }
+
+        return ret
+    }
+
+    // AddRights registers the host fds received by SCM_RIGHTS in the control data of the host
+    // recvmsg, the guest imports them as its files and closes them by the hostfd
+    pub fn AddRights(hdr: &libc::msghdr) {
+        if hdr.msg_control.is_null() || hdr.msg_controllen == 0 {
+            return
+        }
+
+        unsafe {
+            let mut cmsg = CMSG_FIRSTHDR(hdr);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == SOL_SOCKET && (*cmsg).cmsg_type == SCM_RIGHTS {
+                    let len = (*cmsg).cmsg_len as usize - CMSG_LEN(0) as usize;
+                    let fds = slice::from_raw_parts(CMSG_DATA(cmsg) as *const i32, len / 4);
+                    for fd in fds {
+                        Self::AddRecvFd(*fd);
+                    }
+                }
+
+                cmsg = CMSG_NXTHDR(hdr, cmsg);
+            }
+        }
+    }
+
+    fn AddRecvFd(fd: i32) {
+        let mut stat: libc::stat = unsafe { core::mem::zeroed() };
+        let ret = unsafe { fstat(fd, &mut stat) };
+        if ret == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFSOCK {
+            // the guest sockets wait for the host events, the host calls on them must not block
+            Self::UnblockFd(fd);
+            IO_MGR.AddSocket(fd);
+        } else {
+            IO_MGR.AddFile(fd);
+        }
+
+        URING_MGR.lock().Addfd(fd).unwrap();
     }
 
     pub fn IOSendMsg(fd: i32, msghdr: u64, flags: i32) -> i64 {
@@ -680,6 +881,52 @@ impl VMSpace {
         return fdInfo.IOSendMsg(msghdr, flags)
     }
 
+    pub fn IORecvMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fdInfo = match Self::GetFdInfo(fd) {
+            Some(info) => info,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let ret = fdInfo.IORecvMMsg(msgvec, vlen, flags);
+        if ret > 0 {
+            let hdrs = unsafe { slice::from_raw_parts(msgvec as *const mmsghdr, ret as usize) };
+            for hdr in hdrs {
+                Self::AddRights(&hdr.msg_hdr);
+            }
+        }
+
+        return ret
+    }
+
+    pub fn IOSendMMsg(fd: i32, msgvec: u64, vlen: u32, flags: i32) -> i64 {
+        let fdInfo = match Self::GetFdInfo(fd) {
+            Some(info) => info,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        return fdInfo.IOSendMMsg(msgvec, vlen, flags)
+    }
+
+    // SendFile sends count bytes of the host file infd from offset to the socket outfd
+    pub fn SendFile(outfd: i32, infd: i32, offset: i64, count: u64) -> i64 {
+        let outfd = match Self::GetOsfd(outfd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let infd = match Self::GetOsfd(infd) {
+            Some(fd) => fd,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        let mut offset = offset;
+        let ret = unsafe {
+            sendfile(outfd, infd, &mut offset, count as usize)
+        };
+
+        return Self::GetRet(ret as i64)
+    }
+
     pub fn Fcntl(fd: i32, cmd: i32, arg: u64) -> i64 {
         let fdInfo = match Self::GetFdInfo(fd) {
             Some(info) => info,
@@ -988,12 +1235,38 @@ impl VMSpace {
         return Self::GetRet(hostfd as i64);
     }
 
+    pub fn SocketPair(domain: i32, type_: i32, protocol: i32, socketVect: u64) -> i64 {
+        let mut fds: [i32; 2] = [-1; 2];
+        let ret = unsafe {
+            socketpair(domain, type_ | SocketFlags::SOCK_NONBLOCK | SocketFlags::SOCK_CLOEXEC, protocol, &mut fds[0])
+        };
+
+        if ret < 0 {
+            return Self::GetRet(ret as i64);
+        }
+
+        let hostfds = unsafe {
+            &mut *(socketVect as *mut [i32; 2])
+        };
+
+        for i in 0..2 {
+            hostfds[i] = IO_MGR.AddSocket(fds[i]);
+            URING_MGR.lock().Addfd(fds[i]).unwrap();
+        }
+
+        return 0
+    }
+
     pub fn GetSockName(sockfd: i32, addr: u64, addrlen: u64) -> i64 {
         let fdInfo = match Self::GetFdInfo(sockfd) {
             Some(fdInfo) => fdInfo,
             None => return -SysErr::EBADF as i64,
         };
 
+        if let Some(ret) = loopback::LOOPBACK.GetSockName(sockfd, addr, addrlen) {
+            return ret
+        }
+
         return fdInfo.IOGetSockName(addr, addrlen)
     }
 
@@ -1003,6 +1276,10 @@ impl VMSpace {
             None => return -SysErr::EBADF as i64,
         };
 
+        if let Some(ret) = loopback::LOOPBACK.GetPeerName(sockfd, addr, addrlen) {
+            return ret
+        }
+
         return fdInfo.IOGetPeerName(addr, addrlen)
     }
 
@@ -1012,6 +1289,10 @@ impl VMSpace {
             None => return -SysErr::EBADF as i64,
         };
 
+        if let Some(ret) = loopback::LOOPBACK.GetSockOpt(sockfd, level, optname, optval, optlen) {
+            return ret
+        }
+
         return fdInfo.IOGetSockOpt(level, optname, optval, optlen)
     }
 
@@ -1021,6 +1302,11 @@ impl VMSpace {
             None => return -SysErr::EBADF as i64,
         };
 
+        conn_pool::CONN_POOL.Pin(sockfd);
+        if let Some(ret) = loopback::LOOPBACK.SetSockOpt(sockfd, level, optname, optval, optlen) {
+            return ret
+        }
+
         return fdInfo.IOSetSockOpt(level, optname, optval, optlen)
     }
 
@@ -1030,6 +1316,7 @@ impl VMSpace {
             None => return -SysErr::EBADF as i64,
         };
 
+        conn_pool::CONN_POOL.Pin(sockfd);
         return fdInfo.IOBind(sockaddr, addrlen, umask)
     }
 
@@ -1043,7 +1330,8 @@ impl VMSpace {
     }
 
 
-    /*pub fn RDMAListen(sockfd: i32, backlog: i32, block: bool, acceptQueue: AcceptQueue) -> i64 {
+    #[cfg(feature = "rdma")]
+    pub fn RDMAListen(sockfd: i32, backlog: i32, block: bool, acceptQueue: AcceptQueue) -> i64 {
         let fdInfo = match Self::GetFdInfo(sockfd) {
             Some(fdInfo) => fdInfo,
             None => return -SysErr::EBADF as i64,
@@ -1052,6 +1340,7 @@ impl VMSpace {
         return fdInfo.RDMAListen(backlog, block, acceptQueue)
     }
 
+    #[cfg(feature = "rdma")]
     pub fn RDMANotify(sockfd: i32, typ: RDMANotifyType) -> i64 {
         let fdInfo = match Self::GetFdInfo(sockfd) {
             Some(fdInfo) => fdInfo,
@@ -1061,6 +1350,17 @@ impl VMSpace {
         return fdInfo.RDMANotify(typ)
     }
 
+    #[cfg(feature = "rdma")]
+    pub fn RDMAUdpSocket(sockfd: i32, socketBuf: Arc<SocketBuff>) -> i64 {
+        let fdInfo = match Self::GetFdInfo(sockfd) {
+            Some(fdInfo) => fdInfo,
+            None => return -SysErr::EBADF as i64,
+        };
+
+        return fdInfo.RDMAUdpSocket(socketBuf)
+    }
+
+    #[cfg(feature = "rdma")]
     pub fn PostRDMAConnect(msg: &'static mut PostRDMAConnect) {
         let fdInfo = match Self::GetFdInfo(msg.fd) {
             Some(fdInfo) => fdInfo,
@@ -1071,7 +1371,7 @@ impl VMSpace {
         };
 
         fdInfo.PostRDMAConnect(msg);
-    }*/
+    }
 
     pub fn Shutdown(sockfd: i32, how: i32) -> i64 {
         let fdInfo = match Self::GetFdInfo(sockfd) {
@@ -1178,6 +1478,119 @@ impl VMSpace {
         return Self::GetRet(ret as i64)
     }
 
+    // ReadEtcFile returns the size of the generated file, it is copied only when it fits in the
+    // buffer
+    pub fn ReadEtcFile(idx: usize, buf: u64, len: usize) -> i64 {
+        let data = match etc_files::ETC_FILES_MGR.lock().Generate(idx) {
+            Err(Error::SysError(e)) => return -e as i64,
+            Err(e) => {
+                error!("ReadEtcFile {} fail with error {:?}", idx, e);
+                return -SysErr::EIO as i64
+            }
+            Ok(data) => data,
+        };
+
+        if data.len() <= len {
+            let buf = unsafe {
+                slice::from_raw_parts_mut(buf as *mut u8, data.len())
+            };
+            buf.copy_from_slice(&data);
+        }
+
+        return data.len() as i64
+    }
+
+    // ReadRouteDump returns the size of the dump, it is copied only when it fits in the buffer
+    pub fn ReadRouteDump(msgType: u16, buf: u64, len: usize) -> i64 {
+        let dump = route_dump::ROUTE_DUMP.lock();
+        let data = match dump.Get(msgType) {
+            Err(Error::SysError(e)) => return -e as i64,
+            Err(e) => {
+                error!("ReadRouteDump {} fail with error {:?}", msgType, e);
+                return -SysErr::EIO as i64
+            }
+            Ok(data) => data,
+        };
+
+        if data.len() <= len {
+            let buf = unsafe {
+                slice::from_raw_parts_mut(buf as *mut u8, data.len())
+            };
+            buf.copy_from_slice(data);
+        }
+
+        return data.len() as i64
+    }
+
+    // ReadSandboxIdentity returns the size of the identity token, it is copied only when it fits
+    // in the buffer
+    pub fn ReadSandboxIdentity(buf: u64, len: usize) -> i64 {
+        let mut identity = sandbox_identity::SANDBOX_IDENTITY.lock();
+        let data = match identity.Get() {
+            Err(Error::SysError(e)) => return -e as i64,
+            Err(e) => {
+                error!("ReadSandboxIdentity fail with error {:?}", e);
+                return -SysErr::EIO as i64
+            }
+            Ok(data) => data,
+        };
+
+        if data.len() <= len {
+            let buf = unsafe {
+                slice::from_raw_parts_mut(buf as *mut u8, data.len())
+            };
+            buf.copy_from_slice(data);
+        }
+
+        return data.len() as i64
+    }
+
+    // CopyConfigFile copies the host config file to the guest, 0 when there is no file
+    fn CopyConfigFile(path: &str, addr: u64, len: usize) -> i64 {
+        let data = match std::fs::read(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return 0,
+            Err(e) => {
+                error!("CopyConfigFile read {} fail with error {:?}", path, e);
+                return -SysErr::EIO as i64
+            }
+            Ok(data) => data,
+        };
+
+        if data.len() > len {
+            error!("CopyConfigFile {} is larger than {}", path, len);
+            return -SysErr::EFBIG as i64
+        }
+
+        let buf = unsafe {
+            slice::from_raw_parts_mut(addr as *mut u8, data.len())
+        };
+        buf.copy_from_slice(&data);
+        return data.len() as i64
+    }
+
+    pub fn LoadCompatProfiles(addr: u64, len: usize) -> i64 {
+        return Self::CopyConfigFile(COMPAT_FILE, addr, len)
+    }
+
+    pub fn LoadNatRules(addr: u64, len: usize) -> i64 {
+        return Self::CopyConfigFile(NAT_RULES_FILE, addr, len)
+    }
+
+    // LoadNetPolicy copies the net policy annotation of the sandbox to the guest
+    pub fn LoadNetPolicy(addr: u64, len: usize) -> i64 {
+        let policy = NET_POLICY.lock();
+        if policy.len() > len {
+            error!("LoadNetPolicy policy is larger than {}", len);
+            return -SysErr::EFBIG as i64
+        }
+
+        let buf = unsafe {
+            slice::from_raw_parts_mut(addr as *mut u8, policy.len())
+        };
+        buf.copy_from_slice(policy.as_bytes());
+        return policy.len() as i64
+    }
+
     pub fn Sysinfo(info: u64) -> i64 {
         unsafe {
             return Self::GetRet(sysinfo(info as *mut sysinfo) as i64);
@@ -0,0 +1,850 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use spin::Mutex;
+use std::io::Read;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::net::UdpSocket;
+use std::slice;
+use std::sync::Condvar;
+use std::time::Duration;
+use std::time::Instant;
+
+use super::super::qlib::common::*;
+use super::super::qlib::kernel::fs::etc::*;
+use super::super::qlib::linux_def::*;
+use super::super::qlib::qmsg::qcall::*;
+use super::super::SHARE_SPACE;
+use super::etc_files::*;
+
+lazy_static! {
+    pub static ref RESOLVER: Resolver = Resolver::New();
+}
+
+// the resolver threads are started on demand, a lookup waiting for a server which doesn't
+// answer holds one of them only. The lookups beyond the queue limit fail with EAGAIN.
+pub const RESOLVER_THREADS_MAX: usize = 16;
+pub const RESOLVER_QUEUE_MAX: usize = 256;
+pub const RESOLVER_CACHE_MAX: usize = 1024;
+
+// the server which doesn't answer is tried after the others for this time
+pub const RESOLVER_SERVER_DOWN_SECS: u64 = 30;
+
+// the ttl of the cached answers is capped so that the changed records are seen in time
+pub const RESOLVER_TTL_MAX: u32 = 3600;
+
+pub const DNS_PORT: u16 = 53;
+pub const DNS_TYPE_A: u16 = 1;
+pub const DNS_TYPE_SOA: u16 = 6;
+pub const DNS_TYPE_AAAA: u16 = 28;
+pub const DNS_CLASS_IN: u16 = 1;
+
+pub const DNS_RCODE_NOERROR: u16 = 0;
+pub const DNS_RCODE_NXDOMAIN: u16 = 3;
+
+// the limits of the resolv.conf options, same as glibc
+pub const RESOLV_MAXNS: usize = 3;
+pub const RESOLV_MAXNDOTS: usize = 15;
+pub const RESOLV_MAXTIMEOUT: u64 = 30;
+pub const RESOLV_MAXATTEMPTS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<SocketAddr>,
+    pub search: Vec<String>,
+    pub ndots: usize,
+    pub timeout: u64,
+    pub attempts: usize,
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        return Self {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+            timeout: 5,
+            attempts: 2,
+        }
+    }
+}
+
+impl ResolvConf {
+    // Parse reads the resolv.conf of the container, the lines not used by the lookup are
+    // ignored. The local server is used when there is no nameserver, as glibc does.
+    pub fn Parse(data: &str) -> Self {
+        let mut conf = Self::default();
+        for line in data.lines() {
+            let mut fields = line.split_whitespace();
+            let key = match fields.next() {
+                None => continue,
+                Some(k) => k,
+            };
+
+            match key {
+                "nameserver" => {
+                    let addr = match fields.next().and_then(|a| a.parse::<IpAddr>().ok()) {
+                        None => continue,
+                        Some(a) => a,
+                    };
+
+                    if conf.nameservers.len() < RESOLV_MAXNS {
+                        conf.nameservers.push(SocketAddr::new(addr, DNS_PORT));
+                    }
+                }
+                "search" => {
+                    conf.search = fields.map(|d| d.trim_end_matches('.').to_string()).collect();
+                }
+                "domain" => {
+                    conf.search = fields.take(1).map(|d| d.trim_end_matches('.').to_string()).collect();
+                }
+                "options" => {
+                    for opt in fields {
+                        let (name, val) = match opt.find(':') {
+                            None => continue,
+                            Some(i) => (&opt[..i], &opt[i + 1..]),
+                        };
+
+                        let val = match val.parse::<usize>() {
+                            Err(_) => continue,
+                            Ok(v) => v,
+                        };
+
+                        match name {
+                            "ndots" => conf.ndots = core::cmp::min(val, RESOLV_MAXNDOTS),
+                            "timeout" => conf.timeout = core::cmp::max(1, core::cmp::min(val as u64, RESOLV_MAXTIMEOUT)),
+                            "attempts" => conf.attempts = core::cmp::max(1, core::cmp::min(val, RESOLV_MAXATTEMPTS)),
+                            _ => (),
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        conf.search.retain(|d| d.len() > 0);
+        if conf.nameservers.len() == 0 {
+            conf.nameservers.push(SocketAddr::new(IpAddr::from([127, 0, 0, 1]), DNS_PORT));
+        }
+
+        return conf
+    }
+
+    // Load gets the resolv.conf the guest sees, the bind mount source when there is one
+    pub fn Load() -> Self {
+        let idx = EtcFileIdx("/etc/resolv.conf").unwrap();
+        let data = match ETC_FILES_MGR.lock().Generate(idx) {
+            Ok(data) => data,
+            Err(_) => std::fs::read("/etc/resolv.conf").unwrap_or_default(),
+        };
+
+        return Self::Parse(&String::from_utf8_lossy(&data))
+    }
+
+    // SearchNames returns the names to query for name in order, the same as res_search
+    pub fn SearchNames(&self, name: &str) -> Vec<String> {
+        if name.ends_with('.') {
+            return vec![name.trim_end_matches('.').to_string()]
+        }
+
+        let mut names = Vec::new();
+        let dots = name.matches('.').count();
+        if dots >= self.ndots {
+            names.push(name.to_string());
+        }
+
+        for domain in &self.search {
+            names.push(format!("{}.{}", name, domain));
+        }
+
+        if dots < self.ndots {
+            names.push(name.to_string());
+        }
+
+        return names
+    }
+}
+
+// BuildQuery makes the dns query of name with the recursion desired
+pub fn BuildQuery(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>> {
+    if name.len() == 0 || name.len() > 253 {
+        return Err(Error::SysError(SysErr::EINVAL))
+    }
+
+    let mut query = Vec::with_capacity(18 + name.len());
+    query.extend_from_slice(&id.to_be_bytes());
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        if label.len() == 0 || label.len() > 63 {
+            return Err(Error::SysError(SysErr::EINVAL))
+        }
+
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+    return Ok(query)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DnsAnswer {
+    // the addresses of the name and the ttl of the answer
+    Addrs(Vec<ResolvedAddr>, u32),
+    // NXDOMAIN or no record of the type, with the negative ttl of the SOA
+    NotFound(u32),
+}
+
+fn ReadU16(data: &[u8], off: usize) -> Result<u16> {
+    if off + 2 > data.len() {
+        return Err(Error::SysError(SysErr::EBADMSG))
+    }
+
+    return Ok(u16::from_be_bytes([data[off], data[off + 1]]))
+}
+
+fn ReadU32(data: &[u8], off: usize) -> Result<u32> {
+    if off + 4 > data.len() {
+        return Err(Error::SysError(SysErr::EBADMSG))
+    }
+
+    return Ok(u32::from_be_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]]))
+}
+
+// SkipName returns the offset after the name at off, the compressed name ends at the pointer
+fn SkipName(data: &[u8], mut off: usize) -> Result<usize> {
+    loop {
+        if off >= data.len() {
+            return Err(Error::SysError(SysErr::EBADMSG))
+        }
+
+        let len = data[off] as usize;
+        if len == 0 {
+            return Ok(off + 1)
+        }
+
+        match len & 0xc0 {
+            0xc0 => return Ok(off + 2),
+            0 => off += 1 + len,
+            _ => return Err(Error::SysError(SysErr::EBADMSG)),
+        }
+    }
+}
+
+// ParseResponse takes the addresses of qtype from the answer section. The record sets in the
+// answer of a name with CNAMEs are all for the query, so the ttl is the min of all of them.
+pub fn ParseResponse(data: &[u8], qtype: u16) -> Result<DnsAnswer> {
+    let flags = ReadU16(data, 2)?;
+    let qdcount = ReadU16(data, 4)?;
+    let ancount = ReadU16(data, 6)?;
+    let nscount = ReadU16(data, 8)?;
+
+    let rcode = flags & 0xf;
+    if rcode != DNS_RCODE_NOERROR && rcode != DNS_RCODE_NXDOMAIN {
+        return Err(Error::SysError(SysErr::EAGAIN))
+    }
+
+    let mut off = 12;
+    for _ in 0..qdcount {
+        off = SkipName(data, off)? + 4;
+    }
+
+    let mut addrs = Vec::new();
+    let mut ttl = u32::MAX;
+    for _ in 0..ancount {
+        off = SkipName(data, off)?;
+        let rtype = ReadU16(data, off)?;
+        let rttl = ReadU32(data, off + 4)?;
+        let rdlen = ReadU16(data, off + 8)? as usize;
+        let rdata = off + 10;
+        off = rdata + rdlen;
+        if off > data.len() {
+            return Err(Error::SysError(SysErr::EBADMSG))
+        }
+
+        ttl = core::cmp::min(ttl, rttl);
+        let mut addr = ResolvedAddr::default();
+        match (rtype, rdlen) {
+            (DNS_TYPE_A, 4) if qtype == DNS_TYPE_A => addr.family = AFType::AF_INET as u32,
+            (DNS_TYPE_AAAA, 16) if qtype == DNS_TYPE_AAAA => addr.family = AFType::AF_INET6 as u32,
+            _ => continue,
+        }
+
+        addr.addr[..rdlen].copy_from_slice(&data[rdata..off]);
+        addrs.push(addr);
+    }
+
+    if rcode == DNS_RCODE_NOERROR && addrs.len() > 0 {
+        return Ok(DnsAnswer::Addrs(addrs, ttl))
+    }
+
+    // the negative ttl is the min of the SOA ttl and its minimum field, RFC 2308
+    for _ in 0..nscount {
+        off = SkipName(data, off)?;
+        let rtype = ReadU16(data, off)?;
+        let rttl = ReadU32(data, off + 4)?;
+        let rdlen = ReadU16(data, off + 8)? as usize;
+        let rdata = off + 10;
+        off = rdata + rdlen;
+        if off > data.len() {
+            return Err(Error::SysError(SysErr::EBADMSG))
+        }
+
+        if rtype == DNS_TYPE_SOA {
+            let serial = SkipName(data, SkipName(data, rdata)?)?;
+            let minimum = ReadU32(data, serial + 16)?;
+            return Ok(DnsAnswer::NotFound(core::cmp::min(rttl, minimum)))
+        }
+    }
+
+    return Ok(DnsAnswer::NotFound(0))
+}
+
+pub struct CacheEntry {
+    // the addresses, empty for the name not found
+    pub addrs: Vec<ResolvedAddr>,
+    pub expire: Instant,
+}
+
+#[derive(Default)]
+pub struct ResolverQueue {
+    pub reqs: VecDeque<u64>,
+    pub threads: usize,
+    // the threads waiting for the requests
+    pub idle: usize,
+}
+
+impl ResolverQueue {
+    // Push queues the request, it returns whether it is queued and whether a thread is started
+    // for it
+    pub fn Push(&mut self, addr: u64) -> (bool, bool) {
+        if self.reqs.len() >= RESOLVER_QUEUE_MAX {
+            return (false, false)
+        }
+
+        self.reqs.push_back(addr);
+        if self.idle >= self.reqs.len() || self.threads >= RESOLVER_THREADS_MAX {
+            return (true, false)
+        }
+
+        self.threads += 1;
+        return (true, true)
+    }
+}
+
+// Resolver serves the ResolveHost qcalls of the guest. The lookups wait for the dns servers so
+// they are queued to the resolver threads instead of holding the vcpu, the answers are cached
+// with the ttl of the records.
+pub struct Resolver {
+    pub queue: std::sync::Mutex<ResolverQueue>,
+    pub cond: Condvar,
+    pub cache: Mutex<BTreeMap<(String, i32), CacheEntry>>,
+    // the servers which didn't answer and the time they are tried first again
+    pub down: Mutex<BTreeMap<SocketAddr, Instant>>,
+}
+
+impl Resolver {
+    pub fn New() -> Self {
+        return Self {
+            queue: std::sync::Mutex::new(ResolverQueue::default()),
+            cond: Condvar::new(),
+            cache: Mutex::new(BTreeMap::new()),
+            down: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Submit queues the QMsg at addr, the task is woken up when its ret is set
+    pub fn Submit(&'static self, addr: u64) {
+        let (queued, start) = self.queue.lock().unwrap().Push(addr);
+        if !queued {
+            Self::Complete(addr, -SysErr::EAGAIN as i64);
+            return
+        }
+
+        if start {
+            std::thread::Builder::new()
+                .name(String::from("resolver"))
+                .spawn(move || RESOLVER.Process())
+                .unwrap();
+        } else {
+            self.cond.notify_one();
+        }
+    }
+
+    fn Complete(addr: u64, ret: i64) {
+        let qmsg = unsafe { &mut *(addr as *mut QMsg) };
+        let taskId = qmsg.taskId;
+        qmsg.ret = ret as u64;
+        if taskId.Addr() != 0 {
+            SHARE_SPACE.scheduler.ScheduleQ(taskId, taskId.Queue());
+        }
+    }
+
+    fn Process(&self) {
+        loop {
+            let addr = {
+                let mut queue = self.queue.lock().unwrap();
+                loop {
+                    match queue.reqs.pop_front() {
+                        Some(addr) => break addr,
+                        None => {
+                            queue.idle += 1;
+                            queue = self.cond.wait(queue).unwrap();
+                            queue.idle -= 1;
+                        }
+                    }
+                }
+            };
+
+            let qmsg = unsafe { &*(addr as *const QMsg) };
+            let ret = match qmsg.msg {
+                Msg::ResolveHost(msg) => self.Resolve(msg),
+                _ => panic!("resolver: unexpected qcall {:?}", qmsg.msg),
+            };
+
+            Self::Complete(addr, ret);
+        }
+    }
+
+    // Resolve writes the addresses of the name in buf, it returns the count of the entries,
+    // -ENOENT when the name is not found or -EAGAIN when the servers don't answer
+    pub fn Resolve(&self, msg: &ResolveHost) -> i64 {
+        if msg.nameLen == 0 || msg.nameLen > 254 {
+            return -SysErr::EINVAL as i64
+        }
+
+        let name = unsafe { slice::from_raw_parts(msg.name as *const u8, msg.nameLen) };
+        let name = match core::str::from_utf8(name) {
+            Err(_) => return -SysErr::EINVAL as i64,
+            Ok(n) => n.to_ascii_lowercase(),
+        };
+
+        let family = msg.family;
+        let qtypes: &[u16] = match family as u64 {
+            AFType::AF_INET => &[DNS_TYPE_A],
+            AFType::AF_INET6 => &[DNS_TYPE_AAAA],
+            AFType::AF_UNSPEC => &[DNS_TYPE_A, DNS_TYPE_AAAA],
+            _ => return -SysErr::EAFNOSUPPORT as i64,
+        };
+
+        let addrs = match self.Lookup(&name, family) {
+            Some(addrs) => addrs,
+            None => {
+                let conf = ResolvConf::Load();
+                let query = |name: &str, qtype: u16| self.QueryType(&conf, name, qtype);
+                let (addrs, ttl) = match Query(&conf, &name, qtypes, query) {
+                    Err(Error::SysError(e)) => return -e as i64,
+                    Err(e) => {
+                        error!("ResolveHost {} fail with error {:?}", name, e);
+                        return -SysErr::EAGAIN as i64
+                    }
+                    Ok(r) => r,
+                };
+
+                self.Insert(&name, family, &addrs, ttl);
+                addrs
+            }
+        };
+
+        if addrs.len() == 0 {
+            return -SysErr::ENOENT as i64
+        }
+
+        let cnt = core::cmp::min(addrs.len(), msg.len / core::mem::size_of::<ResolvedAddr>());
+        let buf = unsafe { slice::from_raw_parts_mut(msg.buf as *mut ResolvedAddr, cnt) };
+        buf.copy_from_slice(&addrs[..cnt]);
+        return cnt as i64
+    }
+
+    fn Lookup(&self, name: &str, family: i32) -> Option<Vec<ResolvedAddr>> {
+        let mut cache = self.cache.lock();
+        let key = (name.to_string(), family);
+        match cache.get(&key) {
+            None => return None,
+            Some(e) if e.expire > Instant::now() => return Some(e.addrs.clone()),
+            Some(_) => {
+                cache.remove(&key);
+                return None
+            }
+        }
+    }
+
+    fn Insert(&self, name: &str, family: i32, addrs: &[ResolvedAddr], ttl: u32) {
+        let ttl = core::cmp::min(ttl, RESOLVER_TTL_MAX);
+        if ttl == 0 {
+            return
+        }
+
+        let now = Instant::now();
+        let mut cache = self.cache.lock();
+        if cache.len() >= RESOLVER_CACHE_MAX {
+            cache.retain(|_, e| e.expire > now);
+        }
+
+        if cache.len() >= RESOLVER_CACHE_MAX {
+            let key = cache.iter().min_by_key(|(_, e)| e.expire).map(|(k, _)| k.clone()).unwrap();
+            cache.remove(&key);
+        }
+
+        cache.insert((name.to_string(), family), CacheEntry {
+            addrs: addrs.to_vec(),
+            expire: now + Duration::from_secs(ttl as u64),
+        });
+    }
+
+    // Servers returns the servers in the order they are tried, the ones down go last
+    pub fn Servers(&self, conf: &ResolvConf, now: Instant) -> Vec<SocketAddr> {
+        let mut down = self.down.lock();
+        down.retain(|_, until| *until > now);
+        let (mut up, failed): (Vec<SocketAddr>, Vec<SocketAddr>) = conf.nameservers.iter()
+            .partition(|s| !down.contains_key(s));
+        up.extend_from_slice(&failed);
+        return up
+    }
+
+    fn QueryType(&self, conf: &ResolvConf, name: &str, qtype: u16) -> Result<DnsAnswer> {
+        let id = rand::random::<u16>();
+        let query = BuildQuery(id, name, qtype)?;
+        let servers = self.Servers(conf, Instant::now());
+        for _ in 0..conf.attempts {
+            for server in &servers {
+                let resp = match Self::Exchange(server, &query, conf.timeout) {
+                    Err(e) => {
+                        debug!("resolver: query {} to {} fail {:?}", name, server, e);
+                        let until = Instant::now() + Duration::from_secs(RESOLVER_SERVER_DOWN_SECS);
+                        self.down.lock().insert(*server, until);
+                        continue;
+                    }
+                    Ok(r) => {
+                        self.down.lock().remove(server);
+                        r
+                    }
+                };
+
+                match ParseResponse(&resp, qtype) {
+                    Err(e) => debug!("resolver: response of {} from {} fail {:?}", name, server, e),
+                    Ok(answer) => return Ok(answer),
+                }
+            }
+        }
+
+        return Err(Error::SysError(SysErr::EAGAIN))
+    }
+
+    // Exchange sends the query over udp, the truncated response is retried over tcp
+    fn Exchange(server: &SocketAddr, query: &[u8], timeout: u64) -> Result<Vec<u8>> {
+        let timeout = Duration::from_secs(timeout);
+        let local = match server {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+
+        let sock = UdpSocket::bind(local).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+        sock.connect(server).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+        sock.send(query).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 4096];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(Error::SysError(SysErr::ETIMEDOUT))
+            }
+
+            sock.set_read_timeout(Some(deadline - now)).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+            let cnt = sock.recv(&mut buf).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+
+            // the response must be for the query, the others are stale or spoofed
+            if cnt < 12 || buf[..2] != query[..2] || buf[2] & 0x80 == 0 {
+                continue;
+            }
+
+            if buf[2] & 0x02 == 0 {
+                return Ok(buf[..cnt].to_vec())
+            }
+
+            break;
+        }
+
+        let mut stream = TcpStream::connect_timeout(server, timeout).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+        stream.set_read_timeout(Some(timeout)).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+        let mut req = (query.len() as u16).to_be_bytes().to_vec();
+        req.extend_from_slice(query);
+        stream.write_all(&req).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+        let mut resp = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut resp).map_err(|e| Error::IOError(format!("{:?}", e)))?;
+        if resp.len() < 12 || resp[..2] != query[..2] {
+            return Err(Error::SysError(SysErr::EBADMSG))
+        }
+
+        return Ok(resp)
+    }
+}
+
+// Query looks up the names of the search list in order until one of them has addresses. The
+// types of AF_UNSPEC are queried independently, the addresses of one of them are returned when
+// the other fails, without caching them. It returns the addresses with their ttl, or no address
+// with the min negative ttl.
+pub fn Query(conf: &ResolvConf, name: &str, qtypes: &[u16], mut query: impl FnMut(&str, u16) -> Result<DnsAnswer>) -> Result<(Vec<ResolvedAddr>, u32)> {
+    let mut negTtl = u32::MAX;
+    for candidate in conf.SearchNames(name) {
+        let mut addrs = Vec::new();
+        let mut ttl = u32::MAX;
+        let mut err = None;
+        for qtype in qtypes {
+            match query(&candidate, *qtype) {
+                Err(e) => err = Some(e),
+                Ok(DnsAnswer::Addrs(a, t)) => {
+                    addrs.extend_from_slice(&a);
+                    ttl = core::cmp::min(ttl, t);
+                }
+                Ok(DnsAnswer::NotFound(t)) => negTtl = core::cmp::min(negTtl, t),
+            }
+        }
+
+        if addrs.len() > 0 {
+            if err.is_some() {
+                ttl = 0;
+            }
+
+            return Ok((addrs, ttl))
+        }
+
+        if let Some(e) = err {
+            return Err(e)
+        }
+    }
+
+    return Ok((Vec::new(), negTtl))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn Answer(name: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+        let mut rr = name.to_vec();
+        rr.extend_from_slice(&rtype.to_be_bytes());
+        rr.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        rr.extend_from_slice(&ttl.to_be_bytes());
+        rr.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        rr.extend_from_slice(rdata);
+        return rr
+    }
+
+    fn Response(qtype: u16, rcode: u8, answers: &[Vec<u8>], authority: &[Vec<u8>]) -> Vec<u8> {
+        let mut resp = BuildQuery(0x1234, "www.example.com", qtype).unwrap();
+        resp[2] = 0x81;
+        resp[3] = 0x80 | rcode;
+        resp[7] = answers.len() as u8;
+        resp[9] = authority.len() as u8;
+        for rr in answers.iter().chain(authority.iter()) {
+            resp.extend_from_slice(rr);
+        }
+
+        return resp
+    }
+
+    #[test]
+    fn test_build_query() {
+        let query = BuildQuery(0xabcd, "a.bc", DNS_TYPE_AAAA).unwrap();
+        assert_eq!(query, vec![0xab, 0xcd, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+                               1, b'a', 2, b'b', b'c', 0, 0, 28, 0, 1]);
+        assert!(BuildQuery(1, "a..b", DNS_TYPE_A).is_err());
+        assert!(BuildQuery(1, &"a".repeat(64), DNS_TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_parse_response() {
+        // www.example.com CNAME example.com, with the compressed names
+        let cname = Answer(&[0xc0, 12], 5, 300, &[0xc0, 16]);
+        let a = Answer(&[0xc0, 16], DNS_TYPE_A, 60, &[93, 184, 216, 34]);
+        let resp = Response(DNS_TYPE_A, 0, &[cname.clone(), a], &[]);
+        let mut addr = ResolvedAddr { family: AFType::AF_INET as u32, ..Default::default() };
+        addr.addr[..4].copy_from_slice(&[93, 184, 216, 34]);
+        assert_eq!(ParseResponse(&resp, DNS_TYPE_A).unwrap(), DnsAnswer::Addrs(vec![addr], 60));
+
+        // the negative ttl of the SOA: mname, rname, serial, refresh, retry, expire, minimum
+        let mut soa = vec![0xc0, 16, 0xc0, 16];
+        for v in [1u32, 7200, 3600, 1209600, 120].iter() {
+            soa.extend_from_slice(&v.to_be_bytes());
+        }
+
+        let soa = Answer(&[0xc0, 16], DNS_TYPE_SOA, 900, &soa);
+        let resp = Response(DNS_TYPE_AAAA, 3, &[], &[soa.clone()]);
+        assert_eq!(ParseResponse(&resp, DNS_TYPE_AAAA).unwrap(), DnsAnswer::NotFound(120));
+
+        // NODATA, the name has only the CNAME for AAAA
+        let resp = Response(DNS_TYPE_AAAA, 0, &[cname], &[soa]);
+        assert_eq!(ParseResponse(&resp, DNS_TYPE_AAAA).unwrap(), DnsAnswer::NotFound(120));
+
+        // SERVFAIL is not an answer and a truncated record is rejected
+        assert!(ParseResponse(&Response(DNS_TYPE_A, 2, &[], &[]), DNS_TYPE_A).is_err());
+        let resp = Response(DNS_TYPE_A, 0, &[Answer(&[0xc0, 12], DNS_TYPE_A, 60, &[1, 2, 3, 4])], &[]);
+        assert!(ParseResponse(&resp[..resp.len() - 1], DNS_TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_resolv_conf() {
+        let conf = ResolvConf::Parse("# comment\nnameserver 10.96.0.10\nnameserver fe80::1%eth0\n\
+                                      nameserver ::1\nsearch default.svc.cluster.local svc.cluster.local.\n\
+                                      options ndots:5 timeout:60 attempts:3 rotate\n");
+        assert_eq!(conf.nameservers, vec!["10.96.0.10:53".parse().unwrap(), "[::1]:53".parse().unwrap()]);
+        assert_eq!(conf.search, vec!["default.svc.cluster.local", "svc.cluster.local"]);
+        assert_eq!((conf.ndots, conf.timeout, conf.attempts), (5, RESOLV_MAXTIMEOUT, 3));
+
+        let conf = ResolvConf::Parse("domain example.com\n");
+        assert_eq!(conf.nameservers, vec!["127.0.0.1:53".parse().unwrap()]);
+        assert_eq!(conf.search, vec!["example.com"]);
+    }
+
+    #[test]
+    fn test_search_names() {
+        let conf = ResolvConf {
+            search: vec!["ns.svc".to_string(), "svc".to_string()],
+            ndots: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(conf.SearchNames("db"), vec!["db.ns.svc", "db.svc", "db"]);
+        assert_eq!(conf.SearchNames("a.b.c"), vec!["a.b.c", "a.b.c.ns.svc", "a.b.c.svc"]);
+        assert_eq!(conf.SearchNames("db.other."), vec!["db.other"]);
+    }
+
+    fn V4(last: u8) -> ResolvedAddr {
+        let mut addr = ResolvedAddr { family: AFType::AF_INET as u32, ..Default::default() };
+        addr.addr[..4].copy_from_slice(&[10, 0, 0, last]);
+        return addr
+    }
+
+    #[test]
+    fn test_query_unspec() {
+        let conf = ResolvConf {
+            search: vec!["svc".to_string()],
+            ..Default::default()
+        };
+
+        // the A answer is returned when the AAAA query fails, it is not cached
+        let ret = Query(&conf, "db", &[DNS_TYPE_A, DNS_TYPE_AAAA], |_, qtype| match qtype {
+            DNS_TYPE_A => Ok(DnsAnswer::Addrs(vec![V4(1)], 60)),
+            _ => Err(Error::SysError(SysErr::EAGAIN)),
+        });
+        assert_eq!(ret.unwrap(), (vec![V4(1)], 0));
+
+        // the name of the search list without address is skipped
+        let ret = Query(&conf, "db", &[DNS_TYPE_A, DNS_TYPE_AAAA], |name, qtype| match (name, qtype) {
+            ("db", DNS_TYPE_AAAA) => Ok(DnsAnswer::Addrs(vec![V4(2)], 30)),
+            ("db", _) => Ok(DnsAnswer::Addrs(vec![V4(3)], 60)),
+            _ => Ok(DnsAnswer::NotFound(10)),
+        });
+        assert_eq!(ret.unwrap(), (vec![V4(3), V4(2)], 30));
+
+        let ret = Query(&conf, "db", &[DNS_TYPE_A], |_, _| Ok(DnsAnswer::NotFound(10)));
+        assert_eq!(ret.unwrap(), (Vec::new(), 10));
+        assert!(Query(&conf, "db", &[DNS_TYPE_A, DNS_TYPE_AAAA], |_, _| Err(Error::SysError(SysErr::EAGAIN))).is_err());
+    }
+
+    #[test]
+    fn test_resolver_queue() {
+        let mut queue = ResolverQueue::default();
+        for i in 0..RESOLVER_THREADS_MAX {
+            assert_eq!(queue.Push(i as u64), (true, true));
+        }
+
+        assert_eq!(queue.Push(0), (true, false));
+        assert_eq!(queue.threads, RESOLVER_THREADS_MAX);
+
+        // the idle thread takes the request
+        let mut queue = ResolverQueue { idle: 1, ..Default::default() };
+        assert_eq!(queue.Push(1), (true, false));
+        assert_eq!(queue.Push(2), (true, true));
+
+        let mut queue = ResolverQueue { threads: RESOLVER_THREADS_MAX, ..Default::default() };
+        for i in 0..RESOLVER_QUEUE_MAX {
+            assert!(queue.Push(i as u64).0);
+        }
+
+        assert_eq!(queue.Push(0), (false, false));
+    }
+
+    #[test]
+    fn test_servers_down() {
+        let resolver = Resolver::New();
+        let a: SocketAddr = "10.0.0.1:53".parse().unwrap();
+        let b: SocketAddr = "10.0.0.2:53".parse().unwrap();
+        let conf = ResolvConf {
+            nameservers: vec![a, b],
+            ..Default::default()
+        };
+
+        let now = Instant::now();
+        resolver.down.lock().insert(a, now + Duration::from_secs(RESOLVER_SERVER_DOWN_SECS));
+        assert_eq!(resolver.Servers(&conf, now), vec![b, a]);
+        assert_eq!(resolver.Servers(&conf, now + Duration::from_secs(RESOLVER_SERVER_DOWN_SECS)), vec![a, b]);
+        assert!(resolver.down.lock().is_empty());
+    }
+
+    #[test]
+    fn test_cache() {
+        let resolver = Resolver::New();
+        resolver.Insert("db", AFType::AF_INET as i32, &[V4(1)], 60);
+        resolver.Insert("web", AFType::AF_INET as i32, &[V4(2)], 0);
+        assert_eq!(resolver.Lookup("db", AFType::AF_INET as i32), Some(vec![V4(1)]));
+        assert_eq!(resolver.Lookup("db", AFType::AF_INET6 as i32), None);
+        assert_eq!(resolver.Lookup("web", AFType::AF_INET as i32), None);
+
+        for i in 0..RESOLVER_CACHE_MAX + 1 {
+            resolver.Insert(&format!("h{}", i), AFType::AF_INET as i32, &[V4(1)], 60 + i as u32);
+        }
+
+        // the entry expiring first is evicted
+        assert_eq!(resolver.cache.lock().len(), RESOLVER_CACHE_MAX);
+        assert_eq!(resolver.Lookup("db", AFType::AF_INET as i32), None);
+    }
+
+    #[test]
+    fn test_exchange() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let a = Answer(&[0xc0, 12], DNS_TYPE_A, 60, &[10, 0, 0, 1]);
+        let resp = Response(DNS_TYPE_A, 0, &[a], &[]);
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            let (_, peer) = server.recv_from(&mut buf).unwrap();
+            // the response of another query is ignored
+            let mut stale = resp.clone();
+            stale[1] ^= 1;
+            server.send_to(&stale, peer).unwrap();
+            server.send_to(&resp, peer).unwrap();
+        });
+
+        let query = BuildQuery(0x1234, "www.example.com", DNS_TYPE_A).unwrap();
+        let data = Resolver::Exchange(&addr, &query, 5).unwrap();
+        handle.join().unwrap();
+        assert_eq!(ParseResponse(&data, DNS_TYPE_A).unwrap(), DnsAnswer::Addrs(vec![V4(1)], 60));
+    }
+}
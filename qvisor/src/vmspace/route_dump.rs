@@ -0,0 +1,164 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use std::mem;
+use libc::*;
+
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+
+lazy_static! {
+    pub static ref ROUTE_DUMP: Mutex<RouteDump> = Mutex::new(RouteDump::default());
+}
+
+const NLMSG_HDRLEN: usize = LibcConst::NLMSG_HDRLEN as usize;
+
+fn NlmsgAlign(len: usize) -> usize {
+    return (len + LibcConst::NLMSG_ALIGNTO as usize - 1) & !(LibcConst::NLMSG_ALIGNTO as usize - 1)
+}
+
+// RouteDump is the link, address and route dumps of the sandbox network namespace taken at the
+// sandbox start. The guest netlink route sockets answer RTM_GETLINK/RTM_GETADDR/RTM_GETROUTE
+// from it, each dump is the concatenated nlmsgs without NLMSG_DONE.
+#[derive(Default)]
+pub struct RouteDump {
+    pub loaded: bool,
+    pub links: Vec<u8>,
+    pub addrs: Vec<u8>,
+    pub routes: Vec<u8>,
+}
+
+impl RouteDump {
+    pub fn Snapshot(&mut self) -> Result<()> {
+        let fd = unsafe {
+            socket(AF_NETLINK, SOCK_RAW | SOCK_CLOEXEC, NETLINK_ROUTE)
+        };
+
+        if fd < 0 {
+            return Err(Error::SysError(errno::errno().0))
+        }
+
+        defer!(unsafe { close(fd); });
+
+        self.links = Dump(fd, LibcConst::RTM_GETLINK as u16, 1)?;
+        self.addrs = Dump(fd, LibcConst::RTM_GETADDR as u16, 2)?;
+        self.routes = Dump(fd, LibcConst::RTM_GETROUTE as u16, 3)?;
+        self.loaded = true;
+
+        info!("route dump snapshot: links {} bytes, addrs {} bytes, routes {} bytes",
+              self.links.len(), self.addrs.len(), self.routes.len());
+        return Ok(())
+    }
+
+    pub fn Get(&self, msgType: u16) -> Result<&[u8]> {
+        if !self.loaded {
+            return Err(Error::SysError(SysErr::ENODATA))
+        }
+
+        match msgType as u64 {
+            LibcConst::RTM_GETLINK => return Ok(&self.links),
+            LibcConst::RTM_GETADDR => return Ok(&self.addrs),
+            LibcConst::RTM_GETROUTE => return Ok(&self.routes),
+            _ => return Err(Error::SysError(SysErr::EINVAL)),
+        }
+    }
+}
+
+// Dump sends an AF_UNSPEC dump request of msgType and collects the replies till NLMSG_DONE
+fn Dump(fd: i32, msgType: u16, seq: u32) -> Result<Vec<u8>> {
+    // the family header after nlmsghdr: ifinfomsg, ifaddrmsg or rtmsg
+    let familyHdrLen = match msgType as u64 {
+        LibcConst::RTM_GETLINK => 16,
+        LibcConst::RTM_GETADDR => 8,
+        _ => 12,
+    };
+
+    let mut req = vec![0u8; NLMSG_HDRLEN + familyHdrLen];
+    let reqLen = req.len() as u32;
+    req[0..4].copy_from_slice(&reqLen.to_ne_bytes());
+    req[4..6].copy_from_slice(&msgType.to_ne_bytes());
+    req[6..8].copy_from_slice(&((LibcConst::NLM_F_REQUEST | LibcConst::NLM_F_DUMP) as u16).to_ne_bytes());
+    req[8..12].copy_from_slice(&seq.to_ne_bytes());
+
+    let mut addr: sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = AF_NETLINK as u16;
+
+    let ret = unsafe {
+        sendto(fd,
+               req.as_ptr() as *const c_void,
+               req.len(),
+               0,
+               &addr as *const _ as *const sockaddr,
+               mem::size_of::<sockaddr_nl>() as u32)
+    };
+
+    if ret < 0 {
+        return Err(Error::SysError(errno::errno().0))
+    }
+
+    let mut out = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = unsafe {
+            recv(fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0)
+        };
+
+        if n < 0 {
+            let errno = errno::errno().0;
+            if errno == SysErr::EINTR {
+                continue;
+            }
+            return Err(Error::SysError(errno))
+        }
+
+        let n = n as usize;
+        let mut offset = 0;
+        while offset + NLMSG_HDRLEN <= n {
+            let msgLen = u32::from_ne_bytes([buf[offset], buf[offset+1], buf[offset+2], buf[offset+3]]) as usize;
+            if msgLen < NLMSG_HDRLEN || offset + msgLen > n {
+                return Err(Error::SysError(SysErr::EINVAL))
+            }
+
+            let t = u16::from_ne_bytes([buf[offset+4], buf[offset+5]]) as u64;
+            let msgSeq = u32::from_ne_bytes([buf[offset+8], buf[offset+9], buf[offset+10], buf[offset+11]]);
+            let end = core::cmp::min(offset + NlmsgAlign(msgLen), n);
+
+            if msgSeq == seq {
+                match t {
+                    LibcConst::NLMSG_DONE => return Ok(out),
+                    LibcConst::NLMSG_ERROR => {
+                        if msgLen < NLMSG_HDRLEN + 4 {
+                            return Err(Error::SysError(SysErr::EINVAL))
+                        }
+
+                        let errno = i32::from_ne_bytes([buf[offset+16], buf[offset+17], buf[offset+18], buf[offset+19]]);
+                        if errno == 0 {
+                            return Ok(out)
+                        }
+                        return Err(Error::SysError(-errno))
+                    }
+                    _ => {
+                        // the messages are kept 4 bytes aligned so the guest walks them by nlmsg_len
+                        out.extend_from_slice(&buf[offset..end]);
+                        out.resize(NlmsgAlign(out.len()), 0);
+                    }
+                }
+            }
+
+            offset = end;
+        }
+    }
+}
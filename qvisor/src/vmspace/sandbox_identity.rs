@@ -0,0 +1,266 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use ed25519_dalek::Keypair;
+use ed25519_dalek::PublicKey;
+use ed25519_dalek::SecretKey;
+use ed25519_dalek::Signer;
+use sha2::Digest;
+use sha2::Sha256;
+use spin::Mutex;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use super::super::qlib::common::*;
+use super::super::qlib::linux_def::*;
+
+lazy_static! {
+    pub static ref SANDBOX_IDENTITY: Mutex<SandboxIdentity> = Mutex::new(SandboxIdentity::default());
+}
+
+// the Ed25519 secret key (the 32 bytes seed) of the identity token signature, it has to be owned
+// by root and not accessible to the group and the others. The services verify the token with
+// the public key, its sha256 is the kid of the token.
+pub const IDENTITY_KEY_FILE: &str = "/etc/quark/identity.key";
+
+// the annotations carrying the image of the container, the first one set is used
+pub const IMAGE_DIGEST_ANNOTATIONS: [&str; 3] = [
+    "org.opencontainers.image.digest",
+    "io.kubernetes.cri-o.ImageRef",
+    "io.kubernetes.cri.image-name",
+];
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct IdentityClaims {
+    #[serde(rename = "iss")]
+    pub issuer: String,
+    #[serde(rename = "sandbox_id")]
+    pub sandboxId: String,
+    #[serde(rename = "image_digest")]
+    pub imageDigest: String,
+    #[serde(rename = "config_hash")]
+    pub configHash: String,
+    #[serde(rename = "iat")]
+    pub issuedAt: u64,
+    #[serde(rename = "exp")]
+    pub expiration: u64,
+}
+
+// SandboxIdentity is the identity document of the sandbox, a JWT signed with EdDSA (Ed25519) by
+// the key in IDENTITY_KEY_FILE. The claims are collected at the sandbox start and the guest reads
+// the token from /proc/sandbox_identity so that the workloads can prove which sandbox they run
+// in. The token expires after ttl seconds, it is signed again when half of it is passed.
+#[derive(Default)]
+pub struct SandboxIdentity {
+    pub keypair: Option<Keypair>,
+    pub claims: IdentityClaims,
+    pub ttl: u64,
+    pub token: Vec<u8>,
+}
+
+impl SandboxIdentity {
+    pub fn Generate(&mut self, id: &str, bundleDir: &str, annotations: &HashMap<String, String>, ttl: u64) -> Result<()> {
+        let keypair = LoadKey(IDENTITY_KEY_FILE)?;
+
+        let config = std::fs::read(format!("{}/config.json", bundleDir))
+            .map_err(|e| Error::Common(format!("read bundle config fail: {:?}", e)))?;
+
+        let mut imageDigest = String::new();
+        for name in IMAGE_DIGEST_ANNOTATIONS.iter() {
+            match annotations.get(*name) {
+                Some(v) if v.len() > 0 => {
+                    imageDigest = v.to_string();
+                    break;
+                }
+                _ => (),
+            }
+        }
+
+        let configHash = format!("sha256:{}", Hex(&Sha256::digest(&config)));
+        info!("sandbox identity: sandbox {}, image {:?}, config {}, key {}", id, &imageDigest, &configHash, KeyId(&keypair.public));
+
+        self.claims = IdentityClaims {
+            issuer: "quark".to_string(),
+            sandboxId: id.to_string(),
+            imageDigest: imageDigest,
+            configHash: configHash,
+            issuedAt: 0,
+            expiration: 0,
+        };
+        self.ttl = ttl;
+        self.keypair = Some(keypair);
+        return self.Sign(Now())
+    }
+
+    // Sign signs the claims issued at now
+    pub fn Sign(&mut self, now: u64) -> Result<()> {
+        let keypair = match &self.keypair {
+            None => return Err(Error::SysError(SysErr::ENODATA)),
+            Some(k) => k,
+        };
+
+        self.claims.issuedAt = now;
+        self.claims.expiration = now + self.ttl;
+        self.token = SignToken(keypair, &self.claims)?.into_bytes();
+        return Ok(())
+    }
+
+    pub fn Get(&mut self) -> Result<&[u8]> {
+        if self.token.len() == 0 {
+            return Err(Error::SysError(SysErr::ENODATA))
+        }
+
+        let now = Now();
+        if now >= self.claims.issuedAt + self.ttl / 2 {
+            self.Sign(now)?;
+        }
+
+        return Ok(&self.token)
+    }
+}
+
+// LoadKey reads the Ed25519 secret key of the file which only root can access
+pub fn LoadKey(path: &str) -> Result<Keypair> {
+    let meta = std::fs::symlink_metadata(path)
+        .map_err(|e| Error::Common(format!("stat {} fail: {:?}", path, e)))?;
+    if !meta.file_type().is_file() || meta.uid() != 0 || meta.mode() & 0o077 != 0 {
+        return Err(Error::Common(format!("{} must be a regular file of root with mode 0600 or 0400, it is {:o} of uid {}",
+                                         path, meta.mode(), meta.uid())))
+    }
+
+    let key = std::fs::read(path)
+        .map_err(|e| Error::Common(format!("read {} fail: {:?}", path, e)))?;
+    return KeypairFromSeed(&key)
+}
+
+pub fn KeypairFromSeed(seed: &[u8]) -> Result<Keypair> {
+    let secret = SecretKey::from_bytes(seed)
+        .map_err(|e| Error::Common(format!("invalid identity key: {:?}", e)))?;
+    let public = PublicKey::from(&secret);
+    return Ok(Keypair {
+        secret: secret,
+        public: public,
+    })
+}
+
+// KeyId is the kid of the token, the sha256 of the public key
+pub fn KeyId(public: &PublicKey) -> String {
+    return Hex(&Sha256::digest(public.as_bytes()))
+}
+
+// SignToken returns the JWS compact serialization of the claims with a trailing newline
+pub fn SignToken(keypair: &Keypair, claims: &IdentityClaims) -> Result<String> {
+    let header = format!(r#"{{"alg":"EdDSA","typ":"JWT","kid":"{}"}}"#, KeyId(&keypair.public));
+    let claims = serde_json::to_vec(claims)
+        .map_err(|e| Error::Common(format!("identity claims ser fail: {:?}", e)))?;
+
+    let token = format!("{}.{}", Base64Url(header.as_bytes()), Base64Url(&claims));
+    let signature = keypair.sign(token.as_bytes());
+    return Ok(format!("{}.{}\n", token, Base64Url(&signature.to_bytes())))
+}
+
+pub fn Base64Url(data: &[u8]) -> String {
+    return base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn Hex(data: &[u8]) -> String {
+    return data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn Now() -> u64 {
+    return SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Signature;
+    use ed25519_dalek::Verifier;
+    use std::convert::TryFrom;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn Identity(ttl: u64) -> SandboxIdentity {
+        let mut identity = SandboxIdentity::default();
+        identity.keypair = Some(KeypairFromSeed(&[7; 32]).unwrap());
+        identity.claims.sandboxId = "sandbox".to_string();
+        identity.ttl = ttl;
+        return identity
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let mut identity = Identity(600);
+        identity.Sign(1000).unwrap();
+        let token = String::from_utf8(identity.token.clone()).unwrap();
+        let parts: Vec<&str> = token.trim_end().split('.').collect();
+        assert_eq!(parts.len(), 3);
+
+        // the services verify the token with the public key only
+        let public = KeypairFromSeed(&[7; 32]).unwrap().public;
+        let signed = format!("{}.{}", parts[0], parts[1]);
+        let signature = base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD).unwrap();
+        let signature = Signature::try_from(&signature[..]).unwrap();
+        assert!(public.verify(signed.as_bytes(), &signature).is_ok());
+        assert!(public.verify(b"forged", &signature).is_err());
+
+        let header = base64::decode_config(parts[0], base64::URL_SAFE_NO_PAD).unwrap();
+        let header = String::from_utf8(header).unwrap();
+        assert!(header.contains(r#""alg":"EdDSA""#));
+        assert!(header.contains(&KeyId(&public)));
+
+        let claims = base64::decode_config(parts[1], base64::URL_SAFE_NO_PAD).unwrap();
+        let claims = String::from_utf8(claims).unwrap();
+        assert!(claims.contains(r#""iat":1000"#));
+        assert!(claims.contains(r#""exp":1600"#));
+    }
+
+    #[test]
+    fn test_resign() {
+        let mut identity = Identity(600);
+        assert_eq!(identity.Get(), Err(Error::SysError(SysErr::ENODATA)));
+
+        // the token past half of its lifetime is signed again
+        identity.Sign(1000).unwrap();
+        let old = identity.token.clone();
+        let token = identity.Get().unwrap().to_vec();
+        assert_ne!(old, token);
+        assert!(identity.claims.expiration > Now());
+    }
+
+    #[test]
+    fn test_load_key_mode() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[7; 32]).unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(LoadKey(&path).is_err());
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let owner = std::fs::metadata(&path).unwrap().uid();
+        assert_eq!(LoadKey(&path).is_ok(), owner == 0);
+    }
+
+    #[test]
+    fn test_base64url() {
+        assert_eq!(Base64Url(b"f"), "Zg");
+        assert_eq!(Base64Url(b"foob"), "Zm9vYg");
+        assert_eq!(Base64Url(&[0xfb, 0xff]), "-_8");
+    }
+}
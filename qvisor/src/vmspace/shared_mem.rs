@@ -0,0 +1,152 @@
+// Copyright (c) 2021 Quark Container Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// The shared memory segments let the sandboxes on one host exchange data without the network
+// stack. A segment is a tmpfs file of the host named by a random token: "quark shm create"
+// creates it and attaches it to one sandbox and prints the token, "quark shm import" with the token attaches
+// it to another sandbox. The guest sees the segment as /dev/quark-shm/<name>, the host file is
+// mapped shared by the host, so the MAP_SHARED mappings of both guests are the same pages.
+// The segment lives until "quark shm delete", the existing mappings stay valid after it.
+
+use alloc::string::String;
+use rand::RngCore;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use libc::*;
+
+use super::super::qlib::common::*;
+
+pub const SHM_DIR: &str = "/dev/shm/quark";
+// the token is the hex of SHM_TOKEN_BYTES random bytes
+pub const SHM_TOKEN_BYTES: usize = 16;
+pub const SHM_NAME_MAX: usize = 255;
+
+pub fn ValidName(name: &str) -> bool {
+    return name.len() > 0 && name.len() <= SHM_NAME_MAX && !name.contains('/') &&
+        name != "." && name != ".."
+}
+
+pub fn ValidToken(token: &str) -> bool {
+    return token.len() == SHM_TOKEN_BYTES * 2 &&
+        token.bytes().all(|c| (c >= b'0' && c <= b'9') || (c >= b'a' && c <= b'f'))
+}
+
+fn SegmentPath(token: &str) -> Result<CString> {
+    return CString::new(format!("{}/{}", SHM_DIR, token))
+        .map_err(|e| Error::Common(format!("shm: invalid token {:?}", e)))
+}
+
+fn NewToken() -> String {
+    let mut buf = [0u8; SHM_TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut buf);
+    return buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// CreateSegment creates a segment of size bytes and returns its token
+pub fn CreateSegment(size: u64) -> Result<String> {
+    if size == 0 || size > i64::MAX as u64 {
+        return Err(Error::Common(format!("shm: invalid size {}", size)))
+    }
+
+    // only the host root can get the tokens from the directory
+    if let Err(e) = fs::DirBuilder::new().recursive(true).mode(0o700).create(SHM_DIR) {
+        return Err(Error::IOError(format!("shm: create {} fail {:?}", SHM_DIR, e)))
+    }
+
+    let token = NewToken();
+    let path = SegmentPath(&token)?;
+    let fd = unsafe {
+        open(path.as_ptr(), O_RDWR | O_CREAT | O_EXCL | O_CLOEXEC, 0o600)
+    };
+
+    if fd < 0 {
+        return Err(Error::SysError(errno::errno().0))
+    }
+
+    // the guest checks the permission with the host file mode, any guest user can map it
+    let ret = unsafe {
+        if ftruncate(fd, size as i64) < 0 || fchmod(fd, 0o666) < 0 {
+            -1
+        } else {
+            0
+        }
+    };
+
+    let err = errno::errno().0;
+    unsafe {
+        close(fd);
+    }
+
+    if ret < 0 {
+        unsafe {
+            unlink(path.as_ptr());
+        }
+        return Err(Error::SysError(err))
+    }
+
+    info!("shm: segment {} of {} bytes is created", token, size);
+    return Ok(token)
+}
+
+// OpenSegment opens the segment of the token and returns the osfd
+pub fn OpenSegment(token: &str) -> Result<i32> {
+    if !ValidToken(token) {
+        return Err(Error::Common(format!("shm: invalid token {}", token)))
+    }
+
+    let path = SegmentPath(token)?;
+    let fd = unsafe {
+        open(path.as_ptr(), O_RDWR | O_CLOEXEC | O_NOFOLLOW)
+    };
+
+    if fd < 0 {
+        return Err(Error::SysError(errno::errno().0))
+    }
+
+    return Ok(fd)
+}
+
+pub fn DeleteSegment(token: &str) -> Result<()> {
+    if !ValidToken(token) {
+        return Err(Error::Common(format!("shm: invalid token {}", token)))
+    }
+
+    let path = SegmentPath(token)?;
+    let ret = unsafe {
+        unlink(path.as_ptr())
+    };
+
+    if ret < 0 {
+        return Err(Error::SysError(errno::errno().0))
+    }
+
+    return Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid() {
+        assert!(ValidName("data"));
+        assert!(!ValidName(""));
+        assert!(!ValidName(".."));
+        assert!(!ValidName("a/b"));
+        assert!(ValidToken(&NewToken()));
+        assert!(!ValidToken("../../etc/passwd"));
+        assert!(!ValidToken("0123456789ABCDEF0123456789ABCDEF"));
+    }
+}
@@ -15,11 +15,12 @@
 use alloc::vec::Vec;
 
 use super::super::qlib::common::*;
+use super::super::qlib::linux_def::SysErr;
 use super::super::qlib::uring::sys::sys::*;
 use super::super::qlib::uring::*;
 
 use super::super::*;
-use super::host_uring::*;
+use super::io_backend::*;
 
 //#[derive(Debug)]
 pub struct UringMgr {
@@ -27,14 +28,27 @@ pub struct UringMgr {
     pub eventfd: i32,
     pub fds: Vec<i32>,
     pub rings: Vec<IoUring>,
+    // eventfd of the io thread for each uring, -1 means the uring is served by the kernel io thread
+    pub ioEventfds: Vec<i32>,
     pub uringSize: usize
 }
 
 impl Drop for UringMgr {
     fn drop(&mut self) {
+        // the emulated urings have no host fd
         for fd in &self.uringfds {
-            unsafe {
-                libc::close(*fd);
+            if *fd >= 0 {
+                unsafe {
+                    libc::close(*fd);
+                }
+            }
+        }
+
+        for fd in &self.ioEventfds {
+            if *fd >= 0 {
+                unsafe {
+                    libc::close(*fd);
+                }
             }
         }
 
@@ -62,6 +76,7 @@ impl UringMgr {
             eventfd: 0,
             fds: fds,
             rings: Vec::new(),
+            ioEventfds: Vec::new(),
             uringSize: size
         };
 
@@ -73,44 +88,81 @@ impl UringMgr {
         return addr;
     }
 
-    pub fn Init(&mut self, DedicateUringCnt: usize) {
+    pub fn Init(&mut self, DedicateUringCnt: usize, ioThreadCnt: usize) {
         let vcpuMappingDelta = VMS.lock().vcpuMappingDelta;
 
         if DedicateUringCnt == 0 {
-            let ring = Builder::default()
-                .setup_cqsize(self.uringSize as u32 * 2)
-                .build(self.uringSize as u32).expect("InitUring fail");
-            self.uringfds.push(ring.fd.0);
-            self.rings.push(ring);
+            // uring 0 is served by the kernel io thread, the others by their own io thread
+            for i in 0..ioThreadCnt {
+                let ring = Backend().NewRing(i, self.uringSize as u32, self.uringSize as u32 * 2, None)
+                    .expect("InitUring fail");
+                self.uringfds.push(ring.fd.0);
+                self.rings.push(ring);
+
+                if i == 0 {
+                    self.ioEventfds.push(-1);
+                } else {
+                    let efd = unsafe {
+                        libc::eventfd(0, libc::EFD_CLOEXEC)
+                    };
+
+                    if efd < 0 {
+                        panic!("InitUring create io thread eventfd fail, errno is {}", errno::errno().0);
+                    }
+
+                    self.ioEventfds.push(efd);
+                }
+            }
         } else {
             for i in 0..DedicateUringCnt {
-                let ring = Builder::default()
-                    .setup_sqpoll(10)
-                    .setup_sqpoll_cpu(i as u32 + vcpuMappingDelta as u32)
-                    //.setup_iopoll()
-                    //.setup_clamp()
-                    .setup_cqsize(self.uringSize as u32 * 2)
-                    .build(self.uringSize as u32).expect("InitUring fail");
+                let ring = Backend().NewRing(i,
+                                             self.uringSize as u32,
+                                             self.uringSize as u32 * 2,
+                                             Some(i as u32 + vcpuMappingDelta as u32))
+                    .expect("InitUring fail");
                 self.uringfds.push(ring.fd.0);
                 self.rings.push(ring);
+                self.ioEventfds.push(-1);
             }
         }
 
         self.Register(IORING_REGISTER_FILES, &self.fds[0] as * const _ as u64, self.fds.len() as u32).expect("InitUring register files fail");
     }
 
+    // ProbeOps returns the bitmap of the IORING_OP_* supported by the io backend
+    pub fn ProbeOps(&self) -> u64 {
+        return Backend().ProbeOps(&self.rings[0]);
+    }
+
     pub fn SetupEventfd(&mut self, eventfd: i32) {
         self.eventfd = eventfd;
 
-        self.Register(IORING_REGISTER_EVENTFD, &self.eventfd as * const _ as u64, 1).expect("InitUring register eventfd fail");
+        // the completion of the uring with its own io thread wakes up that thread
+        for i in 0..self.rings.len() {
+            let efd = if self.ioEventfds[i] >= 0 {
+                &self.ioEventfds[i]
+            } else {
+                &self.eventfd
+            };
+
+            self.RegisterOne(i, IORING_REGISTER_EVENTFD, efd as * const _ as u64, 1).expect("InitUring register eventfd fail");
+        }
     }
 
-    pub fn Enter(&mut self, idx: usize, toSumbit: u32, minComplete:u32, flags: u32) -> Result<i32> {
-        let ret = IOUringEnter(self.uringfds[idx], toSumbit, minComplete, flags);
-        if ret < 0 {
-            return Err(Error::SysError(-ret as i32))
+    // IOThreadEventfds returns the (uring idx, eventfd) of the urings which have their own io thread
+    pub fn IOThreadEventfds(&self) -> Vec<(usize, i32)> {
+        let mut ret = Vec::new();
+        for i in 0..self.ioEventfds.len() {
+            if self.ioEventfds[i] >= 0 {
+                ret.push((i, self.ioEventfds[i]));
+            }
         }
 
+        return ret;
+    }
+
+    pub fn Enter(&mut self, idx: usize, toSumbit: u32, minComplete:u32, flags: u32) -> Result<i32> {
+        let ret = Backend().Enter(idx, &self.rings[idx], toSumbit, minComplete, flags)?;
         return Ok(ret as i32)
     }
 
@@ -124,38 +176,58 @@ impl UringMgr {
     }
 
     pub fn Wake(&self, idx: usize, minComplete: usize) -> Result<()> {
-        let fd = self.uringfds[idx];
-        let ret = if minComplete == 0 {
-            IOUringEnter(fd, 1, minComplete as u32, IORING_ENTER_SQ_WAKEUP)
+        let efd = self.ioEventfds[idx];
+        if efd >= 0 && minComplete == 0 {
+            let val : u64 = 1;
+            let ret = unsafe {
+                libc::write(efd, &val as * const _ as *const libc::c_void, 8)
+            };
+
+            if ret < 0 {
+                return Err(Error::SysError(errno::errno().0))
+            }
+
+            return Ok(())
+        }
+
+        let flags = if minComplete == 0 {
+            IORING_ENTER_SQ_WAKEUP
         } else {
-            IOUringEnter(fd, 1, minComplete as u32, 0)
+            0
         };
 
         //error!("uring wake minComplete {} ret {}, free {}", minComplete, ret, self.ring.sq.freeSlot());
         //self.ring.sq.Print();
-        if ret < 0 {
-            return Err(Error::SysError(-ret as i32))
-        }
-
+        Backend().Enter(idx, &self.rings[idx], 1, minComplete as u32, flags)?;
         return Ok(());
     }
 
     pub fn Register(&self, opcode: u32, arg: u64, nrArgs: u32) -> Result<()> {
-        for fd in &self.uringfds {
-            self.RegisterOne(*fd, opcode, arg, nrArgs)?;
+        for idx in 0..self.rings.len() {
+            self.RegisterOne(idx, opcode, arg, nrArgs)?;
         }
 
         return Ok(())
     }
 
-    pub fn RegisterOne(&self, fd: i32, opcode: u32, arg: u64, nrArgs: u32) -> Result<()> {
-        let ret = IOUringRegister(fd, opcode, arg, nrArgs);
-        if ret < 0 {
-            error!("IOUringRegister get fail {}", ret);
-            return Err(Error::SysError(-ret as i32))
+    pub fn RegisterOne(&self, idx: usize, opcode: u32, arg: u64, nrArgs: u32) -> Result<()> {
+        return Backend().Register(idx, &self.rings[idx], opcode, arg, nrArgs);
+    }
+
+    // RegisterBufRing registers the provided buffer ring of the guest to the uring idx
+    pub fn RegisterBufRing(&self, idx: usize, ringAddr: u64, entries: u32, bgid: u16) -> Result<()> {
+        if idx >= self.rings.len() {
+            return Err(Error::SysError(SysErr::EINVAL))
         }
 
-        return Ok(())
+        let reg = io_uring_buf_reg {
+            ring_addr: ringAddr,
+            ring_entries: entries,
+            bgid: bgid,
+            ..Default::default()
+        };
+
+        return self.RegisterOne(idx, IORING_REGISTER_PBUF_RING, &reg as * const _ as u64, 1);
     }
 
     pub fn UnRegisterFile(&mut self) -> Result<()> {
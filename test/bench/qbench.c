@@ -0,0 +1,242 @@
+// Quark micro benchmarks. Each benchmark prints one JSON object per line:
+// {"bench":"<name>","iters":<n>,"total_ns":<ns>,"avg_ns":<ns>,"ops_per_sec":<ops>,"extra":<value>}
+// so that the result of different runtimes/commits can be compared by a script.
+//
+// usage: qbench [all|syscall|echo|accept|fileio|wakeup] [iters]
+
+#include <unistd.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <errno.h>
+#include <fcntl.h>
+#include <pthread.h>
+#include <time.h>
+#include <sys/socket.h>
+#include <sys/syscall.h>
+#include <sys/eventfd.h>
+#include <sys/wait.h>
+#include <netinet/in.h>
+#include <netinet/tcp.h>
+#include <arpa/inet.h>
+
+#define ECHO_PORT 9988
+#define ACCEPT_PORT 9989
+#define MSG_SIZE 64
+#define FILE_BLOCK (64 * 1024)
+#define FILE_SIZE (64 * 1024 * 1024)
+
+static long long now_ns() {
+    struct timespec ts;
+    clock_gettime(CLOCK_MONOTONIC, &ts);
+    return (long long)ts.tv_sec * 1000000000LL + ts.tv_nsec;
+}
+
+static void report(const char *name, long iters, long long total, double extra) {
+    double avg = iters > 0 ? (double)total / iters : 0;
+    double ops = total > 0 ? (double)iters * 1e9 / total : 0;
+    printf("{\"bench\":\"%s\",\"iters\":%ld,\"total_ns\":%lld,\"avg_ns\":%.1f,\"ops_per_sec\":%.1f,\"extra\":%.1f}\n",
+           name, iters, total, avg, ops, extra);
+    fflush(stdout);
+}
+
+static void die(const char *msg) {
+    perror(msg);
+    exit(1);
+}
+
+// syscall latency: getppid is not cached by libc and has no side effect
+static void bench_syscall(long iters) {
+    long long start = now_ns();
+    for (long i = 0; i < iters; i++) {
+        syscall(SYS_getppid);
+    }
+    report("syscall_getppid", iters, now_ns() - start, 0);
+}
+
+static int listen_on(int port, int backlog) {
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (fd < 0) die("socket");
+
+    int opt = 1;
+    setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, &opt, sizeof(opt));
+
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    addr.sin_port = htons(port);
+    if (bind(fd, (struct sockaddr *)&addr, sizeof(addr)) < 0) die("bind");
+    if (listen(fd, backlog) < 0) die("listen");
+    return fd;
+}
+
+static int connect_to(int port) {
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    if (fd < 0) die("socket");
+
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+    addr.sin_port = htons(port);
+    if (connect(fd, (struct sockaddr *)&addr, sizeof(addr)) < 0) die("connect");
+    return fd;
+}
+
+static int read_full(int fd, char *buf, int len) {
+    int n = 0;
+    while (n < len) {
+        int r = read(fd, buf + n, len - n);
+        if (r <= 0) return r;
+        n += r;
+    }
+    return n;
+}
+
+static void *echo_server(void *arg) {
+    int lfd = *(int *)arg;
+    int fd = accept(lfd, NULL, NULL);
+    if (fd < 0) die("accept");
+
+    char buf[MSG_SIZE];
+    while (read_full(fd, buf, MSG_SIZE) == MSG_SIZE) {
+        if (write(fd, buf, MSG_SIZE) != MSG_SIZE) break;
+    }
+    close(fd);
+    return NULL;
+}
+
+// socket echo round trip time over loopback
+static void bench_echo(long iters) {
+    int lfd = listen_on(ECHO_PORT, 16);
+    pthread_t t;
+    pthread_create(&t, NULL, echo_server, &lfd);
+
+    int fd = connect_to(ECHO_PORT);
+    int opt = 1;
+    setsockopt(fd, IPPROTO_TCP, TCP_NODELAY, &opt, sizeof(opt));
+
+    char buf[MSG_SIZE];
+    memset(buf, 'q', sizeof(buf));
+    long long start = now_ns();
+    for (long i = 0; i < iters; i++) {
+        if (write(fd, buf, MSG_SIZE) != MSG_SIZE) die("echo write");
+        if (read_full(fd, buf, MSG_SIZE) != MSG_SIZE) die("echo read");
+    }
+    report("socket_echo_rtt", iters, now_ns() - start, MSG_SIZE);
+
+    close(fd);
+    pthread_join(t, NULL);
+    close(lfd);
+}
+
+static void *accept_server(void *arg) {
+    int lfd = *(int *)arg;
+    for (;;) {
+        int fd = accept(lfd, NULL, NULL);
+        if (fd < 0) break;
+        close(fd);
+    }
+    return NULL;
+}
+
+// accept rate: connect and close as fast as possible, the server accepts and closes
+static void bench_accept(long iters) {
+    int lfd = listen_on(ACCEPT_PORT, 1024);
+    pthread_t t;
+    pthread_create(&t, NULL, accept_server, &lfd);
+
+    long long start = now_ns();
+    for (long i = 0; i < iters; i++) {
+        int fd = connect_to(ACCEPT_PORT);
+        close(fd);
+    }
+    report("tcp_accept", iters, now_ns() - start, 0);
+
+    shutdown(lfd, SHUT_RDWR);
+    close(lfd);
+    pthread_join(t, NULL);
+}
+
+// sequential file write and read throughput, extra is MB/s
+static void bench_fileio(long iters) {
+    char path[] = "/tmp/qbench.XXXXXX";
+    int fd = mkstemp(path);
+    if (fd < 0) die("mkstemp");
+    unlink(path);
+
+    char *buf = malloc(FILE_BLOCK);
+    memset(buf, 'q', FILE_BLOCK);
+    long blocks = FILE_SIZE / FILE_BLOCK;
+
+    for (long round = 0; round < iters; round++) {
+        long long start = now_ns();
+        for (long i = 0; i < blocks; i++) {
+            if (pwrite(fd, buf, FILE_BLOCK, i * FILE_BLOCK) != FILE_BLOCK) die("pwrite");
+        }
+        fsync(fd);
+        long long total = now_ns() - start;
+        report("file_write", blocks, total, (double)FILE_SIZE / 1048576.0 * 1e9 / total);
+
+        start = now_ns();
+        for (long i = 0; i < blocks; i++) {
+            if (pread(fd, buf, FILE_BLOCK, i * FILE_BLOCK) != FILE_BLOCK) die("pread");
+        }
+        total = now_ns() - start;
+        report("file_read", blocks, total, (double)FILE_SIZE / 1048576.0 * 1e9 / total);
+    }
+
+    free(buf);
+    close(fd);
+}
+
+static int ping_fd, pong_fd;
+
+static void *wakeup_peer(void *arg) {
+    long iters = *(long *)arg;
+    uint64_t v;
+    for (long i = 0; i < iters; i++) {
+        if (read(ping_fd, &v, sizeof(v)) != sizeof(v)) die("ping read");
+        v = 1;
+        if (write(pong_fd, &v, sizeof(v)) != sizeof(v)) die("pong write");
+    }
+    return NULL;
+}
+
+// scheduler wakeup latency: ping pong between 2 threads blocked on eventfd, avg_ns is one round trip
+static void bench_wakeup(long iters) {
+    ping_fd = eventfd(0, 0);
+    pong_fd = eventfd(0, 0);
+    if (ping_fd < 0 || pong_fd < 0) die("eventfd");
+
+    pthread_t t;
+    pthread_create(&t, NULL, wakeup_peer, &iters);
+
+    uint64_t v;
+    long long start = now_ns();
+    for (long i = 0; i < iters; i++) {
+        v = 1;
+        if (write(ping_fd, &v, sizeof(v)) != sizeof(v)) die("ping write");
+        if (read(pong_fd, &v, sizeof(v)) != sizeof(v)) die("pong read");
+    }
+    report("thread_wakeup_rtt", iters, now_ns() - start, 0);
+
+    pthread_join(t, NULL);
+    close(ping_fd);
+    close(pong_fd);
+}
+
+int main(int argc, char *argv[]) {
+    const char *bench = argc > 1 ? argv[1] : "all";
+    long iters = argc > 2 ? atol(argv[2]) : 0;
+    int all = strcmp(bench, "all") == 0;
+
+    if (all || strcmp(bench, "syscall") == 0) bench_syscall(iters ? iters : 1000000);
+    if (all || strcmp(bench, "echo") == 0) bench_echo(iters ? iters : 100000);
+    if (all || strcmp(bench, "accept") == 0) bench_accept(iters ? iters : 10000);
+    if (all || strcmp(bench, "fileio") == 0) bench_fileio(iters ? iters : 3);
+    if (all || strcmp(bench, "wakeup") == 0) bench_wakeup(iters ? iters : 100000);
+
+    return 0;
+}
@@ -0,0 +1,29 @@
+#!/bin/bash
+# Run the micro benchmarks in a container for each runtime and collect the JSON results.
+#
+# usage: ./run.sh [output.json] [runtime...]
+#   ./run.sh result.json runc quark
+#
+# Each line of the output is the benchmark JSON tagged with the runtime and the quark commit, e.g.
+# {"runtime":"quark","commit":"1a42814","bench":"socket_echo_rtt","iters":100000,...}
+
+set -e
+
+DIR=$(cd "$(dirname "$0")" && pwd)
+OUTPUT=${1:-bench_output.json}
+shift || true
+RUNTIMES=${@:-runc quark}
+IMAGE=${QBENCH_IMAGE:-ubuntu}
+BENCH=${QBENCH:-all}
+COMMIT=$(git -C "$DIR" rev-parse --short HEAD 2>/dev/null || echo unknown)
+
+make -C "$DIR" qbench > /dev/null
+
+: > "$OUTPUT"
+for runtime in $RUNTIMES; do
+    docker run --rm --runtime=$runtime -v "$DIR":/bench "$IMAGE" /bench/qbench $BENCH $QBENCH_ITERS | \
+        grep '^{' | \
+        sed "s/^{/{\"runtime\":\"$runtime\",\"commit\":\"$COMMIT\",/" >> "$OUTPUT"
+done
+
+cat "$OUTPUT"
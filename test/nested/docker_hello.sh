@@ -0,0 +1,28 @@
+#!/bin/bash
+# Run a nested container runtime inside a Quark sandbox: the outer container is started with
+# the quark runtime and runs the docker daemon, the inner "docker run hello-world" uses runc.
+#
+# usage: ./docker_hello.sh [outer image]
+# The test passes when the inner container prints the hello-world greeting.
+
+set -e
+
+IMAGE=${1:-docker:dind}
+RUNTIME=${QUARK_RUNTIME:-quark}
+
+OUTPUT=$(docker run --rm --privileged --runtime=$RUNTIME "$IMAGE" sh -c '
+    dockerd --storage-driver=vfs --iptables=false > /tmp/dockerd.log 2>&1 &
+    for i in $(seq 1 30); do
+        docker info > /dev/null 2>&1 && break
+        sleep 1
+    done
+    docker run --rm hello-world || { tail -50 /tmp/dockerd.log; exit 1; }
+' 2>&1) || true
+
+echo "$OUTPUT"
+if echo "$OUTPUT" | grep -q "Hello from Docker!"; then
+    echo "PASS: docker run hello-world inside $RUNTIME"
+else
+    echo "FAIL: docker run hello-world inside $RUNTIME"
+    exit 1
+fi